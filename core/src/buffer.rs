@@ -0,0 +1,673 @@
+//! Shared memory buffers of the channels, usable without the hypervisor
+//!
+//! The hypervisor builds its [Sampling](crate::sampling::Sampling) and
+//! [Queuing](crate::queuing::Queuing) channels from the primitives of this
+//! module. They only need a message size and, for queues, a capacity, so that
+//! other programs may exchange messages through shared memory the same way.
+//! The buffers of the ARINC 653 buffer services of a partition are
+//! [MessageBuffer]s, which queue messages of varying length.
+//!
+//! Every buffer lives in a sealed memfd, which can not be resized once
+//! created. Its mapping is shared, so that a child created by `fork()` or a
+//! process receiving the fd works on the same memory.
+//!
+//! The memfds of the channels of the hypervisor are named by
+//! [channel_memfd_name], so that they can be told apart in
+//! `/proc/<pid>/maps` or the output of `lsof`, and listed by
+//! [channel_memfds].
+
+use std::fmt::{self, Display};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::time::Duration;
+use std::{fs, io, mem};
+
+use a653rs::bindings::PortDirection;
+use memfd::{FileSeal, Memfd, MemfdOptions};
+use memmap2::MmapMut;
+use procfs::process::{FDTarget, Process};
+use procfs::ProcError;
+use thiserror::Error;
+
+pub use crate::queuing::queue::ConcurrentQueue;
+use crate::sampling::Datagram;
+use crate::time::MonotonicTime;
+
+/// Errors of the shared memory buffers
+#[derive(Error, Debug)]
+pub enum BufferError {
+    #[error("buffer of {actual} bytes does not match the required size of {expected} bytes")]
+    Size { expected: usize, actual: usize },
+    #[error("sampling port memory of {0} bytes is too small")]
+    TooSmall(usize),
+    #[error("sampling port layout version {found} is not supported, expected {expected}")]
+    Version { found: u32, expected: u32 },
+    #[error("queue must hold at least one message")]
+    NoCapacity,
+    #[error("buffer for {capacity} messages of {msg_size} bytes exceeds the maximum size")]
+    TooLarge { msg_size: usize, capacity: usize },
+    #[error("message length of {len} bytes exceeds the message size of {msg_size} bytes")]
+    MessageLength { len: usize, msg_size: usize },
+    #[error("queue for {capacity} messages of {msg_size} bytes was expected, found one for {found_capacity} messages of {found_msg_size} bytes")]
+    QueueLayout {
+        msg_size: usize,
+        capacity: usize,
+        found_msg_size: usize,
+        found_capacity: usize,
+    },
+    #[error("queue for {capacity} messages holds {len} messages")]
+    QueueLength { len: usize, capacity: usize },
+    #[error("queue for {capacity} messages starts at index {first}")]
+    QueueIndex { first: usize, capacity: usize },
+    #[error("queue marks {done} of {reserved} pushes in progress as done")]
+    QueuePushes { reserved: usize, done: usize },
+    #[error("message timestamp lies {0:?} in the future")]
+    FutureTimestamp(Duration),
+    #[error("sampling port sequence changed while the port was checked")]
+    SequenceChanged,
+    #[error("memfd error: {0}")]
+    Memfd(#[from] memfd::Error),
+    #[error("procfs error: {0}")]
+    Proc(#[from] ProcError),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Creates a memfd of `size` bytes, which is inherited by child processes
+/// and sealed against resizing
+pub fn memfd(name: impl AsRef<str>, size: usize) -> Result<Memfd, BufferError> {
+    let mem = MemfdOptions::default()
+        .close_on_exec(false)
+        .allow_sealing(true)
+        .create(name)?;
+    mem.as_file().set_len(size as u64)?;
+    mem.add_seals(&[FileSeal::SealShrink, FileSeal::SealGrow])?;
+
+    Ok(mem)
+}
+
+/// Longest name of a memfd in bytes, as the `memfd:` prefix added by the
+/// kernel counts towards `NAME_MAX`
+pub const MEMFD_NAME_MAX: usize = 249;
+
+/// Start of the names of all channel memfds
+pub const CHANNEL_MEMFD_PREFIX: &str = "a653[";
+
+/// Returns the name of the memfd of one end of a channel
+///
+/// The name is `a653[<module>]:<channel>:<partition>:<dir>`, where `module`
+/// is the pid of the hypervisor, `channel` the name of the source port of the
+/// channel and `partition` the partition the memfd is passed to. Names longer
+/// than [MEMFD_NAME_MAX] are cut short and end in `~` followed by a hash of the
+/// full name, so that they remain distinct.
+pub fn channel_memfd_name(
+    module: u32,
+    channel: &str,
+    partition: &str,
+    dir: PortDirection,
+) -> String {
+    let dir = match dir {
+        PortDirection::Source => "source",
+        PortDirection::Destination => "destination",
+    };
+    truncate_memfd_name(format!(
+        "{CHANNEL_MEMFD_PREFIX}{module}]:{channel}:{partition}:{dir}"
+    ))
+}
+
+fn truncate_memfd_name(mut name: String) -> String {
+    if name.len() <= MEMFD_NAME_MAX {
+        return name;
+    }
+    // FNV-1a, which unlike the hasher of std is stable across releases
+    let hash = name.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    let suffix = format!("~{hash:016x}");
+    let mut end = MEMFD_NAME_MAX - suffix.len();
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    name.truncate(end);
+    name.push_str(&suffix);
+    name
+}
+
+/// A channel memfd open in a process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelMemfd {
+    pub fd: RawFd,
+    pub name: String,
+    /// Size in bytes
+    pub size: u64,
+}
+
+impl Display for ChannelMemfd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.fd, self.size, self.name)
+    }
+}
+
+/// Lists the memfds of `process` named by [channel_memfd_name], ordered by
+/// their fd
+///
+/// Fds closed while the table of `process` is read are left out.
+pub fn channel_memfds(process: &Process) -> Result<Vec<ChannelMemfd>, BufferError> {
+    let mut memfds = Vec::new();
+    for info in process.fd()?.flatten() {
+        let FDTarget::MemFD(name) = &info.target else {
+            continue;
+        };
+        // The kernel shows memfds as deleted files
+        let name = name.strip_suffix(" (deleted)").unwrap_or(name);
+        if !name.starts_with(CHANNEL_MEMFD_PREFIX) {
+            continue;
+        }
+        let Ok(meta) = fs::metadata(format!("/proc/{}/fd/{}", process.pid, info.fd)) else {
+            continue;
+        };
+        memfds.push(ChannelMemfd {
+            fd: info.fd,
+            name: name.to_string(),
+            size: meta.len(),
+        });
+    }
+    memfds.sort_by_key(|m| m.fd);
+
+    Ok(memfds)
+}
+
+/// A [ConcurrentQueue] in a memfd
+///
+/// Producers and a single consumer may access the queue at the same time, as
+/// an element only counts as pushed once its data is written. A producer
+/// killed while pushing holds back later pushes until
+/// [ConcurrentQueue::abandon_pushes] is called.
+///
+/// # Example
+///
+/// ```
+/// use a653rs_linux_core::buffer::QueueBuffer;
+/// use nix::sys::wait::{waitpid, WaitStatus};
+/// use nix::unistd::{fork, ForkResult};
+///
+/// let buffer = QueueBuffer::new("example_queue", 8, 4)?;
+///
+/// // SAFETY: The example is single-threaded
+/// match unsafe { fork() }? {
+///     ForkResult::Child => {
+///         for i in 0..4u64 {
+///             buffer.queue().push(&i.to_ne_bytes());
+///         }
+///         unsafe { nix::libc::_exit(0) }
+///     }
+///     ForkResult::Parent { child } => {
+///         // Waiting for the producer makes sure all messages were pushed
+///         assert_eq!(waitpid(child, None)?, WaitStatus::Exited(child, 0));
+///         for i in 0..4u64 {
+///             let msg = buffer.queue().pop().unwrap();
+///             assert_eq!(*msg, i.to_ne_bytes());
+///         }
+///         assert!(buffer.queue().is_empty());
+///     }
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct QueueBuffer {
+    mmap: MmapMut,
+    fd: OwnedFd,
+}
+
+impl QueueBuffer {
+    /// Largest buffer of a queue, far beyond any sensible channel
+    const MAX_SIZE: usize = i32::MAX as usize;
+
+    /// Creates an empty queue of `capacity` messages of `msg_size` bytes
+    pub fn new(
+        name: impl AsRef<str>,
+        msg_size: usize,
+        capacity: usize,
+    ) -> Result<Self, BufferError> {
+        if capacity == 0 {
+            return Err(BufferError::NoCapacity);
+        }
+        let size = msg_size
+            .checked_mul(capacity)
+            .filter(|data| *data <= Self::MAX_SIZE)
+            .map(|_| ConcurrentQueue::size(msg_size, capacity))
+            .ok_or(BufferError::TooLarge { msg_size, capacity })?;
+
+        let mem = memfd(name, size)?;
+        let mut mmap = unsafe { MmapMut::map_mut(mem.as_raw_fd())? };
+        mem.add_seals(&[FileSeal::SealSeal])?;
+        ConcurrentQueue::try_init_at(&mut mmap, msg_size, capacity)?;
+
+        Ok(Self {
+            mmap,
+            fd: mem.into_file().into(),
+        })
+    }
+
+    /// Maps the queue of a memfd received from another process
+    ///
+    /// # Safety
+    /// `fd` must refer to the memfd of a [QueueBuffer], as created by
+    /// [QueueBuffer::new]. Its header is trusted, so any other memory leads to
+    /// out of bounds accesses.
+    pub unsafe fn from_fd(fd: OwnedFd) -> Result<Self, BufferError> {
+        let mmap = MmapMut::map_mut(fd.as_raw_fd())?;
+        let header = ConcurrentQueue::size(0, 1);
+        if mmap.len() < header {
+            return Err(BufferError::Size {
+                expected: header,
+                actual: mmap.len(),
+            });
+        }
+
+        Ok(Self { mmap, fd })
+    }
+
+    /// The queue in this buffer
+    pub fn queue(&self) -> &ConcurrentQueue {
+        // The buffer was initialized by `new`, or promised to be by `from_fd`
+        unsafe { ConcurrentQueue::load_from(&self.mmap) }
+    }
+
+    /// The memfd holding the queue, to be passed to other processes
+    pub fn fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+/// A [QueueBuffer] of messages of varying length
+///
+/// Every element holds the length of its message, followed by the message of
+/// at most the message size given on creation. Like a [QueueBuffer], the
+/// buffer supports a single consumer at a time.
+///
+/// # Example
+///
+/// ```
+/// use a653rs_linux_core::buffer::MessageBuffer;
+///
+/// let buffer = MessageBuffer::new("example_messages", 8, 2)?;
+/// assert!(buffer.push(b"first")?);
+/// assert!(buffer.push(b"second")?);
+/// // The buffer is full
+/// assert!(!buffer.push(b"third")?);
+///
+/// let mut msg = [0; 8];
+/// let len = buffer.pop(&mut msg)?.unwrap();
+/// assert_eq!(&msg[..len], b"first");
+/// assert_eq!(buffer.len(), 1);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct MessageBuffer {
+    queue: QueueBuffer,
+}
+
+impl MessageBuffer {
+    /// Bytes in front of every message, holding its length
+    const PREFIX: usize = mem::size_of::<u32>();
+
+    /// Creates an empty buffer of `capacity` messages of up to `msg_size`
+    /// bytes
+    pub fn new(
+        name: impl AsRef<str>,
+        msg_size: usize,
+        capacity: usize,
+    ) -> Result<Self, BufferError> {
+        let element_size = u32::try_from(msg_size)
+            .ok()
+            .and_then(|_| msg_size.checked_add(Self::PREFIX))
+            .ok_or(BufferError::TooLarge { msg_size, capacity })?;
+
+        Ok(Self {
+            queue: QueueBuffer::new(name, element_size, capacity)?,
+        })
+    }
+
+    /// Maps the buffer of a memfd received from another process
+    ///
+    /// # Safety
+    /// `fd` must refer to the memfd of a [MessageBuffer], as created by
+    /// [MessageBuffer::new], see [QueueBuffer::from_fd].
+    pub unsafe fn from_fd(fd: OwnedFd) -> Result<Self, BufferError> {
+        let queue = QueueBuffer::from_fd(fd)?;
+        let element_size = queue.queue().msg_size;
+        if element_size < Self::PREFIX {
+            return Err(BufferError::MessageLength {
+                len: Self::PREFIX,
+                msg_size: element_size,
+            });
+        }
+
+        Ok(Self { queue })
+    }
+
+    /// Maximum length of a message
+    pub fn msg_size(&self) -> usize {
+        self.queue.queue().msg_size - Self::PREFIX
+    }
+
+    /// Maximum number of messages
+    pub fn capacity(&self) -> usize {
+        self.queue.queue().msg_capacity
+    }
+
+    /// Number of messages in the buffer
+    pub fn len(&self) -> usize {
+        self.queue.queue().len()
+    }
+
+    /// Whether the buffer holds no messages
+    pub fn is_empty(&self) -> bool {
+        self.queue.queue().is_empty()
+    }
+
+    /// Appends `msg`, returning whether there was space left for it
+    pub fn push(&self, msg: &[u8]) -> Result<bool, BufferError> {
+        let msg_size = self.msg_size();
+        if msg.len() > msg_size {
+            return Err(BufferError::MessageLength {
+                len: msg.len(),
+                msg_size,
+            });
+        }
+
+        let pushed = self.queue.queue().push_then(|element| {
+            let (len, data) = element.split_at_mut(Self::PREFIX);
+            len.copy_from_slice(&(msg.len() as u32).to_ne_bytes());
+            data[..msg.len()].copy_from_slice(msg);
+        });
+        Ok(pushed.is_some())
+    }
+
+    /// Removes the oldest message and copies it into `buf`, returning its
+    /// length, or `None` if the buffer is empty
+    ///
+    /// A message longer than `buf` is left in the buffer.
+    pub fn pop(&self, buf: &mut [u8]) -> Result<Option<usize>, BufferError> {
+        let queue = self.queue.queue();
+        // There is only a single consumer, so the peeked message is the one popped
+        let Some(len) = queue.peek_then(|element| element.map(|e| Self::message(e).len())) else {
+            return Ok(None);
+        };
+        if len > buf.len() {
+            return Err(BufferError::MessageLength {
+                len,
+                msg_size: buf.len(),
+            });
+        }
+
+        Ok(queue.pop_then(|element| {
+            let msg = Self::message(element);
+            buf[..msg.len()].copy_from_slice(msg);
+            msg.len()
+        }))
+    }
+
+    /// The memfd holding the messages, to be passed to other processes
+    pub fn fd(&self) -> BorrowedFd<'_> {
+        self.queue.fd()
+    }
+
+    /// The message of an element, whose length is trusted only up to the
+    /// message size
+    fn message(element: &[u8]) -> &[u8] {
+        let (len, data) = element.split_at(Self::PREFIX);
+        let len = u32::from_ne_bytes(len.try_into().unwrap()) as usize;
+        &data[..len.min(data.len())]
+    }
+}
+
+/// A message read from a [SamplingBuffer]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    /// Bytes copied into the buffer of the reader
+    pub len: usize,
+    /// Time of the write, zero if nothing was written yet
+    pub written: MonotonicTime,
+}
+
+/// A buffer holding the latest message of a sampling port in a memfd
+///
+/// A sampling buffer may be read while it is written, as long as there is
+/// only a single writer. A reader never sees a partially
+/// written message.
+///
+/// # Example
+///
+/// ```
+/// use a653rs_linux_core::buffer::SamplingBuffer;
+/// use nix::sys::wait::{waitpid, WaitStatus};
+/// use nix::unistd::{fork, ForkResult};
+///
+/// let mut buffer = SamplingBuffer::new("example_sampling", 16)?;
+///
+/// // SAFETY: The example is single-threaded
+/// match unsafe { fork() }? {
+///     ForkResult::Child => {
+///         buffer.write(b"first");
+///         buffer.write(b"latest");
+///         unsafe { nix::libc::_exit(0) }
+///     }
+///     ForkResult::Parent { child } => {
+///         assert_eq!(waitpid(child, None)?, WaitStatus::Exited(child, 0));
+///         let mut msg = [0; 16];
+///         let sample = buffer.read(&mut msg);
+///         assert_eq!(&msg[..sample.len], b"latest");
+///         assert!(!sample.written.is_zero());
+///     }
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct SamplingBuffer {
+    mmap: MmapMut,
+    fd: OwnedFd,
+}
+
+impl SamplingBuffer {
+    /// Creates an empty buffer for messages of up to `msg_size` bytes
+    pub fn new(name: impl AsRef<str>, msg_size: usize) -> Result<Self, BufferError> {
+        if msg_size > Datagram::MAX_MSG_SIZE {
+            return Err(BufferError::TooLarge {
+                msg_size,
+                capacity: 1,
+            });
+        }
+
+        let mem = memfd(name, Datagram::size(msg_size) as usize)?;
+        let mut mmap = unsafe { MmapMut::map_mut(mem.as_raw_fd())? };
+        mem.add_seals(&[FileSeal::SealSeal])?;
+        Datagram::init(&mut mmap);
+
+        Ok(Self {
+            mmap,
+            fd: mem.into_file().into(),
+        })
+    }
+
+    /// Maps the buffer of a memfd received from another process, checking
+    /// that its layout is known
+    pub fn from_fd(fd: OwnedFd) -> Result<Self, BufferError> {
+        let mmap = unsafe { MmapMut::map_mut(fd.as_raw_fd())? };
+        Datagram::check_version(&mmap)?;
+
+        Ok(Self { mmap, fd })
+    }
+
+    /// Replaces the message, returning the number of bytes written
+    ///
+    /// Data beyond the message size is cut off. There must be only one
+    /// process writing to a buffer at a time.
+    pub fn write(&mut self, data: &[u8]) -> usize {
+        Datagram::write(&mut self.mmap, data, 0)
+    }
+
+    /// Copies the latest message into `buf`
+    pub fn read(&self, buf: &mut [u8]) -> Sample {
+        let read = Datagram::read(&self.mmap, buf);
+        Sample {
+            len: read.data.len(),
+            written: read.copied,
+        }
+    }
+
+    /// The memfd holding the message, to be passed to other processes
+    pub fn fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_sizes() {
+        assert!(matches!(
+            QueueBuffer::new("buffer_test", 8, 0),
+            Err(BufferError::NoCapacity)
+        ));
+        assert!(matches!(
+            QueueBuffer::new("buffer_test", usize::MAX, 2),
+            Err(BufferError::TooLarge { .. })
+        ));
+        assert!(matches!(
+            SamplingBuffer::new("buffer_test", usize::MAX),
+            Err(BufferError::TooLarge { .. })
+        ));
+
+        let queue = QueueBuffer::new("buffer_test", 3, 2).unwrap();
+        assert_eq!(queue.queue().msg_size, 3);
+        assert_eq!(queue.queue().msg_capacity, 2);
+        assert!(queue.queue().push(&[1, 2, 3]).is_some());
+        assert!(queue.queue().push(&[4, 5, 6]).is_some());
+        assert!(queue.queue().push(&[7, 8, 9]).is_none());
+    }
+
+    #[test]
+    fn message_buffers() {
+        assert!(matches!(
+            MessageBuffer::new("buffer_test", usize::MAX, 2),
+            Err(BufferError::TooLarge { .. })
+        ));
+
+        let buffer = MessageBuffer::new("buffer_test", 4, 2).unwrap();
+        assert_eq!(buffer.msg_size(), 4);
+        assert_eq!(buffer.capacity(), 2);
+        assert!(matches!(
+            buffer.push(b"12345"),
+            Err(BufferError::MessageLength {
+                len: 5,
+                msg_size: 4
+            })
+        ));
+        assert!(buffer.push(b"abcd").unwrap());
+        assert!(buffer.push(b"").unwrap());
+        assert!(!buffer.push(b"x").unwrap());
+        assert_eq!(buffer.len(), 2);
+
+        // A message too long for the area stays in the buffer
+        let mut small = [0; 2];
+        assert!(matches!(
+            buffer.pop(&mut small),
+            Err(BufferError::MessageLength {
+                len: 4,
+                msg_size: 2
+            })
+        ));
+        let mut buf = [0; 4];
+        assert_eq!(buffer.pop(&mut buf).unwrap(), Some(4));
+        assert_eq!(&buf, b"abcd");
+
+        let fd = buffer.fd().try_clone_to_owned().unwrap();
+        let reopened = unsafe { MessageBuffer::from_fd(fd) }.unwrap();
+        assert_eq!(reopened.msg_size(), 4);
+        assert_eq!(reopened.pop(&mut small).unwrap(), Some(0));
+        assert_eq!(reopened.pop(&mut small).unwrap(), None);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn reopened_from_fd() {
+        let queue = QueueBuffer::new("buffer_test", 2, 2).unwrap();
+        queue.queue().push(&[1, 2]);
+        let fd = queue.fd().try_clone_to_owned().unwrap();
+        let reopened = unsafe { QueueBuffer::from_fd(fd) }.unwrap();
+        assert_eq!(reopened.queue().pop().as_deref(), Some(&[1, 2][..]));
+        assert_eq!(queue.queue().len(), 0);
+
+        let mut sampling = SamplingBuffer::new("buffer_test", 4).unwrap();
+        assert_eq!(sampling.write(b"message"), 4);
+        let fd = sampling.fd().try_clone_to_owned().unwrap();
+        let reopened = SamplingBuffer::from_fd(fd).unwrap();
+        let mut buf = [0; 8];
+        let sample = reopened.read(&mut buf);
+        assert_eq!(&buf[..sample.len], b"mess");
+
+        let empty = memfd("buffer_test", 4).unwrap();
+        assert!(matches!(
+            SamplingBuffer::from_fd(empty.into_file().into()),
+            Err(BufferError::TooSmall(4))
+        ));
+    }
+
+    #[test]
+    fn channel_memfd_names() {
+        assert_eq!(
+            channel_memfd_name(42, "a:out", "b", PortDirection::Destination),
+            "a653[42]:a:out:b:destination"
+        );
+        assert_eq!(
+            channel_memfd_name(42, "a:out", "a", PortDirection::Source),
+            "a653[42]:a:out:a:source"
+        );
+
+        let long = "p".repeat(300);
+        let name = channel_memfd_name(42, "a:out", &long, PortDirection::Source);
+        assert_eq!(name.len(), MEMFD_NAME_MAX);
+        assert!(name.starts_with("a653[42]:a:out:ppp"));
+        assert_eq!(
+            name,
+            channel_memfd_name(42, "a:out", &long, PortDirection::Source)
+        );
+        // Names differing after the cut still differ
+        assert_ne!(
+            name,
+            channel_memfd_name(42, "a:out", &long, PortDirection::Destination)
+        );
+        memfd(&name, 8).unwrap();
+
+        // Not cut within a character
+        let wide = "\u{e4}".repeat(150);
+        let name = channel_memfd_name(42, "a:out", &wide, PortDirection::Source);
+        assert_eq!(name.len(), MEMFD_NAME_MAX - 1);
+        let (cut, hash) = name.split_once('~').unwrap();
+        assert!(cut.ends_with('\u{e4}'));
+        assert_eq!(hash.len(), 16);
+    }
+
+    #[test]
+    fn listed_channel_memfds() {
+        let name = channel_memfd_name(
+            std::process::id(),
+            "buffer_test:listed",
+            "b",
+            PortDirection::Source,
+        );
+        let mem = memfd(&name, 24).unwrap();
+        memfd("buffer_test", 8).unwrap();
+
+        let listed = channel_memfds(&Process::myself().unwrap()).unwrap();
+        let found = listed.iter().find(|m| m.fd == mem.as_raw_fd()).unwrap();
+        assert_eq!(found.name, name);
+        assert_eq!(found.size, 24);
+        assert!(listed
+            .iter()
+            .all(|m| m.name.starts_with(CHANNEL_MEMFD_PREFIX)));
+    }
+}
@@ -8,19 +8,29 @@
 //! This approach makes it possible to only manage a certain sub-tree
 //! of cgroups, thereby saving resources. Alternatively, the root cgroup
 //! may be imported, keeping track of all cgroups existing on the host system.
+use std::fmt::Display;
 use std::fs::{self};
-use std::io::BufRead;
+use std::io::{self, BufRead};
 use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 
 use anyhow::{bail, ensure, Context, Ok};
 use itertools::Itertools;
+use nix::errno::Errno;
+use nix::sys::signal::{kill, Signal};
 use nix::sys::statfs;
-use nix::unistd::Pid;
+use nix::sys::wait::waitpid;
+use nix::unistd::{fork, pause, ForkResult, Pid};
+use thiserror::Error;
 use walkdir::WalkDir;
 
+use crate::deadline::Deadline;
+
 const KILLING_TIMEOUT: Duration = Duration::from_secs(1);
 
+/// Longest time a freeze or unfreeze may take during [transition_overhead]
+const TRANSITION_TIMEOUT: Duration = Duration::from_secs(1);
+
 /// A single cgroup inside our tree of managed cgroups
 ///
 /// The tree is not represented by a traditional tree data structure,
@@ -37,6 +47,11 @@ impl CGroup {
     /// path must be the path of an already existing cgroup
     pub fn new_root<P: AsRef<Path>>(path: P, name: &str) -> anyhow::Result<Self> {
         trace!("Create cgroup \"{name}\"");
+        // The name must not lead outside of `path`
+        ensure!(
+            !name.is_empty() && name != "." && name != ".." && !name.contains('/'),
+            "invalid cgroup name {name:?}"
+        );
         // Double-checking if path is cgroup does not hurt, as it is
         // better to not potentially create a directory at a random location.
         ensure!(
@@ -199,17 +214,18 @@ impl CGroup {
         trace!("writing '1' to {}", killfile.display());
         fs::write(killfile, "1")?;
 
-        // Check if all processes were terminated successfully
-        let start = Instant::now();
+        // Check if all processes were terminated successfully, at least once
+        let deadline = Deadline::after(KILLING_TIMEOUT);
         trace!("Killing with a {KILLING_TIMEOUT:?} timeout");
-        while start.elapsed() < KILLING_TIMEOUT {
+        loop {
             if !self.populated()? {
                 trace!("Killed with a {KILLING_TIMEOUT:?} timeout");
                 return Ok(());
             }
+            if deadline.has_passed() {
+                bail!("failed to kill the cgroup")
+            }
         }
-
-        bail!("failed to kill the cgroup")
     }
 
     /// Returns the path of this cgroup
@@ -247,9 +263,140 @@ impl CGroup {
         Ok(())
     }
 
+    /// Enables `controllers` for the children of this cgroup
+    ///
+    /// A controller can only be enabled in `cgroup.subtree_control` if the
+    /// parent enabled it as well, so the ancestors are enabled first, starting
+    /// from the closest one making all of them available in
+    /// `cgroup.controllers`. Controllers enabled already are left alone.
+    pub fn enable_controllers(&self, controllers: &[Controller]) -> anyhow::Result<()> {
+        self.ensure_is_cgroup()?;
+        Ok(enable_controllers(&self.path, controllers)?)
+    }
+
     // TODO: Implement functions to fetch the parents and children
 }
 
+/// A controller of the cgroup v2 hierarchy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Controller {
+    Cpu,
+    Cpuset,
+    Io,
+    Memory,
+    Pids,
+}
+
+impl Controller {
+    /// Name of the controller in `cgroup.controllers` and
+    /// `cgroup.subtree_control`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Controller::Cpu => "cpu",
+            Controller::Cpuset => "cpuset",
+            Controller::Io => "io",
+            Controller::Memory => "memory",
+            Controller::Pids => "pids",
+        }
+    }
+}
+
+impl Display for Controller {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Failure to enable a controller, naming the cgroup at which it failed
+#[derive(Error, Debug)]
+pub enum ControllerError {
+    #[error(
+        "controller {controller} is not available in {}, delegate it to the hypervisor, e.g. with `systemd-run --user --scope -p Delegate={controller}`",
+        level.display()
+    )]
+    Unavailable {
+        level: PathBuf,
+        controller: Controller,
+    },
+    #[error(
+        "cannot enable controller {controller} in {}: {source}, {}",
+        level.display(),
+        enable_hint(source)
+    )]
+    Enable {
+        level: PathBuf,
+        controller: Controller,
+        source: io::Error,
+    },
+    #[error("cannot read {}: {source}", path.display())]
+    Read { path: PathBuf, source: io::Error },
+}
+
+/// Explains the reason of a failed write to `cgroup.subtree_control`
+fn enable_hint(err: &io::Error) -> &'static str {
+    match err.raw_os_error() {
+        Some(errno) if errno == Errno::EBUSY as i32 => {
+            "as it holds processes, run the hypervisor in a delegated cgroup with `--cgroup-use-parent`"
+        }
+        _ => "delegate the cgroup to the hypervisor, e.g. with `systemd-run --user --scope -p Delegate=yes`",
+    }
+}
+
+/// Reads a list of controllers like `cgroup.controllers`
+fn read_controllers(path: &Path) -> Result<Vec<String>, ControllerError> {
+    fs::read_to_string(path)
+        .map(|list| list.split_whitespace().map(str::to_owned).collect())
+        .map_err(|source| ControllerError::Read {
+            path: path.to_owned(),
+            source,
+        })
+}
+
+/// Enables `controllers` in `cgroup.subtree_control` of the cgroup at `path`
+/// and of as many of its ancestors as needed
+fn enable_controllers(path: &Path, controllers: &[Controller]) -> Result<(), ControllerError> {
+    // Walk up until all controllers are available, the levels are enabled from
+    // the top down
+    let mut levels = vec![path];
+    let mut missing = controllers.to_vec();
+    loop {
+        let level = *levels.last().unwrap();
+        let available = read_controllers(&level.join("cgroup.controllers"))?;
+        missing.retain(|c| !available.iter().any(|a| a == c.name()));
+        let Some(&controller) = missing.first() else {
+            break;
+        };
+        match level.parent() {
+            Some(parent) if parent.join("cgroup.controllers").exists() => levels.push(parent),
+            // The root of the hierarchy
+            _ => {
+                return Err(ControllerError::Unavailable {
+                    level: level.to_owned(),
+                    controller,
+                })
+            }
+        }
+    }
+
+    levels.into_iter().rev().try_for_each(|level| {
+        let subtree_control = level.join("cgroup.subtree_control");
+        let enabled = read_controllers(&subtree_control)?;
+        controllers
+            .iter()
+            .filter(|c| !enabled.iter().any(|e| e == c.name()))
+            .try_for_each(|&controller| {
+                debug!("Enable controller {controller} in {}", level.display());
+                fs::write(&subtree_control, format!("+{controller}")).map_err(|source| {
+                    ControllerError::Enable {
+                        level: level.to_owned(),
+                        controller,
+                        source,
+                    }
+                })
+            })
+    })
+}
+
 /// Returns the first cgroup2 mount point found on the host system
 pub fn mount_point() -> anyhow::Result<PathBuf> {
     // TODO: This is an awful old function, replace it!
@@ -276,6 +423,68 @@ pub fn current_cgroup() -> anyhow::Result<PathBuf> {
     Ok(PathBuf::from(path))
 }
 
+/// Measures how long freezing and unfreezing a cgroup takes on this host
+///
+/// A scratch cgroup holding an idle child process is created below the cgroup
+/// at `parent`, frozen and unfrozen `rounds` times and removed again. Every
+/// transition is waited for until `cgroup.events` reports it as done. Returns
+/// the median time of a freeze and the following unfreeze, the least overhead
+/// of every partition window.
+pub fn transition_overhead(parent: &Path, rounds: usize) -> anyhow::Result<Duration> {
+    ensure!(rounds > 0, "the calibration needs at least one round");
+    let cg = CGroup::new_root(parent, &format!("calibration-{}", std::process::id()))?;
+
+    // SAFETY: The child only waits for signals, which is async-signal-safe
+    let result = match unsafe { fork() }? {
+        ForkResult::Child => loop {
+            pause();
+        },
+        ForkResult::Parent { child } => {
+            let result = cg
+                .mv_proc(child)
+                .and_then(|_| measure_transitions(&cg, rounds));
+            // Killed before it is reaped, in case it never reached the cgroup
+            kill(child, Signal::SIGKILL).ok();
+            waitpid(child, None).ok();
+            result
+        }
+    };
+    cg.rm()?;
+
+    result
+}
+
+fn measure_transitions(cg: &CGroup, rounds: usize) -> anyhow::Result<Duration> {
+    let mut times = Vec::with_capacity(rounds);
+    for _ in 0..rounds {
+        let start = Instant::now();
+        cg.freeze()?;
+        wait_for_frozen(cg, true)?;
+        cg.unfreeze()?;
+        wait_for_frozen(cg, false)?;
+        times.push(start.elapsed());
+    }
+    times.sort_unstable();
+
+    Ok(times[rounds / 2])
+}
+
+/// Spins until `cgroup.events` of `cg` reports it as `frozen` or not
+fn wait_for_frozen(cg: &CGroup, frozen: bool) -> anyhow::Result<()> {
+    let expected = format!("frozen {}\n", u8::from(frozen));
+    let deadline = Deadline::after(TRANSITION_TIMEOUT);
+    while !fs::read_to_string(cg.get_events_path())?.contains(&expected) {
+        ensure!(
+            !deadline.has_passed(),
+            "{} did not become {} within {TRANSITION_TIMEOUT:?}",
+            cg.get_path().display(),
+            if frozen { "frozen" } else { "thawed" }
+        );
+    }
+
+    Ok(())
+}
+
 /// Checks if path is a valid cgroup by comparing the device id
 fn is_cgroup(path: &Path) -> anyhow::Result<bool> {
     let st = statfs::statfs(path)?;
@@ -284,7 +493,8 @@ fn is_cgroup(path: &Path) -> anyhow::Result<bool> {
 
 #[cfg(test)]
 mod tests {
-    // The tests must be run as root with --test-threads=1
+    // The tests must be run as root with --test-threads=1, apart from those on a
+    // fake tree of cgroups
 
     use std::{io, process};
 
@@ -303,6 +513,14 @@ mod tests {
         assert!(!path.exists());
     }
 
+    #[test]
+    fn new_root_rejects_paths() {
+        for name in ["", ".", "..", "foo/bar", "../foo"] {
+            let err = CGroup::new_root(get_path(), name).unwrap_err();
+            assert!(err.to_string().contains("invalid cgroup name"), "{name}");
+        }
+    }
+
     #[test]
     fn import_root() {
         let path = get_path().join(gen_name());
@@ -348,6 +566,7 @@ mod tests {
         cg1.mv_proc(pid).unwrap();
         cg2.mv_proc(pid).unwrap();
         proc.kill().unwrap();
+        proc.wait().unwrap();
 
         cg1.rm().unwrap();
     }
@@ -378,6 +597,7 @@ mod tests {
         assert_eq!(pids[0], pid);
 
         proc.kill().unwrap();
+        proc.wait().unwrap();
 
         cg1.rm().unwrap();
     }
@@ -396,6 +616,7 @@ mod tests {
         assert_eq!(cg.populated().unwrap(), !cg.get_pids().unwrap().is_empty());
 
         proc.kill().unwrap();
+        proc.wait().unwrap();
 
         cg.rm().unwrap();
     }
@@ -423,13 +644,14 @@ mod tests {
         assert!(!cg.frozen().unwrap());
 
         proc.kill().unwrap();
+        proc.wait().unwrap();
 
         cg.rm().unwrap();
     }
 
     #[test]
     fn kill() {
-        let proc = spawn_proc().unwrap();
+        let mut proc = spawn_proc().unwrap();
         let pid = Pid::from_raw(proc.id() as i32);
         let cg = CGroup::new_root(get_path(), &gen_name()).unwrap();
 
@@ -440,6 +662,7 @@ mod tests {
         cg.mv_proc(pid).unwrap();
         assert!(cg.populated().unwrap());
         cg.kill().unwrap();
+        proc.wait().unwrap();
 
         cg.rm().unwrap();
 
@@ -447,12 +670,111 @@ mod tests {
         // because the OS may re-assign)
     }
 
+    #[test]
+    fn transition_overhead() {
+        let overhead = super::transition_overhead(&get_path(), 8).unwrap();
+        assert!(overhead > Duration::ZERO);
+        assert!(overhead < TRANSITION_TIMEOUT * 2);
+        assert!(super::transition_overhead(&get_path(), 0).is_err());
+    }
+
     #[test]
     fn is_cgroup() {
         assert!(super::is_cgroup(&get_path()).unwrap());
         assert!(!super::is_cgroup(Path::new("/tmp")).unwrap());
     }
 
+    /// Mimics the cgroupfs files of nested cgroups with plain files, given the
+    /// `cgroup.controllers` and `cgroup.subtree_control` of each level from the
+    /// top down, and returns the path of the deepest one
+    fn fake_tree(levels: &[(&str, &str)]) -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let mut path = dir.path().to_owned();
+        for (i, (controllers, subtree_control)) in levels.iter().enumerate() {
+            if i > 0 {
+                path.push(format!("level{i}"));
+                fs::create_dir(&path).unwrap();
+            }
+            fs::write(path.join("cgroup.controllers"), controllers).unwrap();
+            fs::write(path.join("cgroup.subtree_control"), subtree_control).unwrap();
+        }
+        (dir, path)
+    }
+
+    const LEVELS: &[(&str, &str)] = &[
+        ("cpu memory pids", "memory pids"),
+        ("memory pids", "pids"),
+        ("pids", ""),
+    ];
+
+    #[test]
+    fn enable_available_controllers() {
+        let (dir, path) = fake_tree(LEVELS);
+        let subtree_control =
+            |path: &Path| fs::read_to_string(path.join("cgroup.subtree_control")).unwrap();
+
+        super::enable_controllers(&path, &[Controller::Pids]).unwrap();
+        assert_eq!(subtree_control(&path), "+pids");
+        assert_eq!(subtree_control(path.parent().unwrap()), "pids");
+        assert_eq!(subtree_control(dir.path()), "memory pids");
+
+        // Enabled in the parent first, the top level has it enabled already
+        let (dir, path) = fake_tree(LEVELS);
+        super::enable_controllers(&path, &[Controller::Pids, Controller::Memory]).unwrap();
+        assert_eq!(subtree_control(&path), "+memory");
+        assert_eq!(subtree_control(path.parent().unwrap()), "+memory");
+        assert_eq!(subtree_control(dir.path()), "memory pids");
+
+        let (dir, path) = fake_tree(LEVELS);
+        super::enable_controllers(&path, &[Controller::Cpu]).unwrap();
+        assert_eq!(subtree_control(&path), "+cpu");
+        assert_eq!(subtree_control(path.parent().unwrap()), "+cpu");
+        assert_eq!(subtree_control(dir.path()), "+cpu");
+
+        super::enable_controllers(&path, &[]).unwrap();
+    }
+
+    #[test]
+    fn unavailable_controllers_name_the_level() {
+        let (dir, path) = fake_tree(LEVELS);
+        let err =
+            super::enable_controllers(&path, &[Controller::Pids, Controller::Cpuset]).unwrap_err();
+        assert!(
+            matches!(&err, ControllerError::Unavailable { level, controller: Controller::Cpuset } if level == dir.path()),
+            "{err:?}"
+        );
+        assert!(err.to_string().contains("Delegate=cpuset"), "{err}");
+        // Nothing was enabled
+        assert_eq!(
+            fs::read_to_string(path.join("cgroup.subtree_control")).unwrap(),
+            ""
+        );
+
+        fs::remove_file(path.parent().unwrap().join("cgroup.subtree_control")).unwrap();
+        let err = super::enable_controllers(&path, &[Controller::Memory]).unwrap_err();
+        assert!(
+            matches!(&err, ControllerError::Read { path: p, .. } if p.starts_with(path.parent().unwrap())),
+            "{err:?}"
+        );
+    }
+
+    #[test]
+    fn failed_enables_give_hints() {
+        let err = |errno: Errno| ControllerError::Enable {
+            level: PathBuf::from("/sys/fs/cgroup/user.slice"),
+            controller: Controller::Cpu,
+            source: io::Error::from_raw_os_error(errno as i32),
+        };
+        let msg = err(Errno::EBUSY).to_string();
+        assert!(
+            msg.contains("controller cpu in /sys/fs/cgroup/user.slice"),
+            "{msg}"
+        );
+        assert!(msg.contains("--cgroup-use-parent"), "{msg}");
+        let msg = err(Errno::EACCES).to_string();
+        assert!(msg.contains("Delegate=yes"), "{msg}");
+    }
+
     /// Spawns a child process of sleep(1)
     fn spawn_proc() -> io::Result<process::Child> {
         process::Command::new("sleep")
@@ -2,37 +2,231 @@
 // TODO: Consider merging this module with sampling, as having a module only
 // providing structs might be weird.
 use std::collections::HashSet;
+use std::num::NonZeroUsize;
+use std::time::Duration;
 
+use anyhow::anyhow;
 use bytesize::ByteSize;
-use serde::{Deserialize, Deserializer, Serialize};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{ResultExt, SystemError, TypedResult};
+use crate::queuing::Queuing;
+use crate::sampling::Sampling;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SamplingChannelConfig {
-    #[serde(deserialize_with = "de_size_str")]
+    /// Size of each message, see [crate::size] for the accepted formats
+    #[serde(deserialize_with = "crate::size::deserialize")]
     pub msg_size: ByteSize,
     pub source: PortConfig,
     pub destination: HashSet<PortConfig>,
+    #[serde(default)]
+    pub on_partition_restart: OnPartitionRestart,
+    /// Refresh period of the destination ports
+    ///
+    /// Only used by partitions creating their ports from the configuration,
+    /// otherwise the refresh period is chosen by the partition.
+    #[serde(default, with = "humantime_serde")]
+    pub refresh_period: Option<Duration>,
+    /// Stamp every message transferred to the destinations with an
+    /// incrementing sequence number
+    ///
+    /// Allows destinations to detect messages read twice or overwritten
+    /// before they were read.
+    #[serde(default)]
+    pub sequenced: bool,
+    #[serde(default)]
+    pub transfer: Transfer,
+    /// Overwrite the memory of the channel with zeros whenever a partition
+    /// connected to it is restarted, discarding its messages, and when the
+    /// hypervisor exits
+    ///
+    /// The memory mapped by the hypervisor is also excluded from its core
+    /// dumps. This only removes the residue of past messages. It does not
+    /// protect against a partition reading the messages while they are in
+    /// the channel.
+    #[serde(default)]
+    pub zeroize: bool,
+    /// Number of past messages kept by the hypervisor, e.g. for consumers
+    /// joining late
+    ///
+    /// Every message transferred to the destinations is copied into a ring of
+    /// this many messages in the memory of the hypervisor, evicting the
+    /// oldest one. The history is read with the `history` command of the
+    /// control socket. None are kept by default.
+    #[serde(default)]
+    pub history: Option<NonZeroUsize>,
+    /// Health monitor level handling malformed messages and corrupted memory
+    /// of this channel, `partition` by default
+    ///
+    /// A channel the module can not run without, e.g. one carrying actuator
+    /// commands, is `module` critical, so that its errors shut down or reset
+    /// the module as configured by the `hm_run_table`.
+    #[serde(default)]
+    pub criticality: Criticality,
 }
 
 impl SamplingChannelConfig {
     pub fn name(&self) -> &str {
         &self.source.port
     }
+
+    /// Checks that a channel can be created from this configuration
+    pub fn validate(&self) -> TypedResult<()> {
+        Sampling::checked_msg_size(self.msg_size)?;
+        if self.refresh_period == Some(Duration::ZERO) {
+            return Err(anyhow!(
+                "sampling channel {} has a refresh period of zero",
+                self.name()
+            ))
+            .typ(SystemError::Config);
+        }
+        warn_unaligned("sampling", self.name(), self.msg_size);
+        if let Some(history) = self.history {
+            let Some(size) = self.history_size() else {
+                return Err(anyhow!(
+                    "history of {history} messages of sampling channel {} exceeds the address space",
+                    self.name()
+                ))
+                .typ(SystemError::Config);
+            };
+            info!(
+                "Sampling channel {} keeps a history of {history} messages in {size} of hypervisor memory",
+                self.name()
+            );
+        }
+        Ok(())
+    }
+
+    /// Memory taken by the messages of the history of this channel, `None` if
+    /// it exceeds the address space
+    pub fn history_size(&self) -> Option<ByteSize> {
+        let history = self.history.map_or(0, NonZeroUsize::get);
+        usize::try_from(self.msg_size.as_u64())
+            .ok()?
+            .checked_mul(history)
+            .map(|size| ByteSize::b(size as u64))
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct QueuingChannelConfig {
-    #[serde(deserialize_with = "de_size_str")]
+    /// Size of each message, see [crate::size] for the accepted formats
+    #[serde(deserialize_with = "crate::size::deserialize")]
     pub msg_size: ByteSize,
     pub msg_num: usize,
     pub source: PortConfig,
     pub destination: PortConfig,
+    #[serde(default)]
+    pub on_partition_restart: OnPartitionRestart,
+    /// Maximum number of messages moved to the destination per swap
+    ///
+    /// Bounds the time spent on this channel after each partition window.
+    /// Messages beyond the limit stay at the source until the next swap.
+    /// Unlimited by default.
+    #[serde(default)]
+    pub max_swap_per_frame: Option<NonZeroUsize>,
+    #[serde(default)]
+    pub transfer: Transfer,
+    /// Overwrite the memory of the channel with zeros whenever a partition
+    /// connected to it is restarted, discarding its messages, and when the
+    /// hypervisor exits
+    ///
+    /// The memory mapped by the hypervisor is also excluded from its core
+    /// dumps. This only removes the residue of past messages. It does not
+    /// protect against a partition reading the messages while they are in
+    /// the channel.
+    #[serde(default)]
+    pub zeroize: bool,
+    /// Deliver the messages transferred to the destination but not read yet
+    /// again after the channel was cleared on a restart
+    ///
+    /// The hypervisor keeps a copy of each message until the destination has
+    /// read it. The delivery is at least once, so a destination restarted
+    /// while reading may receive a message twice, which applications must
+    /// tolerate. Only makes a difference with `on_partition_restart: clear`,
+    /// and contradicts `zeroize`.
+    #[serde(default)]
+    pub reliable: bool,
+    /// Health monitor level handling malformed messages and corrupted memory
+    /// of this channel, `partition` by default
+    ///
+    /// A channel the module can not run without, e.g. one carrying actuator
+    /// commands, is `module` critical, so that its errors shut down or reset
+    /// the module as configured by the `hm_run_table`.
+    #[serde(default)]
+    pub criticality: Criticality,
 }
 
 impl QueuingChannelConfig {
     pub fn name(&self) -> &str {
         &self.source.port
     }
+
+    /// Checks that a channel can be created from this configuration
+    pub fn validate(&self) -> TypedResult<()> {
+        Queuing::checked_msg_size(self.msg_size, self.msg_num)?;
+        if self.reliable && self.zeroize {
+            return Err(anyhow!(
+                "queuing channel {} can not be both reliable and zeroized",
+                self.name()
+            ))
+            .typ(SystemError::Config);
+        }
+        warn_unaligned("queuing", self.name(), self.msg_size);
+        Ok(())
+    }
+}
+
+/// What happens to the messages of a channel when a partition connected to it
+/// is restarted
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnPartitionRestart {
+    /// Leave all messages untouched
+    #[default]
+    Keep,
+    /// Discard all messages. Sampling ports read no message until the source
+    /// writes a new one, queuing ports are emptied.
+    Clear,
+}
+
+/// Which health monitor handles the errors of a channel
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Criticality {
+    /// Errors are raised to the health monitor of the partition writing the
+    /// affected memory, which handles them with its own table
+    #[default]
+    Partition,
+    /// Errors are raised to the module, which handles them with its
+    /// `hm_run_table`, bypassing the tables of the partitions
+    Module,
+}
+
+/// When the messages written to the source port of a channel are transferred
+/// to its destinations
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Transfer {
+    /// Right after the window of the source partition, as required by ARINC
+    /// 653. Partitions running later in the same major frame see the messages.
+    #[default]
+    AfterSourceWindow,
+    /// At the end of the major frame, together with all other channels
+    /// transferred at this point. Destinations see a consistent snapshot of
+    /// the whole frame, but only in the next major frame.
+    FrameBoundary,
+}
+
+/// Warns about message sizes which are likely to be a typo
+fn warn_unaligned(kind: &str, name: &str, msg_size: ByteSize) {
+    if !msg_size.as_u64().is_multiple_of(8) {
+        warn!(
+            "{kind} channel {name}: msg_size of {} bytes is not a multiple of 8",
+            msg_size.as_u64()
+        );
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Hash, PartialEq, Eq)]
@@ -47,11 +241,173 @@ impl PortConfig {
     }
 }
 
-fn de_size_str<'de, D>(de: D) -> Result<ByteSize, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    String::deserialize(de)?
-        .parse::<ByteSize>()
-        .map_err(serde::de::Error::custom)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn on_partition_restart_defaults_to_keep() {
+        let yaml = r#"
+msg_size: 16B
+source: { partition: a, port: out }
+destination: [ { partition: b, port: in } ]
+"#;
+        let config: SamplingChannelConfig = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.on_partition_restart, OnPartitionRestart::Keep);
+
+        let yaml = r#"
+msg_size: 16B
+msg_num: 4
+source: { partition: a, port: out }
+destination: { partition: b, port: in }
+on_partition_restart: clear
+"#;
+        let config: QueuingChannelConfig = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.on_partition_restart, OnPartitionRestart::Clear);
+        assert_eq!(config.max_swap_per_frame, None);
+        assert!(!config.zeroize);
+    }
+
+    #[test]
+    fn criticality_defaults_to_partition() {
+        let yaml = r#"
+msg_size: 16B
+source: { partition: a, port: out }
+destination: [ { partition: b, port: in } ]
+"#;
+        let config: SamplingChannelConfig = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.criticality, Criticality::Partition);
+
+        let yaml = r#"
+msg_size: 16B
+msg_num: 4
+source: { partition: a, port: out }
+destination: { partition: b, port: in }
+criticality: module
+"#;
+        let config: QueuingChannelConfig = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.criticality, Criticality::Module);
+    }
+
+    #[test]
+    fn transfer() {
+        let yaml = r#"
+msg_size: 16B
+source: { partition: a, port: out }
+destination: [ { partition: b, port: in } ]
+"#;
+        let config: SamplingChannelConfig = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.transfer, Transfer::AfterSourceWindow);
+
+        let config: SamplingChannelConfig =
+            serde_yaml::from_str(&format!("{yaml}transfer: frame_boundary\n")).unwrap();
+        assert_eq!(config.transfer, Transfer::FrameBoundary);
+        assert!(serde_yaml::from_str::<SamplingChannelConfig>(&format!(
+            "{yaml}transfer: end_of_frame\n"
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn msg_size_formats() {
+        let yaml = r#"
+msg_size: 1000
+source: { partition: a, port: out }
+destination: [ { partition: b, port: in } ]
+"#;
+        let config: SamplingChannelConfig = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.msg_size, ByteSize::b(1000));
+
+        let yaml = yaml.replace("1000", "16MiB");
+        let config: SamplingChannelConfig = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(config.msg_size, ByteSize::mib(16));
+
+        let yaml = yaml.replace("16MiB", "16M");
+        assert!(serde_yaml::from_str::<SamplingChannelConfig>(&yaml).is_err());
+    }
+
+    #[test]
+    fn max_swap_per_frame() {
+        let yaml = r#"
+msg_size: 16B
+msg_num: 4
+source: { partition: a, port: out }
+destination: { partition: b, port: in }
+max_swap_per_frame: 2
+"#;
+        let config: QueuingChannelConfig = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.max_swap_per_frame, NonZeroUsize::new(2));
+
+        let yaml = yaml.replace("max_swap_per_frame: 2", "max_swap_per_frame: 0");
+        assert!(serde_yaml::from_str::<QueuingChannelConfig>(&yaml).is_err());
+    }
+
+    #[test]
+    fn reliable() {
+        let yaml = r#"
+msg_size: 16B
+msg_num: 4
+source: { partition: a, port: out }
+destination: { partition: b, port: in }
+"#;
+        let config: QueuingChannelConfig = serde_yaml::from_str(yaml).unwrap();
+        assert!(!config.reliable);
+
+        let config: QueuingChannelConfig =
+            serde_yaml::from_str(&format!("{yaml}reliable: true\n")).unwrap();
+        assert!(config.reliable);
+        config.validate().unwrap();
+
+        let config: QueuingChannelConfig =
+            serde_yaml::from_str(&format!("{yaml}reliable: true\nzeroize: true\n")).unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn refresh_period() {
+        let yaml = r#"
+msg_size: 16B
+source: { partition: a, port: out }
+destination: [ { partition: b, port: in } ]
+"#;
+        let config: SamplingChannelConfig = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.refresh_period, None);
+        assert!(!config.sequenced);
+
+        let config: SamplingChannelConfig =
+            serde_yaml::from_str(&format!("{yaml}refresh_period: 200ms\n")).unwrap();
+        assert_eq!(config.refresh_period, Some(Duration::from_millis(200)));
+        config.validate().unwrap();
+
+        let config: SamplingChannelConfig =
+            serde_yaml::from_str(&format!("{yaml}refresh_period: 0s\n")).unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn history() {
+        let yaml = r#"
+msg_size: 1KiB
+source: { partition: a, port: out }
+destination: [ { partition: b, port: in } ]
+"#;
+        let config: SamplingChannelConfig = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.history, None);
+        assert_eq!(config.history_size(), Some(ByteSize::b(0)));
+
+        let config: SamplingChannelConfig =
+            serde_yaml::from_str(&format!("{yaml}history: 16\n")).unwrap();
+        assert_eq!(config.history, NonZeroUsize::new(16));
+        assert_eq!(config.history_size(), Some(ByteSize::kib(16)));
+        config.validate().unwrap();
+
+        let config: SamplingChannelConfig =
+            serde_yaml::from_str(&format!("{yaml}history: {}\n", usize::MAX)).unwrap();
+        assert_eq!(config.history_size(), None);
+        assert!(config.validate().is_err());
+
+        assert!(
+            serde_yaml::from_str::<SamplingChannelConfig>(&format!("{yaml}history: 0\n")).is_err()
+        );
+    }
 }
@@ -0,0 +1,134 @@
+//! Conditions of the module affecting a partition
+//!
+//! A failure of the hypervisor on behalf of a partition, e.g. a channel
+//! dropping malformed messages, otherwise only shows as stale data in the
+//! partition. The hypervisor therefore keeps a word of [ModuleConditions] for
+//! every partition in a [TempFile](crate::file::TempFile), which it updates at
+//! the start of every major frame. A condition is set as long as its cause
+//! lasts and cleared again once it resolved.
+
+use std::fmt::Display;
+use std::ops::{BitOr, BitOrAssign};
+
+use serde::{Deserialize, Serialize};
+
+/// Set of conditions of the module affecting a partition
+///
+/// The bits must never change, as they are shared with partitions built
+/// against other versions. Bits unknown to this version are kept.
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ModuleConditions(u32);
+
+impl ModuleConditions {
+    /// No condition is set
+    pub const NONE: Self = Self(0);
+    /// A channel connected to the partition dropped malformed messages during
+    /// the last major frame
+    pub const CHANNEL_DEGRADED: Self = Self(1);
+    /// A channel connected to the partition is bridged to an MQTT broker,
+    /// which the bridge is not connected to
+    pub const BRIDGE_DOWN: Self = Self(1 << 1);
+    /// The host clock was stepped during the last major frame, so that wall
+    /// clock time and module time diverged
+    pub const CLOCK_STEPPED: Self = Self(1 << 2);
+
+    const NAMES: [(Self, &'static str); 3] = [
+        (Self::CHANNEL_DEGRADED, "channel degraded"),
+        (Self::BRIDGE_DOWN, "bridge down"),
+        (Self::CLOCK_STEPPED, "clock stepped"),
+    ];
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    /// Whether no condition is set
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether all conditions of `other` are set
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Sets or clears the conditions of `other`
+    pub fn set(&mut self, other: Self, value: bool) {
+        if value {
+            self.0 |= other.0;
+        } else {
+            self.0 &= !other.0;
+        }
+    }
+
+    /// Names of the conditions known to this version which are set
+    pub fn names(self) -> impl Iterator<Item = &'static str> {
+        Self::NAMES
+            .into_iter()
+            .filter(move |(condition, _)| self.contains(*condition))
+            .map(|(_, name)| name)
+    }
+}
+
+impl BitOr for ModuleConditions {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for ModuleConditions {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl Display for ModuleConditions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return f.write_str("none");
+        }
+        let mut names = self.names().map(str::to_owned).collect::<Vec<_>>();
+        let known = Self::NAMES
+            .iter()
+            .fold(Self::NONE, |known, (condition, _)| known | *condition);
+        let unknown = self.0 & !known.0;
+        if unknown != 0 {
+            names.push(format!("unknown {unknown:#x}"));
+        }
+        f.write_str(&names.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conditions() {
+        let mut conditions = ModuleConditions::NONE;
+        assert!(conditions.is_empty());
+        assert_eq!(conditions.to_string(), "none");
+
+        conditions.set(ModuleConditions::CHANNEL_DEGRADED, true);
+        conditions |= ModuleConditions::CLOCK_STEPPED;
+        assert!(conditions.contains(ModuleConditions::CHANNEL_DEGRADED));
+        assert!(!conditions.contains(ModuleConditions::BRIDGE_DOWN));
+        assert_eq!(conditions.bits(), 0b101);
+        assert_eq!(conditions.to_string(), "channel degraded, clock stepped");
+
+        conditions.set(ModuleConditions::CHANNEL_DEGRADED, false);
+        assert_eq!(conditions, ModuleConditions::CLOCK_STEPPED);
+
+        // Conditions of newer versions are kept
+        let conditions = ModuleConditions::from_bits(0b1000_0010);
+        assert_eq!(conditions.bits(), 0b1000_0010);
+        assert_eq!(conditions.to_string(), "bridge down, unknown 0x80");
+    }
+}
@@ -0,0 +1,149 @@
+//! Absolute deadlines on the monotonic clock
+//!
+//! Waiting with a relative timeout in a loop requires reading the clock again
+//! on every iteration, and each place doing so had its own edge cases once the
+//! time ran out. A [Deadline] is fixed once and answers how much time is left
+//! from then on, saturating at zero once it passed. Its
+//! [poll timeout](Deadline::poll_timeout) is never `None`, so that an expired
+//! deadline never turns into a wait without a timeout.
+
+use std::thread::sleep;
+use std::time::Duration;
+
+use crate::time::MonotonicTime;
+
+/// A point of the monotonic clock by which something has to be done
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Deadline(MonotonicTime);
+
+impl Deadline {
+    /// A deadline at `time`
+    pub fn at(time: MonotonicTime) -> Self {
+        Self(time)
+    }
+
+    /// A deadline `timeout` from now, saturating at the largest representable
+    /// time
+    pub fn after(timeout: Duration) -> Self {
+        Self::after_at(timeout, MonotonicTime::now())
+    }
+
+    fn after_at(timeout: Duration, now: MonotonicTime) -> Self {
+        Self(
+            now.checked_add(timeout)
+                .unwrap_or_else(|| MonotonicTime::from(Duration::MAX)),
+        )
+    }
+
+    /// Point in time of the deadline
+    pub fn time(&self) -> MonotonicTime {
+        self.0
+    }
+
+    /// Time left until the deadline, zero once it passed
+    pub fn remaining(&self) -> Duration {
+        self.remaining_at(MonotonicTime::now())
+    }
+
+    fn remaining_at(&self, now: MonotonicTime) -> Duration {
+        self.0.duration_since(now)
+    }
+
+    /// Whether the deadline passed, i.e. no time is left
+    pub fn has_passed(&self) -> bool {
+        self.has_passed_at(MonotonicTime::now())
+    }
+
+    fn has_passed_at(&self, now: MonotonicTime) -> bool {
+        now >= self.0
+    }
+
+    /// Timeout for waiting on a [Poller](polling::Poller) until the deadline
+    ///
+    /// A deadline which passed results in a zero timeout, which polls without
+    /// blocking.
+    pub fn poll_timeout(&self) -> Option<Duration> {
+        Some(self.remaining())
+    }
+
+    /// Sleeps until the deadline, returning at once if it passed
+    pub fn sleep(&self) {
+        let remaining = self.remaining();
+        if !remaining.is_zero() {
+            sleep(remaining);
+        }
+    }
+
+    /// Deadline of the first of two phases sharing the budget from `start` up
+    /// to this deadline, where the second phase gets the last `reserve` of it
+    ///
+    /// A reserve exceeding the budget leaves nothing to the first phase, whose
+    /// deadline is then `start`.
+    pub fn before_reserve(&self, start: MonotonicTime, reserve: Duration) -> Deadline {
+        let end = self.0.as_duration().saturating_sub(reserve);
+        Self(MonotonicTime::from(end).max(start))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(millis: u64) -> MonotonicTime {
+        MonotonicTime::from(Duration::from_millis(millis))
+    }
+
+    #[test]
+    fn remaining_time() {
+        let deadline = Deadline::at(time(100));
+        assert_eq!(deadline.remaining_at(time(40)), Duration::from_millis(60));
+        assert!(!deadline.has_passed_at(time(40)));
+
+        // Zero once reached or passed
+        assert_eq!(deadline.remaining_at(time(100)), Duration::ZERO);
+        assert!(deadline.has_passed_at(time(100)));
+        assert_eq!(deadline.remaining_at(time(500)), Duration::ZERO);
+        assert!(deadline.has_passed_at(time(500)));
+    }
+
+    #[test]
+    fn zero_timeout() {
+        let deadline = Deadline::after_at(Duration::ZERO, time(100));
+        assert_eq!(deadline, Deadline::at(time(100)));
+        assert!(deadline.has_passed_at(time(100)));
+
+        let passed = Deadline::after(Duration::ZERO);
+        assert!(passed.has_passed());
+        assert_eq!(passed.remaining(), Duration::ZERO);
+        assert_eq!(passed.poll_timeout(), Some(Duration::ZERO));
+        // Returns at once
+        passed.sleep();
+    }
+
+    #[test]
+    fn saturating_timeout() {
+        let deadline = Deadline::after_at(Duration::MAX, time(100));
+        assert_eq!(deadline.time(), MonotonicTime::from(Duration::MAX));
+        assert!(!deadline.has_passed());
+        assert!(deadline.poll_timeout().unwrap() > Duration::from_secs(3600 * 24 * 365));
+    }
+
+    #[test]
+    fn phases() {
+        let deadline = Deadline::at(time(100));
+        assert_eq!(
+            deadline.before_reserve(time(50), Duration::from_millis(20)),
+            Deadline::at(time(80))
+        );
+        assert_eq!(deadline.before_reserve(time(50), Duration::ZERO), deadline);
+        // The reserve takes the whole budget, or more
+        assert_eq!(
+            deadline.before_reserve(time(50), Duration::from_millis(50)),
+            Deadline::at(time(50))
+        );
+        assert_eq!(
+            deadline.before_reserve(time(50), Duration::from_millis(300)),
+            Deadline::at(time(50))
+        );
+    }
+}
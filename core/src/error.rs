@@ -2,6 +2,8 @@
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::health::ModuleRecoveryAction;
+
 /// A Result containing a SystemError with its accompanying source
 pub type TypedResult<T> = Result<T, TypedError>;
 /// A Result containing a SystemError with its accompanying error and time
@@ -15,7 +17,7 @@ pub type LeveledResult<T> = Result<T, LeveledError>;
 /// errnos.
 // TODO: Why can't we just use traditional unix errnos? The anyhow messages should be
 // concrete enough.
-#[derive(Error, Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Error, Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum SystemError {
     #[error("Configuration error")]
     Config,
@@ -82,12 +84,24 @@ pub struct LeveledError {
     err: SystemError,
     level: ErrorLevel,
     source: anyhow::Error,
+    module_action: Option<ModuleRecoveryAction>,
 }
 
 impl LeveledError {
     /// Creates a new LeveledError
     pub fn new(err: SystemError, level: ErrorLevel, source: anyhow::Error) -> Self {
-        Self { err, level, source }
+        Self {
+            err,
+            level,
+            source,
+            module_action: None,
+        }
+    }
+    /// Attaches the module recovery action a partition HM table escalated
+    /// this error with
+    pub fn with_module_action(mut self, action: ModuleRecoveryAction) -> Self {
+        self.module_action = Some(action);
+        self
     }
     /// Returns the SystemError of this TypedError
     pub fn err(&self) -> SystemError {
@@ -101,6 +115,11 @@ impl LeveledError {
     pub fn source(&self) -> &anyhow::Error {
         &self.source
     }
+    /// Returns the module recovery action this error was escalated with, if
+    /// any
+    pub fn module_action(&self) -> Option<ModuleRecoveryAction> {
+        self.module_action
+    }
 }
 impl From<LeveledError> for TypedError {
     fn from(le: LeveledError) -> Self {
@@ -130,11 +149,7 @@ impl<T> TypedResultExt<T> for TypedResult<T> {
     fn lev(self, level: ErrorLevel) -> LeveledResult<T> {
         // This basically just creates a LeveledError with all fields tken even from
         // the TypedResult, except the level being added.
-        self.map_err(|e| LeveledError {
-            err: e.err,
-            level,
-            source: e.source,
-        })
+        self.map_err(|e| LeveledError::new(e.err, level, e.source))
     }
 }
 
@@ -147,10 +162,50 @@ impl<T, E: Into<anyhow::Error>> ResultExt<T> for Result<T, E> {
     }
 
     fn lev_typ(self, err: SystemError, level: ErrorLevel) -> LeveledResult<T> {
-        self.map_err(|e| LeveledError {
-            err,
-            level,
-            source: e.into(),
-        })
+        self.map_err(|e| LeveledError::new(err, level, e.into()))
+    }
+}
+
+/// Adds a hint on raising the limit of open files to an error caused by
+/// running out of file descriptors
+///
+/// The hypervisor keeps file descriptors open for every partition and
+/// channel, so large configurations may exceed the default limit. Other
+/// errors are returned unchanged.
+pub fn fd_limit_hint(err: impl Into<anyhow::Error>) -> anyhow::Error {
+    let err = err.into();
+    let errno = err.chain().find_map(|e| {
+        e.downcast_ref::<std::io::Error>()
+            .and_then(std::io::Error::raw_os_error)
+            .or_else(|| e.downcast_ref::<nix::errno::Errno>().map(|e| *e as i32))
+    });
+    match errno {
+        Some(nix::libc::EMFILE) => err.context(
+            "the hypervisor ran out of file descriptors, raise its limit with `ulimit -n` or \
+             LimitNOFILE of its systemd unit",
+        ),
+        Some(nix::libc::ENFILE) => err.context(
+            "the system ran out of file descriptors, raise its limit with the fs.file-max sysctl",
+        ),
+        _ => err,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::errno::Errno;
+
+    use super::*;
+
+    #[test]
+    fn fd_limit_hints() {
+        let err = fd_limit_hint(std::io::Error::from_raw_os_error(nix::libc::EMFILE));
+        assert!(format!("{err:#}").contains("ulimit -n"), "{err:#}");
+
+        let err = fd_limit_hint(anyhow::Error::from(Errno::ENFILE).context("failed to create"));
+        assert!(format!("{err:#}").contains("fs.file-max"), "{err:#}");
+
+        let err = fd_limit_hint(Errno::EACCES);
+        assert_eq!(format!("{err:#}"), Errno::EACCES.to_string());
     }
 }
@@ -4,13 +4,14 @@
 // https://doc.rust-lang.org/stable/std/os/linux/process/struct.PidFd.html
 use std::io::ErrorKind;
 use std::os::unix::prelude::{AsRawFd, FromRawFd, OwnedFd, RawFd};
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 use anyhow::{anyhow, Result};
 use nix::libc::{c_uint, syscall, SYS_pidfd_open};
 use nix::unistd::Pid;
 use polling::{Event, Events, Poller};
 
+use crate::deadline::Deadline;
 use crate::error::{ResultExt, SystemError, TypedError, TypedResult};
 
 #[derive(Debug)]
@@ -37,7 +38,7 @@ pub struct PidFd(OwnedFd);
 impl PidFd {
     /// Returns when the PidFd is ready to be read or if timeout occurred
     pub fn wait_exited_timeout(&self, timeout: Duration) -> Result<(), PidWaitError> {
-        let now = Instant::now();
+        let deadline = Deadline::after(timeout);
 
         let poller = Poller::new()
             .map_err(anyhow::Error::from)
@@ -52,12 +53,11 @@ impl PidFd {
                 .map_err(anyhow::Error::from)
                 .typ(SystemError::Panic)?;
 
-            let poll_res = poller.wait(
-                &mut Events::new(),
-                Some(timeout.saturating_sub(now.elapsed())),
-            );
+            let poll_res = poller.wait(&mut Events::new(), deadline.poll_timeout());
             match poll_res {
-                Ok(0) => return Err(PidWaitError::Timeout),
+                // The wait may return spuriously before the deadline
+                Ok(0) if deadline.has_passed() => return Err(PidWaitError::Timeout),
+                Ok(0) => {}
                 Ok(_) => return Ok(()),
                 Err(e) => {
                     if e.kind() != ErrorKind::Interrupted {
@@ -9,7 +9,7 @@ use memmap2::{Mmap, MmapMut};
 use nix::unistd::{close, dup};
 use procfs::process::{FDTarget, Process};
 
-use crate::error::{ResultExt, SystemError, TypedError, TypedResult};
+use crate::error::{fd_limit_hint, ResultExt, SystemError, TypedError, TypedResult};
 use crate::shmem::{TypedMmap, TypedMmapMut};
 
 #[derive(Debug, Clone, Copy)]
@@ -28,6 +28,7 @@ impl<T: Send + Clone + Sized> TempFile<T> {
             .close_on_exec(false)
             .allow_sealing(true)
             .create(name)
+            .map_err(fd_limit_hint)
             .typ(SystemError::Panic)?;
         mem.as_file()
             .set_len(
@@ -58,7 +59,7 @@ impl<T: Send + Clone + Sized> TempFile<T> {
     }
 
     /// Set the TempFile to read-only (prevents further seal modifications)
-    pub fn seal_read_only(&self) -> TypedResult<TypedMmapMut<T>> {
+    pub fn seal_read_only<'a>(&self) -> TypedResult<TypedMmapMut<'a, T>> {
         let mmap = self.get_typed_mmap_mut()?;
 
         self.get_memfd()?
@@ -114,32 +115,34 @@ impl<T: Send + Clone + Sized> TempFile<T> {
         Ok(unsafe { data.assume_init() })
     }
 
+    /// Returns the bytes of the TempFile's data without interpreting them as a
+    /// `T`, e.g. for checking them first
+    pub fn read_bytes(&self) -> TypedResult<Vec<u8>> {
+        let mut buf = vec![0; size_of::<T>()];
+        let file = self.get_memfd()?.into_file();
+        let bytes_read = file.read_at(&mut buf, 0).typ(SystemError::Panic)?;
+        buf.truncate(bytes_read);
+        Ok(buf)
+    }
+
     /// Returns a mutable memory map from a TempFile
-    pub fn get_typed_mmap_mut(&self) -> TypedResult<TypedMmapMut<T>> {
-        let fd = dup(self.fd).typ(SystemError::Panic)?;
-        unsafe {
-            MmapMut::map_mut(fd)
-                .map_err(|e| {
-                    close(fd).ok();
-                    anyhow!("Could not get Mmap from {e:#?}")
-                })
-                .typ(SystemError::Panic)?
-                .try_into()
-        }
+    ///
+    /// The map needs neither the fd nor the TempFile, so it may outlive both.
+    pub fn get_typed_mmap_mut<'a>(&self) -> TypedResult<TypedMmapMut<'a, T>> {
+        unsafe { MmapMut::map_mut(self.fd) }
+            .map_err(|e| anyhow!("Could not get Mmap from {e:#?}"))
+            .typ(SystemError::Panic)?
+            .try_into()
     }
 
     /// Returns a memory map from a TemplFile
-    pub fn get_typed_mmap(&self) -> TypedResult<TypedMmap<T>> {
-        let fd = dup(self.fd).typ(SystemError::Panic)?;
-        unsafe {
-            Mmap::map(fd)
-                .map_err(|e| {
-                    close(fd).ok();
-                    anyhow!("Could not get Mmap from {e:#?}")
-                })
-                .typ(SystemError::Panic)?
-                .try_into()
-        }
+    ///
+    /// The map needs neither the fd nor the TempFile, so it may outlive both.
+    pub fn get_typed_mmap<'a>(&self) -> TypedResult<TypedMmap<'a, T>> {
+        unsafe { Mmap::map(self.fd) }
+            .map_err(|e| anyhow!("Could not get Mmap from {e:#?}"))
+            .typ(SystemError::Panic)?
+            .try_into()
     }
 }
 
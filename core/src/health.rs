@@ -1,36 +1,86 @@
 //! Health control types
+//!
+//! Errors of a partition are first looked up in its [PartitionHMTable]. A
+//! [RecoveryAction::Partition] is applied to the partition alone, while a
+//! [RecoveryAction::Module] escalates the error to the module, carrying the
+//! action along. The module applies exactly that action, regardless of what
+//! its own tables say about the error. Only errors without an entry in the
+//! partition table, and errors of the module itself, are looked up in the
+//! [ModuleInitHMTable] or [ModuleRunHMTable], falling back to their `panic`
+//! entry. See [module_action].
+//!
+//! Every entry of a table has a default, so a table only needs to list the
+//! errors it handles differently, down to an empty table `{}`. Actions are
+//! written in CamelCase like `ColdStart`, but lowercase and snake_case names
+//! like `coldstart` or `cold_start` are accepted as well:
+//!
+//! ```yaml
+//! hm_table:
+//!   segmentation: !Partition cold_start
+//!   panic: !Module Reset
+//! ```
 use serde::{Deserialize, Serialize};
 
-use crate::error::SystemError;
+use crate::error::{ErrorLevel, LeveledError, SystemError};
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+/// Action of a [PartitionHMTable]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum RecoveryAction {
+    /// Escalates the error to the module, which applies the given action
+    #[serde(alias = "module")]
     Module(ModuleRecoveryAction),
+    /// Handles the error by changing the operating mode of the partition
+    #[serde(alias = "partition")]
     Partition(PartitionRecoveryAction),
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum ModuleRecoveryAction {
+    /// Keeps running the module
+    #[serde(alias = "ignore")]
     Ignore,
+    /// Stops the hypervisor
+    #[serde(alias = "shutdown")]
     Shutdown,
+    /// Restarts the hypervisor with all partitions
+    #[serde(alias = "reset")]
     Reset,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum PartitionRecoveryAction {
+    /// Stops the partition until the module is restarted
+    #[serde(alias = "idle")]
     Idle,
+    #[serde(alias = "coldstart", alias = "cold_start")]
     ColdStart,
+    #[serde(alias = "warmstart", alias = "warm_start")]
     WarmStart,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Actions of a partition for its errors
+///
+/// Every entry defaults to the action of [PartitionHMTable::default].
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
 pub struct PartitionHMTable {
+    /// The partition failed to start, defaults to `!Module Ignore`
     pub partition_init: RecoveryAction,
+    /// Defaults to `!Partition WarmStart`
     pub segmentation: RecoveryAction,
+    /// The periodic process overran its window, defaults to `!Module Ignore`
     pub time_duration_exceeded: RecoveryAction,
+    /// Raised by the partition itself, defaults to `!Partition WarmStart`
     pub application_error: RecoveryAction,
+    /// Defaults to `!Partition WarmStart`
     pub panic: RecoveryAction,
+    /// Defaults to `!Partition WarmStart`
     pub floating_point_error: RecoveryAction,
+    /// The hypervisor failed to manage the cgroups of the partition, defaults
+    /// to `!Module Shutdown`
+    ///
+    /// This is a fault of the module rather than of the partition, so
+    /// partition actions are warned about by [PartitionHMTable::warnings].
     pub cgroup: RecoveryAction,
 }
 
@@ -47,6 +97,23 @@ impl PartitionHMTable {
             _ => None,
         }
     }
+
+    /// Whether errors of type `err` are ignored by this table
+    pub fn ignores(&self, err: SystemError) -> bool {
+        self.try_action(err) == Some(RecoveryAction::Module(ModuleRecoveryAction::Ignore))
+    }
+
+    /// Describes entries which are likely a mistake, like partition actions
+    /// for errors of the module
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if let RecoveryAction::Partition(action) = self.cgroup {
+            warnings.push(format!(
+                "cgroup errors are errors of the module, but are handled by the partition action {action:?}"
+            ));
+        }
+        warnings
+    }
 }
 
 impl Default for PartitionHMTable {
@@ -59,16 +126,23 @@ impl Default for PartitionHMTable {
             floating_point_error: RecoveryAction::Partition(PartitionRecoveryAction::WarmStart),
             panic: RecoveryAction::Partition(PartitionRecoveryAction::WarmStart),
             application_error: RecoveryAction::Partition(PartitionRecoveryAction::WarmStart),
-            cgroup: RecoveryAction::Partition(PartitionRecoveryAction::WarmStart),
+            cgroup: RecoveryAction::Module(ModuleRecoveryAction::Shutdown),
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Actions of the module for errors while it starts
+///
+/// Every entry defaults to `Shutdown`. `panic` also applies to all errors
+/// without an entry.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
 pub struct ModuleInitHMTable {
+    /// Invalid configuration file
     pub config: ModuleRecoveryAction,
     pub module_config: ModuleRecoveryAction,
     pub partition_config: ModuleRecoveryAction,
+    /// A partition failed to start
     pub partition_init: ModuleRecoveryAction,
     pub panic: ModuleRecoveryAction,
 }
@@ -98,8 +172,15 @@ impl Default for ModuleInitHMTable {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Actions of the module for errors while the schedule runs, including those
+/// escalated by partitions without a module action
+///
+/// Every entry defaults to `Shutdown`. `panic` also applies to all errors
+/// without an entry.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
 pub struct ModuleRunHMTable {
+    /// A partition failed to start
     pub partition_init: ModuleRecoveryAction,
     pub panic: ModuleRecoveryAction,
 }
@@ -122,3 +203,200 @@ impl Default for ModuleRunHMTable {
         }
     }
 }
+
+/// Decides the recovery action of the module for an error which reached it
+///
+/// An action a partition HM table escalated the error with takes precedence
+/// over the module tables.
+pub fn module_action(
+    err: &LeveledError,
+    init: &ModuleInitHMTable,
+    run: &ModuleRunHMTable,
+) -> ModuleRecoveryAction {
+    if let Some(action) = err.module_action() {
+        return action;
+    }
+    match err.level() {
+        ErrorLevel::ModuleInit => init.try_action(err.err()).unwrap_or(init.panic),
+        // Partition errors escalate while the module is running
+        ErrorLevel::ModuleRun | ErrorLevel::Partition => {
+            run.try_action(err.err()).unwrap_or(run.panic)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::*;
+
+    fn escalated(err: SystemError, action: Option<ModuleRecoveryAction>) -> LeveledError {
+        let err = LeveledError::new(err, ErrorLevel::Partition, anyhow!("escalated"));
+        match action {
+            Some(action) => err.with_module_action(action),
+            None => err,
+        }
+    }
+
+    #[test]
+    fn partition_table_takes_precedence() {
+        let init = ModuleInitHMTable::default();
+        let run = ModuleRunHMTable {
+            partition_init: ModuleRecoveryAction::Ignore,
+            panic: ModuleRecoveryAction::Reset,
+        };
+
+        // Both tables have an entry, the one of the partition wins
+        let err = escalated(
+            SystemError::PartitionInit,
+            Some(ModuleRecoveryAction::Shutdown),
+        );
+        assert_eq!(
+            module_action(&err, &init, &run),
+            ModuleRecoveryAction::Shutdown
+        );
+
+        // Only the partition table has an entry
+        let err = escalated(
+            SystemError::Segmentation,
+            Some(ModuleRecoveryAction::Ignore),
+        );
+        assert_eq!(
+            module_action(&err, &init, &run),
+            ModuleRecoveryAction::Ignore
+        );
+
+        // Only the module table has an entry
+        let err = escalated(SystemError::PartitionInit, None);
+        assert_eq!(
+            module_action(&err, &init, &run),
+            ModuleRecoveryAction::Ignore
+        );
+
+        // Neither table has an entry
+        let err = escalated(SystemError::Segmentation, None);
+        assert_eq!(
+            module_action(&err, &init, &run),
+            ModuleRecoveryAction::Reset
+        );
+    }
+
+    #[test]
+    fn empty_tables_are_defaults() {
+        assert_eq!(
+            serde_yaml::from_str::<PartitionHMTable>("{}").unwrap(),
+            PartitionHMTable::default()
+        );
+        assert_eq!(
+            serde_yaml::from_str::<ModuleInitHMTable>("{}").unwrap(),
+            ModuleInitHMTable::default()
+        );
+        assert_eq!(
+            serde_yaml::from_str::<ModuleRunHMTable>("{}").unwrap(),
+            ModuleRunHMTable::default()
+        );
+
+        // Missing entries keep their default
+        let run: ModuleRunHMTable = serde_yaml::from_str("panic: Reset").unwrap();
+        assert_eq!(run.panic, ModuleRecoveryAction::Reset);
+        assert_eq!(run.partition_init, ModuleRecoveryAction::Shutdown);
+    }
+
+    #[test]
+    fn tables_round_trip() {
+        let partition = PartitionHMTable {
+            segmentation: RecoveryAction::Partition(PartitionRecoveryAction::ColdStart),
+            panic: RecoveryAction::Module(ModuleRecoveryAction::Reset),
+            ..Default::default()
+        };
+        let yaml = serde_yaml::to_string(&partition).unwrap();
+        assert_eq!(
+            serde_yaml::from_str::<PartitionHMTable>(&yaml).unwrap(),
+            partition
+        );
+
+        let init = ModuleInitHMTable {
+            config: ModuleRecoveryAction::Ignore,
+            ..Default::default()
+        };
+        let yaml = serde_yaml::to_string(&init).unwrap();
+        assert_eq!(
+            serde_yaml::from_str::<ModuleInitHMTable>(&yaml).unwrap(),
+            init
+        );
+
+        let run = ModuleRunHMTable {
+            partition_init: ModuleRecoveryAction::Reset,
+            ..Default::default()
+        };
+        let yaml = serde_yaml::to_string(&run).unwrap();
+        assert_eq!(
+            serde_yaml::from_str::<ModuleRunHMTable>(&yaml).unwrap(),
+            run
+        );
+    }
+
+    #[test]
+    fn lowercase_action_names() {
+        let yaml = "
+segmentation: !Partition cold_start
+panic: !partition warmstart
+application_error: !Module reset
+floating_point_error: !Partition Idle
+";
+        let table: PartitionHMTable = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(
+            table.segmentation,
+            RecoveryAction::Partition(PartitionRecoveryAction::ColdStart)
+        );
+        assert_eq!(
+            table.panic,
+            RecoveryAction::Partition(PartitionRecoveryAction::WarmStart)
+        );
+        assert_eq!(
+            table.application_error,
+            RecoveryAction::Module(ModuleRecoveryAction::Reset)
+        );
+        assert_eq!(
+            table.floating_point_error,
+            RecoveryAction::Partition(PartitionRecoveryAction::Idle)
+        );
+
+        let init: ModuleInitHMTable = serde_yaml::from_str("config: ignore").unwrap();
+        assert_eq!(init.config, ModuleRecoveryAction::Ignore);
+        assert!(serde_yaml::from_str::<ModuleInitHMTable>("config: IGNORE").is_err());
+    }
+
+    #[test]
+    fn partition_actions_for_cgroup_errors_are_warned_about() {
+        assert!(PartitionHMTable::default().warnings().is_empty());
+        let table = PartitionHMTable {
+            cgroup: RecoveryAction::Partition(PartitionRecoveryAction::WarmStart),
+            ..Default::default()
+        };
+        let warnings = table.warnings();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("WarmStart"), "{warnings:?}");
+    }
+
+    #[test]
+    fn module_errors_use_their_table() {
+        let init = ModuleInitHMTable {
+            config: ModuleRecoveryAction::Ignore,
+            ..Default::default()
+        };
+        let run = ModuleRunHMTable::default();
+
+        let err = LeveledError::new(SystemError::Config, ErrorLevel::ModuleInit, anyhow!("init"));
+        assert_eq!(
+            module_action(&err, &init, &run),
+            ModuleRecoveryAction::Ignore
+        );
+        let err = LeveledError::new(SystemError::Config, ErrorLevel::ModuleRun, anyhow!("run"));
+        assert_eq!(
+            module_action(&err, &init, &run),
+            ModuleRecoveryAction::Shutdown
+        );
+    }
+}
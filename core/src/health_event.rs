@@ -1,11 +1,43 @@
 //! Fetch information from a partition
+//!
+//! A [PartitionCall] is sent in an envelope, see [PartitionCall::encode], so
+//! that a partition and a hypervisor of different releases understand each
+//! other: a one-byte [PARTITION_CALL_VERSION], a one-byte [CallTag] naming the
+//! variant, and the bincode encoding of the contents of the variant. The tag
+//! and the payload of a variant never change. New variants get a new tag and
+//! bump the version, so that an older hypervisor skips them instead of
+//! failing, losing only the new signal.
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
 use a653rs::prelude::OperatingMode;
+use bincode::Options;
 use log::Level;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
-use crate::error::SystemError;
+use crate::error::{ResultExt, SystemError, TypedResult};
+use crate::ipc::Datagram;
+use crate::partition::{PortDecl, Requirements};
+use crate::time::ModuleTime;
+use crate::wire::{self, UnknownValue};
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+/// Version of the encoding of [PartitionCall], bumped with every new variant
+pub const PARTITION_CALL_VERSION: u8 = 2;
+
+/// Largest encoded [PartitionCall], including the envelope
+pub const MAX_PARTITION_CALL_SIZE: usize = 64 * 1024;
+
+/// Bytes of the envelope in front of the payload: the version and the tag
+const ENVELOPE_SIZE: usize = 2;
+
+/// Whether a call of an unknown variant was skipped already, which is only
+/// logged once
+static SKIPPED_UNKNOWN: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone)]
 /// The core unit for communication in that module
 pub enum PartitionCall {
     /// The status of the partition
@@ -13,35 +45,495 @@ pub enum PartitionCall {
     /// Potential errors
     Error(SystemError),
     /// Potential messages
-    Message(String),
+    Message(LogRecord),
+    /// Ports the partition is going to create
+    DeclarePorts(Vec<PortDecl>),
+    /// A port the partition created
+    PortCreated(PortDecl),
+    /// The current value of a gauge of the partition, see
+    /// [telemetry](crate::telemetry)
+    Telemetry { name: String, value: f64 },
+    /// Number of calls the partition dropped so far, because its socket to
+    /// the hypervisor was full
+    DroppedCalls(u64),
+    /// What the partition expects of its configuration, including the ports
+    /// it is going to create
+    DeclareRequirements(Requirements),
+    /// The process of the given kind, which the partition starts right after,
+    /// is released only after the delay, see `DELAYED_START`
+    ///
+    /// The delay of a periodic process counts from each of its release
+    /// points, the one of an aperiodic process from the entry into the NORMAL
+    /// mode or the start of the process, whichever comes later. A zero delay
+    /// undoes the delay of an earlier start.
+    DelayedStart {
+        process: ProcessKind,
+        delay: Duration,
+    },
+}
+
+/// Tag of a variant of [PartitionCall] in its encoding
+///
+/// The values must never change, see the [module](self) documentation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum CallTag {
+    Transition = 1,
+    Error = 2,
+    Message = 3,
+    DeclarePorts = 4,
+    PortCreated = 5,
+    Telemetry = 6,
+    DroppedCalls = 7,
+    DeclareRequirements = 8,
+    /// Since version 2
+    DelayedStart = 9,
+}
+
+impl CallTag {
+    /// Every tag of this version
+    pub const ALL: &'static [CallTag] = &[
+        CallTag::Transition,
+        CallTag::Error,
+        CallTag::Message,
+        CallTag::DeclarePorts,
+        CallTag::PortCreated,
+        CallTag::Telemetry,
+        CallTag::DroppedCalls,
+        CallTag::DeclareRequirements,
+        CallTag::DelayedStart,
+    ];
+}
+
+impl From<&PartitionCall> for CallTag {
+    fn from(call: &PartitionCall) -> Self {
+        match call {
+            PartitionCall::Transition(_) => CallTag::Transition,
+            PartitionCall::Error(_) => CallTag::Error,
+            PartitionCall::Message(_) => CallTag::Message,
+            PartitionCall::DeclarePorts(_) => CallTag::DeclarePorts,
+            PartitionCall::PortCreated(_) => CallTag::PortCreated,
+            PartitionCall::Telemetry { .. } => CallTag::Telemetry,
+            PartitionCall::DroppedCalls(_) => CallTag::DroppedCalls,
+            PartitionCall::DeclareRequirements(_) => CallTag::DeclareRequirements,
+            PartitionCall::DelayedStart { .. } => CallTag::DelayedStart,
+        }
+    }
+}
+
+impl From<CallTag> for u8 {
+    fn from(tag: CallTag) -> Self {
+        tag as u8
+    }
+}
+
+impl TryFrom<u8> for CallTag {
+    type Error = UnknownValue;
+
+    fn try_from(value: u8) -> Result<Self, UnknownValue> {
+        CallTag::ALL
+            .iter()
+            .copied()
+            .find(|tag| u8::from(*tag) == value)
+            .ok_or(UnknownValue {
+                ty: "CallTag",
+                value: value.into(),
+            })
+    }
+}
+
+/// Why a [PartitionCall] could not be encoded or decoded
+#[derive(Error, Debug)]
+pub enum CallError {
+    #[error("partition call exceeds {MAX_PARTITION_CALL_SIZE} bytes")]
+    TooLarge,
+    #[error("partition call without an envelope")]
+    Truncated,
+    #[error("partition call of the unknown version {0}")]
+    UnknownVersion(u8),
+    /// An unknown variant of a version which is not newer, i.e. a corrupted
+    /// call
+    #[error("partition call of the unknown variant {tag} in version {version}")]
+    UnknownVariant { version: u8, tag: u8 },
+    #[error("payload of the partition call: {0}")]
+    Payload(#[source] bincode::Error),
+}
+
+impl From<bincode::Error> for CallError {
+    fn from(e: bincode::Error) -> Self {
+        match *e {
+            bincode::ErrorKind::SizeLimit => CallError::TooLarge,
+            _ => CallError::Payload(e),
+        }
+    }
+}
+
+/// Options of the payloads, the ones of [bincode::serialize] limited to
+/// [MAX_PARTITION_CALL_SIZE]
+fn payload_options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_limit((MAX_PARTITION_CALL_SIZE - ENVELOPE_SIZE) as u64)
+}
+
+/// Decodes the payload `bytes` of a call, which a newer version may have
+/// extended
+fn payload<T: DeserializeOwned>(bytes: &[u8], newer: bool) -> Result<T, CallError> {
+    let value = if newer {
+        payload_options()
+            .allow_trailing_bytes()
+            .deserialize(bytes)?
+    } else {
+        payload_options().deserialize(bytes)?
+    };
+    Ok(value)
+}
+
+/// Process of a partition which emitted a [LogRecord]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ProcessKind {
+    /// The main process, which initializes the partition
+    Main,
+    Periodic,
+    Aperiodic,
+}
+
+impl Display for ProcessKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            ProcessKind::Main => "main",
+            ProcessKind::Periodic => "periodic",
+            ProcessKind::Aperiodic => "aperiodic",
+        };
+        f.pad(s)
+    }
+}
+
+/// A message logged by a partition
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LogRecord {
+    /// Level of the message. Messages reported through the
+    /// REPORT_APPLICATION_MESSAGE service have no level.
+    pub level: Option<Level>,
+    /// Process which emitted the message
+    pub process: ProcessKind,
+    /// Module time at which the message was emitted
+    pub time: ModuleTime,
+    pub message: String,
+}
+
+impl Display for LogRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[{:.6} {:<9}] {}",
+            self.time.as_duration().as_secs_f64(),
+            self.process,
+            self.message
+        )
+    }
 }
 
 impl PartitionCall {
+    /// Encodes the call in its envelope, see the [module](self) documentation
+    pub fn encode(&self) -> Result<Vec<u8>, CallError> {
+        let options = payload_options();
+        let payload = match self {
+            PartitionCall::Transition(mode) => options.serialize(&wire::OperatingMode::from(*mode)),
+            PartitionCall::Error(e) => options.serialize(e),
+            PartitionCall::Message(record) => options.serialize(record),
+            PartitionCall::DeclarePorts(ports) => options.serialize(ports),
+            PartitionCall::PortCreated(port) => options.serialize(port),
+            PartitionCall::Telemetry { name, value } => options.serialize(&(name, value)),
+            PartitionCall::DroppedCalls(dropped) => options.serialize(dropped),
+            PartitionCall::DeclareRequirements(requirements) => options.serialize(requirements),
+            PartitionCall::DelayedStart { process, delay } => options.serialize(&(process, delay)),
+        }?;
+        let mut bytes = Vec::with_capacity(ENVELOPE_SIZE + payload.len());
+        bytes.push(PARTITION_CALL_VERSION);
+        bytes.push(CallTag::from(self).into());
+        bytes.extend(payload);
+        Ok(bytes)
+    }
+
+    /// Decodes a call from its envelope
+    ///
+    /// Calls of variants unknown to this version are skipped, yielding `None`,
+    /// if they come from a newer version. The payloads of known variants may
+    /// have been extended by a newer version.
+    pub fn decode(bytes: &[u8]) -> Result<Option<Self>, CallError> {
+        if bytes.len() > MAX_PARTITION_CALL_SIZE {
+            return Err(CallError::TooLarge);
+        }
+        let [version, tag, contents @ ..] = bytes else {
+            return Err(CallError::Truncated);
+        };
+        let (version, tag) = (*version, *tag);
+        if version == 0 {
+            return Err(CallError::UnknownVersion(version));
+        }
+        let newer = version > PARTITION_CALL_VERSION;
+        let tag = match CallTag::try_from(tag) {
+            Ok(tag) => tag,
+            Err(_) if newer => {
+                if !SKIPPED_UNKNOWN.swap(true, Ordering::Relaxed) {
+                    warn!("Skipping partition calls unknown to version {PARTITION_CALL_VERSION}, like the variant {tag} of version {version}");
+                }
+                return Ok(None);
+            }
+            Err(_) => return Err(CallError::UnknownVariant { version, tag }),
+        };
+
+        let call = match tag {
+            CallTag::Transition => {
+                PartitionCall::Transition(payload::<wire::OperatingMode>(contents, newer)?.into())
+            }
+            CallTag::Error => PartitionCall::Error(payload(contents, newer)?),
+            CallTag::Message => PartitionCall::Message(payload(contents, newer)?),
+            CallTag::DeclarePorts => PartitionCall::DeclarePorts(payload(contents, newer)?),
+            CallTag::PortCreated => PartitionCall::PortCreated(payload(contents, newer)?),
+            CallTag::Telemetry => {
+                let (name, value) = payload(contents, newer)?;
+                PartitionCall::Telemetry { name, value }
+            }
+            CallTag::DroppedCalls => PartitionCall::DroppedCalls(payload(contents, newer)?),
+            CallTag::DeclareRequirements => {
+                PartitionCall::DeclareRequirements(payload(contents, newer)?)
+            }
+            CallTag::DelayedStart => {
+                let (process, delay) = payload(contents, newer)?;
+                PartitionCall::DelayedStart { process, delay }
+            }
+        };
+        Ok(Some(call))
+    }
+
     /// Prints debugs, warnings, traces and errors to their accompanying streams
-    // TODO: Somehow comment what is going on inside this beast
     pub fn print_partition_log(&self, name: &str) {
         let name = &format!("Partition: {name}");
         match self {
             PartitionCall::Error(e) => error!(target: name, "{e:?}"),
-            PartitionCall::Message(msg) => {
-                let mut msg_chars = msg.chars();
-                if let Some(level) = msg_chars.next() {
-                    let msg = msg_chars.collect::<String>();
-                    if let Ok(level) = level.to_string().parse::<usize>() {
-                        return match level {
-                            l if l == Level::Debug as usize => debug!(target: name, "{msg}"),
-                            l if l == Level::Warn as usize => warn!(target: name, "{msg}"),
-                            l if l == Level::Trace as usize => trace!(target: name, "{msg}"),
-                            l if l == Level::Error as usize => error!(target: name, "{msg}"),
-                            _ => info!(target: name, "{msg}"),
-                        };
-                    }
-                }
-                info!(target: name, "{msg}")
+            PartitionCall::Message(record) => {
+                let level = record.level.unwrap_or(Level::Info);
+                log!(target: name, level, "{record}")
             }
             PartitionCall::Transition(mode) => {
                 debug!(target: name, "Received Transition Request: {mode:?}")
             }
+            PartitionCall::DeclarePorts(ports) => {
+                debug!(target: name, "Received declaration of {} ports", ports.len())
+            }
+            PartitionCall::DeclareRequirements(requirements) => {
+                debug!(target: name, "Received requirements {requirements:?}")
+            }
+            PartitionCall::PortCreated(port) => {
+                trace!(target: name, "Received creation of port {port:?}")
+            }
+            PartitionCall::Telemetry { name: gauge, value } => {
+                trace!(target: name, "Received telemetry {gauge} = {value}")
+            }
+            PartitionCall::DroppedCalls(dropped) => {
+                trace!(target: name, "Received {dropped} dropped calls")
+            }
+            PartitionCall::DelayedStart { process, delay } => {
+                debug!(target: name, "Received delayed start of the {process} process by {delay:?}")
+            }
+        }
+    }
+}
+
+impl Datagram for PartitionCall {
+    const MAX_SIZE: usize = MAX_PARTITION_CALL_SIZE;
+
+    fn encode(&self) -> TypedResult<Vec<u8>> {
+        PartitionCall::encode(self).typ(SystemError::Panic)
+    }
+
+    fn decode(bytes: &[u8]) -> TypedResult<Option<Self>> {
+        PartitionCall::decode(bytes).typ(SystemError::Panic)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use a653rs::bindings::PortDirection;
+
+    use super::*;
+
+    /// A call of every variant
+    fn calls() -> Vec<PartitionCall> {
+        let port = PortDecl::Queuing {
+            name: "fuel".into(),
+            dir: PortDirection::Destination,
+            msg_size: 64,
+            max_num_msg: 8,
+        };
+        vec![
+            PartitionCall::Transition(OperatingMode::Normal),
+            PartitionCall::Error(SystemError::TimeDurationExceeded),
+            PartitionCall::Message(LogRecord {
+                level: Some(Level::Debug),
+                process: ProcessKind::Aperiodic,
+                time: ModuleTime::from(Duration::from_millis(42)),
+                message: "fuel low".into(),
+            }),
+            PartitionCall::DeclarePorts(vec![port.clone()]),
+            PartitionCall::PortCreated(port.clone()),
+            PartitionCall::Telemetry {
+                name: "level".into(),
+                value: 0.25,
+            },
+            PartitionCall::DroppedCalls(3),
+            PartitionCall::DeclareRequirements(Requirements {
+                period: Some(Duration::from_millis(500)),
+                min_duration: None,
+                ports: vec![port],
+            }),
+            PartitionCall::DelayedStart {
+                process: ProcessKind::Periodic,
+                delay: Duration::from_millis(150),
+            },
+        ]
+    }
+
+    #[test]
+    fn round_trip() {
+        let calls = calls();
+        let tags: Vec<_> = calls.iter().map(CallTag::from).collect();
+        assert_eq!(tags, CallTag::ALL);
+
+        for call in calls {
+            let bytes = call.encode().unwrap();
+            assert_eq!(bytes[0], PARTITION_CALL_VERSION);
+            assert_eq!(bytes[1], u8::from(CallTag::from(&call)));
+            let decoded = PartitionCall::decode(&bytes).unwrap().unwrap();
+            assert_eq!(format!("{decoded:?}"), format!("{call:?}"));
+        }
+    }
+
+    #[test]
+    fn encoding_is_frozen() {
+        for (value, tag) in CallTag::ALL.iter().enumerate() {
+            assert_eq!(usize::from(u8::from(*tag)), value + 1);
+            assert_eq!(CallTag::try_from(u8::from(*tag)).unwrap(), *tag);
+        }
+        assert!(CallTag::try_from(0).is_err());
+
+        let bytes = PartitionCall::DroppedCalls(5).encode().unwrap();
+        assert_eq!(bytes, [2, 7, 5, 0, 0, 0, 0, 0, 0, 0]);
+        let bytes = PartitionCall::Transition(OperatingMode::Normal)
+            .encode()
+            .unwrap();
+        assert_eq!(bytes, [2, 1, 3, 0, 0, 0]);
+        let bytes = PartitionCall::DelayedStart {
+            process: ProcessKind::Aperiodic,
+            delay: Duration::from_millis(1),
+        }
+        .encode()
+        .unwrap();
+        assert_eq!(
+            bytes,
+            [2, 9, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 64, 66, 15, 0]
+        );
+
+        // Calls of the previous version are still understood
+        let call = PartitionCall::decode(&[1, 7, 5, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        assert!(
+            matches!(call, Some(PartitionCall::DroppedCalls(5))),
+            "{call:?}"
+        );
+    }
+
+    #[test]
+    fn unknown_variants_of_newer_versions_are_skipped() {
+        let newer = PARTITION_CALL_VERSION + 1;
+        assert!(PartitionCall::decode(&[newer, 200, 1, 2, 3])
+            .unwrap()
+            .is_none());
+        // Skipped again, but only logged once
+        assert!(PartitionCall::decode(&[newer, 200]).unwrap().is_none());
+
+        // Known variants of newer versions may carry more than this version knows
+        let mut bytes = PartitionCall::DroppedCalls(5).encode().unwrap();
+        bytes[0] = newer;
+        bytes.extend([0xff; 4]);
+        let call = PartitionCall::decode(&bytes).unwrap().unwrap();
+        assert!(matches!(call, PartitionCall::DroppedCalls(5)), "{call:?}");
+
+        // The same is a corrupted call of this version
+        bytes[0] = PARTITION_CALL_VERSION;
+        assert!(matches!(
+            PartitionCall::decode(&bytes),
+            Err(CallError::Payload(_))
+        ));
+        assert!(matches!(
+            PartitionCall::decode(&[PARTITION_CALL_VERSION, 200]),
+            Err(CallError::UnknownVariant { tag: 200, .. })
+        ));
+        assert!(matches!(
+            PartitionCall::decode(&[0, 1, 3, 0, 0, 0]),
+            Err(CallError::UnknownVersion(0))
+        ));
+    }
+
+    #[test]
+    fn size_is_bounded() {
+        let record = |len| LogRecord {
+            level: None,
+            process: ProcessKind::Main,
+            time: ModuleTime::ZERO,
+            message: "x".repeat(len),
+        };
+        let bytes = PartitionCall::Message(record(MAX_PARTITION_CALL_SIZE / 2))
+            .encode()
+            .unwrap();
+        assert!(bytes.len() <= MAX_PARTITION_CALL_SIZE);
+        assert!(matches!(
+            PartitionCall::Message(record(MAX_PARTITION_CALL_SIZE)).encode(),
+            Err(CallError::TooLarge)
+        ));
+
+        let mut bytes = PartitionCall::DroppedCalls(5).encode().unwrap();
+        bytes.resize(MAX_PARTITION_CALL_SIZE + 1, 0);
+        assert!(matches!(
+            PartitionCall::decode(&bytes),
+            Err(CallError::TooLarge)
+        ));
+        // A length prefix beyond the limit is refused before allocating
+        let mut bytes = vec![PARTITION_CALL_VERSION, u8::from(CallTag::DeclarePorts)];
+        bytes.extend(u64::MAX.to_le_bytes());
+        assert!(PartitionCall::decode(&bytes).is_err());
+
+        for truncated in [&[][..], &[PARTITION_CALL_VERSION]] {
+            assert!(matches!(
+                PartitionCall::decode(truncated),
+                Err(CallError::Truncated)
+            ));
         }
+        assert!(matches!(
+            PartitionCall::decode(&[PARTITION_CALL_VERSION, u8::from(CallTag::DroppedCalls), 5]),
+            Err(CallError::Payload(_))
+        ));
+    }
+
+    #[test]
+    fn log_record_display() {
+        let record = LogRecord {
+            level: Some(Level::Warn),
+            process: ProcessKind::Periodic,
+            time: ModuleTime::from(Duration::from_micros(1_500_250)),
+            message: "tank empty".into(),
+        };
+        assert_eq!(record.to_string(), "[1.500250 periodic ] tank empty");
+
+        let record = LogRecord {
+            level: None,
+            process: ProcessKind::Main,
+            time: ModuleTime::ZERO,
+            message: String::new(),
+        };
+        assert_eq!(record.to_string(), "[0.000000 main     ] ");
     }
 }
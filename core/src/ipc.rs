@@ -1,28 +1,62 @@
 //! Implementation of IPC
+use std::cell::RefCell;
 use std::io::{ErrorKind, IoSlice, IoSliceMut};
 use std::marker::PhantomData;
 use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
 use std::os::unix::net::UnixDatagram;
 use std::os::unix::prelude::{AsRawFd, FromRawFd, RawFd};
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 
-use anyhow::Error;
+use anyhow::{anyhow, Context, Error};
 use nix::cmsg_space;
 use nix::errno::Errno;
 use nix::sys::socket::{
-    recvmsg, sendmsg, socketpair, AddressFamily, ControlMessage, ControlMessageOwned, MsgFlags,
-    SockFlag, SockType,
+    getsockopt, recv, recvmsg, sendmsg, setsockopt, socketpair, sockopt, AddressFamily,
+    ControlMessage, ControlMessageOwned, MsgFlags, SockFlag, SockType,
 };
 use polling::{Event, Events, Poller};
-use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 
 use crate::error::{ResultExt, SystemError, TypedResult};
 
+/// Encoding of the values sent as datagrams by an [IpcSender]
+///
+/// Any serde type is sent in its bincode encoding, while types crossing
+/// versions of the crates, like
+/// [PartitionCall](crate::health_event::PartitionCall), bring an encoding of
+/// their own.
+pub trait Datagram: Sized {
+    /// Largest datagram accepted by [IpcReceiver::try_recv]
+    const MAX_SIZE: usize = usize::MAX;
+
+    fn encode(&self) -> TypedResult<Vec<u8>>;
+
+    /// Decodes a received datagram, yielding `None` for datagrams to be
+    /// skipped
+    fn decode(bytes: &[u8]) -> TypedResult<Option<Self>>;
+}
+
+impl<T: Serialize + DeserializeOwned> Datagram for T {
+    fn encode(&self) -> TypedResult<Vec<u8>> {
+        bincode::serialize(self).typ(SystemError::Panic)
+    }
+
+    fn decode(bytes: &[u8]) -> TypedResult<Option<Self>> {
+        bincode::deserialize(bytes)
+            .map(Some)
+            .typ(SystemError::Panic)
+    }
+}
+
 #[derive(Debug)]
 /// Internal data type for the IPC sender
 pub struct IpcSender<T> {
     socket: UnixDatagram,
+    /// Number of values dropped because the socket was full
+    dropped: AtomicU64,
     _p: PhantomData<T>,
 }
 
@@ -30,22 +64,71 @@ pub struct IpcSender<T> {
 /// Internal data type for the IPC receiver
 pub struct IpcReceiver<T> {
     socket: UnixDatagram,
+    /// Reused for every datagram, so that it only grows to the size of the
+    /// largest datagram
+    buffer: RefCell<Vec<u8>>,
     _p: PhantomData<T>,
 }
 
 impl<T> IpcSender<T>
 where
-    T: Serialize,
+    T: Datagram,
 {
     /// Sends value alongside the IpcSender
     /// This fails if the resource is temporarily not available.
     pub fn try_send(&self, value: &T) -> TypedResult<()> {
-        self.socket
-            .send(bincode::serialize(value).typ(SystemError::Panic)?.as_ref())
-            .typ(SystemError::Panic)?;
+        let bytes = value.encode()?;
+        if let Err(e) = self.socket.send(&bytes) {
+            if e.kind() == ErrorKind::WouldBlock {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            return Err(e).typ(SystemError::Panic);
+        }
         Ok(())
     }
 
+    /// Sends value alongside the IpcSender, waiting while the socket is full
+    ///
+    /// Meant for values which must not be dropped, like the errors for the
+    /// health monitor. The wait ends once the receiver took enough datagrams.
+    pub fn send(&self, value: &T) -> TypedResult<()> {
+        let bytes = value.encode()?;
+        loop {
+            match self.socket.send(&bytes) {
+                Ok(_) => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e).typ(SystemError::Panic),
+            }
+            let poller = Poller::new().typ(SystemError::Panic)?;
+            unsafe {
+                poller
+                    .add(&self.socket, Event::writable(42))
+                    .typ(SystemError::Panic)?;
+            }
+            match poller.wait(&mut Events::new(), None) {
+                Err(e) if e.kind() != ErrorKind::Interrupted => {
+                    return Err(e).typ(SystemError::Panic)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Number of values which were not sent so far, because the socket was
+    /// full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Sets `SO_SNDBUF` of the socket, returning the size in effect
+    ///
+    /// The kernel doubles the size for its bookkeeping and silently limits it
+    /// to [max_send_buffer].
+    pub fn set_send_buffer(&self, size: usize) -> TypedResult<usize> {
+        setsockopt(&self.socket, sockopt::SndBuf, &size).typ(SystemError::Panic)?;
+        getsockopt(&self.socket, sockopt::SndBuf).typ(SystemError::Panic)
+    }
+
     /// Try sending value alongside the IpcSender for a certain duration
     pub fn try_send_timeout(&self, _value: &T, _duration: Duration) -> TypedResult<bool> {
         todo!()
@@ -54,12 +137,40 @@ where
 
 impl<T> IpcReceiver<T>
 where
-    T: for<'de> Deserialize<'de> + Serialize,
+    T: Datagram,
 {
-    /// Reads a single instance of T from the IpcReceiver
+    /// Reads a single instance of T from the IpcReceiver, without waiting for
+    /// one
+    ///
+    /// Datagrams larger than [Datagram::MAX_SIZE] are discarded with an
+    /// error, datagrams which decode to nothing yield `None` like an empty
+    /// socket.
     pub fn try_recv(&self) -> TypedResult<Option<T>> {
-        let mut buffer = vec![0; 65507];
-        let len = match self.socket.recv(&mut buffer) {
+        // Only learn the size of the next datagram, so that the buffer can be grown
+        let len = match recv(
+            self.socket.as_raw_fd(),
+            &mut [],
+            MsgFlags::MSG_PEEK | MsgFlags::MSG_TRUNC | MsgFlags::MSG_DONTWAIT,
+        ) {
+            Ok(len) => len,
+            Err(Errno::EAGAIN | Errno::ETIMEDOUT) => return Ok(None),
+            Err(e) => return Err(Error::from(e)).typ(SystemError::Panic),
+        };
+        if len > T::MAX_SIZE {
+            // Receiving into an empty buffer drops the datagram
+            self.socket.recv(&mut []).typ(SystemError::Panic)?;
+            return Err(anyhow!(
+                "discarded datagram of {len} bytes, larger than {} bytes",
+                T::MAX_SIZE
+            ))
+            .typ(SystemError::Panic);
+        }
+        let mut buffer = self.buffer.borrow_mut();
+        if buffer.len() < len {
+            buffer.resize(len, 0);
+        }
+
+        let len = match self.socket.recv(&mut buffer[..len]) {
             Ok(len) => len,
             Err(e) if e.kind() != ErrorKind::TimedOut => {
                 return Err(Error::from(e)).typ(SystemError::Panic)
@@ -67,10 +178,7 @@ where
             _ => return Ok(None),
         };
 
-        // Serialize the received data into T
-        bincode::deserialize(&buffer[0..len])
-            .map(Some)
-            .typ(SystemError::Panic)
+        T::decode(&buffer[0..len])
     }
 
     /// Reads a single instance of T from the IpcReceiver but fail after
@@ -89,6 +197,36 @@ where
 
         self.try_recv()
     }
+
+    /// Sets `SO_RCVBUF` of the socket, returning the size in effect
+    ///
+    /// The kernel doubles the size for its bookkeeping and silently limits it
+    /// to [max_recv_buffer].
+    pub fn set_recv_buffer(&self, size: usize) -> TypedResult<usize> {
+        setsockopt(&self.socket, sockopt::RcvBuf, &size).typ(SystemError::Panic)?;
+        getsockopt(&self.socket, sockopt::RcvBuf).typ(SystemError::Panic)
+    }
+}
+
+/// Largest `SO_SNDBUF` an unprivileged process may set, `net.core.wmem_max`
+pub fn max_send_buffer() -> TypedResult<usize> {
+    read_sysctl("/proc/sys/net/core/wmem_max")
+}
+
+/// Largest `SO_RCVBUF` an unprivileged process may set, `net.core.rmem_max`
+pub fn max_recv_buffer() -> TypedResult<usize> {
+    read_sysctl("/proc/sys/net/core/rmem_max")
+}
+
+fn read_sysctl(path: &str) -> TypedResult<usize> {
+    let value = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {path}"))
+        .typ(SystemError::Panic)?;
+    value
+        .trim()
+        .parse()
+        .with_context(|| format!("{path} is not a size: {value:?}"))
+        .typ(SystemError::Panic)
 }
 
 pub fn bind_receiver<T>(path: &Path) -> TypedResult<IpcReceiver<T>> {
@@ -132,6 +270,7 @@ impl<T> From<UnixDatagram> for IpcReceiver<T> {
     fn from(value: UnixDatagram) -> Self {
         Self {
             socket: value,
+            buffer: Default::default(),
             _p: PhantomData,
         }
     }
@@ -141,6 +280,7 @@ impl<T> From<UnixDatagram> for IpcSender<T> {
     fn from(value: UnixDatagram) -> Self {
         Self {
             socket: value,
+            dropped: AtomicU64::new(0),
             _p: PhantomData,
         }
     }
@@ -148,37 +288,25 @@ impl<T> From<UnixDatagram> for IpcSender<T> {
 
 impl<T> From<OwnedFd> for IpcSender<T> {
     fn from(value: OwnedFd) -> Self {
-        Self {
-            socket: UnixDatagram::from(value),
-            _p: PhantomData,
-        }
+        Self::from(UnixDatagram::from(value))
     }
 }
 
 impl<T> From<OwnedFd> for IpcReceiver<T> {
     fn from(value: OwnedFd) -> Self {
-        Self {
-            socket: UnixDatagram::from(value),
-            _p: PhantomData,
-        }
+        Self::from(UnixDatagram::from(value))
     }
 }
 
 impl<T> FromRawFd for IpcSender<T> {
     unsafe fn from_raw_fd(fd: RawFd) -> Self {
-        Self {
-            socket: UnixDatagram::from_raw_fd(fd),
-            _p: PhantomData,
-        }
+        Self::from(UnixDatagram::from_raw_fd(fd))
     }
 }
 
 impl<T> FromRawFd for IpcReceiver<T> {
     unsafe fn from_raw_fd(fd: RawFd) -> Self {
-        Self {
-            socket: UnixDatagram::from_raw_fd(fd),
-            _p: PhantomData,
-        }
+        Self::from(UnixDatagram::from_raw_fd(fd))
     }
 }
 
@@ -308,3 +436,147 @@ impl<T> FromRawFd for IoReceiver<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair<T>() -> (IpcSender<T>, IpcReceiver<T>) {
+        let (sender, receiver) = UnixDatagram::pair().unwrap();
+        (IpcSender::from(sender), IpcReceiver::from(receiver))
+    }
+
+    #[test]
+    fn buffer_grows_to_largest_datagram() {
+        let (sender, receiver) = pair::<Vec<u8>>();
+
+        for len in [10, 10_000, 100, 0] {
+            let msg = vec![len as u8; len];
+            sender.try_send(&msg).unwrap();
+            assert_eq!(receiver.try_recv().unwrap(), Some(msg));
+        }
+        // The length prefix of the largest message comes on top
+        assert_eq!(receiver.buffer.borrow().len(), 8 + 10_000);
+    }
+
+    #[test]
+    fn empty_socket_yields_none() {
+        let (sender, receiver) = pair::<Vec<u8>>();
+
+        assert_eq!(receiver.try_recv().unwrap(), None);
+        sender.try_send(&vec![1]).unwrap();
+        assert_eq!(receiver.try_recv().unwrap(), Some(vec![1]));
+        assert_eq!(receiver.try_recv().unwrap(), None);
+    }
+
+    /// Number of 1 KiB datagrams sent until the socket is full
+    fn fill(sender: &IpcSender<Vec<u8>>) -> usize {
+        sender.socket.set_nonblocking(true).unwrap();
+        let msg = vec![0u8; 1024];
+        (0..).take_while(|_| sender.try_send(&msg).is_ok()).count()
+    }
+
+    #[test]
+    fn send_buffer_limits_datagrams_in_flight() {
+        let (small, _small_rx) = pair::<Vec<u8>>();
+        let effective = small.set_send_buffer(4096).unwrap();
+        // The kernel doubles the requested size for its bookkeeping
+        assert!(effective >= 4096, "{effective}");
+        let fit_small = fill(&small);
+        assert_eq!(small.dropped(), 1);
+        assert!(small.try_send(&vec![0u8; 1024]).is_err());
+        assert_eq!(small.dropped(), 2);
+
+        let (large, _large_rx) = pair::<Vec<u8>>();
+        let effective = large.set_send_buffer(64 * 1024).unwrap();
+        assert!(effective >= 64 * 1024, "{effective}");
+        let fit_large = fill(&large);
+        assert!(fit_large > fit_small, "{fit_large} <= {fit_small}");
+    }
+
+    #[test]
+    fn dropped_datagrams_are_counted_until_received() {
+        let (sender, receiver) = pair::<Vec<u8>>();
+        sender.set_send_buffer(4096).unwrap();
+        let sent = fill(&sender);
+        assert_eq!(sender.dropped(), 1);
+
+        for _ in 0..sent {
+            assert!(receiver.try_recv().unwrap().is_some());
+        }
+        // The buffer is free again once the datagrams were received
+        assert!(fill(&sender) > 0);
+        assert_eq!(sender.dropped(), 2);
+    }
+
+    #[test]
+    fn send_waits_while_the_socket_is_full() {
+        let (sender, receiver) = pair::<Vec<u8>>();
+        sender.set_send_buffer(4096).unwrap();
+        let sent = fill(&sender);
+
+        let drain = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            for _ in 0..sent {
+                assert!(receiver.try_recv().unwrap().is_some());
+            }
+            receiver
+        });
+        sender.send(&vec![1]).unwrap();
+        let receiver = drain.join().unwrap();
+
+        assert_eq!(
+            receiver.try_recv_timeout(Duration::from_secs(1)).unwrap(),
+            Some(vec![1])
+        );
+        // Only the datagram of fill was dropped
+        assert_eq!(sender.dropped(), 1);
+    }
+
+    #[test]
+    fn receive_buffer_is_set() {
+        let (_sender, receiver) = pair::<Vec<u8>>();
+        let effective = receiver.set_recv_buffer(32 * 1024).unwrap();
+        assert!(effective >= 32 * 1024, "{effective}");
+    }
+
+    #[test]
+    fn buffer_limits_are_readable() {
+        assert!(max_send_buffer().unwrap() > 0);
+        assert!(max_recv_buffer().unwrap() > 0);
+    }
+
+    /// Checks that the receive path does not leak memory. Run with `--ignored`,
+    /// as it takes a while.
+    #[test]
+    #[ignore]
+    fn stable_rss_over_one_million_messages() {
+        let (sender, receiver) = pair::<String>();
+        let rss = || {
+            procfs::process::Process::myself()
+                .unwrap()
+                .status()
+                .unwrap()
+                .vmrss
+                .unwrap()
+        };
+        let transfer = |n| {
+            for i in 0..n {
+                sender.try_send(&format!("message {i}")).unwrap();
+                assert!(receiver.try_recv().unwrap().is_some());
+            }
+        };
+
+        // Let the buffers and the allocator settle first
+        transfer(10_000);
+        let before = rss();
+        transfer(1_000_000);
+        let after = rss();
+
+        // In kB
+        assert!(
+            after.saturating_sub(before) < 1024,
+            "RSS grew from {before} kB to {after} kB"
+        );
+    }
+}
@@ -11,8 +11,11 @@ extern crate log;
 #[macro_use]
 extern crate enum_primitive;
 
+pub mod buffer;
 pub mod cgroup;
 pub mod channel;
+pub mod conditions;
+pub mod deadline;
 pub mod error;
 pub mod fd;
 pub mod file;
@@ -20,8 +23,15 @@ pub mod health;
 pub mod health_event;
 pub mod ipc;
 pub mod mfd;
+pub mod mode;
+pub mod name;
+pub mod netns;
 pub mod partition;
 pub mod queuing;
 pub mod sampling;
 pub mod shmem;
+pub mod size;
 pub mod syscall;
+pub mod telemetry;
+pub mod time;
+pub mod wire;
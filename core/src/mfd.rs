@@ -27,13 +27,21 @@ impl Mfd {
 
     /// Reads all data available
     pub fn read_all(&mut self) -> Result<Vec<u8>> {
+        let mut buf: Vec<u8> = Vec::new();
+        self.read_into(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Reads all data available into `buf`, replacing its previous content
+    ///
+    /// Allows reusing the allocation of `buf` for multiple reads.
+    pub fn read_into(&mut self, buf: &mut Vec<u8>) -> Result<()> {
         self.0.as_file().seek(SeekFrom::Start(0))?;
         // TODO: Evaluate whether inlining ofsmall message directly into the dtagram
         // makes sense.
-        let mut buf: Vec<u8> = Vec::new();
-        self.0.as_file().read_to_end(&mut buf)?;
-
-        Ok(buf)
+        buf.clear();
+        self.0.as_file().read_to_end(buf)?;
+        Ok(())
     }
 
     /// Wipes the mfd and overwrites it
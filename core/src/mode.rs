@@ -0,0 +1,292 @@
+//! Operating mode of a partition, pushed to it by the hypervisor
+//!
+//! The hypervisor publishes the mode in a [ModeWord] in shared memory, which
+//! the partition reads without any syscall, and signals an eventfd after every
+//! change. Processes of the partition interested in mode changes wait on the
+//! eventfd, see [ModeWatcher::wait_for_change], while everybody else just
+//! reads the word.
+//!
+//! The word is always stored before the eventfd is signalled, so a waiter
+//! woken up by the eventfd is guaranteed to see the new mode. The eventfd is
+//! never read, but waited on edge-triggered instead, so that any number of
+//! waiters is woken by the same signal.
+
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use nix::sys::eventfd::{EfdFlags, EventFd};
+use polling::{Event, Events, PollMode, Poller};
+
+use crate::error::{ResultExt, SystemError, TypedResult};
+use crate::file::TempFile;
+use crate::shmem::{TypedMmap, TypedMmapMut};
+use crate::wire::{OperatingMode, UnknownValue};
+
+/// An operating mode together with the number of changes leading to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModeWord {
+    pub mode: OperatingMode,
+    /// Number of mode changes since the start of the partition, wrapping
+    /// around after [ModeWord::GENERATION_MASK]
+    pub generation: u32,
+}
+
+impl ModeWord {
+    /// Bits of the word holding the generation, above the ones of the mode
+    pub const GENERATION_MASK: u32 = 0x00ff_ffff;
+    const MODE_BITS: u32 = 8;
+
+    /// Word of a partition just started in `mode`
+    pub fn new(mode: OperatingMode) -> Self {
+        Self {
+            mode,
+            generation: 0,
+        }
+    }
+
+    /// Word after changing into `mode`
+    pub fn next(self, mode: OperatingMode) -> Self {
+        Self {
+            mode,
+            generation: self.generation.wrapping_add(1) & Self::GENERATION_MASK,
+        }
+    }
+
+    pub fn to_bits(self) -> u32 {
+        (self.generation << Self::MODE_BITS) | u32::from(self.mode)
+    }
+
+    pub fn from_bits(bits: u32) -> Result<Self, UnknownValue> {
+        Ok(Self {
+            mode: OperatingMode::try_from(bits & ((1 << Self::MODE_BITS) - 1))?,
+            generation: bits >> Self::MODE_BITS,
+        })
+    }
+}
+
+/// The mode of a partition as published by the hypervisor
+#[derive(Debug)]
+pub struct SharedMode {
+    word: TypedMmapMut<'static, u32>,
+    word_fd: OwnedFd,
+    event: EventFd,
+    current: ModeWord,
+}
+
+impl SharedMode {
+    /// Creates the shared memory and the eventfd of a partition starting in
+    /// `mode`
+    ///
+    /// The memory is sealed against writable mappings of anybody else, and
+    /// neither fd is close-on-exec, as both are inherited by the partition.
+    pub fn create(name: &str, mode: OperatingMode) -> TypedResult<Self> {
+        let file = TempFile::<u32>::create(name)?;
+        let word_fd = unsafe { OwnedFd::from_raw_fd(file.fd()) };
+        let word = file.seal_read_only()?;
+        let event = EventFd::from_flags(EfdFlags::EFD_NONBLOCK).typ(SystemError::Panic)?;
+        let shared = Self {
+            word,
+            word_fd,
+            event,
+            current: ModeWord::new(mode),
+        };
+        atomic(shared.word.as_ref()).store(shared.current.to_bits(), Ordering::Release);
+        Ok(shared)
+    }
+
+    pub fn get(&self) -> ModeWord {
+        self.current
+    }
+
+    /// Publishes `mode` and then wakes up the processes waiting for a change
+    pub fn set(&mut self, mode: OperatingMode) -> TypedResult<ModeWord> {
+        self.current = self.current.next(mode);
+        atomic(self.word.as_ref()).store(self.current.to_bits(), Ordering::Release);
+        self.event.write(1).typ(SystemError::Panic)?;
+        Ok(self.current)
+    }
+
+    /// Fd of the shared memory holding the [ModeWord]
+    pub fn word_fd(&self) -> RawFd {
+        self.word_fd.as_raw_fd()
+    }
+
+    /// Fd of the eventfd signalled on every change
+    pub fn event_fd(&self) -> RawFd {
+        self.event.as_raw_fd()
+    }
+}
+
+/// The mode of the partition, as seen by the partition
+#[derive(Debug)]
+pub struct ModeWatcher {
+    word: TypedMmap<'static, u32>,
+    event_fd: RawFd,
+}
+
+impl ModeWatcher {
+    /// Maps the word of a [SharedMode] from the fds the hypervisor passed on
+    pub fn open(word_fd: RawFd, event_fd: RawFd) -> TypedResult<Self> {
+        let word = TempFile::<u32>::try_from(word_fd)?.get_typed_mmap()?;
+        Ok(Self { word, event_fd })
+    }
+
+    /// Reads the current word, without any syscall
+    pub fn get(&self) -> Result<ModeWord, UnknownValue> {
+        ModeWord::from_bits(atomic(self.word.as_ref()).load(Ordering::Acquire))
+    }
+
+    /// Blocks until the generation of the word differs from `seen`, or the
+    /// timeout elapsed
+    ///
+    /// Returns the current word either way. Changes between reading `seen`
+    /// and calling this are not lost, as the word is checked again after
+    /// starting to listen for the eventfd.
+    pub fn wait_for_change(&self, seen: u32, timeout: Option<Duration>) -> TypedResult<ModeWord> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let poller = Poller::new().typ(SystemError::Panic)?;
+        // Edge-triggered, as the eventfd is never read. Registering reports the
+        // signals so far once, which is harmless.
+        unsafe { poller.add_with_mode(self.event_fd, Event::readable(0), PollMode::Edge) }
+            .typ(SystemError::Panic)?;
+
+        let mut events = Events::new();
+        loop {
+            let word = self
+                .get()
+                .map_err(|e| anyhow!("shared mode: {e}"))
+                .typ(SystemError::Segmentation)?;
+            if word.generation != seen {
+                return Ok(word);
+            }
+            let remaining = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) => Some(remaining),
+                    None => return Ok(word),
+                },
+                None => None,
+            };
+            events.clear();
+            poller
+                .wait(&mut events, remaining)
+                .typ(SystemError::Panic)?;
+        }
+    }
+}
+
+/// The word in shared memory, which is only ever accessed atomically
+fn atomic(word: &u32) -> &AtomicU32 {
+    // Same size, and the mapping is page aligned
+    unsafe { &*(word as *const u32 as *const AtomicU32) }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+
+    use super::*;
+
+    fn watcher(shared: &SharedMode) -> ModeWatcher {
+        ModeWatcher::open(shared.word_fd(), shared.event_fd()).unwrap()
+    }
+
+    #[test]
+    fn word_encoding() {
+        for mode in OperatingMode::ALL {
+            let word = ModeWord::new(*mode);
+            assert_eq!(ModeWord::from_bits(word.to_bits()).unwrap(), word);
+        }
+        let word = ModeWord {
+            mode: OperatingMode::Normal,
+            generation: ModeWord::GENERATION_MASK,
+        };
+        assert_eq!(ModeWord::from_bits(word.to_bits()).unwrap(), word);
+        // The generation wraps around
+        let next = word.next(OperatingMode::Idle);
+        assert_eq!(next.generation, 0);
+        assert_eq!(next.mode, OperatingMode::Idle);
+        assert!(ModeWord::from_bits(0xff).is_err());
+    }
+
+    #[test]
+    fn change_before_waiting() {
+        let mut shared = SharedMode::create("mode_test", OperatingMode::ColdStart).unwrap();
+        let watcher = watcher(&shared);
+        let seen = watcher.get().unwrap();
+        assert_eq!(seen, ModeWord::new(OperatingMode::ColdStart));
+
+        // A change between reading the word and waiting is not lost
+        shared.set(OperatingMode::Normal).unwrap();
+        let start = Instant::now();
+        let word = watcher
+            .wait_for_change(seen.generation, Some(Duration::from_secs(5)))
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(word.mode, OperatingMode::Normal);
+        assert_eq!(word.generation, 1);
+
+        // The earlier signal does not wake up the next waiter
+        let timeout = Duration::from_millis(20);
+        let start = Instant::now();
+        let word = watcher
+            .wait_for_change(word.generation, Some(timeout))
+            .unwrap();
+        assert!(start.elapsed() >= timeout);
+        assert_eq!(word.generation, 1);
+
+        // The partition may not map the word writable
+        let file = TempFile::<u32>::try_from(shared.word_fd()).unwrap();
+        assert!(file.get_typed_mmap_mut().is_err());
+    }
+
+    #[test]
+    fn notification_order() {
+        const TRANSITIONS: u32 = 200;
+        let mut shared = SharedMode::create("mode_test", OperatingMode::ColdStart).unwrap();
+        let watcher = Arc::new(watcher(&shared));
+        let started = Arc::new(Barrier::new(3));
+
+        // Two waiters, as the eventfd is never drained by either
+        let waiters: Vec<_> = (0..2)
+            .map(|_| {
+                let watcher = watcher.clone();
+                let started = started.clone();
+                thread::spawn(move || {
+                    let mut seen = watcher.get().unwrap();
+                    started.wait();
+                    while seen.generation != TRANSITIONS {
+                        let word = watcher
+                            .wait_for_change(seen.generation, Some(Duration::from_secs(5)))
+                            .unwrap();
+                        // Woken up means the word changed, and only forward
+                        assert!(word.generation > seen.generation, "{word:?} after {seen:?}");
+                        let expected = if word.generation % 2 == 1 {
+                            OperatingMode::Normal
+                        } else {
+                            OperatingMode::Idle
+                        };
+                        assert_eq!(word.mode, expected);
+                        seen = word;
+                    }
+                })
+            })
+            .collect();
+
+        started.wait();
+        for generation in 1..=TRANSITIONS {
+            let mode = if generation % 2 == 1 {
+                OperatingMode::Normal
+            } else {
+                OperatingMode::Idle
+            };
+            assert_eq!(shared.set(mode).unwrap().generation, generation);
+        }
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+        assert_eq!(shared.get().generation, TRANSITIONS);
+    }
+}
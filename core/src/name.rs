@@ -0,0 +1,52 @@
+//! Validation of partition, channel and port names
+//!
+//! These names become part of cgroup paths, directories and memfd names
+//! (e.g. `a653[{pid}]:{partition}:{port}:{partition}:source`), so they are
+//! restricted to characters that are safe in all of those places.
+
+/// Maximum length of a name in bytes
+pub const MAX_NAME_LEN: usize = 64;
+
+/// Returns why `name` may not be used, or `None` if it is valid.
+///
+/// Valid names are non-empty, at most [MAX_NAME_LEN] bytes long and only
+/// consist of ASCII letters, digits, `_` and `-`. This excludes path
+/// separators as well as `.` and `..`.
+pub fn check(name: &str) -> Option<&'static str> {
+    if name.is_empty() {
+        Some("is empty")
+    } else if name.len() > MAX_NAME_LEN {
+        Some("is longer than 64 bytes")
+    } else if !name
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+    {
+        Some("may only contain ASCII letters, digits, '_' and '-'")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_names() {
+        for name in ["Foo", "fuel_tank_controller", "ping-server", "partition_0"] {
+            assert_eq!(check(name), None, "{name}");
+        }
+        assert_eq!(check(&"a".repeat(MAX_NAME_LEN)), None);
+    }
+
+    #[test]
+    fn rejected_names() {
+        assert!(check("").is_some());
+        assert!(check(&"a".repeat(MAX_NAME_LEN + 1)).is_some());
+        for name in [
+            ".", "..", "foo/bar", "/foo", "foo bar", "foo.bar", "föö", "a:b",
+        ] {
+            assert!(check(name).is_some(), "{name}");
+        }
+    }
+}
@@ -0,0 +1,405 @@
+//! Network setup inside of the network namespace of a partition
+//!
+//! Interfaces, addresses and routes are configured with rtnetlink requests,
+//! which requires `CAP_NET_ADMIN`. Requests for the namespace of a partition
+//! are sent from a helper thread which joins that namespace.
+use std::ffi::{c_int, c_uchar, c_ushort, CString};
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::IpAddr;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context};
+use nix::net::if_::if_nametoindex;
+use nix::sched::{setns, CloneFlags};
+use nix::sys::socket::{socket, AddressFamily, SockFlag, SockProtocol, SockType};
+use nix::unistd::Pid;
+
+/// Maximum length of a network interface name (without the trailing nul byte)
+const IFNAMSIZ: usize = 15;
+
+/// Attribute of `IFLA_INFO_DATA` describing the peer of a veth
+const VETH_INFO_PEER: c_ushort = 1;
+
+/// A veth pair connecting a partition to a bridge on the host
+#[derive(Debug, Clone)]
+pub struct Veth {
+    host: String,
+    peer: String,
+}
+
+impl Veth {
+    /// Name of the veth end inside of the partition
+    pub const PARTITION_INTERFACE: &'static str = "eth0";
+
+    /// Names the veth pair of a partition after its identifier
+    pub fn new(id: impl Display) -> Self {
+        let name = |prefix: &str| {
+            let mut name = format!("{prefix}{id}");
+            name.truncate(IFNAMSIZ);
+            name
+        };
+
+        Self {
+            host: name("a653h"),
+            peer: name("a653p"),
+        }
+    }
+
+    /// Creates the veth pair and attaches its host end to `bridge`.
+    ///
+    /// A pair left over from a previous run of the partition is removed first.
+    pub fn create(&self, bridge: &str) -> anyhow::Result<()> {
+        if !Path::new("/sys/class/net")
+            .join(bridge)
+            .join("bridge")
+            .exists()
+        {
+            bail!("bridge {bridge:?} does not exist on the host, create it before starting the hypervisor");
+        }
+
+        if Path::new("/sys/class/net").join(&self.host).exists() {
+            self.remove()?;
+        }
+
+        let mut netlink = Netlink::open()?;
+        let peer = Message::new(libc::RTM_NEWLINK, libc::NLM_F_CREATE | libc::NLM_F_EXCL)
+            .push(&ifinfomsg(0, 0))
+            .name(&self.host)
+            .nested(libc::IFLA_LINKINFO, |info| {
+                info.attr(libc::IFLA_INFO_KIND, b"veth")
+                    .nested(libc::IFLA_INFO_DATA, |data| {
+                        data.nested(VETH_INFO_PEER, |peer| {
+                            peer.push(&ifinfomsg(0, 0)).name(&self.peer)
+                        })
+                    })
+            });
+        netlink
+            .request(peer)
+            .with_context(|| format!("failed to create veth pair {}", self.host))?;
+
+        let bridge_index = if_nametoindex(bridge)?;
+        let attach = Message::new(libc::RTM_NEWLINK, 0)
+            .push(&ifinfomsg(0, libc::IFF_UP))
+            .name(&self.host)
+            .attr(libc::IFLA_MASTER, &bridge_index.to_ne_bytes());
+        netlink
+            .request(attach)
+            .with_context(|| format!("failed to attach {} to bridge {bridge:?}", self.host))
+    }
+
+    /// Moves the partition end into the network namespace of `pid` and
+    /// configures `address` (in CIDR notation) and the optional default
+    /// `gateway` on it.
+    pub fn move_into(
+        &self,
+        pid: Pid,
+        address: &str,
+        gateway: Option<IpAddr>,
+    ) -> anyhow::Result<()> {
+        let (local, prefix) = parse_cidr(address)?;
+
+        let move_peer = Message::new(libc::RTM_NEWLINK, 0)
+            .push(&ifinfomsg(0, 0))
+            .name(&self.peer)
+            .attr(libc::IFLA_NET_NS_PID, &(pid.as_raw() as u32).to_ne_bytes());
+        Netlink::open()?
+            .request(move_peer)
+            .with_context(|| format!("failed to move {} into the namespace of {pid}", self.peer))?;
+
+        let ns = Path::new("/proc").join(pid.to_string()).join("ns/net");
+        in_namespace(&ns, || {
+            let mut netlink = Netlink::open()?;
+            let iface = Self::PARTITION_INTERFACE;
+            let index = if_nametoindex(self.peer.as_str())? as c_int;
+            let rename = Message::new(libc::RTM_NEWLINK, 0)
+                .push(&ifinfomsg(index, 0))
+                .name(iface);
+            netlink
+                .request(rename)
+                .with_context(|| format!("failed to rename {} to {iface}", self.peer))?;
+
+            let addr = Message::new(libc::RTM_NEWADDR, libc::NLM_F_CREATE | libc::NLM_F_EXCL)
+                .push(&ifaddrmsg(&local, prefix, index))
+                .attr(libc::IFA_LOCAL, &ip_bytes(&local))
+                .attr(libc::IFA_ADDRESS, &ip_bytes(&local));
+            netlink
+                .request(addr)
+                .with_context(|| format!("failed to assign address {address:?}"))?;
+
+            for index in [if_nametoindex("lo")? as c_int, index] {
+                let up = Message::new(libc::RTM_NEWLINK, 0).push(&ifinfomsg(index, libc::IFF_UP));
+                netlink.request(up)?;
+            }
+
+            if let Some(gateway) = gateway {
+                let route = Message::new(libc::RTM_NEWROUTE, libc::NLM_F_CREATE | libc::NLM_F_EXCL)
+                    .push(&rtmsg(&gateway))
+                    .attr(libc::RTA_GATEWAY, &ip_bytes(&gateway));
+                netlink
+                    .request(route)
+                    .with_context(|| format!("failed to set default route via {gateway}"))?;
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Removes the veth pair
+    ///
+    /// Deleting the host end also deletes the end inside of the partition.
+    pub fn remove(&self) -> anyhow::Result<()> {
+        let del = Message::new(libc::RTM_DELLINK, 0)
+            .push(&ifinfomsg(0, 0))
+            .name(&self.host);
+        Netlink::open()?
+            .request(del)
+            .with_context(|| format!("failed to remove veth pair {}", self.host))
+    }
+}
+
+/// Runs `f` on a thread which joined the network namespace `ns`
+fn in_namespace<F>(ns: &Path, f: F) -> anyhow::Result<()>
+where
+    F: FnOnce() -> anyhow::Result<()> + Send,
+{
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            let ns_file =
+                File::open(ns).with_context(|| format!("failed to open namespace {ns:?}"))?;
+            setns(ns_file, CloneFlags::CLONE_NEWNET)
+                .with_context(|| format!("failed to join namespace {ns:?}"))?;
+            f()
+        })
+        .join()
+        .unwrap_or_else(|e| std::panic::resume_unwind(e))
+    })
+}
+
+/// Splits an address in CIDR notation into the address and its prefix length
+fn parse_cidr(cidr: &str) -> anyhow::Result<(IpAddr, u8)> {
+    let (addr, prefix) = cidr
+        .split_once('/')
+        .ok_or_else(|| anyhow!("address {cidr:?} lacks a prefix length"))?;
+    let addr: IpAddr = addr
+        .parse()
+        .with_context(|| format!("invalid address {cidr:?}"))?;
+    let prefix: u8 = prefix
+        .parse()
+        .with_context(|| format!("invalid prefix length in {cidr:?}"))?;
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    if prefix > max {
+        bail!("prefix length of {cidr:?} exceeds {max}");
+    }
+    Ok((addr, prefix))
+}
+
+/// A route netlink socket of the current network namespace
+struct Netlink(File);
+
+impl Netlink {
+    fn open() -> anyhow::Result<Self> {
+        let fd = socket(
+            AddressFamily::Netlink,
+            SockType::Raw,
+            SockFlag::SOCK_CLOEXEC,
+            SockProtocol::NetlinkRoute,
+        )
+        .context("failed to open netlink socket")?;
+        Ok(Self(File::from(fd)))
+    }
+
+    /// Sends `msg` to the kernel and waits for its acknowledgement
+    fn request(&mut self, msg: Message) -> anyhow::Result<()> {
+        let mut msg = msg.0;
+        let len = msg.len() as u32;
+        msg[..4].copy_from_slice(&len.to_ne_bytes());
+        self.0.write_all(&msg)?;
+
+        let mut buf = [0; 4096];
+        loop {
+            let n = match self.0.read(&mut buf) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                r => r?,
+            };
+            let Some(reply) = buf.get(..n.min(20)).filter(|r| r.len() == 20) else {
+                bail!("truncated netlink reply");
+            };
+            if u16::from_ne_bytes([reply[4], reply[5]]) != libc::NLMSG_ERROR as u16 {
+                continue;
+            }
+            return match i32::from_ne_bytes(reply[16..20].try_into().unwrap()) {
+                0 => Ok(()),
+                errno => {
+                    let err = io::Error::from_raw_os_error(-errno);
+                    let hint = if -errno == libc::EPERM {
+                        " (the hypervisor requires CAP_NET_ADMIN for partition networking)"
+                    } else {
+                        ""
+                    };
+                    Err(anyhow!("{err}{hint}"))
+                }
+            };
+        }
+    }
+}
+
+/// A netlink request consisting of a header, a fixed body and attributes
+///
+/// The length in the header is filled in by [Netlink::request].
+struct Message(Vec<u8>);
+
+impl Message {
+    fn new(ty: u16, flags: c_int) -> Self {
+        let flags = (libc::NLM_F_REQUEST | libc::NLM_F_ACK | flags) as u16;
+        let mut header = vec![0; 4];
+        header.extend(ty.to_ne_bytes());
+        header.extend(flags.to_ne_bytes());
+        // Sequence number and port id
+        header.extend([0; 8]);
+        Self(header)
+    }
+
+    /// Appends `bytes`, padded to the netlink alignment of 4 bytes
+    fn push(mut self, bytes: &[u8]) -> Self {
+        self.0.extend_from_slice(bytes);
+        self.0.resize(self.0.len().next_multiple_of(4), 0);
+        self
+    }
+
+    fn attr(mut self, ty: c_ushort, payload: &[u8]) -> Self {
+        self.0.extend((4 + payload.len() as u16).to_ne_bytes());
+        self.0.extend(ty.to_ne_bytes());
+        self.push(payload)
+    }
+
+    /// Appends the nul terminated interface name `name`
+    fn name(self, name: &str) -> Self {
+        let name = CString::new(name).expect("interface names contain no nul byte");
+        self.attr(libc::IFLA_IFNAME, name.as_bytes_with_nul())
+    }
+
+    /// Appends an attribute containing the attributes added by `f`
+    fn nested(mut self, ty: c_ushort, f: impl FnOnce(Self) -> Self) -> Self {
+        let start = self.0.len();
+        self = self.attr(ty, &[]);
+        self = f(self);
+        let len = (self.0.len() - start) as u16;
+        self.0[start..start + 2].copy_from_slice(&len.to_ne_bytes());
+        self
+    }
+}
+
+/// Body of link requests, setting `flags` on the link with `index`
+fn ifinfomsg(index: c_int, flags: c_int) -> Vec<u8> {
+    let mut msg = vec![libc::AF_UNSPEC as u8, 0];
+    // Device type
+    msg.extend(0u16.to_ne_bytes());
+    msg.extend(index.to_ne_bytes());
+    msg.extend((flags as u32).to_ne_bytes());
+    // Change mask
+    msg.extend((flags as u32).to_ne_bytes());
+    msg
+}
+
+/// Body of address requests for `addr` on the link with `index`
+fn ifaddrmsg(addr: &IpAddr, prefix: u8, index: c_int) -> Vec<u8> {
+    let mut msg = vec![family(addr), prefix, 0, libc::RT_SCOPE_UNIVERSE];
+    msg.extend((index as u32).to_ne_bytes());
+    msg
+}
+
+/// Body of requests for a default route via `gateway`
+fn rtmsg(gateway: &IpAddr) -> Vec<u8> {
+    let mut msg = vec![
+        family(gateway),
+        // Destination and source prefix length, type of service
+        0,
+        0,
+        0,
+        libc::RT_TABLE_MAIN,
+        libc::RTPROT_BOOT,
+        libc::RT_SCOPE_UNIVERSE,
+        libc::RTN_UNICAST,
+    ];
+    // Flags
+    msg.extend(0u32.to_ne_bytes());
+    msg
+}
+
+fn family(addr: &IpAddr) -> c_uchar {
+    match addr {
+        IpAddr::V4(_) => libc::AF_INET as c_uchar,
+        IpAddr::V6(_) => libc::AF_INET6 as c_uchar,
+    }
+}
+
+fn ip_bytes(addr: &IpAddr) -> Vec<u8> {
+    match addr {
+        IpAddr::V4(addr) => addr.octets().to_vec(),
+        IpAddr::V6(addr) => addr.octets().to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interface_names_fit() {
+        let veth = Veth::new(7);
+        assert_eq!(veth.host, "a653h7");
+        assert_eq!(veth.peer, "a653p7");
+
+        let veth = Veth::new(i64::MAX);
+        assert_eq!(veth.host.len(), IFNAMSIZ);
+        assert_eq!(veth.peer.len(), IFNAMSIZ);
+        assert_ne!(veth.host, veth.peer);
+    }
+
+    #[test]
+    fn missing_bridge() {
+        let err = Veth::new(1).create("a653-no-such-br").unwrap_err();
+        assert!(err.to_string().contains("does not exist"));
+    }
+
+    #[test]
+    fn cidr() {
+        assert_eq!(
+            parse_cidr("10.10.0.2/24").unwrap(),
+            ("10.10.0.2".parse().unwrap(), 24)
+        );
+        assert_eq!(parse_cidr("fd00::2/64").unwrap().1, 64);
+        assert!(parse_cidr("10.10.0.2").is_err());
+        assert!(parse_cidr("10.10.0.2/33").is_err());
+        assert!(parse_cidr("10.10.0/24").is_err());
+    }
+
+    #[test]
+    fn nested_attributes_are_padded() {
+        let msg = Message::new(libc::RTM_NEWLINK, 0)
+            .push(&ifinfomsg(0, 0))
+            .nested(libc::IFLA_LINKINFO, |info| {
+                info.attr(libc::IFLA_INFO_KIND, b"veth!")
+            })
+            .0;
+        // Header, body, nested attribute header and padded inner attribute
+        assert_eq!(msg.len(), 16 + 16 + 4 + 12);
+        assert_eq!(&msg[32..34], &16u16.to_ne_bytes());
+        assert_eq!(&msg[36..38], &9u16.to_ne_bytes());
+        assert_eq!(&msg[40..45], b"veth!");
+    }
+
+    #[test]
+    #[ignore = "requires CAP_NET_ADMIN and an existing bridge \"a653-test\""]
+    fn create_and_remove() {
+        let veth = Veth::new(4242);
+        veth.create("a653-test").unwrap();
+        // Creating the pair again must replace the existing one
+        veth.create("a653-test").unwrap();
+        assert!(Path::new("/sys/class/net").join(&veth.host).exists());
+        veth.remove().unwrap();
+        assert!(!Path::new("/sys/class/net").join(&veth.host).exists());
+    }
+}
@@ -5,60 +5,391 @@ use std::time::Duration;
 
 use a653rs::bindings::PortDirection;
 use a653rs::prelude::{PartitionId, StartCondition};
+use bincode::Options;
 use memfd::{FileSeal, MemfdOptions};
 use serde::{Deserialize, Serialize};
 
 use crate::error::{ResultExt, SystemError, TypedError, TypedResult};
+use crate::time::ModuleTime;
+use crate::wire;
+
+/// Partitions a module may have
+///
+/// Every partition is a cgroup with its own processes and keeps a few
+/// descriptors open in the hypervisor. Together with [MAX_CHANNELS], the
+/// descriptors stay below 4096, the hard limit of open files on many
+/// distributions, so that the hypervisor can raise its soft limit on its own.
+pub const MAX_PARTITIONS: usize = 256;
+
+/// Channels a module may have
+///
+/// Every channel keeps two memfds open in the hypervisor, see
+/// [MAX_PARTITIONS].
+pub const MAX_CHANNELS: usize = 1024;
+
+/// Sampling ports a partition may have, and the same number of queuing ports
+///
+/// Bounds the registries of the ports created by a partition, which are
+/// copied on every access to a port. At 64 ports, they still fit into a page.
+pub const MAX_PORTS_PER_PARTITION: usize = 64;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PartitionConstants {
     pub name: String,
     pub identifier: PartitionId,
+    pub role: Option<String>,
     pub period: Duration,
     pub duration: Duration,
+    #[serde(with = "wire::start_condition")]
     pub start_condition: StartCondition,
     pub start_time_fd: RawFd,
+    // A TempFile with the current OperatingMode. Superseded by mode_word_fd, and only still
+    // written for partitions of the previous release.
     pub partition_mode_fd: RawFd,
+    // A TempFile with the Option<RestartCause> of the current start.
+    pub restart_cause_fd: RawFd,
+    // A TempFile with the ModuleConditions, updated by the hypervisor at every major frame.
+    pub conditions_fd: RawFd,
 
     // A UNIX domain sockets, that are used to send file descriptors to the partition.
     pub udp_io_fd: RawFd,
     pub tcp_io_fd: RawFd,
 
+    // An eventfd signalled by the hypervisor whenever new data arrived on a destination port.
+    // The counter encodes a [PortActivity].
+    pub activity_fd: RawFd,
+
     pub sampling: Vec<SamplingConstant>,
     pub queuing: Vec<QueuingConstant>,
+
+    // Size of the send buffer of the socket for the calls to the hypervisor, if configured.
+    pub ipc_buffer: Option<usize>,
+
+    // Highest SCHED_FIFO priority the processes may use, if the partition is granted real-time
+    // scheduling. Otherwise, process priorities are mapped to nice values.
+    pub realtime: Option<u8>,
+
+    // Shared memory with the current ModeWord, and an eventfd signalled after every change of it.
+    // See [crate::mode].
+    pub mode_word_fd: RawFd,
+    pub mode_event_fd: RawFd,
 }
 
+/// A sampling port configured for a partition
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SamplingConstant {
     pub name: String,
+    #[serde(with = "wire::port_direction")]
     pub dir: PortDirection,
     pub msg_size: usize,
     pub fd: RawFd,
+    /// Configured refresh period of a destination port
+    pub refresh_period: Option<Duration>,
 }
 
+/// A queuing port configured for a partition
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct QueuingConstant {
     pub name: String,
+    #[serde(with = "wire::port_direction")]
     pub dir: PortDirection,
     pub msg_size: usize,
     pub max_num_msg: usize,
     pub fd: RawFd,
 }
 
+impl SamplingConstant {
+    /// Whether the partition writes to this port
+    pub fn is_source(&self) -> bool {
+        self.dir == PortDirection::Source
+    }
+}
+
+impl QueuingConstant {
+    /// Whether the partition sends on this port
+    pub fn is_source(&self) -> bool {
+        self.dir == PortDirection::Source
+    }
+}
+
+/// A port which a partition intends to create
+///
+/// A partition may declare all of its ports right after its start, so that
+/// mismatches with the channel configuration are reported at once instead of
+/// surfacing one at a time when the ports are created.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub enum PortDecl {
+    Sampling {
+        name: String,
+        #[serde(with = "wire::port_direction")]
+        dir: PortDirection,
+        msg_size: usize,
+    },
+    Queuing {
+        name: String,
+        #[serde(with = "wire::port_direction")]
+        dir: PortDirection,
+        msg_size: usize,
+        max_num_msg: usize,
+    },
+}
+
+impl PortDecl {
+    /// Describes every difference between this declaration and the ports
+    /// configured for the partition. Returns an empty list if the declaration
+    /// matches.
+    pub fn mismatches<'a>(
+        &self,
+        sampling: impl IntoIterator<Item = &'a SamplingConstant>,
+        queuing: impl IntoIterator<Item = &'a QueuingConstant>,
+    ) -> Vec<String> {
+        self.mismatches_in(
+            sampling.into_iter().map(|p| (None, p)),
+            queuing.into_iter().map(|p| (None, p)),
+        )
+    }
+
+    /// Like [PortDecl::mismatches], with the configured ports keyed by the
+    /// name of their channel, which a port declared in the wrong direction is
+    /// reported with
+    pub fn channel_mismatches<'a>(
+        &self,
+        sampling: impl IntoIterator<Item = (&'a String, &'a SamplingConstant)>,
+        queuing: impl IntoIterator<Item = (&'a String, &'a QueuingConstant)>,
+    ) -> Vec<String> {
+        self.mismatches_in(
+            sampling.into_iter().map(|(c, p)| (Some(c.as_str()), p)),
+            queuing.into_iter().map(|(c, p)| (Some(c.as_str()), p)),
+        )
+    }
+
+    fn mismatches_in<'a>(
+        &self,
+        sampling: impl IntoIterator<Item = (Option<&'a str>, &'a SamplingConstant)>,
+        queuing: impl IntoIterator<Item = (Option<&'a str>, &'a QueuingConstant)>,
+    ) -> Vec<String> {
+        let mut mismatches = Vec::new();
+        match self {
+            PortDecl::Sampling {
+                name,
+                dir,
+                msg_size,
+            } => {
+                let Some((channel, port)) = sampling.into_iter().find(|(_, p)| &p.name == name)
+                else {
+                    return vec![format!("sampling port {name:?} is not configured")];
+                };
+                if port.dir != *dir {
+                    mismatches.push(direction_mismatch(
+                        "sampling", name, *dir, port.dir, channel,
+                    ));
+                }
+                if port.msg_size != *msg_size {
+                    mismatches.push(format!(
+                        "sampling port {name:?} is declared with msg_size {msg_size}, but configured with {}",
+                        port.msg_size
+                    ));
+                }
+            }
+            PortDecl::Queuing {
+                name,
+                dir,
+                msg_size,
+                max_num_msg,
+            } => {
+                let Some((channel, port)) = queuing.into_iter().find(|(_, p)| &p.name == name)
+                else {
+                    return vec![format!("queuing port {name:?} is not configured")];
+                };
+                if port.dir != *dir {
+                    mismatches.push(direction_mismatch("queuing", name, *dir, port.dir, channel));
+                }
+                if port.msg_size != *msg_size {
+                    mismatches.push(format!(
+                        "queuing port {name:?} is declared with msg_size {msg_size}, but configured with {}",
+                        port.msg_size
+                    ));
+                }
+                if port.max_num_msg != *max_num_msg {
+                    mismatches.push(format!(
+                        "queuing port {name:?} is declared with msg_num {max_num_msg}, but configured with {}",
+                        port.max_num_msg
+                    ));
+                }
+            }
+        }
+        mismatches
+    }
+}
+
+/// Describes a port declared in the other direction than configured, naming
+/// its role in the `channel`, if known
+fn direction_mismatch(
+    kind: &str,
+    name: &str,
+    declared: PortDirection,
+    configured: PortDirection,
+    channel: Option<&str>,
+) -> String {
+    match channel {
+        Some(channel) => {
+            let role = match configured {
+                PortDirection::Source => "source",
+                PortDirection::Destination => "destination",
+            };
+            format!(
+                "{kind} port {name:?} is declared as {declared:?}, but channel {channel} lists it as {role}"
+            )
+        }
+        None => format!(
+            "{kind} port {name:?} is declared as {declared:?}, but configured as {configured:?}"
+        ),
+    }
+}
+
+/// What a partition expects of its configuration
+///
+/// Extends the [PortDecl]s of a partition by the timing it was designed for,
+/// so that deploying it into a schedule it was not designed for is reported.
+/// Timing left `None` is not checked.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct Requirements {
+    /// Period the partition expects
+    pub period: Option<Duration>,
+    /// Shortest window the partition needs within each period
+    pub min_duration: Option<Duration>,
+    /// Ports the partition is going to create
+    pub ports: Vec<PortDecl>,
+}
+
+impl Requirements {
+    /// Describes every difference between the expected timing and the
+    /// configured `period` and window `duration` of the partition
+    pub fn timing_mismatches(&self, period: Duration, duration: Duration) -> Vec<String> {
+        let mut mismatches = Vec::new();
+        if let Some(expected) = self.period.filter(|p| *p != period) {
+            mismatches.push(format!(
+                "period of {expected:?} is required, but {period:?} is configured"
+            ));
+        }
+        if let Some(min) = self.min_duration.filter(|d| *d > duration) {
+            mismatches.push(format!(
+                "window of at least {min:?} is required, but {duration:?} is configured"
+            ));
+        }
+        mismatches
+    }
+
+    /// Describes every difference between the timing and the ports of these
+    /// requirements and the configuration of the partition. Returns an empty
+    /// list if the requirements are met.
+    pub fn mismatches<'a>(
+        &self,
+        period: Duration,
+        duration: Duration,
+        sampling: impl IntoIterator<Item = &'a SamplingConstant> + Clone,
+        queuing: impl IntoIterator<Item = &'a QueuingConstant> + Clone,
+    ) -> Vec<String> {
+        let mut mismatches = self.timing_mismatches(period, duration);
+        for port in &self.ports {
+            mismatches.extend(port.mismatches(sampling.clone(), queuing.clone()));
+        }
+        mismatches
+    }
+}
+
+/// Error which caused the health monitor to restart a partition
+///
+/// Shared with the partition through a [TempFile](crate::file::TempFile)
+/// holding an `Option<RestartCause>`, which is `None` whenever the current
+/// start was not initiated by the health monitor.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartCause {
+    pub error: SystemError,
+    /// Module time at which the error was handled
+    pub time: ModuleTime,
+}
+
+/// Classes of destination ports on which new data arrived
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PortActivity {
+    /// At least one sampling port was updated
+    pub sampling: bool,
+    /// At least one queuing port received messages
+    pub queuing: bool,
+}
+
+impl PortActivity {
+    // Eventfd counters add up all written values. Sampling and queuing
+    // notifications are therefore counted in separate halves of the counter.
+    const SAMPLING: u64 = 1;
+    const QUEUING: u64 = 1 << 32;
+    const SAMPLING_MASK: u64 = Self::QUEUING - 1;
+
+    /// Returns whether there was any activity at all
+    pub fn any(&self) -> bool {
+        self.sampling || self.queuing
+    }
+
+    /// Value to add to the eventfd counter for signalling this activity
+    pub fn to_eventfd_value(self) -> u64 {
+        let mut value = 0;
+        if self.sampling {
+            value += Self::SAMPLING;
+        }
+        if self.queuing {
+            value += Self::QUEUING;
+        }
+        value
+    }
+
+    /// Decodes the counter read from the eventfd
+    pub fn from_eventfd_value(value: u64) -> Self {
+        Self {
+            sampling: value & Self::SAMPLING_MASK != 0,
+            queuing: value >= Self::QUEUING,
+        }
+    }
+}
+
+impl std::ops::BitOrAssign for PortActivity {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.sampling |= rhs.sampling;
+        self.queuing |= rhs.queuing;
+    }
+}
+
 impl PartitionConstants {
     pub const PARTITION_CONSTANTS_FD: &'static str = "PARTITION_CONSTANTS_FD";
+    /// Environment variable holding the configured role, if any
+    pub const PARTITION_ROLE: &'static str = "PARTITION_ROLE";
+    /// Environment variable holding the fds of the registries of the sampling
+    /// and queuing ports created by the partition, separated by a comma
+    ///
+    /// Set for helper processes spawned by the partition, which inherit the
+    /// registries instead of creating their own.
+    pub const PORT_REGISTRIES: &'static str = "PARTITION_PORT_REGISTRIES";
     pub const PROCESSES_CGROUP: &'static str = "processes";
     pub const MAIN_PROCESS_CGROUP: &'static str = "main";
     pub const APERIODIC_PROCESS_CGROUP: &'static str = "aperiodic";
     pub const PERIODIC_PROCESS_CGROUP: &'static str = "periodic";
+    /// Frozen for good, holding the threads of stopped and suspended
+    /// processes
+    pub const HELD_PROCESSES_CGROUP: &'static str = "held";
     pub const IPC_SENDER: &'static str = "/.inner/ipc";
 
     pub fn open() -> TypedResult<Self> {
-        let fd = std::env::var(Self::PARTITION_CONSTANTS_FD)
+        PartitionConstants::try_from(Self::fd()?).typ(SystemError::PartitionInit)
+    }
+
+    /// The fd the constants were passed in, as given by
+    /// [Self::PARTITION_CONSTANTS_FD]
+    pub fn fd() -> TypedResult<RawFd> {
+        std::env::var(Self::PARTITION_CONSTANTS_FD)
             .typ(SystemError::PartitionInit)?
             .parse::<RawFd>()
-            .typ(SystemError::PartitionInit)?;
-        PartitionConstants::try_from(fd).typ(SystemError::PartitionInit)
+            .typ(SystemError::PartitionInit)
     }
 }
 
@@ -69,7 +400,26 @@ impl TryFrom<RawFd> for PartitionConstants {
         let mut file = File::open(format!("/proc/self/fd/{file}")).typ(SystemError::Panic)?;
         let mut buf = Vec::new();
         file.read_to_end(&mut buf).typ(SystemError::Panic)?;
-        bincode::deserialize(&buf).typ(SystemError::Panic)
+        Self::from_bytes(&buf)
+    }
+}
+
+impl PartitionConstants {
+    /// Upper bound for the encoded size of the constants
+    ///
+    /// Prevents length prefixes of a corrupted encoding from allocating
+    /// arbitrary amounts of memory.
+    const MAX_ENCODED_SIZE: u64 = 1 << 20;
+
+    /// Decodes constants written by the hypervisor
+    pub fn from_bytes(bytes: &[u8]) -> TypedResult<Self> {
+        // Same encoding as [bincode::serialize], just with a size limit
+        bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .allow_trailing_bytes()
+            .with_limit(Self::MAX_ENCODED_SIZE)
+            .deserialize(bytes)
+            .typ(SystemError::Panic)
     }
 }
 
@@ -99,3 +449,270 @@ impl TryFrom<PartitionConstants> for RawFd {
         Ok(mem.into_raw_fd())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use a653rs::bindings::PortDirection;
+    use a653rs::prelude::{PartitionId, StartCondition};
+
+    use super::{
+        PartitionConstants, PortActivity, PortDecl, QueuingConstant, Requirements, RestartCause,
+        SamplingConstant,
+    };
+    use crate::error::SystemError;
+    use crate::file::TempFile;
+    use crate::time::ModuleTime;
+
+    fn configured() -> (Vec<SamplingConstant>, Vec<QueuingConstant>) {
+        let sampling = vec![SamplingConstant {
+            name: "temperature".into(),
+            dir: PortDirection::Destination,
+            msg_size: 16,
+            fd: -1,
+            refresh_period: Some(Duration::from_millis(200)),
+        }];
+        let queuing = vec![QueuingConstant {
+            name: "commands".into(),
+            dir: PortDirection::Source,
+            msg_size: 32,
+            max_num_msg: 4,
+            fd: -1,
+        }];
+        (sampling, queuing)
+    }
+
+    #[test]
+    fn source_ports() {
+        let (sampling, queuing) = configured();
+        assert!(!sampling[0].is_source());
+        assert!(queuing[0].is_source());
+    }
+
+    #[test]
+    fn port_declarations_match() {
+        let (sampling, queuing) = configured();
+        let decls = [
+            PortDecl::Sampling {
+                name: "temperature".into(),
+                dir: PortDirection::Destination,
+                msg_size: 16,
+            },
+            PortDecl::Queuing {
+                name: "commands".into(),
+                dir: PortDirection::Source,
+                msg_size: 32,
+                max_num_msg: 4,
+            },
+        ];
+        for decl in decls {
+            assert!(decl.mismatches(&sampling, &queuing).is_empty());
+        }
+    }
+
+    #[test]
+    fn port_declaration_mismatches() {
+        let (sampling, queuing) = configured();
+
+        let decl = PortDecl::Queuing {
+            name: "commands".into(),
+            dir: PortDirection::Source,
+            msg_size: 64,
+            max_num_msg: 4,
+        };
+        let mismatches = decl.mismatches(&sampling, &queuing);
+        assert_eq!(mismatches.len(), 1);
+        assert!(mismatches[0].contains("msg_size 64"));
+
+        // A queuing port is not a sampling port of the same name
+        let decl = PortDecl::Sampling {
+            name: "commands".into(),
+            dir: PortDirection::Source,
+            msg_size: 32,
+        };
+        assert_eq!(
+            decl.mismatches(&sampling, &queuing),
+            ["sampling port \"commands\" is not configured"]
+        );
+
+        let decl = PortDecl::Sampling {
+            name: "temperature".into(),
+            dir: PortDirection::Source,
+            msg_size: 32,
+        };
+        assert_eq!(decl.mismatches(&sampling, &queuing).len(), 2);
+    }
+
+    #[test]
+    fn channel_direction_mismatches() {
+        let (sampling, queuing) = configured();
+        let sampling_channels = [("temperature".to_string(), sampling[0].clone())];
+        let queuing_channels = [("commands".to_string(), queuing[0].clone())];
+        let sampling = || sampling_channels.iter().map(|(c, p)| (c, p));
+        let queuing = || queuing_channels.iter().map(|(c, p)| (c, p));
+
+        // A destination declared as source
+        let decl = PortDecl::Sampling {
+            name: "temperature".into(),
+            dir: PortDirection::Source,
+            msg_size: 16,
+        };
+        assert_eq!(
+            decl.channel_mismatches(sampling(), queuing()),
+            ["sampling port \"temperature\" is declared as Source, but channel temperature lists it as destination"]
+        );
+
+        // A source declared as destination
+        let decl = PortDecl::Queuing {
+            name: "commands".into(),
+            dir: PortDirection::Destination,
+            msg_size: 32,
+            max_num_msg: 4,
+        };
+        assert_eq!(
+            decl.channel_mismatches(sampling(), queuing()),
+            ["queuing port \"commands\" is declared as Destination, but channel commands lists it as source"]
+        );
+    }
+
+    #[test]
+    fn requirements() {
+        let (sampling, queuing) = configured();
+        let period = Duration::from_millis(500);
+        let duration = Duration::from_millis(100);
+        let mut requirements = Requirements {
+            period: Some(period),
+            min_duration: Some(duration),
+            ports: vec![PortDecl::Sampling {
+                name: "temperature".into(),
+                dir: PortDirection::Destination,
+                msg_size: 16,
+            }],
+        };
+        assert!(requirements
+            .mismatches(period, duration, &sampling, &queuing)
+            .is_empty());
+        // A longer window is fine, and nothing is checked without requirements
+        assert!(requirements
+            .mismatches(period, 2 * duration, &sampling, &queuing)
+            .is_empty());
+        assert!(Requirements::default()
+            .mismatches(period, duration, &sampling, &queuing)
+            .is_empty());
+
+        assert_eq!(
+            requirements.mismatches(2 * period, duration / 2, &sampling, &queuing),
+            [
+                "period of 500ms is required, but 1s is configured",
+                "window of at least 100ms is required, but 50ms is configured",
+            ]
+        );
+
+        requirements.ports.push(PortDecl::Queuing {
+            name: "status".into(),
+            dir: PortDirection::Source,
+            msg_size: 32,
+            max_num_msg: 4,
+        });
+        assert_eq!(
+            requirements.mismatches(period, duration, &sampling, &queuing),
+            ["queuing port \"status\" is not configured"]
+        );
+    }
+
+    #[test]
+    fn port_activity_eventfd_encoding() {
+        for (sampling, queuing) in [(false, false), (true, false), (false, true), (true, true)] {
+            let activity = PortActivity { sampling, queuing };
+            let value = activity.to_eventfd_value();
+            assert_eq!(PortActivity::from_eventfd_value(value), activity);
+        }
+    }
+
+    #[test]
+    fn port_activity_accumulates() {
+        let sampling = PortActivity {
+            sampling: true,
+            queuing: false,
+        };
+        let queuing = PortActivity {
+            sampling: false,
+            queuing: true,
+        };
+
+        // Multiple notifications before the partition reads the eventfd
+        let value = sampling.to_eventfd_value() * 3 + queuing.to_eventfd_value() * 2;
+        let activity = PortActivity::from_eventfd_value(value);
+        assert!(activity.sampling && activity.queuing);
+
+        let value = queuing.to_eventfd_value() * 5;
+        assert_eq!(PortActivity::from_eventfd_value(value), queuing);
+    }
+
+    #[test]
+    fn constants_decoding() {
+        let (sampling, queuing) = configured();
+        let constants = PartitionConstants {
+            name: "foo".into(),
+            identifier: 1,
+            role: Some("sender".into()),
+            period: Duration::from_millis(100),
+            duration: Duration::from_millis(20),
+            start_condition: StartCondition::NormalStart,
+            start_time_fd: 3,
+            partition_mode_fd: 4,
+            restart_cause_fd: 5,
+            conditions_fd: 9,
+            udp_io_fd: 6,
+            tcp_io_fd: 7,
+            activity_fd: 8,
+            sampling,
+            queuing,
+            ipc_buffer: Some(1 << 20),
+            realtime: Some(50),
+            mode_word_fd: 10,
+            mode_event_fd: 11,
+        };
+        let bytes = bincode::serialize(&constants).unwrap();
+        let decoded = PartitionConstants::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.name, "foo");
+        assert_eq!(decoded.role.as_deref(), Some("sender"));
+        assert_eq!(decoded.queuing[0].max_num_msg, 4);
+        let without_role = PartitionConstants {
+            role: None,
+            ..constants
+        };
+        let decoded = bincode::serialize(&without_role).unwrap();
+        assert_eq!(PartitionConstants::from_bytes(&decoded).unwrap().role, None);
+
+        // Truncated input
+        assert!(PartitionConstants::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        // Length prefix of the name far beyond the limit
+        let mut huge = bytes.clone();
+        huge[..8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(PartitionConstants::from_bytes(&huge).is_err());
+        // Nanoseconds of the period beyond one second would overflow the seconds
+        let mut nanos = bytes;
+        // Skip the name, the identifier and the role (tag, length and bytes)
+        let offset = 8 + "foo".len() + std::mem::size_of::<PartitionId>() + 1 + 8 + "sender".len();
+        nanos[offset..offset + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        nanos[offset + 8..offset + 12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(PartitionConstants::from_bytes(&nanos).is_err());
+    }
+
+    #[test]
+    fn restart_cause_shared_file() {
+        let file = TempFile::<Option<RestartCause>>::create("restart_cause_test").unwrap();
+        let cause = RestartCause {
+            error: SystemError::TimeDurationExceeded,
+            time: ModuleTime::from(Duration::from_millis(1500)),
+        };
+        file.write(&Some(cause)).unwrap();
+        let copy = TempFile::<Option<RestartCause>>::try_from(file.fd()).unwrap();
+        assert_eq!(copy.read().unwrap(), Some(cause));
+
+        file.write(&None).unwrap();
+        assert_eq!(copy.read().unwrap(), None);
+    }
+}
@@ -1,10 +1,85 @@
 use std::fmt::Debug;
 use std::mem::size_of;
-use std::time::Instant;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
+use crate::buffer::BufferError;
 use crate::queuing::message::Message;
 use crate::queuing::queue::ConcurrentQueue;
-use crate::queuing::StripFieldExt;
+use crate::queuing::{align_up, StripFieldExt};
+use crate::time::MonotonicTime;
+
+/// Size of the fields of a [SourceDatagram] in front of its queue
+const SOURCE_FIELDS: usize = size_of::<AtomicUsize>() // number of waiting processes
+    + size_of::<usize>() // number of messages in destination
+    + size_of::<bool>(); // flag if queue has overflowed
+
+/// Size of the fields of a [DestinationDatagram] in front of its queue
+///
+/// The timestamp follows two words, so that it is aligned on 32-bit targets as
+/// well.
+const DESTINATION_FIELDS: usize = size_of::<AtomicUsize>() // number of waiting processes
+    + size_of::<usize>() // number of messages in source
+    + size_of::<MonotonicTime>() // timestamp when a clear was requested, zero if none
+    + size_of::<AtomicUsize>() // number of messages read
+    + size_of::<bool>(); // flag if queue is overflowed
+
+/// Number of processes blocked on the port of a datagram
+///
+/// Both datagrams start with this counter. It is changed by the processes of
+/// the partition owning the port, which may run concurrently, so it is atomic.
+pub fn waiting_processes(buffer: &[u8]) -> &AtomicUsize {
+    unsafe { buffer.strip_field::<AtomicUsize>() }.0
+}
+
+/// Checks the queue of a datagram for messages of `msg_size` and its
+/// messages, which may not be stamped later than `now`
+fn validate_queue(
+    buffer: &[u8],
+    msg_size: usize,
+    msg_capacity: usize,
+    now: MonotonicTime,
+) -> Result<(), BufferError> {
+    ConcurrentQueue::validate(buffer)?;
+    let queue = unsafe { ConcurrentQueue::load_from(buffer) };
+    if queue.msg_size != Message::size(msg_size) || queue.msg_capacity != msg_capacity {
+        return Err(BufferError::QueueLayout {
+            msg_size: Message::size(msg_size),
+            capacity: msg_capacity,
+            found_msg_size: queue.msg_size,
+            found_capacity: queue.msg_capacity,
+        });
+    }
+    queue.try_for_each(|entry| {
+        let msg = Message::from_bytes(entry)?;
+        if *msg.timestamp > now {
+            return Err(BufferError::FutureTimestamp(
+                msg.timestamp.duration_since(now),
+            ));
+        }
+        Ok(())
+    })
+}
+
+/// Checks that a number of messages stored at the other end fits into the
+/// capacity
+fn validate_count(len: usize, capacity: usize) -> Result<(), BufferError> {
+    if len > capacity {
+        return Err(BufferError::QueueLength { len, capacity });
+    }
+    Ok(())
+}
+
+/// Checks that `buffer` has the size of a datagram, so that its fields can be
+/// stripped
+fn validate_size(buffer: &[u8], expected: usize) -> Result<(), BufferError> {
+    if buffer.len() != expected {
+        return Err(BufferError::Size {
+            expected,
+            actual: buffer.len(),
+        });
+    }
+    Ok(())
+}
 
 #[derive(Debug)]
 pub struct SourceDatagram<'a> {
@@ -16,21 +91,27 @@ pub struct SourceDatagram<'a> {
 #[derive(Debug)]
 pub struct DestinationDatagram<'a> {
     pub num_messages_in_source: &'a mut usize,
-    pub clear_requested_timestamp: &'a mut Option<Instant>,
+    /// Number of messages popped by the partition, wrapping around
+    ///
+    /// Processes of the partition may pop concurrently, so it is atomic.
+    pub num_messages_read: &'a AtomicUsize,
+    pub clear_requested_timestamp: &'a mut MonotonicTime,
     pub has_overflowed: &'a mut bool,
     pub message_queue: &'a ConcurrentQueue,
 }
 
 impl<'a> SourceDatagram<'a> {
     pub fn size(msg_size: usize, msg_capacity: usize) -> usize {
-        size_of::<usize>() // number of messages in destination
-            + size_of::<bool>() // flag if queue has overflowed
+        align_up(SOURCE_FIELDS) // the fields, padded so that the queue is aligned
             + ConcurrentQueue::size(Message::size(msg_size), msg_capacity) // the message queue
     }
 
     pub fn init_at(msg_size: usize, msg_capacity: usize, buffer: &'a mut [u8]) -> Self {
+        let (waiting_processes, buffer) = unsafe { buffer.strip_field_mut::<AtomicUsize>() };
+        *waiting_processes.get_mut() = 0;
         let (num_messages_in_destination, buffer) = unsafe { buffer.strip_field_mut::<usize>() };
         let (has_overflowed, buffer) = unsafe { buffer.strip_field_mut::<bool>() };
+        let buffer = buffer.strip_padding_mut(SOURCE_FIELDS);
 
         let message_queue = ConcurrentQueue::init_at(buffer, Message::size(msg_size), msg_capacity);
 
@@ -42,8 +123,10 @@ impl<'a> SourceDatagram<'a> {
     }
 
     pub unsafe fn load_from(buffer: &'a mut [u8]) -> Self {
+        let (_waiting_processes, buffer) = unsafe { buffer.strip_field_mut::<AtomicUsize>() };
         let (num_messages_in_destination, buffer) = unsafe { buffer.strip_field_mut::<usize>() };
         let (has_overflowed, buffer) = unsafe { buffer.strip_field_mut::<bool>() };
+        let buffer = buffer.strip_padding_mut(SOURCE_FIELDS);
 
         let message_queue = ConcurrentQueue::load_from(buffer);
 
@@ -54,15 +137,38 @@ impl<'a> SourceDatagram<'a> {
         }
     }
 
-    pub fn pop_then<F: FnOnce(Message<'_>) -> T, T>(&'_ mut self, f: F) -> Option<T> {
+    /// Checks the invariants of a source datagram for `msg_capacity` messages
+    /// of `msg_size` without panicking, see [ConcurrentQueue::validate]
+    pub fn validate(
+        buffer: &[u8],
+        msg_size: usize,
+        msg_capacity: usize,
+        now: MonotonicTime,
+    ) -> Result<(), BufferError> {
+        validate_size(buffer, Self::size(msg_size, msg_capacity))?;
+        let (_waiting_processes, buffer) = unsafe { buffer.strip_field::<AtomicUsize>() };
+        let (num_messages_in_destination, buffer) = unsafe { buffer.strip_field::<usize>() };
+        let (_has_overflowed, buffer) = unsafe { buffer.strip_field::<u8>() };
+        let buffer = buffer.strip_padding(SOURCE_FIELDS);
+
+        validate_count(*num_messages_in_destination, msg_capacity)?;
+        validate_queue(buffer, msg_size, msg_capacity, now)
+    }
+
+    /// Pops the oldest message and maps it with `f`. A malformed message is
+    /// popped as well, but returned as an error.
+    pub fn pop_then<F: FnOnce(Message<'_>) -> T, T>(
+        &'_ mut self,
+        f: F,
+    ) -> Option<Result<T, BufferError>> {
         self.message_queue
-            .pop_then(|entry| f(Message::from_bytes(entry)))
+            .pop_then(|entry| Message::from_bytes(entry).map(f))
     }
 
     pub fn push<'b>(
         &'b mut self,
         data: &'_ [u8],
-        message_timestamp: Instant,
+        message_timestamp: MonotonicTime,
     ) -> Option<Message<'b>> {
         // We need to check if there is enough space left in the queue.
         // This is important, because we could theoretically store twice the number of
@@ -78,63 +184,104 @@ impl<'a> SourceDatagram<'a> {
         let entry = self.message_queue
             .push_then(|entry| Message::init_at(entry, data, message_timestamp)).expect("push to be successful because we just checked if there is space in both the source and destination");
 
-        Some(Message::from_bytes(entry))
+        Some(Message::from_bytes(entry).expect("message to be valid, because it was just written"))
     }
 }
 
 impl<'a> DestinationDatagram<'a> {
     pub fn size(msg_size: usize, msg_capacity: usize) -> usize {
-        size_of::<usize>() // number of messages in source
-            + size_of::<bool>() // flag if queue is overflowed
-            + size_of::<Option<Instant>>() // flag for the timestamp when a clear was requested
+        align_up(DESTINATION_FIELDS) // the fields, padded so that the queue is aligned
             + ConcurrentQueue::size(Message::size(msg_size), msg_capacity) // the message queue
     }
     pub fn init_at(msg_size: usize, msg_capacity: usize, buffer: &'a mut [u8]) -> Self {
+        let (waiting_processes, buffer) = unsafe { buffer.strip_field_mut::<AtomicUsize>() };
+        *waiting_processes.get_mut() = 0;
         let (num_messages_in_source, buffer) = unsafe { buffer.strip_field_mut::<usize>() };
         let (clear_requested_timestamp, buffer) =
-            unsafe { buffer.strip_field_mut::<Option<Instant>>() };
+            unsafe { buffer.strip_field_mut::<MonotonicTime>() };
+        let (num_messages_read, buffer) = unsafe { buffer.strip_field_mut::<AtomicUsize>() };
+        *num_messages_read.get_mut() = 0;
         let (has_overflowed, buffer) = unsafe { buffer.strip_field_mut::<bool>() };
+        let buffer = buffer.strip_padding_mut(DESTINATION_FIELDS);
 
         *num_messages_in_source = 0;
         unsafe {
-            std::ptr::write(clear_requested_timestamp, None);
+            std::ptr::write(clear_requested_timestamp, MonotonicTime::ZERO);
             std::ptr::write(has_overflowed, false);
         }
 
         Self {
             num_messages_in_source,
+            num_messages_read,
             clear_requested_timestamp,
             has_overflowed,
             message_queue: ConcurrentQueue::init_at(buffer, Message::size(msg_size), msg_capacity),
         }
     }
     pub unsafe fn load_from(buffer: &'a mut [u8]) -> Self {
+        let (_waiting_processes, buffer) = unsafe { buffer.strip_field_mut::<AtomicUsize>() };
         let (num_messages_in_source, buffer) = unsafe { buffer.strip_field_mut::<usize>() };
         let (clear_requested_timestamp, buffer) =
-            unsafe { buffer.strip_field_mut::<Option<Instant>>() };
+            unsafe { buffer.strip_field_mut::<MonotonicTime>() };
+        let (num_messages_read, buffer) = unsafe { buffer.strip_field_mut::<AtomicUsize>() };
         let (has_overflown, buffer) = unsafe { buffer.strip_field_mut::<bool>() };
+        let buffer = buffer.strip_padding_mut(DESTINATION_FIELDS);
 
         Self {
             num_messages_in_source,
+            num_messages_read,
             clear_requested_timestamp,
             has_overflowed: has_overflown,
             message_queue: ConcurrentQueue::load_from(buffer),
         }
     }
 
+    /// Checks the invariants of a destination datagram for `msg_capacity`
+    /// messages of `msg_size` without panicking, see
+    /// [ConcurrentQueue::validate]
+    pub fn validate(
+        buffer: &[u8],
+        msg_size: usize,
+        msg_capacity: usize,
+        now: MonotonicTime,
+    ) -> Result<(), BufferError> {
+        validate_size(buffer, Self::size(msg_size, msg_capacity))?;
+        let (_waiting_processes, buffer) = unsafe { buffer.strip_field::<AtomicUsize>() };
+        let (num_messages_in_source, buffer) = unsafe { buffer.strip_field::<usize>() };
+        let (clear_requested_timestamp, buffer) = unsafe { buffer.strip_field::<MonotonicTime>() };
+        let (_num_messages_read, buffer) = unsafe { buffer.strip_field::<AtomicUsize>() };
+        let (_has_overflowed, buffer) = unsafe { buffer.strip_field::<u8>() };
+        let buffer = buffer.strip_padding(DESTINATION_FIELDS);
+
+        validate_count(*num_messages_in_source, msg_capacity)?;
+        if *clear_requested_timestamp > now {
+            return Err(BufferError::FutureTimestamp(
+                clear_requested_timestamp.duration_since(now),
+            ));
+        }
+        validate_queue(buffer, msg_size, msg_capacity, now)
+    }
+
     /// Takes a closure that maps the popped message to some type.
-    /// If there is a message in the queue, the resulting type and a flag
-    /// whether the queue has overflowed is returned.
-    pub fn pop_then<F: FnOnce(Message<'_>) -> T, T>(&mut self, msg_mapper: F) -> Option<(T, bool)> {
-        self.message_queue
-            .pop_then(|entry| msg_mapper(Message::from_bytes(entry)))
-            .map(|t| (t, *self.has_overflowed))
+    /// If there is a message in the queue, the resulting type, or an error if
+    /// the message is malformed, and a flag whether the queue has overflowed is
+    /// returned. Every popped message counts as read.
+    pub fn pop_then<F: FnOnce(Message<'_>) -> T, T>(
+        &mut self,
+        msg_mapper: F,
+    ) -> Option<(Result<T, BufferError>, bool)> {
+        let popped = self
+            .message_queue
+            .pop_then(|entry| Message::from_bytes(entry).map(msg_mapper))?;
+        self.num_messages_read.fetch_add(1, Ordering::AcqRel);
+        Some((popped, *self.has_overflowed))
     }
 
     /// Pushes a data onto the destination queue
     pub fn push<'b>(&'b mut self, data: &'_ [u8]) -> Option<Message<'b>> {
         let entry = self.message_queue.push(data)?;
-        let msg = Message::from_bytes(entry);
+        let msg = Message::from_bytes(entry)
+            .expect("message to be valid, because it was copied from a valid message");
 
         Some(msg)
     }
@@ -1,12 +1,13 @@
 use std::mem::size_of;
 use std::ptr::slice_from_raw_parts;
-use std::time::Instant;
 
-use super::StripFieldExt;
+use super::{align_up, StripFieldExt};
+use crate::buffer::BufferError;
+use crate::time::MonotonicTime;
 
 pub struct Message<'a> {
     pub len: &'a usize,
-    pub timestamp: &'a Instant,
+    pub timestamp: &'a MonotonicTime,
     /// This data slice is always of the same size, controlled by the owning
     /// ConcurrentQueue. That means, that only the first `self.len` bytes in
     /// it contain actual data. Use [Message::get_data] to access just the
@@ -15,33 +16,52 @@ pub struct Message<'a> {
 }
 
 impl<'a> Message<'a> {
+    /// Size of an entry holding a message of `msg_size` bytes
+    ///
+    /// The length is padded, so that the timestamp is aligned on 32-bit
+    /// targets, and the entry is padded, so that the next one is aligned as
+    /// well. The padding of an entry counts to its data, whose size thus
+    /// yields the size of the entry again.
     pub fn size(msg_size: usize) -> usize {
-        size_of::<usize>() // length of this message
-            + size_of::<Instant>() // timestamp when this message was sent
-            + msg_size // actual message byte data
+        align_up(
+            align_up(size_of::<usize>()) // length of this message
+            + size_of::<MonotonicTime>() // timestamp when this message was sent
+            + msg_size, // actual message byte data
+        )
     }
-    pub fn from_bytes(bytes: &'a [u8]) -> Self {
+    /// Parses the message stored in `bytes`
+    ///
+    /// The length field is written by the partition, so a length exceeding the
+    /// message size is an error rather than a bug of the hypervisor.
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self, BufferError> {
         let (len, bytes) = unsafe { bytes.strip_field::<usize>() };
-        let (timestamp, data) = unsafe { bytes.strip_field::<Instant>() };
+        let bytes = bytes.strip_padding(size_of::<usize>());
+        let (timestamp, data) = unsafe { bytes.strip_field::<MonotonicTime>() };
 
-        assert!(
-            *len <= data.len(),
-            "*len={} data.len()={}",
-            *len,
-            data.len()
-        );
+        if *len > data.len() {
+            return Err(BufferError::MessageLength {
+                len: *len,
+                msg_size: data.len(),
+            });
+        }
 
-        Self {
+        Ok(Self {
             len,
             timestamp,
             data,
-        }
+        })
     }
 
-    pub fn init_at(uninitialized_bytes: &mut [u8], data: &[u8], initialization_timestamp: Instant) {
+    pub fn init_at(
+        uninitialized_bytes: &mut [u8],
+        data: &[u8],
+        initialization_timestamp: MonotonicTime,
+    ) {
         let (len_field, uninitialized_bytes) =
             unsafe { uninitialized_bytes.strip_field_mut::<usize>() };
-        let (timestamp, data_field) = unsafe { uninitialized_bytes.strip_field_mut::<Instant>() };
+        let uninitialized_bytes = uninitialized_bytes.strip_padding_mut(size_of::<usize>());
+        let (timestamp, data_field) =
+            unsafe { uninitialized_bytes.strip_field_mut::<MonotonicTime>() };
         assert!(data_field.len() >= data.len());
 
         unsafe {
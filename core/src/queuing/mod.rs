@@ -1,22 +1,30 @@
+use std::collections::VecDeque;
 use std::fmt::Debug;
 use std::mem;
 use std::mem::size_of;
+use std::num::NonZeroUsize;
 use std::os::fd::{AsRawFd, OwnedFd, RawFd};
-use std::time::Instant;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use a653rs::bindings::PortDirection;
-use datagrams::{DestinationDatagram, SourceDatagram};
-use memfd::{FileSeal, Memfd, MemfdOptions};
-use memmap2::MmapMut;
+use anyhow::anyhow;
+use bytesize::ByteSize;
+use datagrams::{waiting_processes, DestinationDatagram, SourceDatagram};
+use memfd::{FileSeal, Memfd};
+use memmap2::{Advice, MmapMut};
 use message::Message;
+use queue::ConcurrentQueue;
 
-use crate::channel::{PortConfig, QueuingChannelConfig};
-use crate::error::{ResultExt, SystemError, TypedError, TypedResult};
+use crate::buffer::{self, BufferError};
+use crate::channel::{Criticality, OnPartitionRestart, PortConfig, QueuingChannelConfig, Transfer};
+use crate::error::{fd_limit_hint, ResultExt, SystemError, TypedError, TypedResult};
 use crate::partition::QueuingConstant;
+use crate::shmem::{lock_error, touch_pages, wipe};
+use crate::time::MonotonicTime;
 
 mod datagrams;
 mod message;
-mod queue;
+pub mod queue;
 
 #[derive(Debug)]
 pub struct Queuing {
@@ -30,26 +38,66 @@ pub struct Queuing {
     destination_sender: MmapMut,
     destination: OwnedFd,
     destination_port: PortConfig,
+    on_partition_restart: OnPartitionRestart,
+
+    max_swap_per_frame: Option<NonZeroUsize>,
+    /// Whether the last swap left messages behind because of
+    /// `max_swap_per_frame`
+    throttled: bool,
+    transfer: Transfer,
+    zeroize: bool,
+    criticality: Criticality,
+    /// Whether swapped messages are kept for [Queuing::take_tapped]
+    tap: bool,
+    tapped: Vec<Vec<u8>>,
+    /// Whether messages transferred but not read yet are delivered again after
+    /// [Queuing::clear_all]
+    reliable: bool,
+    /// Copies of the messages transferred but not read yet, oldest first
+    unread: VecDeque<Vec<u8>>,
+    /// Number of messages read at the destination when last sampled
+    num_read: usize,
+    /// Number of malformed messages dropped by [Queuing::swap]
+    corrupted: u64,
 }
 
 impl TryFrom<QueuingChannelConfig> for Queuing {
     type Error = TypedError;
 
     fn try_from(config: QueuingChannelConfig) -> Result<Self, Self::Error> {
-        let msg_size = config.msg_size.as_u64() as usize;
+        let msg_size = Self::checked_msg_size(config.msg_size, config.msg_num)?;
         let msg_num = config.msg_num;
 
-        let source_port_name = config.source.name();
+        let channel = config.source.name();
+        let module = std::process::id();
         let (source_receiver, source) = Self::source(
-            format!("queuing_{source_port_name}_source"),
+            buffer::channel_memfd_name(
+                module,
+                &channel,
+                &config.source.partition,
+                PortDirection::Source,
+            ),
             msg_size,
             config.msg_num,
         )?;
         let (destination_sender, destination) = Self::destination(
-            format!("queuing_{source_port_name}_destination"),
+            buffer::channel_memfd_name(
+                module,
+                &channel,
+                &config.destination.partition,
+                PortDirection::Destination,
+            ),
             msg_size,
             config.msg_num,
         )?;
+        if config.zeroize {
+            source_receiver
+                .advise(Advice::DontDump)
+                .typ(SystemError::Panic)?;
+            destination_sender
+                .advise(Advice::DontDump)
+                .typ(SystemError::Panic)?;
+        }
 
         Ok(Self {
             msg_size,
@@ -60,11 +108,60 @@ impl TryFrom<QueuingChannelConfig> for Queuing {
             destination_sender,
             destination,
             destination_port: config.destination,
+            on_partition_restart: config.on_partition_restart,
+            max_swap_per_frame: config.max_swap_per_frame,
+            throttled: false,
+            transfer: config.transfer,
+            zeroize: config.zeroize,
+            criticality: config.criticality,
+            tap: false,
+            tapped: Vec::new(),
+            reliable: config.reliable,
+            unread: VecDeque::new(),
+            num_read: 0,
+            corrupted: 0,
         })
     }
 }
 
 impl Queuing {
+    /// Largest queue a channel may allocate for each of its datagrams
+    ///
+    /// Far beyond any sensible channel, while leaving enough room for the
+    /// datagram headers that the buffer size calculations can not overflow.
+    const MAX_QUEUE_SIZE: usize = i32::MAX as usize;
+
+    /// Checks that a channel holding `msg_num` messages of `msg_size` can be
+    /// created, returning the message size in bytes
+    pub(crate) fn checked_msg_size(msg_size: ByteSize, msg_num: usize) -> TypedResult<usize> {
+        if msg_num == 0 {
+            return Err(anyhow!("queuing channel must hold at least one message"))
+                .typ(SystemError::Config);
+        }
+        if msg_num > ConcurrentQueue::MAX_CAPACITY {
+            return Err(anyhow!(
+                "queuing channel of {msg_num} messages exceeds the maximum of {} messages",
+                ConcurrentQueue::MAX_CAPACITY
+            ))
+            .typ(SystemError::Config);
+        }
+
+        let size = usize::try_from(msg_size.as_u64()).ok();
+        size.filter(|size| {
+            Message::size(0)
+                .checked_add(*size)
+                .and_then(|entry| entry.checked_mul(msg_num))
+                .is_some_and(|queue| queue <= Self::MAX_QUEUE_SIZE)
+        })
+        .ok_or_else(|| {
+            anyhow!(
+                "queuing channel of {msg_num} messages with {msg_size} each exceeds the maximum size of {}",
+                ByteSize::b(Self::MAX_QUEUE_SIZE as u64)
+            )
+        })
+        .typ(SystemError::Config)
+    }
+
     pub fn constant(&self, part: impl AsRef<str>) -> Option<QueuingConstant> {
         let (dir, fd, port) = if self.source_port.partition.eq(part.as_ref()) {
             (
@@ -72,12 +169,14 @@ impl Queuing {
                 self.source_fd(),
                 &self.source_port.port,
             )
-        } else {
+        } else if self.destination_port.partition.eq(part.as_ref()) {
             (
                 PortDirection::Destination,
                 self.destination_fd(),
                 &self.destination_port.port,
             )
+        } else {
+            return None;
         };
 
         Some(QueuingConstant {
@@ -93,17 +192,279 @@ impl Queuing {
         format!("{}:{}", &self.source_port.partition, self.source_port.port)
     }
 
-    fn memfd(name: impl AsRef<str>, size: usize) -> TypedResult<Memfd> {
-        let mem = MemfdOptions::default()
-            .close_on_exec(false)
-            .allow_sealing(true)
-            .create(name)
-            .typ(SystemError::Panic)?;
-        mem.as_file().set_len(size as u64).typ(SystemError::Panic)?;
-        mem.add_seals(&[FileSeal::SealShrink, FileSeal::SealGrow])
-            .typ(SystemError::Panic)?;
+    /// Name of the partition with the source port of this channel
+    pub fn source_partition(&self) -> &str {
+        &self.source_port.partition
+    }
+
+    /// Name of the partition with the destination port of this channel
+    pub fn destination_partition(&self) -> &str {
+        &self.destination_port.partition
+    }
+
+    /// Whether `partition` has a port of this channel
+    pub fn is_connected_to(&self, partition: &str) -> bool {
+        self.source_port.partition == partition || self.destination_port.partition == partition
+    }
+
+    pub fn on_partition_restart(&self) -> OnPartitionRestart {
+        self.on_partition_restart
+    }
+
+    pub fn transfer(&self) -> Transfer {
+        self.transfer
+    }
 
-        Ok(mem)
+    /// Which health monitor handles the errors of this channel
+    pub fn criticality(&self) -> Criticality {
+        self.criticality
+    }
+
+    /// Discards all messages of the channel, both the ones not yet swapped
+    /// and the ones waiting at the destination
+    ///
+    /// A reliable channel delivers the messages waiting at the destination
+    /// again, in their order, see [Queuing::is_reliable].
+    pub fn clear_all(&mut self) {
+        self.acknowledge();
+        let source_datagram = unsafe { SourceDatagram::load_from(self.source_receiver.as_mut()) };
+        source_datagram.message_queue.clear();
+        *source_datagram.num_messages_in_destination = 0;
+        *source_datagram.has_overflowed = false;
+
+        let mut destination_datagram =
+            unsafe { DestinationDatagram::load_from(self.destination_sender.as_mut()) };
+        destination_datagram.message_queue.clear();
+        *destination_datagram.num_messages_in_source = 0;
+        *destination_datagram.clear_requested_timestamp = MonotonicTime::ZERO;
+        *destination_datagram.has_overflowed = false;
+        self.throttled = false;
+
+        for msg in &self.unread {
+            destination_datagram
+                .push(msg)
+                .expect("push to succeed, because no more unread messages are kept than fit");
+        }
+        *source_datagram.num_messages_in_destination = self.unread.len();
+        if !self.unread.is_empty() {
+            debug!(
+                "Queuing channel {} delivers {} unread messages again",
+                self.name(),
+                self.unread.len()
+            );
+        }
+    }
+
+    /// Whether the messages transferred to the destination but not read yet
+    /// survive [Queuing::clear_all], e.g. on a restart of the destination
+    /// partition
+    ///
+    /// The hypervisor keeps a copy of every transferred message until the
+    /// destination counts it as read. As a message may be lost in the middle of
+    /// being read, the delivery is at least once: a destination restarted while
+    /// reading may receive a message a second time.
+    pub fn is_reliable(&self) -> bool {
+        self.reliable
+    }
+
+    /// Keeps a copy of the message `entry` transferred to the destination, if
+    /// the channel is reliable
+    fn keep_unread(&mut self, entry: Vec<u8>) {
+        if !self.reliable {
+            return;
+        }
+        self.unread.push_back(entry);
+        // A destination not counting its reads must not grow the copies beyond
+        // the capacity of the channel
+        if self.unread.len() > self.max_num_msg {
+            self.unread.pop_front();
+        }
+    }
+
+    /// Drops the copies of the messages read at the destination since the last
+    /// call
+    fn acknowledge(&mut self) {
+        if !self.reliable {
+            return;
+        }
+        let destination_datagram =
+            unsafe { DestinationDatagram::load_from(self.destination_sender.as_mut()) };
+        let num_read = destination_datagram
+            .num_messages_read
+            .load(Ordering::Acquire);
+        let read = num_read.wrapping_sub(self.num_read);
+        self.unread.drain(..read.min(self.unread.len()));
+        self.num_read = num_read;
+    }
+
+    /// Whether the memory of this channel is to be overwritten with zeros on
+    /// restarts of its partitions and on shutdown
+    pub fn zeroizes(&self) -> bool {
+        self.zeroize
+    }
+
+    /// Overwrites the memory of both ends of this channel with zeros,
+    /// discarding all messages like [Queuing::clear_all]
+    ///
+    /// As the memory is shared, this also affects the mappings of the
+    /// partitions. It only removes residue of past messages. The numbers of
+    /// processes waiting on the ports are kept.
+    pub fn zeroize(&mut self) {
+        let waiting = [&self.source_receiver, &self.destination_sender]
+            .map(|mem| waiting_processes(mem).load(Ordering::Acquire));
+
+        wipe(self.source_receiver.as_mut());
+        SourceDatagram::init_at(
+            self.msg_size,
+            self.max_num_msg,
+            self.source_receiver.as_mut(),
+        );
+        wipe(self.destination_sender.as_mut());
+        DestinationDatagram::init_at(
+            self.msg_size,
+            self.max_num_msg,
+            self.destination_sender.as_mut(),
+        );
+
+        for (mem, waiting) in [&self.source_receiver, &self.destination_sender]
+            .into_iter()
+            .zip(waiting)
+        {
+            waiting_processes(mem).store(waiting, Ordering::Release);
+        }
+        self.throttled = false;
+        self.tapped.clear();
+        for entry in &mut self.unread {
+            wipe(entry);
+        }
+        self.unread.clear();
+        self.num_read = 0;
+    }
+
+    /// Keeps a copy of every message transferred by [Queuing::swap] or
+    /// [Queuing::inject], e.g. for forwarding it outside of the module
+    pub fn tap(&mut self) {
+        self.tap = true;
+    }
+
+    /// Takes the messages transferred since the last call, oldest first, if
+    /// the channel is tapped
+    pub fn take_tapped(&mut self) -> Vec<Vec<u8>> {
+        mem::take(&mut self.tapped)
+    }
+
+    /// Number of messages dropped by [Queuing::swap] so far, because the
+    /// source partition left them malformed
+    pub fn corrupted(&self) -> u64 {
+        self.corrupted
+    }
+
+    /// Appends `data` to the queue of the destination port, as if the source
+    /// had sent and the hypervisor swapped it
+    ///
+    /// Returns whether the channel had room for the message, counting the
+    /// messages still waiting at the source. Fails for messages exceeding the
+    /// message size of the channel.
+    pub fn inject(&mut self, data: &[u8]) -> TypedResult<bool> {
+        if data.len() > self.msg_size {
+            return Err(anyhow!(
+                "message of {} bytes exceeds the message size of {} bytes of queuing channel {}",
+                data.len(),
+                self.msg_size,
+                self.name()
+            ))
+            .typ(SystemError::Config);
+        }
+
+        self.acknowledge();
+        let source_datagram = unsafe { SourceDatagram::load_from(self.source_receiver.as_mut()) };
+        let destination_datagram =
+            unsafe { DestinationDatagram::load_from(self.destination_sender.as_mut()) };
+        let queued = destination_datagram.message_queue.len() + source_datagram.message_queue.len();
+        if queued >= destination_datagram.message_queue.msg_capacity {
+            return Ok(false);
+        }
+        let entry = destination_datagram
+            .message_queue
+            .push_then(|entry| Message::init_at(entry, data, MonotonicTime::now()))
+            .expect("push to succeed, because the channel has room for another message")
+            .to_vec();
+        // The source must not exceed the capacity with the injected message
+        *source_datagram.num_messages_in_destination = destination_datagram.message_queue.len();
+
+        if self.tap {
+            self.tapped.push(data.to_vec());
+        }
+        self.keep_unread(entry);
+        Ok(true)
+    }
+
+    /// Resets the number of waiting processes of the ports of `partition`
+    ///
+    /// Processes waiting on a port are gone after a restart of their partition,
+    /// without having stopped to wait.
+    pub fn reset_waiting(&mut self, partition: &str) {
+        if self.source_port.partition == partition {
+            waiting_processes(&self.source_receiver).store(0, Ordering::Release);
+        }
+        if self.destination_port.partition == partition {
+            waiting_processes(&self.destination_sender).store(0, Ordering::Release);
+        }
+    }
+
+    /// Forgets the pushes to the source port still in progress when
+    /// `partition` was restarted
+    ///
+    /// Its processes died while pushing, which holds back later pushes
+    /// otherwise, see [ConcurrentQueue::abandon_pushes].
+    pub fn abandon_pushes(&mut self, partition: &str) {
+        if self.source_port.partition == partition {
+            let source_datagram =
+                unsafe { SourceDatagram::load_from(self.source_receiver.as_mut()) };
+            source_datagram.message_queue.abandon_pushes();
+        }
+    }
+
+    /// Checks the memory of the source port without panicking
+    ///
+    /// Fails if the counters or the queue are inconsistent, or if a message
+    /// exceeds the message size or was sent later than `now`. The source
+    /// partition may not run meanwhile, as it changes the queue.
+    pub fn verify_source(&self, now: MonotonicTime) -> Result<(), BufferError> {
+        SourceDatagram::validate(&self.source_receiver, self.msg_size, self.max_num_msg, now)
+    }
+
+    /// Checks the memory of the destination port like
+    /// [Queuing::verify_source]
+    pub fn verify_destination(&self, now: MonotonicTime) -> Result<(), BufferError> {
+        DestinationDatagram::validate(
+            &self.destination_sender,
+            self.msg_size,
+            self.max_num_msg,
+            now,
+        )
+    }
+
+    /// Faults in the memory of both ends of this channel, optionally locking
+    /// it into RAM, and returns its size in bytes
+    pub fn prefault(&mut self, lock: bool) -> TypedResult<usize> {
+        for mem in [
+            self.source_receiver.as_ref(),
+            self.destination_sender.as_ref(),
+        ] {
+            touch_pages(mem);
+        }
+        if lock {
+            self.source_receiver.lock().map_err(lock_error)?;
+            self.destination_sender.lock().map_err(lock_error)?;
+        }
+        Ok(self.source_receiver.len() + self.destination_sender.len())
+    }
+
+    fn memfd(name: impl AsRef<str>, size: usize) -> TypedResult<Memfd> {
+        buffer::memfd(name, size)
+            .map_err(fd_limit_hint)
+            .typ(SystemError::Panic)
     }
 
     fn source(
@@ -141,7 +502,11 @@ impl Queuing {
     }
 
     /// Returns true if messages have been transferred
+    ///
+    /// Messages whose header was corrupted by the source partition are dropped
+    /// and counted by [Queuing::corrupted].
     pub fn swap(&mut self) -> bool {
+        self.acknowledge();
         // Parse datagrams
         let mut source_datagram =
             unsafe { SourceDatagram::load_from(self.source_receiver.as_mut()) };
@@ -153,11 +518,12 @@ impl Queuing {
         // This is not actually needed for ARINC653 Part 4, as only one partition can
         // run at a time and all messages are swapped to the destination buffer after
         // every partition execution.
-        if let Some(clear_requested_at) = mem::take(destination_datagram.clear_requested_timestamp)
-        {
+        let clear_requested_at = mem::take(destination_datagram.clear_requested_timestamp);
+        if !clear_requested_at.is_zero() {
             while source_datagram.message_queue.peek_then(|msg| {
-                msg.map_or(false, |msg| {
-                    &clear_requested_at > Message::from_bytes(msg).timestamp
+                msg.is_some_and(|msg| {
+                    // Malformed messages are left to be counted below
+                    Message::from_bytes(msg).is_ok_and(|msg| &clear_requested_at > msg.timestamp)
                 })
             }) {
                 source_datagram.message_queue.pop_then(|_| ());
@@ -165,18 +531,56 @@ impl Queuing {
         };
 
         // Copy new messages from source to destination
+        let max_swap = self
+            .max_swap_per_frame
+            .map_or(usize::MAX, NonZeroUsize::get);
         let mut num_msg_swapped = 0;
-        while let Some(_new_destination_msg) =
-            source_datagram.pop_then(|msg| destination_datagram.push(msg.to_bytes()).expect("push to always succeed, because source and destination datagrams can only contain `msg_capacity` messages in total"))
-        {
-            num_msg_swapped += 1;
+        let mut malformed = Vec::new();
+        let mut transferred = Vec::new();
+        while num_msg_swapped < max_swap {
+            let swapped = source_datagram.pop_then(|msg| {
+                if self.tap {
+                    self.tapped.push(msg.get_data().to_vec());
+                }
+                if self.reliable {
+                    transferred.push(msg.to_bytes().to_vec());
+                }
+                destination_datagram.push(msg.to_bytes()).expect("push to always succeed, because source and destination datagrams can only contain `msg_capacity` messages in total");
+            });
+            match swapped {
+                Some(Ok(())) => num_msg_swapped += 1,
+                Some(Err(e)) => malformed.push(e),
+                None => break,
+            }
         }
 
+        // Messages left behind still count towards the capacity on both sides
+        let num_msg_remaining = source_datagram.message_queue.len();
         *source_datagram.num_messages_in_destination = destination_datagram.message_queue.len();
+        *destination_datagram.num_messages_in_source = num_msg_remaining;
         *destination_datagram.has_overflowed = *source_datagram.has_overflowed;
 
         trace!("Swapped {num_msg_swapped} messages: Destination={destination_datagram:?} Source={source_datagram:?}");
 
+        if num_msg_remaining > 0 && !self.throttled {
+            warn!(
+                "Queuing channel {} reached its limit of {max_swap} messages per swap, {num_msg_remaining} messages remain at the source",
+                self.name()
+            );
+        }
+        self.throttled = num_msg_remaining > 0;
+
+        for entry in transferred {
+            self.keep_unread(entry);
+        }
+        for e in &malformed {
+            warn!(
+                "Queuing channel {} dropped a malformed message: {e}",
+                self.name()
+            );
+        }
+        self.corrupted += malformed.len() as u64;
+
         num_msg_swapped > 0
     }
 
@@ -194,7 +598,7 @@ pub struct QueuingSource(MmapMut);
 impl QueuingSource {
     /// If the message was successfully enqueued, the number of bytes written is
     /// returned.
-    pub fn write(&mut self, data: &[u8], message_timestamp: Instant) -> Option<usize> {
+    pub fn write(&mut self, data: &[u8], message_timestamp: MonotonicTime) -> Option<usize> {
         let mut datagram = unsafe { SourceDatagram::load_from(&mut self.0) };
 
         let res = datagram.push(data, message_timestamp).map(|msg| *msg.len);
@@ -214,6 +618,16 @@ impl QueuingSource {
 
         datagram.message_queue.len() + *datagram.num_messages_in_destination
     }
+
+    /// Number of processes waiting for space in the queue
+    pub fn num_waiting_processes(&self) -> usize {
+        waiting_processes(&self.0).load(Ordering::Acquire)
+    }
+
+    /// Marks a process as waiting for space in the queue, or as done waiting
+    pub fn set_waiting(&mut self, waiting: bool) {
+        set_waiting(waiting_processes(&self.0), waiting)
+    }
 }
 
 impl TryFrom<RawFd> for QueuingSource {
@@ -226,22 +640,54 @@ impl TryFrom<RawFd> for QueuingSource {
     }
 }
 
+/// A message read from a [QueuingDestination]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Received {
+    /// Length of the message
+    pub len: usize,
+    /// Number of bytes copied into the buffer, which is less than `len` if the
+    /// buffer was too small
+    pub copied: usize,
+    /// Whether the queue has overflowed
+    pub overflowed: bool,
+}
+
+impl Received {
+    /// Whether the end of the message was cut off
+    pub fn truncated(&self) -> bool {
+        self.copied < self.len
+    }
+}
+
 impl QueuingDestination {
     /// Reads the current message from the queue into a buffer and increments
-    /// the current read index. If a message was successfully read, the
-    /// number of bytes read and whether the queue has overflowed.
-    pub fn read(&mut self, buffer: &mut [u8]) -> Option<(usize, bool)> {
+    /// the current read index, returning the message if there was one.
+    ///
+    /// A message longer than the buffer is still removed from the queue, only
+    /// its beginning is copied. This is reported by [Received::truncated].
+    /// Malformed messages are skipped.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Option<Received> {
         let mut datagram = unsafe { DestinationDatagram::load_from(&mut self.0) };
 
-        let read_bytes_and_overflowed_flag = datagram.pop_then(|msg| {
-            let data = msg.get_data();
-            let len = data.len().min(buffer.len());
-            buffer[..len].copy_from_slice(&data[..len]);
+        loop {
+            let (read, overflowed) = datagram.pop_then(|msg| {
+                let data = msg.get_data();
+                let copied = data.len().min(buffer.len());
+                buffer[..copied].copy_from_slice(&data[..copied]);
 
-            len
-        });
-
-        read_bytes_and_overflowed_flag
+                (data.len(), copied)
+            })?;
+            match read {
+                Ok((len, copied)) => {
+                    return Some(Received {
+                        len,
+                        copied,
+                        overflowed,
+                    })
+                }
+                Err(e) => warn!("Dropped a malformed message: {e}"),
+            }
+        }
     }
 
     pub fn get_current_num_messages(&mut self) -> usize {
@@ -249,10 +695,27 @@ impl QueuingDestination {
         datagram.message_queue.len() + *datagram.num_messages_in_source
     }
 
-    pub fn clear(&mut self, current_time: Instant) {
+    /// Number of processes waiting for a message
+    pub fn num_waiting_processes(&self) -> usize {
+        waiting_processes(&self.0).load(Ordering::Acquire)
+    }
+
+    /// Marks a process as waiting for a message, or as done waiting
+    pub fn set_waiting(&mut self, waiting: bool) {
+        set_waiting(waiting_processes(&self.0), waiting)
+    }
+
+    pub fn clear(&mut self, current_time: MonotonicTime) {
         let datagram = unsafe { DestinationDatagram::load_from(&mut self.0) };
+        let discarded = datagram.message_queue.len();
         datagram.message_queue.clear();
-        *datagram.clear_requested_timestamp = Some(current_time);
+        // Discarded messages are done with like read ones
+        datagram
+            .num_messages_read
+            .fetch_add(discarded, Ordering::AcqRel);
+        // Messages still waiting at the source are discarded by the next swap
+        *datagram.num_messages_in_source = 0;
+        *datagram.clear_requested_timestamp = current_time;
     }
 }
 
@@ -269,10 +732,33 @@ impl TryFrom<RawFd> for QueuingDestination {
     }
 }
 
+fn set_waiting(counter: &AtomicUsize, waiting: bool) {
+    if waiting {
+        counter.fetch_add(1, Ordering::AcqRel);
+    } else {
+        // Saturate, as the hypervisor resets the counter on a restart of the partition
+        let _ = counter.fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1));
+    }
+}
+
+/// Alignment of the fields, messages and queues within a datagram
+///
+/// Shared memory starts at a page boundary, so every field at a multiple of
+/// this offset is aligned for 64-bit atomics and timestamps, which 32-bit and
+/// 64-bit ARM targets require.
+const ALIGNMENT: usize = 8;
+
+/// Rounds `size` up to the next multiple of [ALIGNMENT]
+const fn align_up(size: usize) -> usize {
+    (size + ALIGNMENT - 1) & !(ALIGNMENT - 1)
+}
+
 /// An extension trait for stripping generic types off of byte arrays.
 trait StripFieldExt {
     unsafe fn strip_field<T>(&self) -> (&T, &Self);
     unsafe fn strip_field_mut<T>(&mut self) -> (&mut T, &mut Self);
+    fn strip_padding(&self, fields: usize) -> &Self;
+    fn strip_padding_mut(&mut self, fields: usize) -> &mut Self;
 }
 
 impl StripFieldExt for [u8] {
@@ -292,4 +778,651 @@ impl StripFieldExt for [u8] {
         let field = (field.as_ptr() as *mut T).as_mut().unwrap();
         (field, rest)
     }
+
+    /// Strips the padding behind fields of `fields` bytes, which were stripped
+    /// before, up to the next multiple of [ALIGNMENT]
+    fn strip_padding(&self, fields: usize) -> &Self {
+        &self[align_up(fields) - fields..]
+    }
+
+    fn strip_padding_mut(&mut self, fields: usize) -> &mut Self {
+        &mut self[align_up(fields) - fields..]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Overwrites the length of the oldest message, like a faulty partition
+    fn corrupt_oldest(source: &mut QueuingSource, len: usize) {
+        let datagram = unsafe { SourceDatagram::load_from(&mut source.0) };
+        let entry = datagram
+            .message_queue
+            .peek_then(|msg| msg.unwrap().as_ptr() as *mut usize);
+        unsafe { entry.write_unaligned(len) };
+    }
+
+    #[test]
+    fn clear_all_empties_both_queues() {
+        let config = QueuingChannelConfig {
+            msg_size: ByteSize::b(8),
+            msg_num: 4,
+            source: PortConfig {
+                partition: "a".into(),
+                port: "out".into(),
+            },
+            destination: PortConfig {
+                partition: "b".into(),
+                port: "in".into(),
+            },
+            on_partition_restart: OnPartitionRestart::Clear,
+            max_swap_per_frame: None,
+            transfer: Transfer::AfterSourceWindow,
+            zeroize: false,
+            reliable: false,
+            criticality: Criticality::Partition,
+        };
+        let mut queuing = Queuing::try_from(config).unwrap();
+        assert!(queuing.is_connected_to("a") && queuing.is_connected_to("b"));
+        assert!(!queuing.is_connected_to("c"));
+        assert_eq!(queuing.constant("a").unwrap().dir, PortDirection::Source);
+        assert_eq!(
+            queuing.constant("b").unwrap().dir,
+            PortDirection::Destination
+        );
+        assert!(queuing.constant("c").is_none());
+
+        let mut source = QueuingSource::try_from(queuing.source_fd()).unwrap();
+        let mut destination = QueuingDestination::try_from(queuing.destination_fd()).unwrap();
+
+        // One message at the destination, one still waiting at the source
+        source.write(b"first", MonotonicTime::now()).unwrap();
+        assert!(queuing.swap());
+        source.write(b"second", MonotonicTime::now()).unwrap();
+        assert_eq!(destination.get_current_num_messages(), 1);
+        assert_eq!(source.get_current_num_messages(), 2);
+
+        queuing.clear_all();
+        assert_eq!(source.get_current_num_messages(), 0);
+        assert_eq!(destination.get_current_num_messages(), 0);
+        assert!(!queuing.swap());
+        assert_eq!(destination.read(&mut [0; 8]), None);
+
+        // The full capacity is available again
+        for _ in 0..4 {
+            source.write(b"again", MonotonicTime::now()).unwrap();
+        }
+        assert!(queuing.swap());
+        assert_eq!(destination.get_current_num_messages(), 4);
+    }
+
+    #[test]
+    fn zeroize_removes_residue() {
+        for zeroize in [false, true] {
+            let config = QueuingChannelConfig {
+                msg_size: ByteSize::b(8),
+                msg_num: 4,
+                source: PortConfig {
+                    partition: "a".into(),
+                    port: "out".into(),
+                },
+                destination: PortConfig {
+                    partition: "b".into(),
+                    port: "in".into(),
+                },
+                on_partition_restart: OnPartitionRestart::Keep,
+                max_swap_per_frame: None,
+                transfer: Transfer::AfterSourceWindow,
+                zeroize,
+                reliable: false,
+                criticality: Criticality::Partition,
+            };
+            let mut queuing = Queuing::try_from(config).unwrap();
+            assert_eq!(queuing.zeroizes(), zeroize);
+            let mut source = QueuingSource::try_from(queuing.source_fd()).unwrap();
+            let mut destination = QueuingDestination::try_from(queuing.destination_fd()).unwrap();
+            // A message at both ends of the channel
+            source.write(b"secret!!", MonotonicTime::now()).unwrap();
+            assert!(queuing.swap());
+            source.write(b"secret!!", MonotonicTime::now()).unwrap();
+            destination.set_waiting(true);
+
+            // What the hypervisor does on a restart of a partition of the channel
+            if queuing.zeroizes() {
+                queuing.zeroize();
+            }
+            for fd in [queuing.source_fd(), queuing.destination_fd()] {
+                let mem = unsafe { memmap2::Mmap::map(fd) }.unwrap();
+                let residue = mem.windows(8).any(|w| w == b"secret!!");
+                assert_eq!(residue, !zeroize);
+            }
+            assert_eq!(destination.num_waiting_processes(), 1);
+
+            // The zeroized channel is still usable
+            if zeroize {
+                assert_eq!(destination.get_current_num_messages(), 0);
+                source.write(b"again", MonotonicTime::now()).unwrap();
+                assert!(queuing.swap());
+                assert_eq!(destination.read(&mut [0; 8]).map(|r| r.len), Some(5));
+            }
+        }
+    }
+
+    #[test]
+    fn prefault_keeps_messages() {
+        let config = QueuingChannelConfig {
+            msg_size: ByteSize::b(8),
+            msg_num: 4,
+            source: PortConfig {
+                partition: "a".into(),
+                port: "out".into(),
+            },
+            destination: PortConfig {
+                partition: "b".into(),
+                port: "in".into(),
+            },
+            on_partition_restart: OnPartitionRestart::Clear,
+            max_swap_per_frame: None,
+            transfer: Transfer::AfterSourceWindow,
+            zeroize: false,
+            reliable: false,
+            criticality: Criticality::Partition,
+        };
+        let mut queuing = Queuing::try_from(config).unwrap();
+        let mut source = QueuingSource::try_from(queuing.source_fd()).unwrap();
+        let mut destination = QueuingDestination::try_from(queuing.destination_fd()).unwrap();
+        source.write(b"first", MonotonicTime::now()).unwrap();
+
+        let size = queuing.prefault(false).unwrap();
+        assert!(size >= 2 * 4 * 8);
+        assert!(queuing.swap());
+        let mut buf = [0; 8];
+        assert_eq!(destination.read(&mut buf).map(|r| r.len), Some(5));
+        assert_eq!(&buf[..5], b"first");
+    }
+
+    #[test]
+    fn inject_and_tap() {
+        let config = QueuingChannelConfig {
+            msg_size: ByteSize::b(8),
+            msg_num: 3,
+            source: PortConfig {
+                partition: "a".into(),
+                port: "out".into(),
+            },
+            destination: PortConfig {
+                partition: "b".into(),
+                port: "in".into(),
+            },
+            on_partition_restart: OnPartitionRestart::Keep,
+            max_swap_per_frame: None,
+            transfer: Transfer::AfterSourceWindow,
+            zeroize: false,
+            reliable: false,
+            criticality: Criticality::Partition,
+        };
+        let mut queuing = Queuing::try_from(config).unwrap();
+        queuing.tap();
+        let mut source = QueuingSource::try_from(queuing.source_fd()).unwrap();
+        let mut destination = QueuingDestination::try_from(queuing.destination_fd()).unwrap();
+
+        source.write(b"first", MonotonicTime::now()).unwrap();
+        assert!(queuing.swap());
+        assert!(queuing.inject(b"injected").unwrap());
+        assert!(queuing.inject(b"too long!").is_err());
+        assert_eq!(queuing.take_tapped(), [&b"first"[..], b"injected"]);
+        assert!(queuing.take_tapped().is_empty());
+
+        // Messages waiting at the source count towards the capacity
+        source.write(b"second", MonotonicTime::now()).unwrap();
+        assert_eq!(source.get_current_num_messages(), 3);
+        assert!(source.write(b"third", MonotonicTime::now()).is_none());
+        assert!(!queuing.inject(b"dropped").unwrap());
+
+        let mut buf = [0; 8];
+        for expected in [&b"first"[..], b"injected"] {
+            let len = destination.read(&mut buf).unwrap().len;
+            assert_eq!(&buf[..len], expected);
+        }
+    }
+
+    #[test]
+    fn reliable_channels_deliver_unread_messages_again() {
+        for reliable in [false, true] {
+            let config = QueuingChannelConfig {
+                msg_size: ByteSize::b(8),
+                msg_num: 4,
+                source: PortConfig {
+                    partition: "a".into(),
+                    port: "out".into(),
+                },
+                destination: PortConfig {
+                    partition: "b".into(),
+                    port: "in".into(),
+                },
+                on_partition_restart: OnPartitionRestart::Clear,
+                max_swap_per_frame: None,
+                transfer: Transfer::AfterSourceWindow,
+                zeroize: false,
+                reliable,
+                criticality: Criticality::Partition,
+            };
+            let mut queuing = Queuing::try_from(config).unwrap();
+            assert_eq!(queuing.is_reliable(), reliable);
+            let mut source = QueuingSource::try_from(queuing.source_fd()).unwrap();
+            let mut destination = QueuingDestination::try_from(queuing.destination_fd()).unwrap();
+            let mut buf = [0; 8];
+            let mut read_all = |destination: &mut QueuingDestination| {
+                let mut read = Vec::new();
+                while let Some(received) = destination.read(&mut buf) {
+                    read.push(String::from_utf8(buf[..received.len].to_vec()).unwrap());
+                }
+                read
+            };
+
+            for msg in ["one", "two"] {
+                source.write(msg.as_bytes(), MonotonicTime::now()).unwrap();
+            }
+            assert!(queuing.swap());
+            assert!(queuing.inject(b"three").unwrap());
+            let received = destination.read(&mut [0; 8]).unwrap();
+            assert_eq!(received.len, 3);
+            // Not yet transferred, so not kept
+            source.write(b"four", MonotonicTime::now()).unwrap();
+
+            // What the hypervisor does on a restart of the destination before it read
+            // the remaining messages
+            queuing.clear_all();
+            if reliable {
+                assert_eq!(source.get_current_num_messages(), 2);
+                assert_eq!(read_all(&mut destination), ["two", "three"]);
+            } else {
+                assert!(read_all(&mut destination).is_empty());
+            }
+
+            // Read messages are not delivered again, neither are discarded ones
+            source.write(b"five", MonotonicTime::now()).unwrap();
+            assert!(queuing.swap());
+            queuing.clear_all();
+            assert_eq!(read_all(&mut destination).len(), usize::from(reliable));
+            source.write(b"six", MonotonicTime::now()).unwrap();
+            assert!(queuing.swap());
+            destination.clear(MonotonicTime::now());
+            queuing.clear_all();
+            assert!(read_all(&mut destination).is_empty());
+            assert_eq!(source.get_current_num_messages(), 0);
+        }
+    }
+
+    #[test]
+    fn waiting_processes_per_port() {
+        let config = QueuingChannelConfig {
+            msg_size: ByteSize::b(8),
+            msg_num: 4,
+            source: PortConfig {
+                partition: "a".into(),
+                port: "out".into(),
+            },
+            destination: PortConfig {
+                partition: "b".into(),
+                port: "in".into(),
+            },
+            on_partition_restart: OnPartitionRestart::Keep,
+            max_swap_per_frame: None,
+            transfer: Transfer::AfterSourceWindow,
+            zeroize: false,
+            reliable: false,
+            criticality: Criticality::Partition,
+        };
+        let mut queuing = Queuing::try_from(config).unwrap();
+        let mut source = QueuingSource::try_from(queuing.source_fd()).unwrap();
+        let mut destination = QueuingDestination::try_from(queuing.destination_fd()).unwrap();
+        // A second mapping, like the one of another process of the partition
+        let other = QueuingDestination::try_from(queuing.destination_fd()).unwrap();
+        assert_eq!(destination.num_waiting_processes(), 0);
+
+        destination.set_waiting(true);
+        destination.set_waiting(true);
+        assert_eq!(other.num_waiting_processes(), 2);
+        assert_eq!(source.num_waiting_processes(), 0);
+        destination.set_waiting(false);
+        assert_eq!(other.num_waiting_processes(), 1);
+
+        // Messages do not interfere with the counter
+        source.write(b"first", MonotonicTime::now()).unwrap();
+        assert!(queuing.swap());
+        assert_eq!(
+            destination.read(&mut [0; 8]),
+            Some(Received {
+                len: 5,
+                copied: 5,
+                overflowed: false
+            })
+        );
+        assert_eq!(other.num_waiting_processes(), 1);
+
+        // Only the ports of the restarted partition are reset
+        source.set_waiting(true);
+        queuing.reset_waiting("b");
+        assert_eq!(other.num_waiting_processes(), 0);
+        assert_eq!(source.num_waiting_processes(), 1);
+        destination.set_waiting(false);
+        assert_eq!(destination.num_waiting_processes(), 0);
+    }
+
+    #[test]
+    fn undersized_buffers() {
+        let config = QueuingChannelConfig {
+            msg_size: ByteSize::b(8),
+            msg_num: 4,
+            source: PortConfig {
+                partition: "a".into(),
+                port: "out".into(),
+            },
+            destination: PortConfig {
+                partition: "b".into(),
+                port: "in".into(),
+            },
+            on_partition_restart: OnPartitionRestart::Keep,
+            max_swap_per_frame: None,
+            transfer: Transfer::AfterSourceWindow,
+            zeroize: false,
+            reliable: false,
+            criticality: Criticality::Partition,
+        };
+        let mut queuing = Queuing::try_from(config).unwrap();
+        let mut source = QueuingSource::try_from(queuing.source_fd()).unwrap();
+        let mut destination = QueuingDestination::try_from(queuing.destination_fd()).unwrap();
+        for _ in 0..3 {
+            source.write(b"message", MonotonicTime::now()).unwrap();
+        }
+        assert!(queuing.swap());
+
+        // Exact fit
+        let mut buf = [0; 7];
+        let received = destination.read(&mut buf).unwrap();
+        assert_eq!((received.len, received.copied), (7, 7));
+        assert!(!received.truncated());
+        assert_eq!(&buf, b"message");
+
+        // One byte short, the message is consumed nevertheless
+        let mut buf = [0; 6];
+        let received = destination.read(&mut buf).unwrap();
+        assert_eq!((received.len, received.copied), (7, 6));
+        assert!(received.truncated());
+        assert_eq!(&buf, b"messag");
+        assert_eq!(destination.get_current_num_messages(), 1);
+
+        // Zero-length buffer
+        let received = destination.read(&mut []).unwrap();
+        assert_eq!((received.len, received.copied), (7, 0));
+        assert!(received.truncated());
+        assert!(!received.overflowed);
+        assert_eq!(destination.read(&mut [0; 8]), None);
+    }
+
+    #[test]
+    fn zero_length_messages() {
+        let config = QueuingChannelConfig {
+            msg_size: ByteSize::b(8),
+            msg_num: 4,
+            source: PortConfig {
+                partition: "a".into(),
+                port: "out".into(),
+            },
+            destination: PortConfig {
+                partition: "b".into(),
+                port: "in".into(),
+            },
+            on_partition_restart: OnPartitionRestart::Keep,
+            max_swap_per_frame: None,
+            transfer: Transfer::AfterSourceWindow,
+            zeroize: false,
+            reliable: false,
+            criticality: Criticality::Partition,
+        };
+        let mut queuing = Queuing::try_from(config).unwrap();
+        let mut source = QueuingSource::try_from(queuing.source_fd()).unwrap();
+        let mut destination = QueuingDestination::try_from(queuing.destination_fd()).unwrap();
+
+        assert_eq!(source.write(&[], MonotonicTime::now()), Some(0));
+        source.write(b"full", MonotonicTime::now()).unwrap();
+        assert_eq!(source.write(&[], MonotonicTime::now()), Some(0));
+        assert_eq!(source.get_current_num_messages(), 3);
+        assert!(queuing.swap());
+        assert_eq!(destination.get_current_num_messages(), 3);
+
+        // Empty messages keep their place in the queue and fit any buffer
+        let mut buf = [0xff; 8];
+        let empty = Received {
+            len: 0,
+            copied: 0,
+            overflowed: false,
+        };
+        assert_eq!(destination.read(&mut buf), Some(empty));
+        assert_eq!(buf, [0xff; 8]);
+        assert_eq!(destination.read(&mut buf).unwrap().len, 4);
+        assert_eq!(destination.read(&mut []), Some(empty));
+        // An empty queue is told apart from an empty message
+        assert_eq!(destination.read(&mut buf), None);
+    }
+
+    #[test]
+    fn oversized_queues() {
+        assert_eq!(
+            Queuing::checked_msg_size(ByteSize::kb(1), 100).unwrap(),
+            1_000
+        );
+        assert!(Queuing::checked_msg_size(ByteSize::b(8), 0).is_err());
+        assert!(Queuing::checked_msg_size(ByteSize::b(u64::MAX), 1).is_err());
+        assert!(Queuing::checked_msg_size(ByteSize::b(1), usize::MAX).is_err());
+        assert!(Queuing::checked_msg_size(ByteSize::gib(1), 4).is_err());
+        assert!(
+            Queuing::checked_msg_size(ByteSize::b(1), ConcurrentQueue::MAX_CAPACITY + 1).is_err()
+        );
+    }
+
+    #[test]
+    fn aligned_datagrams() {
+        let aligned = |ptr: *const u8| (ptr as usize).is_multiple_of(ALIGNMENT);
+        for msg_size in [0, 1, 5, 8, 13] {
+            let entry = Message::size(msg_size);
+            assert_eq!(entry % ALIGNMENT, 0);
+            assert_eq!(Message::size(entry - Message::size(0)), entry);
+        }
+
+        // Made of words, so that it starts aligned like shared memory
+        let size = SourceDatagram::size(5, 3);
+        let mut words = vec![0u64; size.div_ceil(size_of::<u64>())];
+        let buffer = unsafe { std::slice::from_raw_parts_mut(words.as_mut_ptr().cast(), size) };
+        let mut datagram = SourceDatagram::init_at(5, 3, buffer);
+        assert!(aligned(
+            (datagram.message_queue as *const ConcurrentQueue).cast()
+        ));
+
+        for _ in 0..3 {
+            datagram.push(&[1; 5], MonotonicTime::now()).unwrap();
+        }
+        while let Some(msg) = datagram.pop_then(|msg| {
+            aligned((msg.len as *const usize).cast())
+                && aligned((msg.timestamp as *const MonotonicTime).cast())
+        }) {
+            assert!(msg.unwrap());
+        }
+    }
+
+    #[test]
+    fn max_swap_per_frame_keeps_order_and_counts() {
+        const CAP: usize = 3;
+        const NUM: usize = 10 * CAP;
+        let config = QueuingChannelConfig {
+            msg_size: ByteSize::b(8),
+            msg_num: NUM,
+            source: PortConfig {
+                partition: "a".into(),
+                port: "out".into(),
+            },
+            destination: PortConfig {
+                partition: "b".into(),
+                port: "in".into(),
+            },
+            on_partition_restart: OnPartitionRestart::Keep,
+            max_swap_per_frame: NonZeroUsize::new(CAP),
+            transfer: Transfer::AfterSourceWindow,
+            zeroize: false,
+            reliable: false,
+            criticality: Criticality::Partition,
+        };
+        let mut queuing = Queuing::try_from(config).unwrap();
+        let mut source = QueuingSource::try_from(queuing.source_fd()).unwrap();
+        let mut destination = QueuingDestination::try_from(queuing.destination_fd()).unwrap();
+
+        for i in 0..NUM {
+            source
+                .write(&(i as u64).to_ne_bytes(), MonotonicTime::now())
+                .unwrap();
+        }
+        // The channel is full, even though the messages are not swapped yet
+        assert_eq!(source.write(b"overflow", MonotonicTime::now()), None);
+
+        let mut buf = [0; 8];
+        let mut next = 0;
+        for swap in 1..=NUM / CAP {
+            assert!(queuing.swap());
+            assert_eq!(queuing.throttled, swap < NUM / CAP);
+            assert_eq!(destination.get_current_num_messages(), NUM - next);
+            assert_eq!(source.get_current_num_messages(), NUM - next);
+
+            for _ in 0..CAP {
+                let Received {
+                    len, overflowed, ..
+                } = destination.read(&mut buf).unwrap();
+                assert_eq!(len, buf.len());
+                assert_eq!(u64::from_ne_bytes(buf), next as u64);
+                // The failed write is reported until the source succeeds again
+                assert!(overflowed);
+                next += 1;
+            }
+            assert_eq!(destination.read(&mut buf), None);
+        }
+        assert_eq!(next, NUM);
+        assert!(!queuing.swap());
+        assert_eq!(source.get_current_num_messages(), 0);
+        assert_eq!(destination.get_current_num_messages(), 0);
+
+        // Remaining capacity is available to the source again
+        for i in 0..NUM {
+            source
+                .write(&(i as u64).to_ne_bytes(), MonotonicTime::now())
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn malformed_messages_are_dropped() {
+        let config = QueuingChannelConfig {
+            msg_size: ByteSize::b(8),
+            msg_num: 4,
+            source: PortConfig {
+                partition: "a".into(),
+                port: "out".into(),
+            },
+            destination: PortConfig {
+                partition: "b".into(),
+                port: "in".into(),
+            },
+            on_partition_restart: OnPartitionRestart::Keep,
+            max_swap_per_frame: None,
+            transfer: Transfer::AfterSourceWindow,
+            zeroize: false,
+            reliable: false,
+            criticality: Criticality::Partition,
+        };
+        let mut queuing = Queuing::try_from(config).unwrap();
+        let mut source = QueuingSource::try_from(queuing.source_fd()).unwrap();
+        let mut destination = QueuingDestination::try_from(queuing.destination_fd()).unwrap();
+
+        source.write(b"evil", MonotonicTime::now()).unwrap();
+        corrupt_oldest(&mut source, 9);
+        source.write(b"good", MonotonicTime::now()).unwrap();
+        assert!(queuing.swap());
+        assert_eq!(queuing.corrupted(), 1);
+
+        let mut buf = [0; 8];
+        assert_eq!(destination.read(&mut buf).map(|r| r.len), Some(4));
+        assert_eq!(&buf[..4], b"good");
+        assert_eq!(destination.read(&mut buf), None);
+        // The dropped message no longer takes up capacity
+        assert_eq!(source.get_current_num_messages(), 1);
+
+        // Nothing is transferred if all messages are malformed
+        source.write(b"evil", MonotonicTime::now()).unwrap();
+        corrupt_oldest(&mut source, usize::MAX);
+        assert!(!queuing.swap());
+        assert_eq!(queuing.corrupted(), 2);
+        assert_eq!(destination.get_current_num_messages(), 0);
+    }
+
+    #[test]
+    fn verify_detects_corrupted_memory() {
+        let config = QueuingChannelConfig {
+            msg_size: ByteSize::b(8),
+            msg_num: 4,
+            source: PortConfig {
+                partition: "a".into(),
+                port: "out".into(),
+            },
+            destination: PortConfig {
+                partition: "b".into(),
+                port: "in".into(),
+            },
+            on_partition_restart: OnPartitionRestart::Keep,
+            max_swap_per_frame: None,
+            transfer: Transfer::AfterSourceWindow,
+            zeroize: false,
+            reliable: false,
+            criticality: Criticality::Partition,
+        };
+        let mut queuing = Queuing::try_from(config).unwrap();
+        let mut source = QueuingSource::try_from(queuing.source_fd()).unwrap();
+
+        source.write(b"first", MonotonicTime::now()).unwrap();
+        assert!(queuing.swap());
+        source.write(b"second", MonotonicTime::now()).unwrap();
+        let now = MonotonicTime::now();
+        assert!(queuing.verify_source(now).is_ok());
+        assert!(queuing.verify_destination(now).is_ok());
+        assert_eq!(queuing.source_partition(), "a");
+
+        // Messages must not be sent after the check
+        assert!(matches!(
+            queuing.verify_source(MonotonicTime::ZERO),
+            Err(BufferError::FutureTimestamp(_))
+        ));
+        assert!(matches!(
+            queuing.verify_destination(MonotonicTime::ZERO),
+            Err(BufferError::FutureTimestamp(_))
+        ));
+
+        corrupt_oldest(&mut source, 9);
+        assert!(matches!(
+            queuing.verify_source(now),
+            Err(BufferError::MessageLength {
+                len: 9,
+                msg_size: 8
+            })
+        ));
+        corrupt_oldest(&mut source, 6);
+
+        let later = now.checked_add(Duration::from_secs(1)).unwrap();
+        source.write(b"late", later).unwrap();
+        assert!(matches!(
+            queuing.verify_source(now),
+            Err(BufferError::FutureTimestamp(d)) if d == Duration::from_secs(1)
+        ));
+        assert!(queuing.verify_source(later).is_ok());
+    }
 }
@@ -1,8 +1,60 @@
 use std::cell::UnsafeCell;
 use std::fmt::{Debug, Formatter};
-use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(not(loom))]
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::{mem, ptr};
 
+#[cfg(loom)]
+use loom::sync::atomic::{AtomicU64, Ordering};
+
+use crate::buffer::BufferError;
+
+/// Bits of each field packed into the [State] of a queue
+///
+/// The state is 64 bits wide on every target, so that 32-bit targets get the
+/// same capacity and the same layout of the queue.
+const FIELD_BITS: u32 = u64::BITS / 4;
+const FIELD_MASK: u64 = (1 << FIELD_BITS) - 1;
+
+/// Where the elements of a queue lie, packed into a single 64-bit word so that
+/// it changes at once
+///
+/// A push reserves the slot behind the elements and the slots already
+/// reserved, writes it and marks it done. Only once all reserved slots are
+/// done, they become elements, so that no element is read while it is
+/// written, and no push waits for another. Popping and clearing move `first`
+/// only past elements, so the slot of a push in progress stays put.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct State {
+    /// Index of the oldest element
+    first: usize,
+    /// Number of elements
+    len: usize,
+    /// Number of slots behind the elements being pushed
+    reserved: usize,
+    /// Number of reserved slots written completely
+    done: usize,
+}
+
+impl State {
+    fn unpack(word: u64) -> Self {
+        // Every field fits into 16 bits, thus into a usize
+        let field = |i: u32| ((word >> (i * FIELD_BITS)) & FIELD_MASK) as usize;
+        Self {
+            first: field(0),
+            len: field(1),
+            reserved: field(2),
+            done: field(3),
+        }
+    }
+
+    fn pack(self) -> u64 {
+        // Fields exceeding the mask are cut off, but never occur
+        let field = |value: usize, i: u32| (value as u64 & FIELD_MASK) << (i * FIELD_BITS);
+        field(self.first, 0) | field(self.len, 1) | field(self.reserved, 2) | field(self.done, 3)
+    }
+}
+
 /// An unsized bounded concurrent queue (Fifo) that makes use of atomics and
 /// does not use pointers internally. This allows the queue to be
 /// created inside a buffer of type `&[u8]` via [ConcurrentQueue::init_at].
@@ -10,13 +62,19 @@ use std::{mem, ptr};
 /// [ConcurrentQueue::size] by providing the size and maximum number of
 /// entries.
 ///
-/// See [tests] for examples
+/// Any number of threads or processes may push while others pop or clear the
+/// queue. An element is only popped once it was written completely. Concurrent
+/// pushes become visible together, once the last of them is done. Pops by more
+/// than one consumer at a time are not supported, as they may read an element
+/// while it is overwritten. The capacity is limited to
+/// [ConcurrentQueue::MAX_CAPACITY].
+#[repr(C)]
 pub struct ConcurrentQueue {
     pub msg_size: usize,
     pub msg_capacity: usize,
 
-    len: AtomicUsize,
-    first: AtomicUsize,
+    /// The packed [State]
+    state: AtomicU64,
     data: UnsafeCell<[u8]>,
 }
 
@@ -28,6 +86,10 @@ impl ptr_meta::Pointee for ConcurrentQueue {
 }
 
 impl ConcurrentQueue {
+    /// Largest number of elements of a queue, as its indices are packed into
+    /// a single word
+    pub const MAX_CAPACITY: usize = FIELD_MASK as usize;
+
     /// Calculates the required buffer size to fit a MessageQueue object
     /// with `capacity` maximum elements and a fixed size of `element_size`
     /// bytes per element.
@@ -51,15 +113,13 @@ impl ConcurrentQueue {
     fn fields_size() -> usize {
         mem::size_of::<usize>() // entry_size
                 + mem::size_of::<usize>() // capacity
-                + mem::size_of::<AtomicUsize>() // len
-                + mem::size_of::<AtomicUsize>() // first
+                + mem::size_of::<AtomicU64>() // state
     }
 
     /// Returns this struct's alignment
     fn align() -> usize {
-        // This structs maximum alignment is that of a usize (or AtomicUsize, which has
-        // the same data layout)
-        mem::align_of::<usize>()
+        // This structs maximum alignment is that of a usize or AtomicU64
+        mem::align_of::<usize>().max(mem::align_of::<AtomicU64>())
     }
 
     /// Creates a new empty ConcurrentQueue in given buffer.
@@ -69,10 +129,41 @@ impl ConcurrentQueue {
     ///
     /// # Panics
     /// If the buffer size is not exactly the required size to fit this
-    /// `ConcurrentQueue` object.
+    /// `ConcurrentQueue` object, or if `capacity` exceeds
+    /// [ConcurrentQueue::MAX_CAPACITY].
     pub fn init_at(buffer: &mut [u8], element_size: usize, capacity: usize) -> &Self {
         assert_eq!(buffer.len(), Self::size(element_size, capacity));
+        assert!(capacity <= Self::MAX_CAPACITY);
+
+        Self::init_unchecked(buffer, element_size, capacity)
+    }
+
+    /// Creates a new empty ConcurrentQueue in given buffer like
+    /// [ConcurrentQueue::init_at], returning an error instead of panicking if
+    /// the buffer size or the capacity do not fit.
+    pub fn try_init_at(
+        buffer: &mut [u8],
+        element_size: usize,
+        capacity: usize,
+    ) -> Result<&Self, BufferError> {
+        if capacity > Self::MAX_CAPACITY {
+            return Err(BufferError::TooLarge {
+                msg_size: element_size,
+                capacity,
+            });
+        }
+        let expected = Self::size(element_size, capacity);
+        if buffer.len() != expected {
+            return Err(BufferError::Size {
+                expected,
+                actual: buffer.len(),
+            });
+        }
 
+        Ok(Self::init_unchecked(buffer, element_size, capacity))
+    }
+
+    fn init_unchecked(buffer: &mut [u8], element_size: usize, capacity: usize) -> &Self {
         // We cast the `buffer` reference to a `Self` pointer, which can then safely be
         // dereferenced
         let queue = unsafe { &mut *Self::buf_to_self_mut(buffer) };
@@ -81,8 +172,7 @@ impl ConcurrentQueue {
         queue.msg_capacity = capacity;
         // Use `ptr::write` to prevent the compiler from trying to drop previous values.
         unsafe {
-            ptr::write(&mut queue.len, AtomicUsize::new(0));
-            ptr::write(&mut queue.first, AtomicUsize::new(0));
+            ptr::write(&mut queue.state, AtomicU64::new(0));
         }
 
         queue
@@ -114,22 +204,70 @@ impl ConcurrentQueue {
     /// UB, because the ConcurrentQueue relies on internal safety mechanisms
     /// to prevent UB due to shared mutable state.
     pub unsafe fn load_from(buffer: &[u8]) -> &Self {
-        let obj = &*Self::buf_to_self(buffer);
+        #[cfg(debug_assertions)]
+        if let Err(e) = Self::validate(buffer) {
+            panic!("invalid queue: {e}");
+        }
 
-        // Perform some validity checks
-        debug_assert!(obj.len.load(Ordering::SeqCst) <= obj.msg_capacity); // Check length
-        debug_assert!(obj.first.load(Ordering::SeqCst) < obj.msg_capacity); // Check first idx
+        &*Self::buf_to_self(buffer)
+    }
 
-        // Also check if unsized data field is of correct size
-        // Note: obj_data may be longer than `obj.msg_size * obj.msg_capacity` due to
-        // alignment padding. To correct we call `Self::size`.
-        let obj_data = obj.data.get().as_ref().unwrap();
-        debug_assert_eq!(
-            obj_data.len(),
-            Self::size(obj.msg_size, obj.msg_capacity) - Self::fields_size()
-        );
+    /// Checks that `buffer` holds a queue whose length, first index, pushes
+    /// in progress and size are consistent, without panicking
+    ///
+    /// Meant for buffers shared with another process, which may have
+    /// corrupted them.
+    pub fn validate(buffer: &[u8]) -> Result<(), BufferError> {
+        if buffer.len() < Self::fields_size() {
+            return Err(BufferError::Size {
+                expected: Self::fields_size(),
+                actual: buffer.len(),
+            });
+        }
+        let obj = unsafe { &*Self::buf_to_self(buffer) };
+
+        let capacity = obj.msg_capacity;
+        let state = obj.state();
+        let len = state.len + state.reserved;
+        if len > capacity {
+            return Err(BufferError::QueueLength { len, capacity });
+        }
+        if state.first >= capacity {
+            return Err(BufferError::QueueIndex {
+                first: state.first,
+                capacity,
+            });
+        }
+        if state.done > state.reserved {
+            return Err(BufferError::QueuePushes {
+                reserved: state.reserved,
+                done: state.done,
+            });
+        }
 
-        obj
+        // The data may be longer than `msg_size * msg_capacity` due to alignment
+        // padding, which `Self::size` accounts for. A product exceeding the buffer is
+        // wrong anyway, and checking it first keeps `Self::size` from overflowing.
+        let fits = capacity <= Self::MAX_CAPACITY
+            && obj
+                .msg_size
+                .checked_mul(capacity)
+                .and_then(|data| data.checked_add(Self::fields_size()))
+                .is_some_and(|size| size <= buffer.len());
+        if !fits {
+            return Err(BufferError::TooLarge {
+                msg_size: obj.msg_size,
+                capacity,
+            });
+        }
+        let expected = Self::size(obj.msg_size, capacity);
+        if expected != buffer.len() {
+            return Err(BufferError::Size {
+                expected,
+                actual: buffer.len(),
+            });
+        }
+        Ok(())
     }
 
     /// Calculates the physical starting index of an element inside of the
@@ -138,6 +276,21 @@ impl ConcurrentQueue {
         (first + idx) % self.msg_capacity * self.msg_size
     }
 
+    fn state(&self) -> State {
+        State::unpack(self.state.load(Ordering::Acquire))
+    }
+
+    /// Updates the state with `f` until it succeeds or `f` returns `None`,
+    /// returning the previous state
+    fn update(&self, mut f: impl FnMut(State) -> Option<State>) -> Result<State, State> {
+        self.state
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |word| {
+                f(State::unpack(word)).map(State::pack)
+            })
+            .map(State::unpack)
+            .map_err(State::unpack)
+    }
+
     /// Pushes an element to the back of the queue. If there was space, a
     /// mutable reference to the inserted element is returned.
     pub fn push(&self, data: &[u8]) -> Option<&mut [u8]> {
@@ -149,25 +302,47 @@ impl ConcurrentQueue {
     /// Pushes an uninitialized element and then calls a closure to set its
     /// memory in-place. If there was space, a mutable reference to
     /// the inserted element is returned.
+    #[allow(clippy::mut_from_ref)]
     pub fn push_then<F: FnOnce(&'_ mut [u8])>(&self, set_element: F) -> Option<&mut [u8]> {
-        let current_len = self.len.load(Ordering::SeqCst);
-        if current_len == self.msg_capacity {
-            return None;
-        }
-
-        let insert_idx = self.len.fetch_add(1, Ordering::SeqCst);
+        let reserved = self
+            .update(|s| {
+                (s.len + s.reserved < self.msg_capacity).then_some(State {
+                    reserved: s.reserved + 1,
+                    ..s
+                })
+            })
+            .ok()?;
 
-        let idx = self.to_physical_idx(self.first.load(Ordering::SeqCst), insert_idx);
+        let idx = self.to_physical_idx(reserved.first, reserved.len + reserved.reserved);
         let element_slot =
             &mut unsafe { self.data.get().as_mut().unwrap() }[idx..(idx + self.msg_size)];
 
         set_element(element_slot);
 
+        // The last push in progress turns all of them into elements. A push
+        // abandoned meanwhile is not counted anymore.
+        let _ = self.update(|s| {
+            if s.reserved == 0 {
+                None
+            } else if s.done + 1 == s.reserved {
+                Some(State {
+                    len: s.len + s.reserved,
+                    reserved: 0,
+                    done: 0,
+                    ..s
+                })
+            } else {
+                Some(State {
+                    done: s.done + 1,
+                    ..s
+                })
+            }
+        });
+
         Some(element_slot)
     }
 
     /// Tries to pop an element from the front of the queue.
-    #[allow(unused)]
     pub fn pop(&self) -> Option<Box<[u8]>> {
         self.pop_then(|entry| Vec::from(entry).into_boxed_slice())
     }
@@ -177,44 +352,100 @@ impl ConcurrentQueue {
     /// is returned by this function. If the popped element is
     /// needed as owned data, consider using [ConcurrentQueue::pop] instead.
     pub fn pop_then<F: FnOnce(&'_ [u8]) -> T, T>(&'_ self, map_element: F) -> Option<T> {
-        // Decrement length
-        self.len
-            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |len| len.checked_sub(1))
-            .ok()?;
-
-        let prev_first = self
-            .first
-            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |x| {
-                Some((x + 1) % self.msg_capacity)
-            })
-            .unwrap();
-
-        let idx = self.to_physical_idx(prev_first, 0);
-
-        let msg = &unsafe { &*self.data.get() }[idx..(idx + self.msg_size)];
+        let mut msg = vec![0; self.msg_size];
+        loop {
+            let state = self.state();
+            if state.len == 0 {
+                return None;
+            }
 
-        Some(map_element(msg))
+            // The element is copied before its slot is released to the
+            // producers. Only if the state changed meanwhile, e.g. by a clear,
+            // the copy is discarded and taken again.
+            let idx = self.to_physical_idx(state.first, 0);
+            msg.copy_from_slice(&unsafe { &*self.data.get() }[idx..(idx + self.msg_size)]);
+            let popped = State {
+                first: (state.first + 1) % self.msg_capacity,
+                len: state.len - 1,
+                ..state
+            };
+            if self
+                .state
+                .compare_exchange(
+                    state.pack(),
+                    popped.pack(),
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                )
+                .is_ok()
+            {
+                return Some(map_element(&msg));
+            }
+        }
     }
 
     pub fn peek_then<T, F: FnOnce(Option<&[u8]>) -> T>(&self, f: F) -> T {
-        let len = self.len.load(Ordering::SeqCst);
+        let state = self.state();
 
-        let msg = (len > 0).then(|| {
-            let first = self.first.load(Ordering::SeqCst);
-            let idx = self.to_physical_idx(first, 0);
+        let msg = (state.len > 0).then(|| {
+            let idx = self.to_physical_idx(state.first, 0);
             unsafe { &(&*self.data.get())[idx..(idx + self.msg_size)] }
         });
 
         f(msg)
     }
 
+    /// Calls `f` on every element, oldest first, without popping them
+    ///
+    /// Stops at the first error returned by `f`. The queue must have passed
+    /// [ConcurrentQueue::validate].
+    pub fn try_for_each<E>(&self, mut f: impl FnMut(&[u8]) -> Result<(), E>) -> Result<(), E> {
+        let state = self.state();
+        let data = unsafe { &*self.data.get() };
+        (0..state.len).try_for_each(|i| {
+            let idx = self.to_physical_idx(state.first, i);
+            f(&data[idx..(idx + self.msg_size)])
+        })
+    }
+
     /// Returns the current length of this queue
+    ///
+    /// Pushes in progress are not counted.
     pub fn len(&self) -> usize {
-        self.len.load(Ordering::SeqCst)
+        self.state().len
+    }
+
+    /// Returns whether this queue holds no elements
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
+    /// Removes all elements
+    ///
+    /// Pushes in progress are kept and become elements once done.
     pub fn clear(&self) {
-        self.len.store(0, Ordering::SeqCst);
+        let _ = self.update(|s| {
+            (s.len > 0).then(|| State {
+                first: (s.first + s.len) % self.msg_capacity,
+                len: 0,
+                ..s
+            })
+        });
+    }
+
+    /// Forgets the pushes in progress, freeing their slots
+    ///
+    /// Meant for producers which died while pushing, e.g. with their
+    /// partition, as their pushes would hold back those of others forever.
+    /// No other push may be in progress.
+    pub fn abandon_pushes(&self) {
+        let _ = self.update(|s| {
+            (s.reserved > 0).then_some(State {
+                reserved: 0,
+                done: 0,
+                ..s
+            })
+        });
     }
 }
 
@@ -223,15 +454,23 @@ impl Debug for ConcurrentQueue {
         f.debug_struct("ConcurrentQueue")
             .field("msg_size", &self.msg_size)
             .field("msg_capacity", &self.msg_capacity)
-            .field("len", &self.len)
-            .field("first", &self.first)
+            .field("state", &self.state())
             .finish_non_exhaustive()
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, not(loom)))]
 mod tests {
-    use super::ConcurrentQueue;
+    use std::collections::{HashMap, VecDeque};
+    use std::mem::size_of;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::Mutex;
+    use std::thread;
+
+    use proptest::prelude::*;
+
+    use super::{ConcurrentQueue, State};
+    use crate::buffer::BufferError;
 
     #[test]
     fn single_queue() {
@@ -290,6 +529,151 @@ mod tests {
         assert_eq!(queue1.pop(), None);
     }
 
+    #[test]
+    fn validate_detects_corrupted_fields() {
+        const ELEMENT_SIZE: usize = 2;
+        const CAPACITY: usize = 3;
+        const STATE: usize = 2 * size_of::<usize>();
+
+        let mut buffer: Vec<u8> = vec![0u8; ConcurrentQueue::size(ELEMENT_SIZE, CAPACITY)];
+        let queue = ConcurrentQueue::init_at(&mut buffer, ELEMENT_SIZE, CAPACITY);
+        queue.push(&[0x1, 0x2]).unwrap();
+        queue.push(&[0x3, 0x4]).unwrap();
+        assert!(ConcurrentQueue::validate(&buffer).is_ok());
+
+        let mut entries = Vec::new();
+        unsafe { ConcurrentQueue::load_from(&buffer) }
+            .try_for_each(|entry| {
+                entries.push(entry.to_vec());
+                Ok::<_, ()>(())
+            })
+            .unwrap();
+        assert_eq!(entries, [[0x1, 0x2], [0x3, 0x4]]);
+
+        let corrupted = |state: State| {
+            let mut corrupted = buffer.clone();
+            corrupted[STATE..][..size_of::<u64>()].copy_from_slice(&state.pack().to_ne_bytes());
+            ConcurrentQueue::validate(&corrupted)
+        };
+        let state = State {
+            first: 0,
+            len: 2,
+            reserved: 0,
+            done: 0,
+        };
+        assert!(matches!(
+            corrupted(State { len: 4, ..state }),
+            Err(BufferError::QueueLength {
+                len: 4,
+                capacity: CAPACITY
+            })
+        ));
+        assert!(matches!(
+            corrupted(State {
+                reserved: 2,
+                ..state
+            }),
+            Err(BufferError::QueueLength {
+                len: 4,
+                capacity: CAPACITY
+            })
+        ));
+        assert!(matches!(
+            corrupted(State {
+                first: CAPACITY,
+                ..state
+            }),
+            Err(BufferError::QueueIndex {
+                first: CAPACITY,
+                capacity: CAPACITY
+            })
+        ));
+        assert!(matches!(
+            corrupted(State {
+                reserved: 1,
+                done: 2,
+                ..state
+            }),
+            Err(BufferError::QueuePushes {
+                reserved: 1,
+                done: 2
+            })
+        ));
+
+        let mut corrupted = buffer.clone();
+        corrupted[..size_of::<usize>()].copy_from_slice(&usize::MAX.to_ne_bytes());
+        assert!(matches!(
+            ConcurrentQueue::validate(&corrupted),
+            Err(BufferError::TooLarge { .. })
+        ));
+
+        assert!(matches!(
+            ConcurrentQueue::validate(&buffer[..STATE]),
+            Err(BufferError::Size { .. })
+        ));
+    }
+
+    #[test]
+    fn packed_state() {
+        let state = State {
+            first: 3,
+            len: ConcurrentQueue::MAX_CAPACITY,
+            reserved: 1,
+            done: ConcurrentQueue::MAX_CAPACITY - 1,
+        };
+        assert_eq!(State::unpack(state.pack()), state);
+        assert_eq!(State::unpack(0).len, 0);
+    }
+
+    #[test]
+    fn pushes_become_elements_once_all_are_done() {
+        let mut buffer: Vec<u8> = vec![0u8; ConcurrentQueue::size(1, 3)];
+        let queue = ConcurrentQueue::init_at(&mut buffer, 1, 3);
+
+        queue.push_then(|entry| {
+            entry[0] = 1;
+            // Done, but behind a push in progress
+            queue.push(&[2]).unwrap();
+            assert_eq!(queue.len(), 0);
+            assert_eq!(queue.pop(), None);
+            queue.peek_then(|msg| assert_eq!(msg, None));
+        });
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.pop().as_deref(), Some(&[1][..]));
+        assert_eq!(queue.pop().as_deref(), Some(&[2][..]));
+
+        // Cleared elements free their slots, pushes in progress are kept
+        queue.push(&[3]).unwrap();
+        queue.push_then(|entry| {
+            entry[0] = 4;
+            queue.clear();
+            queue.push(&[5]).unwrap();
+        });
+        assert_eq!(queue.pop().as_deref(), Some(&[4][..]));
+        assert_eq!(queue.pop().as_deref(), Some(&[5][..]));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn abandoned_pushes_free_their_slots() {
+        let mut buffer: Vec<u8> = vec![0u8; ConcurrentQueue::size(1, 2)];
+        let queue = ConcurrentQueue::init_at(&mut buffer, 1, 2);
+
+        // A producer dying while it pushes holds back the following pushes
+        let died = panic::catch_unwind(AssertUnwindSafe(|| {
+            queue.push_then(|_| panic!("producer died"));
+        }));
+        assert!(died.is_err());
+        queue.push(&[1]).unwrap();
+        assert_eq!(queue.push(&[2]), None);
+        assert!(queue.is_empty());
+
+        queue.abandon_pushes();
+        queue.push(&[3]).unwrap();
+        assert_eq!(queue.pop().as_deref(), Some(&[3][..]));
+        assert!(queue.is_empty());
+    }
+
     #[test]
     /// Spawn one thousand threads with access to a single queue. Each thread
     /// pushes four elements on the queue. In the end validate that there is the
@@ -315,7 +699,7 @@ mod tests {
         let threads = (0..NUM_QUEUES).map(|_| {
             let cloned_buffer = buffer.clone();
             thread::spawn(move || {
-                let queue = unsafe { ConcurrentQueue::load_from(&*cloned_buffer) };
+                let queue = unsafe { ConcurrentQueue::load_from(&cloned_buffer) };
                 queue.push(&[0x1, 0x2]).unwrap();
                 queue.push(&[0x3, 0x4]).unwrap();
                 queue.push(&[0x5, 0x6]).unwrap();
@@ -325,7 +709,7 @@ mod tests {
 
         threads.for_each(|handle| handle.join().expect("that the thread has not panicked"));
 
-        let queue = unsafe { ConcurrentQueue::load_from(&*buffer) };
+        let queue = unsafe { ConcurrentQueue::load_from(&buffer) };
 
         // There should be 4 elements per thread in the queue
         assert_eq!(queue.len(), 4 * NUM_QUEUES);
@@ -353,6 +737,16 @@ mod tests {
         let _ = ConcurrentQueue::init_at(&mut buffer, 1, 1);
     }
 
+    #[test]
+    fn try_init_wrong_size() {
+        let mut buffer: Vec<u8> = vec![0u8; 100];
+        let err = ConcurrentQueue::try_init_at(&mut buffer, 1, 1).unwrap_err();
+        assert!(matches!(
+            err,
+            BufferError::Size { expected, actual: 100 } if expected == ConcurrentQueue::size(1, 1)
+        ));
+    }
+
     #[test]
     #[should_panic]
     fn element_too_big() {
@@ -379,4 +773,237 @@ mod tests {
 
         queue.push(&[]);
     }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Push(u8),
+        Pop,
+        Peek,
+        Clear,
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            3 => any::<u8>().prop_map(Op::Push),
+            2 => Just(Op::Pop),
+            1 => Just(Op::Peek),
+            1 => Just(Op::Clear),
+        ]
+    }
+
+    fn consumer_op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            4 => Just(Op::Pop),
+            1 => Just(Op::Peek),
+            1 => Just(Op::Clear),
+        ]
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        /// Threads run their operations one at a time in whatever order the
+        /// scheduler picks, each compared to a model of the queue
+        #[test]
+        fn matches_model(
+            capacity in 1..5usize,
+            scripts in prop::collection::vec(prop::collection::vec(op(), 0..32), 1..4),
+        ) {
+            let mut buffer: Vec<u8> = vec![0u8; ConcurrentQueue::size(2, capacity)];
+            let queue = ConcurrentQueue::init_at(&mut buffer, 2, capacity);
+            let model = Mutex::new(VecDeque::new());
+
+            thread::scope(|s| {
+                for (thread, script) in scripts.iter().enumerate() {
+                    let model = &model;
+                    s.spawn(move || {
+                        for op in script {
+                            let mut model = model.lock().unwrap();
+                            match *op {
+                                Op::Push(value) => {
+                                    let msg = [thread as u8, value];
+                                    let pushed = queue.push(&msg).is_some();
+                                    assert_eq!(pushed, model.len() < capacity);
+                                    if pushed {
+                                        model.push_back(msg);
+                                    }
+                                }
+                                Op::Pop => {
+                                    let popped = queue.pop();
+                                    assert_eq!(popped.as_deref(), model.pop_front().as_ref().map(|m| &m[..]));
+                                }
+                                Op::Peek => queue.peek_then(|msg| {
+                                    assert_eq!(msg, model.front().map(|m| &m[..]));
+                                }),
+                                Op::Clear => {
+                                    queue.clear();
+                                    model.clear();
+                                }
+                            }
+                            assert_eq!(queue.len(), model.len());
+                        }
+                    });
+                }
+            });
+
+            let mut remaining = Vec::new();
+            queue
+                .try_for_each(|msg| {
+                    remaining.push([msg[0], msg[1]]);
+                    Ok::<_, ()>(())
+                })
+                .unwrap();
+            prop_assert!(remaining.iter().eq(model.lock().unwrap().iter()));
+            prop_assert!(ConcurrentQueue::validate(&buffer).is_ok());
+        }
+
+        /// Producers push numbered messages while a consumer pops, peeks and
+        /// clears at the same time
+        #[test]
+        fn concurrent_producers_keep_their_order(
+            capacity in 1..8usize,
+            producers in 1..4u8,
+            pushes in 1..64u8,
+            script in prop::collection::vec(consumer_op(), 0..64),
+        ) {
+            let mut buffer: Vec<u8> = vec![0u8; ConcurrentQueue::size(2, capacity)];
+            let queue = ConcurrentQueue::init_at(&mut buffer, 2, capacity);
+
+            let (pushed, mut consumed) = thread::scope(|s| {
+                let handles = (0..producers)
+                    .map(|producer| {
+                        s.spawn(move || {
+                            (0..pushes)
+                                .filter(|seq| queue.push(&[producer, *seq]).is_some())
+                                .count()
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                let mut consumed = Vec::new();
+                for op in &script {
+                    match op {
+                        Op::Pop => consumed.extend(queue.pop()),
+                        Op::Peek => queue.peek_then(|msg| {
+                            if let Some(msg) = msg {
+                                assert!(msg[0] < producers && msg[1] < pushes);
+                            }
+                        }),
+                        _ => queue.clear(),
+                    }
+                }
+                let pushed: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+                (pushed, consumed)
+            });
+            while let Some(msg) = queue.pop() {
+                consumed.push(msg);
+            }
+
+            // Nothing is duplicated, corrupted or reordered
+            let mut last = HashMap::new();
+            for msg in &consumed {
+                prop_assert!(msg[0] < producers && msg[1] < pushes, "corrupted {msg:?}");
+                let previous = last.insert(msg[0], msg[1]);
+                prop_assert!(previous < Some(msg[1]), "{msg:?} after {previous:?}");
+            }
+            if !script.iter().any(|op| matches!(op, Op::Clear)) {
+                prop_assert_eq!(consumed.len(), pushed);
+            }
+            prop_assert!(ConcurrentQueue::validate(&buffer).is_ok());
+        }
+    }
+}
+
+/// Explores all interleavings of concurrent pushes, pops and clears on small
+/// queues with [loom], checking the orderings of the state updates
+///
+/// Run with `RUSTFLAGS="--cfg loom" cargo test -p a653rs-linux-core --release
+/// --lib queue::model_checking`.
+#[cfg(all(test, loom))]
+mod model_checking {
+    use loom::sync::Arc;
+    use loom::thread;
+
+    use super::ConcurrentQueue;
+
+    fn new_queue(capacity: usize, elements: &[u8]) -> Arc<Vec<u8>> {
+        let mut buffer: Vec<u8> = vec![0u8; ConcurrentQueue::size(1, capacity)];
+        let queue = ConcurrentQueue::init_at(&mut buffer, 1, capacity);
+        for element in elements {
+            queue.push(&[*element]).unwrap();
+        }
+        Arc::new(buffer)
+    }
+
+    fn queue(buffer: &[u8]) -> &ConcurrentQueue {
+        unsafe { ConcurrentQueue::load_from(buffer) }
+    }
+
+    fn pop(buffer: &[u8]) -> Option<u8> {
+        queue(buffer).pop().map(|msg| msg[0])
+    }
+
+    fn drain(buffer: &[u8]) -> Vec<u8> {
+        std::iter::from_fn(|| pop(buffer)).collect()
+    }
+
+    #[test]
+    fn concurrent_pushes_and_pop() {
+        loom::model(|| {
+            let buffer = new_queue(2, &[]);
+            let producers = [1, 2].map(|element| {
+                let buffer = buffer.clone();
+                thread::spawn(move || queue(&buffer).push(&[element]).is_some())
+            });
+
+            // Never sees the zeroed slot of a push in progress
+            let mut popped = Vec::from_iter(pop(&buffer));
+            for producer in producers {
+                assert!(producer.join().unwrap());
+            }
+            popped.extend(drain(&buffer));
+            popped.sort();
+            assert_eq!(popped, [1, 2]);
+            assert!(ConcurrentQueue::validate(&buffer).is_ok());
+        });
+    }
+
+    #[test]
+    fn push_and_pop_wrap_around() {
+        loom::model(|| {
+            let buffer = new_queue(1, &[1]);
+            let producer = {
+                let buffer = buffer.clone();
+                thread::spawn(move || queue(&buffer).push(&[2]).is_some())
+            };
+
+            let mut popped = Vec::from_iter(pop(&buffer));
+            let pushed = producer.join().unwrap();
+            popped.extend(drain(&buffer));
+            assert_eq!(popped, if pushed { vec![1, 2] } else { vec![1] });
+            assert!(ConcurrentQueue::validate(&buffer).is_ok());
+        });
+    }
+
+    #[test]
+    fn clear_races_with_push_and_pop() {
+        loom::model(|| {
+            let buffer = new_queue(2, &[1]);
+            let producer = {
+                let buffer = buffer.clone();
+                thread::spawn(move || queue(&buffer).push(&[2]).is_some())
+            };
+            let clearer = {
+                let buffer = buffer.clone();
+                thread::spawn(move || queue(&buffer).clear())
+            };
+
+            let mut popped = Vec::from_iter(pop(&buffer));
+            assert!(producer.join().unwrap());
+            clearer.join().unwrap();
+            popped.extend(drain(&buffer));
+            // Cleared elements are gone, others are popped once and in order
+            assert!(matches!(popped[..], [] | [1] | [2] | [1, 2]), "{popped:?}");
+            assert!(ConcurrentQueue::validate(&buffer).is_ok());
+        });
+    }
 }
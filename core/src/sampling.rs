@@ -1,114 +1,343 @@
-use std::collections::HashSet;
+use std::collections::{HashSet, VecDeque};
 use std::convert::AsRef;
+use std::mem::size_of;
+use std::num::NonZeroUsize;
 use std::os::fd::{AsFd, BorrowedFd};
 use std::os::unix::prelude::{AsRawFd, OwnedFd, RawFd};
-use std::time::Instant;
+use std::sync::atomic::{fence, AtomicU32, Ordering};
+use std::time::Duration;
 
 use a653rs::bindings::PortDirection;
-use memfd::{FileSeal, Memfd, MemfdOptions};
-use memmap2::{Mmap, MmapMut};
+use anyhow::anyhow;
+use bytesize::ByteSize;
+use memfd::{FileSeal, Memfd};
+use memmap2::{Advice, Mmap, MmapMut};
 
-use crate::channel::{PortConfig, SamplingChannelConfig};
-use crate::error::{ResultExt, SystemError, TypedError, TypedResult};
+use crate::buffer::{self, BufferError};
+use crate::channel::{
+    Criticality, OnPartitionRestart, PortConfig, SamplingChannelConfig, Transfer,
+};
+use crate::error::{fd_limit_hint, ResultExt, SystemError, TypedError, TypedResult};
 use crate::partition::SamplingConstant;
+use crate::shmem::{lock_error, touch_pages, wipe};
+use crate::time::MonotonicTime;
 
+/// A message in the shared memory of a sampling port
+///
+/// The memory starts with the version of its layout and a sequence number,
+/// followed by two slots each holding a message:
+///
+/// | Field    | Type                                                          |
+/// |----------|---------------------------------------------------------------|
+/// | version  | `u32`                                                         |
+/// | sequence | `u32`                                                         |
+/// | slots    | 2 × (`MonotonicTime`, `u64` seq, `u32` length, data, padding) |
+///
+/// The lowest bit of the sequence number is the slot holding the current
+/// message. A writer fills the other slot and only then publishes it by
+/// incrementing the sequence number, so a writer frozen in the middle of a
+/// write never leaves a half-updated message behind. Readers retry if the
+/// sequence number changed while they were copying the message.
+///
+/// The `seq` of a message is stamped by the hypervisor on sequenced channels
+/// and zero otherwise.
 #[derive(Debug, Clone)]
-struct Datagram<'a> {
-    copied: Instant,
-    //_len: u32,
-    data: &'a [u8], //data: Vec<u8>,
+pub(crate) struct Datagram<'a> {
+    pub(crate) copied: MonotonicTime,
+    pub(crate) seq: u64,
+    /// Length stored with the message, which exceeds the length of `data` if
+    /// the buffer was too small or the writer corrupted the slot
+    pub(crate) len: usize,
+    pub(crate) data: &'a [u8],
 }
 
 impl<'a> Datagram<'a> {
-    const EXTRA_BYTES: usize = std::mem::size_of::<Instant>() + std::mem::size_of::<u32>();
+    /// Version of the layout, to be increased with every change to it
+    const VERSION: u32 = 3;
 
-    const fn size(msg_size: usize) -> u32 {
-        (msg_size + Self::EXTRA_BYTES) as u32
+    const HEADER_SIZE: usize = 2 * size_of::<u32>();
+
+    const SLOT_HEADER_SIZE: usize =
+        size_of::<MonotonicTime>() + size_of::<u64>() + size_of::<u32>();
+
+    /// Largest message size whose datagram size fits into a `u32`, leaving
+    /// room for the padding of both slots
+    pub(crate) const MAX_MSG_SIZE: usize =
+        (u32::MAX as usize - Self::HEADER_SIZE) / 2 - Self::SLOT_HEADER_SIZE - 7;
+
+    /// Size of a slot, padded so that the timestamp of each slot is aligned
+    const fn slot_size(msg_size: usize) -> usize {
+        (Self::SLOT_HEADER_SIZE + msg_size + 7) & !7
     }
 
-    fn read(mmap: &Mmap, buf: &'a mut [u8]) -> Datagram<'a> {
-        loop {
-            let (copied_u8, rest) = mmap.as_ref().split_at(std::mem::size_of::<Instant>());
-            let (len_u8, data_u8) = rest.split_at(std::mem::size_of::<u32>());
+    pub(crate) const fn size(msg_size: usize) -> u32 {
+        (Self::HEADER_SIZE + 2 * Self::slot_size(msg_size)) as u32
+    }
+
+    /// Writes the header of a new, zeroed datagram
+    pub(crate) fn init(mem: &mut [u8]) {
+        mem[..size_of::<u32>()].copy_from_slice(&Self::VERSION.to_ne_bytes());
+        Self::sequence(mem).store(0, Ordering::Release);
+    }
+
+    /// Checks that `mem` holds a datagram of the layout known to this version
+    pub(crate) fn check_version(mem: &[u8]) -> Result<(), BufferError> {
+        if mem.len() < Self::HEADER_SIZE + 2 * Self::SLOT_HEADER_SIZE {
+            return Err(BufferError::TooSmall(mem.len()));
+        }
+        let version = u32::from_ne_bytes(mem[..size_of::<u32>()].try_into().unwrap());
+        if version != Self::VERSION {
+            return Err(BufferError::Version {
+                found: version,
+                expected: Self::VERSION,
+            });
+        }
+        Ok(())
+    }
+
+    fn sequence(mem: &[u8]) -> &AtomicU32 {
+        unsafe { &*(mem[size_of::<u32>()..].as_ptr() as *const AtomicU32) }
+    }
+
+    fn slot(mem: &[u8], index: usize) -> &[u8] {
+        let size = (mem.len() - Self::HEADER_SIZE) / 2;
+        &mem[Self::HEADER_SIZE + index * size..][..size]
+    }
+
+    fn slot_mut(mem: &mut [u8], index: usize) -> &mut [u8] {
+        let size = (mem.len() - Self::HEADER_SIZE) / 2;
+        &mut mem[Self::HEADER_SIZE + index * size..][..size]
+    }
+
+    /// Reads the timestamp, sequence number and length of the current slot
+    /// of `sequence`, returning them with the data of the slot
+    fn slot_header(mem: &[u8], sequence: u32) -> (MonotonicTime, u64, u32, &[u8]) {
+        let slot = Self::slot(mem, sequence as usize & 1);
+        let (copied_u8, rest) = slot.split_at(size_of::<MonotonicTime>());
+        let (seq_u8, rest) = rest.split_at(size_of::<u64>());
+        let (len_u8, data_u8) = rest.split_at(size_of::<u32>());
+
+        let copied = unsafe { (copied_u8.as_ptr() as *const MonotonicTime).read_volatile() };
+        let seq = unsafe { (seq_u8.as_ptr() as *const u64).read_volatile() };
+        let len = unsafe { (len_u8.as_ptr() as *const u32).read_volatile() };
+        (copied, seq, len, data_u8)
+    }
+
+    /// Checks that `mem` holds a datagram for messages of `msg_size`, whose
+    /// current message fits into it and was not written later than `now`
+    ///
+    /// Unlike [Datagram::read], this does not retry, but fails with
+    /// [BufferError::SequenceChanged] if the message was replaced while it was
+    /// checked.
+    pub(crate) fn validate(
+        mem: &[u8],
+        msg_size: usize,
+        now: MonotonicTime,
+    ) -> Result<(), BufferError> {
+        let expected = Self::size(msg_size) as usize;
+        if mem.len() != expected {
+            return Err(BufferError::Size {
+                expected,
+                actual: mem.len(),
+            });
+        }
+        Self::check_version(mem)?;
+
+        let sequence = Self::sequence(mem).load(Ordering::Acquire);
+        let (copied, _, len, _) = Self::slot_header(mem, sequence);
+        fence(Ordering::Acquire);
+        if Self::sequence(mem).load(Ordering::Acquire) != sequence {
+            return Err(BufferError::SequenceChanged);
+        }
+
+        if len as usize > msg_size {
+            return Err(BufferError::MessageLength {
+                len: len as usize,
+                msg_size,
+            });
+        }
+        if copied > now {
+            return Err(BufferError::FutureTimestamp(copied.duration_since(now)));
+        }
+        Ok(())
+    }
 
-            let copied = unsafe { *(copied_u8.as_ptr() as *const Instant).as_ref().unwrap() };
-            let len = unsafe { *(len_u8.as_ptr() as *const u32).as_ref().unwrap() };
+    pub(crate) fn read(mem: &[u8], buf: &'a mut [u8]) -> Datagram<'a> {
+        loop {
+            let sequence = Self::sequence(mem).load(Ordering::Acquire);
+            let (copied, seq, len, data_u8) = Self::slot_header(mem, sequence);
 
-            let len = std::cmp::min(len as usize, std::cmp::min(data_u8.len(), buf.len()));
-            buf[..len].copy_from_slice(&data_u8[..len]);
+            let copy_len = std::cmp::min(len as usize, std::cmp::min(data_u8.len(), buf.len()));
+            buf[..copy_len].copy_from_slice(&data_u8[..copy_len]);
 
-            // Make sure that the underlying value didn't change
-            let check = unsafe { *(copied_u8.as_ptr() as *const Instant).as_ref().unwrap() };
-            if copied == check {
+            // Make sure that the slot was not reused while copying
+            fence(Ordering::Acquire);
+            if Self::sequence(mem).load(Ordering::Acquire) == sequence {
                 return Datagram {
                     copied,
-                    //_len: len as u32,
-                    data: &buf[..len],
+                    seq,
+                    len: len as usize,
+                    data: &buf[..copy_len],
                 };
             }
         }
     }
 
-    fn write(mmap: &mut MmapMut, write: &[u8]) -> usize {
-        let (copied_u8, rest) = mmap.as_mut().split_at_mut(std::mem::size_of::<Instant>());
-        let (len_u8, data_u8) = rest.split_at_mut(std::mem::size_of::<u32>());
+    pub(crate) fn write(mem: &mut [u8], write: &[u8], seq: u64) -> usize {
+        // There is only a single writer, so nobody else changes the sequence
+        let sequence = Self::sequence(mem).load(Ordering::Acquire).wrapping_add(1);
+        let slot = Self::slot_mut(mem, sequence as usize & 1);
+        let (copied_u8, rest) = slot.split_at_mut(size_of::<MonotonicTime>());
+        let (seq_u8, rest) = rest.split_at_mut(size_of::<u64>());
+        let (len_u8, data_u8) = rest.split_at_mut(size_of::<u32>());
 
-        let mut_len = unsafe { (len_u8.as_mut_ptr() as *mut u32).as_mut().unwrap() };
         let len = std::cmp::min(data_u8.len(), write.len());
-        *mut_len = len as u32;
-
         data_u8[..len].copy_from_slice(&write[..len]);
+        unsafe {
+            (len_u8.as_mut_ptr() as *mut u32).write_volatile(len as u32);
+            (seq_u8.as_mut_ptr() as *mut u64).write_volatile(seq);
+            (copied_u8.as_mut_ptr() as *mut MonotonicTime).write_volatile(MonotonicTime::now());
+        }
 
-        let mut_copied = unsafe { (copied_u8.as_mut_ptr() as *mut Instant).as_mut().unwrap() };
-        *mut_copied = Instant::now();
-
+        Self::sequence(mem).store(sequence, Ordering::Release);
         len
     }
 }
 
+/// A message kept in the history of a sampling channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryRecord {
+    /// Sequence number stamped on the message, zero on channels that are not
+    /// sequenced
+    pub seq: u64,
+    /// When the message was transferred to the destinations
+    pub transferred: MonotonicTime,
+    pub data: Vec<u8>,
+}
+
 #[derive(Debug)]
 pub struct Sampling {
     msg_size: usize,
     source_receiver: Mmap,
     source: OwnedFd,
     source_port: PortConfig,
-    last: Instant,
+    last: MonotonicTime,
     destination_sender: MmapMut,
     destination: OwnedFd,
     destination_ports: HashSet<PortConfig>,
+    on_partition_restart: OnPartitionRestart,
+    refresh_period: Option<Duration>,
+    /// Whether messages are stamped with `seq`
+    sequenced: bool,
+    /// Sequence number of the last message transferred to the destination
+    seq: u64,
+    transfer: Transfer,
+    zeroize: bool,
+    criticality: Criticality,
+    /// Whether swapped messages are kept for [Sampling::take_tapped]
+    tap: bool,
+    tapped: Option<Vec<u8>>,
+    /// Latest messages transferred, oldest first
+    history: VecDeque<HistoryRecord>,
+    /// Capacity of `history`, zero if no history is kept
+    history_len: usize,
+    /// Number of malformed messages dropped by [Sampling::swap]
+    corrupted: u64,
 }
 
 impl TryFrom<SamplingChannelConfig> for Sampling {
     type Error = TypedError;
 
     fn try_from(config: SamplingChannelConfig) -> TypedResult<Self> {
-        let msg_size = config.msg_size.as_u64() as usize;
-        let source_port_name = config.source.name();
-        let (source_receiver, source) =
-            Self::source(format!("sampling_{source_port_name}_source"), msg_size)?;
-        let (destination_sender, destination) =
-            Self::destination(format!("sampling_{source_port_name}_destination"), msg_size)?;
+        let msg_size = Self::checked_msg_size(config.msg_size)?;
+        let channel = config.source.name();
+        let module = std::process::id();
+        let (source_receiver, source) = Self::source(
+            buffer::channel_memfd_name(
+                module,
+                &channel,
+                &config.source.partition,
+                PortDirection::Source,
+            ),
+            msg_size,
+        )?;
+        // All destinations share the memfd
+        let mut destinations: Vec<_> = config
+            .destination
+            .iter()
+            .map(|p| p.partition.as_str())
+            .collect();
+        destinations.sort_unstable();
+        let (destination_sender, destination) = Self::destination(
+            buffer::channel_memfd_name(
+                module,
+                &channel,
+                &destinations.join(","),
+                PortDirection::Destination,
+            ),
+            msg_size,
+        )?;
+        if config.zeroize {
+            source_receiver
+                .advise(Advice::DontDump)
+                .typ(SystemError::Panic)?;
+            destination_sender
+                .advise(Advice::DontDump)
+                .typ(SystemError::Panic)?;
+        }
 
+        let history_len = config.history.map_or(0, NonZeroUsize::get);
         Ok(Self {
             msg_size,
             source,
             source_receiver,
             source_port: config.source,
-            last: Instant::now(),
+            last: MonotonicTime::ZERO,
             destination,
             destination_sender,
             destination_ports: config.destination,
+            on_partition_restart: config.on_partition_restart,
+            refresh_period: config.refresh_period,
+            sequenced: config.sequenced,
+            seq: 0,
+            transfer: config.transfer,
+            zeroize: config.zeroize,
+            criticality: config.criticality,
+            tap: false,
+            tapped: None,
+            history: VecDeque::with_capacity(history_len),
+            history_len,
+            corrupted: 0,
         })
     }
 }
 
 impl Sampling {
+    /// Checks that a channel for messages of `msg_size` can be created,
+    /// returning the message size in bytes
+    ///
+    /// Each end of the channel holds two messages, so that a message is never
+    /// read while it is written.
+    pub(crate) fn checked_msg_size(msg_size: ByteSize) -> TypedResult<usize> {
+        usize::try_from(msg_size.as_u64())
+            .ok()
+            .filter(|size| *size <= Datagram::MAX_MSG_SIZE)
+            .ok_or_else(|| {
+                anyhow!(
+                    "sampling message size {msg_size} exceeds the maximum of {}, as each end of the channel holds two messages",
+                    ByteSize::b(Datagram::MAX_MSG_SIZE as u64)
+                )
+            })
+            .typ(SystemError::Config)
+    }
+
     pub fn constant<T: AsRef<str>>(&self, part: T) -> Option<SamplingConstant> {
-        let (dir, fd, port) = if self.source_port.partition.eq(part.as_ref()) {
+        let (dir, fd, port, refresh_period) = if self.source_port.partition.eq(part.as_ref()) {
             (
                 PortDirection::Source,
                 self.source_fd().as_raw_fd(),
                 &self.source_port.port,
+                None,
             )
         } else if let Some(port) = self
             .destination_ports
@@ -119,6 +348,7 @@ impl Sampling {
                 PortDirection::Destination,
                 self.destination_fd().as_raw_fd(),
                 &port.port,
+                self.refresh_period,
             )
         } else {
             return None;
@@ -129,6 +359,7 @@ impl Sampling {
             dir,
             msg_size: self.msg_size,
             fd,
+            refresh_period,
         })
     }
 
@@ -136,25 +367,152 @@ impl Sampling {
         format!("{}:{}", &self.source_port.partition, &self.source_port.port)
     }
 
-    fn memfd<T: AsRef<str>>(name: T, msg_size: usize) -> TypedResult<Memfd> {
-        let size = Datagram::size(msg_size);
+    /// Name of the partition with the source port of this channel
+    pub fn source_partition(&self) -> &str {
+        &self.source_port.partition
+    }
 
-        let mem = MemfdOptions::default()
-            .close_on_exec(false)
-            .allow_sealing(true)
-            .create(name)
-            .typ(SystemError::Panic)?;
-        mem.as_file().set_len(size as u64).typ(SystemError::Panic)?;
-        mem.add_seals(&[FileSeal::SealShrink, FileSeal::SealGrow])
-            .typ(SystemError::Panic)?;
+    /// Names of all partitions with a destination port of this channel
+    pub fn destination_partitions(&self) -> impl Iterator<Item = &str> {
+        self.destination_ports.iter().map(|p| p.partition.as_str())
+    }
+
+    /// Whether `partition` has a port of this channel
+    pub fn is_connected_to(&self, partition: &str) -> bool {
+        self.source_port.partition == partition
+            || self.destination_partitions().any(|p| p == partition)
+    }
+
+    pub fn on_partition_restart(&self) -> OnPartitionRestart {
+        self.on_partition_restart
+    }
+
+    pub fn transfer(&self) -> Transfer {
+        self.transfer
+    }
+
+    /// Which health monitor handles the errors of this channel
+    pub fn criticality(&self) -> Criticality {
+        self.criticality
+    }
 
-        Ok(mem)
+    /// Discards the current message, so that destination ports read no
+    /// message until the source writes a new one
+    ///
+    /// The sequence numbers of a sequenced channel continue where they left
+    /// off, so that destinations can tell the next message from earlier ones.
+    pub fn clear(&mut self) -> TypedResult<()> {
+        // The source is only mapped read-only for the regular swaps
+        let mut source =
+            unsafe { MmapMut::map_mut(self.source.as_raw_fd()).typ(SystemError::Panic)? };
+        source.fill(0);
+        Datagram::init(&mut source);
+        self.destination_sender.fill(0);
+        Datagram::init(&mut self.destination_sender);
+        self.last = MonotonicTime::ZERO;
+        Ok(())
+    }
+
+    /// Whether the memory of this channel is to be overwritten with zeros on
+    /// restarts of its partitions and on shutdown
+    pub fn zeroizes(&self) -> bool {
+        self.zeroize
+    }
+
+    /// Overwrites the memory of both ends of this channel with zeros,
+    /// discarding the current message like [Sampling::clear]
+    ///
+    /// As the memory is shared, this also affects the mappings of the
+    /// partitions. It only removes residue of past messages.
+    pub fn zeroize(&mut self) -> TypedResult<()> {
+        let mut source =
+            unsafe { MmapMut::map_mut(self.source.as_raw_fd()).typ(SystemError::Panic)? };
+        wipe(&mut source);
+        Datagram::init(&mut source);
+        wipe(&mut self.destination_sender);
+        Datagram::init(&mut self.destination_sender);
+        self.last = MonotonicTime::ZERO;
+        self.tapped = None;
+        for record in &mut self.history {
+            wipe(&mut record.data);
+        }
+        self.history.clear();
+        Ok(())
+    }
+
+    /// Keeps a copy of every message transferred by [Sampling::swap] or
+    /// [Sampling::inject], e.g. for forwarding it outside of the module
+    pub fn tap(&mut self) {
+        self.tap = true;
+    }
+
+    /// Takes the latest message transferred since the last call, if the
+    /// channel is tapped
+    pub fn take_tapped(&mut self) -> Option<Vec<u8>> {
+        self.tapped.take()
+    }
+
+    /// The messages kept in the history of this channel, oldest first
+    ///
+    /// Holds up to the `history` of the configuration of the latest messages
+    /// transferred by [Sampling::swap] or [Sampling::inject].
+    pub fn history(&self) -> impl ExactSizeIterator<Item = &HistoryRecord> {
+        self.history.iter()
+    }
+
+    /// Whether this channel keeps a history of its messages
+    pub fn keeps_history(&self) -> bool {
+        self.history_len > 0
+    }
+
+    /// Writes `data` to the destination ports, as if the source had written
+    /// and the hypervisor swapped it
+    ///
+    /// Fails for messages exceeding the message size of the channel. The
+    /// message is replaced by the next one written by the source.
+    pub fn inject(&mut self, data: &[u8]) -> TypedResult<()> {
+        if data.len() > self.msg_size {
+            return Err(anyhow!(
+                "message of {} bytes exceeds the message size of {} bytes of sampling channel {}",
+                data.len(),
+                self.msg_size,
+                self.name()
+            ))
+            .typ(SystemError::Config);
+        }
+        self.transfer_message(data);
+        Ok(())
+    }
+
+    /// Faults in the memory of both ends of this channel, optionally locking
+    /// it into RAM, and returns its size in bytes
+    pub fn prefault(&mut self, lock: bool) -> TypedResult<usize> {
+        for mem in [
+            self.source_receiver.as_ref(),
+            self.destination_sender.as_ref(),
+        ] {
+            touch_pages(mem);
+        }
+        if lock {
+            self.source_receiver.lock().map_err(lock_error)?;
+            self.destination_sender.lock().map_err(lock_error)?;
+        }
+        Ok(self.source_receiver.len() + self.destination_sender.len())
+    }
+
+    fn memfd<T: AsRef<str>>(name: T, msg_size: usize) -> TypedResult<Memfd> {
+        buffer::memfd(name, Datagram::size(msg_size) as usize)
+            .map_err(fd_limit_hint)
+            .typ(SystemError::Panic)
     }
 
     fn source<T: AsRef<str>>(name: T, msg_size: usize) -> TypedResult<(Mmap, OwnedFd)> {
         let mem = Self::memfd(name, msg_size)?;
 
-        let mmap = unsafe { Mmap::map(mem.as_raw_fd()).typ(SystemError::Panic)? };
+        let mut mmap = unsafe { MmapMut::map_mut(mem.as_raw_fd()).typ(SystemError::Panic)? };
+        Datagram::init(&mut mmap);
+        // The hypervisor only ever reads from the source
+        let mmap = mmap.make_read_only().typ(SystemError::Panic)?;
 
         mem.add_seals(&[FileSeal::SealSeal])
             .typ(SystemError::Panic)?;
@@ -165,7 +523,8 @@ impl Sampling {
     fn destination<T: AsRef<str>>(name: T, msg_size: usize) -> TypedResult<(MmapMut, OwnedFd)> {
         let mem = Self::memfd(name, msg_size)?;
 
-        let mmap = unsafe { MmapMut::map_mut(mem.as_raw_fd()).typ(SystemError::Panic)? };
+        let mut mmap = unsafe { MmapMut::map_mut(mem.as_raw_fd()).typ(SystemError::Panic)? };
+        Datagram::init(&mut mmap);
 
         mem.add_seals(&[FileSeal::SealFutureWrite, FileSeal::SealSeal])
             .typ(SystemError::Panic)?;
@@ -174,6 +533,10 @@ impl Sampling {
     }
 
     //// Returns whether a swap was performed or not
+    ///
+    /// A message whose length was corrupted by the source partition is dropped
+    /// and counted by [Sampling::corrupted], keeping the previous message at
+    /// the destination.
     pub fn swap(&mut self) -> bool {
         let mut buf = vec![0; self.msg_size];
         let read = Datagram::read(&self.source_receiver, &mut buf);
@@ -181,16 +544,100 @@ impl Sampling {
             return false;
         }
         self.last = read.copied;
-
-        Datagram::write(&mut self.destination_sender, read.data);
+        if read.len > self.msg_size {
+            let e = BufferError::MessageLength {
+                len: read.len,
+                msg_size: self.msg_size,
+            };
+            warn!(
+                "Sampling channel {} dropped a malformed message: {e}",
+                self.name()
+            );
+            self.corrupted += 1;
+            return false;
+        }
+        self.transfer_message(read.data);
         true
     }
 
+    /// Checks that the memory of the source port holds a message of at most
+    /// the message size, written no later than `now`
+    ///
+    /// The source partition may not run meanwhile, as it could replace the
+    /// message while it is checked.
+    pub fn verify_source(&self, now: MonotonicTime) -> Result<(), BufferError> {
+        Datagram::validate(&self.source_receiver, self.msg_size, now)
+    }
+
+    /// Checks the memory of the destination ports like
+    /// [Sampling::verify_source], which the hypervisor alone writes
+    pub fn verify_destination(&self, now: MonotonicTime) -> Result<(), BufferError> {
+        Datagram::validate(&self.destination_sender, self.msg_size, now)
+    }
+
+    /// Number of messages dropped by [Sampling::swap] so far, because the
+    /// source partition left them malformed
+    pub fn corrupted(&self) -> u64 {
+        self.corrupted
+    }
+
+    /// Writes `data` to the destination, stamping the next sequence number
+    fn transfer_message(&mut self, data: &[u8]) {
+        let seq = if self.sequenced {
+            self.seq += 1;
+            self.seq
+        } else {
+            0
+        };
+        Datagram::write(&mut self.destination_sender, data, seq);
+        if self.tap {
+            self.tapped = Some(data.to_vec());
+        }
+        if self.history_len > 0 {
+            self.record(data, seq);
+        }
+    }
+
+    /// Appends `data` to the history, evicting the oldest message once it is
+    /// full
+    fn record(&mut self, data: &[u8], seq: u64) {
+        // The buffer of the evicted message is reused, so that a full history
+        // allocates no more memory
+        let mut buf = if self.history.len() == self.history_len {
+            self.history
+                .pop_front()
+                .map(|record| record.data)
+                .unwrap_or_default()
+        } else {
+            Vec::with_capacity(self.msg_size)
+        };
+        if self.zeroize {
+            wipe(&mut buf);
+        }
+        buf.clear();
+        buf.extend_from_slice(data);
+        self.history.push_back(HistoryRecord {
+            seq,
+            transferred: MonotonicTime::now(),
+            data: buf,
+        });
+    }
+
     pub fn replace_source(&mut self) -> TypedResult<()> {
         let (source_receiver, source) = Self::source(
-            format!("sampling_{}_source", self.source_port.port),
+            buffer::channel_memfd_name(
+                std::process::id(),
+                &self.name(),
+                &self.source_port.partition,
+                PortDirection::Source,
+            ),
             self.msg_size,
         )?;
+        if self.zeroize {
+            source_receiver
+                .advise(Advice::DontDump)
+                .typ(SystemError::Panic)?;
+        }
 
         self.source = source;
         self.source_receiver = source_receiver;
@@ -198,11 +645,11 @@ impl Sampling {
         Ok(())
     }
 
-    pub fn source_fd(&self) -> BorrowedFd {
+    pub fn source_fd(&self) -> BorrowedFd<'_> {
         self.source.as_fd()
     }
 
-    pub fn destination_fd(&self) -> BorrowedFd {
+    pub fn destination_fd(&self) -> BorrowedFd<'_> {
         self.destination.as_fd()
     }
 }
@@ -212,7 +659,8 @@ pub struct SamplingSource(MmapMut);
 
 impl SamplingSource {
     pub fn write(&mut self, data: &[u8]) -> usize {
-        Datagram::write(&mut self.0, data)
+        // Only the hypervisor stamps sequence numbers
+        Datagram::write(&mut self.0, data, 0)
     }
 }
 
@@ -221,6 +669,7 @@ impl TryFrom<RawFd> for SamplingSource {
 
     fn try_from(file: RawFd) -> Result<Self, Self::Error> {
         let mmap = unsafe { MmapMut::map_mut(file).typ(SystemError::Panic)? };
+        Datagram::check_version(&mmap).typ(SystemError::Panic)?;
 
         Ok(Self(mmap))
     }
@@ -230,10 +679,23 @@ impl TryFrom<RawFd> for SamplingSource {
 pub struct SamplingDestination(Mmap);
 
 impl SamplingDestination {
-    pub fn read(&mut self, data: &mut [u8]) -> (usize, Instant) {
+    /// Reads the current message into `data`, returning its length and when
+    /// it was copied to the destination
+    ///
+    /// The timestamp is [MonotonicTime::ZERO] as long as the port never
+    /// received a message, which tells it apart from an empty message.
+    pub fn read(&mut self, data: &mut [u8]) -> (usize, MonotonicTime) {
+        let (len, copied, _) = self.read_sequenced(data);
+        (len, copied)
+    }
+
+    /// Reads like [SamplingDestination::read], additionally returning the
+    /// sequence number of the message, which is zero on channels that are not
+    /// sequenced
+    pub fn read_sequenced(&mut self, data: &mut [u8]) -> (usize, MonotonicTime, u64) {
         let dat = Datagram::read(&self.0, data);
 
-        (dat.data.len(), dat.copied)
+        (dat.data.len(), dat.copied, dat.seq)
     }
 }
 
@@ -242,7 +704,431 @@ impl TryFrom<RawFd> for SamplingDestination {
 
     fn try_from(file: RawFd) -> Result<Self, Self::Error> {
         let mmap = unsafe { Mmap::map(file).typ(SystemError::Panic)? };
+        Datagram::check_version(&mmap).typ(SystemError::Panic)?;
 
         Ok(Self(mmap))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clear_discards_message() {
+        let config = SamplingChannelConfig {
+            msg_size: ByteSize::b(8),
+            source: PortConfig {
+                partition: "a".into(),
+                port: "out".into(),
+            },
+            destination: HashSet::from([PortConfig {
+                partition: "b".into(),
+                port: "in".into(),
+            }]),
+            on_partition_restart: OnPartitionRestart::Clear,
+            refresh_period: None,
+            sequenced: false,
+            transfer: Transfer::AfterSourceWindow,
+            zeroize: false,
+            history: None,
+            criticality: Criticality::Partition,
+        };
+        let mut sampling = Sampling::try_from(config).unwrap();
+        assert!(sampling.is_connected_to("a") && sampling.is_connected_to("b"));
+        assert!(!sampling.is_connected_to("c"));
+
+        let mut source = SamplingSource::try_from(sampling.source_fd().as_raw_fd()).unwrap();
+        let mut destination =
+            SamplingDestination::try_from(sampling.destination_fd().as_raw_fd()).unwrap();
+        let mut buf = [0; 8];
+
+        source.write(b"hello");
+        assert!(sampling.swap());
+        assert_eq!(destination.read(&mut buf).0, 5);
+
+        sampling.clear().unwrap();
+        let (len, copied) = destination.read(&mut buf);
+        assert_eq!(len, 0);
+        assert!(copied.is_zero());
+        // The cleared source must not be swapped as a new message
+        assert!(!sampling.swap());
+
+        source.write(b"again");
+        assert!(sampling.swap());
+        assert_eq!(destination.read(&mut buf).0, 5);
+    }
+
+    fn channel(msg_size: u64) -> Sampling {
+        sequenced_channel(msg_size, false)
+    }
+
+    fn sequenced_channel(msg_size: u64, sequenced: bool) -> Sampling {
+        Sampling::try_from(SamplingChannelConfig {
+            msg_size: ByteSize::b(msg_size),
+            source: PortConfig {
+                partition: "a".into(),
+                port: "out".into(),
+            },
+            destination: HashSet::from([PortConfig {
+                partition: "b".into(),
+                port: "in".into(),
+            }]),
+            on_partition_restart: OnPartitionRestart::Keep,
+            refresh_period: None,
+            sequenced,
+            transfer: Transfer::AfterSourceWindow,
+            zeroize: false,
+            history: None,
+            criticality: Criticality::Partition,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn inject_and_tap() {
+        let mut sampling = sequenced_channel(8, true);
+        let mut source = SamplingSource::try_from(sampling.source_fd().as_raw_fd()).unwrap();
+        let mut destination =
+            SamplingDestination::try_from(sampling.destination_fd().as_raw_fd()).unwrap();
+        let mut buf = [0; 8];
+
+        // Untapped channels keep no copies
+        source.write(b"first");
+        assert!(sampling.swap());
+        assert_eq!(sampling.take_tapped(), None);
+
+        sampling.tap();
+        sampling.inject(b"injected").unwrap();
+        let (len, _, seq) = destination.read_sequenced(&mut buf);
+        assert_eq!((&buf[..len], seq), (&b"injected"[..], 2));
+        assert!(sampling.inject(b"too long!").is_err());
+
+        // The injected message is not mistaken for a new one of the source
+        assert!(!sampling.swap());
+        source.write(b"second");
+        assert!(sampling.swap());
+        assert_eq!(sampling.take_tapped().as_deref(), Some(&b"second"[..]));
+        assert_eq!(sampling.take_tapped(), None);
+    }
+
+    #[test]
+    fn history_keeps_latest_messages() {
+        let mut sampling = Sampling::try_from(SamplingChannelConfig {
+            msg_size: ByteSize::b(8),
+            source: PortConfig {
+                partition: "a".into(),
+                port: "out".into(),
+            },
+            destination: HashSet::from([PortConfig {
+                partition: "b".into(),
+                port: "in".into(),
+            }]),
+            on_partition_restart: OnPartitionRestart::Keep,
+            refresh_period: None,
+            sequenced: true,
+            transfer: Transfer::AfterSourceWindow,
+            zeroize: false,
+            history: NonZeroUsize::new(3),
+            criticality: Criticality::Partition,
+        })
+        .unwrap();
+        assert!(sampling.keeps_history());
+        assert_eq!(sampling.history().len(), 0);
+        let mut source = SamplingSource::try_from(sampling.source_fd().as_raw_fd()).unwrap();
+
+        for msg in ["m1", "m2", "m3", "m4"] {
+            source.write(msg.as_bytes());
+            assert!(sampling.swap());
+        }
+        // Unchanged sources are not recorded again
+        assert!(!sampling.swap());
+        sampling.inject(b"m5").unwrap();
+
+        let history = sampling.history().collect::<Vec<_>>();
+        let messages = history
+            .iter()
+            .map(|r| (r.seq, r.data.as_slice()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [(3, &b"m3"[..]), (4, &b"m4"[..]), (5, &b"m5"[..])]
+        );
+        assert!(history
+            .windows(2)
+            .all(|pair| pair[0].transferred <= pair[1].transferred));
+
+        sampling.zeroize().unwrap();
+        assert_eq!(sampling.history().len(), 0);
+
+        // Channels without a history keep nothing
+        let mut sampling = channel(8);
+        assert!(!sampling.keeps_history());
+        sampling.inject(b"m1").unwrap();
+        assert_eq!(sampling.history().len(), 0);
+    }
+
+    /// Whether the memory behind `fd` contains `pattern`
+    fn contains(fd: RawFd, pattern: &[u8]) -> bool {
+        let mem = unsafe { Mmap::map(fd) }.unwrap();
+        mem.windows(pattern.len()).any(|w| w == pattern)
+    }
+
+    #[test]
+    fn zeroize_removes_residue() {
+        for zeroize in [false, true] {
+            let mut sampling = Sampling::try_from(SamplingChannelConfig {
+                msg_size: ByteSize::b(8),
+                source: PortConfig {
+                    partition: "a".into(),
+                    port: "out".into(),
+                },
+                destination: HashSet::from([PortConfig {
+                    partition: "b".into(),
+                    port: "in".into(),
+                }]),
+                on_partition_restart: OnPartitionRestart::Keep,
+                refresh_period: None,
+                sequenced: false,
+                transfer: Transfer::AfterSourceWindow,
+                zeroize,
+                history: None,
+                criticality: Criticality::Partition,
+            })
+            .unwrap();
+            assert_eq!(sampling.zeroizes(), zeroize);
+            let mut source = SamplingSource::try_from(sampling.source_fd().as_raw_fd()).unwrap();
+            source.write(b"secret!!");
+            assert!(sampling.swap());
+
+            // What the hypervisor does on a restart of a partition of the channel
+            if sampling.zeroizes() {
+                sampling.zeroize().unwrap();
+            }
+            for fd in [sampling.source_fd(), sampling.destination_fd()] {
+                assert_eq!(contains(fd.as_raw_fd(), b"secret!!"), !zeroize);
+            }
+        }
+
+        // The zeroized channel is still usable
+        let mut sampling = channel(8);
+        sampling.zeroize().unwrap();
+        let mut source = SamplingSource::try_from(sampling.source_fd().as_raw_fd()).unwrap();
+        let mut destination =
+            SamplingDestination::try_from(sampling.destination_fd().as_raw_fd()).unwrap();
+        source.write(b"again");
+        assert!(sampling.swap());
+        assert_eq!(destination.read(&mut [0; 8]).0, 5);
+    }
+
+    #[test]
+    fn torn_write_is_never_read() {
+        let mut sampling = channel(8);
+        let mut source = SamplingSource::try_from(sampling.source_fd().as_raw_fd()).unwrap();
+        let mut destination =
+            SamplingDestination::try_from(sampling.destination_fd().as_raw_fd()).unwrap();
+        let mut buf = [0; 8];
+
+        source.write(b"aaaaaaaa");
+        assert!(sampling.swap());
+
+        // The partition is frozen in the middle of writing the next message
+        let sequence = Datagram::sequence(&source.0).load(Ordering::SeqCst);
+        let slot = Datagram::slot_mut(&mut source.0, (sequence as usize + 1) & 1);
+        let data = &mut slot[Datagram::SLOT_HEADER_SIZE..];
+        data[..4].copy_from_slice(b"bbbb");
+        slot[Datagram::SLOT_HEADER_SIZE - size_of::<u32>()..][..4]
+            .copy_from_slice(&8u32.to_ne_bytes());
+
+        assert!(!sampling.swap());
+        assert_eq!(destination.read(&mut buf).0, 8);
+        assert_eq!(&buf, b"aaaaaaaa");
+
+        // The interrupted write is completed in the next window
+        source.write(b"cccccccc");
+        assert!(sampling.swap());
+        assert_eq!(destination.read(&mut buf).0, 8);
+        assert_eq!(&buf, b"cccccccc");
+    }
+
+    #[test]
+    fn concurrent_reads_are_consistent() {
+        let sampling = channel(64);
+        let mut source = SamplingSource::try_from(sampling.source_fd().as_raw_fd()).unwrap();
+        let writer = std::thread::spawn(move || {
+            for i in 0..10_000u32 {
+                source.write(&[i as u8; 64]);
+            }
+        });
+
+        let mut buf = [0; 64];
+        while !writer.is_finished() {
+            let read = Datagram::read(&sampling.source_receiver, &mut buf);
+            assert!(read.data.iter().all(|b| *b == read.data[0]));
+        }
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn sequence_numbers() {
+        let mut sampling = sequenced_channel(8, true);
+        let mut source = SamplingSource::try_from(sampling.source_fd().as_raw_fd()).unwrap();
+        let mut destination =
+            SamplingDestination::try_from(sampling.destination_fd().as_raw_fd()).unwrap();
+        let mut buf = [0; 8];
+
+        source.write(b"first");
+        assert!(sampling.swap());
+        assert_eq!(destination.read_sequenced(&mut buf).2, 1);
+
+        // No new data keeps the sequence number
+        assert!(!sampling.swap());
+        assert_eq!(destination.read_sequenced(&mut buf).2, 1);
+
+        source.write(b"second");
+        assert!(sampling.swap());
+        assert_eq!(destination.read_sequenced(&mut buf).2, 2);
+
+        // A restart of a partition does not reset the sequence
+        sampling.clear().unwrap();
+        assert_eq!(destination.read_sequenced(&mut buf).0, 0);
+        source.write(b"third");
+        assert!(sampling.swap());
+        let (len, _, seq) = destination.read_sequenced(&mut buf);
+        assert_eq!((&buf[..len], seq), (b"third".as_slice(), 3));
+
+        // Channels which are not sequenced always read zero
+        let mut sampling = channel(8);
+        let mut source = SamplingSource::try_from(sampling.source_fd().as_raw_fd()).unwrap();
+        let mut destination =
+            SamplingDestination::try_from(sampling.destination_fd().as_raw_fd()).unwrap();
+        source.write(b"first");
+        assert!(sampling.swap());
+        assert_eq!(destination.read_sequenced(&mut buf).2, 0);
+    }
+
+    #[test]
+    fn empty_messages() {
+        let mut sampling = channel(8);
+        let mut source = SamplingSource::try_from(sampling.source_fd().as_raw_fd()).unwrap();
+        let mut destination =
+            SamplingDestination::try_from(sampling.destination_fd().as_raw_fd()).unwrap();
+        let mut buf = [0; 8];
+
+        // Never written
+        assert!(!sampling.swap());
+        assert_eq!(destination.read(&mut buf), (0, MonotonicTime::ZERO));
+
+        source.write(b"data");
+        assert!(sampling.swap());
+        assert_eq!(destination.read(&mut buf).0, 4);
+
+        // An empty message replaces the previous one and is still stamped
+        source.write(&[]);
+        assert!(sampling.swap());
+        let (len, copied) = destination.read(&mut buf);
+        assert_eq!(len, 0);
+        assert!(!copied.is_zero());
+
+        sampling.clear().unwrap();
+        assert_eq!(destination.read(&mut buf), (0, MonotonicTime::ZERO));
+    }
+
+    #[test]
+    fn layout_version_is_checked() {
+        let sampling = channel(8);
+        let fd = sampling.destination_fd().as_raw_fd();
+        assert!(SamplingDestination::try_from(fd).is_ok());
+
+        let mut source = unsafe { MmapMut::map_mut(sampling.source_fd().as_raw_fd()).unwrap() };
+        source[..size_of::<u32>()].copy_from_slice(&1u32.to_ne_bytes());
+        assert!(SamplingSource::try_from(sampling.source_fd().as_raw_fd()).is_err());
+    }
+
+    #[test]
+    fn oversized_messages() {
+        assert_eq!(
+            Sampling::checked_msg_size(ByteSize::kb(10)).unwrap(),
+            10_000
+        );
+        let max = ByteSize::b(Datagram::MAX_MSG_SIZE as u64);
+        let max_size = Datagram::HEADER_SIZE
+            + 2 * Datagram::slot_size(Sampling::checked_msg_size(max).unwrap());
+        assert!(max_size <= u32::MAX as usize);
+        assert!(max_size > u32::MAX as usize - 64);
+        assert!(Sampling::checked_msg_size(max + ByteSize::b(1)).is_err());
+        assert!(Sampling::checked_msg_size(ByteSize::b(u64::MAX)).is_err());
+    }
+
+    #[test]
+    fn malformed_messages_are_dropped() {
+        let mut sampling = channel(8);
+        let mut source = SamplingSource::try_from(sampling.source_fd().as_raw_fd()).unwrap();
+        let mut destination =
+            SamplingDestination::try_from(sampling.destination_fd().as_raw_fd()).unwrap();
+        let mut buf = [0; 8];
+
+        source.write(b"good");
+        assert!(sampling.swap());
+
+        // A faulty partition overwrites the length of its current message
+        for (i, len) in [9, u32::MAX].into_iter().enumerate() {
+            source.write(b"evil");
+            let sequence = Datagram::sequence(&source.0).load(Ordering::SeqCst);
+            let slot = Datagram::slot_mut(&mut source.0, sequence as usize & 1);
+            slot[Datagram::SLOT_HEADER_SIZE - size_of::<u32>()..][..4]
+                .copy_from_slice(&len.to_ne_bytes());
+
+            assert!(!sampling.swap());
+            assert_eq!(sampling.corrupted(), i as u64 + 1);
+            let len = destination.read(&mut buf).0;
+            assert_eq!(&buf[..len], b"good");
+        }
+
+        // The same message is only counted once
+        assert!(!sampling.swap());
+        assert_eq!(sampling.corrupted(), 2);
+
+        source.write(b"fixed");
+        assert!(sampling.swap());
+        let len = destination.read(&mut buf).0;
+        assert_eq!(&buf[..len], b"fixed");
+    }
+
+    #[test]
+    fn verify_detects_corrupted_memory() {
+        let mut sampling = channel(8);
+        let mut source = SamplingSource::try_from(sampling.source_fd().as_raw_fd()).unwrap();
+        assert_eq!(sampling.source_partition(), "a");
+
+        source.write(b"hello");
+        assert!(sampling.swap());
+        let now = MonotonicTime::now();
+        assert!(sampling.verify_source(now).is_ok());
+        assert!(sampling.verify_destination(now).is_ok());
+        assert!(matches!(
+            sampling.verify_destination(MonotonicTime::ZERO),
+            Err(BufferError::FutureTimestamp(_))
+        ));
+
+        // The length of the current message, in the second slot after one write
+        let len = Datagram::HEADER_SIZE
+            + Datagram::slot_size(8)
+            + size_of::<MonotonicTime>()
+            + size_of::<u64>();
+        source.0[len..][..size_of::<u32>()].copy_from_slice(&9u32.to_ne_bytes());
+        assert!(matches!(
+            sampling.verify_source(now),
+            Err(BufferError::MessageLength {
+                len: 9,
+                msg_size: 8
+            })
+        ));
+
+        source.0[..size_of::<u32>()].copy_from_slice(&0u32.to_ne_bytes());
+        assert!(matches!(
+            sampling.verify_source(now),
+            Err(BufferError::Version { found: 0, .. })
+        ));
+    }
+}
@@ -1,4 +1,5 @@
 //! Implementation for shared memory
+use std::io;
 use std::marker::PhantomData;
 use std::mem::size_of;
 
@@ -100,3 +101,39 @@ impl<'a, T: Send + Sized> TryFrom<Mmap> for TypedMmap<'a, T> {
         })
     }
 }
+
+/// Accesses every page of `mem` once, so that the kernel allocates the memory
+/// now instead of on its first use.
+///
+/// Reading suffices, as the pages of a shared memory file are allocated on any
+/// access. The content of `mem` is left untouched.
+pub fn touch_pages(mem: &[u8]) {
+    let page_size = unsafe { nix::libc::sysconf(nix::libc::_SC_PAGESIZE) };
+    let page_size = usize::try_from(page_size).unwrap_or(4096);
+    for offset in (0..mem.len()).step_by(page_size) {
+        unsafe { std::ptr::read_volatile(&mem[offset]) };
+    }
+}
+
+/// Overwrites `mem` with zeros
+///
+/// The writes are volatile, so that they are not optimized away even if `mem`
+/// is unmapped right after.
+pub fn wipe(mem: &mut [u8]) {
+    for byte in mem.iter_mut() {
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+}
+
+/// Converts an error of `mlock`, hinting at the limit of lockable memory if
+/// the kernel denied it
+pub fn lock_error(err: io::Error) -> TypedError {
+    let err = match err.raw_os_error() {
+        Some(nix::libc::EPERM | nix::libc::ENOMEM | nix::libc::EAGAIN) => anyhow!(err).context(
+            "could not lock channel memory, raise RLIMIT_MEMLOCK (see `ulimit -l`) \
+             or disable `lock_channels`",
+        ),
+        _ => anyhow!(err),
+    };
+    TypedError::new(SystemError::Config, err)
+}
@@ -0,0 +1,186 @@
+//! Parsing of byte sizes in the configuration
+//!
+//! Sizes are given either as a plain integer, which is a number of bytes, or
+//! as a string of an integer followed by a unit. Both SI (`KB`, `MB`, ...,
+//! powers of 1000) and binary units (`KiB`, `MiB`, ..., powers of 1024) are
+//! supported. Units which do not clearly state one of both, like `K` or `M`,
+//! are rejected, as are units ending in a lowercase `b`, which usually denotes
+//! bits.
+
+use std::fmt;
+
+use anyhow::{anyhow, bail};
+use bytesize::ByteSize;
+use serde::de::{self, Visitor};
+use serde::Deserializer;
+
+/// Units with their factor, matched case-insensitively except for the final
+/// `B`
+const UNITS: &[(&str, u64)] = &[
+    ("B", 1),
+    ("KB", 1000),
+    ("MB", 1000_u64.pow(2)),
+    ("GB", 1000_u64.pow(3)),
+    ("TB", 1000_u64.pow(4)),
+    ("KiB", 1 << 10),
+    ("MiB", 1 << 20),
+    ("GiB", 1 << 30),
+    ("TiB", 1 << 40),
+];
+
+/// Parses a size like `1000`, `16B`, `10KB` or `4 MiB`
+pub fn parse_size(s: &str) -> anyhow::Result<ByteSize> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let unit = unit.trim_start();
+
+    if number.is_empty() {
+        bail!(
+            "invalid size {s:?}, expected an integer optionally followed by a unit like KB or KiB"
+        );
+    }
+    let number: u64 = number
+        .parse()
+        .map_err(|_| anyhow!("size {s:?} is too large"))?;
+    if unit.starts_with(['.', ',']) {
+        bail!("invalid size {s:?}, only integers are supported");
+    }
+
+    let factor = match unit {
+        "" => 1,
+        unit if unit.ends_with('b') && unit.len() <= 3 => {
+            bail!("ambiguous unit in size {s:?}, use an uppercase B for bytes (a lowercase b denotes bits)")
+        }
+        unit => match UNITS.iter().find(|(u, _)| {
+            u.len() == unit.len() && unit.ends_with('B') && u.eq_ignore_ascii_case(unit)
+        }) {
+            Some((_, factor)) => *factor,
+            None if unit.len() == 1 && "KkMmGgTt".contains(unit) => {
+                let prefix = unit.to_ascii_uppercase();
+                bail!(
+                    "ambiguous unit in size {s:?}, use {prefix}B for powers of 1000 or {prefix}iB for powers of 1024"
+                )
+            }
+            None => bail!(
+                "unknown unit {unit:?} in size {s:?}, expected one of {}",
+                UNITS.iter().map(|(u, _)| *u).collect::<Vec<_>>().join(", ")
+            ),
+        },
+    };
+
+    number
+        .checked_mul(factor)
+        .map(ByteSize::b)
+        .ok_or_else(|| anyhow!("size {s:?} is too large"))
+}
+
+/// Deserializes a size from either an integer number of bytes or a string
+/// accepted by [parse_size]
+pub fn deserialize<'de, D>(de: D) -> Result<ByteSize, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    de.deserialize_any(SizeVisitor)
+}
+
+struct SizeVisitor;
+
+impl<'de> Visitor<'de> for SizeVisitor {
+    type Value = ByteSize;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a number of bytes or a size like \"10KB\" or \"4MiB\"")
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        Ok(ByteSize::b(v))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        u64::try_from(v)
+            .map(ByteSize::b)
+            .map_err(|_| E::custom(format!("size {v} must not be negative")))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        parse_size(v).map_err(E::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[test]
+    fn accepted_sizes() {
+        for (s, bytes) in [
+            ("0", 0),
+            ("1000", 1000),
+            (" 16 ", 16),
+            ("16B", 16),
+            ("16 B", 16),
+            ("10KB", 10_000),
+            ("10kB", 10_000),
+            ("10 KB", 10_000),
+            ("1MB", 1_000_000),
+            ("1GB", 1_000_000_000),
+            ("1TB", 1_000_000_000_000),
+            ("10KiB", 10_240),
+            ("10KIB", 10_240),
+            ("16MiB", 16 << 20),
+            ("1GiB", 1 << 30),
+            ("1TiB", 1 << 40),
+        ] {
+            assert_eq!(parse_size(s).unwrap(), ByteSize::b(bytes), "{s}");
+        }
+    }
+
+    #[test]
+    fn rejected_sizes() {
+        for s in [
+            "", "B", "KB", "-1", "1.5KB", "1,5KB", "10K", "10k", "16M", "1G", "1T", "10kb", "10Kb",
+            "16b", "10Mib", "10XB", "10 K B", "10KBB", "1e3",
+        ] {
+            assert!(parse_size(s).is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn overflowing_sizes() {
+        assert_eq!(
+            parse_size(&u64::MAX.to_string()).unwrap(),
+            ByteSize::b(u64::MAX)
+        );
+        assert!(parse_size("18446744073709551616").is_err());
+        assert!(parse_size("18446744073709552KB").is_err());
+        assert!(parse_size("16777216TiB").is_err());
+    }
+
+    #[test]
+    fn helpful_errors() {
+        let err = parse_size("16M").unwrap_err().to_string();
+        assert!(err.contains("MB") && err.contains("MiB"), "{err}");
+        let err = parse_size("16Mb").unwrap_err().to_string();
+        assert!(err.contains("bits"), "{err}");
+    }
+
+    #[test]
+    fn deserialize_integers_and_strings() {
+        #[derive(Deserialize)]
+        struct WithSize {
+            #[serde(deserialize_with = "deserialize")]
+            size: ByteSize,
+        }
+        let parse = |yaml: &str| serde_yaml::from_str::<WithSize>(yaml).map(|s| s.size);
+
+        assert_eq!(parse("size: 1000").unwrap(), ByteSize::b(1000));
+        assert_eq!(parse("size: \"1000\"").unwrap(), ByteSize::b(1000));
+        assert_eq!(parse("size: 4MiB").unwrap(), ByteSize::mib(4));
+        assert!(parse("size: -1").is_err());
+        assert!(parse("size: 1.5").is_err());
+        assert!(parse("size: 4M").is_err());
+    }
+}
@@ -19,18 +19,20 @@ type SyscallRequest = (SyscallType, Vec<u8>);
 
 // This is the data type that is returned from the hypervisor to the partition
 // when a syscall was handled. In contrast to [`SyscallRequest`], a generic can
-// be used for the return value's type.
-type SyscallResponse<T> = Result<T, a653rs::bindings::ErrorReturnCode>;
+// be used for the return value's type. The error is encoded independently of
+// the a653rs version.
+type SyscallResponse<T> = Result<T, crate::wire::ErrorReturnCode>;
 
 #[cfg(test)]
 mod tests {
     use std::collections::VecDeque;
     use std::os::unix::net::UnixDatagram;
     use std::thread;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
 
     use a653rs::bindings::ApexSystemTime;
     use a653rs::prelude::{QueueOverflow, QueuingPortId};
+    use nix::unistd::getpid;
 
     use super::SyscallType;
     use crate::syscall::receiver::{self, SyscallReceiver};
@@ -41,7 +43,7 @@ mod tests {
         let (sender, receiver) = UnixDatagram::pair().unwrap();
         (
             SyscallSender::from_datagram(sender),
-            SyscallReceiver::from_datagram(receiver),
+            SyscallReceiver::from_datagram(receiver).unwrap(),
         )
     }
 
@@ -168,4 +170,99 @@ mod tests {
         // join the receiver thread just to be safe
         receiver_thread.join().unwrap();
     }
+
+    #[test]
+    pub fn concurrent_senders() {
+        let (sender, receiver) = UnixDatagram::pair().unwrap();
+        let receiver = SyscallReceiver::from_datagram(receiver).unwrap();
+
+        // Responds with the id of the port a message is requested from
+        let receiver_thread = thread::spawn(move || {
+            for _ in 0..20 {
+                let syscall_was_handled = receiver
+                    .receive_one(Some(Duration::from_secs(1)), |ty, serialized_params| {
+                        assert_eq!(ty, SyscallType::ReceiveQueuingMessage);
+                        receiver::wrap_serialization::<syscalls::ReceiveQueuingMessage, _>(
+                            serialized_params,
+                            |(id, _)| Ok((false as QueueOverflow, id.to_le_bytes().to_vec())),
+                        )
+                        .expect("serialization to succeed")
+                    })
+                    .unwrap();
+                assert!(syscall_was_handled);
+            }
+            // Threads of one process are a single peer
+            assert_eq!(receiver.num_peers(), 1);
+        });
+
+        let senders: Vec<_> = (0..2 as QueuingPortId)
+            .map(|id| {
+                let sender = SyscallSender::from_datagram(sender.try_clone().unwrap());
+                thread::spawn(move || {
+                    for _ in 0..10 {
+                        let response = sender
+                            .execute::<syscalls::ReceiveQueuingMessage>((id, 0 as ApexSystemTime))
+                            .expect("sending and receiving a response to succeed");
+                        assert_eq!(response, Ok((false, id.to_le_bytes().to_vec())));
+                    }
+                })
+            })
+            .collect();
+
+        for sender in senders {
+            sender.join().unwrap();
+        }
+        receiver_thread.join().unwrap();
+    }
+
+    #[test]
+    pub fn rejected_peer() {
+        let (sender, receiver) = UnixDatagram::pair().unwrap();
+        let sender = SyscallSender::from_datagram(sender);
+        let me = getpid();
+        let receiver = SyscallReceiver::from_datagram(receiver)
+            .unwrap()
+            .with_peer_filter(move |pid| pid != me);
+
+        let receiver_thread = thread::spawn(move || {
+            let syscall_was_handled = receiver
+                .receive_one(Some(Duration::from_secs(1)), |_, _| {
+                    panic!("the request of a rejected peer must not be handled")
+                })
+                .unwrap();
+            assert!(syscall_was_handled);
+            assert_eq!(receiver.num_peers(), 1);
+        });
+
+        let response = sender
+            .execute::<syscalls::SendQueuingMessage>((
+                0 as QueuingPortId,
+                &[1, 2, 3],
+                0 as ApexSystemTime,
+            ))
+            .expect("sending and receiving a response to succeed");
+        assert_eq!(
+            response,
+            Err(a653rs::bindings::ErrorReturnCode::InvalidConfig)
+        );
+
+        receiver_thread.join().unwrap();
+    }
+
+    #[test]
+    fn receiving_times_out() {
+        let (_sender, receiver) = new_sender_receiver_pair();
+        let handler = |_: SyscallType, _: &[u8]| -> Vec<u8> { panic!("no syscall was sent") };
+
+        let start = Instant::now();
+        assert!(!receiver
+            .receive_one(Some(Duration::from_millis(20)), handler)
+            .unwrap());
+        assert!(!receiver.receive_one(Some(Duration::ZERO), handler).unwrap());
+        let handled = receiver
+            .receive_all::<(), (), ()>(Some(Duration::from_millis(20)), handler)
+            .unwrap();
+        assert_eq!(handled, 0);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
 }
@@ -1,32 +1,102 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::io::IoSliceMut;
 use std::num::NonZeroUsize;
 use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
 use std::os::unix::net::UnixDatagram;
 use std::path::Path;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 use anyhow::{anyhow, bail, Result};
 use nix::libc::EINTR;
-use nix::sys::socket::{recvmsg, ControlMessageOwned, MsgFlags};
+use nix::sys::socket::{
+    recvmsg, setsockopt, sockopt, ControlMessageOwned, MsgFlags, UnixCredentials,
+};
+use nix::unistd::Pid;
 use nix::{cmsg_space, unistd};
 use polling::{Event, Events, Poller};
 
 use super::SyscallType;
+use crate::deadline::Deadline;
 use crate::mfd::{Mfd, Seals};
 use crate::syscall::syscalls::Syscall;
-use crate::syscall::{SyscallRequest, SyscallResponse};
+use crate::syscall::SyscallResponse;
+use crate::wire;
 
-pub struct SyscallReceiver(UnixDatagram);
+/// Decides whether a process may make syscalls
+type PeerFilter = Box<dyn Fn(Pid) -> bool + Send>;
+
+/// Receiving end of the syscall socket
+///
+/// Every request carries the file descriptors its response is written to, so
+/// a response always reaches the process which made the request. The
+/// receiver additionally learns the pid of each requesting process from the
+/// kernel, counts the distinct processes and optionally rejects processes
+/// not belonging to the partition.
+pub struct SyscallReceiver {
+    socket: UnixDatagram,
+    /// Number of requests received from each process
+    peers: RefCell<HashMap<Pid, usize>>,
+    filter: Option<PeerFilter>,
+    /// Reused for every request, so that it only grows to the size of the
+    /// largest request
+    request: RefCell<Vec<u8>>,
+}
 
 impl SyscallReceiver {
-    pub fn from_datagram(datagram: UnixDatagram) -> Self {
-        Self(datagram)
+    pub fn from_datagram(socket: UnixDatagram) -> Result<Self> {
+        // Let the kernel attach the credentials of the sender to every request
+        setsockopt(&socket, sockopt::PassCred, &true)?;
+        Ok(Self {
+            socket,
+            peers: Default::default(),
+            filter: None,
+            request: Default::default(),
+        })
     }
 
     pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
         let socket = UnixDatagram::bind(path)?;
         socket.set_nonblocking(true)?;
-        Ok(Self(socket))
+        Self::from_datagram(socket)
+    }
+
+    /// Only handles the requests of processes for which `filter` returns true,
+    /// e.g. the processes in the cgroup of the partition
+    ///
+    /// The requests of all other processes are answered with
+    /// [ErrorReturnCode::InvalidConfig](wire::ErrorReturnCode::InvalidConfig).
+    pub fn with_peer_filter(mut self, filter: impl Fn(Pid) -> bool + Send + 'static) -> Self {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Number of distinct processes which made requests so far
+    ///
+    /// More than one usually means that a forked helper process shares the
+    /// connection of the partition.
+    pub fn num_peers(&self) -> usize {
+        self.peers.borrow().len()
+    }
+
+    /// Counts a request of `peer`, returning whether it may be handled
+    fn accept(&self, peer: Pid) -> bool {
+        let mut peers = self.peers.borrow_mut();
+        let new = !peers.contains_key(&peer);
+        *peers.entry(peer).or_default() += 1;
+        if new && peers.len() > 1 {
+            warn!(
+                "{} processes are making syscalls, the latest one is {peer}",
+                peers.len()
+            );
+        }
+        drop(peers);
+
+        let accepted = self.filter.as_ref().is_none_or(|f| f(peer));
+        if !accepted {
+            warn!("rejecting syscall of process {peer}, which does not belong to the partition");
+        }
+        accepted
     }
 
     /// Returns whether a syscall was handled
@@ -36,7 +106,7 @@ impl SyscallReceiver {
         handler: impl FnOnce(SyscallType, &[u8]) -> Vec<u8>,
     ) -> Result<bool> {
         if self.wait_fds(timeout)? {
-            let [request_fd, resp_fd, event_fd] = self.recv_fd_triple()?;
+            let (peer, [request_fd, resp_fd, event_fd]) = self.recv_fd_triple()?;
             let mut request_fd = Mfd::from_fd(request_fd)?;
             let mut response_fd = Mfd::from_fd(resp_fd)?;
 
@@ -45,13 +115,23 @@ impl SyscallReceiver {
             // response. Thus returning here would discard the received
             // message and the partition will never unblock.
 
-            // Fetch the request
-            let serialized_payload = request_fd.read_all()?;
+            let serialized_response = if self.accept(peer) {
+                // Fetch the request
+                let mut serialized_payload = self.request.borrow_mut();
+                request_fd.read_into(&mut serialized_payload)?;
 
-            // Deserialize the type and data
-            let payload: SyscallRequest = bincode::deserialize(&serialized_payload)?;
+                // Deserialize the type and data, the latter borrowed from the request buffer.
+                // Its encoding is the same as that of the owned data in a `SyscallRequest`.
+                let (ty, params): (SyscallType, &[u8]) = bincode::deserialize(&serialized_payload)?;
 
-            let serialized_response = handler(payload.0, &payload.1);
+                handler(ty, params)
+            } else {
+                // The encoding of an error does not depend on the type of the successful
+                // response
+                bincode::serialize(&SyscallResponse::<()>::Err(
+                    wire::ErrorReturnCode::InvalidConfig,
+                ))?
+            };
 
             // Write the response
             response_fd.write(&serialized_response)?;
@@ -74,17 +154,15 @@ impl SyscallReceiver {
         mut handler: impl FnMut(SyscallType, &[u8]) -> Vec<u8>,
     ) -> Result<usize>
 where {
-        let start = Instant::now();
+        let deadline = timeout.map(Deadline::after);
 
         let mut num_syscalls = 0;
         // A loop in which each iteration resembles the execution of one syscall
         loop {
-            let remaining_time = timeout.map(|timeout| timeout.saturating_sub(start.elapsed()));
-
-            if let Some(Duration::ZERO) = remaining_time {
+            if deadline.is_some_and(|d| d.has_passed()) {
                 break;
             }
-            if self.receive_one(remaining_time, &mut handler)? {
+            if self.receive_one(deadline.map(|d| d.remaining()), &mut handler)? {
                 num_syscalls += 1;
             }
         }
@@ -92,55 +170,58 @@ where {
         Ok(num_syscalls)
     }
 
-    /// Receives an FD triple from fd
+    /// Receives an FD triple from fd, together with the pid of its sender
     // TODO: Use generics here
-    fn recv_fd_triple(&self) -> Result<[OwnedFd; 3]> {
-        let mut cmsg = cmsg_space!([RawFd; 3]);
+    fn recv_fd_triple(&self) -> Result<(Pid, [OwnedFd; 3])> {
+        let mut cmsg = cmsg_space!([RawFd; 3], UnixCredentials);
         let mut iobuf = [0u8];
         let mut iov = [IoSliceMut::new(&mut iobuf)];
         let res = recvmsg::<()>(
-            self.0.as_raw_fd(),
+            self.socket.as_raw_fd(),
             &mut iov,
             Some(&mut cmsg),
             MsgFlags::empty(),
         )?;
 
-        let fds: Vec<RawFd> = match res.cmsgs()?.next().unwrap() {
-            ControlMessageOwned::ScmRights(fds) => fds,
-            _ => bail!("received an unknown cmsg"),
-        };
+        let mut fds = None;
+        let mut peer = None;
+        for cmsg in res.cmsgs()? {
+            match cmsg {
+                ControlMessageOwned::ScmRights(received) => fds = Some(received),
+                ControlMessageOwned::ScmCredentials(creds) => {
+                    peer = Some(Pid::from_raw(creds.pid()))
+                }
+                _ => bail!("received an unknown cmsg"),
+            }
+        }
         let fds = fds
+            .ok_or_else(|| anyhow!("received no fds"))?
             .into_iter()
             .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) })
             .collect::<Vec<_>>();
-        fds.try_into()
-            .map_err(|_| anyhow!("received fds but not a tripe"))
+        let fds = fds
+            .try_into()
+            .map_err(|_| anyhow!("received fds but not a tripe"))?;
+        let peer = peer.ok_or_else(|| anyhow!("received no credentials of the sender"))?;
+        Ok((peer, fds))
     }
 
     /// Waits for readable data on fd
     ///
-    /// Note: Even if timeout is None, this can return Ok(false). See
-    /// [Poller::wait]
+    /// Returns `Ok(false)` once `timeout` elapsed without data, `None` waits
+    /// forever.
     fn wait_fds(&self, timeout: Option<Duration>) -> Result<bool> {
-        let end_of_timeout = timeout.map(|duration| Instant::now() + duration);
-
-        // This closure calculates the duratioon until the end of timeout is reached.
-        // It is necessary, so that the timeout duration can be recalculated in case the
-        // `poller.wait` method returns spuriously.
-        let remaining_timeout_duration =
-            || end_of_timeout.map(|end| end.duration_since(Instant::now()));
+        let deadline = timeout.map(Deadline::after);
 
         let poller = Poller::new()?;
         let mut events = Events::with_capacity(NonZeroUsize::MIN);
-        unsafe { poller.add(self.0.as_raw_fd(), Event::readable(0))? };
+        unsafe { poller.add(self.socket.as_raw_fd(), Event::readable(0))? };
         loop {
-            match poller.wait(&mut events, remaining_timeout_duration()) {
-                Ok(0) => {
-                    // The poller's `wait` method may return spuriously.
-                    // In that case call it again. `remaining_time_duration` will automatically
-                    // calculate the new timeout duration.
-                    continue;
-                }
+            match poller.wait(&mut events, deadline.and_then(|d| d.poll_timeout())) {
+                // The poller's `wait` method may return spuriously, in which case it is
+                // called again with the time left
+                Ok(0) if deadline.is_some_and(|d| d.has_passed()) => return Ok(false),
+                Ok(0) => continue,
                 Ok(1) => return Ok(true),
                 Err(e) => {
                     if e.raw_os_error() == Some(EINTR) {
@@ -171,7 +252,7 @@ where
 {
     let params: S::Params = bincode::deserialize(serialized_params)?;
 
-    let response: SyscallResponse<S::Returns> = f(params);
+    let response: SyscallResponse<S::Returns> = f(params).map_err(Into::into);
 
     bincode::serialize(&response).map_err(Into::into)
 }
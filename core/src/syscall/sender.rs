@@ -99,6 +99,6 @@ impl SyscallSender {
         let data = response_fd.read_all()?;
         let response: SyscallResponse<S::Returns> = bincode::deserialize(&data)?;
 
-        Ok(response)
+        Ok(response.map_err(Into::into))
     }
 }
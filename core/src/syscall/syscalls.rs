@@ -1,7 +1,15 @@
-use a653rs::bindings::*;
+use a653rs::bindings::{
+    ApexByte, ApexSystemTime, MessageRange, MessageSize, ProcessId, QueueOverflow, QueuingPortId,
+    QueuingPortName, SamplingPortId, SamplingPortName,
+};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
+use crate::wire::{
+    ErrorCode, OperatingMode, PartitionStatus, PortDirection, QueuingDiscipline, QueuingPortStatus,
+    Validity,
+};
+
 pub trait Syscall<'params> {
     const TY: super::SyscallType;
     type Params: Serialize + Deserialize<'params>;
@@ -9,6 +17,9 @@ pub trait Syscall<'params> {
 }
 
 /// `'params` is available as a lifetime for parameter types
+///
+/// Enums of a653rs must be given as their counterparts of [crate::wire], so
+/// that their encoding does not depend on the version of a653rs.
 macro_rules! define_syscall {
     ($name:ident: |$params:ty| -> $returns:ty) => {
         pub struct $name;
@@ -28,7 +39,7 @@ macro_rules! define_multiple_syscalls {
 
 // ApexPartitionP4
 define_multiple_syscalls!(
-    GetPartitionStatus: |()| -> ApexPartitionStatus,
+    GetPartitionStatus: |()| -> PartitionStatus,
     SetPartitionMode: |OperatingMode| -> (),
 );
 
@@ -0,0 +1,120 @@
+//! Gauges reported by partitions to the hypervisor
+//!
+//! A partition reports the current value of a named gauge with
+//! [PartitionCall::Telemetry](crate::health_event::PartitionCall::Telemetry).
+//! The hypervisor keeps the last value of every gauge and exports it, so that
+//! partitions need no exporter of their own. Names are restricted by
+//! [check_name] and the updates of a partition by a [RateLimit], both on the
+//! side of the partition and again by the hypervisor.
+
+use std::time::Duration;
+
+/// Maximum length of a gauge name in bytes
+pub const MAX_NAME_LEN: usize = 64;
+
+/// Updates a partition may report per second, across all of its gauges
+pub const MAX_UPDATES_PER_SECOND: u32 = 100;
+
+/// Returns why `name` may not be used for a gauge, or `None` if it is valid.
+///
+/// Valid names are non-empty, at most [MAX_NAME_LEN] bytes long, start with
+/// an ASCII letter or `_` and only consist of ASCII letters, digits and `_`,
+/// like the names of Prometheus metrics.
+pub fn check_name(name: &str) -> Option<&'static str> {
+    if name.is_empty() {
+        Some("is empty")
+    } else if name.len() > MAX_NAME_LEN {
+        Some("is longer than 64 bytes")
+    } else if name.starts_with(|c: char| c.is_ascii_digit()) {
+        Some("starts with a digit")
+    } else if !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') {
+        Some("may only contain ASCII letters, digits and '_'")
+    } else {
+        None
+    }
+}
+
+/// Limits the number of updates within each second of the module time
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    max_per_second: u32,
+    /// Second of the module time the updates are counted for
+    second: u64,
+    count: u32,
+}
+
+impl RateLimit {
+    pub fn new(max_per_second: u32) -> Self {
+        Self {
+            max_per_second,
+            second: 0,
+            count: 0,
+        }
+    }
+
+    /// Counts an update at the module time `now`, returning whether it is
+    /// within the limit
+    pub fn allow(&mut self, now: Duration) -> bool {
+        if now.as_secs() != self.second {
+            self.second = now.as_secs();
+            self.count = 0;
+        }
+        if self.count < self.max_per_second {
+            self.count += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self::new(MAX_UPDATES_PER_SECOND)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gauge_names() {
+        for name in [
+            "fuel_level",
+            "_internal",
+            "Tank2",
+            &"a".repeat(MAX_NAME_LEN),
+        ] {
+            assert_eq!(check_name(name), None, "{name}");
+        }
+        for name in [
+            "",
+            "2nd_tank",
+            "fuel-level",
+            "fuel level",
+            "fuel.level",
+            "füel",
+            "a\"b",
+            &"a".repeat(MAX_NAME_LEN + 1),
+        ] {
+            assert!(check_name(name).is_some(), "{name}");
+        }
+    }
+
+    #[test]
+    fn updates_per_second() {
+        let mut limit = RateLimit::new(3);
+        let at = Duration::from_millis;
+        assert!(limit.allow(at(0)));
+        assert!(limit.allow(at(100)));
+        assert!(limit.allow(at(999)));
+        assert!(!limit.allow(at(999)));
+        // A new second starts a new count
+        assert!(limit.allow(at(1000)));
+        assert!(limit.allow(at(1500)));
+        assert!(limit.allow(at(1600)));
+        assert!(!limit.allow(at(1700)));
+        assert!(limit.allow(at(5000)));
+    }
+}
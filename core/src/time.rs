@@ -0,0 +1,411 @@
+//! Points in time shared between processes
+//!
+//! [std::time::Instant] is opaque and its representation is not guaranteed to
+//! be meaningful in another process, so it must never be placed in memory
+//! shared between the hypervisor and the partitions. [MonotonicTime] is an
+//! explicit reading of `CLOCK_MONOTONIC` instead, which every process on the
+//! system agrees on.
+//!
+//! [std::time::Instant] remains the right choice for measuring time within a
+//! single process.
+//!
+//! Besides the raw readings of the clocks, three time domains are kept apart
+//! by their own types:
+//! - [ModuleTime] counts from the start of the module, i.e. the first major
+//!   frame. It is the time of the APEX services, the log and the schedule.
+//! - [WindowTime] counts from the start of the current window of a partition.
+//! - [WallTime] is the time of day of `CLOCK_REALTIME`, which is only fit for
+//!   presenting points in time to humans.
+//!
+//! Converting between the domains requires the anchor relating them: the
+//! [MonotonicTime] at which the module started, the [ModuleTime] at which the
+//! window started, or a [ClockAnchor] taken from both clocks.
+
+use std::fmt::Debug;
+use std::ops::{Add, AddAssign};
+use std::time::{Duration, SystemTime};
+
+use nix::time::{clock_gettime, ClockId};
+use serde::{Deserialize, Serialize};
+
+/// A reading of `CLOCK_MONOTONIC` in nanoseconds since boot
+///
+/// The zero value is never returned by [MonotonicTime::now], so it can be used
+/// to mark the absence of a timestamp in shared memory.
+#[repr(transparent)]
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MonotonicTime(u64);
+
+impl MonotonicTime {
+    /// The absence of a timestamp
+    pub const ZERO: MonotonicTime = MonotonicTime(0);
+
+    /// Reads the monotonic clock
+    pub fn now() -> Self {
+        let now = clock_gettime(ClockId::CLOCK_MONOTONIC)
+            .expect("CLOCK_MONOTONIC to be supported by every Linux kernel");
+        Duration::from(now).into()
+    }
+
+    /// Time passed since `self`, zero if `self` lies in the future
+    pub fn elapsed(&self) -> Duration {
+        Self::now().duration_since(*self)
+    }
+
+    /// Time passed between `earlier` and `self`, zero if `earlier` is later
+    /// than `self`
+    pub fn duration_since(&self, earlier: MonotonicTime) -> Duration {
+        Duration::from_nanos(self.0.saturating_sub(earlier.0))
+    }
+
+    /// Returns `self + duration`, or `None` on overflow
+    pub fn checked_add(&self, duration: Duration) -> Option<Self> {
+        let nanos = u64::try_from(duration.as_nanos()).ok()?;
+        self.0.checked_add(nanos).map(Self)
+    }
+
+    /// Whether this is [MonotonicTime::ZERO]
+    pub fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Time since boot represented by this value
+    pub fn as_duration(&self) -> Duration {
+        Duration::from_nanos(self.0)
+    }
+}
+
+impl From<Duration> for MonotonicTime {
+    /// Saturates at the largest representable time (more than 584 years after
+    /// boot)
+    fn from(since_boot: Duration) -> Self {
+        Self(since_boot.as_nanos().try_into().unwrap_or(u64::MAX))
+    }
+}
+
+impl From<MonotonicTime> for Duration {
+    fn from(time: MonotonicTime) -> Self {
+        time.as_duration()
+    }
+}
+
+impl Debug for MonotonicTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("MonotonicTime")
+            .field(&self.as_duration())
+            .finish()
+    }
+}
+
+/// Time since the start of the module
+///
+/// The module starts with its first major frame. The hypervisor shares the
+/// [MonotonicTime] of this start with every partition, which is the anchor
+/// of every conversion from and to [MonotonicTime].
+///
+/// Encoded like the [Duration] it wraps, both in memory and by serde.
+#[repr(transparent)]
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct ModuleTime(Duration);
+
+impl ModuleTime {
+    /// The start of the module
+    pub const ZERO: ModuleTime = ModuleTime(Duration::ZERO);
+
+    /// The current module time of a module started at `start`
+    pub fn now(start: MonotonicTime) -> Self {
+        Self::at(MonotonicTime::now(), start)
+    }
+
+    /// The module time of `time` for a module started at `start`, zero if
+    /// `time` lies before the start
+    pub fn at(time: MonotonicTime, start: MonotonicTime) -> Self {
+        Self(time.duration_since(start))
+    }
+
+    /// The reading of the monotonic clock at this module time of a module
+    /// started at `start`
+    ///
+    /// Saturates like the conversion of a [Duration] to [MonotonicTime].
+    pub fn to_monotonic(self, start: MonotonicTime) -> MonotonicTime {
+        start.as_duration().saturating_add(self.0).into()
+    }
+
+    /// Time passed between `earlier` and `self`, zero if `earlier` is later
+    /// than `self`
+    pub fn duration_since(self, earlier: ModuleTime) -> Duration {
+        self.0.saturating_sub(earlier.0)
+    }
+
+    /// Time since the start of the module represented by this value
+    pub fn as_duration(self) -> Duration {
+        self.0
+    }
+}
+
+impl From<Duration> for ModuleTime {
+    /// Interprets `since_start` as the time since the start of the module
+    fn from(since_start: Duration) -> Self {
+        Self(since_start)
+    }
+}
+
+impl From<ModuleTime> for Duration {
+    fn from(time: ModuleTime) -> Self {
+        time.as_duration()
+    }
+}
+
+impl Add<Duration> for ModuleTime {
+    type Output = ModuleTime;
+
+    fn add(self, rhs: Duration) -> Self::Output {
+        Self(self.0 + rhs)
+    }
+}
+
+impl AddAssign<Duration> for ModuleTime {
+    fn add_assign(&mut self, rhs: Duration) {
+        self.0 += rhs
+    }
+}
+
+/// Time since the start of the current window of a partition
+///
+/// Only meaningful together with the [ModuleTime] at which the window started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WindowTime(Duration);
+
+impl WindowTime {
+    /// The start of the window
+    pub const ZERO: WindowTime = WindowTime(Duration::ZERO);
+
+    /// The position of `time` in the window starting at `window_start`, zero
+    /// if `time` lies before the window
+    pub fn at(time: ModuleTime, window_start: ModuleTime) -> Self {
+        Self(time.duration_since(window_start))
+    }
+
+    /// The module time of this position in the window starting at
+    /// `window_start`
+    pub fn to_module(self, window_start: ModuleTime) -> ModuleTime {
+        window_start + self.0
+    }
+
+    /// Time since the start of the window represented by this value
+    pub fn as_duration(self) -> Duration {
+        self.0
+    }
+}
+
+impl From<Duration> for WindowTime {
+    /// Interprets `since_start` as the time since the start of the window
+    fn from(since_start: Duration) -> Self {
+        Self(since_start)
+    }
+}
+
+impl From<WindowTime> for Duration {
+    fn from(time: WindowTime) -> Self {
+        time.as_duration()
+    }
+}
+
+/// A reading of `CLOCK_REALTIME`
+///
+/// The wall clock jumps whenever the time of the host is set, e.g. by NTP. A
+/// [MonotonicTime] converted to wall time through a [ClockAnchor] is therefore
+/// only accurate as long as the wall clock was not stepped since the anchor
+/// was taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WallTime(SystemTime);
+
+impl WallTime {
+    /// Reads the wall clock
+    pub fn now() -> Self {
+        Self(SystemTime::now())
+    }
+
+    /// The wall time of `time`, relative to a pair of readings of both clocks
+    pub fn at(time: MonotonicTime, anchor: ClockAnchor) -> Self {
+        if time >= anchor.monotonic {
+            Self(anchor.wall.0 + time.duration_since(anchor.monotonic))
+        } else {
+            Self(anchor.wall.0 - anchor.monotonic.duration_since(time))
+        }
+    }
+
+    /// Time since the Unix epoch, zero for points in time before it
+    pub fn since_unix_epoch(self) -> Duration {
+        self.0
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+
+    pub fn as_system_time(self) -> SystemTime {
+        self.0
+    }
+}
+
+impl From<SystemTime> for WallTime {
+    fn from(time: SystemTime) -> Self {
+        Self(time)
+    }
+}
+
+impl From<WallTime> for SystemTime {
+    fn from(time: WallTime) -> Self {
+        time.as_system_time()
+    }
+}
+
+/// Readings of the wall clock and the monotonic clock taken at the same time,
+/// relating both clocks to each other
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockAnchor {
+    pub wall: WallTime,
+    pub monotonic: MonotonicTime,
+}
+
+impl ClockAnchor {
+    /// Reads both clocks
+    pub fn now() -> Self {
+        Self {
+            monotonic: MonotonicTime::now(),
+            wall: WallTime::now(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::size_of;
+
+    use nix::sys::wait::{waitpid, WaitStatus};
+    use nix::unistd::{fork, ForkResult};
+
+    use super::*;
+    use crate::file::TempFile;
+
+    #[test]
+    fn monotonic() {
+        let a = MonotonicTime::now();
+        let b = MonotonicTime::now();
+        assert!(!a.is_zero());
+        assert!(a <= b);
+        assert_eq!(a.duration_since(b), Duration::ZERO);
+        assert_eq!(b.duration_since(a), b.as_duration() - a.as_duration());
+    }
+
+    #[test]
+    fn conversions() {
+        let d = Duration::new(3, 42);
+        assert_eq!(Duration::from(MonotonicTime::from(d)), d);
+        assert_eq!(MonotonicTime::from(Duration::MAX), MonotonicTime(u64::MAX));
+        assert_eq!(
+            MonotonicTime::from(d).checked_add(Duration::from_nanos(1)),
+            Some(MonotonicTime::from(Duration::new(3, 43)))
+        );
+        assert_eq!(
+            MonotonicTime(u64::MAX).checked_add(Duration::from_nanos(1)),
+            None
+        );
+        assert_eq!(MonotonicTime::ZERO.checked_add(Duration::MAX), None);
+    }
+
+    fn secs(secs: f64) -> Duration {
+        Duration::from_secs_f64(secs)
+    }
+
+    #[test]
+    fn module_time_conversions() {
+        let start = MonotonicTime::from(secs(10.0));
+        let time = MonotonicTime::from(secs(12.5));
+        let module_time = ModuleTime::at(time, start);
+        assert_eq!(module_time, ModuleTime::from(secs(2.5)));
+        assert_eq!(module_time.to_monotonic(start), time);
+        assert_eq!(ModuleTime::at(start, time), ModuleTime::ZERO);
+        assert_eq!(ModuleTime::ZERO.to_monotonic(start), start);
+        assert_eq!(
+            ModuleTime::from(Duration::MAX).to_monotonic(start),
+            MonotonicTime(u64::MAX)
+        );
+
+        assert_eq!(module_time + secs(0.5), ModuleTime::from(secs(3.0)));
+        assert_eq!(module_time.duration_since(ModuleTime::ZERO), secs(2.5));
+        assert_eq!(ModuleTime::ZERO.duration_since(module_time), Duration::ZERO);
+
+        let now = ModuleTime::now(MonotonicTime::now());
+        assert!(now.as_duration() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn window_time_conversions() {
+        let window_start = ModuleTime::from(secs(1.0));
+        let time = ModuleTime::from(secs(1.25));
+        let window_time = WindowTime::at(time, window_start);
+        assert_eq!(window_time, WindowTime::from(secs(0.25)));
+        assert_eq!(window_time.to_module(window_start), time);
+        assert_eq!(
+            WindowTime::at(ModuleTime::ZERO, window_start),
+            WindowTime::ZERO
+        );
+    }
+
+    #[test]
+    fn wall_time_from_anchor() {
+        let anchor = ClockAnchor {
+            wall: WallTime::from(SystemTime::UNIX_EPOCH + secs(1000.0)),
+            monotonic: MonotonicTime::from(secs(5.0)),
+        };
+        let wall = |monotonic| WallTime::at(MonotonicTime::from(secs(monotonic)), anchor);
+        assert_eq!(wall(5.0), anchor.wall);
+        assert_eq!(wall(7.5).since_unix_epoch(), secs(1002.5));
+        assert_eq!(wall(4.0).since_unix_epoch(), secs(999.0));
+
+        let before_epoch = WallTime::from(SystemTime::UNIX_EPOCH - secs(1.0));
+        assert_eq!(before_epoch.since_unix_epoch(), Duration::ZERO);
+    }
+
+    #[test]
+    fn module_time_is_encoded_like_duration() {
+        let time = Duration::new(7, 123);
+        assert_eq!(
+            bincode::serialize(&ModuleTime::from(time)).unwrap(),
+            bincode::serialize(&time).unwrap()
+        );
+        let decoded: ModuleTime =
+            bincode::deserialize(&bincode::serialize(&time).unwrap()).unwrap();
+        assert_eq!(decoded.as_duration(), time);
+        assert_eq!(size_of::<ModuleTime>(), size_of::<Duration>());
+    }
+
+    #[test]
+    fn shared_with_child_process() {
+        let file = TempFile::<MonotonicTime>::create("monotonic_time_test").unwrap();
+        let written = MonotonicTime::now();
+        file.write(&written).unwrap();
+
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                // Only report through the exit code, the test harness must not run in the
+                // child
+                let code = match file.read() {
+                    Ok(read) if read != written => 1,
+                    Ok(read) if read > MonotonicTime::now() => 2,
+                    Ok(read) if read.elapsed() > Duration::from_secs(60) => 3,
+                    Ok(_) => 0,
+                    Err(_) => 4,
+                };
+                unsafe { nix::libc::_exit(code) }
+            }
+            ForkResult::Parent { child } => {
+                let status = waitpid(child, None).unwrap();
+                assert_eq!(status, WaitStatus::Exited(child, 0));
+            }
+        }
+    }
+}
@@ -0,0 +1,424 @@
+//! Stable encodings of the a653rs types crossing process boundaries
+//!
+//! The hypervisor and a partition may be built against different versions of
+//! a653rs. Serializing its enums directly encodes the index of their variants,
+//! which shifts whenever a variant is added or reordered upstream. Every enum
+//! sent to another process is therefore encoded through one of the types of
+//! this module, whose numeric values are frozen.
+//!
+//! Each type converts from and to its a653rs counterpart, and comes with a
+//! module for `#[serde(with)]`, so that the a653rs type can stay in structs:
+//!
+//! ```
+//! use a653rs::prelude::OperatingMode;
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Status {
+//!     #[serde(with = "a653rs_linux_core::wire::operating_mode")]
+//!     mode: OperatingMode,
+//! }
+//! ```
+//!
+//! The values must never change, extending an enum is fine.
+
+use a653rs::bindings as apex;
+use a653rs::bindings::{
+    ApexSystemTime, LockLevel, MessageRange, MessageSize, NumCores, PartitionId, WaitingRange,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// A value that is not part of the encoding of a type
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("{value} is not a valid {ty}")]
+pub struct UnknownValue {
+    pub ty: &'static str,
+    pub value: u32,
+}
+
+macro_rules! wire_enum {
+    ($(#[$meta:meta])* $name:ident, $module:ident { $($variant:ident = $value:literal,)* }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+        #[serde(into = "u32", try_from = "u32")]
+        #[repr(u32)]
+        pub enum $name {
+            $($variant = $value,)*
+        }
+
+        impl $name {
+            /// Every value of this type
+            pub const ALL: &'static [$name] = &[$($name::$variant,)*];
+        }
+
+        impl From<apex::$name> for $name {
+            fn from(value: apex::$name) -> Self {
+                match value {
+                    $(apex::$name::$variant => $name::$variant,)*
+                }
+            }
+        }
+
+        impl From<$name> for apex::$name {
+            fn from(value: $name) -> Self {
+                match value {
+                    $($name::$variant => apex::$name::$variant,)*
+                }
+            }
+        }
+
+        impl From<$name> for u32 {
+            fn from(value: $name) -> Self {
+                value as u32
+            }
+        }
+
+        impl TryFrom<u32> for $name {
+            type Error = UnknownValue;
+
+            fn try_from(value: u32) -> Result<Self, Self::Error> {
+                match value {
+                    $($value => Ok($name::$variant),)*
+                    value => Err(UnknownValue {
+                        ty: stringify!($name),
+                        value,
+                    }),
+                }
+            }
+        }
+
+        #[doc = concat!("Encodes an [a653rs::bindings::", stringify!($name), "] as a [", stringify!($name), "], for use with `#[serde(with)]`")]
+        pub mod $module {
+            use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+            use super::apex;
+
+            pub fn serialize<S: Serializer>(
+                value: &apex::$name,
+                serializer: S,
+            ) -> Result<S::Ok, S::Error> {
+                super::$name::from(*value).serialize(serializer)
+            }
+
+            pub fn deserialize<'de, D: Deserializer<'de>>(
+                deserializer: D,
+            ) -> Result<apex::$name, D::Error> {
+                super::$name::deserialize(deserializer).map(Into::into)
+            }
+        }
+    };
+}
+
+wire_enum!(
+    /// Encoding of an [a653rs::bindings::ErrorReturnCode], the values are
+    /// those of the ARINC 653 `RETURN_CODE_TYPE`
+    ErrorReturnCode,
+    error_return_code {
+        NoAction = 1,
+        NotAvailable = 2,
+        InvalidParam = 3,
+        InvalidConfig = 4,
+        InvalidMode = 5,
+        TimedOut = 6,
+    }
+);
+
+wire_enum!(
+    /// Encoding of an [a653rs::bindings::OperatingMode]
+    OperatingMode,
+    operating_mode {
+        Idle = 0,
+        ColdStart = 1,
+        WarmStart = 2,
+        Normal = 3,
+    }
+);
+
+wire_enum!(
+    /// Encoding of a [a653rs::bindings::StartCondition]
+    StartCondition,
+    start_condition {
+        NormalStart = 0,
+        PartitionRestart = 1,
+        HmModuleRestart = 2,
+        HmPartitionRestart = 3,
+    }
+);
+
+wire_enum!(
+    /// Encoding of a [a653rs::bindings::Validity]
+    Validity,
+    validity {
+        Invalid = 0,
+        Valid = 1,
+    }
+);
+
+wire_enum!(
+    /// Encoding of a [a653rs::bindings::PortDirection]
+    PortDirection,
+    port_direction {
+        Source = 0,
+        Destination = 1,
+    }
+);
+
+wire_enum!(
+    /// Encoding of a [a653rs::bindings::QueuingDiscipline]
+    QueuingDiscipline,
+    queuing_discipline {
+        Fifo = 0,
+        Priority = 1,
+    }
+);
+
+wire_enum!(
+    /// Encoding of an [a653rs::bindings::ErrorCode]
+    ErrorCode,
+    error_code {
+        DeadlineMissed = 0,
+        ApplicationError = 1,
+        NumericError = 2,
+        IllegalRequest = 3,
+        StackOverflow = 4,
+        MemoryViolation = 5,
+        HardwareFault = 6,
+        PowerFail = 7,
+    }
+);
+
+/// Encoding of an [a653rs::bindings::ApexPartitionStatus]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartitionStatus {
+    pub period: ApexSystemTime,
+    pub duration: ApexSystemTime,
+    pub identifier: PartitionId,
+    pub lock_level: LockLevel,
+    pub operating_mode: OperatingMode,
+    pub start_condition: StartCondition,
+    pub num_assigned_cores: NumCores,
+}
+
+impl From<apex::ApexPartitionStatus> for PartitionStatus {
+    fn from(status: apex::ApexPartitionStatus) -> Self {
+        Self {
+            period: status.period,
+            duration: status.duration,
+            identifier: status.identifier,
+            lock_level: status.lock_level,
+            operating_mode: status.operating_mode.into(),
+            start_condition: status.start_condition.into(),
+            num_assigned_cores: status.num_assigned_cores,
+        }
+    }
+}
+
+impl From<PartitionStatus> for apex::ApexPartitionStatus {
+    fn from(status: PartitionStatus) -> Self {
+        Self {
+            period: status.period,
+            duration: status.duration,
+            identifier: status.identifier,
+            lock_level: status.lock_level,
+            operating_mode: status.operating_mode.into(),
+            start_condition: status.start_condition.into(),
+            num_assigned_cores: status.num_assigned_cores,
+        }
+    }
+}
+
+/// Encoding of a [a653rs::bindings::QueuingPortStatus]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueuingPortStatus {
+    pub nb_message: MessageRange,
+    pub max_nb_message: MessageRange,
+    pub max_message_size: MessageSize,
+    pub port_direction: PortDirection,
+    pub waiting_processes: WaitingRange,
+}
+
+impl From<apex::QueuingPortStatus> for QueuingPortStatus {
+    fn from(status: apex::QueuingPortStatus) -> Self {
+        Self {
+            nb_message: status.nb_message,
+            max_nb_message: status.max_nb_message,
+            max_message_size: status.max_message_size,
+            port_direction: status.port_direction.into(),
+            waiting_processes: status.waiting_processes,
+        }
+    }
+}
+
+impl From<QueuingPortStatus> for apex::QueuingPortStatus {
+    fn from(status: QueuingPortStatus) -> Self {
+        Self {
+            nb_message: status.nb_message,
+            max_nb_message: status.max_nb_message,
+            max_message_size: status.max_message_size,
+            port_direction: status.port_direction.into(),
+            waiting_processes: status.waiting_processes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The frozen encoding of every type. A failure here means that the
+    /// hypervisor and partitions built from different versions of this crate
+    /// can no longer talk to each other.
+    const GOLDEN: &[(&str, &[(&str, u32)])] = &[
+        (
+            "ErrorReturnCode",
+            &[
+                ("NoAction", 1),
+                ("NotAvailable", 2),
+                ("InvalidParam", 3),
+                ("InvalidConfig", 4),
+                ("InvalidMode", 5),
+                ("TimedOut", 6),
+            ],
+        ),
+        (
+            "OperatingMode",
+            &[
+                ("Idle", 0),
+                ("ColdStart", 1),
+                ("WarmStart", 2),
+                ("Normal", 3),
+            ],
+        ),
+        (
+            "StartCondition",
+            &[
+                ("NormalStart", 0),
+                ("PartitionRestart", 1),
+                ("HmModuleRestart", 2),
+                ("HmPartitionRestart", 3),
+            ],
+        ),
+        ("Validity", &[("Invalid", 0), ("Valid", 1)]),
+        ("PortDirection", &[("Source", 0), ("Destination", 1)]),
+        ("QueuingDiscipline", &[("Fifo", 0), ("Priority", 1)]),
+        (
+            "ErrorCode",
+            &[
+                ("DeadlineMissed", 0),
+                ("ApplicationError", 1),
+                ("NumericError", 2),
+                ("IllegalRequest", 3),
+                ("StackOverflow", 4),
+                ("MemoryViolation", 5),
+                ("HardwareFault", 6),
+                ("PowerFail", 7),
+            ],
+        ),
+    ];
+
+    /// Names and values of all variants of `T`, checking that each of them
+    /// survives every conversion
+    fn encoding<T, A>(all: &[T]) -> Vec<(String, u32)>
+    where
+        T: Copy
+            + PartialEq
+            + std::fmt::Debug
+            + Into<u32>
+            + Into<A>
+            + From<A>
+            + TryFrom<u32, Error = UnknownValue>
+            + Serialize
+            + for<'de> Deserialize<'de>,
+    {
+        all.iter()
+            .map(|&wire| {
+                let value: u32 = wire.into();
+                assert_eq!(T::try_from(value), Ok(wire));
+                assert_eq!(T::from(Into::<A>::into(wire)), wire);
+
+                let encoded = bincode::serialize(&wire).unwrap();
+                assert_eq!(encoded, bincode::serialize(&value).unwrap());
+                assert_eq!(bincode::deserialize::<T>(&encoded).unwrap(), wire);
+
+                (format!("{wire:?}"), value)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn golden_table() {
+        let actual = [
+            (
+                "ErrorReturnCode",
+                encoding::<ErrorReturnCode, apex::ErrorReturnCode>(ErrorReturnCode::ALL),
+            ),
+            (
+                "OperatingMode",
+                encoding::<OperatingMode, apex::OperatingMode>(OperatingMode::ALL),
+            ),
+            (
+                "StartCondition",
+                encoding::<StartCondition, apex::StartCondition>(StartCondition::ALL),
+            ),
+            (
+                "Validity",
+                encoding::<Validity, apex::Validity>(Validity::ALL),
+            ),
+            (
+                "PortDirection",
+                encoding::<PortDirection, apex::PortDirection>(PortDirection::ALL),
+            ),
+            (
+                "QueuingDiscipline",
+                encoding::<QueuingDiscipline, apex::QueuingDiscipline>(QueuingDiscipline::ALL),
+            ),
+            (
+                "ErrorCode",
+                encoding::<ErrorCode, apex::ErrorCode>(ErrorCode::ALL),
+            ),
+        ];
+
+        assert_eq!(actual.len(), GOLDEN.len());
+        for ((ty, actual), (golden_ty, golden)) in actual.iter().zip(GOLDEN) {
+            assert_eq!(ty, golden_ty);
+            let golden: Vec<_> = golden
+                .iter()
+                .map(|(name, value)| (name.to_string(), *value))
+                .collect();
+            assert_eq!(actual, &golden, "encoding of {ty} changed");
+        }
+    }
+
+    #[test]
+    fn unknown_values() {
+        assert_eq!(
+            ErrorReturnCode::try_from(0),
+            Err(UnknownValue {
+                ty: "ErrorReturnCode",
+                value: 0
+            })
+        );
+        assert!(OperatingMode::try_from(4).is_err());
+        assert!(bincode::deserialize::<Validity>(&2u32.to_le_bytes()).is_err());
+    }
+
+    #[test]
+    fn serde_with() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Status {
+            #[serde(with = "operating_mode")]
+            mode: apex::OperatingMode,
+            #[serde(with = "port_direction")]
+            dir: apex::PortDirection,
+        }
+
+        let status = Status {
+            mode: apex::OperatingMode::Normal,
+            dir: apex::PortDirection::Destination,
+        };
+        let encoded = bincode::serialize(&status).unwrap();
+        assert_eq!(encoded, [3u32.to_le_bytes(), 1u32.to_le_bytes()].concat());
+        assert_eq!(bincode::deserialize::<Status>(&encoded).unwrap(), status);
+    }
+}
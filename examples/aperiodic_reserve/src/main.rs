@@ -0,0 +1,32 @@
+//! # Example `aperiodic_reserve`
+//!
+//! Shows a partition whose periodic process `Greedy` never waits for its next
+//! period, but spins through every window. As the partition reserves a fifth
+//! of its windows with `aperiodic_reserve`, the hypervisor freezes `Greedy`
+//! once only the reserve is left, and the aperiodic process `Background` still
+//! runs in every window. It logs its progress every 10ms.
+
+use std::thread::sleep;
+use std::time::Duration;
+
+use a653rs_linux::builder::{PartitionBuilder, ProcessOptions};
+use a653rs_linux::partition::ApexLogger;
+use log::info;
+
+fn main() {
+    ApexLogger::install_panic_hook();
+    ApexLogger::install_logger(log::LevelFilter::Info).unwrap();
+
+    PartitionBuilder::new()
+        .cold_start(|_| ())
+        .periodic("Greedy", ProcessOptions::default(), |_| loop {
+            std::hint::spin_loop()
+        })
+        .aperiodic("Background", ProcessOptions::default(), |_| {
+            for n in 1.. {
+                sleep(Duration::from_millis(10));
+                info!("Background work {n}");
+            }
+        })
+        .run()
+}
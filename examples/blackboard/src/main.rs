@@ -0,0 +1,124 @@
+//! # Example `blackboard`
+//!
+//! Shows two processes of a partition communicating over a blackboard. The
+//! periodic process `Writer` displays a message in every odd period and clears
+//! the blackboard in every even one, logging the number of processes waiting
+//! for a message beforehand. The aperiodic process `Reader` logs every new
+//! message it reads and when it finds the blackboard empty, after which it
+//! waits for the next message to be displayed.
+
+use core::str::FromStr;
+use core::time::Duration;
+
+use a653rs::bindings::{
+    ApexBlackboardP1, ApexName, ApexSystemTime, BlackboardId, ErrorReturnCode, MessageSize,
+};
+use a653rs::prelude::*;
+use a653rs_linux::partition::{ApexLinuxPartition, ApexLogger};
+use log::{error, info, warn};
+
+const BLACKBOARD: &str = "Messages";
+const MSG_SIZE: usize = 32;
+
+fn main() {
+    ApexLogger::install_panic_hook();
+    ApexLogger::install_logger(log::LevelFilter::Info).unwrap();
+
+    BlackboardPartition.run()
+}
+
+type Hypervisor = ApexLinuxPartition;
+
+pub struct BlackboardPartition;
+
+impl a653rs::prelude::Partition<Hypervisor> for BlackboardPartition {
+    fn cold_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        Hypervisor::create_blackboard(name(), MSG_SIZE as MessageSize).unwrap();
+
+        for (name, entry_point, period) in [
+            (
+                "Writer",
+                writer as extern "C" fn(),
+                SystemTime::Normal(Duration::ZERO),
+            ),
+            ("Reader", reader as extern "C" fn(), SystemTime::Infinite),
+        ] {
+            let process_attributes = ProcessAttribute {
+                period,
+                time_capacity: SystemTime::Infinite,
+                entry_point,
+                stack_size: 100_000,
+                base_priority: 1,
+                deadline: Deadline::Soft,
+                name: Name::from_str(name).unwrap(),
+            };
+            let process_handle = ctx.create_process(process_attributes).unwrap();
+            process_handle.start().unwrap();
+        }
+    }
+
+    fn warm_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        self.cold_start(ctx)
+    }
+}
+
+fn name() -> ApexName {
+    Name::from_str(BLACKBOARD).unwrap().into_inner()
+}
+
+/// The blackboard created by the main process
+fn blackboard() -> BlackboardId {
+    Hypervisor::get_blackboard_id(name()).unwrap()
+}
+
+extern "C" fn writer() {
+    let id = blackboard();
+    for period in 1.. {
+        match Hypervisor::get_blackboard_status(id) {
+            Ok(status) => info!(
+                "Writer sees waiting processes: {}",
+                status.waiting_processes
+            ),
+            Err(e) => error!("failed to get the status of the blackboard: {e:?}"),
+        }
+
+        if period % 2 == 1 {
+            let message = format!("message {period}");
+            Hypervisor::display_blackboard(id, message.as_bytes()).unwrap();
+            info!("Writer displayed {message}");
+        } else {
+            Hypervisor::clear_blackboard(id).unwrap();
+            info!("Writer cleared the blackboard");
+        }
+        Hypervisor::periodic_wait().unwrap();
+    }
+}
+
+extern "C" fn reader() {
+    let id = blackboard();
+    let mut last = None;
+    loop {
+        let mut buf = [0; MSG_SIZE];
+        match unsafe { Hypervisor::read_blackboard(id, 0, &mut buf) } {
+            Err(ErrorReturnCode::NotAvailable) if last.is_some() => {
+                info!("Reader found the blackboard empty");
+                last = None;
+            }
+            _ => {}
+        }
+
+        // Waits for a message while the blackboard is empty, for at most a second
+        let timeout = Duration::from_secs(1).as_nanos() as ApexSystemTime;
+        match unsafe { Hypervisor::read_blackboard(id, timeout, &mut buf) } {
+            Ok(len) => {
+                let message = String::from_utf8_lossy(&buf[..len as usize]).into_owned();
+                if last.as_ref() != Some(&message) {
+                    info!("Reader read {message}");
+                    last = Some(message);
+                }
+            }
+            Err(e) => warn!("Reader did not read a message: {e:?}"),
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
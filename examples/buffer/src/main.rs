@@ -0,0 +1,133 @@
+//! # Example `buffer`
+//!
+//! Shows two processes of a partition communicating over a buffer. The
+//! aperiodic process `Producer` sends numbered messages as fast as it can,
+//! until the buffer is full, after which it logs the overflow and blocks until
+//! there is space again. The periodic process `Consumer` logs the status of the
+//! buffer and then receives up to as many messages as the buffer holds in
+//! every period, checking that they arrive in the order they were sent.
+
+use core::str::FromStr;
+use core::time::Duration;
+
+use a653rs::bindings::{
+    ApexBufferP1, ApexName, ApexSystemTime, BufferId, ErrorReturnCode, MessageRange, MessageSize,
+    QueuingDiscipline,
+};
+use a653rs::prelude::*;
+use a653rs_linux::partition::{ApexLinuxPartition, ApexLogger};
+use log::{error, info, warn};
+
+const BUFFER: &str = "Messages";
+const MSG_SIZE: usize = 32;
+const CAPACITY: usize = 4;
+
+fn main() {
+    ApexLogger::install_panic_hook();
+    ApexLogger::install_logger(log::LevelFilter::Info).unwrap();
+
+    BufferPartition.run()
+}
+
+type Hypervisor = ApexLinuxPartition;
+
+pub struct BufferPartition;
+
+impl a653rs::prelude::Partition<Hypervisor> for BufferPartition {
+    fn cold_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        Hypervisor::create_buffer(
+            name(),
+            MSG_SIZE as MessageSize,
+            CAPACITY as MessageRange,
+            QueuingDiscipline::Fifo,
+        )
+        .unwrap();
+
+        for (name, entry_point, period) in [
+            (
+                "Consumer",
+                consumer as extern "C" fn(),
+                SystemTime::Normal(Duration::ZERO),
+            ),
+            (
+                "Producer",
+                producer as extern "C" fn(),
+                SystemTime::Infinite,
+            ),
+        ] {
+            let process_attributes = ProcessAttribute {
+                period,
+                time_capacity: SystemTime::Infinite,
+                entry_point,
+                stack_size: 100_000,
+                base_priority: 1,
+                deadline: Deadline::Soft,
+                name: Name::from_str(name).unwrap(),
+            };
+            let process_handle = ctx.create_process(process_attributes).unwrap();
+            process_handle.start().unwrap();
+        }
+    }
+
+    fn warm_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        self.cold_start(ctx)
+    }
+}
+
+fn name() -> ApexName {
+    Name::from_str(BUFFER).unwrap().into_inner()
+}
+
+/// The buffer created by the main process
+fn buffer() -> BufferId {
+    Hypervisor::get_buffer_id(name()).unwrap()
+}
+
+extern "C" fn producer() {
+    let id = buffer();
+    for i in 1.. {
+        let message = format!("message {i}");
+        if let Err(ErrorReturnCode::NotAvailable) =
+            Hypervisor::send_buffer(id, message.as_bytes(), 0)
+        {
+            info!("Producer found the buffer full");
+            // Waits for space in the buffer, for at most two seconds
+            let timeout = Duration::from_secs(2).as_nanos() as ApexSystemTime;
+            if let Err(e) = Hypervisor::send_buffer(id, message.as_bytes(), timeout) {
+                warn!("Producer did not send {message}: {e:?}");
+                continue;
+            }
+        }
+        info!("Producer sent {message}");
+    }
+}
+
+extern "C" fn consumer() {
+    let id = buffer();
+    let mut expected = 1;
+    loop {
+        match Hypervisor::get_buffer_status(id) {
+            Ok(status) => info!(
+                "Consumer sees {} messages and waiting processes: {}",
+                status.nb_message, status.waiting_processes
+            ),
+            Err(e) => error!("failed to get the status of the buffer: {e:?}"),
+        }
+
+        // At most one buffer full per period, as the producer refills it meanwhile
+        for _ in 0..CAPACITY {
+            let mut buf = [0; MSG_SIZE];
+            let Ok(len) = (unsafe { Hypervisor::receive_buffer(id, 0, &mut buf) }) else {
+                break;
+            };
+            let message = String::from_utf8_lossy(&buf[..len as usize]).into_owned();
+            if message == format!("message {expected}") {
+                info!("Consumer received {message}");
+            } else {
+                error!("Consumer received {message} out of order, expected message {expected}");
+            }
+            expected += 1;
+        }
+        Hypervisor::periodic_wait().unwrap();
+    }
+}
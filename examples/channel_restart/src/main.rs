@@ -0,0 +1,129 @@
+//! # Example `channel_restart`
+//!
+//! Shows what becomes of the messages of channels when a partition connected
+//! to them restarts. The partition with the role `sender` sends a single
+//! message on each of its ports in its first period. Of the sampling and of
+//! the queuing channels, one keeps its message on a restart and the other one
+//! is cleared, see `on_partition_restart` in `channel_restart.yaml`.
+//!
+//! The partition with the role `receiver` restarts itself as soon as the
+//! messages arrived, without reading them. The restarted partition reads every
+//! port once and logs what it found.
+
+use core::str::FromStr;
+use core::time::Duration;
+
+use a653rs::bindings::{ApexPartitionP4, MessageSize, StartCondition};
+use a653rs::prelude::*;
+use a653rs_linux::builder::{PartitionBuilder, ProcessContext, ProcessOptions};
+use a653rs_linux::partition::{ApexLinuxPartition, ApexLogger};
+use log::info;
+
+type Hypervisor = ApexLinuxPartition;
+
+const MSG_SIZE: MessageSize = 16;
+
+/// Names of the ports, which are the same in both partitions
+const SAMPLING: [&str; 2] = ["kept_sampling", "cleared_sampling"];
+const QUEUING: [&str; 2] = ["kept_queuing", "cleared_queuing"];
+
+fn main() {
+    ApexLogger::install_panic_hook();
+    ApexLogger::install_logger(log::LevelFilter::Info).unwrap();
+
+    PartitionBuilder::new()
+        .cold_start(start)
+        .periodic("Channels", ProcessOptions::default(), channels)
+        .run()
+}
+
+/// The ports of either role
+enum Ports {
+    Sender {
+        sampling: [SamplingPortSource<Hypervisor>; 2],
+        queuing: [QueuingPortSender<Hypervisor>; 2],
+    },
+    Receiver {
+        sampling: [SamplingPortDestination<Hypervisor>; 2],
+        queuing: [QueuingPortReceiver<Hypervisor>; 2],
+    },
+}
+
+fn start(ctx: &mut StartContext<Hypervisor>) -> Ports {
+    let name = |port: &str| Name::from_str(port).unwrap();
+    if Hypervisor::role() == Some("sender") {
+        Ports::Sender {
+            sampling: SAMPLING.map(|port| {
+                ctx.create_sampling_port_source(name(port), MSG_SIZE)
+                    .unwrap()
+            }),
+            queuing: QUEUING.map(|port| {
+                ctx.create_queuing_port_sender(name(port), MSG_SIZE, 4, QueuingDiscipline::Fifo)
+                    .unwrap()
+            }),
+        }
+    } else {
+        Ports::Receiver {
+            sampling: SAMPLING.map(|port| {
+                ctx.create_sampling_port_destination(name(port), MSG_SIZE, Duration::from_secs(10))
+                    .unwrap()
+            }),
+            queuing: QUEUING.map(|port| {
+                ctx.create_queuing_port_receiver(name(port), MSG_SIZE, 4, QueuingDiscipline::Fifo)
+                    .unwrap()
+            }),
+        }
+    }
+}
+
+fn channels(ctx: &ProcessContext<Ports>) {
+    match ctx.state() {
+        Ports::Sender { sampling, queuing } => {
+            for port in sampling {
+                port.send(b"before restart").unwrap();
+            }
+            for port in queuing {
+                port.send(b"before restart", SystemTime::Normal(Duration::ZERO))
+                    .unwrap();
+            }
+            info!("Sent a message on every port");
+        }
+        Ports::Receiver { sampling, queuing } => {
+            if Hypervisor::get_partition_status().start_condition == StartCondition::NormalStart {
+                while queuing[0].status().nb_message == 0 {
+                    ctx.periodic_wait().unwrap();
+                }
+                // The messages are left unread
+                info!("Restarting with unread messages");
+                Hypervisor::set_partition_mode(OperatingMode::ColdStart).unwrap();
+            }
+
+            let mut buf = [0; MSG_SIZE as usize];
+            for (name, port) in SAMPLING.iter().zip(sampling) {
+                let msg = port.receive(&mut buf).map_or(&[][..], |(_, msg)| msg);
+                report(name, msg);
+            }
+            for (name, port) in QUEUING.iter().zip(queuing) {
+                let msg = port
+                    .receive(&mut buf, SystemTime::Normal(Duration::ZERO))
+                    .map_or(&[][..], |(msg, _)| msg);
+                report(name, msg);
+            }
+        }
+    }
+
+    loop {
+        ctx.periodic_wait().unwrap();
+    }
+}
+
+fn report(port: &str, msg: &[u8]) {
+    if msg.is_empty() {
+        info!("{port} is empty after the restart");
+    } else {
+        info!(
+            "{port} holds {:?} after the restart",
+            String::from_utf8_lossy(msg)
+        );
+    }
+}
@@ -0,0 +1,36 @@
+//! # Example `conformance`
+//!
+//! Runs the smoke tests of all implemented APEX services, see
+//! [`a653rs_linux::conformance::smoke`]. The start hook creates the objects
+//! the tests use, and a periodic process runs every test once in NORMAL and
+//! logs its outcome.
+//!
+//! `conformance.yaml` provides the channels of the tests, and ignores the
+//! application error raised by the test of `RAISE_APPLICATION_ERROR`. The
+//! tests run in the partition with the role `tester`, while the one with the
+//! role `feeder` only provides the source of the queuing channel.
+
+use a653rs::prelude::*;
+use a653rs_linux::conformance::smoke;
+use a653rs_linux::partition::{ApexLinuxPartition, ApexLogger};
+
+struct Conformance;
+
+impl Partition<ApexLinuxPartition> for Conformance {
+    fn cold_start(&self, _ctx: &mut StartContext<ApexLinuxPartition>) {
+        if ApexLinuxPartition::role() == Some("tester") {
+            smoke::prepare();
+        }
+    }
+
+    fn warm_start(&self, ctx: &mut StartContext<ApexLinuxPartition>) {
+        self.cold_start(ctx)
+    }
+}
+
+fn main() {
+    ApexLogger::install_panic_hook();
+    ApexLogger::install_logger(log::LevelFilter::Info).unwrap();
+
+    Conformance.run()
+}
@@ -0,0 +1,52 @@
+//! # Example `declared_ports`
+//!
+//! Shows a partition declaring the ports it is going to create. The builder
+//! sends the declaration to the hypervisor before the start hook runs, which
+//! logs every mismatch with the channels of `declared_ports.yaml` right away.
+//! With `strict_ports: true`, a mismatch fails the initialization of the
+//! partition instead, and the partition health monitor decides what becomes
+//! of it.
+//!
+//! The start hook does not rely on the declaration: if the port can not be
+//! created, the periodic process `Sensor` runs without it.
+
+use core::str::FromStr;
+
+use a653rs::bindings::PortDirection;
+use a653rs::prelude::*;
+use a653rs_linux::builder::{PartitionBuilder, ProcessOptions};
+use a653rs_linux::partition::{ApexLogger, PortDecl};
+use log::{info, warn};
+
+const MSG_SIZE: MessageSize = 8;
+
+fn main() {
+    ApexLogger::install_panic_hook();
+    ApexLogger::install_logger(log::LevelFilter::Info).unwrap();
+
+    PartitionBuilder::new()
+        .declare_ports([PortDecl::Sampling {
+            name: "reading".to_string(),
+            dir: PortDirection::Source,
+            msg_size: MSG_SIZE as usize,
+        }])
+        .cold_start(|ctx| {
+            ctx.create_sampling_port_source(Name::from_str("reading").unwrap(), MSG_SIZE)
+                .inspect_err(|e| warn!("Could not create the port reading: {e:?}"))
+                .ok()
+        })
+        .periodic("Sensor", ProcessOptions::default(), |ctx| {
+            let Some(port) = ctx.state() else {
+                info!("Running in NORMAL without the port");
+                loop {
+                    ctx.periodic_wait().unwrap();
+                }
+            };
+            info!("Running in NORMAL with the port");
+            for reading in 0u64.. {
+                port.send(&reading.to_le_bytes()).unwrap();
+                ctx.periodic_wait().unwrap();
+            }
+        })
+        .run()
+}
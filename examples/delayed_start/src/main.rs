@@ -0,0 +1,66 @@
+//! # Example `delayed_start`
+//!
+//! Shows the periodic process `Delayed`, which is started with DELAYED_START.
+//! Its delay in milliseconds is the role of the partition, so that partitions
+//! sharing the image are released at different offsets into their windows.
+//! The process logs its release in every period, at about the start of the
+//! window of the partition plus the delay.
+
+use core::str::FromStr;
+use core::time::Duration;
+
+use a653rs::bindings::{ApexProcessP1, ApexSystemTime};
+use a653rs::prelude::*;
+use a653rs_linux::partition::{ApexLinuxPartition, ApexLogger};
+use log::{error, info};
+
+fn main() {
+    ApexLogger::install_panic_hook();
+    ApexLogger::install_logger(log::LevelFilter::Info).unwrap();
+
+    DelayedPartition.run()
+}
+
+type Hypervisor = ApexLinuxPartition;
+
+pub struct DelayedPartition;
+
+/// Delay of the periodic process, as configured by the role of the partition
+fn delay() -> Duration {
+    let millis = Hypervisor::role().and_then(|role| role.parse().ok());
+    Duration::from_millis(millis.unwrap_or(0))
+}
+
+impl a653rs::prelude::Partition<Hypervisor> for DelayedPartition {
+    fn cold_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        let process_attributes = ProcessAttribute {
+            period: SystemTime::Normal(Duration::ZERO),
+            time_capacity: SystemTime::Infinite,
+            entry_point: delayed,
+            stack_size: 100_000,
+            base_priority: 1,
+            deadline: Deadline::Soft,
+            name: Name::from_str("Delayed").unwrap(),
+        };
+        ctx.create_process(process_attributes).unwrap();
+
+        let id =
+            Hypervisor::get_process_id(Name::from_str("Delayed").unwrap().into_inner()).unwrap();
+        let delay = delay();
+        if let Err(e) = Hypervisor::delayed_start(id, delay.as_nanos() as ApexSystemTime) {
+            error!("failed to start the process with a delay of {delay:?}: {e:?}");
+        }
+    }
+
+    fn warm_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        self.cold_start(ctx)
+    }
+}
+
+extern "C" fn delayed() {
+    let delay = delay();
+    for period in 1.. {
+        info!("Released after {}ms in period {period}", delay.as_millis());
+        Hypervisor::periodic_wait().unwrap();
+    }
+}
@@ -0,0 +1,109 @@
+//! # Example `event`
+//!
+//! Shows a periodic process of a partition releasing an aperiodic one with an
+//! event. The periodic process `Ticker` logs the number of processes waiting
+//! for the event and then sets it in every period. The aperiodic process
+//! `Listener` waits for the event, which spans the windows of the partition,
+//! and resets it again after every wakeup, so that it sleeps until the next
+//! period of the ticker.
+
+use core::str::FromStr;
+use core::time::Duration;
+
+use a653rs::bindings::{ApexEventP1, ApexName, ApexSystemTime, EventId, EventState};
+use a653rs::prelude::*;
+use a653rs_linux::partition::{ApexLinuxPartition, ApexLogger};
+use log::{error, info, warn};
+
+const EVENT: &str = "Tick";
+
+fn main() {
+    ApexLogger::install_panic_hook();
+    ApexLogger::install_logger(log::LevelFilter::Info).unwrap();
+
+    EventPartition.run()
+}
+
+type Hypervisor = ApexLinuxPartition;
+
+pub struct EventPartition;
+
+impl a653rs::prelude::Partition<Hypervisor> for EventPartition {
+    fn cold_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        Hypervisor::create_event(name()).unwrap();
+
+        for (name, entry_point, period) in [
+            (
+                "Ticker",
+                ticker as extern "C" fn(),
+                SystemTime::Normal(Duration::ZERO),
+            ),
+            (
+                "Listener",
+                listener as extern "C" fn(),
+                SystemTime::Infinite,
+            ),
+        ] {
+            let process_attributes = ProcessAttribute {
+                period,
+                time_capacity: SystemTime::Infinite,
+                entry_point,
+                stack_size: 100_000,
+                base_priority: 1,
+                deadline: Deadline::Soft,
+                name: Name::from_str(name).unwrap(),
+            };
+            let process_handle = ctx.create_process(process_attributes).unwrap();
+            process_handle.start().unwrap();
+        }
+    }
+
+    fn warm_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        self.cold_start(ctx)
+    }
+}
+
+fn name() -> ApexName {
+    Name::from_str(EVENT).unwrap().into_inner()
+}
+
+/// The event created by the main process
+fn event() -> EventId {
+    Hypervisor::get_event_id(name()).unwrap()
+}
+
+extern "C" fn ticker() {
+    let id = event();
+    for period in 1.. {
+        match Hypervisor::get_event_status(id) {
+            Ok(status) => info!(
+                "Ticker sees waiting processes: {}",
+                status.waiting_processes
+            ),
+            Err(e) => error!("failed to get the status of the event: {e:?}"),
+        }
+        // Logged before, as the listener wakes up at once
+        info!("Ticker sets the event in period {period}");
+        Hypervisor::set_event(id).unwrap();
+        Hypervisor::periodic_wait().unwrap();
+    }
+}
+
+extern "C" fn listener() {
+    let id = event();
+    // Longer than the period of the ticker
+    let timeout = Duration::from_secs(2).as_nanos() as ApexSystemTime;
+    loop {
+        if let Err(e) = Hypervisor::wait_event(id, timeout) {
+            warn!("Listener did not see the event: {e:?}");
+            continue;
+        }
+        info!("Listener woke up");
+        Hypervisor::reset_event(id).unwrap();
+        match Hypervisor::get_event_status(id) {
+            Ok(status) if matches!(status.event_state, EventState::Down) => {}
+            Ok(_) => error!("the event is still up after resetting it"),
+            Err(e) => error!("failed to get the status of the event: {e:?}"),
+        }
+    }
+}
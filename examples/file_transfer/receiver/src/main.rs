@@ -0,0 +1,76 @@
+//! # Example `file_transfer_receiver`
+//!
+//! Receives a file from the `file_transfer_sender` partition and writes it to
+//! `/data/received.bin`, which is mounted from the host by the configuration.
+//! The receiver logs the length and a hash of the file, so that it can be
+//! compared with the one sent.
+
+use core::str::FromStr;
+use core::time::Duration;
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::Hasher;
+
+use a653rs::bindings::QueuingPortId;
+use a653rs::prelude::*;
+use a653rs_linux::chunked;
+use a653rs_linux::partition::{ApexLinuxPartition, ApexLogger};
+use log::{error, info};
+use once_cell::sync::OnceCell;
+
+fn main() {
+    ApexLogger::install_panic_hook();
+    ApexLogger::install_logger(log::LevelFilter::Info).unwrap();
+
+    FileTransferReceiver.run()
+}
+
+type Hypervisor = ApexLinuxPartition;
+
+/// Where the received file is written to
+const FILE: &str = "/data/received.bin";
+
+/// Name of the queuing destination port
+const PORT: &str = "file_in";
+
+static PORT_ID: OnceCell<QueuingPortId> = OnceCell::new();
+
+pub struct FileTransferReceiver;
+
+impl a653rs::prelude::Partition<Hypervisor> for FileTransferReceiver {
+    fn cold_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        // There are no sampling ports, so their refresh period does not matter
+        let ports = Hypervisor::create_configured_ports(Duration::from_secs(1)).unwrap();
+        PORT_ID.set(ports.queuing[PORT].0).unwrap();
+
+        let process_attributes = ProcessAttribute {
+            period: SystemTime::Infinite,
+            time_capacity: SystemTime::Infinite,
+            entry_point: receive,
+            stack_size: 100_000,
+            base_priority: 1,
+            deadline: Deadline::Soft,
+            name: Name::from_str("receive").unwrap(),
+        };
+        let process_handle = ctx.create_process(process_attributes).unwrap();
+        process_handle.start().unwrap();
+    }
+
+    fn warm_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        self.cold_start(ctx)
+    }
+}
+
+extern "C" fn receive() {
+    match chunked::recv_file(*PORT_ID.get().unwrap(), FILE, SystemTime::Infinite) {
+        Ok(len) => {
+            let mut hasher = DefaultHasher::new();
+            hasher.write(&fs::read(FILE).unwrap());
+            info!(
+                "received {FILE} of {len} bytes with hash {:016x}",
+                hasher.finish()
+            );
+        }
+        Err(e) => error!("failed to receive {FILE}: {e}"),
+    }
+}
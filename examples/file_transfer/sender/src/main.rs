@@ -0,0 +1,102 @@
+//! # Example `file_transfer_sender`
+//!
+//! Sends `/data/file.bin` once to the `file_transfer_receiver` partition,
+//! using the chunked transfer of the `extensions` feature of `a653rs-linux`.
+//! If the file does not exist, a megabyte of pseudo-random bytes is written
+//! to it first. `/data` is mounted from the host by the configuration, as the
+//! file would not fit into the tmpfs of the partition.
+
+use core::str::FromStr;
+use core::time::Duration;
+use std::fs;
+use std::path::Path;
+
+use a653rs::bindings::QueuingPortId;
+use a653rs::prelude::*;
+use a653rs_linux::chunked::{self, HEADER_LEN};
+use a653rs_linux::partition::{ApexLinuxPartition, ApexLogger};
+use log::{error, info};
+use once_cell::sync::OnceCell;
+
+fn main() {
+    ApexLogger::install_panic_hook();
+    ApexLogger::install_logger(log::LevelFilter::Info).unwrap();
+
+    FileTransferSender.run()
+}
+
+type Hypervisor = ApexLinuxPartition;
+
+/// The file to send
+const FILE: &str = "/data/file.bin";
+
+/// Length of the file written if there is none
+const GENERATED_LEN: usize = 1 << 20;
+
+/// Name of the queuing source port
+const PORT: &str = "file_out";
+
+static PORT_ID: OnceCell<QueuingPortId> = OnceCell::new();
+
+pub struct FileTransferSender;
+
+impl a653rs::prelude::Partition<Hypervisor> for FileTransferSender {
+    fn cold_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        // There are no sampling ports, so their refresh period does not matter
+        let ports = Hypervisor::create_configured_ports(Duration::from_secs(1)).unwrap();
+        PORT_ID.set(ports.queuing[PORT].0).unwrap();
+
+        let process_attributes = ProcessAttribute {
+            period: SystemTime::Infinite,
+            time_capacity: SystemTime::Infinite,
+            entry_point: send,
+            stack_size: 100_000,
+            base_priority: 1,
+            deadline: Deadline::Soft,
+            name: Name::from_str("send").unwrap(),
+        };
+        let process_handle = ctx.create_process(process_attributes).unwrap();
+        process_handle.start().unwrap();
+    }
+
+    fn warm_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        self.cold_start(ctx)
+    }
+}
+
+/// Pseudo-random bytes, the same in every run
+fn pseudo_random(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_u32;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+extern "C" fn send() {
+    if !Path::new(FILE).exists() {
+        fs::write(FILE, pseudo_random(GENERATED_LEN)).unwrap();
+    }
+
+    // Every chunk fills a whole message of the port
+    let msg_size = Hypervisor::queuing_port_configs()
+        .iter()
+        .find(|port| port.name == PORT)
+        .unwrap()
+        .msg_size;
+    // Blocks whenever the queue is full, until the hypervisor transferred the
+    // chunks after the window of this partition
+    match chunked::send_file(
+        *PORT_ID.get().unwrap(),
+        FILE,
+        msg_size - HEADER_LEN,
+        SystemTime::Infinite,
+    ) {
+        Ok(len) => info!("sent {FILE} of {len} bytes"),
+        Err(e) => error!("failed to send {FILE}: {e}"),
+    }
+}
@@ -0,0 +1,84 @@
+//! # Example `fork`
+//!
+//! Shows that a process forked by a partition may not use the services of the
+//! partition. The periodic process `Parent` forks once, and the child tries to
+//! report an application message, which is refused with `InvalidMode` instead
+//! of being sent to the hypervisor. Services only reading the status of the
+//! partition, like `GET_TIME`, remain available to the child. The parent logs
+//! the outcome reported by the exit code of the child.
+//!
+//! Processes using the services of the partition are started with
+//! `ApexLinuxPartition::spawn_helper` instead, see the `helper_process`
+//! example.
+
+use core::str::FromStr;
+use core::time::Duration;
+
+use a653rs::bindings::{ApexErrorP4, ApexTimeP4, ErrorReturnCode};
+use a653rs::prelude::*;
+use a653rs_linux::partition::{ApexLinuxPartition, ApexLogger};
+use log::{error, info};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{fork, ForkResult};
+
+/// Exit code of the child if its message was refused
+const REFUSED: i32 = 0;
+
+fn main() {
+    ApexLogger::install_panic_hook();
+    ApexLogger::install_logger(log::LevelFilter::Info).unwrap();
+
+    ForkPartition.run()
+}
+
+type Hypervisor = ApexLinuxPartition;
+
+pub struct ForkPartition;
+
+impl a653rs::prelude::Partition<Hypervisor> for ForkPartition {
+    fn cold_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        let process_attributes = ProcessAttribute {
+            period: SystemTime::Normal(Duration::ZERO),
+            time_capacity: SystemTime::Infinite,
+            entry_point: parent,
+            stack_size: 100_000,
+            base_priority: 1,
+            deadline: Deadline::Soft,
+            name: Name::from_str("Parent").unwrap(),
+        };
+        let process_handle = ctx.create_process(process_attributes).unwrap();
+        process_handle.start().unwrap();
+    }
+
+    fn warm_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        self.cold_start(ctx)
+    }
+}
+
+extern "C" fn parent() {
+    match unsafe { fork() } {
+        Ok(ForkResult::Child) => child(),
+        Ok(ForkResult::Parent { child }) => match waitpid(child, None) {
+            Ok(WaitStatus::Exited(_, REFUSED)) => {
+                info!("The forked child was refused the services of the partition")
+            }
+            Ok(status) => error!("The forked child used the services of the partition: {status:?}"),
+            Err(e) => error!("failed to wait for the forked child: {e}"),
+        },
+        Err(e) => error!("failed to fork: {e}"),
+    }
+    loop {
+        <Hypervisor as ApexTimeP4>::periodic_wait().unwrap();
+    }
+}
+
+fn child() -> ! {
+    // Reading the time is still allowed
+    let _ = <Hypervisor as ApexTimeP4>::get_time();
+    let message = b"Message of the forked child";
+    let code = match <Hypervisor as ApexErrorP4>::report_application_message(message) {
+        Err(ErrorReturnCode::InvalidMode) => REFUSED,
+        _ => 1,
+    };
+    unsafe { nix::libc::_exit(code) }
+}
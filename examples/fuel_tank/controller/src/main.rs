@@ -14,15 +14,27 @@ fn main() {
 
 #[partition(a653rs_linux::partition::ApexLinuxPartition)]
 mod hello {
+    use a653rs::bindings::ErrorReturnCode;
     use a653rs::prelude::SystemTime;
+    use a653rs_linux::snapshot::SnapshotReader;
+    use log::*;
 
-    #[sampling_in(name = "fuel_sensors", msg_size = "10KB", refresh_period = "10s")]
-    struct FuelSensors;
+    #[sampling_in(name = "fuel_level", msg_size = "8B", refresh_period = "10s")]
+    struct FuelLevel;
+
+    #[sampling_in(name = "fuel_flow", msg_size = "8B", refresh_period = "10s")]
+    struct FuelFlow;
+
+    #[sampling_in(name = "fuel_tick", msg_size = "8B", refresh_period = "10s")]
+    struct FuelTick;
 
     #[sampling_out(name = "fuel_actuators", msg_size = "10KB")]
     struct FuelActuators;
     #[start(cold)]
     fn cold_start(mut ctx: start::Context) {
+        ctx.create_fuel_level().unwrap();
+        ctx.create_fuel_flow().unwrap();
+        ctx.create_fuel_tick().unwrap();
         ctx.create_periodic().unwrap().start().unwrap();
     }
 
@@ -39,8 +51,27 @@ mod hello {
         deadline = "Soft"
     )]
     fn periodic(ctx: periodic::Context) {
+        // The sensors of the simulation are published as one snapshot
+        let mut sensors = SnapshotReader::new();
+        sensors.add(ctx.fuel_level.unwrap());
+        sensors.add(ctx.fuel_flow.unwrap());
+        sensors.add(ctx.fuel_tick.unwrap());
+        let (mut level, mut flow, mut tick) = ([0; 8], [0; 8], [0; 8]);
+
         loop {
-            // Empty for now
+            let read = sensors.read(&mut [&mut level, &mut flow, &mut tick]);
+            match read {
+                Ok(snapshot) if snapshot.coherent() => debug!(
+                    "Tick {}: {} fuel left, {} taken per tick",
+                    u64::from_le_bytes(tick),
+                    f32::from_le_bytes(level[..4].try_into().unwrap()),
+                    f32::from_le_bytes(flow[..4].try_into().unwrap())
+                ),
+                Ok(snapshot) => warn!("Sensors of different ticks: {:?}", snapshot.seqs),
+                // The simulation did not publish its sensors yet
+                Err(ErrorReturnCode::NoAction) => {}
+                Err(e) => error!("Reading the sensors failed: {e:?}"),
+            }
             ctx.periodic_wait().unwrap();
         }
     }
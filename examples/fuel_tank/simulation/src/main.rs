@@ -13,18 +13,27 @@ fn main() {
 #[partition(a653rs_linux::partition::ApexLinuxPartition)]
 mod hello {
     use a653rs::prelude::SystemTime;
+    use a653rs_linux::snapshot::SnapshotPublisher;
     use log::*;
 
     #[sampling_in(name = "fuel_actuators", msg_size = "10KB", refresh_period = "20ms")]
     struct FuelActuators;
 
-    #[sampling_out(name = "fuel_sensors", msg_size = "10KB")]
-    struct FuelSensors;
+    #[sampling_out(name = "fuel_level", msg_size = "8B")]
+    struct FuelLevel;
+
+    #[sampling_out(name = "fuel_flow", msg_size = "8B")]
+    struct FuelFlow;
+
+    #[sampling_out(name = "fuel_tick", msg_size = "8B")]
+    struct FuelTick;
 
     #[start(cold)]
     fn cold_start(mut ctx: start::Context) {
         ctx.create_fuel_actuators().unwrap();
-        ctx.create_fuel_sensors().unwrap();
+        ctx.create_fuel_level().unwrap();
+        ctx.create_fuel_flow().unwrap();
+        ctx.create_fuel_tick().unwrap();
         ctx.create_periodic().unwrap().start().unwrap();
     }
 
@@ -43,6 +52,23 @@ mod hello {
     fn periodic(ctx: periodic::Context) {
         info!("Start Aperiodic");
 
+        // Ticks of the simulation per partition window
+        const TICKS: usize = 50;
+
+        // Fuel taken from the tank per tick
+        const FLOW: f32 = 0.01;
+
+        // The measurements of a tick are split over several ports, which are published
+        // together, so that the controller never mixes those of different ticks. Only
+        // the measurements of the last tick of a window reach the controller, so they
+        // are copied into the channels once per window.
+        let mut sensors = SnapshotPublisher::new();
+        sensors.add(ctx.fuel_level.unwrap());
+        sensors.add(ctx.fuel_flow.unwrap());
+        sensors.add(ctx.fuel_tick.unwrap());
+        let mut fuel = 1000.0f32;
+        let mut tick = 0u64;
+
         // TODO implement cascading flow, filling one f32 takes from a slice of f32,
         // starting from the right most element in the slice. After consumption move all
         // fuel as far as possible to the left.
@@ -52,13 +78,25 @@ mod hello {
 
             // Step 2: apply control commands
 
-            // Step 3: advance the simulation by one tick
+            for _ in 0..TICKS {
+                // Step 3: advance the simulation by one tick
+                let flow = FLOW.min(fuel);
+                fuel -= flow;
+                tick += 1;
 
-            // Step 4: check for errors
+                // Step 4: check for errors
 
-            // Step 5: take sensor measurements
+                // Step 5: take sensor measurements
+                sensors
+                    .write(&[
+                        &fuel.to_le_bytes(),
+                        &flow.to_le_bytes(),
+                        &tick.to_le_bytes(),
+                    ])
+                    .unwrap();
+            }
 
-            // wait until next slot
+            // wait until next slot, publishing the last measurements
             ctx.periodic_wait().unwrap();
         }
     }
@@ -0,0 +1,42 @@
+//! # Example `guess_game`
+//!
+//! A partition reading guesses of its secret number from stdin, one per line,
+//! and logging whether they are too low, too high or correct. With
+//! `stdin: pipe` in `guess_game.yaml`, the guesses are sent to the control
+//! socket of the hypervisor, encoded as base64:
+//!
+//! ```sh
+//! a653rs-linux-hypervisor --control-socket /tmp/a653rs.sock examples/guess_game/guess_game.yaml
+//! echo "stdin GuessGame $(echo 50 | base64)" | socat - UNIX-SENDTO:/tmp/a653rs.sock
+//! ```
+
+use std::io::BufRead;
+
+use a653rs_linux::builder::{PartitionBuilder, ProcessOptions};
+use a653rs_linux::partition::ApexLogger;
+use log::{info, warn};
+
+/// The number to guess, fixed so that the game can be scripted
+const SECRET: u32 = 42;
+
+fn main() {
+    ApexLogger::install_panic_hook();
+    ApexLogger::install_logger(log::LevelFilter::Info).unwrap();
+
+    PartitionBuilder::new()
+        .cold_start(|_| ())
+        .aperiodic("Player", ProcessOptions::default(), |_| {
+            info!("Guess a number between 0 and 100");
+            for line in std::io::stdin().lock().lines() {
+                let line = line.unwrap();
+                match line.trim().parse::<u32>() {
+                    Ok(guess) if guess < SECRET => info!("{guess} is too low"),
+                    Ok(guess) if guess > SECRET => info!("{guess} is too high"),
+                    Ok(guess) => info!("{guess} is correct"),
+                    Err(_) => warn!("{line:?} is not a number"),
+                }
+            }
+            info!("No more guesses");
+        })
+        .run()
+}
@@ -0,0 +1,47 @@
+//! Logic of the hello partition
+//!
+//! Only depends on the hypervisor independent traits of a653rs-linux, so that
+//! it also builds without the Linux backend:
+//!
+//! ```sh
+//! cargo check -p hello_part --lib --no-default-features
+//! ```
+
+use core::time::Duration;
+
+use a653rs_linux::ext::PartitionRole;
+use serde::{Deserialize, Serialize};
+
+/// Role of a hello partition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Sender,
+    Receiver,
+}
+
+impl Role {
+    /// Role of the partition as configured for hypervisor `H`
+    pub fn of<H: PartitionRole>() -> Option<Self> {
+        match H::role()? {
+            "sender" => Some(Role::Sender),
+            "receiver" => Some(Role::Receiver),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CustomMessage {
+    msg: String,
+    when: Duration,
+}
+
+impl CustomMessage {
+    /// Message sent in the `i`th period of the sender, one every fifth period
+    pub fn nth(i: i32, when: Duration) -> Self {
+        Self {
+            msg: format!("Sampling MSG {}", i / 5),
+            when,
+        }
+    }
+}
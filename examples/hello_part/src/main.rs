@@ -14,10 +14,11 @@ mod hello {
     use core::time::Duration;
     use std::thread::sleep;
 
+    use a653rs_linux::partition::ApexLinuxPartition;
     use a653rs_postcard::prelude::*;
+    use hello_part::{CustomMessage, Role};
     use humantime::format_duration;
     use log::*;
-    use serde::{Deserialize, Serialize};
 
     #[sampling_out(name = "Hello", msg_size = "10KB")]
     struct HelloSource;
@@ -27,13 +28,16 @@ mod hello {
 
     #[start(cold)]
     fn cold_start(mut ctx: start::Context) {
-        // Get the partition ID, and based on that decide whether this becomes the
+        // Get the configured role, and based on that decide whether this becomes the
         // sender or the receiver partition
-        let ident = ctx.get_partition_status().identifier;
-        if ident == 0 {
-            ctx.create_hello_source().unwrap();
-        } else if ident == 1 {
-            ctx.create_hello_destination().unwrap();
+        match Role::of::<ApexLinuxPartition>() {
+            Some(Role::Sender) => {
+                ctx.create_hello_source().unwrap();
+            }
+            Some(Role::Receiver) => {
+                ctx.create_hello_destination().unwrap();
+            }
+            None => {}
         }
 
         // create aperiodic process
@@ -66,12 +70,6 @@ mod hello {
         }
     }
 
-    #[derive(Clone, Debug, Serialize, Deserialize)]
-    pub struct CustomMessage {
-        msg: String,
-        when: Duration,
-    }
-
     #[periodic(
         period = "0ms",
         time_capacity = "Infinite",
@@ -80,7 +78,7 @@ mod hello {
         deadline = "Soft"
     )]
     fn periodic(ctx: periodic::Context) {
-        let ident = ctx.get_partition_status().identifier;
+        let role = Role::of::<ApexLinuxPartition>();
         for i in 1..i32::MAX {
             if let SystemTime::Normal(time) = ctx.get_time() {
                 let round = Duration::from_millis(time.as_millis() as u64);
@@ -89,16 +87,13 @@ mod hello {
             sleep(Duration::from_millis(1));
 
             if i % 5 == 0 {
-                if ident == 0 {
+                if role == Some(Role::Sender) {
                     ctx.hello_source
                         .unwrap()
-                        .send_type(CustomMessage {
-                            msg: format!("Sampling MSG {}", i / 5),
-                            when: ctx.get_time().unwrap_duration(),
-                        })
+                        .send_type(CustomMessage::nth(i, ctx.get_time().unwrap_duration()))
                         .ok()
                         .unwrap();
-                } else if ident == 1 {
+                } else if role == Some(Role::Receiver) {
                     let (valid, data) = ctx
                         .hello_destination
                         .unwrap()
@@ -0,0 +1,125 @@
+//! # Example `helper_process`
+//!
+//! Shows a partition spawning a helper process, which uses a port of the
+//! partition. The image is started with one of two roles:
+//!
+//! - `writer`: its aperiodic process spawns the image itself once more as a
+//!   helper with [ApexLinuxPartition::spawn_helper], which converts a message
+//!   and writes it to the sampling source port `Converted` of the partition
+//! - `reader`: its periodic process logs the messages received on its sampling
+//!   destination port `Converted`
+//!
+//! The helper is the same binary, as it is the only one within the namespace
+//! of the partition.
+
+use core::str::FromStr;
+use core::time::Duration;
+use std::process::Command;
+
+use a653rs::bindings::{ApexSamplingPortP4, ErrorReturnCode, SamplingPortId};
+use a653rs::prelude::*;
+use a653rs_linux::partition::{ApexLinuxPartition, ApexLogger};
+use log::{error, info};
+use once_cell::sync::OnceCell;
+
+/// Argument starting the binary as a helper, followed by the port id
+const HELPER_ARG: &str = "--helper";
+
+/// Name of the sampling port of both roles
+const PORT: &str = "Converted";
+
+/// What the helper writes to the port
+const MESSAGE: &str = "converted by helper";
+
+/// Refresh period of the destination port, unless configured for the channel
+///
+/// The helper writes only once, so its message stays valid for a minute.
+const REFRESH: Duration = Duration::from_secs(60);
+
+fn main() {
+    ApexLogger::install_panic_hook();
+    ApexLogger::install_logger(log::LevelFilter::Info).unwrap();
+
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.get(1).map(String::as_str) == Some(HELPER_ARG) {
+        let port = args.get(2).and_then(|id| id.parse().ok()).unwrap();
+        return helper(port);
+    }
+    HelperPartition.run()
+}
+
+type Hypervisor = ApexLinuxPartition;
+
+static PORT_ID: OnceCell<SamplingPortId> = OnceCell::new();
+
+pub struct HelperPartition;
+
+impl a653rs::prelude::Partition<Hypervisor> for HelperPartition {
+    fn cold_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        let ports = Hypervisor::create_configured_ports(REFRESH).unwrap();
+        PORT_ID.set(ports.sampling[PORT].0).unwrap();
+
+        let (entry_point, period) = match Hypervisor::role() {
+            Some("writer") => (spawn as extern "C" fn(), SystemTime::Infinite),
+            Some("reader") => (read as extern "C" fn(), SystemTime::Normal(Duration::ZERO)),
+            role => panic!("unknown role {role:?}"),
+        };
+        let process_attributes = ProcessAttribute {
+            period,
+            time_capacity: SystemTime::Infinite,
+            entry_point,
+            stack_size: 100_000,
+            base_priority: 1,
+            deadline: Deadline::Soft,
+            name: Name::from_str("main").unwrap(),
+        };
+        let process_handle = ctx.create_process(process_attributes).unwrap();
+        process_handle.start().unwrap();
+    }
+
+    fn warm_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        self.cold_start(ctx)
+    }
+}
+
+/// Spawns the helper once and waits for it
+extern "C" fn spawn() {
+    let mut command = Command::new("/bin");
+    command
+        .arg(HELPER_ARG)
+        .arg(PORT_ID.get().unwrap().to_string());
+    match Hypervisor::spawn_helper(command).and_then(|mut child| child.wait()) {
+        Ok(status) => info!("helper exited with {status}"),
+        Err(e) => error!("failed to run the helper: {e}"),
+    }
+}
+
+/// Logs the messages received from the helper of the writer
+extern "C" fn read() {
+    let mut buf = [0; 64];
+    loop {
+        match unsafe { Hypervisor::read_sampling_message(*PORT_ID.get().unwrap(), &mut buf) } {
+            Ok((validity, len)) => {
+                let msg = String::from_utf8_lossy(&buf[..len as usize]);
+                info!("received {msg:?}, {validity:?}");
+            }
+            Err(ErrorReturnCode::NoAction) => info!("nothing received yet"),
+            Err(e) => error!("failed to read {PORT}: {e:?}"),
+        }
+        Hypervisor::periodic_wait().unwrap();
+    }
+}
+
+/// Entry point of the helper, writing to the sampling port `port` of the
+/// partition which spawned it
+fn helper(port: SamplingPortId) {
+    // Stands in for an actual conversion
+    let converted = MESSAGE.as_bytes();
+    match Hypervisor::write_sampling_message(port, converted) {
+        Ok(()) => info!("helper wrote {} bytes", converted.len()),
+        Err(e) => {
+            error!("helper failed to write {PORT}: {e:?}");
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,296 @@
+//! # Example `mesh_part`
+//!
+//! All partitions of `hypervisor/testdata/large_module.yaml` run this image.
+//! Each period, a partition sends messages following a deterministic pattern
+//! on every source port configured for it, and checks the messages received
+//! on its destination ports against the same pattern. This way, messages
+//! which were lost, reordered or corrupted by the hypervisor are noticed
+//! without any partition knowing the others. The `role` of a partition selects
+//! how many messages it sends:
+//!
+//! - `steady`: one message per port and period
+//! - `burst`: as many messages per queuing port and period as fit into the
+//!   queue, up to [BURST_LEN]
+//! - `sporadic`: one message per port in every third period
+//!
+//! The results are reported as telemetry gauges once per period:
+//!
+//! - `verified_messages`: received messages matching the pattern
+//! - `mismatched_messages`: received messages not matching the pattern
+//! - `silent_ports`: destination ports which did not receive a message yet
+
+use core::str::FromStr;
+use core::time::Duration;
+
+use a653rs::bindings::{
+    ApexQueuingPortP4, ApexSamplingPortP4, PortDirection, QueuingPortId, SamplingPortId,
+};
+use a653rs::prelude::*;
+use a653rs_linux::partition::{ApexLinuxPartition, ApexLogger, ConfiguredPorts};
+use log::{error, info};
+use once_cell::sync::OnceCell;
+
+fn main() {
+    ApexLogger::install_panic_hook();
+    ApexLogger::install_logger(log::LevelFilter::Info).unwrap();
+
+    MeshPartition.run()
+}
+
+type Hypervisor = ApexLinuxPartition;
+
+/// Messages sent per queuing port and period by a partition with the `burst`
+/// role
+const BURST_LEN: usize = 4;
+
+/// Length of the sequence number at the start of every message
+const HEADER_LEN: usize = 8;
+
+/// Refresh period of sampling destinations without a configured one
+const DEFAULT_REFRESH: Duration = Duration::from_secs(1);
+
+static PORTS: OnceCell<ConfiguredPorts> = OnceCell::new();
+
+pub struct MeshPartition;
+
+impl a653rs::prelude::Partition<Hypervisor> for MeshPartition {
+    fn cold_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        let ports = Hypervisor::create_configured_ports(DEFAULT_REFRESH).unwrap();
+        info!(
+            "created {} sampling and {} queuing ports",
+            ports.sampling.len(),
+            ports.queuing.len()
+        );
+        PORTS.set(ports).unwrap();
+
+        let process_attributes = ProcessAttribute {
+            period: 0.into(),
+            time_capacity: SystemTime::Infinite,
+            entry_point: periodic,
+            stack_size: 100_000,
+            base_priority: 1,
+            deadline: Deadline::Soft,
+            name: Name::from_str("periodic").unwrap(),
+        };
+        let process_handle = ctx.create_process(process_attributes).unwrap();
+        process_handle.start().unwrap();
+    }
+
+    fn warm_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        self.cold_start(ctx)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Steady,
+    Burst,
+    Sporadic,
+}
+
+impl Role {
+    fn configured() -> Self {
+        match Hypervisor::role() {
+            Some("burst") => Role::Burst,
+            Some("sporadic") => Role::Sporadic,
+            _ => Role::Steady,
+        }
+    }
+
+    /// Number of messages sent per port in the `period`th period
+    fn messages(&self, period: u64, queuing: bool) -> usize {
+        match self {
+            Role::Steady => 1,
+            Role::Burst if queuing => BURST_LEN,
+            Role::Burst => 1,
+            Role::Sporadic => usize::from(period.is_multiple_of(3)),
+        }
+    }
+}
+
+/// A port of the partition along with the sequence number of the next
+/// message sent on it, or of the last message received on it
+struct Port<Id> {
+    name: &'static str,
+    id: Id,
+    msg_size: usize,
+    seq: Option<u64>,
+}
+
+/// Length of the `seq`th message on a port for messages of up to `msg_size`
+/// bytes, so that all lengths are used
+fn message_len(seq: u64, msg_size: usize) -> usize {
+    HEADER_LEN + (seq % (msg_size - HEADER_LEN + 1) as u64) as usize
+}
+
+fn pattern(seq: u64, i: usize) -> u8 {
+    seq.wrapping_mul(31).wrapping_add(i as u64) as u8
+}
+
+/// Writes the `seq`th message of a port to `buf`, returning its length
+fn encode(seq: u64, msg_size: usize, buf: &mut [u8]) -> usize {
+    let len = message_len(seq, msg_size);
+    buf[..HEADER_LEN].copy_from_slice(&seq.to_le_bytes());
+    for (i, b) in buf[HEADER_LEN..len].iter_mut().enumerate() {
+        *b = pattern(seq, i);
+    }
+    len
+}
+
+/// Checks a received message against the pattern, returning its sequence
+/// number
+fn decode(msg: &[u8], msg_size: usize) -> Result<u64, String> {
+    let header = msg
+        .get(..HEADER_LEN)
+        .ok_or_else(|| format!("message of {} bytes is too short", msg.len()))?;
+    let seq = u64::from_le_bytes(header.try_into().unwrap());
+    let expected = message_len(seq, msg_size);
+    if msg.len() != expected {
+        return Err(format!(
+            "message {seq} has {} instead of {expected} bytes",
+            msg.len()
+        ));
+    }
+    match msg[HEADER_LEN..]
+        .iter()
+        .enumerate()
+        .find(|(i, b)| **b != pattern(seq, *i))
+    {
+        Some((i, _)) => Err(format!("message {seq} differs at byte {}", HEADER_LEN + i)),
+        None => Ok(seq),
+    }
+}
+
+#[derive(Default)]
+struct Results {
+    verified: u64,
+    mismatched: u64,
+}
+
+impl Results {
+    fn check(&mut self, port: &str, result: Result<(), String>) {
+        match result {
+            Ok(()) => self.verified += 1,
+            Err(e) => {
+                error!("{port}: {e}");
+                self.mismatched += 1;
+            }
+        }
+    }
+}
+
+fn ports<Id: Copy>(
+    created: &std::collections::HashMap<String, (Id, PortDirection)>,
+    configs: impl Iterator<Item = (&'static str, usize)>,
+    source: bool,
+) -> Vec<Port<Id>> {
+    configs
+        .filter_map(|(name, msg_size)| {
+            let (id, dir) = created.get(name)?;
+            ((*dir == PortDirection::Source) == source).then_some(Port {
+                name,
+                id: *id,
+                msg_size,
+                seq: source.then_some(0),
+            })
+        })
+        .collect()
+}
+
+extern "C" fn periodic() {
+    let role = Role::configured();
+    let created = PORTS.get().unwrap();
+    let sampling = || {
+        Hypervisor::sampling_port_configs()
+            .iter()
+            .map(|p| (p.name.as_str(), p.msg_size))
+    };
+    let queuing = || {
+        Hypervisor::queuing_port_configs()
+            .iter()
+            .map(|p| (p.name.as_str(), p.msg_size))
+    };
+    let mut sampling_sources: Vec<Port<SamplingPortId>> =
+        ports(&created.sampling, sampling(), true);
+    let mut sampling_destinations: Vec<Port<SamplingPortId>> =
+        ports(&created.sampling, sampling(), false);
+    let mut queuing_sources: Vec<Port<QueuingPortId>> = ports(&created.queuing, queuing(), true);
+    let mut queuing_destinations: Vec<Port<QueuingPortId>> =
+        ports(&created.queuing, queuing(), false);
+
+    let max_msg_size = sampling()
+        .chain(queuing())
+        .map(|(_, msg_size)| msg_size)
+        .max()
+        .unwrap_or(HEADER_LEN);
+    let mut buf = vec![0; max_msg_size];
+    let mut results = Results::default();
+
+    for period in 0.. {
+        for port in &mut sampling_sources {
+            for _ in 0..role.messages(period, false) {
+                let seq = port.seq.unwrap();
+                let len = encode(seq, port.msg_size, &mut buf);
+                Hypervisor::write_sampling_message(port.id, &buf[..len]).unwrap();
+                port.seq = Some(seq + 1);
+            }
+        }
+        for port in &mut queuing_sources {
+            for _ in 0..role.messages(period, true) {
+                let seq = port.seq.unwrap();
+                let len = encode(seq, port.msg_size, &mut buf);
+                // A full queue is not an error, the message is sent later
+                if Hypervisor::send_queuing_message(port.id, &buf[..len], 0).is_err() {
+                    break;
+                }
+                port.seq = Some(seq + 1);
+            }
+        }
+
+        for port in &mut sampling_destinations {
+            let buf = &mut buf[..port.msg_size];
+            // Fails with NoAction as long as nothing was written
+            let Ok((_, len)) = (unsafe { Hypervisor::read_sampling_message(port.id, buf) }) else {
+                continue;
+            };
+            let result = decode(&buf[..len as usize], port.msg_size).and_then(|seq| {
+                // Messages may be read more than once, but never go back
+                match port.seq.replace(seq) {
+                    Some(last) if seq < last => Err(format!("message {seq} follows {last}")),
+                    _ => Ok(()),
+                }
+            });
+            results.check(port.name, result);
+        }
+        for port in &mut queuing_destinations {
+            let buf = &mut buf[..port.msg_size];
+            while let Ok((len, _)) = unsafe { Hypervisor::receive_queuing_message(port.id, 0, buf) }
+            {
+                let result = decode(&buf[..len as usize], port.msg_size).and_then(|seq| {
+                    // Sources only advance the sequence number once a message was sent
+                    match port.seq.replace(seq) {
+                        Some(last) if seq != last + 1 => {
+                            Err(format!("message {seq} follows {last}"))
+                        }
+                        _ => Ok(()),
+                    }
+                });
+                results.check(port.name, result);
+            }
+        }
+
+        let silent = sampling_destinations
+            .iter()
+            .filter(|p| p.seq.is_none())
+            .count()
+            + queuing_destinations
+                .iter()
+                .filter(|p| p.seq.is_none())
+                .count();
+        Hypervisor::telemetry("verified_messages", results.verified as f64).ok();
+        Hypervisor::telemetry("mismatched_messages", results.mismatched as f64).ok();
+        Hypervisor::telemetry("silent_ports", silent as f64).ok();
+
+        Hypervisor::periodic_wait().unwrap();
+    }
+}
@@ -0,0 +1,158 @@
+//! # Example `mutex`
+//!
+//! Shows two processes of a partition sharing a mutex. The periodic process
+//! `Ticker` acquires the mutex in every odd period and holds it across its
+//! periodic wait. In the next period it acquires the mutex a second time,
+//! logs its lock count and releases it twice. The aperiodic process `Worker`
+//! acquires the mutex whenever it can. While the ticker holds it across the
+//! windows of the partition, the worker first times out and then waits until
+//! the mutex is released.
+//!
+//! Both processes record themselves as the owner of the mutex while owning it,
+//! and log an error should they ever find the other one there.
+
+use core::str::FromStr;
+use core::sync::atomic::{AtomicU8, Ordering};
+use core::time::Duration;
+
+use a653rs::bindings::{
+    ApexMutexP1, ApexName, ApexSystemTime, ErrorReturnCode, MutexId, MutexState, QueuingDiscipline,
+};
+use a653rs::prelude::*;
+use a653rs_linux::partition::{ApexLinuxPartition, ApexLogger};
+use log::{error, info, warn};
+
+const MUTEX: &str = "Shared";
+/// Priority of the mutex, which may not be below that of its owners
+const MUTEX_PRIORITY: i32 = 10;
+
+/// Process owning the mutex, if any
+static OWNER: AtomicU8 = AtomicU8::new(NOBODY);
+const NOBODY: u8 = 0;
+const TICKER: u8 = 1;
+const WORKER: u8 = 2;
+
+fn main() {
+    ApexLogger::install_panic_hook();
+    ApexLogger::install_logger(log::LevelFilter::Info).unwrap();
+
+    MutexPartition.run()
+}
+
+type Hypervisor = ApexLinuxPartition;
+
+pub struct MutexPartition;
+
+impl a653rs::prelude::Partition<Hypervisor> for MutexPartition {
+    fn cold_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        Hypervisor::create_mutex(name(), MUTEX_PRIORITY, QueuingDiscipline::Fifo).unwrap();
+
+        for (name, entry_point, period) in [
+            (
+                "Ticker",
+                ticker as extern "C" fn(),
+                SystemTime::Normal(Duration::ZERO),
+            ),
+            ("Worker", worker as extern "C" fn(), SystemTime::Infinite),
+        ] {
+            let process_attributes = ProcessAttribute {
+                period,
+                time_capacity: SystemTime::Infinite,
+                entry_point,
+                stack_size: 100_000,
+                base_priority: 1,
+                deadline: Deadline::Soft,
+                name: Name::from_str(name).unwrap(),
+            };
+            let process_handle = ctx.create_process(process_attributes).unwrap();
+            process_handle.start().unwrap();
+        }
+    }
+
+    fn warm_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        self.cold_start(ctx)
+    }
+}
+
+fn name() -> ApexName {
+    Name::from_str(MUTEX).unwrap().into_inner()
+}
+
+/// The mutex created by the main process
+fn mutex() -> MutexId {
+    Hypervisor::get_mutex_id(name()).unwrap()
+}
+
+fn timeout(duration: Duration) -> ApexSystemTime {
+    duration.as_nanos() as ApexSystemTime
+}
+
+/// Records `process` as the owner of the mutex
+fn enter(process: u8) {
+    let owner = OWNER.swap(process, Ordering::SeqCst);
+    if owner != NOBODY {
+        error!("process {process} acquired the mutex owned by process {owner}");
+    }
+}
+
+fn leave() {
+    OWNER.store(NOBODY, Ordering::SeqCst);
+}
+
+extern "C" fn ticker() {
+    let id = mutex();
+    for period in 1.. {
+        if period % 2 == 1 {
+            // Infinite timeout
+            Hypervisor::acquire_mutex(id, -1).unwrap();
+            enter(TICKER);
+            info!("Ticker acquired the mutex in period {period}");
+        } else {
+            // Acquired again by its owner, which only counts
+            Hypervisor::acquire_mutex(id, 0).unwrap();
+            match Hypervisor::get_mutex_status(id) {
+                Ok(status) => info!(
+                    "Ticker holds the mutex {} times, waiting processes: {}",
+                    status.lock_count, status.waiting_processes
+                ),
+                Err(e) => error!("failed to get the status of the mutex: {e:?}"),
+            }
+            leave();
+            Hypervisor::release_mutex(id).unwrap();
+            Hypervisor::release_mutex(id).unwrap();
+            info!("Ticker released the mutex in period {period}");
+        }
+        Hypervisor::periodic_wait().unwrap();
+    }
+}
+
+extern "C" fn worker() {
+    let id = mutex();
+    loop {
+        match Hypervisor::acquire_mutex(id, timeout(Duration::from_millis(50))) {
+            Ok(()) => {}
+            Err(ErrorReturnCode::TimedOut) => {
+                info!("Worker timed out acquiring the mutex");
+                if let Err(e) = Hypervisor::acquire_mutex(id, timeout(Duration::from_secs(2))) {
+                    warn!("Worker did not acquire the mutex: {e:?}");
+                    continue;
+                }
+            }
+            Err(e) => {
+                warn!("Worker did not acquire the mutex: {e:?}");
+                continue;
+            }
+        }
+        enter(WORKER);
+        info!("Worker acquired the mutex");
+        match Hypervisor::get_mutex_status(id) {
+            Ok(status) if matches!(status.mutex_state, MutexState::Owned) => {}
+            Ok(_) => error!("the mutex is available while the worker owns it"),
+            Err(e) => error!("failed to get the status of the mutex: {e:?}"),
+        }
+        leave();
+        Hypervisor::release_mutex(id).unwrap();
+        // Leave the ticker a chance at the start of its period
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
@@ -0,0 +1,69 @@
+//! # Example `partition_fs`
+//!
+//! Shows the files of a partition outliving it. The periodic process `Writer`
+//! appends a line to `/data/log.txt` in every period, which the hypervisor
+//! syncs to the `host_dir` of the `partition_fs` of the partition. The count
+//! continues from the lines already in the file, which the hypervisor restores
+//! on every start of the partition.
+
+use core::str::FromStr;
+use core::time::Duration;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+use a653rs::prelude::*;
+use a653rs_linux::partition::{ApexLinuxPartition, ApexLogger};
+use log::{error, info};
+
+const LOG: &str = "/data/log.txt";
+
+fn main() {
+    ApexLogger::install_panic_hook();
+    ApexLogger::install_logger(log::LevelFilter::Info).unwrap();
+
+    LogbookPartition.run()
+}
+
+type Hypervisor = ApexLinuxPartition;
+
+pub struct LogbookPartition;
+
+impl a653rs::prelude::Partition<Hypervisor> for LogbookPartition {
+    fn cold_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        let process_attributes = ProcessAttribute {
+            period: SystemTime::Normal(Duration::ZERO),
+            time_capacity: SystemTime::Infinite,
+            entry_point: writer,
+            stack_size: 100_000,
+            base_priority: 1,
+            deadline: Deadline::Soft,
+            name: Name::from_str("Writer").unwrap(),
+        };
+        let process_handle = ctx.create_process(process_attributes).unwrap();
+        process_handle.start().unwrap();
+    }
+
+    fn warm_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        self.cold_start(ctx)
+    }
+}
+
+extern "C" fn writer() {
+    let restored = fs::read_to_string(LOG)
+        .map(|log| log.lines().count())
+        .unwrap_or(0);
+    info!("Restored {restored} lines");
+
+    for period in restored + 1.. {
+        let res = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(LOG)
+            .and_then(|mut log| writeln!(log, "period {period}"));
+        match res {
+            Ok(()) => info!("Appended period {period}"),
+            Err(e) => error!("failed to append to {LOG}: {e}"),
+        }
+        Hypervisor::periodic_wait().unwrap();
+    }
+}
@@ -38,11 +38,17 @@ mod ping_queue_client {
         ctx.create_ping_request().unwrap();
         ctx.create_ping_response().unwrap();
 
-        // create and start a periodic process
+        // create and start a periodic process sending requests
         ctx.create_periodic_ping_queue_client()
             .unwrap()
             .start()
             .unwrap();
+
+        // and an aperiodic process waiting for the responses
+        ctx.create_aperiodic_ping_queue_receiver()
+            .unwrap()
+            .start()
+            .unwrap();
     }
 
     // do the same as a cold_start
@@ -90,23 +96,50 @@ mod ping_queue_client {
             };
             info!("Sending request took {:?}", time_after_send - time);
 
-            // then receive a response, if any:
+            // the aperiodic process spends most of its time blocked on the response port,
+            // which is reported by the status of the port
+            let status = ctx.ping_response.unwrap().status();
+            info!(
+                "{} process(es) waiting for a response",
+                status.waiting_processes
+            );
+
+            // wait until the beginning of this partitions next MiF. In scheduling terms
+            // this function would probably be called `yield()`.
+            ctx.periodic_wait().unwrap();
+        }
+    }
 
+    // this process blocks until a response arrives, which happens once the
+    // hypervisor transferred it at the end of a partition window of the server
+    #[aperiodic(
+        time_capacity = "Infinite",
+        stack_size = "8KB",
+        base_priority = 1,
+        deadline = "Soft"
+    )]
+    fn aperiodic_ping_queue_receiver(ctx: aperiodic_ping_queue_receiver::Context) {
+        info!("started aperiodic_ping_queue_receiver process");
+
+        loop {
             // allocate a buffer on the stack for receival of the response
             let mut buf = [0u8; 32];
 
-            // sample the ping_response sampling port into `buf`
-            // - validity indicates whether data received was sitting in the samplin port
-            //   for no more than the refresh_period
+            // receive a response from the ping_response queuing port into `buf`, blocking
+            // until there is one
             // - `bytes` is a subslice of `buf`, containing only the bytes actually read
-            //   from the sampling port
-
+            //   from the queuing port
             match ctx
                 .ping_response
                 .unwrap()
-                .receive(&mut buf, SystemTime::Normal(Duration::from_secs(10)))
+                .receive(&mut buf, SystemTime::Infinite)
             {
                 Ok((bytes, false)) => {
+                    let SystemTime::Normal(time) = ctx.get_time() else {
+                        panic!("could not read time");
+                    };
+                    let time_in_nanoseconds = time.as_nanos();
+
                     // deserialize the bytes into an u128
                     let request_timestamp = u128::from_le_bytes(bytes[0..16].try_into().unwrap());
                     let response_timestamp = u128::from_le_bytes(bytes[16..32].try_into().unwrap());
@@ -124,13 +157,9 @@ mod ping_queue_client {
                     // and log the results!
                     info!("Received valid response: RTT={round_trip:?}  client-to-server={to_server:?}  server-to-client={from_server:?}");
                 }
-                Err(Error::NotAvailable) => warn!("Failed to receive ping response"),
+                Err(Error::TimedOut) => warn!("Failed to receive ping response"),
                 other => panic!("Failed to receive ping response: {:?}", other),
             };
-
-            // wait until the beginning of this partitions next MiF. In scheduling terms
-            // this function would probably be called `yield()`.
-            ctx.periodic_wait().unwrap();
         }
     }
 }
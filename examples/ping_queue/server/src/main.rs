@@ -12,6 +12,9 @@ fn main() {
 
 #[partition(a653rs_linux::partition::ApexLinuxPartition)]
 mod ping_queue_server {
+    use core::time::Duration;
+
+    use a653rs_linux::partition::ApexLinuxPartition;
     use log::{info, warn};
 
     #[queuing_in(
@@ -36,8 +39,8 @@ mod ping_queue_server {
         ctx.create_ping_request().unwrap();
         ctx.create_ping_response().unwrap();
 
-        // create and start a periodic process
-        ctx.create_periodic_ping_queue_server()
+        // create and start an aperiodic process
+        ctx.create_aperiodic_ping_queue_server()
             .unwrap()
             .start()
             .unwrap();
@@ -49,54 +52,77 @@ mod ping_queue_server {
         cold_start(ctx);
     }
 
-    // the server process is super simple; all it does is receive a request and
-    // respond to it
-    #[periodic(
-        period = "0ms",
+    // the server process is super simple; it sleeps until requests arrive and
+    // responds to each of them
+    #[aperiodic(
         time_capacity = "Infinite",
         stack_size = "8KB",
         base_priority = 1,
         deadline = "Soft"
     )]
-    fn periodic_ping_queue_server(ctx: periodic_ping_queue_server::Context) {
+    fn aperiodic_ping_queue_server(ctx: aperiodic_ping_queue_server::Context) {
         info!("started ping_queue_server process");
         loop {
-            // allocate a buffer to receive into
-            let mut buf = [0u8; 32];
-
-            // receive a request into `&mut buf`, and save the slice of actual received data
-            // as `bytes`
-            match ctx
-                .ping_request
-                .unwrap()
-                .receive(&mut buf, SystemTime::Infinite)
-            {
-                Ok((_bytes, false)) => {
-                    // `ctx.get_time()` returns a [SystemTime], which might be `Infinite`, or just a
-                    // normal time. Thus we have to check that indeed a normal time was returned.
-                    let SystemTime::Normal(time) = ctx.get_time() else {
-                        panic!("could not read time");
-                    };
-
-                    // convert current time to bytes and store in upper 16 bytes of request
-                    let time_in_nanoseconds = time.as_nanos();
-                    buf[16..32].copy_from_slice(&time_in_nanoseconds.to_le_bytes());
-
-                    info!("Forwarding request with timestamp as response");
-
-                    // send the contents of `buf` back as response
-                    match ctx.ping_response.unwrap().send(&buf, SystemTime::Infinite) {
-                        Ok(_) => {}
-                        Err(Error::NotAvailable) => warn!("Failed to send ping response"),
-                        Err(other) => panic!("Failed to send ping response: {:?}", other),
-                    }
+            // instead of polling the request port, sleep until the hypervisor delivered new
+            // messages to one of our queuing ports
+            match ApexLinuxPartition::wait_for_port_activity(SystemTime::Infinite) {
+                Ok(activity) if activity.queuing => {}
+                Ok(_) => continue,
+                Err(e) => {
+                    warn!("Failed to wait for port activity: {e:?}");
+                    continue;
                 }
-                Err(Error::NotAvailable) => warn!("Failed to receive ping request"),
-                other => panic!("Failed to receive ping request: {:?}", other),
             }
 
-            // wait until the next partition window / MiF
-            ctx.periodic_wait().unwrap();
+            let SystemTime::Normal(woken_at) = ctx.get_time() else {
+                panic!("could not read time");
+            };
+
+            // answer all pending requests without blocking
+            loop {
+                // allocate a buffer to receive into
+                let mut buf = [0u8; 32];
+
+                match ctx
+                    .ping_request
+                    .unwrap()
+                    .receive(&mut buf, SystemTime::Normal(Duration::ZERO))
+                {
+                    Ok((_bytes, false)) => {
+                        // `ctx.get_time()` returns a [SystemTime], which might be `Infinite`, or
+                        // just a normal time. Thus we have to check that indeed a normal time
+                        // was returned.
+                        let SystemTime::Normal(time) = ctx.get_time() else {
+                            panic!("could not read time");
+                        };
+
+                        // the client stored its send time in the lower 16 bytes of the request
+                        let request_timestamp = u128::from_le_bytes(buf[0..16].try_into().unwrap());
+                        let since_request =
+                            Duration::from_nanos((time.as_nanos() - request_timestamp) as u64);
+                        info!(
+                            "Handling request {since_request:?} after it was sent, {:?} after wakeup",
+                            time - woken_at
+                        );
+
+                        // convert current time to bytes and store in upper 16 bytes of request
+                        let time_in_nanoseconds = time.as_nanos();
+                        buf[16..32].copy_from_slice(&time_in_nanoseconds.to_le_bytes());
+
+                        info!("Forwarding request with timestamp as response");
+
+                        // send the contents of `buf` back as response
+                        match ctx.ping_response.unwrap().send(&buf, SystemTime::Infinite) {
+                            Ok(_) => {}
+                            Err(Error::NotAvailable) => warn!("Failed to send ping response"),
+                            Err(other) => panic!("Failed to send ping response: {:?}", other),
+                        }
+                    }
+                    // all requests are answered
+                    Err(Error::NotAvailable) => break,
+                    other => panic!("Failed to receive ping request: {:?}", other),
+                }
+            }
         }
     }
 }
@@ -0,0 +1,119 @@
+//! # Example `priorities`
+//!
+//! Shows two processes of different priorities competing for a single CPU
+//! within the window of their partition. The periodic process `Low` and the
+//! aperiodic process `High` do the same amount of work, starting at the same
+//! time. As the priority of `High` is mapped to a lower nice value, it gets
+//! the larger share of the CPU and finishes first. It then lowers its own
+//! priority with `SET_PRIORITY` and logs the priority reported by
+//! `GET_PROCESS_STATUS`.
+
+use core::str::FromStr;
+use core::time::Duration;
+use std::hint::black_box;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use a653rs::bindings::ApexProcessP1;
+use a653rs::prelude::*;
+use a653rs_linux::partition::{ApexLinuxPartition, ApexLogger};
+use log::{error, info};
+use nix::sched::{sched_setaffinity, CpuSet};
+use nix::unistd::Pid;
+
+/// Priority `High` lowers itself to after its work
+const LOWERED_PRIORITY: i32 = 0;
+
+/// Iterations of the work of each process, which takes some ten milliseconds
+/// on a single core without contention
+const WORK: u64 = 20_000_000;
+
+/// Set by `Low` once it started, so that both processes work at the same time
+static LOW_STARTED: AtomicBool = AtomicBool::new(false);
+
+fn main() {
+    ApexLogger::install_panic_hook();
+    ApexLogger::install_logger(log::LevelFilter::Info).unwrap();
+
+    PrioritiesPartition.run()
+}
+
+type Hypervisor = ApexLinuxPartition;
+
+pub struct PrioritiesPartition;
+
+impl a653rs::prelude::Partition<Hypervisor> for PrioritiesPartition {
+    fn cold_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        // Processes inherit the affinity of the main process, so that they compete for
+        // the same CPU
+        let mut cpus = CpuSet::new();
+        cpus.set(0).unwrap();
+        sched_setaffinity(Pid::from_raw(0), &cpus).unwrap();
+
+        for (name, entry_point, period, base_priority) in [
+            (
+                "Low",
+                low as extern "C" fn(),
+                SystemTime::Normal(Duration::ZERO),
+                10,
+            ),
+            ("High", high as extern "C" fn(), SystemTime::Infinite, 200),
+        ] {
+            let process_attributes = ProcessAttribute {
+                period,
+                time_capacity: SystemTime::Infinite,
+                entry_point,
+                stack_size: 100_000,
+                base_priority,
+                deadline: Deadline::Soft,
+                name: Name::from_str(name).unwrap(),
+            };
+            let process_handle = ctx.create_process(process_attributes).unwrap();
+            process_handle.start().unwrap();
+        }
+    }
+
+    fn warm_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        self.cold_start(ctx)
+    }
+}
+
+/// Busy work, which can not be optimized away
+fn work(name: &str) {
+    let mut x = 1u64;
+    for _ in 0..WORK {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        black_box(x);
+    }
+    info!("{name} finished its work");
+}
+
+extern "C" fn low() {
+    LOW_STARTED.store(true, Ordering::SeqCst);
+    work("Low");
+    loop {
+        Hypervisor::periodic_wait().unwrap();
+    }
+}
+
+extern "C" fn high() {
+    // The aperiodic process already runs in the window the partition enters NORMAL,
+    // while the periodic one is released in the next
+    while !LOW_STARTED.load(Ordering::SeqCst) {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    work("High");
+
+    let lowered = Hypervisor::get_my_id().and_then(|id| {
+        Hypervisor::set_priority(id, LOWERED_PRIORITY)?;
+        Hypervisor::get_process_status(id)
+    });
+    match lowered {
+        Ok(status) => info!("High now runs at priority {}", status.current_priority),
+        Err(e) => error!("failed to lower the priority of High: {e:?}"),
+    }
+    loop {
+        std::thread::sleep(Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,155 @@
+//! # Example `process_lifecycle`
+//!
+//! Shows processes stopping, suspending and resuming each other. At the cold
+//! start, the periodic process `Ticker` is started first, and waits for the
+//! partition to enter NORMAL while the aperiodic process `Supervisor` is still
+//! dormant. The supervisor suspends itself until the ticker resumes it in its
+//! second period, and then times out suspending itself again. The ticker
+//! stops itself in the third period of its first run, which the supervisor
+//! notices and starts it again, from its entry point. In its second run, the
+//! ticker suspends the supervisor in its first period and resumes it in the
+//! next one.
+
+use core::str::FromStr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+
+use a653rs::bindings::{
+    ApexProcessP1, ApexProcessP4, ApexSystemTime, ErrorReturnCode, ProcessId, ProcessState,
+};
+use a653rs::prelude::*;
+use a653rs_linux::partition::{ApexLinuxPartition, ApexLogger};
+use log::{error, info, warn};
+
+/// Number of times the ticker was started
+static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+fn main() {
+    ApexLogger::install_panic_hook();
+    ApexLogger::install_logger(log::LevelFilter::Info).unwrap();
+
+    LifecyclePartition.run()
+}
+
+type Hypervisor = ApexLinuxPartition;
+
+pub struct LifecyclePartition;
+
+impl a653rs::prelude::Partition<Hypervisor> for LifecyclePartition {
+    fn cold_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        let [ticker, supervisor] = [
+            (
+                "Ticker",
+                ticker as extern "C" fn(),
+                SystemTime::Normal(Duration::ZERO),
+            ),
+            (
+                "Supervisor",
+                supervisor as extern "C" fn(),
+                SystemTime::Infinite,
+            ),
+        ]
+        .map(|(name, entry_point, period)| {
+            let process_attributes = ProcessAttribute {
+                period,
+                time_capacity: SystemTime::Infinite,
+                entry_point,
+                stack_size: 100_000,
+                base_priority: 1,
+                deadline: Deadline::Soft,
+                name: Name::from_str(name).unwrap(),
+            };
+            ctx.create_process(process_attributes).unwrap()
+        });
+
+        // The started ticker waits for the partition to enter NORMAL
+        ticker.start().unwrap();
+        match (state(ticker.id()), state(supervisor.id())) {
+            (Some(ProcessState::Waiting), Some(ProcessState::Dormant)) => {
+                info!("The started ticker is waiting, the supervisor is dormant")
+            }
+            states => warn!("The states of the processes did not differ: {states:?}"),
+        }
+        supervisor.start().unwrap();
+    }
+
+    fn warm_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        self.cold_start(ctx)
+    }
+}
+
+fn process(name: &str) -> ProcessId {
+    Hypervisor::get_process_id(Name::from_str(name).unwrap().into_inner()).unwrap()
+}
+
+fn state(id: ProcessId) -> Option<ProcessState> {
+    match Hypervisor::get_process_status(id) {
+        Ok(status) => Some(status.process_state),
+        Err(e) => {
+            error!("failed to get the status of process {id}: {e:?}");
+            None
+        }
+    }
+}
+
+fn timeout(duration: Duration) -> ApexSystemTime {
+    duration.as_nanos() as ApexSystemTime
+}
+
+extern "C" fn ticker() {
+    let supervisor = process("Supervisor");
+    let run = RUNS.fetch_add(1, Ordering::SeqCst) + 1;
+    info!("Ticker started, run {run}");
+
+    for period in 1.. {
+        info!("Ticker period {period} of run {run}");
+        match period {
+            1 if run > 1 => match Hypervisor::suspend(supervisor) {
+                Ok(()) => {
+                    info!("Ticker suspended the supervisor");
+                    if let Some(ProcessState::Waiting) = state(supervisor) {
+                        info!("The suspended supervisor is waiting");
+                    }
+                }
+                Err(e) => warn!("Ticker did not suspend the supervisor: {e:?}"),
+            },
+            2 => match Hypervisor::resume(supervisor) {
+                Ok(()) => info!("Ticker resumed the supervisor"),
+                Err(e) => warn!("Ticker did not resume the supervisor: {e:?}"),
+            },
+            3 if run == 1 => {
+                info!("Ticker stops itself");
+                Hypervisor::stop_self();
+                error!("Ticker continued after stopping itself");
+            }
+            _ => {}
+        }
+        Hypervisor::periodic_wait().unwrap();
+    }
+}
+
+extern "C" fn supervisor() {
+    let ticker = process("Ticker");
+
+    // Infinite timeout
+    match Hypervisor::suspend_self(-1) {
+        Ok(()) => info!("Supervisor was resumed"),
+        Err(e) => warn!("Supervisor did not suspend itself: {e:?}"),
+    }
+    match Hypervisor::suspend_self(timeout(Duration::from_millis(50))) {
+        Err(ErrorReturnCode::TimedOut) => info!("Supervisor timed out suspending itself"),
+        res => warn!("Supervisor did not time out suspending itself: {res:?}"),
+    }
+
+    while !matches!(state(ticker), Some(ProcessState::Dormant)) {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    info!("Ticker is dormant, starting it again");
+    if let Err(e) = Hypervisor::start(ticker) {
+        error!("failed to start the ticker again: {e:?}");
+    }
+
+    loop {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
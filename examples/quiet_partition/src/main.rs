@@ -0,0 +1,73 @@
+//! # Example `quiet_partition`
+//!
+//! Shows a partition whose log records are suppressed by the hypervisor with
+//! `suppress_messages`. The periodic process `Chatter` logs a burst of
+//! records in every period, which the hypervisor only counts. In its third
+//! period it raises an application error, which is still printed and restarts
+//! the partition. The restarted partition reports its start condition as the
+//! telemetry gauge `hm_restart`, as its log records are not printed.
+
+use core::str::FromStr;
+use core::time::Duration;
+
+use a653rs::bindings::{ApexErrorP4, ApexPartitionP4, ErrorCode, StartCondition};
+use a653rs::prelude::*;
+use a653rs_linux::partition::{ApexLinuxPartition, ApexLogger};
+use log::{info, warn};
+
+/// Log records per period
+const CHATTER: usize = 200;
+
+fn main() {
+    ApexLogger::install_panic_hook();
+    ApexLogger::install_logger(log::LevelFilter::Info).unwrap();
+
+    QuietPartition.run()
+}
+
+type Hypervisor = ApexLinuxPartition;
+
+pub struct QuietPartition;
+
+impl a653rs::prelude::Partition<Hypervisor> for QuietPartition {
+    fn cold_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        let process_attributes = ProcessAttribute {
+            period: SystemTime::Normal(Duration::ZERO),
+            time_capacity: SystemTime::Infinite,
+            entry_point: chatter,
+            stack_size: 100_000,
+            base_priority: 1,
+            deadline: Deadline::Soft,
+            name: Name::from_str("Chatter").unwrap(),
+        };
+        let process_handle = ctx.create_process(process_attributes).unwrap();
+        process_handle.start().unwrap();
+    }
+
+    fn warm_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        self.cold_start(ctx)
+    }
+}
+
+extern "C" fn chatter() {
+    let restarted = matches!(
+        Hypervisor::get_partition_status().start_condition,
+        StartCondition::HmPartitionRestart
+    );
+    Hypervisor::telemetry("hm_restart", restarted as u8 as f64).ok();
+
+    for period in 1.. {
+        for i in 0..CHATTER {
+            info!("chatter {period}.{i}");
+        }
+        if period == 3 && !restarted {
+            if let Err(e) = <Hypervisor as ApexErrorP4>::raise_application_error(
+                ErrorCode::ApplicationError,
+                b"Chatter raised an error",
+            ) {
+                warn!("failed to raise an application error: {e:?}");
+            }
+        }
+        Hypervisor::periodic_wait().unwrap();
+    }
+}
@@ -0,0 +1,161 @@
+//! # Example `semaphore`
+//!
+//! Shows two processes of a partition taking turns on a binary semaphore. The
+//! periodic process `Writer` takes the semaphore in every odd period and holds
+//! it across its periodic wait, until it releases it in the next period, after
+//! logging the number of processes waiting for it. The aperiodic process
+//! `Reader` takes the semaphore whenever it is free. Finding it taken, it
+//! first waits briefly, which times out while the writer holds the semaphore
+//! across the windows of the partition, and then waits until it is released.
+//!
+//! Both processes record themselves as the holder of the semaphore while
+//! holding it, and log an error should they ever find the other one there.
+
+use core::str::FromStr;
+use core::sync::atomic::{AtomicU8, Ordering};
+use core::time::Duration;
+
+use a653rs::bindings::{
+    ApexName, ApexSemaphoreP1, ApexSystemTime, ErrorReturnCode, QueuingDiscipline, SemaphoreId,
+};
+use a653rs::prelude::*;
+use a653rs_linux::partition::{ApexLinuxPartition, ApexLogger};
+use log::{error, info, warn};
+
+const SEMAPHORE: &str = "Turns";
+
+/// Process holding the semaphore, if any
+static HOLDER: AtomicU8 = AtomicU8::new(NOBODY);
+const NOBODY: u8 = 0;
+const WRITER: u8 = 1;
+const READER: u8 = 2;
+
+fn main() {
+    ApexLogger::install_panic_hook();
+    ApexLogger::install_logger(log::LevelFilter::Info).unwrap();
+
+    SemaphorePartition.run()
+}
+
+type Hypervisor = ApexLinuxPartition;
+
+pub struct SemaphorePartition;
+
+impl a653rs::prelude::Partition<Hypervisor> for SemaphorePartition {
+    fn cold_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        Hypervisor::create_semaphore(name(), 1, 1, QueuingDiscipline::Fifo).unwrap();
+
+        for (name, entry_point, period) in [
+            (
+                "Writer",
+                writer as extern "C" fn(),
+                SystemTime::Normal(Duration::ZERO),
+            ),
+            ("Reader", reader as extern "C" fn(), SystemTime::Infinite),
+        ] {
+            let process_attributes = ProcessAttribute {
+                period,
+                time_capacity: SystemTime::Infinite,
+                entry_point,
+                stack_size: 100_000,
+                base_priority: 1,
+                deadline: Deadline::Soft,
+                name: Name::from_str(name).unwrap(),
+            };
+            let process_handle = ctx.create_process(process_attributes).unwrap();
+            process_handle.start().unwrap();
+        }
+    }
+
+    fn warm_start(&self, ctx: &mut StartContext<Hypervisor>) {
+        self.cold_start(ctx)
+    }
+}
+
+fn name() -> ApexName {
+    Name::from_str(SEMAPHORE).unwrap().into_inner()
+}
+
+/// The semaphore created by the main process
+fn semaphore() -> SemaphoreId {
+    Hypervisor::get_semaphore_id(name()).unwrap()
+}
+
+fn timeout(duration: Duration) -> ApexSystemTime {
+    duration.as_nanos() as ApexSystemTime
+}
+
+/// Records `process` as the holder of the semaphore
+fn enter(process: u8) {
+    let holder = HOLDER.swap(process, Ordering::SeqCst);
+    if holder != NOBODY {
+        error!("process {process} took the semaphore held by process {holder}");
+    }
+}
+
+fn leave() {
+    HOLDER.store(NOBODY, Ordering::SeqCst);
+}
+
+extern "C" fn writer() {
+    let id = semaphore();
+    for period in 1.. {
+        if period % 2 == 1 {
+            // Infinite timeout
+            Hypervisor::wait_semaphore(id, -1).unwrap();
+            enter(WRITER);
+            info!("Writer took the semaphore");
+        } else {
+            match Hypervisor::get_semaphore_status(id) {
+                Ok(status) => info!(
+                    "Writer sees waiting processes: {}",
+                    status.waiting_processes
+                ),
+                Err(e) => error!("failed to get the status of the semaphore: {e:?}"),
+            }
+            leave();
+            Hypervisor::signal_semaphore(id).unwrap();
+            info!("Writer released the semaphore");
+        }
+        Hypervisor::periodic_wait().unwrap();
+    }
+}
+
+extern "C" fn reader() {
+    let id = semaphore();
+    loop {
+        match Hypervisor::wait_semaphore(id, 0) {
+            Ok(()) => {}
+            Err(ErrorReturnCode::NotAvailable) => {
+                info!("Reader found the semaphore taken");
+                match Hypervisor::wait_semaphore(id, timeout(Duration::from_millis(50))) {
+                    Ok(()) => {}
+                    Err(ErrorReturnCode::TimedOut) => {
+                        info!("Reader timed out waiting for the semaphore");
+                        if let Err(e) =
+                            Hypervisor::wait_semaphore(id, timeout(Duration::from_secs(2)))
+                        {
+                            warn!("Reader did not take the semaphore: {e:?}");
+                            continue;
+                        }
+                        info!("Reader took the semaphore after waiting");
+                    }
+                    Err(e) => {
+                        warn!("Reader did not take the semaphore: {e:?}");
+                        continue;
+                    }
+                }
+            }
+            Err(e) => {
+                error!("failed to wait for the semaphore: {e:?}");
+                continue;
+            }
+        }
+
+        enter(READER);
+        std::thread::sleep(Duration::from_millis(1));
+        leave();
+        Hypervisor::signal_semaphore(id).unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
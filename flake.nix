@@ -32,15 +32,30 @@
         # Rust distribution for our hostSystem
         fenix = inputs.fenix.packages.${system};
 
+        # ARM targets which the workspace is cross-built and unit tested for,
+        # with the static cross toolchain to link and the qemu to run them
+        cross-targets = [
+          {
+            target = "aarch64-unknown-linux-musl";
+            cc = pkgs.pkgsCross.aarch64-multiplatform-musl.pkgsStatic.stdenv.cc;
+            qemu = "qemu-aarch64";
+          }
+          {
+            target = "armv7-unknown-linux-musleabihf";
+            cc = pkgs.pkgsCross.armv7l-hf-multiplatform.pkgsStatic.stdenv.cc;
+            qemu = "qemu-arm";
+          }
+        ];
+
         rust-toolchain = with fenix;
-          combine [
+          combine ([
             stable.rustc
             stable.cargo
             stable.clippy
             latest.rustfmt
             targets.${rust-target}.stable.rust-std
             targets.thumbv6m-none-eabi.stable.rust-std # for no_std test
-          ];
+          ] ++ builtins.map ({ target, ... }: targets.${target}.stable.rust-std) cross-targets);
 
         # overrides a naersk-lib which uses the stable toolchain expressed above
         naersk-lib = (naersk.lib.${system}.override {
@@ -140,6 +155,7 @@
             cargo-watch
             cargo-audit
             cargo-expand
+            cargo-fuzz
             nixpkgs-fmt
             nodePackages.prettier
           ];
@@ -185,7 +201,20 @@
               help = "Verify that the library builds for no_std without std-features";
               category = "dev";
             }
-          ] ++ (
+          ] ++ (builtins.map
+            ({ target, cc, qemu }: {
+              name = "cross-test-${target}";
+              command = ''
+                cd "$PRJ_ROOT"
+                export CARGO_TARGET_${shout target}_LINKER="${cc}/bin/${cc.targetPrefix}cc"
+                export CARGO_TARGET_${shout target}_RUNNER="${pkgs.qemu}/bin/${qemu}"
+                cargo build --workspace --target ${target}
+                cargo test --target ${target} --package a653rs-linux-core --package a653rs-linux --lib $@
+              '';
+              help = "Build the workspace for ${target} and run the unit tests of the libraries in qemu";
+              category = "dev";
+            })
+            cross-targets) ++ (
             let
               inherit (builtins) map concatStringsSep;
               inherit (nixpkgs.lib) flatten;
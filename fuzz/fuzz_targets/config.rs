@@ -0,0 +1,17 @@
+//! Parses arbitrary input as a hypervisor configuration and validates it
+//!
+//! Neither parsing nor validation may panic, every invalid configuration has
+//! to be reported as an error.
+#![no_main]
+
+use a653rs_linux_hypervisor::hypervisor::config::Config;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(yaml) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(config) = serde_yaml::from_str::<Config>(yaml) {
+        let _ = config.validate();
+    }
+});
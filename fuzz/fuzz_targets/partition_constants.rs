@@ -0,0 +1,10 @@
+//! Decodes arbitrary input as the constants the hypervisor passes to a
+//! partition
+#![no_main]
+
+use a653rs_linux_core::partition::PartitionConstants;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = PartitionConstants::from_bytes(data);
+});
@@ -0,0 +1,603 @@
+//! Budgets of the space taken by the files capturing a run of the module
+//!
+//! The trace of `--trace-file` and the [recording](super::record) of channels
+//! grow for as long as the module runs, which may fill the disk on a long soak
+//! run. The optional `capture` section of the configuration limits them:
+//!
+//! ```yaml
+//! capture:
+//!   max_total: 2GB
+//!   trace: { max_size: 500MB, keep: 4 }
+//!   recording: { max_size: 1GB }
+//! ```
+//!
+//! A feature with a `max_size` and `keep: N` rotates its file once the file
+//! reaches `max_size / (N + 1)`: `trace.json` becomes `trace.json.1`, the
+//! previous `trace.json.1` becomes `trace.json.2` and so on, and the file
+//! beyond `trace.json.N` is deleted. Every file is complete on its own, a trace
+//! closes its JSON array and a recording starts with its header. Without
+//! `keep`, whatever exceeds the `max_size` of the feature is dropped.
+//!
+//! Should a write take all features together beyond `max_total`, the oldest
+//! rotated file of any feature is deleted first, until the write fits. It is
+//! only dropped once no rotated file is left.
+//!
+//! Writers register with the [CaptureManager] and ask it before every write.
+//! It accounts the bytes the writers report, so that no file is examined while
+//! the module runs. Dropping writes is warned about once per breach of a
+//! budget, i.e. again only after a write fit into the budget in between.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::{fs, io};
+
+use a653rs_linux_core::error::{ResultExt, SystemError, TypedResult};
+use anyhow::anyhow;
+use bytesize::ByteSize;
+use serde::{Deserialize, Serialize};
+
+/// Budgets of the captured files
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct CaptureConfig {
+    /// Space the files of all features may take together
+    #[serde(default)]
+    pub max_total: Option<ByteSize>,
+
+    /// Budget of the trace of `--trace-file`
+    #[serde(default)]
+    pub trace: CaptureBudget,
+
+    /// Budget of the [recording](super::record) of channels
+    #[serde(default)]
+    pub recording: CaptureBudget,
+}
+
+/// Budget of the files of a single feature
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct CaptureBudget {
+    /// Space the current and the rotated files may take together
+    #[serde(default)]
+    pub max_size: Option<ByteSize>,
+
+    /// Number of rotated files kept besides the current one, requires a
+    /// `max_size`
+    #[serde(default)]
+    pub keep: usize,
+}
+
+impl CaptureConfig {
+    /// Checks that every budget holds at least a byte per file
+    pub fn validate(&self) -> TypedResult<()> {
+        let mut invalid = Vec::new();
+        if self.max_total == Some(ByteSize::b(0)) {
+            invalid.push("max_total must not be zero".to_string());
+        }
+        for feature in [Feature::Trace, Feature::Recording] {
+            let budget = self.budget(feature);
+            match budget.max_size {
+                None if budget.keep > 0 => {
+                    invalid.push(format!("keep of the {feature} requires a max_size"))
+                }
+                Some(max_size) if max_size.as_u64() / (budget.keep as u64 + 1) == 0 => invalid
+                    .push(format!(
+                        "max_size of the {feature} is too small to keep {} files",
+                        budget.keep + 1
+                    )),
+                _ => {}
+            }
+        }
+
+        if !invalid.is_empty() {
+            return Err(anyhow!("invalid capture:\n{}", invalid.join("\n")))
+                .typ(SystemError::Config);
+        }
+        Ok(())
+    }
+
+    fn budget(&self, feature: Feature) -> &CaptureBudget {
+        match feature {
+            Feature::Trace => &self.trace,
+            Feature::Recording => &self.recording,
+        }
+    }
+}
+
+/// Feature writing captured files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    Trace,
+    Recording,
+}
+
+impl Display for Feature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Feature::Trace => write!(f, "trace"),
+            Feature::Recording => write!(f, "recording"),
+        }
+    }
+}
+
+/// Decision about a write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    /// The bytes fit into the current file
+    Write,
+    /// The current file is full, so the writer completes it and calls
+    /// [Capture::rotate] before asking again
+    Rotate,
+    /// The bytes exceed a budget and are dropped
+    Drop,
+}
+
+/// Budget whose breach was warned about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Breach {
+    Total,
+    Feature(Feature),
+}
+
+#[derive(Debug)]
+struct Rotated {
+    size: u64,
+    /// Number of the rotation among those of all features, the lowest being
+    /// the oldest file
+    rotation: u64,
+}
+
+/// Files of a registered feature
+#[derive(Debug)]
+struct Files {
+    path: PathBuf,
+    /// Bytes written to the current file
+    current: u64,
+    /// Whether a write was admitted to the current file, which is only rotated
+    /// then
+    admitted: bool,
+    /// The rotated files, the newest first
+    rotated: VecDeque<Rotated>,
+}
+
+impl Files {
+    /// Path of the `n`-th rotated file, counting from the newest one
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        rotated_path(&self.path, n)
+    }
+}
+
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(format!(".{n}"));
+    PathBuf::from(path)
+}
+
+#[derive(Debug, Default)]
+struct State {
+    files: HashMap<Feature, Files>,
+    /// Bytes of the files of all features
+    total: u64,
+    rotations: u64,
+    breached: HashSet<Breach>,
+}
+
+impl State {
+    /// Deletes the oldest rotated file of any feature, returning whether there
+    /// was one
+    fn remove_oldest(&mut self) -> bool {
+        let oldest = self
+            .files
+            .iter()
+            .filter_map(|(feature, files)| Some((*feature, files.rotated.back()?.rotation)))
+            .min_by_key(|(_, rotation)| *rotation);
+        let Some((feature, _)) = oldest else {
+            return false;
+        };
+        let files = self
+            .files
+            .get_mut(&feature)
+            .expect("feature of a rotated file");
+        let path = files.rotated_path(files.rotated.len());
+        if let Some(oldest) = files.rotated.pop_back() {
+            self.total -= oldest.size;
+        }
+        if let Err(e) = fs::remove_file(&path) {
+            warn!("Failed to delete the rotated file {path:?}: {e}");
+        }
+        true
+    }
+
+    /// Drops a write exceeding `max`, warning about the first of a breach
+    fn drop_write(&mut self, breach: Breach, max: ByteSize) -> Admission {
+        if self.breached.insert(breach) {
+            match breach {
+                Breach::Total => {
+                    warn!(
+                        "Dropping captured data, as all captures reached their max_total of {max}"
+                    )
+                }
+                Breach::Feature(feature) => {
+                    warn!("Dropping parts of the {feature}, as it reached its max_size of {max}")
+                }
+            }
+        }
+        Admission::Drop
+    }
+}
+
+/// Accounts the files of all captures against their budgets
+#[derive(Debug)]
+pub struct CaptureManager {
+    config: CaptureConfig,
+    state: Mutex<State>,
+}
+
+impl CaptureManager {
+    pub fn new(config: CaptureConfig) -> Arc<Self> {
+        Arc::new(Self {
+            config,
+            state: Mutex::default(),
+        })
+    }
+
+    /// Registers `feature`, which writes a new file at `path`
+    ///
+    /// Rotated files left at `path` by an earlier run are deleted, as they are
+    /// not accounted.
+    pub fn register(self: &Arc<Self>, feature: Feature, path: &Path) -> Capture {
+        for n in 1..=self.config.budget(feature).keep {
+            fs::remove_file(rotated_path(path, n)).ok();
+        }
+        let files = Files {
+            path: path.to_path_buf(),
+            current: 0,
+            admitted: false,
+            rotated: VecDeque::new(),
+        };
+        self.state.lock().unwrap().files.insert(feature, files);
+        Capture {
+            manager: self.clone(),
+            feature,
+            path: path.to_path_buf(),
+        }
+    }
+
+    fn admit(&self, feature: Feature, len: u64) -> Admission {
+        let mut state = self.state.lock().unwrap();
+        let budget = self.config.budget(feature);
+        let files = &state.files[&feature];
+        if let Some(max_size) = budget.max_size {
+            let max_file = max_size.as_u64() / (budget.keep as u64 + 1);
+            if files.current + len > max_file {
+                // Records larger than a file would never fit into one
+                if budget.keep > 0 && files.admitted && len <= max_file {
+                    return Admission::Rotate;
+                }
+                return state.drop_write(Breach::Feature(feature), max_size);
+            }
+        }
+        if let Some(max_total) = self.config.max_total {
+            while state.total + len > max_total.as_u64() {
+                if !state.remove_oldest() {
+                    return state.drop_write(Breach::Total, max_total);
+                }
+            }
+        }
+
+        state.breached.remove(&Breach::Feature(feature));
+        state.breached.remove(&Breach::Total);
+        if let Some(files) = state.files.get_mut(&feature) {
+            files.admitted = true;
+        }
+        Admission::Write
+    }
+
+    fn written(&self, feature: Feature, len: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.total += len;
+        if let Some(files) = state.files.get_mut(&feature) {
+            files.current += len;
+        }
+    }
+
+    fn rotate(&self, feature: Feature) -> io::Result<()> {
+        let keep = self.config.budget(feature).keep;
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        state.rotations += 1;
+        let Some(files) = state.files.get_mut(&feature) else {
+            return Ok(());
+        };
+
+        // Oldest first, so that no file is overwritten
+        for n in (1..=files.rotated.len()).rev() {
+            fs::rename(files.rotated_path(n), files.rotated_path(n + 1))?;
+        }
+        fs::rename(&files.path, files.rotated_path(1))?;
+        files.rotated.push_front(Rotated {
+            size: files.current,
+            rotation: state.rotations,
+        });
+        files.current = 0;
+        files.admitted = false;
+
+        while files.rotated.len() > keep {
+            let path = files.rotated_path(files.rotated.len());
+            if let Some(oldest) = files.rotated.pop_back() {
+                state.total -= oldest.size;
+            }
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Handle of a feature registered with the [CaptureManager]
+#[derive(Debug, Clone)]
+pub struct Capture {
+    manager: Arc<CaptureManager>,
+    feature: Feature,
+    path: PathBuf,
+}
+
+impl Capture {
+    /// Path of the current file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Decides whether `len` more bytes may be written to the current file
+    pub fn admit(&self, len: u64) -> Admission {
+        self.manager.admit(self.feature, len)
+    }
+
+    /// Accounts `len` bytes written to the current file
+    ///
+    /// Bytes a writer adds on its own, e.g. the header of a new file, are
+    /// accounted without asking for them.
+    pub fn written(&self, len: u64) {
+        self.manager.written(self.feature, len)
+    }
+
+    /// Renames the current file to the newest rotated one, deleting those
+    /// beyond the ones kept
+    ///
+    /// The writer then creates a new file at [Capture::path].
+    pub fn rotate(&self) -> io::Result<()> {
+        self.manager.rotate(self.feature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// Writer of numbered records of `len` bytes, like the actual ones
+    struct Writer {
+        capture: Capture,
+        file: File,
+        /// Number of the next record
+        next: u8,
+    }
+
+    impl Writer {
+        fn new(manager: &Arc<CaptureManager>, feature: Feature, dir: &TempDir) -> Self {
+            let path = dir.path().join(feature.to_string());
+            Self {
+                capture: manager.register(feature, &path),
+                file: File::create(&path).unwrap(),
+                next: 0,
+            }
+        }
+
+        /// Writes the next record, returning whether it was written
+        fn write(&mut self, len: usize) -> bool {
+            let record = vec![self.next; len];
+            self.next += 1;
+            loop {
+                match self.capture.admit(len as u64) {
+                    Admission::Write => break,
+                    Admission::Drop => return false,
+                    Admission::Rotate => {
+                        self.capture.rotate().unwrap();
+                        self.file = File::create(self.capture.path()).unwrap();
+                    }
+                }
+            }
+            self.file.write_all(&record).unwrap();
+            self.capture.written(len as u64);
+            true
+        }
+
+        /// Numbers of the records in the current and the rotated files, the
+        /// newest first, up to the first missing file
+        fn files(&self) -> Vec<Vec<u8>> {
+            let mut files = vec![fs::read(self.capture.path()).unwrap()];
+            files.extend((1..).map_while(|n| fs::read(rotated_path(self.capture.path(), n)).ok()));
+            files
+                .into_iter()
+                .map(|mut data| {
+                    data.dedup();
+                    data
+                })
+                .collect()
+        }
+    }
+
+    fn config(yaml: &str) -> CaptureConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn configuration() {
+        let parsed = config("{ max_total: 2GB, trace: { max_size: 500MB, keep: 4 } }");
+        assert_eq!(parsed.max_total, Some(ByteSize::gb(2)));
+        assert_eq!(parsed.trace.max_size, Some(ByteSize::mb(500)));
+        assert_eq!(parsed.trace.keep, 4);
+        assert_eq!(parsed.recording, CaptureBudget::default());
+        parsed.validate().unwrap();
+        CaptureConfig::default().validate().unwrap();
+
+        let err =
+            config("{ max_total: 0B, trace: { keep: 1 }, recording: { max_size: 2B, keep: 2 } }")
+                .validate()
+                .unwrap_err()
+                .to_string();
+        assert!(err.contains("max_total must not be zero"), "{err}");
+        assert!(
+            err.contains("keep of the trace requires a max_size"),
+            "{err}"
+        );
+        assert!(
+            err.contains("recording is too small to keep 3 files"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn unlimited() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = CaptureManager::new(CaptureConfig::default());
+        let mut writer = Writer::new(&manager, Feature::Trace, &dir);
+        assert!((0..100).all(|_| writer.write(1000)));
+        assert_eq!(fs::metadata(writer.capture.path()).unwrap().len(), 100_000);
+    }
+
+    #[test]
+    fn drops_beyond_max_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = CaptureManager::new(config("trace: { max_size: 25B }"));
+        let mut writer = Writer::new(&manager, Feature::Trace, &dir);
+        assert!(writer.write(10));
+        assert!(writer.write(10));
+        assert!(!writer.write(10));
+        // Smaller records still fit
+        assert!(writer.write(5));
+        assert!(!writer.write(1));
+        assert_eq!(writer.files(), [vec![0, 1, 3]]);
+    }
+
+    #[test]
+    fn rotates_keeping_the_newest_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = CaptureManager::new(config("recording: { max_size: 60B, keep: 2 }"));
+        let mut writer = Writer::new(&manager, Feature::Recording, &dir);
+        // Files of 20 bytes, two records each
+        assert!((0..9).all(|_| writer.write(10)));
+        assert_eq!(writer.files(), [vec![8], vec![6, 7], vec![4, 5]]);
+        assert_eq!(manager.state.lock().unwrap().total, 50);
+
+        // A record larger than a file never fits
+        assert!(!writer.write(21));
+        assert_eq!(writer.files(), [vec![8], vec![6, 7], vec![4, 5]]);
+    }
+
+    #[test]
+    fn stale_rotated_files_are_deleted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace");
+        fs::write(rotated_path(&path, 1), "earlier run").unwrap();
+        fs::write(rotated_path(&path, 2), "earlier run").unwrap();
+
+        let manager = CaptureManager::new(config("trace: { max_size: 2MB, keep: 1 }"));
+        let mut writer = Writer::new(&manager, Feature::Trace, &dir);
+        assert!(writer.write(1));
+        assert_eq!(writer.files(), [vec![0]]);
+        // Beyond the files kept, and thus never overwritten
+        assert!(rotated_path(&path, 2).exists());
+    }
+
+    #[test]
+    fn total_deletes_the_oldest_rotated_file_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = CaptureManager::new(config(
+            r#"
+max_total: 100B
+trace: { max_size: 80B, keep: 3 }
+recording: { max_size: 60B, keep: 2 }
+"#,
+        ));
+        let mut trace = Writer::new(&manager, Feature::Trace, &dir);
+        let mut recording = Writer::new(&manager, Feature::Recording, &dir);
+
+        // Rotated trace files of 20 bytes, the older one ahead of the recording
+        assert!((0..5).all(|_| trace.write(10)));
+        assert!((0..4).all(|_| recording.write(10)));
+        assert_eq!(trace.files(), [vec![4], vec![2, 3], vec![0, 1]]);
+        assert_eq!(recording.files(), [vec![2, 3], vec![0, 1]]);
+        assert_eq!(manager.state.lock().unwrap().total, 90);
+
+        // Fits without deleting anything, after the recording rotated its file
+        assert!(recording.write(10));
+        assert_eq!(manager.state.lock().unwrap().total, 100);
+        assert_eq!(recording.files(), [vec![4], vec![2, 3], vec![0, 1]]);
+
+        // Makes room by deleting the oldest rotated files, regardless of their feature
+        assert!(recording.write(10));
+        assert_eq!(trace.files(), [vec![4], vec![2, 3]]);
+        assert!(trace.write(10));
+        assert_eq!(trace.files(), [vec![4, 5], vec![2, 3]]);
+        assert_eq!(manager.state.lock().unwrap().total, 100);
+        // The rotated trace file is older than that of the recording
+        assert!(trace.write(10));
+        assert_eq!(trace.files(), [vec![6], vec![4, 5]]);
+        assert_eq!(recording.files(), [vec![4, 5], vec![2, 3], vec![0, 1]]);
+        assert_eq!(manager.state.lock().unwrap().total, 90);
+        // Now the rotated file of the recording is the oldest one
+        assert!(trace.write(10));
+        assert!(trace.write(10));
+        assert_eq!(trace.files(), [vec![8], vec![6, 7], vec![4, 5]]);
+        assert_eq!(recording.files(), [vec![4, 5], vec![2, 3]]);
+        assert_eq!(manager.state.lock().unwrap().total, 90);
+    }
+
+    #[test]
+    fn drops_once_no_rotated_file_is_left() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = CaptureManager::new(config(
+            "{ max_total: 30B, trace: { max_size: 40B, keep: 1 } }",
+        ));
+        let mut trace = Writer::new(&manager, Feature::Trace, &dir);
+        let mut recording = Writer::new(&manager, Feature::Recording, &dir);
+
+        assert!(trace.write(10));
+        assert!(trace.write(10));
+        assert!(trace.write(10));
+        assert_eq!(trace.files(), [vec![2], vec![0, 1]]);
+        // Deletes the rotated trace file, after which the current files are full
+        assert!(recording.write(20));
+        assert_eq!(trace.files(), [vec![2]]);
+        assert!(!recording.write(1));
+        assert!(!trace.write(1));
+
+        let breached = manager.state.lock().unwrap().breached.clone();
+        assert_eq!(breached, HashSet::from([Breach::Total]));
+    }
+
+    #[test]
+    fn warns_once_per_breach() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = CaptureManager::new(config("trace: { max_size: 10B }"));
+        let mut writer = Writer::new(&manager, Feature::Trace, &dir);
+        let breached = || manager.state.lock().unwrap().breached.clone();
+
+        assert!(writer.write(8));
+        assert!(!writer.write(8));
+        assert_eq!(breached(), HashSet::from([Breach::Feature(Feature::Trace)]));
+        // Still the same breach, as nothing fit in between
+        assert!(!writer.write(8));
+        assert_eq!(breached(), HashSet::from([Breach::Feature(Feature::Trace)]));
+        // Ends the breach
+        assert!(writer.write(2));
+        assert!(breached().is_empty());
+    }
+}
@@ -0,0 +1,220 @@
+//! Building partition images from a cargo workspace
+//!
+//! Meant for development only: with `--allow-cargo-build`, the hypervisor runs
+//! `cargo build` for every [CargoImage] before creating the partitions, so
+//! that the configuration need not be kept in sync with the target directory.
+//! The output of cargo is logged, and a failed build is a configuration error.
+
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use a653rs_linux_core::error::{ResultExt, SystemError, TypedResult};
+use anyhow::{anyhow, Context};
+
+use super::config::CargoImage;
+
+/// Builds the binary of `image`, returning its path
+pub(crate) fn build(image: &CargoImage) -> TypedResult<PathBuf> {
+    let CargoImage {
+        package,
+        bin,
+        target,
+        profile,
+        manifest_path,
+    } = image;
+
+    let mut cmd = cargo();
+    cmd.args(["build", "--color", "never", "--package", package]);
+    cmd.args(["--profile", profile]);
+    if let Some(manifest_path) = manifest_path {
+        cmd.arg("--manifest-path").arg(manifest_path);
+    }
+    if let Some(bin) = bin {
+        cmd.args(["--bin", bin]);
+    }
+    if let Some(target) = target {
+        cmd.args(["--target", target]);
+    }
+    info!("building partition image: {cmd:?}");
+
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("failed to run cargo")
+        .typ(SystemError::Config)?;
+    // Cargo reports its progress on stderr. The pipe is closed once the loop
+    // ends, so that cargo can not block on it if reading fails.
+    let stderr = child.stderr.take().expect("stderr to be piped");
+    for line in BufReader::new(stderr).split(b'\n') {
+        match line {
+            Ok(line) => info!("cargo: {}", String::from_utf8_lossy(&line)),
+            Err(e) => {
+                warn!("failed to read the output of cargo: {e}");
+                break;
+            }
+        }
+    }
+    let status = child.wait().typ(SystemError::Config)?;
+    if !status.success() {
+        return Err(anyhow!("cargo build of package {package} failed, {status}"))
+            .typ(SystemError::Config);
+    }
+
+    let mut path = target_directory(manifest_path.as_deref())?;
+    if let Some(target) = target {
+        path.push(target);
+    }
+    path.push(profile_dir(profile));
+    path.push(bin.as_deref().unwrap_or(package));
+    if !path.is_file() {
+        return Err(anyhow!(
+            "cargo build of package {package} did not produce {}",
+            path.display()
+        ))
+        .typ(SystemError::Config);
+    }
+    Ok(path)
+}
+
+/// The cargo running the hypervisor, if any, otherwise the one in `$PATH`
+fn cargo() -> Command {
+    Command::new(std::env::var_os("CARGO").unwrap_or_else(|| OsString::from("cargo")))
+}
+
+/// Asks cargo for the target directory of the workspace
+fn target_directory(manifest_path: Option<&Path>) -> TypedResult<PathBuf> {
+    let mut cmd = cargo();
+    cmd.args(["metadata", "--format-version", "1", "--no-deps"]);
+    if let Some(manifest_path) = manifest_path {
+        cmd.arg("--manifest-path").arg(manifest_path);
+    }
+    let output = cmd
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .context("failed to run cargo metadata")
+        .typ(SystemError::Config)?;
+    if !output.status.success() {
+        return Err(anyhow!("cargo metadata failed, {}", output.status)).typ(SystemError::Config);
+    }
+
+    let metadata = String::from_utf8_lossy(&output.stdout);
+    json_string(&metadata, "target_directory")
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow!("cargo metadata did not report a target directory"))
+        .typ(SystemError::Config)
+}
+
+/// Directory of the build artifacts of `profile` below the target directory
+fn profile_dir(profile: &str) -> &str {
+    match profile {
+        "dev" | "test" => "debug",
+        "bench" => "release",
+        profile => profile,
+    }
+}
+
+/// Extracts the first string value of `key` from a JSON document
+///
+/// Only meant for the output of `cargo metadata`, whose `target_directory`
+/// is unique across the document.
+fn json_string(json: &str, key: &str) -> Option<String> {
+    let start = json.find(&format!("\"{key}\":\""))? + key.len() + 4;
+    let mut value = String::new();
+    let mut chars = json[start..].chars();
+    loop {
+        match chars.next()? {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                'r' => value.push('\r'),
+                'b' => value.push('\u{8}'),
+                'f' => value.push('\u{c}'),
+                'u' => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    value.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                }
+                c => value.push(c),
+            },
+            c => value.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// Creates a crate outside of any workspace, with `main` as its main
+    /// function
+    fn fixture(main: &str) -> (tempfile::TempDir, CargoImage) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"fixture-part\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[workspace]\n",
+        )
+        .unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/main.rs"), main).unwrap();
+
+        let image = CargoImage {
+            package: "fixture-part".into(),
+            bin: None,
+            target: None,
+            profile: "dev".into(),
+            manifest_path: Some(dir.path().join("Cargo.toml")),
+        };
+        (dir, image)
+    }
+
+    #[test]
+    fn builds_fixture_crate() {
+        let (dir, image) = fixture("fn main() {}\n");
+        let bin = build(&image).unwrap();
+        assert!(bin.is_file());
+        assert!(bin.starts_with(dir.path()) || std::env::var_os("CARGO_TARGET_DIR").is_some());
+        assert!(bin.ends_with("debug/fixture-part"), "{}", bin.display());
+    }
+
+    #[test]
+    fn build_failures_are_config_errors() {
+        let (_dir, image) = fixture("fn main() { not_a_function() }\n");
+        let err = build(&image).unwrap_err();
+        assert_eq!(err.err(), SystemError::Config);
+        assert!(err.to_string().contains("fixture-part"), "{err}");
+
+        let (_dir, mut image) = fixture("fn main() {}\n");
+        image.package = "missing".into();
+        assert!(build(&image).is_err());
+    }
+
+    #[test]
+    fn profile_dirs() {
+        assert_eq!(profile_dir("dev"), "debug");
+        assert_eq!(profile_dir("test"), "debug");
+        assert_eq!(profile_dir("release"), "release");
+        assert_eq!(profile_dir("bench"), "release");
+        assert_eq!(profile_dir("ci"), "ci");
+    }
+
+    #[test]
+    fn json_strings() {
+        let json = r#"{"packages":[],"target_directory":"/home/\"me\"\\t\u00e4rget","version":1}"#;
+        assert_eq!(
+            json_string(json, "target_directory").unwrap(),
+            "/home/\"me\"\\t\u{e4}rget"
+        );
+        assert_eq!(json_string(json, "workspace_root"), None);
+        assert_eq!(
+            json_string(r#"{"target_directory":"/unterminated"#, "target_directory"),
+            None
+        );
+    }
+}
@@ -0,0 +1,152 @@
+//! Detection of steps of the host clock
+//!
+//! The schedule runs on the
+//! [ModuleTime](a653rs_linux_core::time::ModuleTime) and channels are stamped
+//! with [MonotonicTime](a653rs_linux_core::time::MonotonicTime), both of which
+//! are based on `CLOCK_MONOTONIC`. Log lines however carry the wall time of
+//! `CLOCK_REALTIME`, which jumps whenever the clock of the host is stepped,
+//! e.g. by NTP. Comparing both clocks once per major frame reveals such steps,
+//! so that the log can explain why its timestamps stop matching the module
+//! time.
+
+use std::fmt::Display;
+use std::time::Duration;
+
+use a653rs_linux_core::time::ClockAnchor;
+
+/// A step of the wall clock relative to the monotonic clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ClockStep {
+    pub forward: bool,
+    pub by: Duration,
+}
+
+impl Display for ClockStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let direction = if self.forward { "forward" } else { "backward" };
+        write!(f, "{direction} by {}", humantime::Duration::from(self.by))
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ClockStepDetector {
+    /// Smallest change of the offset between the clocks that is reported
+    threshold: Duration,
+    /// Offset of the wall clock to the monotonic clock at the last sample, in
+    /// nanoseconds
+    offset: Option<i128>,
+    steps: u64,
+}
+
+impl ClockStepDetector {
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            offset: None,
+            steps: 0,
+        }
+    }
+
+    /// Reads both clocks, returning a step since the previous sample
+    pub fn sample(&mut self) -> Option<ClockStep> {
+        let anchor = ClockAnchor::now();
+        self.sample_with(
+            anchor.wall.since_unix_epoch(),
+            anchor.monotonic.as_duration(),
+        )
+    }
+
+    /// Compares a pair of readings of the wall clock and the monotonic clock
+    /// with the previous one
+    ///
+    /// Both clocks advance at the same rate apart from the slewing of NTP,
+    /// which stays far below the threshold between two samples.
+    fn sample_with(&mut self, realtime: Duration, monotonic: Duration) -> Option<ClockStep> {
+        let offset = realtime.as_nanos() as i128 - monotonic.as_nanos() as i128;
+        let change = offset - self.offset.replace(offset)?;
+        let by = Duration::from_nanos(u64::try_from(change.unsigned_abs()).unwrap_or(u64::MAX));
+        if by <= self.threshold {
+            return None;
+        }
+
+        self.steps += 1;
+        Some(ClockStep {
+            forward: change > 0,
+            by,
+        })
+    }
+
+    /// Number of steps detected so far
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn steps_of_injected_clocks() {
+        let mut detector = ClockStepDetector::new(Duration::from_millis(100));
+
+        // The first sample only establishes the offset
+        assert_eq!(detector.sample_with(ms(1_000_000), ms(10_000)), None);
+        // Both clocks advancing together, with some slewing
+        assert_eq!(detector.sample_with(ms(1_001_000), ms(11_000)), None);
+        assert_eq!(detector.sample_with(ms(1_002_050), ms(12_000)), None);
+        assert_eq!(detector.steps(), 0);
+
+        assert_eq!(
+            detector.sample_with(ms(1_005_050), ms(13_000)),
+            Some(ClockStep {
+                forward: true,
+                by: ms(2_000)
+            })
+        );
+        // The new offset is the reference from now on
+        assert_eq!(detector.sample_with(ms(1_006_050), ms(14_000)), None);
+        assert_eq!(
+            detector.sample_with(ms(900_050), ms(15_000)),
+            Some(ClockStep {
+                forward: false,
+                by: ms(107_000)
+            })
+        );
+        assert_eq!(detector.steps(), 2);
+    }
+
+    #[test]
+    fn changes_up_to_threshold_are_ignored() {
+        let mut detector = ClockStepDetector::new(Duration::from_millis(100));
+        detector.sample_with(Duration::from_millis(5000), Duration::ZERO);
+        assert_eq!(
+            detector.sample_with(Duration::from_millis(5100), Duration::ZERO),
+            None
+        );
+        assert!(detector
+            .sample_with(Duration::from_millis(5201), Duration::ZERO)
+            .is_some());
+    }
+
+    #[test]
+    fn real_clocks_do_not_step() {
+        let mut detector = ClockStepDetector::new(Duration::from_millis(100));
+        assert_eq!(detector.sample(), None);
+        assert_eq!(detector.sample(), None);
+        assert_eq!(detector.steps(), 0);
+    }
+
+    #[test]
+    fn display() {
+        let step = ClockStep {
+            forward: false,
+            by: Duration::from_millis(1500),
+        };
+        assert_eq!(step.to_string(), "backward by 1s 500ms");
+    }
+}
@@ -0,0 +1,200 @@
+//! Conditions of the module shared with the partitions
+//!
+//! At the start of every major frame the [ConditionMonitor] evaluates what
+//! happened since the previous one, and every partition is handed the
+//! [ModuleConditions] affecting it:
+//!
+//! - [ModuleConditions::CHANNEL_DEGRADED] for the source and destinations of a
+//!   channel which dropped malformed messages during the last frame
+//! - [ModuleConditions::BRIDGE_DOWN] for the partitions connected to a channel
+//!   of the MQTT bridge, while it is not connected to its broker
+//! - [ModuleConditions::CLOCK_STEPPED] for all partitions, if the host clock
+//!   was stepped during the last frame
+//!
+//! As they are evaluated anew every frame, the conditions clear as soon as
+//! their cause resolved.
+
+use std::collections::{HashMap, HashSet};
+
+use a653rs_linux_core::conditions::ModuleConditions;
+use a653rs_linux_core::queuing::Queuing;
+use a653rs_linux_core::sampling::Sampling;
+
+use super::mqtt::MqttBridge;
+
+/// Tracks the channels between major frames to evaluate the conditions
+#[derive(Debug, Default)]
+pub(crate) struct ConditionMonitor {
+    /// Malformed messages every sampling channel dropped until the last frame
+    sampling_corrupted: HashMap<String, u64>,
+    /// Malformed messages every queuing channel dropped until the last frame
+    queuing_corrupted: HashMap<String, u64>,
+}
+
+/// The conditions of the module during the last major frame
+#[derive(Debug, Default)]
+pub(crate) struct FrameConditions {
+    degraded: HashSet<String>,
+    bridge_down: HashSet<String>,
+    clock_stepped: bool,
+}
+
+impl ConditionMonitor {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Evaluates the conditions since the last call
+    pub(crate) fn evaluate(
+        &mut self,
+        sampling: &HashMap<String, Sampling>,
+        queuing: &HashMap<String, Queuing>,
+        mqtt: Option<&MqttBridge>,
+        clock_stepped: bool,
+    ) -> FrameConditions {
+        let mut conditions = FrameConditions {
+            clock_stepped,
+            ..Default::default()
+        };
+        for (name, channel) in sampling {
+            if grew(&mut self.sampling_corrupted, name, channel.corrupted()) {
+                conditions
+                    .degraded
+                    .insert(channel.source_partition().to_string());
+                conditions
+                    .degraded
+                    .extend(channel.destination_partitions().map(str::to_string));
+            }
+        }
+        for (name, channel) in queuing {
+            if grew(&mut self.queuing_corrupted, name, channel.corrupted()) {
+                conditions
+                    .degraded
+                    .insert(channel.source_partition().to_string());
+                conditions
+                    .degraded
+                    .insert(channel.destination_partition().to_string());
+            }
+        }
+        if let Some(mqtt) = mqtt.filter(|mqtt| !mqtt.is_connected()) {
+            conditions.bridge_down = mqtt.bridged_partitions(sampling, queuing);
+        }
+        conditions
+    }
+}
+
+impl FrameConditions {
+    /// The conditions affecting `partition`
+    pub(crate) fn of(&self, partition: &str) -> ModuleConditions {
+        let mut conditions = ModuleConditions::NONE;
+        conditions.set(
+            ModuleConditions::CHANNEL_DEGRADED,
+            self.degraded.contains(partition),
+        );
+        conditions.set(
+            ModuleConditions::BRIDGE_DOWN,
+            self.bridge_down.contains(partition),
+        );
+        conditions.set(ModuleConditions::CLOCK_STEPPED, self.clock_stepped);
+        conditions
+    }
+}
+
+/// Records `count` for `name`, returning whether it grew since the last record
+fn grew(counts: &mut HashMap<String, u64>, name: &str, count: u64) -> bool {
+    let last = counts.insert(name.to_string(), count).unwrap_or(0);
+    count > last
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::fd::AsRawFd;
+
+    use a653rs_linux_core::sampling::SamplingSource;
+
+    use super::*;
+    use crate::hypervisor::config::Channel;
+
+    const CHANNELS: &str = r#"
+- !Sampling
+  msg_size: 5B
+  source: { partition: sensor, port: Fuel }
+  destination: [ { partition: ctrl, port: Fuel } ]
+- !Queuing
+  msg_size: 8B
+  msg_num: 2
+  source: { partition: ctrl, port: Log }
+  destination: { partition: logger, port: Log }
+"#;
+
+    fn channels() -> (HashMap<String, Sampling>, HashMap<String, Queuing>) {
+        let channels: Vec<Channel> = serde_yaml::from_str(CHANNELS).unwrap();
+        let mut sampling = HashMap::new();
+        let mut queuing = HashMap::new();
+        for channel in channels {
+            match channel {
+                Channel::Sampling(s) => {
+                    let s = Sampling::try_from(s).unwrap();
+                    sampling.insert(s.name(), s);
+                }
+                Channel::Queuing(q) => {
+                    let q = Queuing::try_from(q).unwrap();
+                    queuing.insert(q.name(), q);
+                }
+            }
+        }
+        (sampling, queuing)
+    }
+
+    #[test]
+    fn degraded_channels_set_conditions_until_resolved() {
+        let (mut sampling, queuing) = channels();
+        let mut monitor = ConditionMonitor::new();
+        let conditions = monitor.evaluate(&sampling, &queuing, None, false);
+        for partition in ["sensor", "ctrl", "logger"] {
+            assert_eq!(conditions.of(partition), ModuleConditions::NONE);
+        }
+
+        // A faulty source writes beyond the message size, which is only
+        // bounded by the padding of its slot
+        let fuel = sampling.get_mut("sensor:Fuel").unwrap();
+        let mut source = SamplingSource::try_from(fuel.source_fd().as_raw_fd()).unwrap();
+        assert!(source.write(b"too long!") > 5);
+        assert!(!fuel.swap());
+        assert_eq!(fuel.corrupted(), 1);
+
+        let conditions = monitor.evaluate(&sampling, &queuing, None, false);
+        let degraded = ModuleConditions::CHANNEL_DEGRADED;
+        assert_eq!(conditions.of("sensor"), degraded);
+        assert_eq!(conditions.of("ctrl"), degraded);
+        assert_eq!(conditions.of("logger"), ModuleConditions::NONE);
+
+        // Cleared in the next frame without further malformed messages
+        let fuel = sampling.get_mut("sensor:Fuel").unwrap();
+        source.write(b"fine");
+        assert!(fuel.swap());
+        let conditions = monitor.evaluate(&sampling, &queuing, None, false);
+        assert_eq!(conditions.of("ctrl"), ModuleConditions::NONE);
+    }
+
+    #[test]
+    fn clock_steps_affect_all_partitions() {
+        let (sampling, queuing) = channels();
+        let mut monitor = ConditionMonitor::new();
+        let conditions = monitor.evaluate(&sampling, &queuing, None, true);
+        assert_eq!(conditions.of("logger"), ModuleConditions::CLOCK_STEPPED);
+        assert_eq!(conditions.of("unknown"), ModuleConditions::CLOCK_STEPPED);
+        let conditions = monitor.evaluate(&sampling, &queuing, None, false);
+        assert!(conditions.of("logger").is_empty());
+    }
+
+    #[test]
+    fn counts_grow() {
+        let mut counts = HashMap::new();
+        assert!(!grew(&mut counts, "a", 0));
+        assert!(grew(&mut counts, "a", 2));
+        assert!(!grew(&mut counts, "a", 2));
+        assert!(grew(&mut counts, "b", 1));
+        assert!(grew(&mut counts, "a", 3));
+    }
+}
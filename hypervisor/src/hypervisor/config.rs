@@ -6,7 +6,10 @@
 //! most one partition that executes during the slot. A slot has a fixed
 //! duration and offset inside the MAF. A partition may occupy multiple slots
 //! inside the schedule, in which case it may be repeated using the `period`
-//! parameter. Also the MAF must be cleanly dividable by this period.
+//! parameter. The period must either divide the MAF, giving the partition
+//! several windows per MAF, or be a multiple of it. In the latter case the
+//! window is still part of every MAF, but the periodic process of the
+//! partition is only released in every n-th MAF.
 //!
 //! The hypervisor runs the executable file specified by `image` for each
 //! partition as a long-running process that is started and stopped according to
@@ -14,7 +17,9 @@
 //!
 //! Partitions can communicate using channels (Sampling and Queuing). The name
 //! of the ports by which a partition can access a channel is the same for all
-//! attached partitions.
+//! attached partitions. Messages are transferred right after the window of
+//! the source partition, unless a channel sets `transfer: frame_boundary`,
+//! which defers it to the end of the major frame.
 
 //! ```rust
 //! # use a653rs_linux_hypervisor::hypervisor::config::Config;
@@ -26,16 +31,24 @@
 //!     duration: 10ms
 //!     offset: 0ms
 //!     period: 500ms
-//!     image: target/x86_64-unknown-linux-musl/release/hello_part
+//!     image: hello_part
+//!     role: sender
 //!   - id: 1
 //!     name: Bar
 //!     offset: 100ms
 //!     duration: 10ms
-//!     image: target/x86_64-unknown-linux-musl/release/hello_part
+//!     image: hello_part
 //!     period: 1s
+//!     aperiodic_reserve: 20%
 //!     sockets:
 //!       - type: tcp_connect
 //!         address: 127.0.0.1:8083
+//!         options:
+//!           nodelay: true
+//!           recv_buffer: 256KB
+//!     partition_fs:
+//!       host_dir: /var/lib/bar
+//!       sync_interval: 10s
 //! channel:
 //!   - !Sampling
 //!     msg_size: 10KB
@@ -45,21 +58,38 @@
 //!     destination:
 //!       - partition: Bar
 //!         port: Hello
+//!     on_partition_restart: clear
 //! # ";
 //! # serde_yaml::from_str::<Config>(yaml).unwrap();
 //! ```
 
-use std::net::{SocketAddr, ToSocketAddrs};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
 use std::path::PathBuf;
 use std::time::Duration;
 
 use a653rs::bindings::PartitionId;
-use a653rs_linux_core::channel::{QueuingChannelConfig, SamplingChannelConfig};
+use a653rs_linux_core::cgroup::Controller;
+use a653rs_linux_core::channel::{
+    Criticality, PortConfig, QueuingChannelConfig, SamplingChannelConfig,
+};
 use a653rs_linux_core::error::{ResultExt, SystemError, TypedResult};
 use a653rs_linux_core::health::{ModuleInitHMTable, ModuleRunHMTable, PartitionHMTable};
+use a653rs_linux_core::name;
+use a653rs_linux_core::partition::{
+    PartitionConstants, MAX_CHANNELS, MAX_PARTITIONS, MAX_PORTS_PER_PARTITION,
+};
 use anyhow::anyhow;
+use bytesize::ByteSize;
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
 
+use crate::hypervisor::capture::CaptureConfig;
+use crate::hypervisor::cargo;
+use crate::hypervisor::layout::CgroupLayout;
+use crate::hypervisor::mqtt::MqttBridgeConfig;
+use crate::hypervisor::partition_fs::PartitionFsConfig;
+use crate::hypervisor::record::RecordingConfig;
 use crate::hypervisor::scheduler::{PartitionSchedule, ScheduledTimeframe};
 
 /// Main configuration of the hypervisor
@@ -77,6 +107,31 @@ pub struct Config {
     #[serde(default)]
     pub cgroup: PathBuf,
 
+    /// Placement of the cgroups of the hypervisor inside of `cgroup`, chosen
+    /// on the command line
+    #[serde(skip)]
+    pub cgroup_layout: CgroupLayout,
+
+    /// Whether the memory shared with the partitions is checked at every major
+    /// frame, chosen on the command line
+    #[serde(skip)]
+    pub verify_shared_state: bool,
+
+    /// Whether a configuration without partitions is run, idling in empty
+    /// major frames, chosen on the command line
+    #[serde(skip)]
+    pub allow_empty: bool,
+
+    /// Recording whose messages are written to the channels, chosen on the
+    /// command line
+    #[serde(skip)]
+    pub replay: Option<PathBuf>,
+
+    /// Directory the temporary files are created in, chosen on the command
+    /// line, see [RuntimeDir](super::runtime_dir::RuntimeDir)
+    #[serde(skip)]
+    pub runtime_dir: Option<PathBuf>,
+
     /// List of partitions
     ///
     /// The partitions contain the applications ran on the hypervisor.
@@ -90,13 +145,83 @@ pub struct Config {
     #[serde(default)]
     pub channel: Vec<Channel>,
 
-    // TODO fill in documentation
+    /// Fault in the memory of all channels before the schedule starts
+    ///
+    /// Otherwise the memory is only allocated on its first use, which delays
+    /// the first frames that use a channel.
+    #[serde(default)]
+    pub prefault_channels: bool,
+
+    /// Lock the memory of all channels into RAM, implies `prefault_channels`
+    ///
+    /// Requires a sufficient `RLIMIT_MEMLOCK` (see `ulimit -l`) or
+    /// `CAP_IPC_LOCK`.
+    #[serde(default)]
+    pub lock_channels: bool,
+
+    /// Bridge of channels to the topics of an MQTT broker, see
+    /// [mqtt](crate::hypervisor::mqtt)
+    #[serde(default)]
+    pub mqtt_bridge: Option<MqttBridgeConfig>,
+
+    /// Recording of the messages of channels to a file, see
+    /// [record](crate::hypervisor::record)
+    #[serde(default)]
+    pub recording: Option<RecordingConfig>,
+
+    /// Budgets of the space taken by the trace and the recording, see
+    /// [capture](crate::hypervisor::capture)
+    #[serde(default)]
+    pub capture: CaptureConfig,
+
+    /// File to which the telemetry of the partitions is written, see
+    /// [telemetry](crate::hypervisor::telemetry)
+    #[serde(default)]
+    pub telemetry_file: Option<PathBuf>,
+
+    /// What happens to partition windows shorter than
+    /// [Config::MIN_WINDOW_QUANTA] times the [Config::scheduler_quantum]
+    #[serde(default)]
+    pub min_window_enforcement: MinWindowEnforcement,
+
+    /// Time freezing and unfreezing a partition takes on this host, measured
+    /// when the hypervisor starts
+    ///
+    /// Windows much shorter than this leave no time for the partition to run.
+    /// It is not read from the configuration, but part of `--dump-config`.
+    #[serde(
+        default,
+        skip_deserializing,
+        skip_serializing_if = "Option::is_none",
+        with = "humantime_serde"
+    )]
+    pub scheduler_quantum: Option<Duration>,
+
+    /// Actions for errors while the hypervisor starts, see
+    /// [ModuleInitHMTable]
     #[serde(default)]
     pub hm_init_table: ModuleInitHMTable,
 
-    // TODO fill in documentation
+    /// Actions for errors while the schedule runs, see [ModuleRunHMTable]
     #[serde(default)]
     pub hm_run_table: ModuleRunHMTable,
+
+    /// Whether this is the configuration of [Config::solo], whose channels
+    /// lead to partitions which are not part of it
+    #[serde(skip)]
+    solo: bool,
+}
+
+/// How partition windows too short for this host are handled, see
+/// [Config::min_window_enforcement]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MinWindowEnforcement {
+    /// Reject the configuration
+    #[default]
+    Error,
+    /// Only log a warning
+    Warn,
 }
 
 /// Partition configuration
@@ -122,15 +247,22 @@ pub struct Partition {
     #[serde(with = "humantime_serde")]
     pub offset: Duration,
 
-    /// Repetition interval of the slice inside the MAF.
-    // TODO add an explanation
+    /// Repetition interval of the window, which is also the period of the
+    /// periodic process
+    ///
+    /// A period dividing the MaF ([Config::major_frame]) repeats the window
+    /// at `offset`, `offset + period`, … within each MaF. A period that is a
+    /// multiple of the MaF keeps the single window at `offset` in every MaF,
+    /// but releases the periodic process only in every `period / major_frame`
+    /// MaF, starting with the first one. The aperiodic process gets the
+    /// whole window in the others. Any other period is rejected.
     #[serde(with = "humantime_serde")]
     pub period: Duration,
 
-    /// Path to the executable of the partition
-    pub image: PathBuf,
+    /// Executable of the partition
+    pub image: Image,
 
-    // TODO
+    /// Actions for the errors of this partition, see [PartitionHMTable]
     #[serde(default)]
     pub hm_table: PartitionHMTable,
 
@@ -147,26 +279,402 @@ pub struct Partition {
 
     #[serde(default)]
     pub sockets: Vec<PosixSocket>,
+
+    /// Share of the partition window reserved for the aperiodic process
+    ///
+    /// Either a percentage of [Partition::duration] (e.g. `20%`) or an
+    /// absolute duration (e.g. `2ms`). The periodic process is frozen once
+    /// only the reserve is left of the window, so that the aperiodic process
+    /// is guaranteed to get at least this much time. If the periodic process
+    /// did not finish its period until then, a
+    /// [SystemError::TimeDurationExceeded] is raised.
+    #[serde(default)]
+    pub aperiodic_reserve: Option<AperiodicReserve>,
+
+    /// Network configuration of the partition
+    ///
+    /// Without it, the partition has its own network namespace containing only
+    /// a loopback interface.
+    #[serde(default)]
+    pub network: Option<Network>,
+
+    /// Fail the initialization of the partition if the ports it declares do
+    /// not match the channel configuration
+    ///
+    /// This also refuses to start the hypervisor if the
+    /// [port manifest](super::manifest) next to the image of the partition
+    /// does not match. Mismatches are always logged. Partitions which do not
+    /// declare their ports are not affected.
+    #[serde(default)]
+    pub strict_ports: bool,
+
+    /// Refuse to bring the partition to NORMAL if the requirements it declares
+    /// are not met by this configuration
+    ///
+    /// Requirements cover the period, the shortest window and the ports of the
+    /// partition. Mismatches are always logged. Partitions which do not declare
+    /// requirements are not affected.
+    #[serde(default)]
+    pub strict_requirements: bool,
+
+    /// Free-form role of the partition
+    ///
+    /// Lets multiple partitions running the same image decide what to do
+    /// without comparing partition ids. Roles need not be unique.
+    #[serde(default)]
+    pub role: Option<String>,
+
+    /// Standard input of the partition
+    ///
+    /// Without it, the partition reads from `/dev/null`.
+    #[serde(default, with = "stdin_format")]
+    pub stdin: Stdin,
+
+    /// Further environment variables passed on from the hypervisor
+    ///
+    /// Of the environment of the hypervisor, partitions only get
+    /// [FORWARDED_ENV] and the variables listed here, if they are set.
+    #[serde(default)]
+    pub forward_env: Vec<String>,
+
+    /// Maximum number of telemetry gauges of the partition
+    ///
+    /// Further names reported by the partition are rejected.
+    #[serde(default = "Partition::default_max_telemetry")]
+    pub max_telemetry: usize,
+
+    /// Size of the socket buffers for the calls of the partition to the
+    /// hypervisor, e.g. `1MB`
+    ///
+    /// Calls, like log records and telemetry, which do not fit into the
+    /// buffers are dropped and counted. The kernel limits the size to
+    /// `net.core.wmem_max` and `net.core.rmem_max`, besides the number of
+    /// calls in flight to `net.unix.max_dgram_qlen`. Without it, the buffers
+    /// keep the defaults of the kernel.
+    #[serde(default)]
+    pub ipc_buffer: Option<ByteSize>,
+
+    /// Grants the processes of the partition real-time scheduling, up to this
+    /// `SCHED_FIFO` priority (1 to 99)
+    ///
+    /// The processes are then scheduled with `SCHED_FIFO` priorities derived
+    /// from their APEX priority, instead of nice values. This requires the
+    /// hypervisor to be allowed to raise `RLIMIT_RTPRIO` of the partition. A
+    /// process that never waits keeps other threads on its CPU from running,
+    /// including those of the hypervisor.
+    #[serde(default)]
+    pub realtime: Option<u8>,
+
+    /// Files of the partition kept in a directory of the host across
+    /// restarts, see [partition_fs](super::partition_fs)
+    #[serde(default)]
+    pub partition_fs: Option<PartitionFsConfig>,
+
+    /// Count the log records of the partition instead of printing them
+    ///
+    /// Errors and mode transitions of the partition are still printed and
+    /// handed to the health monitor. The count is exported with the
+    /// [telemetry](super::telemetry), and the control socket toggles the flag
+    /// at run-time, see [control](super::control).
+    #[serde(default)]
+    pub suppress_messages: bool,
+}
+
+impl Partition {
+    fn default_max_telemetry() -> usize {
+        32
+    }
+}
+
+/// Environment variables of the hypervisor passed on to every partition
+pub const FORWARDED_ENV: &[&str] = &["RUST_LOG", "RUST_BACKTRACE", "TZ"];
+
+/// Executable of a partition
+///
+/// Either a path, or a package of a cargo workspace for development:
+///
+/// ```yaml
+/// image: { cargo: { package: hello_part, target: x86_64-unknown-linux-musl, profile: release } }
+/// ```
+///
+/// Packages are only built if the hypervisor is started with
+/// `--allow-cargo-build`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Image {
+    Path(PathBuf),
+    Cargo { cargo: CargoImage },
+}
+
+impl From<&str> for Image {
+    fn from(path: &str) -> Self {
+        Image::Path(path.into())
+    }
+}
+
+impl From<PathBuf> for Image {
+    fn from(path: PathBuf) -> Self {
+        Image::Path(path)
+    }
+}
+
+/// Binary of a cargo package, built by the hypervisor
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct CargoImage {
+    pub package: String,
+
+    /// Binary of the package, defaults to the name of the package
+    #[serde(default)]
+    pub bin: Option<String>,
+
+    /// Target triple, defaults to the host
+    #[serde(default)]
+    pub target: Option<String>,
+
+    #[serde(default = "CargoImage::default_profile")]
+    pub profile: String,
+
+    /// Manifest of the workspace, defaults to the one of the current directory
+    #[serde(default)]
+    pub manifest_path: Option<PathBuf>,
+}
+
+impl CargoImage {
+    /// Statically linked target of the architecture the hypervisor is built
+    /// for, which the examples and tests build their partition images for
+    #[cfg(target_arch = "aarch64")]
+    pub const HOST_MUSL_TARGET: &'static str = "aarch64-unknown-linux-musl";
+    #[cfg(target_arch = "arm")]
+    pub const HOST_MUSL_TARGET: &'static str = "armv7-unknown-linux-musleabihf";
+    #[cfg(target_arch = "x86")]
+    pub const HOST_MUSL_TARGET: &'static str = "i686-unknown-linux-musl";
+    #[cfg(not(any(target_arch = "aarch64", target_arch = "arm", target_arch = "x86")))]
+    pub const HOST_MUSL_TARGET: &'static str = "x86_64-unknown-linux-musl";
+
+    fn default_profile() -> String {
+        "dev".into()
+    }
+}
+
+/// Standard input of a partition
+///
+/// ```yaml
+/// stdin: null
+/// stdin: { file: /srv/input }
+/// stdin: pipe
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Stdin {
+    /// `/dev/null`
+    #[default]
+    Null,
+    /// File on the host
+    ///
+    /// It is opened read-only on every start of the partition, so that each
+    /// start reads it from the beginning.
+    File(PathBuf),
+    /// Pipe whose write end is kept by the hypervisor
+    ///
+    /// Data is written to it with the `stdin` command of the control socket.
+    /// The pipe outlives restarts of the partition, so data written before a
+    /// start is read after it.
+    Pipe,
+}
+
+/// [Stdin] as a map with a single key, or as a plain `null` or `pipe`
+mod stdin_format {
+    use serde::{Deserializer, Serializer};
+    use serde_yaml::with::singleton_map;
+
+    use super::Stdin;
+
+    pub fn serialize<S: Serializer>(stdin: &Stdin, serializer: S) -> Result<S::Ok, S::Error> {
+        singleton_map::serialize(stdin, serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Stdin, D::Error> {
+        // YAML null is a unit rather than the name of a variant
+        let stdin: Option<Stdin> = singleton_map::deserialize(deserializer)?;
+        Ok(stdin.unwrap_or_default())
+    }
+}
+
+/// Network configuration of a partition
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Network {
+    /// Connect the partition to a bridge on the host using a veth pair
+    pub veth: Option<VethNetwork>,
+}
+
+/// Veth pair between a partition and a bridge on the host
+///
+/// The end inside of the partition is called `eth0`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct VethNetwork {
+    /// Name of the existing bridge on the host
+    pub bridge: String,
+
+    /// Address of the partition in CIDR notation, e.g. `10.10.0.2/24`
+    pub address: String,
+
+    /// Default gateway of the partition
+    #[serde(default)]
+    pub gateway: Option<IpAddr>,
+}
+
+/// Reserved time of a partition window for the aperiodic process
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum AperiodicReserve {
+    /// Percentage of the partition window (0 to 100)
+    Percent(u8),
+    /// Absolute duration
+    Duration(Duration),
+}
+
+impl AperiodicReserve {
+    /// Returns the reserved time for a window of the given duration.
+    ///
+    /// The reserve never exceeds the window itself.
+    pub fn of(&self, window: Duration) -> Duration {
+        match self {
+            AperiodicReserve::Percent(p) => window
+                .checked_mul(u32::from(*p))
+                .map_or_else(|| window / 100 * u32::from(*p), |d| d / 100),
+            AperiodicReserve::Duration(d) => std::cmp::min(*d, window),
+        }
+    }
+}
+
+impl TryFrom<String> for AperiodicReserve {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let value = value.trim();
+        if let Some(percent) = value.strip_suffix('%') {
+            let percent: u8 = percent
+                .trim()
+                .parse()
+                .map_err(|e| anyhow!("invalid aperiodic reserve percentage {value:?}: {e}"))?;
+            if percent > 100 {
+                return Err(anyhow!(
+                    "aperiodic reserve may not exceed 100%, got {percent}%"
+                ));
+            }
+            return Ok(AperiodicReserve::Percent(percent));
+        }
+
+        humantime::parse_duration(value)
+            .map(AperiodicReserve::Duration)
+            .map_err(|e| anyhow!("invalid aperiodic reserve {value:?}: {e}"))
+    }
+}
+
+impl From<AperiodicReserve> for String {
+    fn from(value: AperiodicReserve) -> Self {
+        match value {
+            AperiodicReserve::Percent(p) => format!("{p}%"),
+            AperiodicReserve::Duration(d) => humantime::format_duration(d).to_string(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum PosixSocket {
-    TcpConnect { address: String },
-    Udp { address: String },
+    TcpConnect {
+        address: String,
+        #[serde(default)]
+        options: SocketOptions,
+    },
+    Udp {
+        address: String,
+        #[serde(default)]
+        options: SocketOptions,
+    },
+}
+
+impl PosixSocket {
+    pub fn options(&self) -> &SocketOptions {
+        match self {
+            PosixSocket::TcpConnect { options, .. } | PosixSocket::Udp { options, .. } => options,
+        }
+    }
+
+    /// Checks that the options of this socket can be applied
+    fn validate(&self) -> Result<(), String> {
+        let options = self.options();
+        if let (PosixSocket::Udp { .. }, Some(_)) = (self, options.nodelay) {
+            return Err("nodelay only applies to tcp sockets".into());
+        }
+        for (name, size) in [
+            ("send_buffer", options.send_buffer),
+            ("recv_buffer", options.recv_buffer),
+        ] {
+            if size.is_some_and(|size| size.as_u64() > SocketOptions::MAX_BUFFER) {
+                return Err(format!(
+                    "{name} exceeds the maximum of {}",
+                    ByteSize::b(SocketOptions::MAX_BUFFER)
+                ));
+            }
+        }
+        if let Some(device) = &options.bind_to_device {
+            if device.is_empty() || device.len() >= SocketOptions::IFNAMSIZ {
+                return Err(format!(
+                    "bind_to_device {device:?} is not an interface name"
+                ));
+            }
+        }
+        Ok(())
+    }
 }
 
 impl ToSocketAddrs for PosixSocket {
     type Iter = std::vec::IntoIter<SocketAddr>;
     fn to_socket_addrs(&self) -> std::io::Result<Self::Iter> {
         match self {
-            PosixSocket::TcpConnect { address } | PosixSocket::Udp { address } => {
+            PosixSocket::TcpConnect { address, .. } | PosixSocket::Udp { address, .. } => {
                 address.to_socket_addrs()
             }
         }
     }
 }
 
+/// Options the hypervisor sets on a socket before connecting or binding it
+///
+/// Options which are not given keep the defaults of the kernel. A partition
+/// may still change `nodelay`, the buffer sizes and `keepalive` of the socket
+/// it receives, with buffer sizes being limited by `net.core.rmem_max` and
+/// `net.core.wmem_max` for it as well as for the hypervisor. `reuse_addr` and
+/// `bind_to_device` only take effect before the socket is connected or bound,
+/// so they can only be set here.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct SocketOptions {
+    /// `TCP_NODELAY`, disabling Nagle's algorithm, for tcp sockets only
+    pub nodelay: Option<bool>,
+    /// `SO_SNDBUF`
+    pub send_buffer: Option<ByteSize>,
+    /// `SO_RCVBUF`
+    pub recv_buffer: Option<ByteSize>,
+    /// `SO_REUSEADDR`
+    pub reuse_addr: Option<bool>,
+    /// `SO_KEEPALIVE`
+    pub keepalive: Option<bool>,
+    /// `SO_BINDTODEVICE`, the name of a network interface
+    pub bind_to_device: Option<String>,
+}
+
+impl SocketOptions {
+    /// Largest buffer size, which the kernel doubles to fit into an `int`
+    pub const MAX_BUFFER: u64 = i32::MAX as u64 / 2;
+
+    /// Size of an interface name including its terminating zero
+    const IFNAMSIZ: usize = 16;
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum Channel {
     Queuing(QueuingChannelConfig),
@@ -187,6 +695,33 @@ impl Channel {
         }
         None
     }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Queuing(q) => q.name(),
+            Self::Sampling(s) => s.name(),
+        }
+    }
+
+    pub fn criticality(&self) -> Criticality {
+        match self {
+            Self::Queuing(q) => q.criticality,
+            Self::Sampling(s) => s.criticality,
+        }
+    }
+
+    /// Whether `partition` has a port of this channel
+    pub fn is_connected_to(&self, partition: &str) -> bool {
+        match self {
+            Self::Queuing(q) => {
+                q.source.partition == partition || q.destination.partition == partition
+            }
+            Self::Sampling(s) => {
+                s.source.partition == partition
+                    || s.destination.iter().any(|p| p.partition == partition)
+            }
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -196,38 +731,1421 @@ pub enum ModuleStates {
 }
 
 impl Config {
+    /// Upper bound for the number of partition windows in a major frame
+    ///
+    /// Keeps a misconfigured period from allocating an absurdly large
+    /// schedule.
+    const MAX_TIMEFRAMES: u128 = 1 << 16;
+
+    /// Lower bound for the major frame
+    ///
+    /// Shorter frames leave no time for the partitions and keep the hypervisor
+    /// busy scheduling.
+    const MIN_MAJOR_FRAME: Duration = Duration::from_millis(1);
+
+    /// Shortest partition window in multiples of the
+    /// [Config::scheduler_quantum]
+    ///
+    /// Every window freezes and unfreezes the processes of its partition, and
+    /// the partition needs time to run in between.
+    pub const MIN_WINDOW_QUANTA: u32 = 4;
+
+    /// Parses a configuration from `yaml`
+    ///
+    /// Configurations of earlier versions are still accepted, with a
+    /// deprecation warning for every legacy field.
+    /// `--dump-config` prints their modern form.
+    pub fn from_yaml(yaml: &str) -> TypedResult<Config> {
+        let mut value: Value = serde_yaml::from_str(yaml).typ(SystemError::Config)?;
+        let deprecated = modernize(&mut value)
+            .map_err(|e| anyhow!("unsupported legacy configuration: {e}"))
+            .typ(SystemError::Config)?;
+        if deprecated.is_empty() {
+            // Parsed from the text again, as only then errors name their location
+            return serde_yaml::from_str(yaml).typ(SystemError::Config);
+        }
+        for deprecation in deprecated {
+            warn!("Deprecated configuration: {deprecation}");
+        }
+        serde_yaml::from_value(value).typ(SystemError::Config)
+    }
+
+    /// Derives a configuration running only the partition `name`, for
+    /// debugging it in isolation
+    ///
+    /// The partition gets a single window spanning the whole major frame, so
+    /// it is only frozen briefly for the swaps of its channels at the end of
+    /// each major frame. Its channels are kept even though their other ends
+    /// are not running, so that creating its ports succeeds. Destination ports
+    /// simply never receive a message.
+    pub fn solo(&self, name: &str) -> TypedResult<Config> {
+        let Some(partition) = self.partitions.iter().find(|p| p.name == name) else {
+            return Err(anyhow!("partition {name:?} is not configured")).typ(SystemError::Config);
+        };
+        // Check the channels while all partitions are still known
+        self.validate_endpoints()?;
+        let partition = Partition {
+            duration: self.major_frame,
+            offset: Duration::ZERO,
+            period: self.major_frame,
+            ..partition.clone()
+        };
+        let channel = self
+            .channel
+            .iter()
+            .filter(|c| c.is_connected_to(name))
+            .cloned()
+            .collect_vec();
+        let mqtt_bridge = self.mqtt_bridge.clone().map(|mut bridge| {
+            bridge.retain_channels(&channel);
+            bridge
+        });
+        let recording = self.recording.clone().map(|mut recording| {
+            recording.retain_channels(&channel);
+            recording
+        });
+
+        Ok(Config {
+            partitions: vec![partition],
+            channel,
+            mqtt_bridge,
+            recording,
+            solo: true,
+            ..self.clone()
+        })
+    }
+
+    /// Controllers of the cgroups of the partitions needed by the features this
+    /// configuration uses, see
+    /// [CGroup::enable_controllers](a653rs_linux_core::cgroup::CGroup::enable_controllers)
+    ///
+    /// None of the current features needs one.
+    pub fn controllers(&self) -> Vec<Controller> {
+        Vec::new()
+    }
+
+    /// Builds the images of all partitions given as cargo packages, replacing
+    /// them by the paths of the built binaries
+    ///
+    /// Partitions sharing an image are built only once.
+    pub fn build_cargo_images(&mut self) -> TypedResult<()> {
+        let mut built: Vec<(CargoImage, PathBuf)> = Vec::new();
+        for partition in &mut self.partitions {
+            let Image::Cargo { cargo } = &partition.image else {
+                continue;
+            };
+            let bin = match built.iter().find(|(image, _)| image == cargo) {
+                Some((_, bin)) => bin.clone(),
+                None => {
+                    let bin = cargo::build(cargo)?;
+                    built.push((cargo.clone(), bin.clone()));
+                    bin
+                }
+            };
+            partition.image = Image::Path(bin);
+        }
+        Ok(())
+    }
+
+    /// Checks the schedule and all channels of this configuration without
+    /// creating any of them
+    pub fn validate(&self) -> TypedResult<()> {
+        self.validate_limits()?;
+        self.validate_names()?;
+        self.validate_endpoints()?;
+        self.validate_sockets()?;
+        self.validate_forward_env()?;
+        self.validate_realtime()?;
+        self.validate_windows()?;
+        self.generate_schedule()?;
+        for channel in &self.channel {
+            match channel {
+                Channel::Queuing(q) => q.validate()?,
+                Channel::Sampling(s) => s.validate()?,
+            }
+        }
+        if let Some(bridge) = &self.mqtt_bridge {
+            bridge.validate(&self.channel)?;
+        }
+        if let Some(recording) = &self.recording {
+            recording.validate(&self.channel)?;
+        }
+        self.capture.validate()?;
+        for p in &self.partitions {
+            if let Some(fs) = &p.partition_fs {
+                fs.validate(&p.name)?;
+            }
+            for warning in p.hm_table.warnings() {
+                warn!("HM table of partition {:?}: {warning}", p.name);
+            }
+        }
+        for warning in self.critical_channel_warnings() {
+            warn!("{warning}");
+        }
+        Ok(())
+    }
+
+    /// Errors of a partition which may precede a corruption of its channels
+    ///
+    /// Partition init and time duration errors are left out, as they are
+    /// ignored by default.
+    const CORRUPTION_PRECURSORS: [SystemError; 4] = [
+        SystemError::Segmentation,
+        SystemError::Panic,
+        SystemError::ApplicationError,
+        SystemError::FloatingPoint,
+    ];
+
+    /// Describes the module-critical channels connected to partitions which
+    /// ignore errors that may precede a corruption of the channel
+    fn critical_channel_warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let critical = self
+            .channel
+            .iter()
+            .filter(|c| c.criticality() == Criticality::Module);
+        for channel in critical {
+            for p in self
+                .partitions
+                .iter()
+                .filter(|p| channel.is_connected_to(&p.name))
+            {
+                let ignored = Self::CORRUPTION_PRECURSORS
+                    .into_iter()
+                    .filter(|err| p.hm_table.ignores(*err))
+                    .collect::<Vec<_>>();
+                if !ignored.is_empty() {
+                    warnings.push(format!(
+                        "partition {:?} ignores {ignored:?}, which may mask the cause of a corruption of its module-critical channel {}",
+                        p.name,
+                        channel.name()
+                    ));
+                }
+            }
+        }
+        warnings
+    }
+
+    /// Checks the number of partitions, channels and ports against the
+    /// maximum numbers supported
+    fn validate_limits(&self) -> TypedResult<()> {
+        let mut exceeded = Vec::new();
+        if self.partitions.len() > MAX_PARTITIONS {
+            exceeded.push(format!(
+                "{} partitions are configured, more than MAX_PARTITIONS ({MAX_PARTITIONS})",
+                self.partitions.len()
+            ));
+        }
+        if self.channel.len() > MAX_CHANNELS {
+            exceeded.push(format!(
+                "{} channels are configured, more than MAX_CHANNELS ({MAX_CHANNELS})",
+                self.channel.len()
+            ));
+        }
+        let ports = self
+            .channel
+            .iter()
+            .flat_map(|c| {
+                let (kind, ports): (_, Vec<&PortConfig>) = match c {
+                    Channel::Queuing(q) => ("queuing", vec![&q.source, &q.destination]),
+                    Channel::Sampling(s) => (
+                        "sampling",
+                        std::iter::once(&s.source).chain(&s.destination).collect(),
+                    ),
+                };
+                ports.into_iter().map(move |p| (&p.partition, kind))
+            })
+            .counts();
+        exceeded.extend(
+            ports
+                .into_iter()
+                .filter(|(_, n)| *n > MAX_PORTS_PER_PARTITION)
+                .sorted()
+                .map(|((partition, kind), n)| {
+                    format!(
+                        "partition {partition:?} has {n} {kind} ports, more than MAX_PORTS_PER_PARTITION ({MAX_PORTS_PER_PARTITION})"
+                    )
+                }),
+        );
+        if !exceeded.is_empty() {
+            return Err(anyhow!("limits exceeded:\n{}", exceeded.join("\n")))
+                .typ(SystemError::Config);
+        }
+        Ok(())
+    }
+
+    /// Checks all partition and port names, as they are used for paths and
+    /// file names
+    fn validate_names(&self) -> TypedResult<()> {
+        let partitions = self.partitions.iter().map(|p| ("partition", &p.name));
+        let ports = self.channel.iter().flat_map(|c| {
+            let ports: Vec<&PortConfig> = match c {
+                Channel::Queuing(q) => vec![&q.source, &q.destination],
+                Channel::Sampling(s) => std::iter::once(&s.source).chain(&s.destination).collect(),
+            };
+            ports
+                .into_iter()
+                .flat_map(|p| [("partition", &p.partition), ("port", &p.port)])
+        });
+
+        let mut invalid = partitions
+            .chain(ports)
+            .filter_map(|(kind, name)| name::check(name).map(|e| format!("{kind} {name:?} {e}")))
+            .unique()
+            .collect_vec();
+        // The hypervisor shares a delegated cgroup with the cgroups of the partitions
+        if self.cgroup_layout == CgroupLayout::Delegated {
+            invalid.extend(
+                self.partitions
+                    .iter()
+                    .filter(|p| p.name == CgroupLayout::SUPERVISOR)
+                    .map(|p| format!("partition {:?} is reserved for the hypervisor", p.name)),
+            );
+        }
+        if !invalid.is_empty() {
+            return Err(anyhow!("invalid names:\n{}", invalid.join("\n"))).typ(SystemError::Config);
+        }
+        Ok(())
+    }
+
+    /// Checks the options of the sockets of every partition
+    fn validate_sockets(&self) -> TypedResult<()> {
+        let mut invalid =
+            self.partitions
+                .iter()
+                .flat_map(|p| p.sockets.iter().map(move |s| (p, s)))
+                .filter_map(|(p, s)| {
+                    let address = match s {
+                        PosixSocket::TcpConnect { address, .. }
+                        | PosixSocket::Udp { address, .. } => address,
+                    };
+                    s.validate()
+                        .err()
+                        .map(|e| format!("socket {address} of partition {:?}: {e}", p.name))
+                })
+                .collect_vec();
+        invalid.extend(
+            self.partitions
+                .iter()
+                .filter(|p| {
+                    p.ipc_buffer
+                        .is_some_and(|size| size.as_u64() > SocketOptions::MAX_BUFFER)
+                })
+                .map(|p| {
+                    format!(
+                        "ipc_buffer of partition {:?} exceeds the maximum of {}",
+                        p.name,
+                        ByteSize::b(SocketOptions::MAX_BUFFER)
+                    )
+                }),
+        );
+        if !invalid.is_empty() {
+            return Err(anyhow!("invalid sockets:\n{}", invalid.join("\n")))
+                .typ(SystemError::Config);
+        }
+        Ok(())
+    }
+
+    /// Checks the environment variables forwarded to the partitions, which
+    /// may not replace those set by the hypervisor
+    fn validate_forward_env(&self) -> TypedResult<()> {
+        let reserved = [
+            PartitionConstants::PARTITION_CONSTANTS_FD,
+            PartitionConstants::PARTITION_ROLE,
+        ];
+        let invalid = self
+            .partitions
+            .iter()
+            .flat_map(|p| p.forward_env.iter().map(move |var| (p, var)))
+            .filter_map(|(p, var)| {
+                let problem = if var.is_empty() || var.contains(['=', '\0']) {
+                    "is not a variable name"
+                } else if reserved.contains(&var.as_str()) {
+                    "is set by the hypervisor"
+                } else {
+                    return None;
+                };
+                Some(format!("{var:?} of partition {:?} {problem}", p.name))
+            })
+            .collect_vec();
+        if !invalid.is_empty() {
+            return Err(anyhow!(
+                "invalid forwarded environment variables:\n{}",
+                invalid.join("\n")
+            ))
+            .typ(SystemError::Config);
+        }
+        Ok(())
+    }
+
+    /// Checks that the real-time scheduling granted to partitions is within
+    /// the priorities of `SCHED_FIFO`
+    fn validate_realtime(&self) -> TypedResult<()> {
+        let invalid = self
+            .partitions
+            .iter()
+            .filter(|p| {
+                p.realtime
+                    .is_some_and(|ceiling| !(1..=99).contains(&ceiling))
+            })
+            .map(|p| format!("{:?}", p.name))
+            .collect_vec();
+        if !invalid.is_empty() {
+            return Err(anyhow!(
+                "realtime of partitions {} is not a SCHED_FIFO priority (1 to 99)",
+                invalid.join(", ")
+            ))
+            .typ(SystemError::Config);
+        }
+        Ok(())
+    }
+
+    /// Checks the window of every partition against the measured
+    /// [Config::scheduler_quantum], if any
+    fn validate_windows(&self) -> TypedResult<()> {
+        let Some(quantum) = self.scheduler_quantum else {
+            return Ok(());
+        };
+        let min = quantum * Self::MIN_WINDOW_QUANTA;
+        let short = self
+            .partitions
+            .iter()
+            .filter(|p| p.duration < min)
+            .map(|p| format!("{:?}", p.name))
+            .collect_vec();
+        if short.is_empty() {
+            return Ok(());
+        }
+        let msg = format!(
+            "windows of partitions {} are shorter than {}, {} times the {} freezing and unfreezing a partition takes on this host",
+            short.join(", "),
+            humantime::Duration::from(min),
+            Self::MIN_WINDOW_QUANTA,
+            humantime::Duration::from(quantum)
+        );
+        match self.min_window_enforcement {
+            MinWindowEnforcement::Error => Err(anyhow!(msg)).typ(SystemError::Config),
+            MinWindowEnforcement::Warn => {
+                warn!("{msg}");
+                Ok(())
+            }
+        }
+    }
+
+    /// Checks that every channel only connects configured partitions
+    fn validate_endpoints(&self) -> TypedResult<()> {
+        if self.solo {
+            return Ok(());
+        }
+        let unknown = self
+            .channel
+            .iter()
+            .flat_map(|c| {
+                let (kind, ports): (_, Vec<&PortConfig>) = match c {
+                    Channel::Queuing(q) => ("queuing", vec![&q.source, &q.destination]),
+                    Channel::Sampling(s) => (
+                        "sampling",
+                        std::iter::once(&s.source).chain(&s.destination).collect(),
+                    ),
+                };
+                ports.into_iter().map(move |p| (kind, p))
+            })
+            .filter(|(_, p)| !self.partitions.iter().any(|q| q.name == p.partition))
+            .map(|(kind, p)| {
+                format!(
+                    "{kind} port {:?} belongs to the unknown partition {:?}",
+                    p.port, p.partition
+                )
+            })
+            .collect_vec();
+        if !unknown.is_empty() {
+            return Err(anyhow!("invalid channels:\n{}", unknown.join("\n")))
+                .typ(SystemError::Config);
+        }
+        Ok(())
+    }
+
     pub(crate) fn generate_schedule(&self) -> TypedResult<PartitionSchedule> {
-        // Verify Periods and Major Frame
-        let lcm_periods = self
+        // Verify Partitions, Periods, Durations and Major Frame
+        if self.partitions.is_empty() && !self.allow_empty {
+            return Err(anyhow!(
+                "no partitions are configured, pass --allow-empty to idle in empty major frames"
+            ))
+            .typ(SystemError::Config);
+        }
+        if self.major_frame.is_zero() {
+            return Err(anyhow!("major frame must not be zero")).typ(SystemError::Config);
+        }
+        if self.major_frame < Self::MIN_MAJOR_FRAME {
+            return Err(anyhow!(
+                "major frame of {:?} is shorter than the minimum of {:?}",
+                self.major_frame,
+                Self::MIN_MAJOR_FRAME
+            ))
+            .typ(SystemError::Config);
+        }
+        for p in &self.partitions {
+            if p.duration.is_zero() {
+                return Err(anyhow!("duration of partition {} must not be zero", p.name))
+                    .typ(SystemError::Config);
+            }
+            if p.period.is_zero() {
+                return Err(anyhow!("period of partition {} must not be zero", p.name))
+                    .typ(SystemError::Config);
+            }
+        }
+
+        // Periods dividing the major frame are repeated within it, while those
+        // spanning multiple major frames skip the release of the periodic process
+        let major_frame = self.major_frame.as_nanos();
+        for p in &self.partitions {
+            let period = p.period.as_nanos();
+            if !major_frame.is_multiple_of(period) && !period.is_multiple_of(major_frame) {
+                return Err(anyhow!(
+                    "period of partition {} neither divides the major frame nor is a multiple of it.\n\
+                    period: {:?}, major_frame: {:?}",
+                    p.name,
+                    p.period,
+                    self.major_frame
+                ))
+                .typ(SystemError::Config);
+            }
+        }
+
+        let num_timeframes: u128 = self
             .partitions
             .iter()
-            .map(|p| p.period.as_nanos())
-            .reduce(num::integer::lcm);
-        if let Some(lcm_periods) = lcm_periods {
-            if self.major_frame.as_nanos() % lcm_periods != 0 {
-                return Err(anyhow!("major frame is not a multiple of the least-common-multiple of all partition periods.\n\
-                lcm: {:?}, major_frame: {:?}", Duration::from_nanos(lcm_periods as u64), self.major_frame))
+            .map(|p| (major_frame / p.period.as_nanos()).max(1))
+            .sum();
+        if num_timeframes > Self::MAX_TIMEFRAMES {
+            return Err(anyhow!(
+                "schedule has {num_timeframes} partition windows per major frame, at most {} are supported",
+                Self::MAX_TIMEFRAMES
+            ))
+            .typ(SystemError::Config);
+        }
+
+        // Verify aperiodic reserves fit into their partition windows
+        for p in &self.partitions {
+            if let Some(AperiodicReserve::Duration(reserve)) = p.aperiodic_reserve {
+                if reserve > p.duration {
+                    return Err(anyhow!(
+                        "aperiodic reserve of partition {} exceeds its duration.\n\
+                    reserve: {reserve:?}, duration: {:?}",
+                        p.name,
+                        p.duration
+                    ))
                     .typ(SystemError::Config);
+                }
             }
         }
 
         // Generate Schedule
-        let timeframes = self
+        let mut timeframes = Vec::new();
+        for p in &self.partitions {
+            // Bounded by MAX_TIMEFRAMES
+            let pimf = (major_frame / p.period.as_nanos()).max(1) as u32;
+            let release_every = u64::try_from(p.period.as_nanos() / major_frame)
+                .unwrap_or(u64::MAX)
+                .max(1);
+            for i in 0..pimf {
+                let start = p
+                    .period
+                    .checked_mul(i)
+                    .and_then(|start| start.checked_add(p.offset));
+                let end = start.and_then(|start| start.checked_add(p.duration));
+                match (start, end) {
+                    (Some(start), Some(end)) if end <= self.major_frame => {
+                        timeframes.push(ScheduledTimeframe {
+                            start,
+                            end,
+                            partition: p.id,
+                            release_every,
+                        })
+                    }
+                    _ => {
+                        return Err(anyhow!(
+                            "window {i} of partition {} ends after the major frame.\n\
+                            offset: {:?}, period: {:?}, duration: {:?}, major_frame: {:?}",
+                            p.name,
+                            p.offset,
+                            p.period,
+                            p.duration,
+                            self.major_frame
+                        ))
+                        .typ(SystemError::Config)
+                    }
+                }
+            }
+        }
+
+        PartitionSchedule::from_timeframes(timeframes).typ(SystemError::PartitionConfig)
+    }
+}
+
+/// Rewrites the legacy fields of the parsed configuration `config` into
+/// their current form, returning a deprecation message for each of them
+///
+/// Earlier versions
+/// - named the executable of a partition `bin` instead of `image`
+/// - had the partitions as a set, or as a map by their name
+/// - placed the hypervisor in the cgroup `cgroup_root`/`cgroup_name`, which is
+///   chosen with `--cgroup` now
+///
+/// Legacy constructs without an unambiguous translation are errors.
+fn modernize(config: &mut Value) -> Result<Vec<String>, String> {
+    let mut deprecated = Vec::new();
+    let Some(config) = config.as_mapping_mut() else {
+        // Left to the deserialization to report
+        return Ok(deprecated);
+    };
+
+    for field in ["cgroup_root", "cgroup_name"] {
+        if let Some(value) = config.remove(field) {
+            let value = serde_yaml::to_string(&value).unwrap_or_default();
+            deprecated.push(format!(
+                "`{field}: {}` is ignored, choose the cgroup of the hypervisor with `--cgroup` instead",
+                value.trim_end()
+            ));
+        }
+    }
+
+    if let Some(partitions) = config.get_mut("partitions") {
+        if let Value::Tagged(tagged) = partitions {
+            // e.g. `!!set`
+            *partitions = tagged.value.clone();
+        }
+        if let Value::Mapping(map) = partitions {
+            *partitions = Value::Sequence(
+                map.iter()
+                    .map(|(key, value)| legacy_partition(key, value))
+                    .collect::<Result<_, _>>()?,
+            );
+            deprecated.push("`partitions` is a set or map, list the partitions instead".into());
+        }
+        for partition in partitions
+            .as_sequence_mut()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_mapping_mut)
+        {
+            let Some(bin) = partition.remove("bin") else {
+                continue;
+            };
+            let name = partition
+                .get("name")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_owned();
+            if partition.contains_key("image") {
+                return Err(format!(
+                    "partition {name:?} has both `bin` and `image`, remove `bin`"
+                ));
+            }
+            partition.insert("image".into(), bin);
+            deprecated.push(format!("`bin` of partition {name:?} is renamed to `image`"));
+        }
+    }
+
+    Ok(deprecated)
+}
+
+/// Turns the entry `key: value` of the legacy partition set or map into a
+/// partition
+fn legacy_partition(key: &Value, value: &Value) -> Result<Value, String> {
+    match (key, value) {
+        // An element of a set
+        (Value::Mapping(_), Value::Null) => Ok(key.clone()),
+        (Value::String(name), Value::Mapping(partition)) => {
+            let mut partition = partition.clone();
+            match partition.get("name") {
+                None => {
+                    partition.insert("name".into(), name.as_str().into());
+                }
+                Some(Value::String(other)) if other == name => {}
+                Some(_) => {
+                    return Err(format!(
+                        "partition {name:?} has a different `name`, remove it"
+                    ))
+                }
+            }
+            Ok(Value::Mapping(partition))
+        }
+        _ => Err("the partitions have an entry which is not a partition".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use a653rs_linux_core::channel::Criticality;
+    use a653rs_linux_core::error::SystemError;
+    use a653rs_linux_core::health::{ModuleInitHMTable, ModuleRunHMTable, PartitionHMTable};
+    use a653rs_linux_core::partition::{MAX_CHANNELS, MAX_PARTITIONS, MAX_PORTS_PER_PARTITION};
+    use bytesize::ByteSize;
+
+    use super::{
+        modernize, AperiodicReserve, CargoImage, CgroupLayout, Config, Image, MinWindowEnforcement,
+        PartitionConstants, SocketOptions, Stdin,
+    };
+
+    fn config(major_frame: &str, partitions: &[(&str, &str, &str)]) -> Config {
+        let mut yaml = format!("major_frame: {major_frame}\npartitions:\n");
+        for (i, (duration, offset, period)) in partitions.iter().enumerate() {
+            yaml += &format!(
+                "  - {{ id: {i}, name: p{i}, duration: {duration}, offset: {offset}, period: {period}, image: /bin/true }}\n"
+            );
+        }
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    #[test]
+    fn parse_aperiodic_reserve() {
+        assert_eq!(
+            AperiodicReserve::try_from("20%".to_string()).unwrap(),
+            AperiodicReserve::Percent(20)
+        );
+        assert_eq!(
+            AperiodicReserve::try_from("2ms".to_string()).unwrap(),
+            AperiodicReserve::Duration(Duration::from_millis(2))
+        );
+        assert!(AperiodicReserve::try_from("101%".to_string()).is_err());
+        assert!(AperiodicReserve::try_from("-1%".to_string()).is_err());
+        assert!(AperiodicReserve::try_from("lots".to_string()).is_err());
+    }
+
+    #[test]
+    fn aperiodic_reserve_of_window() {
+        let window = Duration::from_millis(10);
+        assert_eq!(
+            AperiodicReserve::Percent(20).of(window),
+            Duration::from_millis(2)
+        );
+        assert_eq!(AperiodicReserve::Percent(100).of(window), window);
+        assert_eq!(
+            AperiodicReserve::Duration(Duration::from_millis(3)).of(window),
+            Duration::from_millis(3)
+        );
+        assert_eq!(
+            AperiodicReserve::Duration(Duration::from_millis(30)).of(window),
+            window
+        );
+    }
+
+    #[test]
+    fn aperiodic_reserve_round_trip() {
+        for reserve in [
+            AperiodicReserve::Percent(35),
+            AperiodicReserve::Duration(Duration::from_micros(1500)),
+        ] {
+            let s: String = reserve.into();
+            assert_eq!(AperiodicReserve::try_from(s).unwrap(), reserve);
+        }
+    }
+
+    #[test]
+    fn solo_partition() {
+        let mut yaml = "major_frame: 1s\npartitions:\n".to_string();
+        for (i, offset) in ["0ms", "100ms", "200ms"].iter().enumerate() {
+            yaml += &format!(
+                "  - {{ id: {i}, name: p{i}, duration: 10ms, offset: {offset}, period: 1s, image: /bin/true }}\n"
+            );
+        }
+        yaml += "channel:\n";
+        for (source, destination) in [("p0", "p1"), ("p1", "p2"), ("p2", "p0")] {
+            yaml += &format!(
+                "  - !Sampling {{ msg_size: 8B, source: {{ partition: {source}, port: out }}, destination: [ {{ partition: {destination}, port: in }} ] }}\n"
+            );
+        }
+        let config: Config = serde_yaml::from_str(&yaml).unwrap();
+
+        let solo = config.solo("p1").unwrap();
+        solo.validate().unwrap();
+        assert_eq!(solo.partitions.len(), 1);
+        let schedule = solo.generate_schedule().unwrap();
+        let windows = schedule.iter().collect::<Vec<_>>();
+        assert_eq!(windows.len(), 1);
+        assert_eq!(windows[0].partition, 1);
+        assert_eq!(
+            (windows[0].start, windows[0].end),
+            (Duration::ZERO, Duration::from_secs(1))
+        );
+        // The channel between p2 and p0 is of no use to p1
+        assert_eq!(solo.channel.len(), 2);
+        assert!(solo.channel.iter().all(|c| c.is_connected_to("p1")));
+        solo.validate().unwrap();
+
+        assert!(config.solo("p3").is_err());
+    }
+
+    #[test]
+    fn unknown_partitions_in_channels() {
+        let mut yaml = "major_frame: 1s\npartitions:\n".to_string();
+        yaml +=
+            "  - { id: 0, name: p0, duration: 10ms, offset: 0ms, period: 1s, image: /bin/true }\n";
+        yaml += "channel:\n";
+        yaml += "  - !Sampling { msg_size: 8B, source: { partition: p0, port: out }, destination: [ { partition: gone, port: in } ] }\n";
+        let config: Config = serde_yaml::from_str(&yaml).unwrap();
+
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("\"gone\""), "{err}");
+        assert!(config.solo("p0").is_err());
+    }
+
+    /// A module of `partitions` partitions, with `ring` queuing channels from
+    /// each partition to the next and `sampling` channels from p0 to p1
+    fn module(partitions: usize, ring: usize, sampling: usize) -> Config {
+        let mut yaml = "major_frame: 1s\npartitions:\n".to_string();
+        for i in 0..partitions {
+            yaml += &format!(
+                "  - {{ id: {i}, name: p{i}, duration: 1ms, offset: {i}ms, period: 1s, image: /bin/true }}\n"
+            );
+        }
+        yaml += "channel:\n";
+        for i in 0..ring {
+            let (source, destination) = (i % partitions, (i + 1) % partitions);
+            yaml += &format!(
+                "  - !Queuing {{ msg_size: 8B, msg_num: 1, source: {{ partition: p{source}, port: qout{i} }}, destination: {{ partition: p{destination}, port: qin{i} }} }}\n"
+            );
+        }
+        for i in 0..sampling {
+            yaml += &format!(
+                "  - !Sampling {{ msg_size: 8B, source: {{ partition: p0, port: out{i} }}, destination: [ {{ partition: p1, port: in{i} }} ] }}\n"
+            );
+        }
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    #[test]
+    fn limits() {
+        module(
+            MAX_PARTITIONS,
+            MAX_CHANNELS - MAX_PORTS_PER_PARTITION,
+            MAX_PORTS_PER_PARTITION,
+        )
+        .validate()
+        .unwrap();
+        // Every partition of the ring has as many queuing ports as channels
+        module(2, MAX_PORTS_PER_PARTITION, 0).validate().unwrap();
+
+        let err = module(MAX_PARTITIONS + 1, 0, 0).validate().unwrap_err();
+        assert_eq!(err.err(), SystemError::Config);
+        assert!(
+            err.to_string()
+                .contains("257 partitions are configured, more than MAX_PARTITIONS (256)"),
+            "{err}"
+        );
+
+        let err = module(MAX_PARTITIONS, MAX_CHANNELS + 1, 0)
+            .validate()
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("1025 channels are configured, more than MAX_CHANNELS (1024)"),
+            "{err}"
+        );
+        assert!(!err.contains("MAX_PORTS_PER_PARTITION"), "{err}");
+
+        let err = module(2, 0, MAX_PORTS_PER_PARTITION + 1)
+            .validate()
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains(
+                "partition \"p0\" has 65 sampling ports, more than MAX_PORTS_PER_PARTITION (64)"
+            ),
+            "{err}"
+        );
+        assert!(
+            err.contains("partition \"p1\" has 65 sampling ports"),
+            "{err}"
+        );
+
+        let err = module(2, MAX_PORTS_PER_PARTITION + 2, 0)
+            .validate()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("has 66 queuing ports"), "{err}");
+    }
+
+    #[test]
+    fn valid_schedule() {
+        let config = config("1s", &[("10ms", "0ms", "500ms"), ("10ms", "100ms", "1s")]);
+        config.validate().unwrap();
+        assert_eq!(config.generate_schedule().unwrap().iter().count(), 3);
+    }
+
+    #[test]
+    fn invalid_schedules_are_errors() {
+        // Zero period, duration and major frame
+        assert!(config("1s", &[("10ms", "0ms", "0s")]).validate().is_err());
+        assert!(config("1s", &[("0s", "0ms", "1s")]).validate().is_err());
+        assert!(config("0s", &[("10ms", "0ms", "1s")]).validate().is_err());
+        // Huge periods, which neither divide nor are multiples of the major frame
+        let config_lcm = config(
+            "1s",
+            &[
+                ("1ns", "0ns", "18446744073709551557ns"),
+                ("1ns", "1ns", "18446744073709551533ns"),
+                ("1ns", "2ns", "18446744073709551521ns"),
+            ],
+        );
+        assert!(config_lcm.validate().is_err());
+        // Far too many windows
+        assert!(config("1s", &[("1ns", "0ns", "1ns")]).validate().is_err());
+        // Windows beyond the major frame, overflowing the offset
+        assert!(config("1s", &[("10ms", "995ms", "1s")]).validate().is_err());
+        let mut huge = config("1s", &[("10ms", "0ms", "1s")]);
+        huge.partitions[0].offset = Duration::MAX;
+        assert!(huge.validate().is_err());
+        huge.partitions[0].offset = Duration::ZERO;
+        huge.partitions[0].duration = Duration::MAX;
+        assert!(huge.validate().is_err());
+    }
+
+    #[test]
+    fn degenerate_configs_are_errors() {
+        let mut empty = config("1s", &[("10ms", "0ms", "1s")]);
+        empty.partitions.clear();
+        let cases = [
+            (empty.clone(), "no partitions are configured"),
+            (
+                config("0s", &[("10ms", "0ms", "1s")]),
+                "major frame must not be zero",
+            ),
+            (
+                config("999us", &[("10us", "0ms", "999us")]),
+                "major frame of 999µs is shorter than the minimum of 1ms",
+            ),
+            (
+                config("1s", &[("10ms", "0ms", "1s"), ("0s", "20ms", "1s")]),
+                "duration of partition p1 must not be zero",
+            ),
+            (
+                config("1s", &[("10ms", "0ms", "0s")]),
+                "period of partition p0 must not be zero",
+            ),
+        ];
+        for (config, expected) in cases {
+            let err = config.validate().unwrap_err();
+            assert_eq!(err.err(), SystemError::Config);
+            assert!(err.to_string().contains(expected), "{err}");
+        }
+
+        // Empty modules are only run on request
+        empty.allow_empty = true;
+        empty.validate().unwrap();
+        assert!(empty.generate_schedule().unwrap().iter().next().is_none());
+        assert!(config("1ms", &[("1ms", "0ms", "1ms")]).validate().is_ok());
+    }
+
+    #[test]
+    fn periods_and_major_frame() {
+        // Periods dividing the major frame repeat the window within it
+        let schedule = config("1s", &[("10ms", "0ms", "250ms"), ("10ms", "20ms", "1s")])
+            .generate_schedule()
+            .unwrap();
+        let windows = schedule.iter().map(|w| (w.start, w.release_every));
+        assert_eq!(
+            windows.collect::<Vec<_>>(),
+            [
+                (Duration::ZERO, 1),
+                (Duration::from_millis(20), 1),
+                (Duration::from_millis(250), 1),
+                (Duration::from_millis(500), 1),
+                (Duration::from_millis(750), 1),
+            ]
+        );
+
+        // Multiples of the major frame keep a single window, releasing the periodic
+        // process every few frames
+        let config = config("1s", &[("10ms", "0ms", "3s"), ("10ms", "20ms", "500ms")]);
+        config.validate().unwrap();
+        let schedule = config.generate_schedule().unwrap();
+        let p0 = schedule
+            .iter()
+            .filter(|w| w.partition == 0)
+            .collect::<Vec<_>>();
+        assert_eq!(p0.len(), 1);
+        assert_eq!(p0[0].release_every, 3);
+        assert!(p0[0].releases_in(0) && p0[0].releases_in(3));
+        assert!(!p0[0].releases_in(1) && !p0[0].releases_in(2));
+    }
+
+    #[test]
+    fn periods_unrelated_to_major_frame_are_errors() {
+        for period in ["300ms", "1500ms"] {
+            let config = config("1s", &[("10ms", "0ms", "1s"), ("10ms", "20ms", period)]);
+            let err = config.validate().unwrap_err();
+            assert_eq!(err.err(), SystemError::Config);
+            let msg = err.to_string();
+            assert!(msg.contains("period of partition p1"), "{msg}");
+            assert!(
+                msg.contains(&format!(
+                    "period: {:?}, major_frame: 1s",
+                    humantime::parse_duration(period).unwrap()
+                )),
+                "{msg}"
+            );
+        }
+    }
+
+    #[test]
+    fn empty_hm_tables_are_defaults() {
+        let yaml = r#"
+major_frame: 1s
+hm_init_table: {}
+hm_run_table: {}
+partitions:
+  - { id: 0, name: a, duration: 10ms, offset: 0ms, period: 1s, image: /bin/true, hm_table: {} }
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.hm_init_table, ModuleInitHMTable::default());
+        assert_eq!(config.hm_run_table, ModuleRunHMTable::default());
+        assert_eq!(config.partitions[0].hm_table, PartitionHMTable::default());
+
+        // The dumped configuration lists every entry and reads back the same
+        let dump = serde_yaml::to_string(&config).unwrap();
+        assert!(dump.contains("time_duration_exceeded"), "{dump}");
+        let dumped: Config = serde_yaml::from_str(&dump).unwrap();
+        assert_eq!(dumped.hm_init_table, config.hm_init_table);
+        assert_eq!(dumped.hm_run_table, config.hm_run_table);
+        assert_eq!(dumped.partitions[0].hm_table, config.partitions[0].hm_table);
+    }
+
+    #[test]
+    fn critical_channels_warn_about_ignored_errors() {
+        let yaml = r#"
+major_frame: 1s
+partitions:
+  - { id: 0, name: ctrl, duration: 10ms, offset: 0ms, period: 1s, image: /bin/true, hm_table: { panic: !Module Ignore } }
+  - { id: 1, name: act, duration: 10ms, offset: 10ms, period: 1s, image: /bin/true }
+  - { id: 2, name: log, duration: 10ms, offset: 20ms, period: 1s, image: /bin/true, hm_table: { segmentation: !Module Ignore } }
+channel:
+  - !Sampling
+    msg_size: 8B
+    source: { partition: ctrl, port: Command }
+    destination: [ { partition: act, port: Command } ]
+    criticality: module
+  - !Sampling
+    msg_size: 8B
+    source: { partition: log, port: Status }
+    destination: [ { partition: act, port: Status } ]
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.channel[0].criticality(), Criticality::Module);
+        assert_eq!(config.channel[1].criticality(), Criticality::Partition);
+        let warnings = config.critical_channel_warnings();
+        assert_eq!(warnings.len(), 1, "{warnings:?}");
+        assert!(
+            warnings[0].contains("\"ctrl\" ignores [Panic]"),
+            "{warnings:?}"
+        );
+        assert!(warnings[0].contains("channel Command"), "{warnings:?}");
+    }
+
+    #[test]
+    fn optional_role() {
+        let config = config("1s", &[("10ms", "0ms", "1s")]);
+        assert_eq!(config.partitions[0].role, None);
+
+        let yaml = r#"
+major_frame: 1s
+partitions:
+  - { id: 0, name: a, duration: 10ms, offset: 0ms, period: 1s, image: /bin/true, role: sender }
+  - { id: 1, name: b, duration: 10ms, offset: 10ms, period: 1s, image: /bin/true, role: sender }
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        config.validate().unwrap();
+        assert!(config
             .partitions
             .iter()
-            .flat_map(|p| {
-                let pimf = (self.major_frame.as_nanos() / p.period.as_nanos()) as u32;
-                (0..pimf).map(|i| {
-                    let start = p.offset + (p.period * i);
-                    ScheduledTimeframe {
-                        start,
-                        end: start + p.duration,
-                        partition: p.id,
-                    }
-                })
-            })
+            .all(|p| p.role.as_deref() == Some("sender")));
+    }
+
+    #[test]
+    fn stdin_sources() {
+        let config = config("1s", &[("10ms", "0ms", "1s")]);
+        assert_eq!(config.partitions[0].stdin, Stdin::Null);
+
+        let yaml = r#"
+major_frame: 1s
+partitions:
+  - { id: 0, name: a, duration: 10ms, offset: 0ms, period: 1s, image: /bin/true, stdin: null }
+  - { id: 1, name: b, duration: 10ms, offset: 10ms, period: 1s, image: /bin/true, stdin: { file: /tmp/input } }
+  - { id: 2, name: c, duration: 10ms, offset: 20ms, period: 1s, image: /bin/true, stdin: pipe }
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        let stdin = config
+            .partitions
+            .iter()
+            .map(|p| &p.stdin)
             .collect::<Vec<_>>();
+        assert_eq!(
+            stdin,
+            [
+                &Stdin::Null,
+                &Stdin::File("/tmp/input".into()),
+                &Stdin::Pipe
+            ]
+        );
 
-        PartitionSchedule::from_timeframes(timeframes).typ(SystemError::PartitionConfig)
+        // Dumped configurations read the same
+        let dumped: Config =
+            serde_yaml::from_str(&serde_yaml::to_string(&config).unwrap()).unwrap();
+        assert_eq!(
+            dumped
+                .partitions
+                .iter()
+                .map(|p| &p.stdin)
+                .collect::<Vec<_>>(),
+            stdin
+        );
+
+        let yaml = yaml.replace("stdin: pipe", "stdin: socket");
+        assert!(serde_yaml::from_str::<Config>(&yaml).is_err());
+    }
+
+    #[test]
+    fn forwarded_environment() {
+        let config = config("1s", &[("10ms", "0ms", "1s")]);
+        assert!(config.partitions[0].forward_env.is_empty());
+
+        let yaml = r#"
+major_frame: 1s
+partitions:
+  - { id: 0, name: a, duration: 10ms, offset: 0ms, period: 1s, image: /bin/true, forward_env: [MY_VAR] }
+"#;
+        let mut config: Config = serde_yaml::from_str(yaml).unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.partitions[0].forward_env, ["MY_VAR"]);
+
+        for var in ["", "A=B", PartitionConstants::PARTITION_ROLE] {
+            config.partitions[0].forward_env = vec![var.into()];
+            let err = format!("{:?}", config.validate().unwrap_err());
+            assert!(err.contains("forwarded environment"), "{err}");
+        }
+    }
+
+    #[test]
+    fn telemetry_settings() {
+        let config = config("1s", &[("10ms", "0ms", "1s")]);
+        assert_eq!(config.telemetry_file, None);
+        assert_eq!(config.partitions[0].max_telemetry, 32);
+        assert!(!config.partitions[0].suppress_messages);
+
+        let yaml = r#"
+major_frame: 1s
+telemetry_file: /var/lib/node_exporter/a653rs.prom
+partitions:
+  - { id: 0, name: a, duration: 10ms, offset: 0ms, period: 1s, image: /bin/true, max_telemetry: 4, suppress_messages: true }
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(
+            config.telemetry_file,
+            Some(PathBuf::from("/var/lib/node_exporter/a653rs.prom"))
+        );
+        assert_eq!(config.partitions[0].max_telemetry, 4);
+        assert!(config.partitions[0].suppress_messages);
+    }
+
+    #[test]
+    fn ipc_buffer() {
+        let mut config = config("1s", &[("10ms", "0ms", "1s")]);
+        assert_eq!(config.partitions[0].ipc_buffer, None);
+
+        let yaml = r#"
+major_frame: 1s
+partitions:
+  - { id: 0, name: a, duration: 10ms, offset: 0ms, period: 1s, image: /bin/true, ipc_buffer: 1MB }
+"#;
+        let parsed: Config = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(parsed.partitions[0].ipc_buffer, Some(ByteSize::mb(1)));
+
+        config.partitions[0].ipc_buffer = Some(ByteSize::gib(2));
+        let err = format!("{:?}", config.validate().unwrap_err());
+        assert!(err.contains("ipc_buffer of partition"), "{err}");
+    }
+
+    #[test]
+    fn realtime() {
+        let mut config = config("1s", &[("10ms", "0ms", "1s"), ("10ms", "500ms", "1s")]);
+        assert_eq!(config.partitions[0].realtime, None);
+        config.validate().unwrap();
+
+        config.partitions[0].realtime = Some(99);
+        config.validate().unwrap();
+
+        config.partitions[0].realtime = Some(0);
+        config.partitions[1].realtime = Some(100);
+        let err = format!("{:?}", config.validate().unwrap_err());
+        assert!(err.contains("realtime of partitions"), "{err}");
+    }
+
+    #[test]
+    fn windows_shorter_than_quantum() {
+        let mut config = config("1s", &[("50us", "0ms", "1s"), ("10ms", "500ms", "1s")]);
+        // Not measured, e.g. without a cgroup
+        config.validate().unwrap();
+
+        config.scheduler_quantum = Some(Duration::from_micros(300));
+        let err = format!("{:?}", config.validate().unwrap_err());
+        assert!(err.contains("windows of partitions"), "{err}");
+
+        config.min_window_enforcement = MinWindowEnforcement::Warn;
+        config.validate().unwrap();
+
+        let dump = serde_yaml::to_string(&config).unwrap();
+        assert!(dump.contains("scheduler_quantum: 300us"), "{dump}");
+        assert!(dump.contains("min_window_enforcement: warn"), "{dump}");
+        // Always measured again
+        let parsed: Config = serde_yaml::from_str(&dump).unwrap();
+        assert_eq!(parsed.scheduler_quantum, None);
+    }
+
+    #[test]
+    fn cargo_images() {
+        let yaml = r#"
+major_frame: 1s
+partitions:
+  - { id: 0, name: a, duration: 10ms, offset: 0ms, period: 1s, image: hello_part }
+  - { id: 1, name: b, duration: 10ms, offset: 10ms, period: 1s, image: { cargo: { package: hello_part, target: x86_64-unknown-linux-musl, profile: release } } }
+  - { id: 2, name: c, duration: 10ms, offset: 20ms, period: 1s, image: { cargo: { package: ping_client } } }
+"#;
+        let mut config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.partitions[0].image, Image::from("hello_part"));
+        assert_eq!(
+            config.partitions[1].image,
+            Image::Cargo {
+                cargo: CargoImage {
+                    package: "hello_part".into(),
+                    bin: None,
+                    target: Some("x86_64-unknown-linux-musl".into()),
+                    profile: "release".into(),
+                    manifest_path: None,
+                }
+            }
+        );
+        let Image::Cargo { cargo } = &config.partitions[2].image else {
+            panic!("expected a cargo image");
+        };
+        assert_eq!(cargo.profile, "dev");
+
+        // Typos are not silently taken for a path
+        let typo = yaml.replace("package: ping_client", "pakage: ping_client");
+        assert!(serde_yaml::from_str::<Config>(&typo).is_err());
+
+        // Paths are kept as they are
+        config.partitions.truncate(1);
+        config.build_cargo_images().unwrap();
+        assert_eq!(config.partitions[0].image, Image::from("hello_part"));
+    }
+
+    #[test]
+    fn invalid_names_are_errors() {
+        for name in ["", ".", "..", "foo/bar", "a b"] {
+            let mut config = config("1s", &[("10ms", "0ms", "1s")]);
+            config.partitions[0].name = name.into();
+            let err = config.validate().unwrap_err();
+            assert!(format!("{err:?}").contains(&format!("{name:?}")), "{name}");
+        }
+
+        let yaml = r#"
+major_frame: 1s
+partitions:
+  - { id: 0, name: a, duration: 10ms, offset: 0ms, period: 1s, image: /bin/true }
+channel:
+  - !Sampling
+    msg_size: 1KB
+    source: { partition: a, port: ../out }
+    destination: [ { partition: b/c, port: in } ]
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        let err = format!("{:?}", config.validate().unwrap_err());
+        assert!(err.contains("port \"../out\""), "{err}");
+        assert!(err.contains("partition \"b/c\""), "{err}");
+    }
+
+    #[test]
+    fn supervisor_is_reserved_in_delegated_cgroups() {
+        let mut config = config("1s", &[("10ms", "0ms", "1s")]);
+        config.partitions[0].name = CgroupLayout::SUPERVISOR.into();
+        config.validate().unwrap();
+
+        config.cgroup_layout = CgroupLayout::Delegated;
+        let err = format!("{:?}", config.validate().unwrap_err());
+        assert!(err.contains("reserved for the hypervisor"), "{err}");
+    }
+
+    #[test]
+    fn example_configs_are_valid() {
+        for example in [
+            "dev_random/dev_random.yaml",
+            "fuel_tank/fuel_tank.yaml",
+            "hello_part/hello_part.yaml",
+            "hello_part_no_macros/hello_part_no_macros.yaml",
+            "ping/ping.yaml",
+            "ping_queue/ping_queue.yaml",
+            "redirect_stdio/redirect_stdio.yaml",
+        ] {
+            let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../examples/").to_owned() + example;
+            let yaml = std::fs::read_to_string(&path).unwrap();
+            let config: Config = serde_yaml::from_str(&yaml).unwrap();
+            config
+                .validate()
+                .unwrap_or_else(|e| panic!("{path}: {e:?}"));
+        }
+    }
+
+    #[test]
+    fn invalid_channels_are_errors() {
+        let yaml = r#"
+major_frame: 1s
+partitions: []
+channel:
+  - !Queuing
+    msg_size: 1KB
+    msg_num: 0
+    source: { partition: a, port: out }
+    destination: { partition: b, port: in }
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn socket_options() {
+        let yaml = r#"
+major_frame: 1s
+partitions:
+  - id: 0
+    name: a
+    duration: 10ms
+    offset: 0ms
+    period: 1s
+    image: a
+    sockets:
+      - type: tcp_connect
+        address: 127.0.0.1:8083
+        options:
+          nodelay: true
+          recv_buffer: 256KB
+          bind_to_device: lo
+      - type: udp
+        address: 127.0.0.1:8084
+channel: []
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        config.validate().unwrap();
+        let sockets = &config.partitions[0].sockets;
+        assert_eq!(sockets[0].options().nodelay, Some(true));
+        assert_eq!(sockets[0].options().recv_buffer, Some(ByteSize::kb(256)));
+        assert_eq!(sockets[0].options().bind_to_device.as_deref(), Some("lo"));
+        assert_eq!(sockets[1].options(), &SocketOptions::default());
+
+        let unknown = yaml.replace("nodelay: true", "no_delay: true");
+        assert!(serde_yaml::from_str::<Config>(&unknown).is_err());
+
+        for invalid in [
+            yaml.replace(
+                "type: udp",
+                "type: udp\n        options: { nodelay: false }",
+            ),
+            yaml.replace("recv_buffer: 256KB", "recv_buffer: 2GB"),
+            yaml.replace(
+                "bind_to_device: lo",
+                "bind_to_device: a_very_long_interface",
+            ),
+        ] {
+            let config: Config = serde_yaml::from_str(&invalid).unwrap();
+            let err = config.validate().unwrap_err();
+            assert_eq!(err.err(), SystemError::Config);
+        }
+    }
+
+    #[test]
+    fn legacy_configs_are_translated() {
+        let testdata = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/legacy/");
+        let legacy = std::fs::read_to_string(testdata.to_owned() + "hello_part.yaml").unwrap();
+        let modern =
+            std::fs::read_to_string(testdata.to_owned() + "hello_part_modern.yaml").unwrap();
+
+        let mut value = serde_yaml::from_str(&legacy).unwrap();
+        let deprecated = modernize(&mut value).unwrap();
+        assert_eq!(deprecated.len(), 5, "{deprecated:?}");
+        assert!(deprecated[0].contains("`cgroup_root: /sys/fs/cgroup` is ignored"));
+        assert!(deprecated[3].contains("`bin` of partition \"Foo\""));
+
+        let mut value = serde_yaml::from_str(&modern).unwrap();
+        assert!(modernize(&mut value).unwrap().is_empty());
+
+        let legacy = Config::from_yaml(&legacy).unwrap();
+        legacy.validate().unwrap();
+        assert_eq!(
+            serde_yaml::to_string(&legacy).unwrap(),
+            serde_yaml::to_string(&Config::from_yaml(&modern).unwrap()).unwrap()
+        );
+    }
+
+    #[test]
+    fn legacy_partition_sets() {
+        let yaml = r#"
+major_frame: 1s
+partitions:
+  ? { id: 0, name: a, duration: 10ms, offset: 0ms, period: 1s, bin: /bin/true }
+  ? { id: 1, name: b, duration: 10ms, offset: 20ms, period: 1s, bin: /bin/true }
+"#;
+        let config = Config::from_yaml(yaml).unwrap();
+        assert_eq!(
+            config
+                .partitions
+                .iter()
+                .map(|p| &p.name)
+                .collect::<Vec<_>>(),
+            ["a", "b"]
+        );
+        assert!(
+            matches!(&config.partitions[1].image, Image::Path(p) if p.to_str() == Some("/bin/true"))
+        );
+    }
+
+    #[test]
+    fn unsupported_legacy_configs_are_errors() {
+        let yaml = r#"
+major_frame: 1s
+partitions:
+  a: { id: 0, duration: 10ms, offset: 0ms, period: 1s, bin: /bin/true }
+"#;
+        Config::from_yaml(yaml).unwrap();
+        for (invalid, msg) in [
+            (
+                yaml.replace("bin: /bin/true", "bin: /bin/true, image: /bin/false"),
+                "partition \"a\" has both `bin` and `image`",
+            ),
+            (
+                yaml.replace("id: 0,", "id: 0, name: b,"),
+                "partition \"a\" has a different `name`",
+            ),
+            (
+                yaml.replace("a: {", "a: [")
+                    .replace("/bin/true }", "/bin/true ]"),
+                "not a partition",
+            ),
+        ] {
+            let err = Config::from_yaml(&invalid).unwrap_err();
+            assert_eq!(err.err(), SystemError::Config);
+            let err = err.to_string();
+            assert!(err.contains(msg), "{err}");
+        }
+    }
+
+    #[test]
+    fn aperiodic_reserve_of_huge_window() {
+        assert_eq!(
+            AperiodicReserve::Percent(50).of(Duration::MAX),
+            Duration::MAX / 100 * 50
+        );
     }
 }
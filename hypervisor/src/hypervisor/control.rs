@@ -0,0 +1,257 @@
+//! Control socket of the hypervisor
+//!
+//! Started with `--control-socket <path>`, the hypervisor receives commands as
+//! datagrams on a Unix socket in between scheduling steps:
+//!
+//! ```sh
+//! echo "extend 30s" | socat -t 1 - UNIX-SENDTO:/run/a653rs.sock,bind=/tmp/client.sock
+//! ```
+//!
+//! Each command is answered with a line starting with `ok` or `error`, if the
+//! sender is bound to a path. Commands are:
+//!
+//! - `extend <duration>` adds the duration to the limit of `--duration`
+//! - `history <channel>` returns the messages kept in the history of the
+//!   sampling channel named `<partition>:<port>` after its source port. The
+//!   first line of the reply is followed by one line per message, oldest first,
+//!   holding its sequence number, the module time of its transfer in
+//!   nanoseconds and its data encoded as base64, separated by spaces.
+//! - `list-shm` returns the memfds of the channels open in the hypervisor. The
+//!   first line of the reply is followed by one line per memfd, holding its fd,
+//!   its size in bytes and its name, separated by spaces.
+//! - `suppress-messages <partition> on|off` counts the log records of the
+//!   partition instead of printing them, or prints them again, like its
+//!   `suppress_messages` in the configuration. Errors of the partition are
+//!   always printed.
+//! - `stdin <partition> <base64>` writes the data encoded as base64 to the
+//!   stdin of a partition configured with `stdin: pipe`. The data must fit
+//!   into the pipe as a whole, or nothing is written.
+
+use std::fs;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use a653rs_linux_core::error::{ResultExt, SystemError, TypedResult};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+/// Largest command that is received, leaving room for about 3KiB of data for
+/// `stdin`
+const MAX_COMMAND_LEN: usize = 4096;
+
+/// A command received on the control socket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Extend the duration limit of the run
+    Extend(Duration),
+    /// Return the history of a sampling channel
+    History(String),
+    /// List the memfds of the channels
+    ListShm,
+    /// Count the log records of a partition instead of printing them, or not
+    SuppressMessages { partition: String, suppress: bool },
+    /// Write data to the stdin pipe of a partition
+    Stdin { partition: String, data: Vec<u8> },
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let command = match (words.next(), words.next()) {
+            (Some("extend"), Some(by)) => humantime::parse_duration(by)
+                .map(Command::Extend)
+                .map_err(|e| format!("invalid duration {by:?}: {e}"))?,
+            (Some("extend"), None) => return Err("extend needs a duration".into()),
+            (Some("history"), Some(channel)) => Command::History(channel.to_string()),
+            (Some("history"), None) => return Err("history needs a channel".into()),
+            (Some("list-shm"), None) => Command::ListShm,
+            (Some("list-shm"), Some(extra)) => {
+                return Err(format!("unexpected argument {extra:?}"))
+            }
+            (Some("suppress-messages"), Some(partition)) => {
+                let suppress = match words.next() {
+                    Some("on") => true,
+                    Some("off") => false,
+                    Some(other) => return Err(format!("expected on or off, got {other:?}")),
+                    None => return Err("suppress-messages needs on or off".into()),
+                };
+                Command::SuppressMessages {
+                    partition: partition.to_string(),
+                    suppress,
+                }
+            }
+            (Some("suppress-messages"), None) => {
+                return Err("suppress-messages needs a partition".into())
+            }
+            (Some("stdin"), Some(partition)) => {
+                let data = words.next().ok_or("stdin needs base64 data")?;
+                Command::Stdin {
+                    partition: partition.to_string(),
+                    data: STANDARD
+                        .decode(data)
+                        .map_err(|e| format!("invalid base64 data: {e}"))?,
+                }
+            }
+            (Some("stdin"), None) => return Err("stdin needs a partition".into()),
+            (Some(command), _) => return Err(format!("unknown command {command:?}")),
+            (None, _) => return Err("empty command".into()),
+        };
+        match words.next() {
+            Some(extra) => Err(format!("unexpected argument {extra:?}")),
+            None => Ok(command),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ControlSocket {
+    socket: UnixDatagram,
+    path: PathBuf,
+}
+
+impl ControlSocket {
+    /// Binds the control socket to `path`, replacing a socket left over from
+    /// a previous run
+    pub fn bind(path: &Path) -> TypedResult<Self> {
+        if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+            fs::remove_file(path).typ(SystemError::Config)?;
+        }
+        let socket = UnixDatagram::bind(path).typ(SystemError::Config)?;
+        socket.set_nonblocking(true).typ(SystemError::Panic)?;
+        Ok(Self {
+            socket,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Executes all pending commands with `execute`, replying with its result
+    ///
+    /// Never blocks, so that it can be called in between scheduling steps.
+    pub fn handle(&self, mut execute: impl FnMut(Command) -> Result<String, String>) {
+        let mut buf = [0; MAX_COMMAND_LEN];
+        while let Ok((len, sender)) = self.socket.recv_from(&mut buf) {
+            let result = std::str::from_utf8(&buf[..len])
+                .map_err(|_| "command is not valid UTF-8".to_string())
+                .and_then(Command::from_str)
+                .and_then(&mut execute);
+            let reply = match result {
+                Ok(msg) => format!("ok: {msg}\n"),
+                Err(e) => {
+                    warn!("Control socket: {e}");
+                    format!("error: {e}\n")
+                }
+            };
+            if let Some(sender) = sender.as_pathname() {
+                // The sender may be gone already, which does not concern us
+                let _ = self.socket.send_to(reply.as_bytes(), sender);
+            }
+        }
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(
+            "extend 30s".parse(),
+            Ok(Command::Extend(Duration::from_secs(30)))
+        );
+        assert_eq!(
+            " extend  1m 500ms\n".parse::<Command>().unwrap_err(),
+            "unexpected argument \"500ms\""
+        );
+        assert_eq!(
+            "extend 2min\n".parse(),
+            Ok(Command::Extend(Duration::from_secs(120)))
+        );
+        assert_eq!(
+            "history sender:out\n".parse(),
+            Ok(Command::History("sender:out".into()))
+        );
+        assert_eq!("list-shm\n".parse(), Ok(Command::ListShm));
+        assert_eq!(
+            "suppress-messages chatty on\n".parse(),
+            Ok(Command::SuppressMessages {
+                partition: "chatty".into(),
+                suppress: true
+            })
+        );
+        assert_eq!(
+            "suppress-messages chatty off".parse(),
+            Ok(Command::SuppressMessages {
+                partition: "chatty".into(),
+                suppress: false
+            })
+        );
+        assert_eq!(
+            "stdin guess NDIK\n".parse(),
+            Ok(Command::Stdin {
+                partition: "guess".into(),
+                data: b"42\n".to_vec()
+            })
+        );
+        for invalid in [
+            "",
+            "extend",
+            "extend soon",
+            "shrink 30s",
+            "history",
+            "history a:out b:out",
+            "list-shm a:out",
+            "suppress-messages",
+            "suppress-messages chatty",
+            "suppress-messages chatty yes",
+            "suppress-messages chatty on off",
+            "stdin",
+            "stdin guess",
+            "stdin guess 42!",
+            "stdin guess NDIK NDIK",
+        ] {
+            assert!(invalid.parse::<Command>().is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn commands_are_answered() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("control.sock");
+        // A stale socket is replaced
+        drop(UnixDatagram::bind(&path).unwrap());
+        let control = ControlSocket::bind(&path).unwrap();
+        let client = UnixDatagram::bind(dir.path().join("client.sock")).unwrap();
+
+        client.send_to(b"extend 30s\n", &path).unwrap();
+        client.send_to(b"extend later", &path).unwrap();
+        let mut received = Vec::new();
+        control.handle(|command| {
+            received.push(command);
+            Ok("terminating after 1m 30s".into())
+        });
+        assert_eq!(received, [Command::Extend(Duration::from_secs(30))]);
+
+        let mut buf = [0; MAX_COMMAND_LEN];
+        let len = client.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"ok: terminating after 1m 30s\n");
+        let len = client.recv(&mut buf).unwrap();
+        assert!(buf[..len].starts_with(b"error: invalid duration"));
+
+        // Nothing pending does not block
+        control.handle(|_| unreachable!());
+        drop(control);
+        assert!(!path.exists());
+    }
+}
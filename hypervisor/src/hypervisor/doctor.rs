@@ -0,0 +1,653 @@
+//! Self-check of the environment the hypervisor runs in
+//!
+//! The `doctor` command probes everything the hypervisor relies on before any
+//! partition is started: a delegated cgroup v2 hierarchy, user namespaces,
+//! sealable memfds, a writable runtime directory, tmpfs mounts inside of a
+//! user namespace and unix socket paths below the runtime directory. Given a
+//! configuration, it additionally checks that every partition image exists and
+//! is statically linked for the architecture of the host, as the images are
+//! executed in an otherwise empty root filesystem, and that the limit of open
+//! files suffices for its partitions and channels.
+//!
+//! Every probe is a separate function returning a [Check], so that all
+//! problems are reported at once instead of one by one.
+
+use std::fmt::Write as _;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::{fs, process};
+
+use a653rs_linux_core::cgroup;
+use a653rs_linux_core::error::{ErrorLevel, LeveledResult, ResultExt, SystemError};
+use a653rs_linux_core::partition::PartitionConstants;
+use anyhow::{anyhow, Context};
+use memfd::{FileSeal, MemfdOptions};
+use nix::mount::{mount, MsFlags};
+use nix::sched::{unshare, CloneFlags};
+use nix::sys::resource::{getrlimit, Resource};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{fork, getgid, getuid, ForkResult};
+
+use super::config::{CargoImage, Config, Image, Partition as PartitionConfig};
+use super::fd_limit;
+use super::runtime_dir::RuntimeDir;
+use super::trace::escape;
+
+/// Maximum length of the path of a unix socket, including the nul byte
+const SUN_PATH_LEN: usize = 108;
+
+/// Outcome of a single probe
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    /// Probed property
+    pub name: String,
+    pub passed: bool,
+    /// What was found, or how to fix a failure
+    pub detail: String,
+}
+
+impl Check {
+    fn pass(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            passed: true,
+            detail: detail.into(),
+        }
+    }
+
+    fn fail(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            passed: false,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Runs all probes and prints their results, failing if any probe failed
+///
+/// Without `cgroup`, the cgroup of the calling process is checked, and without
+/// `runtime_dir` the default runtime directory, like the hypervisor does.
+pub fn run(
+    config_file: Option<&Path>,
+    cgroup: Option<PathBuf>,
+    runtime_dir: Option<&Path>,
+    json: bool,
+) -> LeveledResult<()> {
+    let checks = checks(config_file, cgroup, runtime_dir);
+    if json {
+        println!("{}", report_json(&checks));
+    } else {
+        print!("{}", report(&checks));
+    }
+
+    let failed = checks.iter().filter(|c| !c.passed).count();
+    if failed > 0 {
+        return Err(anyhow!("{failed} of {} checks failed", checks.len()))
+            .lev_typ(SystemError::Config, ErrorLevel::ModuleInit);
+    }
+    Ok(())
+}
+
+/// Runs all probes
+pub fn checks(
+    config_file: Option<&Path>,
+    cgroup: Option<PathBuf>,
+    runtime_dir: Option<&Path>,
+) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let cgroup = cgroup.or_else(|| match cgroup::mount_point() {
+        Ok(mount) => {
+            checks.push(Check::pass("cgroup2 mount", mount.display().to_string()));
+            cgroup::current_cgroup().ok().map(|c| mount.join(c))
+        }
+        Err(e) => {
+            checks.push(Check::fail(
+                "cgroup2 mount",
+                format!("{e}, mount a cgroup v2 hierarchy"),
+            ));
+            None
+        }
+    });
+    if let Some(cgroup) = cgroup {
+        checks.push(cgroup_controllers(&cgroup));
+        checks.push(cgroup_delegation(&cgroup));
+    }
+
+    checks.push(user_namespaces(Path::new("/proc/sys")));
+    checks.push(memfd_seals());
+    match RuntimeDir::open(runtime_dir) {
+        Ok(dir) => {
+            checks.push(runtime_dir_check(&dir));
+            checks.push(tmpfs_mount(dir.path()));
+            checks.push(socket_path(dir.path()));
+        }
+        Err(e) => checks.push(Check::fail(
+            "runtime directory",
+            format!("{:#}, choose another one with --runtime-dir", e.source()),
+        )),
+    }
+
+    if let Some(config_file) = config_file {
+        let config = fs::read_to_string(config_file)
+            .map_err(anyhow::Error::from)
+            .and_then(|yaml| Config::from_yaml(&yaml).map_err(Into::into));
+        match config {
+            Ok(config) => {
+                checks.extend(config.partitions.iter().map(partition_image));
+                checks.push(open_files(&config));
+            }
+            Err(e) => checks.push(Check::fail(
+                "configuration",
+                format!("{}: {e}", config_file.display()),
+            )),
+        }
+    }
+
+    checks
+}
+
+/// Lists the controllers available in the cgroup
+fn cgroup_controllers(cgroup: &Path) -> Check {
+    const NAME: &str = "cgroup controllers";
+    match fs::read_to_string(cgroup.join("cgroup.controllers")) {
+        Ok(controllers) if controllers.trim().is_empty() => {
+            Check::pass(NAME, format!("none enabled in {}", cgroup.display()))
+        }
+        Ok(controllers) => Check::pass(NAME, controllers.trim().to_string()),
+        Err(e) => Check::fail(
+            NAME,
+            format!("{} is not a cgroup v2 directory: {e}", cgroup.display()),
+        ),
+    }
+}
+
+/// Checks that cgroups can be created below the cgroup
+fn cgroup_delegation(cgroup: &Path) -> Check {
+    const NAME: &str = "cgroup delegation";
+    let probe = cgroup.join(format!("a653rs-doctor-{}", process::id()));
+    match fs::create_dir(&probe).and_then(|_| fs::remove_dir(&probe)) {
+        Ok(()) => Check::pass(NAME, format!("{} is writable", cgroup.display())),
+        Err(e) => Check::fail(
+            NAME,
+            format!(
+                "cannot create cgroups in {}: {e}, run the hypervisor in a delegated cgroup, e.g. with `systemd-run --user --scope`",
+                cgroup.display()
+            ),
+        ),
+    }
+}
+
+/// Checks the sysctls limiting user namespaces, below `proc_sys`
+fn user_namespaces(proc_sys: &Path) -> Check {
+    const NAME: &str = "user namespaces";
+    let read = |file: &str| -> Option<i64> {
+        fs::read_to_string(proc_sys.join(file))
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+    };
+
+    match read("user/max_user_namespaces") {
+        None => {
+            return Check::fail(
+                NAME,
+                "user/max_user_namespaces is missing, the kernel lacks CONFIG_USER_NS",
+            )
+        }
+        Some(0) => return Check::fail(NAME, "user.max_user_namespaces is 0"),
+        Some(_) => {}
+    }
+    // Only exists on kernels patched by some distributions
+    if read("kernel/unprivileged_userns_clone") == Some(0) {
+        return Check::fail(NAME, "kernel.unprivileged_userns_clone is 0");
+    }
+
+    Check::pass(NAME, "available")
+}
+
+/// Checks that memfds can be sealed, as done for all channels
+fn memfd_seals() -> Check {
+    const NAME: &str = "memfd seals";
+    match seal_memfd() {
+        Ok(()) => Check::pass(NAME, "supported"),
+        Err(e) => Check::fail(NAME, format!("{e:#}")),
+    }
+}
+
+fn seal_memfd() -> anyhow::Result<()> {
+    let mem = MemfdOptions::default()
+        .allow_sealing(true)
+        .create("a653rs-doctor")?;
+    mem.as_file().set_len(4096)?;
+    mem.add_seals(&[FileSeal::SealShrink, FileSeal::SealGrow])?;
+    mem.add_seal(FileSeal::SealSeal)?;
+    Ok(())
+}
+
+/// Checks whether the writable runtime directory `dir` is on a tmpfs
+fn runtime_dir_check(dir: &RuntimeDir) -> Check {
+    const NAME: &str = "runtime directory";
+    let detail = if dir.is_tmpfs() {
+        format!("{} is a writable tmpfs", dir.path().display())
+    } else {
+        format!(
+            "{} is writable, but not a tmpfs as preferred",
+            dir.path().display()
+        )
+    };
+    Check::pass(NAME, detail)
+}
+
+/// Checks that a tmpfs can be mounted in a new user and mount namespace below
+/// the runtime directory `tmp`, like the root filesystem of every partition
+fn tmpfs_mount(tmp: &Path) -> Check {
+    const NAME: &str = "tmpfs mount";
+    let dir = match tempfile::tempdir_in(tmp) {
+        Ok(dir) => dir,
+        Err(e) => return Check::fail(NAME, format!("cannot create a temporary directory: {e}")),
+    };
+    let (uid, gid) = (getuid(), getgid());
+
+    // The probe exits with the number of the step which failed
+    match unsafe { fork() } {
+        Ok(ForkResult::Child) => {
+            let code = if unshare(CloneFlags::CLONE_NEWUSER | CloneFlags::CLONE_NEWNS).is_err() {
+                1
+            } else if fs::write("/proc/self/uid_map", format!("0 {uid} 1"))
+                .and_then(|_| fs::write("/proc/self/setgroups", b"deny"))
+                .and_then(|_| fs::write("/proc/self/gid_map", format!("0 {gid} 1")))
+                .is_err()
+            {
+                2
+            } else if mount(
+                Some("tmpfs"),
+                dir.path(),
+                Some("tmpfs"),
+                MsFlags::empty(),
+                Some("size=4k"),
+            )
+            .is_err()
+            {
+                3
+            } else {
+                0
+            };
+            unsafe { libc::_exit(code) }
+        }
+        Ok(ForkResult::Parent { child }) => match waitpid(child, None) {
+            Ok(WaitStatus::Exited(_, 0)) => Check::pass(NAME, "permitted in a user namespace"),
+            Ok(WaitStatus::Exited(_, 1)) => {
+                Check::fail(NAME, "cannot create a user and mount namespace")
+            }
+            Ok(WaitStatus::Exited(_, 2)) => {
+                Check::fail(NAME, "cannot map the user into a user namespace")
+            }
+            Ok(WaitStatus::Exited(_, _)) => {
+                Check::fail(NAME, "cannot mount a tmpfs in a user namespace")
+            }
+            Ok(status) => Check::fail(NAME, format!("probe ended unexpectedly: {status:?}")),
+            Err(e) => Check::fail(NAME, format!("probe could not be awaited: {e}")),
+        },
+        Err(e) => Check::fail(NAME, format!("cannot fork the probe: {e}")),
+    }
+}
+
+/// Checks that the IPC socket of a partition can be bound in a working
+/// directory created below `tmp`
+fn socket_path(tmp: &Path) -> Check {
+    const NAME: &str = "socket path";
+    let res = tempfile::tempdir_in(tmp)
+        .context("cannot create a working directory")
+        .and_then(|dir| {
+            let path = dir
+                .path()
+                .join(PartitionConstants::IPC_SENDER.trim_start_matches('/'));
+            let len = path.as_os_str().len();
+            if len >= SUN_PATH_LEN {
+                return Err(anyhow!(
+                    "{} is {len} bytes long, at most {} are allowed, choose a shorter --runtime-dir",
+                    path.display(),
+                    SUN_PATH_LEN - 1
+                ));
+            }
+            fs::create_dir_all(path.parent().unwrap())?;
+            UnixDatagram::bind(&path).with_context(|| format!("cannot bind {}", path.display()))?;
+            Ok(())
+        });
+    match res {
+        Ok(()) => Check::pass(NAME, format!("sockets can be bound in {}", tmp.display())),
+        Err(e) => Check::fail(NAME, format!("{e:#}")),
+    }
+}
+
+/// Checks that the image of a partition exists and is statically linked for
+/// the architecture of the host
+fn partition_image(partition: &PartitionConfig) -> Check {
+    let name = format!("image of partition {}", partition.name);
+    if let Image::Cargo { cargo } = &partition.image {
+        return Check::pass(
+            name,
+            format!(
+                "cargo package {} is built with --allow-cargo-build",
+                cargo.package
+            ),
+        );
+    }
+    let bin = match partition.get_partition_bin() {
+        Ok(bin) => bin,
+        Err(e) => return Check::fail(name, format!("{:#}", e.source())),
+    };
+    let header = fs::read(&bin)
+        .map_err(anyhow::Error::from)
+        .and_then(|elf| elf_header(&elf));
+    let target = CargoImage::HOST_MUSL_TARGET;
+    match header {
+        Ok(ElfHeader { machine, .. }) if HOST_MACHINE.is_some_and(|host| host != machine) => {
+            Check::fail(
+                name,
+                format!(
+                    "{} is built for another architecture (ELF machine {machine}), build it for {target}",
+                    bin.display()
+                ),
+            )
+        }
+        Ok(ElfHeader {
+            linkage: Linkage::Static,
+            ..
+        }) => Check::pass(name, format!("{} is statically linked", bin.display())),
+        Ok(ElfHeader {
+            linkage: Linkage::Dynamic,
+            ..
+        }) => Check::fail(
+            name,
+            format!(
+                "{} is dynamically linked, build it for a musl target like {target}",
+                bin.display()
+            ),
+        ),
+        Err(e) => Check::fail(name, format!("{}: {e}", bin.display())),
+    }
+}
+
+/// Checks that the hard limit of open files suffices for `config`, as the
+/// hypervisor raises its soft limit up to the hard limit
+fn open_files(config: &Config) -> Check {
+    const NAME: &str = "open files";
+    let needed = fd_limit::estimate(config);
+    match getrlimit(Resource::RLIMIT_NOFILE) {
+        Ok((_, hard)) if needed <= hard => {
+            Check::pass(NAME, format!("need ~{needed} fds, limit is {hard}"))
+        }
+        Ok((_, hard)) => Check::fail(
+            NAME,
+            format!(
+                "need ~{needed} fds, limit is {hard}; raise with `ulimit -n` or LimitNOFILE of the systemd unit"
+            ),
+        ),
+        Err(e) => Check::fail(NAME, format!("failed to read the limit of open files: {e}")),
+    }
+}
+
+/// ELF machine of the architecture the hypervisor is built for, if known
+#[cfg(target_arch = "x86_64")]
+const HOST_MACHINE: Option<u64> = Some(62);
+#[cfg(target_arch = "aarch64")]
+const HOST_MACHINE: Option<u64> = Some(183);
+#[cfg(target_arch = "arm")]
+const HOST_MACHINE: Option<u64> = Some(40);
+#[cfg(target_arch = "x86")]
+const HOST_MACHINE: Option<u64> = Some(3);
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "arm",
+    target_arch = "x86"
+)))]
+const HOST_MACHINE: Option<u64> = None;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Linkage {
+    Static,
+    /// Requires an interpreter, which does not exist inside of a partition
+    Dynamic,
+}
+
+/// What the checks need to know of an ELF executable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ElfHeader {
+    /// Architecture the executable is built for, `e_machine`
+    machine: u64,
+    linkage: Linkage,
+}
+
+/// Determines the architecture of an ELF executable from its header and its
+/// linkage from its program headers
+fn elf_header(elf: &[u8]) -> anyhow::Result<ElfHeader> {
+    const PT_INTERP: u32 = 3;
+
+    if elf.get(..4) != Some(b"\x7fELF".as_slice()) {
+        return Err(anyhow!("not an ELF file"));
+    }
+    let little_endian = match elf.get(5) {
+        Some(1) => true,
+        Some(2) => false,
+        _ => return Err(anyhow!("unknown ELF byte order")),
+    };
+    let int = |offset: usize, len: usize| -> anyhow::Result<u64> {
+        let bytes = elf
+            .get(offset..offset.saturating_add(len))
+            .ok_or_else(|| anyhow!("truncated ELF file"))?;
+        let fold = |acc: u64, b: &u8| (acc << 8) | u64::from(*b);
+        Ok(if little_endian {
+            bytes.iter().rev().fold(0, fold)
+        } else {
+            bytes.iter().fold(0, fold)
+        })
+    };
+
+    let machine = int(0x12, 2)?;
+
+    // Offsets of e_phoff, e_phentsize and e_phnum
+    let (phoff, phentsize, phnum) = match elf.get(4) {
+        Some(1) => (int(0x1c, 4)?, int(0x2a, 2)?, int(0x2c, 2)?),
+        Some(2) => (int(0x20, 8)?, int(0x36, 2)?, int(0x38, 2)?),
+        _ => return Err(anyhow!("unknown ELF class")),
+    };
+
+    for i in 0..phnum {
+        let header = phoff
+            .checked_add(i * phentsize)
+            .and_then(|h| usize::try_from(h).ok())
+            .ok_or_else(|| anyhow!("invalid ELF program header offset"))?;
+        if int(header, 4)? == u64::from(PT_INTERP) {
+            return Ok(ElfHeader {
+                machine,
+                linkage: Linkage::Dynamic,
+            });
+        }
+    }
+    Ok(ElfHeader {
+        machine,
+        linkage: Linkage::Static,
+    })
+}
+
+/// Renders the checks as human-readable lines
+pub fn report(checks: &[Check]) -> String {
+    let width = checks
+        .iter()
+        .map(|c| c.name.len())
+        .max()
+        .unwrap_or_default();
+    let mut out = String::new();
+    for c in checks {
+        let status = if c.passed { " ok " } else { "FAIL" };
+        let _ = writeln!(out, "[{status}] {:width$}  {}", c.name, c.detail);
+    }
+    out
+}
+
+/// Renders the checks as a JSON array
+pub fn report_json(checks: &[Check]) -> String {
+    let checks = checks
+        .iter()
+        .map(|c| {
+            format!(
+                "{{\"name\":\"{}\",\"passed\":{},\"detail\":\"{}\"}}",
+                escape(&c.name),
+                c.passed,
+                escape(&c.detail)
+            )
+        })
+        .collect::<Vec<_>>();
+    format!("[{}]", checks.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Minimal aarch64 ELF header with a single program header of type
+    /// `p_type`
+    fn elf64(p_type: u32) -> Vec<u8> {
+        let mut elf = vec![0; 64 + 56];
+        elf[..4].copy_from_slice(b"\x7fELF");
+        elf[4] = 2;
+        elf[5] = 1;
+        elf[0x12..0x14].copy_from_slice(&183u16.to_le_bytes());
+        elf[0x20..0x28].copy_from_slice(&64u64.to_le_bytes());
+        elf[0x36..0x38].copy_from_slice(&56u16.to_le_bytes());
+        elf[0x38..0x3a].copy_from_slice(&1u16.to_le_bytes());
+        elf[64..68].copy_from_slice(&p_type.to_le_bytes());
+        elf
+    }
+
+    #[test]
+    fn elf_headers() {
+        let linkage = |elf: &[u8]| elf_header(elf).map(|header| header.linkage);
+        // PT_LOAD only
+        assert_eq!(linkage(&elf64(1)).unwrap(), Linkage::Static);
+        // PT_INTERP
+        assert_eq!(linkage(&elf64(3)).unwrap(), Linkage::Dynamic);
+        assert_eq!(elf_header(&elf64(1)).unwrap().machine, 183);
+
+        let mut big_endian = elf64(0);
+        big_endian[5] = 2;
+        big_endian[0x12..0x14].copy_from_slice(&40u16.to_be_bytes());
+        big_endian[0x20..0x28].copy_from_slice(&64u64.to_be_bytes());
+        big_endian[0x36..0x38].copy_from_slice(&56u16.to_be_bytes());
+        big_endian[0x38..0x3a].copy_from_slice(&1u16.to_be_bytes());
+        big_endian[64..68].copy_from_slice(&3u32.to_be_bytes());
+        assert_eq!(
+            elf_header(&big_endian).unwrap(),
+            ElfHeader {
+                machine: 40,
+                linkage: Linkage::Dynamic
+            }
+        );
+
+        assert!(elf_header(b"#!/bin/sh\n").is_err());
+        assert!(elf_header(&elf64(3)[..60]).is_err());
+    }
+
+    #[test]
+    fn fake_cgroups() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(!cgroup_controllers(dir.path()).passed);
+
+        fs::write(dir.path().join("cgroup.controllers"), "cpu memory pids\n").unwrap();
+        let check = cgroup_controllers(dir.path());
+        assert!(check.passed);
+        assert_eq!(check.detail, "cpu memory pids");
+
+        assert!(cgroup_delegation(dir.path()).passed);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+        assert!(!cgroup_delegation(&dir.path().join("missing")).passed);
+    }
+
+    #[test]
+    fn fake_user_namespace_sysctls() {
+        let dir = tempfile::tempdir().unwrap();
+        let sysctl = |file: &str, value: &str| {
+            let path = dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, value).unwrap();
+        };
+
+        assert!(!user_namespaces(dir.path()).passed);
+        sysctl("user/max_user_namespaces", "0\n");
+        assert!(!user_namespaces(dir.path()).passed);
+        sysctl("user/max_user_namespaces", "63432\n");
+        assert!(user_namespaces(dir.path()).passed);
+        sysctl("kernel/unprivileged_userns_clone", "0\n");
+        assert!(!user_namespaces(dir.path()).passed);
+        sysctl("kernel/unprivileged_userns_clone", "1\n");
+        assert!(user_namespaces(dir.path()).passed);
+    }
+
+    #[test]
+    fn memfds_are_sealable() {
+        assert!(memfd_seals().passed);
+    }
+
+    #[test]
+    fn runtime_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let check = runtime_dir_check(&RuntimeDir::open(Some(dir.path())).unwrap());
+        assert!(check.passed, "{}", check.detail);
+        assert!(check.detail.contains("writable"), "{}", check.detail);
+
+        let missing = dir.path().join("missing");
+        let checks = checks(None, None, Some(&missing));
+        let check = checks
+            .iter()
+            .find(|c| c.name == "runtime directory")
+            .unwrap();
+        assert!(!check.passed);
+        assert!(check.detail.contains("--runtime-dir"), "{}", check.detail);
+        // Nothing is probed in a missing runtime directory
+        assert!(!checks.iter().any(|c| c.name == "socket path"));
+    }
+
+    #[test]
+    fn long_socket_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(
+            socket_path(dir.path()).passed,
+            "{:?}",
+            socket_path(dir.path())
+        );
+
+        let long = dir.path().join("a".repeat(SUN_PATH_LEN));
+        fs::create_dir(&long).unwrap();
+        let check = socket_path(&long);
+        assert!(!check.passed);
+        assert!(check.detail.contains("--runtime-dir"), "{}", check.detail);
+    }
+
+    #[test]
+    fn missing_partition_image() {
+        let yaml = "{ id: 0, name: p, duration: 10ms, offset: 0ms, period: 1s, image: /nonexistent/image }";
+        let partition: PartitionConfig = serde_yaml::from_str(yaml).unwrap();
+        let check = partition_image(&partition);
+        assert!(!check.passed);
+        assert!(check.detail.contains("does not exist"), "{}", check.detail);
+    }
+
+    #[test]
+    fn reports() {
+        let checks = [
+            Check::pass("memfd seals", "supported"),
+            Check::fail("socket path", "path \"x\" too long"),
+        ];
+        assert_eq!(
+            report(&checks),
+            "[ ok ] memfd seals  supported\n[FAIL] socket path  path \"x\" too long\n"
+        );
+        assert_eq!(
+            report_json(&checks),
+            r#"[{"name":"memfd seals","passed":true,"detail":"supported"},{"name":"socket path","passed":false,"detail":"path \"x\" too long"}]"#
+        );
+    }
+}
@@ -0,0 +1,180 @@
+//! Upper bound of the file descriptors used by the hypervisor
+//!
+//! The hypervisor keeps a few file descriptors open for every partition and
+//! channel during the whole run. Large configurations therefore exceed the
+//! soft limit of `RLIMIT_NOFILE`, which is as low as 1024 on most systems,
+//! and fail somewhere in the middle of creating the channels or starting the
+//! partitions. Estimating the descriptors from the configuration allows
+//! raising the soft limit up to the hard limit beforehand, or otherwise
+//! failing right away with a message naming the required limit.
+
+use a653rs_linux_core::error::{ResultExt, SystemError, TypedResult};
+use anyhow::anyhow;
+use nix::sys::resource::{getrlimit, setrlimit, Resource};
+
+use super::config::Config;
+
+/// Descriptors independent of the configuration
+///
+/// Covers stdio, the log, the system time and the trace file, as well as the
+/// pipes and sockets only open while a partition is started.
+const BASE: u64 = 32;
+/// Descriptors kept for every partition: its restart cause, operating mode,
+/// activity eventfd and syscall socket, plus the two sockets passing it io
+/// sockets
+const PER_PARTITION: u64 = 6;
+/// Every channel keeps a memfd for its source and one for its destination
+const PER_CHANNEL: u64 = 2;
+/// Sockets sent to a partition count against the limit of the hypervisor
+/// until the partition received them
+const PER_SOCKET: u64 = 1;
+
+/// Estimates the number of file descriptors the hypervisor needs for `config`
+pub(crate) fn estimate(config: &Config) -> u64 {
+    let partitions = config.partitions.len() as u64;
+    let channels = config.channel.len() as u64;
+    let sockets: u64 = config
+        .partitions
+        .iter()
+        .map(|p| p.sockets.len() as u64)
+        .sum();
+
+    BASE + partitions * PER_PARTITION + channels * PER_CHANNEL + sockets * PER_SOCKET
+}
+
+/// Makes sure the limit of open files allows `needed` descriptors, raising
+/// the soft limit to the hard limit if necessary
+///
+/// Returns the soft limit in effect.
+pub(crate) fn ensure(needed: u64) -> TypedResult<u64> {
+    let (soft, hard) = getrlimit(Resource::RLIMIT_NOFILE).typ(SystemError::Panic)?;
+    ensure_with(needed, soft, hard, |soft| {
+        setrlimit(Resource::RLIMIT_NOFILE, soft, hard)
+    })
+}
+
+fn ensure_with(
+    needed: u64,
+    soft: u64,
+    hard: u64,
+    raise: impl FnOnce(u64) -> nix::Result<()>,
+) -> TypedResult<u64> {
+    if needed <= soft {
+        return Ok(soft);
+    }
+    if needed > hard {
+        return Err(anyhow!(
+            "need ~{needed} fds, limit is {hard}; raise with `ulimit -n` or LimitNOFILE of the \
+             systemd unit"
+        ))
+        .typ(SystemError::Config);
+    }
+
+    // An unlimited hard limit can not be set as the soft limit, as the kernel
+    // caps the number of descriptors at `fs.nr_open`
+    let target = if hard == nix::libc::RLIM_INFINITY {
+        needed
+    } else {
+        hard
+    };
+    match raise(target) {
+        Ok(()) => {
+            info!("raised the limit of open files from {soft} to {target}");
+            Ok(target)
+        }
+        Err(e) => Err(anyhow!(
+            "need ~{needed} fds, limit is {soft} and raising it to {target} failed: {e}; raise \
+             with `ulimit -n` or LimitNOFILE of the systemd unit"
+        ))
+        .typ(SystemError::Config),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use a653rs_linux_core::partition::{MAX_CHANNELS, MAX_PARTITIONS};
+    use nix::errno::Errno;
+
+    use super::*;
+
+    fn config(partitions: usize, sockets: usize, channels: usize) -> Config {
+        let mut yaml = String::from("major_frame: 1s\npartitions:\n");
+        for i in 0..partitions {
+            let sockets = vec!["{ type: udp, address: \"127.0.0.1:0\" }"; sockets].join(", ");
+            yaml += &format!(
+                "  - id: {i}\n    name: P{i}\n    duration: 1ms\n    offset: {i}ms\n    \
+                 period: 1s\n    image: /bin/true\n    sockets: [{sockets}]\n"
+            );
+        }
+        yaml += "channel:\n";
+        for i in 0..channels {
+            yaml += &format!(
+                "  - !Queuing\n    msg_size: 1KB\n    msg_num: 2\n    \
+                 source:\n      partition: P0\n      port: Out{i}\n    \
+                 destination:\n      partition: P1\n      port: In{i}\n"
+            );
+        }
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    #[test]
+    fn estimation() {
+        assert_eq!(estimate(&config(0, 0, 0)), BASE);
+        assert_eq!(estimate(&config(2, 0, 0)), BASE + 2 * PER_PARTITION);
+        assert_eq!(estimate(&config(2, 3, 0)), BASE + 2 * PER_PARTITION + 6);
+        // 50 partitions with 100 channels between them
+        assert_eq!(estimate(&config(50, 1, 100)), 32 + 300 + 200 + 50);
+    }
+
+    #[test]
+    fn estimation_at_limits() {
+        // See MAX_PARTITIONS
+        assert!(estimate(&config(MAX_PARTITIONS, 0, MAX_CHANNELS)) < 4096);
+    }
+
+    #[test]
+    fn raise_to_hard_limit() {
+        let raised = Cell::new(None);
+        let limit = ensure_with(100, 64, 4096, |soft| {
+            raised.set(Some(soft));
+            Ok(())
+        });
+        assert_eq!(limit.unwrap(), 4096);
+        assert_eq!(raised.get(), Some(4096));
+
+        let limit = ensure_with(100, 64, nix::libc::RLIM_INFINITY, |soft| {
+            raised.set(Some(soft));
+            Ok(())
+        });
+        assert_eq!(limit.unwrap(), 100);
+        assert_eq!(raised.get(), Some(100));
+    }
+
+    #[test]
+    fn no_raise_below_soft_limit() {
+        let limit = ensure_with(100, 1024, 4096, |_| panic!("limit raised"));
+        assert_eq!(limit.unwrap(), 1024);
+    }
+
+    #[test]
+    fn limit_too_low() {
+        let err = ensure_with(612, 64, 256, |_| panic!("limit raised")).unwrap_err();
+        assert_eq!(err.err(), SystemError::Config);
+        assert!(
+            err.to_string().contains("need ~612 fds, limit is 256"),
+            "{err}"
+        );
+
+        let err = ensure_with(612, 64, 1024, |_| Err(Errno::EPERM)).unwrap_err();
+        assert_eq!(err.err(), SystemError::Config);
+        assert!(err.to_string().contains("limit is 64"), "{err}");
+    }
+
+    #[test]
+    fn current_limit_suffices_for_small_configs() {
+        let needed = estimate(&config(2, 1, 1));
+        assert!(ensure(needed).unwrap() >= needed);
+    }
+}
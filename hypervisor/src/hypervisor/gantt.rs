@@ -0,0 +1,230 @@
+//! ASCII Gantt chart of the schedule, printed by `--print-schedule`
+//!
+//! The chart shows one major frame as it is executed, as it is drawn from the
+//! [timeline](PartitionSchedule::timeline) the scheduler steps through. Every
+//! partition gets a row, in which its windows span from `[` to `]`, or are a
+//! single `I` if they are shorter than a column. The `swaps` row marks with
+//! `v` where the source ports of the partition of a window are swapped. The
+//! table below lists the share of the major frame every partition gets, the
+//! idle time and the tightest gap between two windows.
+
+use std::time::Duration;
+
+use a653rs::bindings::PartitionId;
+use a653rs_linux_core::error::TypedResult;
+
+use crate::hypervisor::config::Config;
+use crate::hypervisor::scheduler::{PartitionSchedule, Point};
+
+/// Number of columns the major frame is scaled to
+const COLUMNS: u128 = 50;
+
+/// Renders the chart of the schedule of `config`
+pub fn render(config: &Config) -> TypedResult<String> {
+    let schedule = config.generate_schedule()?;
+    Ok(Chart::new(config, &schedule).to_string())
+}
+
+struct Chart<'a> {
+    config: &'a Config,
+    schedule: &'a PartitionSchedule,
+    major_frame: u128,
+    /// Width of the column of names
+    width: usize,
+}
+
+impl<'a> Chart<'a> {
+    fn new(config: &'a Config, schedule: &'a PartitionSchedule) -> Self {
+        let width = config
+            .partitions
+            .iter()
+            .map(|p| p.name.len())
+            .chain(["partition".len()])
+            .max()
+            .unwrap_or_default();
+        Self {
+            config,
+            schedule,
+            major_frame: config.major_frame.as_nanos(),
+            width,
+        }
+    }
+
+    /// Column of the time `offset` into the major frame
+    fn column(&self, offset: Duration) -> usize {
+        (offset.as_nanos() * COLUMNS / self.major_frame).min(COLUMNS - 1) as usize
+    }
+
+    /// Last column before the time `offset`, which ends a window
+    fn end_column(&self, offset: Duration) -> usize {
+        let end = (offset.as_nanos() * COLUMNS).div_ceil(self.major_frame);
+        (end.max(1) - 1).min(COLUMNS - 1) as usize
+    }
+
+    fn row(&self, partition: PartitionId) -> String {
+        let mut cells = vec![' '; COLUMNS as usize];
+        for timeframe in self.schedule.iter() {
+            if timeframe.partition != partition {
+                continue;
+            }
+            let start = self.column(timeframe.start);
+            let end = self.end_column(timeframe.end).max(start);
+            cells[start..=end].fill('=');
+            if start == end {
+                cells[start] = 'I';
+            } else {
+                cells[start] = '[';
+                cells[end] = ']';
+            }
+        }
+        cells.into_iter().collect()
+    }
+
+    fn swaps(&self) -> String {
+        let mut cells = vec![' '; COLUMNS as usize];
+        for (offset, point) in self.schedule.timeline() {
+            if let Point::Swap(_) = point {
+                cells[self.end_column(offset)] = 'v';
+            }
+        }
+        cells.into_iter().collect()
+    }
+
+    /// The shortest time between the end of a window and the start of the
+    /// next one, including the first one of the next major frame, along with
+    /// the partitions of both windows and the end of the first one
+    fn tightest_gap(&self) -> Option<(Duration, PartitionId, PartitionId, Duration)> {
+        let timeframes = self.schedule.iter().collect::<Vec<_>>();
+        let first = timeframes.first()?;
+        let mut tightest: Option<(Duration, PartitionId, PartitionId, Duration)> = None;
+        for (i, prev) in timeframes.iter().enumerate() {
+            let (next, next_start) = match timeframes.get(i + 1) {
+                Some(next) => (next, next.start),
+                None => (first, first.start + self.config.major_frame),
+            };
+            let gap = next_start.saturating_sub(prev.end);
+            if tightest.is_none_or(|(tightest, ..)| gap < tightest) {
+                tightest = Some((gap, prev.partition, next.partition, prev.end));
+            }
+        }
+        tightest
+    }
+
+    fn name(&self, partition: PartitionId) -> &str {
+        self.config
+            .partitions
+            .iter()
+            .find(|p| p.id == partition)
+            .map_or("", |p| p.name.as_str())
+    }
+
+    fn percent(&self, duration: Duration) -> f64 {
+        duration.as_nanos() as f64 * 100.0 / self.major_frame as f64
+    }
+}
+
+impl std::fmt::Display for Chart<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let width = self.width;
+        let scale = Duration::from_nanos((self.major_frame / COLUMNS) as u64);
+        writeln!(
+            f,
+            "major frame: {}, one column: {}",
+            humantime::Duration::from(self.config.major_frame),
+            humantime::Duration::from(scale)
+        )?;
+        for p in &self.config.partitions {
+            writeln!(f, "{:<width$}  |{}|", p.name, self.row(p.id))?;
+        }
+        writeln!(f, "{:<width$}  |{}|", "swaps", self.swaps())?;
+        writeln!(f)?;
+
+        writeln!(
+            f,
+            "{:<width$}  {:>7}  {:>8}  {:>11}",
+            "partition", "windows", "busy", "utilization"
+        )?;
+        let mut idle = self.config.major_frame;
+        for p in &self.config.partitions {
+            let windows = self.schedule.iter().filter(|t| t.partition == p.id);
+            let (count, busy) = windows.fold((0, Duration::ZERO), |(count, busy), t| {
+                (count + 1, busy + (t.end - t.start))
+            });
+            idle = idle.saturating_sub(busy);
+            writeln!(
+                f,
+                "{:<width$}  {count:>7}  {:>8}  {:>10.1}%",
+                p.name,
+                humantime::Duration::from(busy).to_string(),
+                self.percent(busy)
+            )?;
+        }
+        writeln!(
+            f,
+            "{:<width$}  {:>7}  {:>8}  {:>10.1}%",
+            "idle",
+            "",
+            humantime::Duration::from(idle).to_string(),
+            self.percent(idle)
+        )?;
+
+        match self.tightest_gap() {
+            Some((gap, prev, next, at)) => writeln!(
+                f,
+                "tightest gap: {}, from {} to {} at {}",
+                humantime::Duration::from(gap),
+                self.name(prev),
+                self.name(next),
+                humantime::Duration::from(at)
+            ),
+            None => writeln!(f, "no windows"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(yaml: &str) -> Config {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn ping() {
+        let config = config(include_str!("../../../examples/ping/ping.yaml"));
+        assert_eq!(
+            render(&config).unwrap(),
+            include_str!("../../testdata/schedule/ping.txt")
+        );
+    }
+
+    #[test]
+    fn fuel_tank() {
+        let config = config(include_str!("../../../examples/fuel_tank/fuel_tank.yaml"));
+        assert_eq!(
+            render(&config).unwrap(),
+            include_str!("../../testdata/schedule/fuel_tank.txt")
+        );
+    }
+
+    #[test]
+    fn short_and_repeated_windows() {
+        let config = config(
+            r#"
+major_frame: 1s
+partitions:
+  - { id: 0, name: a, duration: 5ms, offset: 0ms, period: 500ms, image: /bin/true }
+  - { id: 1, name: b, duration: 100ms, offset: 300ms, period: 1s, image: /bin/true }
+"#,
+        );
+        let chart = render(&config).unwrap();
+        let rows = chart.lines().collect::<Vec<_>>();
+        assert_eq!(rows[1], format!("a          |I{:24}I{:24}|", "", ""));
+        assert_eq!(rows[2], format!("b          |{:15}[===]{:30}|", "", ""));
+        assert!(
+            chart.ends_with("tightest gap: 100ms, from b to a at 400ms\n"),
+            "{chart}"
+        );
+    }
+}
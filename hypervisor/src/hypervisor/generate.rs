@@ -0,0 +1,518 @@
+//! Generation of partition crates from a configuration
+//!
+//! The `generate` command writes the skeleton of a crate for a partition of a
+//! configuration, using the port macros of `a653rs`. Every channel connected
+//! to the partition becomes a port with the name and size of the
+//! configuration, which is created on a cold or warm start, together with an
+//! empty periodic and aperiodic process:
+//!
+//! ```sh
+//! a653rs-linux-hypervisor generate examples/ping/ping.yaml ping_client --out crates/ping_client
+//! ```
+//!
+//! The configuration is validated like before a run, so that the generated
+//! ports never disagree with the hypervisor. The
+//! [port manifest](super::manifest) written along with the crate lets the
+//! hypervisor check the ports again once they were edited, when placed next
+//! to the binary of the crate.
+
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use a653rs_linux_core::error::{ResultExt, SystemError, TypedResult};
+use anyhow::anyhow;
+use bytesize::ByteSize;
+use itertools::Itertools;
+
+use super::config::{Channel, Config};
+
+/// Version of `a653rs` the generated crates depend on
+const A653RS_VERSION: &str = "0.6";
+
+/// Names of the processes of the generated crates, which ports must not use
+const PROCESSES: [&str; 2] = ["periodic_process", "aperiodic_process"];
+
+/// Files of a generated partition crate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Skeleton {
+    /// Name of the package and its binary, which is the image of the
+    /// partition
+    pub package: String,
+    pub cargo_toml: String,
+    pub main_rs: String,
+    /// Manifest of the ports, named `<package>.ports.yaml`
+    pub ports_yaml: String,
+}
+
+/// A port of the partition, derived from a channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Port {
+    name: String,
+    kind: PortKind,
+    msg_size: ByteSize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PortKind {
+    SamplingOut,
+    SamplingIn { refresh_period: Duration },
+    QueuingOut { msg_count: usize },
+    QueuingIn { msg_count: usize },
+}
+
+impl Port {
+    /// Name of the struct representing the port
+    fn type_name(&self) -> String {
+        camel_case(&self.name)
+    }
+
+    fn attribute(&self) -> String {
+        let size = format!("msg_size = \"{}B\"", self.msg_size.as_u64());
+        match &self.kind {
+            PortKind::SamplingOut => format!("#[sampling_out(name = \"{}\", {size})]", self.name),
+            PortKind::SamplingIn { refresh_period } => format!(
+                "#[sampling_in(name = \"{}\", {size}, refresh_period = \"{}\")]",
+                self.name,
+                humantime::format_duration(*refresh_period)
+            ),
+            PortKind::QueuingOut { msg_count } | PortKind::QueuingIn { msg_count } => {
+                let direction = match self.kind {
+                    PortKind::QueuingOut { .. } => "out",
+                    _ => "in",
+                };
+                format!(
+                    "#[queuing_{direction}(\n        name = \"{}\",\n        {size},\n        msg_count = \"{msg_count}\",\n        discipline = \"Fifo\"\n    )]",
+                    self.name
+                )
+            }
+        }
+    }
+
+    fn is_sampling(&self) -> bool {
+        matches!(
+            self.kind,
+            PortKind::SamplingOut | PortKind::SamplingIn { .. }
+        )
+    }
+
+    /// Entry of the port in the port manifest
+    fn manifest_entry(&self) -> String {
+        let size = self.msg_size.as_u64();
+        let (dir, msg_num) = match self.kind {
+            PortKind::SamplingOut => ("source", None),
+            PortKind::SamplingIn { .. } => ("destination", None),
+            PortKind::QueuingOut { msg_count } => ("source", Some(msg_count)),
+            PortKind::QueuingIn { msg_count } => ("destination", Some(msg_count)),
+        };
+        match msg_num {
+            None => format!("{{ name: {:?}, dir: {dir}, msg_size: {size}B }}", self.name),
+            Some(msg_num) => format!(
+                "{{ name: {:?}, dir: {dir}, msg_size: {size}B, msg_num: {msg_num} }}",
+                self.name
+            ),
+        }
+    }
+}
+
+/// Port manifest of the `package` with `ports`
+fn ports_yaml(package: &str, ports: &[Port]) -> String {
+    let mut yaml = format!(
+        "# Ports of {package}, checked by the hypervisor if placed next to its binary as\n\
+         # {package}.ports.yaml\n"
+    );
+    for (key, sampling) in [("sampling", true), ("queuing", false)] {
+        let entries = ports
+            .iter()
+            .filter(|p| p.is_sampling() == sampling)
+            .map(|p| format!("  - {}\n", p.manifest_entry()))
+            .collect::<String>();
+        if entries.is_empty() {
+            writeln!(yaml, "{key}: []").expect("writing to a String to succeed");
+        } else {
+            write!(yaml, "{key}:\n{entries}").expect("writing to a String to succeed");
+        }
+    }
+    yaml
+}
+
+/// Generates the crate of the partition `name` of `config`
+pub fn skeleton(config: &Config, name: &str) -> TypedResult<Skeleton> {
+    config.validate()?;
+    if !config.partitions.iter().any(|p| p.name == name) {
+        return Err(anyhow!("partition {name:?} is not configured")).typ(SystemError::Config);
+    }
+    let ports = ports(config, name)?;
+    let module = snake_case(name);
+
+    let cargo_toml = format!(
+        "[package]\n\
+         name = \"{module}\"\n\
+         version = \"0.1.0\"\n\
+         edition = \"2021\"\n\
+         \n\
+         [dependencies]\n\
+         a653rs = {{ version = \"{A653RS_VERSION}\", features = [\"macros\"] }}\n\
+         a653rs-linux = \"{}\"\n\
+         log = \"0\"\n",
+        env!("CARGO_PKG_VERSION")
+    );
+
+    let mut main_rs = format!(
+        "use a653rs::partition;\n\
+         use a653rs::prelude::PartitionExt;\n\
+         use a653rs_linux::partition::ApexLogger;\n\
+         use log::LevelFilter;\n\
+         \n\
+         fn main() {{\n    \
+             ApexLogger::install_panic_hook();\n    \
+             ApexLogger::install_logger(LevelFilter::Info).unwrap();\n\
+         \n    \
+             {module}::Partition.run()\n\
+         }}\n\
+         \n\
+         #[partition(a653rs_linux::partition::ApexLinuxPartition)]\n\
+         mod {module} {{\n    \
+             use log::info;\n"
+    );
+    for port in &ports {
+        write!(
+            main_rs,
+            "\n    {}\n    struct {};\n",
+            port.attribute(),
+            port.type_name()
+        )
+        .expect("writing to a String to succeed");
+    }
+
+    main_rs.push_str("\n    #[start(cold)]\n    fn cold_start(mut ctx: start::Context) {\n");
+    for port in &ports {
+        writeln!(
+            main_rs,
+            "        ctx.create_{}().unwrap();",
+            snake_case(&port.type_name())
+        )
+        .expect("writing to a String to succeed");
+    }
+    main_rs.push_str(
+        "        ctx.create_periodic_process().unwrap().start().unwrap();
+        ctx.create_aperiodic_process().unwrap().start().unwrap();
+    }
+
+    #[start(warm)]
+    fn warm_start(ctx: start::Context) {
+        cold_start(ctx);
+    }
+
+    #[periodic(
+        period = \"0ms\",
+        time_capacity = \"Infinite\",
+        stack_size = \"100KB\",
+        base_priority = 1,
+        deadline = \"Soft\"
+    )]
+    fn periodic_process(ctx: periodic_process::Context) {
+        info!(\"started periodic process\");
+        loop {
+            // TODO: the work of each period
+
+            ctx.periodic_wait().unwrap();
+        }
+    }
+
+    #[aperiodic(
+        time_capacity = \"Infinite\",
+        stack_size = \"100KB\",
+        base_priority = 1,
+        deadline = \"Soft\"
+    )]
+    fn aperiodic_process(ctx: aperiodic_process::Context) {
+        info!(\"started aperiodic process\");
+        // TODO: background work, e.g. waiting for messages on a queuing port
+        let _ = ctx;
+    }
+}
+",
+    );
+
+    Ok(Skeleton {
+        ports_yaml: ports_yaml(&module, &ports),
+        package: module,
+        cargo_toml,
+        main_rs,
+    })
+}
+
+/// Writes the crate of the partition `name` of `config` to `out`, refusing to
+/// overwrite existing files
+///
+/// By default, the crate is written to a directory named after its package in
+/// the current directory.
+pub fn run(config: &Config, name: &str, out: Option<&Path>) -> TypedResult<Skeleton> {
+    let skeleton = skeleton(config, name)?;
+    let out = out.unwrap_or(Path::new(&skeleton.package));
+    let files = [
+        (out.join("Cargo.toml"), &skeleton.cargo_toml),
+        (out.join("src").join("main.rs"), &skeleton.main_rs),
+        (
+            out.join(format!("{}.ports.yaml", skeleton.package)),
+            &skeleton.ports_yaml,
+        ),
+    ];
+    if let Some((existing, _)) = files.iter().find(|(path, _)| path.exists()) {
+        return Err(anyhow!("{} already exists", existing.display())).typ(SystemError::Config);
+    }
+    fs::create_dir_all(out.join("src")).typ(SystemError::Config)?;
+    for (path, content) in files {
+        fs::write(&path, content).typ(SystemError::Config)?;
+    }
+    println!(
+        "Generated package {} in {}, which is the image of partition {name:?}",
+        skeleton.package,
+        out.display()
+    );
+    println!(
+        "Copy {}.ports.yaml next to the built binary, so that the hypervisor checks its ports",
+        skeleton.package
+    );
+    Ok(skeleton)
+}
+
+/// Ports of the partition `name`, in the order of the channels
+fn ports(config: &Config, name: &str) -> TypedResult<Vec<Port>> {
+    let mut ports = Vec::new();
+    for channel in &config.channel {
+        match channel {
+            Channel::Sampling(s) => {
+                if s.source.partition == name {
+                    ports.push(Port {
+                        name: s.source.port.clone(),
+                        kind: PortKind::SamplingOut,
+                        msg_size: s.msg_size,
+                    });
+                }
+                let refresh_period = s.refresh_period.unwrap_or_else(|| {
+                    // Twice the period of the source tolerates the jitter of its writes
+                    config
+                        .partitions
+                        .iter()
+                        .find(|p| p.name == s.source.partition)
+                        .map_or(config.major_frame, |p| p.period)
+                        * 2
+                });
+                ports.extend(
+                    s.destination
+                        .iter()
+                        .filter(|d| d.partition == name)
+                        .sorted_by_key(|d| &d.port)
+                        .map(|d| Port {
+                            name: d.port.clone(),
+                            kind: PortKind::SamplingIn { refresh_period },
+                            msg_size: s.msg_size,
+                        }),
+                );
+            }
+            Channel::Queuing(q) => {
+                let msg_count = q.msg_num;
+                if q.source.partition == name {
+                    ports.push(Port {
+                        name: q.source.port.clone(),
+                        kind: PortKind::QueuingOut { msg_count },
+                        msg_size: q.msg_size,
+                    });
+                }
+                if q.destination.partition == name {
+                    ports.push(Port {
+                        name: q.destination.port.clone(),
+                        kind: PortKind::QueuingIn { msg_count },
+                        msg_size: q.msg_size,
+                    });
+                }
+            }
+        }
+    }
+
+    // The macros derive the names of the fields and functions of the ports
+    let clashes = ports
+        .iter()
+        .map(|p| snake_case(&p.type_name()))
+        .chain(PROCESSES.map(String::from))
+        .duplicates()
+        .collect_vec();
+    if !clashes.is_empty() {
+        return Err(anyhow!(
+            "ports of partition {name:?} share the names {}",
+            clashes.join(", ")
+        ))
+        .typ(SystemError::Config);
+    }
+    Ok(ports)
+}
+
+/// Splits a name into lowercase words at `_`, `-` and the start of
+/// capitalized words
+fn words(name: &str) -> Vec<String> {
+    let mut words: Vec<String> = Vec::new();
+    let mut prev: Option<char> = None;
+    for c in name.chars() {
+        if c == '_' || c == '-' {
+            prev = None;
+            continue;
+        }
+        let new_word = match prev {
+            None => true,
+            Some(p) => c.is_ascii_uppercase() && !p.is_ascii_uppercase(),
+        };
+        if new_word {
+            words.push(String::new());
+        }
+        words
+            .last_mut()
+            .expect("a word to be started")
+            .push(c.to_ascii_lowercase());
+        prev = Some(c);
+    }
+    words
+}
+
+/// Name of a module or function, e.g. `ping_req` for `PingReq`
+fn snake_case(name: &str) -> String {
+    let snake = words(name).join("_");
+    if snake.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{snake}")
+    } else {
+        snake
+    }
+}
+
+/// Name of a type, e.g. `PingRequest` for `ping_request`
+fn camel_case(name: &str) -> String {
+    let camel: String = words(name)
+        .iter()
+        .map(|w| w[..1].to_ascii_uppercase() + &w[1..])
+        .collect();
+    if camel.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("Port{camel}")
+    } else {
+        camel
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hypervisor::manifest::PortManifest;
+
+    #[test]
+    fn identifiers() {
+        assert_eq!(snake_case("PingReq"), "ping_req");
+        assert_eq!(snake_case("fuel-tank_controller"), "fuel_tank_controller");
+        assert_eq!(snake_case("ABS"), "abs");
+        assert_eq!(snake_case("2nd"), "_2nd");
+        assert_eq!(camel_case("ping_request"), "PingRequest");
+        assert_eq!(camel_case("PingRes"), "PingRes");
+        assert_eq!(camel_case("2nd"), "Port2nd");
+    }
+
+    fn config(yaml: &str) -> Config {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn hello_receiver() {
+        let config = config(include_str!("../../../examples/hello_part/hello_part.yaml"));
+        let skeleton = skeleton(&config, "Bar").unwrap();
+        assert_eq!(skeleton.package, "bar");
+        assert_eq!(
+            skeleton.cargo_toml,
+            include_str!("../../testdata/generate/bar.toml")
+        );
+        assert_eq!(
+            skeleton.main_rs,
+            include_str!("../../testdata/generate/bar.rs")
+        );
+    }
+
+    #[test]
+    fn ping_client() {
+        let config = config(include_str!("../../../examples/ping/ping.yaml"));
+        let skeleton = skeleton(&config, "ping_client").unwrap();
+        assert_eq!(
+            skeleton.cargo_toml,
+            include_str!("../../testdata/generate/ping_client.toml")
+        );
+        assert_eq!(
+            skeleton.main_rs,
+            include_str!("../../testdata/generate/ping_client.rs")
+        );
+    }
+
+    #[test]
+    fn ping_queue_server() {
+        let config = config(include_str!("../../../examples/ping_queue/ping_queue.yaml"));
+        let main_rs = skeleton(&config, "ping_queue_server").unwrap().main_rs;
+        assert!(main_rs.contains(
+            "    #[queuing_in(\n        name = \"req_dest\",\n        msg_size = \"16B\",\n        msg_count = \"10\",\n        discipline = \"Fifo\"\n    )]\n    struct ReqDest;\n"
+        ));
+        assert!(main_rs.contains("        ctx.create_res_source().unwrap();\n"));
+    }
+
+    #[test]
+    fn port_manifests() {
+        for (yaml, partition) in [
+            (
+                include_str!("../../../examples/ping/ping.yaml"),
+                "ping_client",
+            ),
+            (
+                include_str!("../../../examples/ping_queue/ping_queue.yaml"),
+                "ping_queue_server",
+            ),
+        ] {
+            let config = config(yaml);
+            let ports_yaml = skeleton(&config, partition).unwrap().ports_yaml;
+            let manifest: PortManifest = serde_yaml::from_str(&ports_yaml).unwrap();
+            assert!(!manifest.decls().is_empty(), "{ports_yaml}");
+            assert!(
+                manifest.mismatches(&config, partition).is_empty(),
+                "{ports_yaml}"
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_partitions_and_clashing_ports() {
+        let config = config(include_str!("../../../examples/ping/ping.yaml"));
+        assert!(skeleton(&config, "ping_router").is_err());
+
+        let mut clashing = config.clone();
+        let Channel::Sampling(s) = &mut clashing.channel[1] else {
+            unreachable!()
+        };
+        s.destination = [a653rs_linux_core::channel::PortConfig {
+            partition: "ping_client".into(),
+            port: "ping-req".into(),
+        }]
+        .into();
+        let err = skeleton(&clashing, "ping_client").unwrap_err();
+        assert!(err.to_string().contains("ping_req"), "{err}");
+    }
+
+    #[test]
+    fn existing_files_are_kept() {
+        let config = config(include_str!("../../../examples/ping/ping.yaml"));
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("ping_client");
+        run(&config, "ping_client", Some(&out)).unwrap();
+        assert!(out.join("src/main.rs").is_file());
+        assert!(out.join("ping_client.ports.yaml").is_file());
+
+        fs::write(out.join("Cargo.toml"), "edited").unwrap();
+        assert!(run(&config, "ping_client", Some(&out)).is_err());
+        assert_eq!(
+            fs::read_to_string(out.join("Cargo.toml")).unwrap(),
+            "edited"
+        );
+    }
+}
@@ -0,0 +1,210 @@
+//! Layout of the cgroups of the hypervisor
+//!
+//! By default, the hypervisor creates a cgroup of its own below the target
+//! cgroup, holding both its process and the cgroups of the partitions:
+//!
+//! ```text
+//! <target>/linux-hypervisor-<pid>/          hypervisor
+//! <target>/linux-hypervisor-<pid>/<partition>/
+//! ```
+//!
+//! A cgroup delegated to the hypervisor, e.g. by systemd for a unit with
+//! `Delegate=yes`, is used directly instead, as systemd only keeps track of
+//! the cgroup of the unit itself. A cgroup whose children have controllers
+//! enabled may not hold any process, so the hypervisor moves itself into a
+//! leaf of its own:
+//!
+//! ```text
+//! <target>/supervisor/                      hypervisor
+//! <target>/<partition>/
+//! ```
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use a653rs_linux_core::cgroup::CGroup;
+use a653rs_linux_core::error::{ResultExt, SystemError, TypedResult};
+
+/// Placement of the cgroups of the hypervisor inside of its target cgroup
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CgroupLayout {
+    /// A cgroup named after the target cgroup and the pid of the hypervisor
+    /// is created next to it
+    #[default]
+    Nested,
+    /// The target cgroup was delegated to the hypervisor and holds the
+    /// partitions directly
+    Delegated,
+}
+
+impl CgroupLayout {
+    /// Name of the leaf cgroup of the hypervisor in a delegated cgroup
+    pub const SUPERVISOR: &'static str = "supervisor";
+}
+
+/// The cgroups created by the hypervisor
+#[derive(Debug)]
+pub(crate) struct Cgroups {
+    layout: CgroupLayout,
+    /// Parent of the cgroups of the partitions
+    root: CGroup,
+    /// Cgroup the hypervisor returns to when it is done
+    prev: PathBuf,
+}
+
+impl Cgroups {
+    /// Creates the cgroups of the hypervisor for the cgroup `target` of its
+    /// configuration
+    ///
+    /// Cgroups left over from a previous start are removed. In the delegated
+    /// layout, the hypervisor moves into its leaf right away, so that the
+    /// cgroups of the partitions can be created next to it.
+    pub fn create(target: &Path, layout: CgroupLayout) -> TypedResult<Self> {
+        match layout {
+            CgroupLayout::Nested => {
+                let prev = PathBuf::from(target.parent().unwrap());
+                let file_name = target.file_name().unwrap().to_str().unwrap();
+                let name = format!("{file_name}-{}", std::process::id());
+                let root = Self::create_nested(&prev, &name)?;
+                Ok(Self { layout, root, prev })
+            }
+            CgroupLayout::Delegated => {
+                let root = CGroup::import_root(target).typ(SystemError::CGroup)?;
+                let supervisor = target.join(CgroupLayout::SUPERVISOR);
+                let supervisor = if supervisor.exists() {
+                    CGroup::import_root(supervisor)
+                } else {
+                    root.new(CgroupLayout::SUPERVISOR)
+                }
+                .typ(SystemError::CGroup)?;
+                supervisor
+                    .mv_proc(nix::unistd::getpid())
+                    .typ(SystemError::CGroup)?;
+
+                for path in stale_children(target).typ(SystemError::CGroup)? {
+                    warn!("Removing cgroup {path:?} left over from a previous start");
+                    CGroup::import_root(&path)
+                        .and_then(|leftover| leftover.rm())
+                        .typ(SystemError::CGroup)?;
+                }
+
+                Ok(Self {
+                    layout,
+                    root,
+                    prev: supervisor.get_path(),
+                })
+            }
+        }
+    }
+
+    /// Creates the cgroup of the nested layout
+    ///
+    /// Its name contains our pid, so an existing cgroup of the same name can
+    /// only be left over from a previous attempt of this process to start. It
+    /// is removed instead of failing again.
+    fn create_nested(parent: &Path, name: &str) -> TypedResult<CGroup> {
+        let path = parent.join(name);
+        if path.exists() {
+            warn!("Removing cgroup {path:?} left over from a previous start");
+            // Leave the cgroup first, as removing it kills all processes inside
+            CGroup::import_root(parent)
+                .and_then(|parent| parent.mv_proc(nix::unistd::getpid()))
+                .and_then(|_| CGroup::import_root(&path))
+                .and_then(|leftover| leftover.rm())
+                .typ(SystemError::CGroup)?;
+        }
+
+        CGroup::new_root(parent, name).typ(SystemError::CGroup)
+    }
+
+    /// Parent of the cgroups of the partitions
+    pub fn root(&self) -> &CGroup {
+        &self.root
+    }
+
+    /// Cgroup the hypervisor runs in while the schedule is running
+    pub fn supervisor(&self) -> PathBuf {
+        match self.layout {
+            CgroupLayout::Nested => self.root.get_path(),
+            CgroupLayout::Delegated => self.prev.clone(),
+        }
+    }
+
+    /// Moves the hypervisor out of the cgroups which are removed
+    pub fn leave(&self) -> anyhow::Result<()> {
+        CGroup::import_root(&self.prev)?.mv_proc(nix::unistd::getpid())
+    }
+
+    /// Removes the cgroups of the hypervisor, after those of the partitions
+    ///
+    /// A delegated cgroup is owned by whoever delegated it, and its leaf
+    /// still holds the hypervisor, so both are kept.
+    pub fn remove(&self) -> anyhow::Result<()> {
+        match self.layout {
+            CgroupLayout::Nested => self.root.rm(),
+            CgroupLayout::Delegated => Ok(()),
+        }
+    }
+}
+
+/// Lists the cgroups in the delegated cgroup `target` apart from the leaf of
+/// the hypervisor
+///
+/// The delegated cgroup belongs to the hypervisor alone, so these can only be
+/// partitions of a previous start.
+fn stale_children(target: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut stale = Vec::new();
+    for entry in fs::read_dir(target)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() && entry.file_name() != CgroupLayout::SUPERVISOR {
+            stale.push(entry.path());
+        }
+    }
+    stale.sort();
+    Ok(stale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Mimics a cgroup delegated by systemd, with plain directories
+    fn delegated(children: &[&str]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["cgroup.procs", "cgroup.subtree_control", "memory.max"] {
+            fs::write(dir.path().join(file), "").unwrap();
+        }
+        for child in children {
+            fs::create_dir(dir.path().join(child)).unwrap();
+            fs::write(dir.path().join(child).join("cgroup.procs"), "").unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn stale_partitions_of_delegated_layout() {
+        let dir = delegated(&[]);
+        assert!(stale_children(dir.path()).unwrap().is_empty());
+
+        let dir = delegated(&[CgroupLayout::SUPERVISOR]);
+        assert!(stale_children(dir.path()).unwrap().is_empty());
+
+        let dir = delegated(&["fuel_tank_simulation", CgroupLayout::SUPERVISOR, "ctrl"]);
+        assert_eq!(
+            stale_children(dir.path()).unwrap(),
+            [
+                dir.path().join("ctrl"),
+                dir.path().join("fuel_tank_simulation")
+            ]
+        );
+    }
+
+    #[test]
+    fn plain_directories_are_no_cgroups() {
+        let dir = delegated(&[CgroupLayout::SUPERVISOR]);
+        let err = Cgroups::create(dir.path(), CgroupLayout::Delegated).unwrap_err();
+        assert_eq!(err.err(), SystemError::CGroup);
+        // Nothing was moved or removed
+        assert!(dir.path().join(CgroupLayout::SUPERVISOR).is_dir());
+    }
+}
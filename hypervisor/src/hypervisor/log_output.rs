@@ -0,0 +1,188 @@
+//! Output of the log of the hypervisor, which never holds up the schedule
+//!
+//! The hypervisor is often run with its stderr connected to a pipe, e.g. by a
+//! supervisor like journald. Should the reader of the pipe stall, writes block
+//! once the pipe is full. Should it exit, writes fail with `EPIPE`, or kill
+//! the hypervisor with `SIGPIPE`. Either would freeze the schedule in the
+//! middle of a major frame.
+//!
+//! Log records are therefore handed to a thread of their own through a
+//! bounded queue, which writes them to the actual output. Records which do
+//! not fit into the queue, or which the thread fails to write, are dropped and
+//! counted. The thread notes how many records were dropped once it writes
+//! again.
+
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use nix::sys::signal::{signal, SigHandler, Signal};
+
+/// Number of log records queued for the output
+pub const QUEUED_RECORDS: usize = 4096;
+
+/// Ignores `SIGPIPE`, so that writing to a closed pipe fails with `EPIPE`
+/// instead of killing the hypervisor
+pub fn ignore_sigpipe() -> nix::Result<()> {
+    // Ignoring a signal installs no handler which could be unsound
+    unsafe { signal(Signal::SIGPIPE, SigHandler::SigIgn) }.map(drop)
+}
+
+enum Message {
+    Record(Vec<u8>),
+    /// Acknowledged once all records queued before are written
+    Drain(SyncSender<()>),
+}
+
+/// Writer of log records, which never blocks
+///
+/// Every write is taken as a single log record and queued for the thread
+/// writing the output. Writes always succeed, the records which are dropped
+/// instead are counted.
+#[derive(Debug, Clone)]
+pub struct LogWriter {
+    queue: SyncSender<Message>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl LogWriter {
+    /// Starts the thread writing the records to `out`, of which at most
+    /// `capacity` are queued
+    pub fn spawn<W: Write + Send + 'static>(out: W, capacity: usize) -> std::io::Result<Self> {
+        let (queue, records) = mpsc::sync_channel(capacity);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let counter = dropped.clone();
+        thread::Builder::new()
+            .name("log output".to_string())
+            .spawn(move || write_records(out, records, &counter))?;
+        Ok(Self { queue, dropped })
+    }
+
+    /// Number of records dropped since they were last noted in the output
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Waits for at most `timeout` until the records queued so far are
+    /// written, e.g. before the hypervisor exits
+    ///
+    /// Returns whether they were written in time.
+    pub fn drain(&self, timeout: Duration) -> bool {
+        let (done, drained) = mpsc::sync_channel(1);
+        if self.queue.try_send(Message::Drain(done)).is_err() {
+            return false;
+        }
+        drained.recv_timeout(timeout).is_ok()
+    }
+}
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.queue.try_send(Message::Record(buf.to_vec())).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(buf.len())
+    }
+
+    /// Does not wait for the output, see [LogWriter::drain] for this
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn write_records(mut out: impl Write, messages: Receiver<Message>, dropped: &AtomicU64) {
+    for message in messages {
+        let record = match message {
+            Message::Record(record) => record,
+            Message::Drain(done) => {
+                out.flush().ok();
+                done.try_send(()).ok();
+                continue;
+            }
+        };
+
+        let lost = dropped.swap(0, Ordering::Relaxed);
+        let mut write = || {
+            if lost > 0 {
+                writeln!(
+                    out,
+                    "[{lost} log records were dropped, as the log output did not keep up]"
+                )?;
+            }
+            out.write_all(&record)?;
+            out.flush()
+        };
+        if write().is_err() {
+            dropped.fetch_add(lost + 1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Read;
+    use std::time::Instant;
+
+    use nix::fcntl::OFlag;
+    use nix::unistd::pipe2;
+
+    use super::*;
+
+    const RECORD: &[u8] = &[b'x'; 1024];
+
+    #[test]
+    fn full_pipe() {
+        let (rx, tx) = pipe2(OFlag::O_CLOEXEC).unwrap();
+        let mut writer = LogWriter::spawn(File::from(tx), 4).unwrap();
+
+        // Far more than the pipe holds, while nothing reads it
+        let start = Instant::now();
+        for _ in 0..1024 {
+            writer.write_all(RECORD).unwrap();
+            writer.flush().unwrap();
+        }
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(writer.dropped() > 0);
+        assert!(!writer.drain(Duration::from_millis(10)));
+
+        // The output catches up once the pipe is read again
+        let reader = thread::spawn(move || {
+            let mut output = Vec::new();
+            File::from(rx).read_to_end(&mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        });
+        while !writer.drain(Duration::from_secs(10)) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        writer.write_all(b"last\n").unwrap();
+        assert!(writer.drain(Duration::from_secs(10)));
+        // Closes the pipe, once the thread writing it exits
+        drop(writer);
+
+        let output = reader.join().unwrap();
+        assert!(output.ends_with("last\n"), "{output}");
+        assert!(output.contains("log records were dropped"));
+    }
+
+    #[test]
+    fn closed_pipe() {
+        // Ignored by the test harness as well, like by the hypervisor
+        ignore_sigpipe().unwrap();
+        let (rx, tx) = pipe2(OFlag::O_CLOEXEC).unwrap();
+        drop(rx);
+        let mut writer = LogWriter::spawn(File::from(tx), 4).unwrap();
+
+        writer.write_all(RECORD).unwrap();
+        assert!(writer.drain(Duration::from_secs(10)));
+        assert_eq!(writer.dropped(), 1);
+
+        writer.write_all(RECORD).unwrap();
+        assert!(writer.drain(Duration::from_secs(10)));
+        // Including the record of the first write
+        assert_eq!(writer.dropped(), 2);
+    }
+}
@@ -0,0 +1,344 @@
+//! Manifests of the ports of a partition image
+//!
+//! A partition reports the ports it creates only once it runs, so a port
+//! created in the other direction than its channel lists it used to surface
+//! as an error within the partition. A manifest next to the image, named
+//! `<image>.ports.yaml`, lists the ports the image creates, which the
+//! hypervisor cross-checks against the channels before entering the schedule:
+//!
+//! ```yaml
+//! sampling:
+//!   - { name: "Hello", dir: destination, msg_size: 32B }
+//! queuing:
+//!   - { name: "req_dest", dir: destination, msg_size: 16B, msg_num: 10 }
+//! ```
+//!
+//! The `generate` command writes the manifest of each crate it generates.
+//! Mismatches are logged, and refuse the start of the hypervisor for
+//! partitions with `strict_ports`. Images without a manifest are checked once
+//! their partition declares its ports.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use a653rs::bindings::PortDirection;
+use a653rs_linux_core::error::{ResultExt, SystemError, TypedResult};
+use a653rs_linux_core::partition::{PortDecl, QueuingConstant, SamplingConstant};
+use anyhow::{anyhow, Context};
+use bytesize::ByteSize;
+use itertools::Itertools;
+use serde::Deserialize;
+
+use super::config::{Channel, Config};
+
+/// Ports created by a partition image
+#[derive(Debug, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct PortManifest {
+    #[serde(default)]
+    pub sampling: Vec<SamplingPort>,
+    #[serde(default)]
+    pub queuing: Vec<QueuingPort>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct SamplingPort {
+    pub name: String,
+    pub dir: Direction,
+    #[serde(deserialize_with = "a653rs_linux_core::size::deserialize")]
+    pub msg_size: ByteSize,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct QueuingPort {
+    pub name: String,
+    pub dir: Direction,
+    #[serde(deserialize_with = "a653rs_linux_core::size::deserialize")]
+    pub msg_size: ByteSize,
+    pub msg_num: usize,
+}
+
+/// Direction of a port, as written in a manifest
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Source,
+    Destination,
+}
+
+impl From<Direction> for PortDirection {
+    fn from(dir: Direction) -> Self {
+        match dir {
+            Direction::Source => PortDirection::Source,
+            Direction::Destination => PortDirection::Destination,
+        }
+    }
+}
+
+impl PortManifest {
+    /// Path of the manifest of the partition image `image`
+    pub fn path(image: &Path) -> PathBuf {
+        let mut path = image.as_os_str().to_owned();
+        path.push(".ports.yaml");
+        path.into()
+    }
+
+    /// Reads the manifest next to `image`, `None` if there is none
+    pub fn read(image: &Path) -> TypedResult<Option<Self>> {
+        let path = Self::path(image);
+        let yaml = match fs::read_to_string(&path) {
+            Ok(yaml) => yaml,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("failed to read port manifest {}", path.display()))
+                    .typ(SystemError::Config)
+            }
+        };
+        serde_yaml::from_str(&yaml)
+            .with_context(|| format!("invalid port manifest {}", path.display()))
+            .typ(SystemError::Config)
+            .map(Some)
+    }
+
+    /// The ports of this manifest, as if declared by the partition
+    pub fn decls(&self) -> Vec<PortDecl> {
+        let sampling = self.sampling.iter().map(|p| PortDecl::Sampling {
+            name: p.name.clone(),
+            dir: p.dir.into(),
+            msg_size: p.msg_size.as_u64() as usize,
+        });
+        let queuing = self.queuing.iter().map(|p| PortDecl::Queuing {
+            name: p.name.clone(),
+            dir: p.dir.into(),
+            msg_size: p.msg_size.as_u64() as usize,
+            max_num_msg: p.msg_num,
+        });
+        sampling.chain(queuing).collect()
+    }
+
+    /// Describes every difference between this manifest and the ports the
+    /// channels of `config` connect to `partition`
+    pub fn mismatches(&self, config: &Config, partition: &str) -> Vec<String> {
+        let (sampling, queuing) = configured_ports(config, partition);
+        self.decls()
+            .iter()
+            .flat_map(|d| d.channel_mismatches(&sampling, &queuing))
+            .map(|m| format!("partition {partition}: {m}"))
+            .collect()
+    }
+}
+
+/// Cross-checks the manifests next to the images of all partitions of
+/// `config` against its channels
+///
+/// Mismatches are logged, and are a configuration error for partitions with
+/// `strict_ports`.
+pub fn verify(config: &Config) -> TypedResult<()> {
+    let mut strict = Vec::new();
+    for partition in &config.partitions {
+        // An image which is not found is reported once the partition is created
+        let Ok(image) = partition.get_partition_bin() else {
+            continue;
+        };
+        let Some(manifest) = PortManifest::read(&image)? else {
+            debug!("Partition {} has no port manifest", partition.name);
+            continue;
+        };
+
+        let mismatches = manifest.mismatches(config, &partition.name);
+        if mismatches.is_empty() {
+            debug!(
+                "All ports in the manifest of {} match the configuration",
+                partition.name
+            );
+        }
+        for m in &mismatches {
+            if partition.strict_ports {
+                error!("{m}");
+            } else {
+                warn!("{m}");
+            }
+        }
+        if partition.strict_ports {
+            strict.extend(mismatches);
+        }
+    }
+
+    if !strict.is_empty() {
+        return Err(anyhow!(
+            "{} mismatches between port manifests and the channels: {}",
+            strict.len(),
+            strict.join("; ")
+        ))
+        .typ(SystemError::Config);
+    }
+    Ok(())
+}
+
+/// Ports of `partition` as configured by the channels of `config`, keyed by
+/// the name of their channel
+///
+/// No channel is created for this, so the ports have no file descriptors.
+fn configured_ports(
+    config: &Config,
+    partition: &str,
+) -> (
+    HashMap<String, SamplingConstant>,
+    HashMap<String, QueuingConstant>,
+) {
+    let mut sampling = HashMap::new();
+    let mut queuing = HashMap::new();
+    for channel in &config.channel {
+        match channel {
+            Channel::Sampling(s) => {
+                let port = if s.source.partition == partition {
+                    Some((&s.source.port, PortDirection::Source, None))
+                } else {
+                    s.destination
+                        .iter()
+                        .filter(|d| d.partition == partition)
+                        .sorted_by_key(|d| &d.port)
+                        .next()
+                        .map(|d| (&d.port, PortDirection::Destination, s.refresh_period))
+                };
+                if let Some((name, dir, refresh_period)) = port {
+                    let constant = SamplingConstant {
+                        name: name.clone(),
+                        dir,
+                        msg_size: s.msg_size.as_u64() as usize,
+                        fd: -1,
+                        refresh_period,
+                    };
+                    sampling.insert(s.name().to_string(), constant);
+                }
+            }
+            Channel::Queuing(q) => {
+                let port = if q.source.partition == partition {
+                    Some((&q.source.port, PortDirection::Source))
+                } else if q.destination.partition == partition {
+                    Some((&q.destination.port, PortDirection::Destination))
+                } else {
+                    None
+                };
+                if let Some((name, dir)) = port {
+                    let constant = QueuingConstant {
+                        name: name.clone(),
+                        dir,
+                        msg_size: q.msg_size.as_u64() as usize,
+                        max_num_msg: q.msg_num,
+                        fd: -1,
+                    };
+                    queuing.insert(q.name().to_string(), constant);
+                }
+            }
+        }
+    }
+    (sampling, queuing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A queuing channel from `sender` to `receiver`, and a sampling channel
+    /// from `receiver` back to `sender`
+    fn config() -> Config {
+        serde_yaml::from_str(
+            r#"
+major_frame: 1s
+partitions:
+  - { id: 0, name: sender, duration: 10ms, offset: 0ms, period: 1s, image: ./sender }
+  - { id: 1, name: receiver, duration: 10ms, offset: 100ms, period: 1s, image: ./receiver }
+channel:
+  - !Queuing
+    msg_size: 16B
+    msg_num: 4
+    source: { partition: sender, port: commands }
+    destination: { partition: receiver, port: commands_in }
+  - !Sampling
+    msg_size: 8B
+    source: { partition: receiver, port: status }
+    destination: [ { partition: sender, port: status_in } ]
+"#,
+        )
+        .unwrap()
+    }
+
+    fn manifest(yaml: &str) -> PortManifest {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn matching_manifests() {
+        let config = config();
+        let sender = manifest(
+            "queuing: [ { name: commands, dir: source, msg_size: 16B, msg_num: 4 } ]\n\
+             sampling: [ { name: status_in, dir: destination, msg_size: 8 } ]\n",
+        );
+        assert!(sender.mismatches(&config, "sender").is_empty());
+        let receiver = manifest(
+            "queuing: [ { name: commands_in, dir: destination, msg_size: 16B, msg_num: 4 } ]\n\
+             sampling: [ { name: status, dir: source, msg_size: 8B } ]\n",
+        );
+        assert!(receiver.mismatches(&config, "receiver").is_empty());
+        assert!(PortManifest::default()
+            .mismatches(&config, "receiver")
+            .is_empty());
+    }
+
+    #[test]
+    fn source_declared_as_destination() {
+        let sender = manifest(
+            "queuing: [ { name: commands, dir: destination, msg_size: 16B, msg_num: 4 } ]",
+        );
+        assert_eq!(
+            sender.mismatches(&config(), "sender"),
+            ["partition sender: queuing port \"commands\" is declared as Destination, but channel commands lists it as source"]
+        );
+    }
+
+    #[test]
+    fn destination_declared_as_source() {
+        let sender = manifest("sampling: [ { name: status_in, dir: source, msg_size: 8B } ]");
+        assert_eq!(
+            sender.mismatches(&config(), "sender"),
+            ["partition sender: sampling port \"status_in\" is declared as Source, but channel status lists it as destination"]
+        );
+    }
+
+    #[test]
+    fn other_mismatches() {
+        let receiver = manifest(
+            "queuing: [ { name: commands_in, dir: destination, msg_size: 32B, msg_num: 4 } ]\n\
+             sampling: [ { name: unknown, dir: source, msg_size: 8B } ]\n",
+        );
+        assert_eq!(receiver.mismatches(&config(), "receiver").len(), 2);
+    }
+
+    #[test]
+    fn manifests_next_to_images() {
+        assert_eq!(
+            PortManifest::path(Path::new("/opt/bin/sender")),
+            Path::new("/opt/bin/sender.ports.yaml")
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("sender");
+        assert_eq!(PortManifest::read(&image).unwrap(), None);
+        fs::write(
+            PortManifest::path(&image),
+            "sampling: [ { name: status_in, dir: destination, msg_size: 8B } ]",
+        )
+        .unwrap();
+        let manifest = PortManifest::read(&image).unwrap().unwrap();
+        assert_eq!(manifest.sampling.len(), 1);
+
+        fs::write(PortManifest::path(&image), "ports: []").unwrap();
+        assert!(PortManifest::read(&image).is_err());
+    }
+}
@@ -1,76 +1,209 @@
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::thread::sleep;
 use std::time::{Duration, Instant};
 
 use a653rs::bindings::PartitionId;
+use a653rs::prelude::OperatingMode;
+use a653rs_linux_core::buffer;
 use a653rs_linux_core::cgroup::CGroup;
-use a653rs_linux_core::error::{ErrorLevel, LeveledResult, ResultExt, SystemError, TypedResultExt};
+use a653rs_linux_core::error::{
+    ErrorLevel, LeveledResult, ResultExt, SystemError, TypedResult, TypedResultExt,
+};
 use a653rs_linux_core::file::TempFile;
 use a653rs_linux_core::queuing::Queuing;
 use a653rs_linux_core::sampling::Sampling;
+use a653rs_linux_core::time::MonotonicTime;
 use anyhow::{anyhow, Context};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bytesize::ByteSize;
+use capture::CaptureManager;
+use clock::ClockStepDetector;
+use conditions::ConditionMonitor;
 use config::{Channel, Config};
+use control::{Command, ControlSocket};
+use layout::Cgroups;
+use mqtt::MqttBridge;
 use once_cell::sync::OnceCell;
 use partition::Partition;
-use scheduler::{Scheduler, Timeout};
+use partition_fs::Syncer;
+use procfs::process::Process;
+use record::{Recorder, Replayer};
+use runtime_dir::RuntimeDir;
+use scheduler::{Action, Scheduler, Step};
+use sd_notify::Notifier;
+use tap::Transferred;
+use telemetry::TelemetryFile;
+use trace::Tracer;
+use verify::Verifier;
 
+pub mod capture;
+pub(crate) mod cargo;
+pub(crate) mod clock;
+pub(crate) mod conditions;
 pub mod config;
+pub mod control;
+pub mod doctor;
+pub(crate) mod fd_limit;
+pub mod gantt;
+pub mod generate;
+pub mod layout;
+pub mod log_output;
+pub mod manifest;
+pub mod mqtt;
 pub mod partition;
+pub mod partition_fs;
 pub mod process;
+pub mod record;
+pub(crate) mod reload;
 pub mod rpc;
+pub mod runtime_dir;
 pub mod scheduler;
+pub mod sd_notify;
+pub(crate) mod shutdown;
+pub(crate) mod socket;
 #[allow(unused)]
 pub mod syscall;
+pub(crate) mod tap;
+pub(crate) mod telemetry;
+pub mod trace;
+pub mod validate;
+pub(crate) mod verify;
 
-pub static SYSTEM_START_TIME: OnceCell<TempFile<Instant>> = OnceCell::new();
+pub static SYSTEM_START_TIME: OnceCell<TempFile<MonotonicTime>> = OnceCell::new();
+
+/// Number of major frames after which ports not created by their partition are
+/// reported
+const UNCREATED_PORTS_FRAMES: u64 = 10;
+
+/// Smallest step of the host clock between two major frames that is reported
+const CLOCK_STEP_THRESHOLD: Duration = Duration::from_millis(100);
 
 //#[derive(Debug)]
 pub struct Hypervisor {
-    cg: CGroup,
-    major_frame: Duration,
+    cgroups: Cgroups,
     scheduler: Scheduler,
     partitions: HashMap<PartitionId, Partition>,
     sampling_channel: HashMap<String, Sampling>,
     queuing_channel: HashMap<String, Queuing>,
-    prev_cg: PathBuf,
     _config: Config,
     terminate_after: Option<Duration>,
-    t0: Option<Instant>,
+    tracer: Tracer,
+    clock: ClockStepDetector,
+    mqtt: Option<MqttBridge>,
+    recorder: Option<Recorder>,
+    partition_fs: Option<Syncer>,
+    /// The recording to replay until the schedule starts
+    replay: Option<PathBuf>,
+    replayer: Option<Replayer>,
+    telemetry_file: Option<TelemetryFile>,
+    verifier: Option<Verifier>,
+    conditions: ConditionMonitor,
+}
+
+/// Why [Hypervisor::run] returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// The duration limit was reached or a shutdown was requested
+    Completed,
+    /// The configuration is to be reloaded, see [reload]
+    Reload,
 }
 
 impl Hypervisor {
-    pub fn new(config: Config, terminate_after: Option<Duration>) -> LeveledResult<Self> {
+    pub fn new(
+        config: Config,
+        terminate_after: Option<Duration>,
+        trace_file: Option<&Path>,
+    ) -> LeveledResult<Self> {
         // Init SystemTime
         SYSTEM_START_TIME
             .get_or_try_init(|| TempFile::create("system_time").lev(ErrorLevel::ModuleInit))?;
 
-        let prev_cg = PathBuf::from(config.cgroup.parent().unwrap());
-
+        config.validate().lev(ErrorLevel::ModuleInit)?;
+        manifest::verify(&config).lev(ErrorLevel::ModuleInit)?;
+        fd_limit::ensure(fd_limit::estimate(&config)).lev(ErrorLevel::ModuleInit)?;
+        let runtime_dir =
+            RuntimeDir::open(config.runtime_dir.as_deref()).lev(ErrorLevel::ModuleInit)?;
+        info!(
+            "Creating temporary files in {}",
+            runtime_dir.path().display()
+        );
+        if !runtime_dir.is_tmpfs() {
+            warn!(
+                "Runtime directory {} is not on a tmpfs, choose one with --runtime-dir",
+                runtime_dir.path().display()
+            );
+        }
         let schedule = config.generate_schedule().lev(ErrorLevel::ModuleInit)?;
-        let pid = std::process::id();
-        let file_name = config.cgroup.file_name().unwrap().to_str().unwrap();
-        let cg_name = format!("{file_name}-{pid}");
-        let cg = CGroup::new_root(&prev_cg, cg_name.as_str())
-            .typ(SystemError::CGroup)
-            .lev(ErrorLevel::ModuleInit)?;
+        if config.partitions.is_empty() {
+            info!(
+                "No partitions are configured, idling in major frames of {}",
+                humantime::Duration::from(config.major_frame)
+            );
+        }
+        let captures = CaptureManager::new(config.capture.clone());
+        let tracer = match trace_file {
+            Some(path) => Tracer::create(path, &captures).lev(ErrorLevel::ModuleInit)?,
+            None => Tracer::disabled(),
+        };
+
+        // Nothing fallible may happen between creating the cgroups and `hv`, whose drop
+        // removes them again
+        let cgroups =
+            Cgroups::create(&config.cgroup, config.cgroup_layout).lev(ErrorLevel::ModuleInit)?;
+        shutdown::set_cgroups(
+            &cgroups.root().get_path(),
+            config.partitions.iter().map(|p| p.name.as_str()),
+        );
 
         let mut hv = Self {
-            cg,
-            scheduler: Scheduler::new(schedule),
-            major_frame: config.major_frame,
+            cgroups,
+            scheduler: Scheduler::new(schedule, config.major_frame, terminate_after),
             partitions: Default::default(),
-            prev_cg,
             _config: config.clone(),
             sampling_channel: Default::default(),
             queuing_channel: Default::default(),
             terminate_after,
-            t0: None,
+            tracer,
+            clock: ClockStepDetector::new(CLOCK_STEP_THRESHOLD),
+            mqtt: None,
+            recorder: None,
+            partition_fs: None,
+            replay: config.replay.clone(),
+            replayer: None,
+            telemetry_file: config
+                .telemetry_file
+                .clone()
+                .map(|path| TelemetryFile::new(path, config.scheduler_quantum)),
+            verifier: config.verify_shared_state.then(Verifier::new),
+            conditions: ConditionMonitor::new(),
         };
+        hv.cgroups
+            .root()
+            .enable_controllers(&config.controllers())
+            .lev_typ(SystemError::CGroup, ErrorLevel::ModuleInit)?;
 
-        for c in config.channel {
+        for c in config.channel.iter().cloned() {
             hv.add_channel(c)?;
         }
+        if let Some(bridge) = &config.mqtt_bridge {
+            let bridge =
+                MqttBridge::connect(bridge, &config.channel).lev(ErrorLevel::ModuleInit)?;
+            bridge.tap(&mut hv.sampling_channel, &mut hv.queuing_channel);
+            hv.mqtt = Some(bridge);
+        }
+        if let Some(recording) = &config.recording {
+            let recorder = Recorder::create(recording, &config.channel, &captures)
+                .lev(ErrorLevel::ModuleInit)?;
+            recorder.tap(&mut hv.sampling_channel, &mut hv.queuing_channel);
+            hv.recorder = Some(recorder);
+        }
+        if config.prefault_channels || config.lock_channels {
+            hv.prefault_channels(config.lock_channels)?;
+        }
 
         for p in config.partitions.iter() {
             if hv.partitions.contains_key(&p.id) {
@@ -80,18 +213,40 @@ impl Hypervisor {
             hv.partitions.insert(
                 p.id,
                 Partition::new(
-                    hv.cg.get_path(),
+                    hv.cgroups.root().get_path(),
                     p.clone(),
+                    &runtime_dir,
                     &hv.sampling_channel,
                     &hv.queuing_channel,
                 )
                 .lev(ErrorLevel::ModuleInit)?,
             );
+            hv.tracer.add_partition(p.id, &p.name);
         }
 
         Ok(hv)
     }
 
+    /// Faults in the memory of all channels, so that the first frames do not
+    /// pay for its allocation
+    fn prefault_channels(&mut self, lock: bool) -> LeveledResult<()> {
+        let mut size = 0;
+        for s in self.sampling_channel.values_mut() {
+            size += s.prefault(lock).lev(ErrorLevel::ModuleInit)?;
+        }
+        for q in self.queuing_channel.values_mut() {
+            size += q.prefault(lock).lev(ErrorLevel::ModuleInit)?;
+        }
+
+        let size = ByteSize::b(size as u64);
+        if lock {
+            info!("Locked {size} of channel memory");
+        } else {
+            info!("Prefaulted {size} of channel memory");
+        }
+        Ok(())
+    }
+
     fn add_channel(&mut self, channel: Channel) -> LeveledResult<()> {
         match channel {
             Channel::Queuing(q) => {
@@ -99,6 +254,12 @@ impl Hypervisor {
                     return Err(anyhow!("Queuing Channel \"{}\" already exists", q.name()))
                         .lev_typ(SystemError::PartitionConfig, ErrorLevel::ModuleInit);
                 }
+                info!(
+                    "Queuing channel {}: {} messages of {} bytes",
+                    q.name(),
+                    q.msg_num,
+                    q.msg_size.as_u64()
+                );
                 let queuing = Queuing::try_from(q).lev(ErrorLevel::ModuleInit)?;
                 self.queuing_channel.insert(queuing.name(), queuing);
             }
@@ -108,6 +269,11 @@ impl Hypervisor {
                         .lev_typ(SystemError::PartitionConfig, ErrorLevel::ModuleInit);
                 }
 
+                info!(
+                    "Sampling channel {}: messages of {} bytes",
+                    s.name(),
+                    s.msg_size.as_u64()
+                );
                 let sampling = Sampling::try_from(s).lev(ErrorLevel::ModuleInit)?;
                 self.sampling_channel.insert(sampling.name(), sampling);
             }
@@ -116,49 +282,317 @@ impl Hypervisor {
         Ok(())
     }
 
-    pub fn run(mut self) -> LeveledResult<()> {
-        self.cg
-            .mv_proc(nix::unistd::getpid())
+    /// Prepares the first major frame of the schedule
+    fn start(&mut self) -> LeveledResult<()> {
+        CGroup::import_root(self.cgroups.supervisor())
+            .and_then(|supervisor| supervisor.mv_proc(nix::unistd::getpid()))
             .typ(SystemError::CGroup)
             .lev(ErrorLevel::ModuleInit)?;
 
-        let mut frame_start = Instant::now();
-
-        // retain the first frame start as our sytems t0
-        let t0 = self.t0.unwrap_or(frame_start);
-
-        let terminate_after_timeout = self
-            .terminate_after
-            .map(|duration| Timeout::new(t0, duration));
+        // The first frame start is our systems t0, the start of the module time
+        let t0 = MonotonicTime::now();
+        self.tracer.set_epoch(Instant::now());
 
         let sys_time = SYSTEM_START_TIME
             .get()
             .context("SystemTime was not set")
             .lev_typ(SystemError::Panic, ErrorLevel::ModuleInit)?;
-        sys_time.write(&frame_start).lev(ErrorLevel::ModuleInit)?;
+        sys_time.write(&t0).lev(ErrorLevel::ModuleInit)?;
         sys_time.seal_read_only().lev(ErrorLevel::ModuleInit)?;
+
+        self.scheduler.start(t0);
+        // Only establishes the offset between the clocks
+        self.clock.sample();
+        // Started only now, as the partitions are created by cloning our process
+        if let Some(mqtt) = &mut self.mqtt {
+            mqtt.start().lev(ErrorLevel::ModuleInit)?;
+        }
+        if let Some(recorder) = &mut self.recorder {
+            recorder.start().lev(ErrorLevel::ModuleInit)?;
+        }
+        let filesystems = self.partitions.values().filter_map(Partition::fs).collect();
+        self.partition_fs = Syncer::start(filesystems).lev(ErrorLevel::ModuleInit)?;
+        if let Some(path) = self.replay.take() {
+            info!("Replaying the recording {path:?}");
+            self.replayer = Some(Replayer::open(&path).lev(ErrorLevel::ModuleInit)?);
+        }
+        Ok(())
+    }
+
+    /// Executes a single scheduling action, starting the schedule on the
+    /// first call.
+    ///
+    /// The returned [Step] describes what happened. The caller is expected to
+    /// wait until its `next_deadline` before calling this again.
+    pub fn step(&mut self) -> LeveledResult<Step> {
+        if !self.scheduler.is_started() {
+            self.start()?;
+        }
+        if let Some(mqtt) = &mut self.mqtt {
+            mqtt.inject(&mut self.sampling_channel, &mut self.queuing_channel);
+        }
+        if let Some(replayer) = &mut self.replayer {
+            replayer.inject(
+                self.scheduler.now(),
+                &mut self.sampling_channel,
+                &mut self.queuing_channel,
+            );
+        }
+        let step = self.scheduler.step(
+            &mut self.partitions,
+            &mut self.sampling_channel,
+            &mut self.queuing_channel,
+            &mut self.tracer,
+        )?;
+        if self.mqtt.is_some() || self.recorder.is_some() {
+            let transferred =
+                Transferred::take(&mut self.sampling_channel, &mut self.queuing_channel);
+            if let Some(mqtt) = &mut self.mqtt {
+                mqtt.publish(&transferred);
+            }
+            if let Some(recorder) = &mut self.recorder {
+                recorder.record(&transferred, self.scheduler.now());
+            }
+        }
+
+        if step.action == Action::FrameStart {
+            let clock_step = self.clock.sample();
+            if let Some(clock_step) = clock_step {
+                warn!(
+                    "The host clock was stepped {clock_step} before frame {}, the timestamps of the log no longer match the module time ({} steps so far)",
+                    step.frame,
+                    self.clock.steps()
+                );
+            }
+            self.update_module_conditions(clock_step.is_some());
+            if step.frame == UNCREATED_PORTS_FRAMES {
+                for p in self.partitions.values() {
+                    p.warn_uncreated_ports(UNCREATED_PORTS_FRAMES);
+                }
+            }
+            if step.frame > 0 {
+                for p in self.partitions.values_mut() {
+                    p.telemetry().1.end_frame();
+                }
+            }
+            if let Some(file) = &mut self.telemetry_file {
+                let now = self.scheduler.now().as_duration();
+                let partitions = self.partitions.values_mut().map(Partition::telemetry);
+                if let Err(e) = file.update(now, partitions) {
+                    warn!("Could not write the telemetry file: {e:?}");
+                }
+            }
+            self.verify_shared_state()?;
+        }
+        Ok(step)
+    }
+
+    /// Shares the conditions of the module during the last major frame with
+    /// the partitions
+    fn update_module_conditions(&mut self, clock_stepped: bool) {
+        let conditions = self.conditions.evaluate(
+            &self.sampling_channel,
+            &self.queuing_channel,
+            self.mqtt.as_ref(),
+            clock_stepped,
+        );
+        for partition in self.partitions.values_mut() {
+            let current = conditions.of(partition.name());
+            if let Err(e) = partition.set_module_conditions(current) {
+                warn!(
+                    "Could not share the module conditions with {}: {e:?}",
+                    partition.name()
+                );
+            }
+        }
+    }
+
+    /// Checks the memory shared with the partitions, if enabled, raising the
+    /// violations to the health monitors of the partitions
+    fn verify_shared_state(&mut self) -> LeveledResult<()> {
+        let Some(verifier) = &mut self.verifier else {
+            return Ok(());
+        };
+        let violations = verifier.check(
+            &self.partitions,
+            &self.sampling_channel,
+            &self.queuing_channel,
+        );
+        if let Some(err) = violations.module {
+            return TypedResult::Err(err).lev(ErrorLevel::ModuleRun);
+        }
+        for (name, err) in violations.partitions {
+            if let Some(partition) = self.partitions.values_mut().find(|p| p.name() == name) {
+                partition.handle_error(
+                    err,
+                    &mut self.sampling_channel,
+                    &mut self.queuing_channel,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the schedule until the duration limit is reached, a shutdown is
+    /// requested or the configuration is to be reloaded, executing the
+    /// commands of `control` in between steps
+    ///
+    /// `notifier` is told about the readiness, the status and the shutdown of
+    /// the module.
+    pub fn run(
+        &mut self,
+        control: Option<&ControlSocket>,
+        notifier: &mut Notifier,
+    ) -> LeveledResult<Stop> {
         loop {
-            // terminate hypervisor now if timeout is over
-            if let Some(timeout) = &terminate_after_timeout {
-                if !timeout.has_time_left() {
+            if shutdown::requested() {
+                notifier.stopping();
+                // Overwrite the echoed ^C, which must not fail with a closed stdout
+                let mut stdout = std::io::stdout();
+                write!(stdout, "\r").and_then(|_| stdout.flush()).ok();
+                info!("Exiting");
+                self.report_clock_steps();
+                self.report_verifier();
+                return Ok(Stop::Completed);
+            }
+            if reload::take_requested() {
+                info!("Reloading the configuration");
+                return Ok(Stop::Reload);
+            }
+            if let Some(control) = control {
+                control.handle(|command| self.execute(command));
+            }
+
+            let step = self.step()?;
+            if step.action == Action::FrameStart {
+                notifier.frame_started(step.frame, || self.status(step.frame));
+            }
+            if step.action == Action::Terminate {
+                notifier.stopping();
+                if let Some(duration) = self.terminate_after {
                     info!(
                         "quitting, as a run-time of {} was reached",
-                        humantime::Duration::from(timeout.total_duration())
+                        humantime::Duration::from(duration)
                     );
-                    quit::with_code(0)
                 }
+                self.report_clock_steps();
+                self.report_verifier();
+                return Ok(Stop::Completed);
             }
 
-            self.scheduler.run_major_frame(
-                frame_start,
-                &mut self.partitions,
-                &mut self.sampling_channel,
-                &mut self.queuing_channel,
-            )?;
+            // The partitions are frozen, so their files may be synced
+            partition_fs::idle(|| sleep(step.next_deadline.duration_since(self.scheduler.now())));
+        }
+    }
+
+    /// Summary of the module at the start of the major frame `frame`, e.g.
+    /// for the status of the service
+    fn status(&self, frame: u64) -> String {
+        let normal = self
+            .partitions
+            .values()
+            .filter(|p| p.mode() == OperatingMode::Normal)
+            .count();
+        let errors: usize = self
+            .partitions
+            .values()
+            .map(|p| p.observations().errors.len())
+            .sum();
+        format!(
+            "frame {frame}, {normal}/{} partitions NORMAL, {errors} HM events",
+            self.partitions.len()
+        )
+    }
+
+    /// Executes a command of the control socket, returning the reply
+    fn execute(&mut self, command: Command) -> Result<String, String> {
+        match command {
+            Command::Extend(by) => {
+                let limit = self
+                    .scheduler
+                    .extend(by)
+                    .ok_or("the run has no duration limit")?;
+                self.terminate_after = Some(limit);
+                let limit = humantime::Duration::from(limit);
+                info!("Extended the run-time to {limit}");
+                Ok(format!("terminating after {limit}"))
+            }
+            Command::History(channel) => {
+                let sampling = self
+                    .sampling_channel
+                    .get(&channel)
+                    .ok_or_else(|| format!("unknown sampling channel {channel:?}"))?;
+                if !sampling.keeps_history() {
+                    return Err(format!("sampling channel {channel} keeps no history"));
+                }
+                let mut reply = format!("{} messages", sampling.history().len());
+                for record in sampling.history() {
+                    let time = self.scheduler.module_time(record.transferred);
+                    reply.push_str(&format!(
+                        "\n{} {} {}",
+                        record.seq,
+                        time.as_duration().as_nanos(),
+                        STANDARD.encode(&record.data)
+                    ));
+                }
+                Ok(reply)
+            }
+            Command::ListShm => {
+                let memfds = Process::myself()
+                    .map_err(buffer::BufferError::from)
+                    .and_then(|process| buffer::channel_memfds(&process))
+                    .map_err(|e| format!("could not list the memfds: {e}"))?;
+                let mut reply = format!("{} memfds", memfds.len());
+                for memfd in memfds {
+                    reply.push_str(&format!("\n{memfd}"));
+                }
+                Ok(reply)
+            }
+            Command::SuppressMessages {
+                partition,
+                suppress,
+            } => {
+                let suppressed = self
+                    .partitions
+                    .values_mut()
+                    .find(|p| p.name() == partition)
+                    .ok_or_else(|| format!("unknown partition {partition:?}"))?
+                    .suppress_messages(suppress);
+                let state = if suppress { "suppressed" } else { "printed" };
+                info!("Log records of partition {partition} are {state} now");
+                Ok(format!(
+                    "log records of {partition} are {state}, {suppressed} were suppressed so far"
+                ))
+            }
+            Command::Stdin { partition, data } => {
+                self.partitions
+                    .values()
+                    .find(|p| p.name() == partition)
+                    .ok_or_else(|| format!("unknown partition {partition:?}"))?
+                    .write_stdin(&data)?;
+                Ok(format!(
+                    "wrote {} bytes to the stdin of {partition}",
+                    data.len()
+                ))
+            }
+        }
+    }
+
+    /// Number of steps of the host clock detected since the start
+    pub fn clock_steps(&self) -> u64 {
+        self.clock.steps()
+    }
 
-            sleep(self.major_frame.saturating_sub(frame_start.elapsed()));
+    fn report_clock_steps(&self) {
+        let steps = self.clock_steps();
+        if steps > 0 {
+            warn!("The host clock was stepped {steps} times during this run");
+        }
+    }
 
-            frame_start += self.major_frame;
+    fn report_verifier(&self) {
+        if let Some(verifier) = &self.verifier {
+            verifier.report();
         }
     }
 }
@@ -166,23 +600,27 @@ impl Hypervisor {
 impl Drop for Hypervisor {
     fn drop(&mut self) {
         let now = Instant::now();
+        // Stops the bridge, recorder and replay threads, which report their
+        // counters
+        self.mqtt = None;
+        self.recorder = None;
+        self.replayer = None;
+        self.partition_fs = None;
         for (p, m) in self.partitions.iter_mut() {
             trace!("freezing partition {p}");
             if let Err(e) = m.freeze() {
                 error!("{e}")
             }
         }
+        for m in self.partitions.values() {
+            if let Some(fs) = m.fs() {
+                trace!("syncing the files of partition {}", m.name());
+                fs.sync_now();
+            }
+        }
 
-        trace!(
-            "moving own process to previous cgroup {:?}",
-            self.prev_cg.as_path()
-        );
-        // Using unwrap in this context is probably safe, as a failure in import_root
-        // requires that the cgroup must have been deleted externally
-        if let Err(e) = CGroup::import_root(&self.prev_cg)
-            .unwrap()
-            .mv_proc(nix::unistd::getpid())
-        {
+        trace!("moving own process out of the cgroups to be removed");
+        if let Err(e) = self.cgroups.leave() {
             error!("{e}")
         }
 
@@ -193,10 +631,71 @@ impl Drop for Hypervisor {
             }
         }
 
+        // The partitions are gone, so nothing reads the channels anymore
+        for (name, channel) in self.sampling_channel.iter_mut() {
+            if channel.zeroizes() {
+                trace!("zeroizing sampling channel {name}");
+                if let Err(e) = channel.zeroize() {
+                    error!("{e}")
+                }
+            }
+        }
+        for (name, channel) in self.queuing_channel.iter_mut() {
+            if channel.zeroizes() {
+                trace!("zeroizing queuing channel {name}");
+                channel.zeroize();
+            }
+        }
+
         trace!("deleting former own cgroup");
-        if let Err(e) = self.cg.rm() {
+        if let Err(e) = self.cgroups.remove() {
             error!("{e}")
         }
         trace!("Hypervisor clean up took: {:?}", now.elapsed())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    // Like the cgroup tests, this must be run as root with --test-threads=1
+
+    use a653rs_linux_core::cgroup;
+
+    use super::*;
+
+    #[test]
+    fn restart_after_failed_start() {
+        let mut yaml = "major_frame: 1s\npartitions:\n".to_string();
+        for (i, image) in ["/bin/true", "/bin/true", "./does-not-exist"]
+            .iter()
+            .enumerate()
+        {
+            let offset = i * 100;
+            yaml += &format!(
+                "  - {{ id: {i}, name: p{i}, duration: 10ms, offset: {offset}ms, period: 1s, image: {image} }}\n"
+            );
+        }
+        let mut config: Config = serde_yaml::from_str(&yaml).unwrap();
+        config.cgroup = cgroup::mount_point()
+            .unwrap()
+            .join(cgroup::current_cgroup().unwrap())
+            .join("hypervisor-test");
+        let cg_path = config
+            .cgroup
+            .with_file_name(format!("hypervisor-test-{}", std::process::id()));
+
+        // Every failed attempt cleans up after itself, so that the error stays the same
+        for _ in 0..2 {
+            let err = Hypervisor::new(config.clone(), None, None).err().unwrap();
+            assert!(matches!(err.level(), ErrorLevel::ModuleInit));
+            assert!(err.to_string().contains("does-not-exist"), "{err}");
+            assert!(!cg_path.exists());
+        }
+
+        config.partitions[2].image = "/bin/true".into();
+        let hv = Hypervisor::new(config, None, None).unwrap();
+        assert!(cg_path.exists());
+        drop(hv);
+        assert!(!cg_path.exists());
+    }
+}
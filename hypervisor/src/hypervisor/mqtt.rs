@@ -0,0 +1,1028 @@
+//! Bridge between channels and the topics of an MQTT broker
+//!
+//! Lab equipment like dashboards often speaks MQTT. The optional
+//! `mqtt_bridge` of the configuration maps channels to topics:
+//!
+//! ```yaml
+//! mqtt_bridge:
+//!   broker: mqtt://localhost:1883
+//!   mappings:
+//!     - { channel: fuel_sensors, topic: acme/fuel/sensors, direction: publish }
+//!     - { channel: Cmd, topic: acme/fuel/cmd, direction: subscribe, encoding: base64 }
+//! ```
+//!
+//! Every message transferred on a published channel is sent to its topic.
+//! Payloads received on a subscribed topic are written to the destinations of
+//! the channel before the next window, as if its source had sent them.
+//!
+//! The connection to the broker is served by a thread of its own, which
+//! reconnects with an exponential backoff. It exchanges the messages with the
+//! scheduler through bounded queues, which drop and count messages when they
+//! are full, so that a slow or unreachable broker never delays the schedule.
+//! The thread is only started with the schedule, after the partitions were
+//! created.
+//!
+//! The client for an actual broker requires the `mqtt` feature.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use a653rs_linux_core::error::{ResultExt, SystemError, TypedResult};
+use a653rs_linux_core::queuing::Queuing;
+use a653rs_linux_core::sampling::Sampling;
+use anyhow::anyhow;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::hypervisor::config::Channel;
+use crate::hypervisor::tap::{ChannelKey, Transferred};
+
+/// Time the bridge thread waits for incoming messages before it publishes
+/// the outgoing ones
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Delay before the first attempt to reconnect, doubled on every failure
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Configuration of the MQTT bridge
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct MqttBridgeConfig {
+    /// URL of the broker, `mqtt://<host>[:<port>]`
+    pub broker: String,
+
+    /// Client identifier announced to the broker
+    #[serde(default = "MqttBridgeConfig::default_client_id")]
+    pub client_id: String,
+
+    /// Number of messages buffered in each direction between the scheduler
+    /// and the connection to the broker
+    #[serde(default = "MqttBridgeConfig::default_queue_size")]
+    pub queue_size: usize,
+
+    pub mappings: Vec<MqttMapping>,
+}
+
+/// Mapping of a channel to a topic
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct MqttMapping {
+    /// Name of the source port of the channel, or `<partition>:<port>` if
+    /// several channels share it
+    pub channel: String,
+    pub topic: String,
+    pub direction: MqttDirection,
+    #[serde(default)]
+    pub encoding: PayloadEncoding,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MqttDirection {
+    /// Messages of the channel are published on the topic
+    Publish,
+    /// Messages of the topic are written to the channel
+    Subscribe,
+}
+
+/// Representation of the messages of a channel in the MQTT payloads
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadEncoding {
+    /// The bytes of the message as they are
+    #[default]
+    Raw,
+    /// The message in standard base64, for consumers expecting text
+    Base64,
+}
+
+impl PayloadEncoding {
+    fn encode(self, data: Vec<u8>) -> Vec<u8> {
+        match self {
+            PayloadEncoding::Raw => data,
+            PayloadEncoding::Base64 => STANDARD.encode(data).into_bytes(),
+        }
+    }
+
+    fn decode(self, payload: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        match self {
+            PayloadEncoding::Raw => Ok(payload),
+            PayloadEncoding::Base64 => Ok(STANDARD.decode(payload)?),
+        }
+    }
+}
+
+impl MqttBridgeConfig {
+    fn default_client_id() -> String {
+        "a653rs-linux-hypervisor".to_string()
+    }
+
+    fn default_queue_size() -> usize {
+        64
+    }
+
+    /// Host and port of the broker
+    pub fn address(&self) -> Result<(String, u16), String> {
+        let url = self.broker.trim_end_matches('/');
+        let address = url
+            .strip_prefix("mqtt://")
+            .or_else(|| url.strip_prefix("tcp://"))
+            .ok_or_else(|| format!("broker {:?} is no mqtt:// URL", self.broker))?;
+        let (host, port) = match address.rsplit_once(':') {
+            Some((host, port)) => {
+                let port = port
+                    .parse()
+                    .map_err(|_| format!("broker {:?} has an invalid port", self.broker))?;
+                (host, port)
+            }
+            None => (address, 1883),
+        };
+        if host.is_empty() {
+            return Err(format!("broker {:?} has no host", self.broker));
+        }
+        Ok((host.to_string(), port))
+    }
+
+    /// Keeps only the mappings of `channels`, for
+    /// [Config::solo](super::config::Config::solo)
+    pub(crate) fn retain_channels(&mut self, channels: &[Channel]) {
+        self.mappings
+            .retain(|m| ChannelKey::resolve(channels, &m.channel).is_ok());
+    }
+
+    /// Checks the broker and that every mapping refers to one of `channels`
+    pub fn validate(&self, channels: &[Channel]) -> TypedResult<()> {
+        let mut invalid = Vec::new();
+        if let Err(e) = self.address() {
+            invalid.push(e);
+        }
+        if self.queue_size == 0 {
+            invalid.push("queue_size must be at least 1".to_string());
+        }
+        for mapping in &self.mappings {
+            if let Err(e) = ChannelKey::resolve(channels, &mapping.channel) {
+                invalid.push(e);
+            }
+            if mapping.topic.is_empty() || mapping.topic.contains(['+', '#', '\0']) {
+                invalid.push(format!(
+                    "topic {:?} of channel {:?} must be a non-empty topic without wildcards",
+                    mapping.topic, mapping.channel
+                ));
+            }
+        }
+        // A topic in both directions would echo every message back into the module
+        invalid.extend(
+            self.mappings
+                .iter()
+                .filter(|m| m.direction == MqttDirection::Publish)
+                .filter(|p| {
+                    self.mappings
+                        .iter()
+                        .any(|s| s.direction == MqttDirection::Subscribe && s.topic == p.topic)
+                })
+                .map(|m| format!("topic {:?} is both published and subscribed", m.topic))
+                .unique(),
+        );
+
+        if !invalid.is_empty() {
+            return Err(anyhow!("invalid MQTT bridge:\n{}", invalid.join("\n")))
+                .typ(SystemError::Config);
+        }
+        Ok(())
+    }
+}
+
+/// A connection to an MQTT broker
+///
+/// All methods are called from the thread of the bridge.
+pub trait MqttClient: Send {
+    /// Establishes a new connection, subscribing to `topics`
+    fn connect(&mut self, topics: &[String]) -> anyhow::Result<()>;
+
+    /// Publishes `payload` on `topic`, without waiting for the broker
+    fn publish(&mut self, topic: &str, payload: Vec<u8>) -> anyhow::Result<()>;
+
+    /// Waits up to `timeout` for a message on one of the subscribed topics
+    ///
+    /// An error means that the connection was lost.
+    fn poll(&mut self, timeout: Duration) -> anyhow::Result<Option<(String, Vec<u8>)>>;
+}
+
+/// Message counters of the bridge
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BridgeStats {
+    /// Messages sent to the broker
+    pub published: u64,
+    /// Messages written to channels
+    pub received: u64,
+    /// Messages of published channels which were not sent, because the
+    /// queue to the broker was full or sending failed
+    pub dropped_outgoing: u64,
+    /// Payloads of subscribed topics which were not written to their
+    /// channel, because the queue from the broker was full, they could not
+    /// be decoded, exceeded the message size or the queuing channel was full
+    pub dropped_incoming: u64,
+    /// Successful connections to the broker, including the first one
+    pub connects: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    published: AtomicU64,
+    received: AtomicU64,
+    dropped_outgoing: AtomicU64,
+    dropped_incoming: AtomicU64,
+    connects: AtomicU64,
+    /// Whether the bridge is currently connected to the broker
+    connected: AtomicBool,
+}
+
+impl Counters {
+    fn inc(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> BridgeStats {
+        BridgeStats {
+            published: self.published.load(Ordering::Relaxed),
+            received: self.received.load(Ordering::Relaxed),
+            dropped_outgoing: self.dropped_outgoing.load(Ordering::Relaxed),
+            dropped_incoming: self.dropped_incoming.load(Ordering::Relaxed),
+            connects: self.connects.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct Outgoing {
+    topic: String,
+    encoding: PayloadEncoding,
+    data: Vec<u8>,
+}
+
+struct Incoming {
+    channel: ChannelKey,
+    data: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+struct Subscription {
+    topic: String,
+    channel: ChannelKey,
+    encoding: PayloadEncoding,
+}
+
+/// State of the bridge thread
+struct Connection {
+    client: Box<dyn MqttClient>,
+    subscriptions: Vec<Subscription>,
+    outgoing: Receiver<Outgoing>,
+    incoming: SyncSender<Incoming>,
+    counters: Arc<Counters>,
+    stop: Arc<AtomicBool>,
+}
+
+pub struct MqttBridge {
+    /// Topics of every published channel
+    publications: Vec<(ChannelKey, Vec<(String, PayloadEncoding)>)>,
+    /// All published and subscribed channels
+    bridged: Vec<ChannelKey>,
+    outgoing: SyncSender<Outgoing>,
+    incoming: Receiver<Incoming>,
+    counters: Arc<Counters>,
+    stop: Arc<AtomicBool>,
+    /// The connection until the bridge is started
+    connection: Option<Connection>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MqttBridge {
+    /// Prepares the bridge of `config` between `channels` and the broker
+    /// behind `client`, without connecting yet
+    pub fn new(
+        config: &MqttBridgeConfig,
+        channels: &[Channel],
+        client: Box<dyn MqttClient>,
+    ) -> TypedResult<Self> {
+        config.validate(channels)?;
+        let mut publications: Vec<(ChannelKey, Vec<(String, PayloadEncoding)>)> = Vec::new();
+        let mut subscriptions = Vec::new();
+        for mapping in &config.mappings {
+            let channel = ChannelKey::resolve(channels, &mapping.channel)
+                .map_err(|e| anyhow!(e))
+                .typ(SystemError::Config)?;
+            match mapping.direction {
+                MqttDirection::Publish => {
+                    let topic = (mapping.topic.clone(), mapping.encoding);
+                    match publications.iter_mut().find(|(c, _)| *c == channel) {
+                        Some((_, topics)) => topics.push(topic),
+                        None => publications.push((channel, vec![topic])),
+                    }
+                }
+                MqttDirection::Subscribe => subscriptions.push(Subscription {
+                    topic: mapping.topic.clone(),
+                    channel,
+                    encoding: mapping.encoding,
+                }),
+            }
+        }
+
+        let bridged = publications
+            .iter()
+            .map(|(channel, _)| channel)
+            .chain(subscriptions.iter().map(|s| &s.channel))
+            .unique()
+            .cloned()
+            .collect();
+
+        let (outgoing_tx, outgoing_rx) = sync_channel(config.queue_size);
+        let (incoming_tx, incoming_rx) = sync_channel(config.queue_size);
+        let counters = Arc::new(Counters::default());
+        let stop = Arc::new(AtomicBool::new(false));
+        Ok(Self {
+            publications,
+            bridged,
+            outgoing: outgoing_tx,
+            incoming: incoming_rx,
+            counters: counters.clone(),
+            stop: stop.clone(),
+            connection: Some(Connection {
+                client,
+                subscriptions,
+                outgoing: outgoing_rx,
+                incoming: incoming_tx,
+                counters,
+                stop,
+            }),
+            thread: None,
+        })
+    }
+
+    /// Prepares the bridge of `config` to its broker
+    #[cfg(feature = "mqtt")]
+    pub fn connect(config: &MqttBridgeConfig, channels: &[Channel]) -> TypedResult<Self> {
+        let client = rumqtt::RumqttClient::new(config).typ(SystemError::Config)?;
+        Self::new(config, channels, Box::new(client))
+    }
+
+    /// Prepares the bridge of `config` to its broker
+    #[cfg(not(feature = "mqtt"))]
+    pub fn connect(_config: &MqttBridgeConfig, _channels: &[Channel]) -> TypedResult<Self> {
+        Err(anyhow!(
+            "the MQTT bridge requires a hypervisor built with the \"mqtt\" feature"
+        ))
+        .typ(SystemError::Config)
+    }
+
+    /// Keeps the messages of the published channels for [MqttBridge::publish]
+    pub fn tap(
+        &self,
+        sampling: &mut HashMap<String, Sampling>,
+        queuing: &mut HashMap<String, Queuing>,
+    ) {
+        for (channel, _) in &self.publications {
+            channel.tap(sampling, queuing);
+        }
+    }
+
+    /// Starts the thread serving the connection to the broker
+    pub fn start(&mut self) -> TypedResult<()> {
+        let Some(connection) = self.connection.take() else {
+            return Ok(());
+        };
+        let thread = thread::Builder::new()
+            .name("mqtt-bridge".to_string())
+            .spawn(move || connection.run())
+            .typ(SystemError::Panic)?;
+        self.thread = Some(thread);
+        Ok(())
+    }
+
+    /// Writes the payloads received since the last call to their channels
+    pub fn inject(
+        &mut self,
+        sampling: &mut HashMap<String, Sampling>,
+        queuing: &mut HashMap<String, Queuing>,
+    ) {
+        for msg in self.incoming.try_iter() {
+            let injected = match &msg.channel {
+                ChannelKey::Sampling(name) => sampling
+                    .get_mut(name)
+                    .map(|c| c.inject(&msg.data).map(|_| true)),
+                ChannelKey::Queuing(name) => queuing.get_mut(name).map(|c| c.inject(&msg.data)),
+            };
+            match injected {
+                Some(Ok(true)) => Counters::inc(&self.counters.received),
+                Some(Ok(false)) => {
+                    debug!("Dropped an MQTT message for a full channel");
+                    Counters::inc(&self.counters.dropped_incoming)
+                }
+                Some(Err(e)) => {
+                    warn!("Dropped an MQTT message: {e}");
+                    Counters::inc(&self.counters.dropped_incoming)
+                }
+                None => Counters::inc(&self.counters.dropped_incoming),
+            }
+        }
+    }
+
+    /// Hands the messages `transferred` on the published channels to the
+    /// bridge thread
+    pub(crate) fn publish(&mut self, transferred: &Transferred) {
+        for (channel, topics) in &self.publications {
+            for data in transferred.of(channel) {
+                for (topic, encoding) in topics {
+                    let msg = Outgoing {
+                        topic: topic.clone(),
+                        encoding: *encoding,
+                        data: data.clone(),
+                    };
+                    if self.outgoing.try_send(msg).is_err() {
+                        Counters::inc(&self.counters.dropped_outgoing);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn stats(&self) -> BridgeStats {
+        self.counters.snapshot()
+    }
+
+    /// Whether the bridge is currently connected to the broker, which it is
+    /// not before the first connection succeeded
+    pub fn is_connected(&self) -> bool {
+        self.counters.connected.load(Ordering::Relaxed)
+    }
+
+    /// Names of the partitions connected to a published or subscribed channel
+    pub fn bridged_partitions(
+        &self,
+        sampling: &HashMap<String, Sampling>,
+        queuing: &HashMap<String, Queuing>,
+    ) -> HashSet<String> {
+        let mut partitions = HashSet::new();
+        for channel in &self.bridged {
+            match channel {
+                ChannelKey::Sampling(name) => {
+                    if let Some(channel) = sampling.get(name) {
+                        partitions.insert(channel.source_partition().to_string());
+                        partitions.extend(channel.destination_partitions().map(str::to_string));
+                    }
+                }
+                ChannelKey::Queuing(name) => {
+                    if let Some(channel) = queuing.get(name) {
+                        partitions.insert(channel.source_partition().to_string());
+                        partitions.insert(channel.destination_partition().to_string());
+                    }
+                }
+            }
+        }
+        partitions
+    }
+}
+
+impl Drop for MqttBridge {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("The MQTT bridge thread panicked");
+            }
+        }
+        let stats = self.stats();
+        info!(
+            "MQTT bridge published {} and received {} messages, dropping {} outgoing and {} incoming ones",
+            stats.published, stats.received, stats.dropped_outgoing, stats.dropped_incoming
+        );
+    }
+}
+
+impl Connection {
+    fn stopped(&self) -> bool {
+        self.stop.load(Ordering::Acquire)
+    }
+
+    /// Keeps connecting to the broker until the bridge is dropped
+    fn run(mut self) {
+        let topics = self
+            .subscriptions
+            .iter()
+            .map(|s| s.topic.clone())
+            .unique()
+            .collect_vec();
+        let mut backoff = MIN_BACKOFF;
+        while !self.stopped() {
+            match self.client.connect(&topics) {
+                Ok(()) => {
+                    Counters::inc(&self.counters.connects);
+                    self.counters.connected.store(true, Ordering::Relaxed);
+                    backoff = MIN_BACKOFF;
+                    let served = self.serve();
+                    self.counters.connected.store(false, Ordering::Relaxed);
+                    match served {
+                        Ok(()) => return,
+                        Err(e) => warn!("Lost the connection to the MQTT broker: {e}"),
+                    }
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to connect to the MQTT broker, retrying in {}: {e}",
+                        humantime::Duration::from(backoff)
+                    );
+                    self.wait(backoff);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+
+    /// Sleeps for `duration`, unless the bridge is dropped in the meantime
+    fn wait(&self, duration: Duration) {
+        let until = Instant::now() + duration;
+        while !self.stopped() {
+            let left = until.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return;
+            }
+            thread::sleep(left.min(POLL_INTERVAL));
+        }
+    }
+
+    /// Exchanges messages with the broker until the connection is lost or the
+    /// bridge is dropped
+    fn serve(&mut self) -> anyhow::Result<()> {
+        while !self.stopped() {
+            for msg in self.outgoing.try_iter() {
+                let payload = msg.encoding.encode(msg.data);
+                match self.client.publish(&msg.topic, payload) {
+                    Ok(()) => Counters::inc(&self.counters.published),
+                    Err(e) => {
+                        debug!("Failed to publish on {:?}: {e}", msg.topic);
+                        Counters::inc(&self.counters.dropped_outgoing);
+                    }
+                }
+            }
+
+            let Some((topic, payload)) = self.client.poll(POLL_INTERVAL)? else {
+                continue;
+            };
+            for sub in self.subscriptions.iter().filter(|s| s.topic == topic) {
+                let data = match sub.encoding.decode(payload.clone()) {
+                    Ok(data) => data,
+                    Err(e) => {
+                        warn!("Dropped an invalid payload of topic {topic:?}: {e}");
+                        Counters::inc(&self.counters.dropped_incoming);
+                        continue;
+                    }
+                };
+                let msg = Incoming {
+                    channel: sub.channel.clone(),
+                    data,
+                };
+                if self.incoming.try_send(msg).is_err() {
+                    Counters::inc(&self.counters.dropped_incoming);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "mqtt")]
+mod rumqtt {
+    use std::time::Duration;
+
+    use anyhow::{anyhow, bail, Context};
+    use rumqttc::{
+        Client, Connection, Event, MqttOptions, Packet, QoS, RecvTimeoutError, SubscribeFilter,
+    };
+
+    use super::{MqttBridgeConfig, MqttClient};
+
+    /// Time the broker has to accept a connection
+    const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Requests buffered by the client, independent of the queues of the
+    /// bridge
+    const CLIENT_CAPACITY: usize = 16;
+
+    pub struct RumqttClient {
+        options: MqttOptions,
+        session: Option<(Client, Connection)>,
+    }
+
+    impl RumqttClient {
+        pub fn new(config: &MqttBridgeConfig) -> anyhow::Result<Self> {
+            let (host, port) = config.address().map_err(|e| anyhow!(e))?;
+            let mut options = MqttOptions::new(&config.client_id, host, port);
+            options.set_keep_alive(Duration::from_secs(5));
+            Ok(Self {
+                options,
+                session: None,
+            })
+        }
+    }
+
+    impl MqttClient for RumqttClient {
+        fn connect(&mut self, topics: &[String]) -> anyhow::Result<()> {
+            self.session = None;
+            let (client, mut connection) = Client::new(self.options.clone(), CLIENT_CAPACITY);
+            loop {
+                match connection.recv_timeout(CONNECT_TIMEOUT) {
+                    Ok(Ok(Event::Incoming(Packet::ConnAck(_)))) => break,
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => return Err(e.into()),
+                    Err(_) => bail!("no answer from the broker"),
+                }
+            }
+            // A single request, as the client only buffers CLIENT_CAPACITY of
+            // them until the connection is polled
+            if !topics.is_empty() {
+                client.try_subscribe_many(
+                    topics
+                        .iter()
+                        .map(|topic| SubscribeFilter::new(topic.clone(), QoS::AtMostOnce)),
+                )?;
+            }
+            self.session = Some((client, connection));
+            Ok(())
+        }
+
+        fn publish(&mut self, topic: &str, payload: Vec<u8>) -> anyhow::Result<()> {
+            let (client, _) = self.session.as_mut().context("not connected")?;
+            client.try_publish(topic, QoS::AtMostOnce, false, payload)?;
+            Ok(())
+        }
+
+        fn poll(&mut self, timeout: Duration) -> anyhow::Result<Option<(String, Vec<u8>)>> {
+            let (_, connection) = self.session.as_mut().context("not connected")?;
+            match connection.recv_timeout(timeout) {
+                Ok(Ok(Event::Incoming(Packet::Publish(publish)))) => {
+                    Ok(Some((publish.topic, publish.payload.to_vec())))
+                }
+                Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => Ok(None),
+                Ok(Err(e)) => Err(e.into()),
+                Err(RecvTimeoutError::Disconnected) => bail!("the connection was closed"),
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+
+        use super::*;
+
+        #[test]
+        fn subscribing_to_more_topics_than_buffered_requests() {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let port = listener.local_addr().unwrap().port();
+            // Accepts the connection and returns the type of the next packet
+            let broker = std::thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut header = [0; 2];
+                stream.read_exact(&mut header).unwrap();
+                stream.read_exact(&mut vec![0; header[1].into()]).unwrap();
+                // CONNACK, accepted
+                stream.write_all(&[0x20, 2, 0, 0]).unwrap();
+                stream.read_exact(&mut header).unwrap();
+                header[0]
+            });
+
+            let config: MqttBridgeConfig = serde_yaml::from_str(&format!(
+                "{{ broker: mqtt://127.0.0.1:{port}, mappings: [] }}"
+            ))
+            .unwrap();
+            let mut client = RumqttClient::new(&config).unwrap();
+            let topics: Vec<_> = (0..CLIENT_CAPACITY * 2)
+                .map(|i| format!("lab/{i}"))
+                .collect();
+            client.connect(&topics).unwrap();
+            while !broker.is_finished() {
+                client.poll(Duration::from_millis(10)).unwrap();
+            }
+            // SUBSCRIBE
+            assert_eq!(broker.join().unwrap(), 0x82);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::mem;
+    use std::os::fd::AsRawFd;
+    use std::sync::Mutex;
+
+    use a653rs_linux_core::queuing::{QueuingDestination, QueuingSource};
+    use a653rs_linux_core::sampling::{SamplingDestination, SamplingSource};
+    use a653rs_linux_core::time::MonotonicTime;
+    use anyhow::bail;
+
+    use super::*;
+
+    const BRIDGE: &str = r#"
+broker: mqtt://localhost
+queue_size: 4
+mappings:
+  - { channel: Sensors, topic: lab/sensors, direction: publish, encoding: base64 }
+  - { channel: Sensors, topic: lab/sensors/raw, direction: publish }
+  - { channel: Cmd, topic: lab/cmd, direction: subscribe }
+  - { channel: "ctrl:Log", topic: lab/log, direction: publish }
+  - { channel: Setpoint, topic: lab/setpoint, direction: subscribe, encoding: base64 }
+"#;
+
+    const CHANNELS: &str = r#"
+- !Sampling
+  msg_size: 8B
+  source: { partition: sim, port: Sensors }
+  destination: [ { partition: ctrl, port: Sensors } ]
+- !Sampling
+  msg_size: 8B
+  source: { partition: lab, port: Setpoint }
+  destination: [ { partition: ctrl, port: Setpoint } ]
+- !Queuing
+  msg_size: 8B
+  msg_num: 2
+  source: { partition: lab, port: Cmd }
+  destination: { partition: ctrl, port: Cmd }
+- !Queuing
+  msg_size: 8B
+  msg_num: 4
+  source: { partition: ctrl, port: Log }
+  destination: { partition: sim, port: Log }
+"#;
+
+    /// The broker behind a [MockClient]
+    #[derive(Debug, Default)]
+    struct MockBroker {
+        /// Number of connection attempts which still fail
+        failing_connects: usize,
+        /// Whether the connection is lost on the next poll
+        disconnect: bool,
+        subscribed: Vec<String>,
+        published: Vec<(String, Vec<u8>)>,
+        inbox: VecDeque<(String, Vec<u8>)>,
+    }
+
+    struct MockClient(Arc<Mutex<MockBroker>>);
+
+    impl MqttClient for MockClient {
+        fn connect(&mut self, topics: &[String]) -> anyhow::Result<()> {
+            let mut broker = self.0.lock().unwrap();
+            if broker.failing_connects > 0 {
+                broker.failing_connects -= 1;
+                bail!("connection refused");
+            }
+            broker.subscribed = topics.to_vec();
+            Ok(())
+        }
+
+        fn publish(&mut self, topic: &str, payload: Vec<u8>) -> anyhow::Result<()> {
+            let mut broker = self.0.lock().unwrap();
+            broker.published.push((topic.to_string(), payload));
+            Ok(())
+        }
+
+        fn poll(&mut self, timeout: Duration) -> anyhow::Result<Option<(String, Vec<u8>)>> {
+            let mut broker = self.0.lock().unwrap();
+            if mem::take(&mut broker.disconnect) {
+                bail!("connection reset");
+            }
+            let msg = broker.inbox.pop_front();
+            drop(broker);
+            if msg.is_none() {
+                thread::sleep(timeout);
+            }
+            Ok(msg)
+        }
+    }
+
+    struct Setup {
+        broker: Arc<Mutex<MockBroker>>,
+        bridge: MqttBridge,
+        sampling: HashMap<String, Sampling>,
+        queuing: HashMap<String, Queuing>,
+    }
+
+    fn setup() -> Setup {
+        let config: MqttBridgeConfig = serde_yaml::from_str(BRIDGE).unwrap();
+        let channels: Vec<Channel> = serde_yaml::from_str(CHANNELS).unwrap();
+        let mut sampling = HashMap::new();
+        let mut queuing = HashMap::new();
+        for channel in channels.iter().cloned() {
+            match channel {
+                Channel::Sampling(s) => {
+                    let s = Sampling::try_from(s).unwrap();
+                    sampling.insert(s.name(), s);
+                }
+                Channel::Queuing(q) => {
+                    let q = Queuing::try_from(q).unwrap();
+                    queuing.insert(q.name(), q);
+                }
+            }
+        }
+
+        let broker = Arc::new(Mutex::new(MockBroker::default()));
+        let client = Box::new(MockClient(broker.clone()));
+        let bridge = MqttBridge::new(&config, &channels, client).unwrap();
+        bridge.tap(&mut sampling, &mut queuing);
+        Setup {
+            broker,
+            bridge,
+            sampling,
+            queuing,
+        }
+    }
+
+    /// Waits a few seconds for `condition` to hold
+    fn eventually(mut condition: impl FnMut() -> bool) -> bool {
+        let until = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < until {
+            if condition() {
+                return true;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        condition()
+    }
+
+    #[test]
+    fn channels_and_topics() {
+        let Setup {
+            broker,
+            mut bridge,
+            mut sampling,
+            mut queuing,
+        } = setup();
+        bridge.start().unwrap();
+        assert!(eventually(
+            || broker.lock().unwrap().subscribed == ["lab/cmd", "lab/setpoint"]
+        ));
+        assert_eq!(
+            bridge.bridged_partitions(&sampling, &queuing),
+            HashSet::from(["sim", "ctrl", "lab"].map(String::from))
+        );
+
+        let sensors = sampling.get_mut("sim:Sensors").unwrap();
+        let mut source = SamplingSource::try_from(sensors.source_fd().as_raw_fd()).unwrap();
+        source.write(b"fuel");
+        assert!(sensors.swap());
+        let log = queuing.get_mut("ctrl:Log").unwrap();
+        let mut source = QueuingSource::try_from(log.source_fd()).unwrap();
+        source.write(b"one", MonotonicTime::now()).unwrap();
+        source.write(b"two", MonotonicTime::now()).unwrap();
+        assert!(log.swap());
+
+        bridge.publish(&Transferred::take(&mut sampling, &mut queuing));
+        // Only messages transferred since the last call are published
+        bridge.publish(&Transferred::take(&mut sampling, &mut queuing));
+        assert!(eventually(|| bridge.stats().published == 4));
+        assert_eq!(
+            broker.lock().unwrap().published,
+            [
+                ("lab/sensors".to_string(), b"ZnVlbA==".to_vec()),
+                ("lab/sensors/raw".to_string(), b"fuel".to_vec()),
+                ("lab/log".to_string(), b"one".to_vec()),
+                ("lab/log".to_string(), b"two".to_vec()),
+            ]
+        );
+
+        broker.lock().unwrap().inbox.extend([
+            ("lab/cmd".to_string(), b"open".to_vec()),
+            ("lab/setpoint".to_string(), b"NDI=".to_vec()),
+            ("lab/setpoint".to_string(), b"not base64!".to_vec()),
+            ("lab/cmd".to_string(), b"too long!".to_vec()),
+            ("lab/unknown".to_string(), b"ignored".to_vec()),
+        ]);
+        assert!(eventually(|| {
+            bridge.inject(&mut sampling, &mut queuing);
+            let stats = bridge.stats();
+            stats.received + stats.dropped_incoming == 4
+        }));
+        let stats = bridge.stats();
+        assert_eq!((stats.received, stats.dropped_incoming), (2, 2));
+
+        let mut buf = [0; 8];
+        let cmd = &queuing["lab:Cmd"];
+        let mut destination = QueuingDestination::try_from(cmd.destination_fd()).unwrap();
+        let len = destination.read(&mut buf).unwrap().len;
+        assert_eq!(&buf[..len], b"open");
+        assert_eq!(destination.read(&mut buf), None);
+        let setpoint = &sampling["lab:Setpoint"];
+        let mut destination =
+            SamplingDestination::try_from(setpoint.destination_fd().as_raw_fd()).unwrap();
+        let (len, _) = destination.read(&mut buf);
+        assert_eq!(&buf[..len], b"42");
+    }
+
+    #[test]
+    fn reconnects_and_drops_when_full() {
+        let Setup {
+            broker,
+            mut bridge,
+            mut sampling,
+            mut queuing,
+        } = setup();
+
+        // Nothing drains the queue before the bridge is started
+        for i in 0..3 {
+            let sensors = sampling.get_mut("sim:Sensors").unwrap();
+            let mut source = SamplingSource::try_from(sensors.source_fd().as_raw_fd()).unwrap();
+            source.write(&[i]);
+            assert!(sensors.swap());
+            bridge.publish(&Transferred::take(&mut sampling, &mut queuing));
+        }
+        assert_eq!(bridge.stats().dropped_outgoing, 2);
+
+        broker.lock().unwrap().failing_connects = 2;
+        bridge.start().unwrap();
+        assert!(!bridge.is_connected());
+        assert!(eventually(|| bridge.stats().connects == 1));
+        assert!(eventually(|| bridge.is_connected()));
+        assert_eq!(broker.lock().unwrap().failing_connects, 0);
+        assert!(eventually(|| bridge.stats().published == 4));
+
+        // The bridge is down until one of the reconnects succeeds
+        {
+            let mut broker = broker.lock().unwrap();
+            broker.disconnect = true;
+            broker.failing_connects = 3;
+        }
+        assert!(eventually(|| !bridge.is_connected()));
+        assert!(eventually(|| bridge.stats().connects == 2));
+        assert!(eventually(|| bridge.is_connected()));
+        // Dropping the bridge stops its thread
+        drop(bridge);
+    }
+
+    #[test]
+    fn broker_addresses() {
+        let config: MqttBridgeConfig = serde_yaml::from_str(BRIDGE).unwrap();
+        assert_eq!(config.client_id, "a653rs-linux-hypervisor");
+        let address = |broker: &str| {
+            MqttBridgeConfig {
+                broker: broker.to_string(),
+                ..config.clone()
+            }
+            .address()
+        };
+        assert_eq!(address("mqtt://localhost"), Ok(("localhost".into(), 1883)));
+        assert_eq!(
+            address("tcp://10.0.0.1:1884/"),
+            Ok(("10.0.0.1".into(), 1884))
+        );
+        assert!(address("http://localhost").is_err());
+        assert!(address("mqtt://:1883").is_err());
+        assert!(address("mqtt://localhost:mqtt").is_err());
+    }
+
+    #[test]
+    fn invalid_bridges_are_errors() {
+        let config: MqttBridgeConfig = serde_yaml::from_str(BRIDGE).unwrap();
+        let mut channels: Vec<Channel> = serde_yaml::from_str(CHANNELS).unwrap();
+        config.validate(&channels).unwrap();
+
+        let bridge = r#"
+broker: mqtt://localhost
+mappings:
+  - { channel: Missing, topic: lab/missing, direction: publish }
+  - { channel: Cmd, topic: "lab/#", direction: subscribe }
+  - { channel: Sensors, topic: lab/echo, direction: publish }
+  - { channel: Setpoint, topic: lab/echo, direction: subscribe }
+"#;
+        let invalid: MqttBridgeConfig = serde_yaml::from_str(bridge).unwrap();
+        let err = invalid.validate(&channels).unwrap_err();
+        assert_eq!(err.err(), SystemError::Config);
+        let err = format!("{err:?}");
+        for problem in [
+            "channel \"Missing\" is not configured",
+            "topic \"lab/#\" of channel \"Cmd\"",
+            "topic \"lab/echo\" is both published and subscribed",
+        ] {
+            assert!(err.contains(problem), "{err}");
+        }
+
+        // Channels sharing the name of their source port are told apart by partition
+        channels.extend(
+            serde_yaml::from_str::<Vec<Channel>>(
+                r#"
+- !Sampling
+  msg_size: 8B
+  source: { partition: sim2, port: Sensors }
+  destination: []
+"#,
+            )
+            .unwrap(),
+        );
+        let err = config.validate(&channels).unwrap_err();
+        assert!(format!("{err:?}").contains("ambiguous"), "{err:?}");
+    }
+}
@@ -1,38 +1,65 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{ErrorKind, Write};
 use std::net::{TcpStream, UdpSocket};
+use std::os::unix::fs::FileExt;
 use std::os::unix::prelude::{AsRawFd, FromRawFd, OwnedFd, PermissionsExt, RawFd};
 use std::os::unix::process::CommandExt;
 use std::path::{self, Path, PathBuf};
 use std::process::{Command, Stdio};
-use std::thread::sleep;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use a653rs::bindings::{PartitionId, PortDirection};
 use a653rs::prelude::{OperatingMode, StartCondition};
 use a653rs_linux_core::cgroup::{self, CGroup};
+use a653rs_linux_core::channel::{Criticality, OnPartitionRestart, Transfer};
+use a653rs_linux_core::conditions::ModuleConditions;
+use a653rs_linux_core::deadline::Deadline;
 use a653rs_linux_core::error::{
     ErrorLevel, LeveledResult, ResultExt, SystemError, TypedError, TypedResult, TypedResultExt,
 };
 use a653rs_linux_core::file::TempFile;
 use a653rs_linux_core::health::{ModuleRecoveryAction, PartitionHMTable, RecoveryAction};
-use a653rs_linux_core::health_event::PartitionCall;
-use a653rs_linux_core::ipc::{bind_receiver, io_pair, IoReceiver, IoSender, IpcReceiver};
-use a653rs_linux_core::partition::{PartitionConstants, QueuingConstant, SamplingConstant};
+use a653rs_linux_core::health_event::{PartitionCall, ProcessKind};
+use a653rs_linux_core::ipc::{self, bind_receiver, io_pair, IoReceiver, IoSender, IpcReceiver};
+use a653rs_linux_core::mode::SharedMode;
+use a653rs_linux_core::netns::Veth;
+use a653rs_linux_core::partition::{
+    PartitionConstants, PortActivity, PortDecl, QueuingConstant, Requirements, RestartCause,
+    SamplingConstant,
+};
 use a653rs_linux_core::queuing::Queuing;
 use a653rs_linux_core::sampling::Sampling;
+use a653rs_linux_core::time::{ModuleTime, MonotonicTime};
+use a653rs_linux_core::wire;
 use anyhow::{anyhow, Context};
 use bytesize::ByteSize;
 use itertools::Itertools;
 pub use mounting::FileMounter;
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
 use nix::mount::{umount2, MntFlags};
 use nix::sched::{unshare, CloneFlags};
-use nix::unistd::{chdir, close, getpid, gettid, pivot_root, setgid, setuid, Gid, Pid, Uid};
+use nix::sys::eventfd::{EfdFlags, EventFd};
+use nix::sys::signal::{kill, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag};
+use nix::unistd::{
+    chdir, close, getpid, gettid, pipe, pipe2, pivot_root, read, setgid, setuid, Gid, Pid, Uid,
+};
 use polling::{Event, Events, Poller};
 use procfs::process::Process;
-use tempfile::{tempdir, TempDir};
+use tempfile::TempDir;
 
-use super::config::PosixSocket;
-use super::scheduler::Timeout;
+use super::config::{
+    AperiodicReserve, Image, PosixSocket, SocketOptions, Stdin, VethNetwork, FORWARDED_ENV,
+};
+use super::partition_fs::{self, PartitionFs};
+use super::runtime_dir::RuntimeDir;
+use super::socket;
+use super::telemetry::Telemetry;
+use super::trace::Tracer;
 use crate::hypervisor::config::Partition as PartitionConfig;
 use crate::hypervisor::SYSTEM_START_TIME;
 use crate::problem;
@@ -47,12 +74,43 @@ pub enum TransitionAction {
     Error,
 }
 
+/// Release of the aperiodic process started with `DELAYED_START`, see
+/// [PartitionCall::DelayedStart]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Release {
+    /// The delay, counted from the entry into the NORMAL mode
+    After(Duration),
+    At(MonotonicTime),
+}
+
+impl Release {
+    /// Resolves the release, if the process can be released `next` at the
+    /// earliest, returning `None` once it is due
+    fn resolve(self, next: MonotonicTime) -> Option<Release> {
+        let at = match self {
+            Release::After(delay) => next
+                .checked_add(delay)
+                .unwrap_or_else(|| MonotonicTime::from(Duration::MAX)),
+            Release::At(at) => at,
+        };
+        (at > next).then_some(Release::At(at))
+    }
+
+    fn deadline(self) -> Option<Deadline> {
+        match self {
+            Release::After(_) => None,
+            Release::At(at) => Some(Deadline::at(at)),
+        }
+    }
+}
+
 // Struct for holding information of a partition which is not in Idle Mode
 #[derive(Debug)]
 pub(crate) struct Run {
     _cgroup_main: CGroup,
     cgroup_aperiodic: CGroup,
     cgroup_periodic: CGroup,
+    _cgroup_held: CGroup,
 
     _main: Pid,
     periodic: bool,
@@ -60,13 +118,23 @@ pub(crate) struct Run {
 
     mode: OperatingMode,
     _mode_file_fd: OwnedFd,
-    mode_file: TempFile<OperatingMode>,
+    // Only still written for partitions of the previous release, see [SharedMode]
+    mode_file: TempFile<wire::OperatingMode>,
+    shared_mode: SharedMode,
     call_rx: IpcReceiver<PartitionCall>,
     // We need to keep the struct for the sender's side, so
     // the sockets currently in transmission are not closed
     // before the partition has received them.
     _io_udp_tx: IoSender<UdpSocket>,
     _io_tcp_tx: IoSender<TcpStream>,
+
+    /// Whether the partition had processes when the [EventPoller] last looked
+    populated: bool,
+    /// Delay of the releases of the periodic process into its release
+    /// windows, if it was started with one
+    periodic_delay: Option<Duration>,
+    /// Release of the aperiodic process, while it is delayed
+    aperiodic_release: Option<Release>,
 }
 
 impl Run {
@@ -85,6 +153,10 @@ impl Run {
         let cgroup_aperiodic = cgroup_processes
             .new_threaded(PartitionConstants::APERIODIC_PROCESS_CGROUP)
             .typ(SystemError::CGroup)?;
+        let cgroup_held = cgroup_processes
+            .new_threaded(PartitionConstants::HELD_PROCESSES_CGROUP)
+            .typ(SystemError::CGroup)?;
+        cgroup_held.freeze().typ(SystemError::CGroup)?;
         cgroup_base.freeze().typ(SystemError::CGroup)?;
 
         let real_uid = nix::unistd::getuid();
@@ -100,7 +172,17 @@ impl Run {
             .path()
             .join(PartitionConstants::IPC_SENDER.trim_start_matches('/'));
         std::fs::create_dir_all(ipc_path.parent().unwrap()).typ(SystemError::Panic)?;
+        // Replaces the socket of the previous run, if the partition restarted
+        match std::fs::remove_file(&ipc_path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(e).typ(SystemError::Panic)
+            }
+            _ => {}
+        }
         let call_rx = bind_receiver::<PartitionCall>(&ipc_path)?;
+        if let Some(size) = base.ipc_buffer {
+            call_rx.set_recv_buffer(size)?;
+        }
 
         // TODO add a `::new(warm_start: bool)->Self` function to `OperatingMode`, use
         // it here
@@ -111,7 +193,28 @@ impl Run {
         };
         let mode_file = TempFile::create("operation_mode")?;
         let mode_file_fd = unsafe { OwnedFd::from_raw_fd(mode_file.as_raw_fd()) };
-        mode_file.write(&mode)?;
+        mode_file.write(&mode.into())?;
+        let shared_mode = SharedMode::create("operation_mode_word", mode.into())?;
+        let mode_word_fd = shared_mode.word_fd();
+        let mode_event_fd = shared_mode.event_fd();
+
+        let mut stdin = match &base.stdin {
+            Stdin::Null => None,
+            Stdin::File(file) => Some(
+                File::open(file)
+                    .with_context(|| format!("failed to open stdin {file:?} of {}", base.name())),
+            ),
+            Stdin::Pipe => base.stdin_pipe.as_ref().map(|pipe| {
+                pipe.read
+                    .try_clone()
+                    .map(File::from)
+                    .context("failed to duplicate the stdin pipe")
+            }),
+        }
+        .transpose()
+        .typ(SystemError::PartitionInit)?;
+
+        let env = forwarded_env(&base.forward_env, |var| std::env::var_os(var));
 
         let IoTxRx {
             udp_io_tx,
@@ -120,6 +223,15 @@ impl Run {
             tcp_io_rx,
         } = send_sockets(base)?;
 
+        // The partition waits for the hypervisor to set up its network before starting
+        // the partition binary
+        let (network_ready_rx, network_ready_tx) = pipe().typ(SystemError::PartitionInit)?;
+        if let Some((veth, config)) = &base.veth {
+            veth.create(&config.bridge)
+                .with_context(|| format!("failed to create veth pair of {}", base.name()))
+                .typ(SystemError::PartitionInit)?;
+        }
+
         let callback = Box::new(move || -> isize {
             // Map User and user group (required for tmpfs mounts)
             std::fs::write(
@@ -148,8 +260,15 @@ impl Run {
             keep.extend_from_slice(&base.queuing_fds());
             keep.push(sys_time.as_raw_fd());
             keep.push(mode_file.as_raw_fd());
+            keep.push(base.restart_cause.as_raw_fd());
+            keep.push(base.conditions.as_raw_fd());
             keep.push(udp_io_rx.as_raw_fd());
             keep.push(tcp_io_rx.as_raw_fd());
+            keep.push(network_ready_rx.as_raw_fd());
+            keep.push(base.activity.as_raw_fd());
+            keep.push(mode_word_fd);
+            keep.push(mode_event_fd);
+            keep.extend(stdin.as_ref().map(|f| f.as_raw_fd()));
 
             Partition::release_fds(&keep).unwrap();
 
@@ -170,6 +289,10 @@ impl Run {
                 // IPC Socket for Syscalls
                 FileMounter::bind_rw(&ipc_path, ipc_path_inner).unwrap(),
             ];
+            // Files kept on the host, mounted before the mounts below it
+            if let Some(fs) = &base.fs {
+                mounts.push(FileMounter::tmpfs(&fs.config().target, fs.config().size));
+            }
 
             for (source, target) in base.mounts.iter().cloned() {
                 // make target path relative because they will later be appended to the
@@ -196,6 +319,19 @@ impl Run {
                     .typ(SystemError::Panic)
                     .unwrap();
             }
+            if let Some(fs) = &base.fs {
+                let target = tmpfs_path.join(fs.config().relative_target());
+                let stats = partition_fs::restore(&fs.config().host_dir, &target)
+                    .with_context(|| {
+                        format!(
+                            "failed to restore the files of {}",
+                            fs.config().host_dir.display()
+                        )
+                    })
+                    .typ(SystemError::PartitionInit)
+                    .unwrap();
+                debug!("restored {} files", stats.copied);
+            }
 
             // Change working directory and root (unmount old root)
             chdir(&tmpfs_path).unwrap();
@@ -206,15 +342,23 @@ impl Run {
             let constants: RawFd = PartitionConstants {
                 name: base.name.clone(),
                 identifier: base.id,
+                role: base.role.clone(),
                 period: base.period,
                 duration: base.duration,
                 start_condition: condition,
                 start_time_fd: sys_time.as_raw_fd(),
                 partition_mode_fd: mode_file.as_raw_fd(),
+                restart_cause_fd: base.restart_cause.as_raw_fd(),
+                conditions_fd: base.conditions.as_raw_fd(),
                 udp_io_fd: udp_io_rx.as_raw_fd(),
                 tcp_io_fd: tcp_io_rx.as_raw_fd(),
+                activity_fd: base.activity.as_raw_fd(),
                 sampling: base.sampling_channel.clone().into_values().collect_vec(),
                 queuing: base.queuing_channel.clone().into_values().collect_vec(),
+                ipc_buffer: base.ipc_buffer,
+                realtime: base.realtime,
+                mode_word_fd,
+                mode_event_fd,
             }
             .try_into()
             .unwrap();
@@ -223,13 +367,20 @@ impl Run {
             let mut command = Command::new("/bin");
             let mut command = command
                 .stdout(Stdio::null())
-                .stdin(Stdio::null())
+                .stdin(stdin.take().map_or_else(Stdio::null, Stdio::from))
                 .stderr(Stdio::null())
+                // Only pass on an allowlist of the environment of the hypervisor
+                .env_clear()
+                .envs(env.iter().map(|(var, value)| (var, value)))
                 // Set Partition Name Env
                 .env(
                     PartitionConstants::PARTITION_CONSTANTS_FD,
                     constants.to_string(),
                 );
+            // Also available to partitions not using the partition library
+            if let Some(role) = &base.role {
+                command = command.env(PartitionConstants::PARTITION_ROLE, role);
+            }
             unsafe {
                 let path = cgroup::mount_point().typ(SystemError::CGroup).unwrap();
                 let path = path
@@ -237,14 +388,16 @@ impl Run {
                     .join(PartitionConstants::MAIN_PROCESS_CGROUP);
                 let cgroup_main = CGroup::import_root(path).typ(SystemError::CGroup).unwrap();
 
-                command = command.pre_exec(move || {
-                    cgroup_main
-                        .mv_proc(gettid())
-                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
-                });
+                command = command
+                    .pre_exec(move || cgroup_main.mv_proc(gettid()).map_err(std::io::Error::other));
             }
 
-            command.exec();
+            // Block until the hypervisor closes its end of the pipe, retrying
+            // reads interrupted by signals
+            let network_ready = network_ready_rx.as_raw_fd();
+            while let Ok(1..) | Err(Errno::EINTR) = read(network_ready, &mut [0; 1]) {}
+
+            let _ = command.exec();
             unsafe { libc::_exit(0) };
         });
 
@@ -264,6 +417,35 @@ impl Run {
             )
         }
         .unwrap();
+
+        let setup = match &base.veth {
+            Some((veth, config)) => veth
+                .move_into(pid, &config.address, config.gateway)
+                .with_context(|| format!("failed to set up network of {}", base.name()))
+                .typ(SystemError::PartitionInit),
+            None => Ok(()),
+        }
+        .and_then(|_| grant_priorities(base.name(), pid, base.realtime));
+        if let Err(e) = setup {
+            // The partition never got to exec, it still waits for its network
+            if let Err(e) = kill(pid, Signal::SIGKILL) {
+                warn!("failed to kill the partition {}: {e}", base.name());
+            } else {
+                // Reaped with __WALL since the clone reports no exit signal
+                waitpid(pid, Some(WaitPidFlag::__WALL)).ok();
+            }
+            if let Some((veth, _)) = &base.veth {
+                if let Err(e) = veth.remove() {
+                    warn!("failed to remove veth pair of {}: {e:?}", base.name());
+                }
+            }
+            return Err(e);
+        }
+        if let Some(fs) = &base.fs {
+            fs.started(pid);
+        }
+        drop(network_ready_tx);
+
         debug!(
             "Successfully created Partition {}. Main Pid: {pid}",
             base.name()
@@ -273,15 +455,20 @@ impl Run {
             _cgroup_main: cgroup_main,
             cgroup_aperiodic,
             cgroup_periodic,
+            _cgroup_held: cgroup_held,
             _main: pid,
             mode,
             mode_file,
+            shared_mode,
             call_rx,
             _io_udp_tx: udp_io_tx,
             _io_tcp_tx: tcp_io_tx,
             periodic: false,
             aperiodic: false,
             _mode_file_fd: mode_file_fd,
+            populated: true,
+            periodic_delay: None,
+            aperiodic_release: None,
         })
     }
 
@@ -289,6 +476,27 @@ impl Run {
         self.mode
     }
 
+    /// Checks that the mode file shared with the partition holds the current
+    /// operating mode
+    pub fn verify_mode_file(&self) -> TypedResult<()> {
+        let bytes = self.mode_file.read_bytes()?;
+        let value = <[u8; 4]>::try_from(bytes.as_slice())
+            .map(u32::from_ne_bytes)
+            .map_err(|_| anyhow!("mode file holds {} bytes", bytes.len()))
+            .typ(SystemError::Segmentation)?;
+        let mode = wire::OperatingMode::try_from(value)
+            .map_err(|e| anyhow!("mode file: {e}"))
+            .typ(SystemError::Segmentation)?;
+        if mode != wire::OperatingMode::from(self.mode) {
+            return Err(anyhow!(
+                "mode file holds {mode:?}, while the partition is in {:?}",
+                self.mode
+            ))
+            .typ(SystemError::Segmentation);
+        }
+        Ok(())
+    }
+
     pub fn receiver(&self) -> &IpcReceiver<PartitionCall> {
         &self.call_rx
     }
@@ -317,14 +525,16 @@ impl Run {
         Ok(false)
     }
 
-    pub fn periodic_events(&self) -> TypedResult<OwnedFd> {
-        Ok(std::fs::File::open(self.cgroup_periodic.get_events_path())
-            .typ(SystemError::CGroup)?
-            .into())
+    /// Opens the `cgroup.events` file of the periodic process, which reports
+    /// whether it is frozen
+    pub fn periodic_events(&self) -> TypedResult<File> {
+        File::open(self.cgroup_periodic.get_events_path()).typ(SystemError::CGroup)
     }
 
+    /// Whether the periodic process is done with its window, see
+    /// [periodic_done]
     pub fn is_periodic_frozen(&self) -> TypedResult<bool> {
-        self.cgroup_periodic.frozen().typ(SystemError::CGroup)
+        periodic_done(&self.cgroup_periodic)
     }
 
     pub fn freeze_periodic(&self) -> TypedResult<bool> {
@@ -335,12 +545,59 @@ impl Run {
         Ok(false)
     }
 
+    /// Delays the release of the `process`, which the partition starts right
+    /// after, see [PartitionCall::DelayedStart]
+    ///
+    /// The process is scheduled from now on, even if the partition is in the
+    /// NORMAL mode already.
+    pub fn delay(&mut self, process: ProcessKind, delay: Duration) {
+        let release = Some(Release::After(delay));
+        match process {
+            ProcessKind::Periodic => {
+                // A zero delay undoes the one of an earlier start
+                self.periodic_delay = (!delay.is_zero()).then_some(delay);
+                self.periodic |= self.mode == OperatingMode::Normal;
+            }
+            ProcessKind::Aperiodic if self.mode == OperatingMode::Normal => {
+                self.aperiodic_release = release.and_then(|r| r.resolve(MonotonicTime::now()));
+                self.aperiodic = true;
+            }
+            ProcessKind::Aperiodic => self.aperiodic_release = release,
+            ProcessKind::Main => warn!("Ignoring the delayed start of the main process"),
+        }
+    }
+
+    /// Release of the periodic process in a release window starting `now`,
+    /// `None` unless the process is delayed beyond it
+    ///
+    /// The delay of the periodic process counts from the start of every
+    /// release window after it was started, so that it keeps its offset to
+    /// the release points of the partition.
+    pub fn periodic_release(&self, now: MonotonicTime) -> Option<Deadline> {
+        self.periodic_delay
+            .and_then(|delay| now.checked_add(delay))
+            .map(Deadline::at)
+    }
+
+    /// Release of the aperiodic process, `None` unless it is still delayed
+    ///
+    /// The delay counts from the entry into the NORMAL mode.
+    pub fn aperiodic_release(&mut self) -> Option<Deadline> {
+        if self.mode == OperatingMode::Normal {
+            let now = MonotonicTime::now();
+            self.aperiodic_release = self.aperiodic_release.and_then(|r| r.resolve(now));
+        }
+        self.aperiodic_release.and_then(Release::deadline)
+    }
+
     /// Return error if invalid transition was requested
     /// Return Ok(None) if no action was taken
     pub fn handle_transition(
         &mut self,
         base: &Base,
         mode: OperatingMode,
+        sampling_channels: &mut HashMap<String, Sampling>,
+        queuing: &mut HashMap<String, Queuing>,
     ) -> TypedResult<Option<OperatingMode>> {
         match (mode, self.mode) {
             // TODO this should be an error
@@ -353,11 +610,25 @@ impl Run {
                 TypedResult::Ok(Some(OperatingMode::Idle))
             }
             (OperatingMode::ColdStart, _) => {
-                self.start_transition(base, false, StartCondition::PartitionRestart)?;
+                self.start_transition(
+                    base,
+                    false,
+                    StartCondition::PartitionRestart,
+                    None,
+                    sampling_channels,
+                    queuing,
+                )?;
                 TypedResult::Ok(Some(OperatingMode::ColdStart))
             }
             (OperatingMode::WarmStart, _) => {
-                self.start_transition(base, true, StartCondition::PartitionRestart)?;
+                self.start_transition(
+                    base,
+                    true,
+                    StartCondition::PartitionRestart,
+                    None,
+                    sampling_channels,
+                    queuing,
+                )?;
                 TypedResult::Ok(Some(OperatingMode::WarmStart))
             }
             (OperatingMode::Normal, _) => {
@@ -387,18 +658,33 @@ impl Run {
         self.freeze_periodic()?;
 
         self.mode = OperatingMode::Normal;
-        self.mode_file.write(&self.mode)?;
+        self.mode_file.write(&self.mode.into())?;
+        // Wakes up the threads waiting for the change, once they are unfrozen
+        self.shared_mode.set(self.mode.into())?;
 
-        self.cgroup_aperiodic.unfreeze().typ(SystemError::CGroup)?;
+        // A delayed aperiodic process is released by the aperiodic phase of a
+        // later window
+        if self.aperiodic_release().is_none() {
+            self.cgroup_aperiodic.unfreeze().typ(SystemError::CGroup)?;
+        }
         base.unfreeze()?;
         Ok(())
     }
 
+    /// Restarts the partition
+    ///
+    /// `cause` is the error which made the health monitor restart the
+    /// partition, if any. It is passed on to the new incarnation. The channels
+    /// connected to the partition are cleared as configured once its
+    /// processes are gone, before any other partition runs again.
     pub fn start_transition(
         &mut self,
         base: &Base,
         warm_start: bool,
         cond: StartCondition,
+        cause: Option<SystemError>,
+        sampling_channels: &mut HashMap<String, Sampling>,
+        queuing: &mut HashMap<String, Queuing>,
     ) -> TypedResult<()> {
         if base.is_frozen()? {
             return Err(anyhow!("May not transition while in a frozen state"))
@@ -406,7 +692,21 @@ impl Run {
         }
 
         base.freeze()?;
+        // Keeps what the partition wrote since the last sync
+        if let Some(fs) = &base.fs {
+            fs.sync_now();
+        }
         base.kill()?;
+        // The new run creates the cgroups of its processes again
+        CGroup::import_root(
+            base.cgroup
+                .get_path()
+                .join(PartitionConstants::PROCESSES_CGROUP),
+        )
+        .and_then(|processes| processes.rm())
+        .typ(SystemError::CGroup)?;
+        base.clear_channels(sampling_channels, queuing);
+        base.write_restart_cause(cause)?;
 
         *self = Run::new(base, cond, warm_start).typ(SystemError::PartitionInit)?;
 
@@ -424,7 +724,9 @@ impl Run {
         self.freeze_periodic()?;
 
         self.mode = OperatingMode::Idle;
-        self.mode_file.write(&self.mode)?;
+        self.mode_file.write(&self.mode.into())?;
+        // Nothing wakes up, as the partition stays frozen until it is restarted
+        self.shared_mode.set(self.mode.into())?;
 
         Ok(())
     }
@@ -434,6 +736,28 @@ impl Run {
     }
 }
 
+/// What the hypervisor observed of a partition since its creation, as
+/// reported by `validate-partition`
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Observations {
+    /// Module time at which the partition first entered the NORMAL mode
+    pub normal_at: Option<ModuleTime>,
+    /// Ports of the last declaration of the partition, if it declared any
+    pub declared_ports: Option<Vec<PortDecl>>,
+    /// Mismatches of the declared ports with the configuration
+    pub port_mismatches: Vec<String>,
+    /// Mismatches of the declared period and window with the configuration
+    pub timing_mismatches: Vec<String>,
+    /// Ports created by the partition, in the order of their creation
+    pub created_ports: Vec<String>,
+    /// Ports created by the partition which are not configured
+    pub unconfigured_ports: Vec<String>,
+    /// Number of log records sent by the partition
+    pub log_records: u64,
+    /// Errors of the partition handled by the health monitor
+    pub errors: Vec<SystemError>,
+}
+
 struct IoTxRx {
     udp_io_tx: IoSender<UdpSocket>,
     udp_io_rx: IoReceiver<UdpSocket>,
@@ -441,16 +765,43 @@ struct IoTxRx {
     tcp_io_rx: IoReceiver<TcpStream>,
 }
 
+/// Whether the periodic process in `cgroup` is done with its window
+///
+/// It either froze its cgroup waiting for its next period, or its thread left
+/// the cgroup, as the process was stopped. Stopping itself must not keep the
+/// rest of the window from the aperiodic process.
+fn periodic_done(cgroup: &CGroup) -> TypedResult<bool> {
+    Ok(
+        cgroup.frozen().typ(SystemError::CGroup)?
+            || !cgroup.populated().typ(SystemError::CGroup)?,
+    )
+}
+
+/// Environment variables passed on to a partition: those of [FORWARDED_ENV]
+/// and `extra` which are set according to `lookup`
+fn forwarded_env(
+    extra: &[String],
+    lookup: impl Fn(&str) -> Option<OsString>,
+) -> Vec<(String, OsString)> {
+    FORWARDED_ENV
+        .iter()
+        .copied()
+        .chain(extra.iter().map(String::as_str))
+        .unique()
+        .filter_map(|var| lookup(var).map(|value| (var.to_string(), value)))
+        .collect()
+}
+
 fn send_sockets(base: &Base) -> Result<IoTxRx, a653rs_linux_core::error::TypedError> {
     let (udp_io_tx, udp_io_rx) = io_pair::<UdpSocket>()?;
     let (tcp_io_tx, tcp_io_rx) = io_pair::<TcpStream>()?;
     for addr in base.sockets.iter() {
         match addr {
-            PosixSocket::TcpConnect { address } => tcp_io_tx
-                .try_send(TcpStream::connect(address.clone()).typ(SystemError::Panic)?)
+            PosixSocket::TcpConnect { address, options } => tcp_io_tx
+                .try_send(socket::connect_tcp(address, options)?)
                 .typ(SystemError::Panic)?,
-            PosixSocket::Udp { address } => udp_io_tx
-                .try_send(UdpSocket::bind(address.clone()).typ(SystemError::Panic)?)
+            PosixSocket::Udp { address, options } => udp_io_tx
+                .try_send(socket::bind_udp(address, options)?)
                 .typ(SystemError::Panic)?,
         }
     }
@@ -462,6 +813,28 @@ fn send_sockets(base: &Base) -> Result<IoTxRx, a653rs_linux_core::error::TypedEr
     })
 }
 
+/// Both ends of the stdin pipe of a partition
+///
+/// Every start of the partition gets a copy of the read end. The write end is
+/// non-blocking, so that a full pipe does not stall the schedule.
+#[derive(Debug)]
+struct StdinPipe {
+    read: OwnedFd,
+    write: File,
+}
+
+impl StdinPipe {
+    fn new() -> TypedResult<Self> {
+        let (read, write) = pipe2(OFlag::O_CLOEXEC).typ(SystemError::PartitionInit)?;
+        fcntl(write.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK))
+            .typ(SystemError::PartitionInit)?;
+        Ok(Self {
+            read,
+            write: write.into(),
+        })
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct Base {
     name: String,
@@ -476,6 +849,38 @@ pub(crate) struct Base {
     period: Duration,
     working_dir: TempDir,
     sockets: Vec<PosixSocket>,
+    aperiodic_reserve: Option<AperiodicReserve>,
+    veth: Option<(Veth, VethNetwork)>,
+    activity: EventFd,
+    strict_ports: bool,
+    strict_requirements: bool,
+    /// Requirements declared by the partition which are not met, if they keep
+    /// it from entering NORMAL
+    unmet_requirements: Vec<String>,
+    role: Option<String>,
+    stdin: Stdin,
+    /// The pipe of `stdin: pipe`
+    stdin_pipe: Option<StdinPipe>,
+    forward_env: Vec<String>,
+    restart_cause: TempFile<Option<RestartCause>>,
+    /// The conditions of the module affecting the partition, as last written
+    /// to `conditions`
+    module_conditions: ModuleConditions,
+    conditions: TempFile<ModuleConditions>,
+    /// Sampling channels whose port was created by the partition
+    created_sampling: HashSet<String>,
+    /// Queuing channels whose port was created by the partition
+    created_queuing: HashSet<String>,
+    observed: Observations,
+    telemetry: Telemetry,
+    /// Size of the buffers of the socket for the calls of the partition
+    ipc_buffer: Option<usize>,
+    /// Highest `SCHED_FIFO` priority granted to the processes of the partition
+    realtime: Option<u8>,
+    /// Files of the partition kept on the host
+    fs: Option<Arc<PartitionFs>>,
+    /// Whether log records of the partition are counted instead of printed
+    suppress_messages: bool,
 }
 
 impl Base {
@@ -487,6 +892,98 @@ impl Base {
         self.cgroup.unfreeze().typ(SystemError::CGroup)
     }
 
+    /// Discards the messages of all channels connected to this partition,
+    /// which are configured to be cleared or zeroized on a restart, and
+    /// forgets the processes waiting on its queuing ports
+    fn clear_channels(
+        &self,
+        sampling_channels: &mut HashMap<String, Sampling>,
+        queuing: &mut HashMap<String, Queuing>,
+    ) {
+        let name = self.name();
+        for (channel_name, channel) in sampling_channels
+            .iter_mut()
+            .filter(|(_, s)| s.is_connected_to(name))
+        {
+            let res = if channel.zeroizes() {
+                debug!("zeroizing sampling channel {channel_name} after restart of {name}");
+                channel.zeroize()
+            } else if channel.on_partition_restart() == OnPartitionRestart::Clear {
+                debug!("clearing sampling channel {channel_name} after restart of {name}");
+                channel.clear()
+            } else {
+                continue;
+            };
+            if let Err(e) = res {
+                warn!("failed to clear sampling channel {channel_name}: {e}");
+            }
+        }
+
+        for (channel_name, channel) in queuing.iter_mut().filter(|(_, q)| q.is_connected_to(name)) {
+            // The processes waiting on or pushing to the ports are gone in any case
+            channel.reset_waiting(name);
+            channel.abandon_pushes(name);
+            if channel.zeroizes() {
+                debug!("zeroizing queuing channel {channel_name} after restart of {name}");
+                channel.zeroize();
+            } else if channel.on_partition_restart() == OnPartitionRestart::Clear {
+                debug!("clearing queuing channel {channel_name} after restart of {name}");
+                channel.clear_all();
+            }
+        }
+    }
+
+    /// Records why the next incarnation of the partition is started, `None`
+    /// if the health monitor did not initiate the start
+    fn write_restart_cause(&self, error: Option<SystemError>) -> TypedResult<()> {
+        let cause = match error {
+            Some(error) => Some(RestartCause {
+                error,
+                time: module_time()?,
+            }),
+            None => None,
+        };
+        self.restart_cause.write(&cause)
+    }
+
+    /// Updates the conditions of the module shared with the partition, if
+    /// they changed
+    fn set_module_conditions(&mut self, conditions: ModuleConditions) -> TypedResult<()> {
+        if conditions == self.module_conditions {
+            return Ok(());
+        }
+        info!(
+            "Module conditions of {} changed to: {conditions}",
+            self.name
+        );
+        self.conditions.write(&conditions)?;
+        self.module_conditions = conditions;
+        Ok(())
+    }
+
+    /// Prints a log record of the partition, or only counts it if its
+    /// messages are suppressed
+    fn print_log(&mut self, record: &PartitionCall) {
+        self.observed.log_records += 1;
+        if self.suppress_messages {
+            self.telemetry.record_suppressed_message();
+        } else {
+            record.print_partition_log(&self.name)
+        }
+    }
+
+    /// Records a value of a gauge reported by the partition
+    fn record_telemetry(&mut self, name: &str, value: f64) -> TypedResult<()> {
+        let now = module_time()?.as_duration();
+        self.telemetry.record(&self.name, name, value, now);
+        Ok(())
+    }
+
+    /// Records the number of calls the partition dropped so far
+    fn record_dropped_calls(&mut self, dropped: u64) {
+        self.telemetry.record_dropped_calls(&self.name, dropped);
+    }
+
     pub fn sampling_fds(&self) -> Vec<RawFd> {
         self.sampling_channel.values().map(|s| s.fd).collect_vec()
     }
@@ -503,6 +1000,12 @@ impl Base {
         self.cgroup.frozen().typ(SystemError::CGroup)
     }
 
+    /// Opens the `cgroup.events` file of the partition, which reports whether
+    /// any of its processes is alive
+    pub fn cgroup_events(&self) -> TypedResult<File> {
+        File::open(self.cgroup.get_events_path()).typ(SystemError::CGroup)
+    }
+
     pub fn part_hm(&self) -> &PartitionHMTable {
         &self.hm
     }
@@ -510,6 +1013,244 @@ impl Base {
     pub fn kill(&self) -> TypedResult<()> {
         self.cgroup.kill().typ(SystemError::CGroup)
     }
+
+    /// Wakes up processes of the partition waiting for new data on their ports
+    pub fn notify_port_activity(&self, activity: PortActivity) {
+        if !activity.any() {
+            return;
+        }
+        if let Err(e) = self.activity.write(activity.to_eventfd_value()) {
+            warn!("failed to notify {} about port activity: {e}", self.name);
+        }
+    }
+
+    /// Records a port created by the partition
+    ///
+    /// The channel of a source port is only swapped after its creation, as
+    /// nothing can be written to it before.
+    fn port_created(&mut self, port: &PortDecl) {
+        self.observed.created_ports.push(describe_port(port));
+        let (channel, created) = match port {
+            PortDecl::Sampling { name, .. } => (
+                self.sampling_channel
+                    .iter()
+                    .find(|(_, s)| &s.name == name)
+                    .map(|(c, _)| c),
+                &mut self.created_sampling,
+            ),
+            PortDecl::Queuing { name, .. } => (
+                self.queuing_channel
+                    .iter()
+                    .find(|(_, q)| &q.name == name)
+                    .map(|(c, _)| c),
+                &mut self.created_queuing,
+            ),
+        };
+        match channel {
+            Some(channel) => {
+                if created.insert(channel.clone()) {
+                    debug!("{} created its port of channel {channel}", self.name);
+                }
+            }
+            None => {
+                warn!("{} created the unconfigured port {port:?}", self.name);
+                self.observed.unconfigured_ports.push(describe_port(port));
+            }
+        }
+    }
+
+    /// Warns about every configured port which the partition did not create
+    pub fn warn_uncreated_ports(&self, frames: u64) {
+        for port in uncreated_ports(
+            &self.sampling_channel,
+            &self.queuing_channel,
+            &self.created_sampling,
+            &self.created_queuing,
+        ) {
+            warn!(
+                "Partition {} did not create its {port} within {frames} major frames",
+                self.name
+            );
+        }
+    }
+
+    /// Cross-checks the ports declared by the partition against its channel
+    /// configuration
+    pub fn verify_port_declarations(&mut self, decls: &[PortDecl]) -> TypedResult<()> {
+        self.observed.declared_ports = Some(decls.to_vec());
+        self.observed.port_mismatches = decls
+            .iter()
+            .flat_map(|d| {
+                d.channel_mismatches(self.sampling_channel.iter(), self.queuing_channel.iter())
+            })
+            .collect();
+        verify_port_declarations(
+            &self.name,
+            self.strict_ports,
+            decls,
+            self.sampling_channel.iter(),
+            self.queuing_channel.iter(),
+        )
+    }
+
+    /// Cross-checks the requirements declared by the partition against its
+    /// configuration, logging every mismatch
+    ///
+    /// With `strict_requirements`, the mismatches keep the partition from
+    /// entering NORMAL.
+    pub fn verify_requirements(&mut self, requirements: &Requirements) {
+        let ports = requirements
+            .ports
+            .iter()
+            .flat_map(|d| {
+                d.channel_mismatches(self.sampling_channel.iter(), self.queuing_channel.iter())
+            })
+            .collect_vec();
+        let timing = requirements.timing_mismatches(self.period, self.duration);
+        let mismatches = timing.iter().chain(&ports).cloned().collect_vec();
+
+        if mismatches.is_empty() {
+            debug!("All requirements declared by {} are met", self.name);
+        }
+        for m in &mismatches {
+            if self.strict_requirements {
+                error!("Partition {}: {m}", self.name);
+            } else {
+                warn!("Partition {}: {m}", self.name);
+            }
+        }
+        self.unmet_requirements = if self.strict_requirements {
+            mismatches
+        } else {
+            Vec::new()
+        };
+        self.observed.declared_ports = Some(requirements.ports.clone());
+        self.observed.port_mismatches = ports;
+        self.observed.timing_mismatches = timing;
+    }
+}
+
+/// The current module time
+fn module_time() -> TypedResult<ModuleTime> {
+    let start = SYSTEM_START_TIME
+        .get()
+        .context("SystemTime was not set")
+        .typ(SystemError::Panic)?
+        .read()?;
+    Ok(ModuleTime::now(start))
+}
+
+/// Describes a port by its kind and name
+pub(crate) fn describe_port(port: &PortDecl) -> String {
+    match port {
+        PortDecl::Sampling { name, .. } => format!("sampling port {name:?}"),
+        PortDecl::Queuing { name, .. } => format!("queuing port {name:?}"),
+    }
+}
+
+/// Describes the configured ports whose channel is not in the created ones
+fn uncreated_ports(
+    sampling: &HashMap<String, SamplingConstant>,
+    queuing: &HashMap<String, QueuingConstant>,
+    created_sampling: &HashSet<String>,
+    created_queuing: &HashSet<String>,
+) -> Vec<String> {
+    let sampling = sampling
+        .iter()
+        .filter(|(c, _)| !created_sampling.contains(*c))
+        .map(|(c, s)| format!("sampling port {:?} of channel {c}", s.name));
+    let queuing = queuing
+        .iter()
+        .filter(|(c, _)| !created_queuing.contains(*c))
+        .map(|(c, q)| format!("queuing port {:?} of channel {c}", q.name));
+    sampling.chain(queuing).sorted().collect()
+}
+
+/// Logs all mismatches between the declared and the configured ports at once.
+/// In `strict` mode, any mismatch fails the initialization of the partition.
+fn verify_port_declarations<'a>(
+    name: &str,
+    strict: bool,
+    decls: &[PortDecl],
+    sampling: impl Iterator<Item = (&'a String, &'a SamplingConstant)> + Clone,
+    queuing: impl Iterator<Item = (&'a String, &'a QueuingConstant)> + Clone,
+) -> TypedResult<()> {
+    let mismatches = decls
+        .iter()
+        .flat_map(|d| d.channel_mismatches(sampling.clone(), queuing.clone()))
+        .collect_vec();
+    if mismatches.is_empty() {
+        debug!(
+            "All {} ports declared by {name} match the configuration",
+            decls.len()
+        );
+        return Ok(());
+    }
+
+    for m in &mismatches {
+        if strict {
+            error!("Partition {name}: {m}");
+        } else {
+            warn!("Partition {name}: {m}");
+        }
+    }
+    if strict {
+        problem!(
+            PartitionInit,
+            "{} mismatches between the ports declared by {name} and the configuration",
+            mismatches.len()
+        );
+    }
+
+    Ok(())
+}
+
+/// Limits the configured `ipc_buffer` of `partition` to `net.core.wmem_max`
+/// and `net.core.rmem_max`, which the kernel would otherwise do silently
+fn ipc_buffer(partition: &str, size: ByteSize) -> TypedResult<usize> {
+    let max = ipc::max_send_buffer()?.min(ipc::max_recv_buffer()?);
+    // The size is limited by the validation of the configuration
+    let size = size.as_u64().min(SocketOptions::MAX_BUFFER) as usize;
+    if size > max {
+        warn!(
+            "ipc_buffer of partition {partition} is clamped from {} to {} by net.core.wmem_max and net.core.rmem_max",
+            ByteSize::b(size as u64),
+            ByteSize::b(max as u64)
+        );
+        return Ok(max);
+    }
+    Ok(size)
+}
+
+/// Lets the processes of the partition `pid` use the Linux priorities their
+/// APEX priorities are mapped to, before it executes its image
+///
+/// Raising `RLIMIT_NICE` lets a process return to a higher priority after
+/// lowering it. Without the privileges for it, processes may still lower
+/// their priority, which is no reason to fail the partition. `RLIMIT_RTPRIO`
+/// is raised to the `realtime` ceiling, if the partition is granted one.
+fn grant_priorities(partition: &str, pid: Pid, realtime: Option<u8>) -> TypedResult<()> {
+    let set_limit = |resource, limit: libc::rlim_t| {
+        let limit = libc::rlimit {
+            rlim_cur: limit,
+            rlim_max: limit,
+        };
+        match unsafe { libc::prlimit(pid.as_raw(), resource, &limit, std::ptr::null_mut()) } {
+            0 => Ok(()),
+            _ => Err(std::io::Error::last_os_error()),
+        }
+    };
+
+    // Processes may lower their nice value down to 20 minus this limit
+    if let Err(e) = set_limit(libc::RLIMIT_NICE, 20) {
+        debug!("processes of partition {partition} can not raise their priority again: {e}");
+    }
+    if let Some(ceiling) = realtime {
+        set_limit(libc::RLIMIT_RTPRIO, ceiling.into())
+            .with_context(|| format!("failed to grant real-time scheduling to {partition}"))
+            .typ(SystemError::PartitionInit)?;
+    }
+    Ok(())
 }
 
 #[derive(Debug)]
@@ -522,12 +1263,28 @@ impl Partition {
     pub(crate) fn new<P: AsRef<Path>>(
         cgroup_root: P,
         config: PartitionConfig,
+        runtime_dir: &RuntimeDir,
         sampling: &HashMap<String, Sampling>,
         queuing: &HashMap<String, Queuing>,
     ) -> TypedResult<Self> {
-        // Todo implement drop for cgroup (in error case)
         let cgroup = CGroup::new_root(cgroup_root, &config.name).typ(SystemError::PartitionInit)?;
 
+        // Remove the cgroup again on failure, so that a later attempt can create it
+        let path = cgroup.get_path();
+        Self::with_cgroup(cgroup, config, runtime_dir, sampling, queuing).inspect_err(|_| {
+            if let Err(rm) = CGroup::import_root(&path).and_then(|cg| cg.rm()) {
+                warn!("failed to remove cgroup {path:?} of failed partition: {rm:?}");
+            }
+        })
+    }
+
+    fn with_cgroup(
+        cgroup: CGroup,
+        config: PartitionConfig,
+        runtime_dir: &RuntimeDir,
+        sampling: &HashMap<String, Sampling>,
+        queuing: &HashMap<String, Queuing>,
+    ) -> TypedResult<Self> {
         let sampling_channel = sampling
             .iter()
             .filter_map(|(n, s)| s.constant(&config.name).map(|s| (n.clone(), s)))
@@ -538,9 +1295,23 @@ impl Partition {
             .filter_map(|(n, q)| q.constant(&config.name).map(|q| (n.clone(), q)))
             .collect();
 
-        let working_dir = tempdir().typ(SystemError::PartitionInit)?;
+        let working_dir = runtime_dir.tempdir(&format!("a653rs-{}-", config.name))?;
+        // Not close-on-exec, as it is inherited by the partition
+        let activity =
+            EventFd::from_flags(EfdFlags::EFD_NONBLOCK).typ(SystemError::PartitionInit)?;
         trace!("CGroup Working directory: {:?}", working_dir.path());
         let bin = config.get_partition_bin()?;
+        let restart_cause = TempFile::create(format!("restart_cause_{}", config.name))?;
+        let conditions = TempFile::create(format!("module_conditions_{}", config.name))?;
+        conditions.write(&ModuleConditions::NONE)?;
+        let ipc_buffer = config
+            .ipc_buffer
+            .map(|size| ipc_buffer(&config.name, size))
+            .transpose()?;
+
+        let fs = config
+            .partition_fs
+            .map(|fs| PartitionFs::new(&config.name, fs));
 
         let base = Base {
             name: config.name,
@@ -555,7 +1326,34 @@ impl Partition {
             sampling_channel,
             sockets: config.sockets,
             queuing_channel,
+            aperiodic_reserve: config.aperiodic_reserve,
+            veth: config
+                .network
+                .and_then(|n| n.veth)
+                .map(|v| (Veth::new(config.id), v)),
+            activity,
+            strict_ports: config.strict_ports,
+            strict_requirements: config.strict_requirements,
+            unmet_requirements: Vec::new(),
+            role: config.role,
+            stdin_pipe: (config.stdin == Stdin::Pipe)
+                .then(StdinPipe::new)
+                .transpose()?,
+            stdin: config.stdin,
+            forward_env: config.forward_env,
+            restart_cause,
+            module_conditions: ModuleConditions::NONE,
+            conditions,
+            created_sampling: Default::default(),
+            created_queuing: Default::default(),
+            observed: Default::default(),
+            telemetry: Telemetry::new(config.max_telemetry),
+            ipc_buffer,
+            realtime: config.realtime,
+            fs,
+            suppress_messages: config.suppress_messages,
         };
+        base.write_restart_cause(None)?;
         // TODO use StartCondition::HmModuleRestart in case of a ModuleRestart!!
         let run =
             Run::new(&base, StartCondition::NormalStart, false).typ(SystemError::PartitionInit)?;
@@ -567,6 +1365,103 @@ impl Partition {
         self.base.name()
     }
 
+    /// The current operating mode of the partition
+    pub(crate) fn mode(&self) -> OperatingMode {
+        self.run.mode()
+    }
+
+    /// Shares the conditions of the module affecting the partition with it
+    pub(crate) fn set_module_conditions(
+        &mut self,
+        conditions: ModuleConditions,
+    ) -> TypedResult<()> {
+        self.base.set_module_conditions(conditions)
+    }
+
+    /// The name of the partition together with its telemetry
+    pub(crate) fn telemetry(&mut self) -> (&str, &mut Telemetry) {
+        (&self.base.name, &mut self.base.telemetry)
+    }
+
+    /// Counts the log records of the partition instead of printing them, or
+    /// prints them again, returning the number of records counted so far
+    pub(crate) fn suppress_messages(&mut self, suppress: bool) -> u64 {
+        self.base.suppress_messages = suppress;
+        self.base.telemetry.suppressed_messages()
+    }
+
+    /// Writes `data` to the stdin pipe of the partition without blocking,
+    /// failing if it does not fit into the pipe as a whole
+    pub(crate) fn write_stdin(&self, data: &[u8]) -> Result<(), String> {
+        let pipe = self
+            .base
+            .stdin_pipe
+            .as_ref()
+            .ok_or_else(|| format!("the stdin of partition {} is no pipe", self.name()))?;
+        match (&pipe.write).write(data) {
+            Ok(written) if written == data.len() => Ok(()),
+            Ok(written) => Err(format!(
+                "only {written} of {} bytes fit into the stdin pipe of {}",
+                data.len(),
+                self.name()
+            )),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                Err(format!("the stdin pipe of {} is full", self.name()))
+            }
+            Err(e) => Err(format!(
+                "could not write to the stdin of {}: {e}",
+                self.name()
+            )),
+        }
+    }
+
+    /// What was observed of the partition since its creation
+    pub(crate) fn observations(&self) -> &Observations {
+        &self.base.observed
+    }
+
+    /// Describes every configured port which the partition did not create yet
+    pub(crate) fn uncreated_ports(&self) -> Vec<String> {
+        uncreated_ports(
+            &self.base.sampling_channel,
+            &self.base.queuing_channel,
+            &self.base.created_sampling,
+            &self.base.created_queuing,
+        )
+    }
+
+    /// Applies a transition requested by the partition, returning the new
+    /// mode if it changed
+    ///
+    /// NORMAL is refused if the partition declared requirements which are not
+    /// met and `strict_requirements` is set.
+    fn transition(
+        &mut self,
+        mode: OperatingMode,
+        sampling_channels: &mut HashMap<String, Sampling>,
+        queuing: &mut HashMap<String, Queuing>,
+    ) -> TypedResult<Option<OperatingMode>> {
+        if mode == OperatingMode::Normal && !self.base.unmet_requirements.is_empty() {
+            problem!(
+                PartitionInit,
+                "Partition {} may not enter NORMAL, as its requirements are not met: {}",
+                self.base.name,
+                self.base.unmet_requirements.join("; ")
+            );
+        }
+        let changed = self
+            .run
+            .handle_transition(&self.base, mode, sampling_channels, queuing)?;
+        if changed == Some(OperatingMode::Normal) && self.base.observed.normal_at.is_none() {
+            self.base.observed.normal_at = Some(module_time()?);
+        }
+        Ok(changed)
+    }
+
+    pub(crate) fn id(&self) -> PartitionId {
+        self.base.id
+    }
+
     fn release_fds(keep: &[RawFd]) -> TypedResult<()> {
         let proc = Process::myself().typ(SystemError::Panic)?;
         for fd in proc
@@ -606,6 +1501,14 @@ impl Partition {
         }
     }
 
+    /// Time of each partition window reserved for the aperiodic process
+    pub fn aperiodic_reserve(&self) -> Duration {
+        self.base
+            .aperiodic_reserve
+            .map(|r| r.of(self.base.duration))
+            .unwrap_or_default()
+    }
+
     pub fn get_base_run(&mut self) -> (&Base, &mut Run) {
         (&self.base, &mut self.run)
     }
@@ -620,8 +1523,15 @@ impl Partition {
     //    Ok(())
     //}
 
-    fn _verify() -> TypedResult<()> {
-        todo!("Verify integrity of Partition")
+    /// Checks the state shared with the partition outside of its channels,
+    /// see [Run::verify_mode_file]
+    pub(crate) fn verify_shared_state(&self) -> TypedResult<()> {
+        self.run.verify_mode_file()
+    }
+
+    /// The files of the partition kept on the host, if it has any
+    pub(crate) fn fs(&self) -> Option<Arc<PartitionFs>> {
+        self.base.fs.clone()
     }
 
     pub(crate) fn freeze(&self) -> TypedResult<()> {
@@ -629,14 +1539,31 @@ impl Partition {
     }
 
     pub(crate) fn rm(self) -> TypedResult<()> {
+        if let Some((veth, _)) = &self.base.veth {
+            if let Err(e) = veth.remove() {
+                warn!("failed to remove veth pair of {}: {e:?}", self.base.name());
+            }
+        }
         self.base.cgroup.rm().typ(SystemError::CGroup)
     }
 
+    /// Swaps all source channels of this partition which are transferred at
+    /// `transfer`, in the order of their names.
+    /// Returns the port activity this caused for each destination partition.
+    ///
+    /// The partition stays frozen throughout, so the messages it wrote before
+    /// are transferred together, see `SchedulablePartition::swap`.
     pub fn run_post_timeframe(
         &mut self,
+        transfer: Transfer,
         sampling_channels: &mut HashMap<String, Sampling>,
         queuing: &mut HashMap<String, Queuing>,
-    ) {
+        tracer: &mut Tracer,
+    ) -> LeveledResult<HashMap<String, PortActivity>> {
+        let mut activity: HashMap<String, PortActivity> = HashMap::new();
+        // Channels which dropped messages corrupted by this partition
+        let mut corrupted: Vec<(&str, Criticality)> = Vec::new();
+
         // TODO remove because a base freeze is not necessary here, as all run_* methods
         // should freeze base themself after execution. Before removal of this, check
         // all run_* methods.
@@ -647,8 +1574,24 @@ impl Partition {
             .sampling_channel
             .iter()
             .filter(|(_, s)| s.dir == PortDirection::Source)
+            .sorted_by_key(|(c, _)| *c)
         {
-            sampling_channels.get_mut(name).unwrap().swap();
+            let channel = sampling_channels.get_mut(name).unwrap();
+            if channel.transfer() != transfer {
+                continue;
+            }
+            let dropped = channel.corrupted();
+            let start = Instant::now();
+            let swapped = channel.swap();
+            tracer.record_swap(name, start, Instant::now());
+            if channel.corrupted() > dropped {
+                corrupted.push((name.as_str(), channel.criticality()));
+            }
+            if swapped {
+                for partition in channel.destination_partitions() {
+                    activity.entry(partition.to_string()).or_default().sampling = true;
+                }
+            }
         }
 
         for (name, _) in self
@@ -656,35 +1599,83 @@ impl Partition {
             .queuing_channel
             .iter()
             .filter(|(_, q)| q.dir == PortDirection::Source)
+            .sorted_by_key(|(c, _)| *c)
         {
-            queuing.get_mut(name).unwrap().swap();
+            let channel = queuing.get_mut(name).unwrap();
+            if channel.transfer() != transfer {
+                continue;
+            }
+            let dropped = channel.corrupted();
+            let start = Instant::now();
+            let swapped = channel.swap();
+            tracer.record_swap(name, start, Instant::now());
+            if channel.corrupted() > dropped {
+                corrupted.push((name.as_str(), channel.criticality()));
+            }
+            if swapped {
+                activity
+                    .entry(channel.destination_partition().to_string())
+                    .or_default()
+                    .queuing = true;
+            }
         }
+
+        match corruption_error(&corrupted) {
+            Some((err, Criticality::Module)) => {
+                return TypedResult::Err(err).lev(ErrorLevel::ModuleRun)
+            }
+            Some((err, Criticality::Partition)) => {
+                self.handle_error(err, sampling_channels, queuing)?
+            }
+            None => {}
+        }
+
+        Ok(activity)
+    }
+
+    pub fn notify_port_activity(&self, activity: PortActivity) {
+        self.base.notify_port_activity(activity)
     }
 
-    /// Executes the periodic process for a maximum duration specified through
-    /// the `timeout` parameter. Returns whether the periodic process exists
-    /// and was run.
-    pub fn run_periodic_process(&mut self, timeout: Timeout) -> TypedResult<bool> {
+    pub fn warn_uncreated_ports(&self, frames: u64) {
+        self.base.warn_uncreated_ports(frames)
+    }
+
+    /// Release of the periodic process in the release window starting now,
+    /// `None` unless the process is delayed beyond the start of the window
+    pub fn periodic_release(&self) -> Option<Deadline> {
+        self.run.periodic_release(MonotonicTime::now())
+    }
+
+    /// Executes the periodic process until the `deadline` at most. Returns
+    /// whether the periodic process exists and was run.
+    pub fn run_periodic_process(
+        &mut self,
+        deadline: Deadline,
+        sampling_channels: &mut HashMap<String, Sampling>,
+        queuing: &mut HashMap<String, Queuing>,
+    ) -> TypedResult<bool> {
         match self.run.unfreeze_periodic() {
             Ok(true) => {}
             other => return other,
         }
 
-        let mut poller = PeriodicPoller::new(&self.run)?;
+        let mut poller = EventPoller::new_periodic(&self.base, &self.run)?;
 
         self.base.unfreeze()?;
 
-        while timeout.has_time_left() {
-            let event = poller.wait_timeout(&mut self.run, timeout)?;
+        while !deadline.has_passed() {
+            let event = poller.wait_timeout(&mut self.run, deadline)?;
             match &event {
-                PeriodicEvent::Timeout => {}
-                PeriodicEvent::Frozen => {
+                PartitionEvent::Timeout => {}
+                PartitionEvent::Populated(populated) => self.log_populated(*populated),
+                PartitionEvent::Frozen => {
                     self.base.freeze()?;
 
                     return Ok(true);
                 }
                 // TODO Error Handling with HM
-                PeriodicEvent::Call(e @ PartitionCall::Error(se)) => {
+                PartitionEvent::Call(e @ PartitionCall::Error(se)) => {
                     e.print_partition_log(self.base.name());
                     match self.base.part_hm().try_action(*se) {
                         Some(RecoveryAction::Module(ModuleRecoveryAction::Ignore)) => {}
@@ -701,12 +1692,31 @@ impl Partition {
                         }
                     };
                 }
-                PeriodicEvent::Call(c @ PartitionCall::Message(_)) => {
-                    c.print_partition_log(self.base.name())
+                PartitionEvent::Call(c @ PartitionCall::Message(_)) => self.base.print_log(c),
+                PartitionEvent::Call(PartitionCall::DeclarePorts(decls)) => {
+                    self.base.verify_port_declarations(decls)?
+                }
+                PartitionEvent::Call(PartitionCall::DeclareRequirements(requirements)) => {
+                    self.base.verify_requirements(requirements)
+                }
+                PartitionEvent::Call(PartitionCall::PortCreated(port)) => {
+                    self.base.port_created(port)
+                }
+                PartitionEvent::Call(PartitionCall::Telemetry { name, value }) => {
+                    self.base.record_telemetry(name, *value)?
+                }
+                PartitionEvent::Call(PartitionCall::DroppedCalls(dropped)) => {
+                    self.base.record_dropped_calls(*dropped)
                 }
-                PeriodicEvent::Call(PartitionCall::Transition(mode)) => {
+                PartitionEvent::Call(PartitionCall::DelayedStart { process, delay }) => {
+                    self.run.delay(*process, *delay)
+                }
+                PartitionEvent::Call(PartitionCall::Transition(mode)) => {
                     // Only exit run_periodic, if we changed our mode
-                    if self.run.handle_transition(&self.base, *mode)?.is_some() {
+                    if self
+                        .transition(*mode, sampling_channels, queuing)?
+                        .is_some()
+                    {
                         return Ok(true);
                     }
                 }
@@ -718,23 +1728,76 @@ impl Partition {
         Ok(true)
     }
 
-    pub fn run_aperiodic_process(&mut self, timeout: Timeout) -> TypedResult<bool> {
-        match self.run.unfreeze_aperiodic() {
-            Ok(true) => {}
-            other => return other,
+    fn log_populated(&self, populated: bool) {
+        if populated {
+            debug!("partition {} has processes again", self.base.name());
+        } else {
+            warn!("all processes of partition {} exited", self.base.name());
+        }
+    }
+
+    /// Freezes the periodic process after it used up its share of the window,
+    /// so that it can not eat into the aperiodic reserve. Raises a
+    /// [SystemError::TimeDurationExceeded] if the periodic process was still
+    /// running.
+    pub fn stop_periodic_overrun(&mut self) -> TypedResult<()> {
+        if !self.run.periodic_running() || self.run.is_periodic_frozen()? {
+            return Ok(());
+        }
+
+        self.run.freeze_periodic()?;
+        warn!(
+            "periodic process of partition {} overran into the aperiodic reserve",
+            self.base.name()
+        );
+
+        let se = SystemError::TimeDurationExceeded;
+        match self.base.part_hm().try_action(se) {
+            Some(RecoveryAction::Module(ModuleRecoveryAction::Ignore)) => Ok(()),
+            Some(_) => Err(TypedError::new(
+                se,
+                anyhow!("Periodic process exceeded its share of the partition window"),
+            )),
+            None => Err(TypedError::new(
+                SystemError::Panic,
+                anyhow!("Could not get recovery action for requested partition error: {se}"),
+            )),
+        }
+    }
+
+    /// Executes the aperiodic process until the `deadline` at most. Returns
+    /// whether the aperiodic process exists and was run.
+    ///
+    /// A delayed aperiodic process is only unfrozen once its release is due,
+    /// which may be within the window.
+    pub fn run_aperiodic_process(
+        &mut self,
+        deadline: Deadline,
+        sampling_channels: &mut HashMap<String, Sampling>,
+        queuing: &mut HashMap<String, Queuing>,
+    ) -> TypedResult<bool> {
+        let mut release = self.run.aperiodic_release();
+        if release.is_none() {
+            match self.run.unfreeze_aperiodic() {
+                Ok(true) => {}
+                other => return other,
+            }
         }
 
         // Did we even need to unfreeze aperiodic?
         self.base.unfreeze()?;
 
-        while timeout.has_time_left() {
-            match &self
-                .run
-                .receiver()
-                .try_recv_timeout(timeout.remaining_time())?
-            {
-                Some(m @ PartitionCall::Message(_)) => m.print_partition_log(self.base.name()),
-                Some(e @ PartitionCall::Error(se)) => {
+        let mut poller = EventPoller::new(&self.base, &self.run)?;
+
+        while !deadline.has_passed() {
+            if release.is_some_and(|release| release.has_passed()) {
+                self.run.unfreeze_aperiodic()?;
+                release = None;
+            }
+            let wait = release.map_or(deadline, |release| release.min(deadline));
+            match &poller.wait_timeout(&mut self.run, wait)? {
+                PartitionEvent::Call(m @ PartitionCall::Message(_)) => self.base.print_log(m),
+                PartitionEvent::Call(e @ PartitionCall::Error(se)) => {
                     e.print_partition_log(self.base.name());
                     match self.base.part_hm().try_action(*se) {
                         Some(RecoveryAction::Module(ModuleRecoveryAction::Ignore)) => {}
@@ -751,17 +1814,41 @@ impl Partition {
                         }
                     };
                 }
-                Some(t @ PartitionCall::Transition(mode)) => {
+                PartitionEvent::Call(PartitionCall::DeclarePorts(decls)) => {
+                    self.base.verify_port_declarations(decls)?
+                }
+                PartitionEvent::Call(PartitionCall::DeclareRequirements(requirements)) => {
+                    self.base.verify_requirements(requirements)
+                }
+                PartitionEvent::Call(PartitionCall::PortCreated(port)) => {
+                    self.base.port_created(port)
+                }
+                PartitionEvent::Call(PartitionCall::Telemetry { name, value }) => {
+                    self.base.record_telemetry(name, *value)?
+                }
+                PartitionEvent::Call(PartitionCall::DroppedCalls(dropped)) => {
+                    self.base.record_dropped_calls(*dropped)
+                }
+                PartitionEvent::Call(PartitionCall::DelayedStart { process, delay }) => {
+                    self.run.delay(*process, *delay)
+                }
+                PartitionEvent::Call(t @ PartitionCall::Transition(mode)) => {
                     // In case of a transition to idle, just sleep. Do not care for the rest
                     t.print_partition_log(self.base.name());
-                    if let Some(OperatingMode::Idle) =
-                        self.run.handle_transition(&self.base, *mode)?
-                    {
-                        sleep(timeout.remaining_time());
-                        return Ok(true);
+                    match self.transition(*mode, sampling_channels, queuing)? {
+                        Some(OperatingMode::Idle) => {
+                            deadline.sleep();
+                            return Ok(true);
+                        }
+                        // The restarted partition calls through a new receiver
+                        Some(OperatingMode::ColdStart | OperatingMode::WarmStart) => {
+                            poller = EventPoller::new(&self.base, &self.run)?
+                        }
+                        _ => {}
                     }
                 }
-                None => {}
+                PartitionEvent::Populated(populated) => self.log_populated(*populated),
+                PartitionEvent::Timeout | PartitionEvent::Frozen => {}
             }
         }
 
@@ -771,17 +1858,21 @@ impl Partition {
     }
 
     /// Currently the same as run_aperiodic
-    pub fn run_start(&mut self, timeout: Timeout, _warm_start: bool) -> TypedResult<()> {
+    pub fn run_start(
+        &mut self,
+        deadline: Deadline,
+        _warm_start: bool,
+        sampling_channels: &mut HashMap<String, Sampling>,
+        queuing: &mut HashMap<String, Queuing>,
+    ) -> TypedResult<()> {
         self.base.unfreeze()?;
 
-        while timeout.has_time_left() {
-            match &self
-                .run
-                .receiver()
-                .try_recv_timeout(timeout.remaining_time())?
-            {
-                Some(m @ PartitionCall::Message(_)) => m.print_partition_log(self.base.name()),
-                Some(e @ PartitionCall::Error(se)) => {
+        let mut poller = EventPoller::new(&self.base, &self.run)?;
+
+        while !deadline.has_passed() {
+            match &poller.wait_timeout(&mut self.run, deadline)? {
+                PartitionEvent::Call(m @ PartitionCall::Message(_)) => self.base.print_log(m),
+                PartitionEvent::Call(e @ PartitionCall::Error(se)) => {
                     e.print_partition_log(self.base.name());
                     match self.base.part_hm().try_action(*se) {
                         Some(RecoveryAction::Module(ModuleRecoveryAction::Ignore)) => {}
@@ -798,17 +1889,41 @@ impl Partition {
                         }
                     };
                 }
-                Some(t @ PartitionCall::Transition(mode)) => {
+                PartitionEvent::Call(PartitionCall::DeclarePorts(decls)) => {
+                    self.base.verify_port_declarations(decls)?
+                }
+                PartitionEvent::Call(PartitionCall::DeclareRequirements(requirements)) => {
+                    self.base.verify_requirements(requirements)
+                }
+                PartitionEvent::Call(PartitionCall::PortCreated(port)) => {
+                    self.base.port_created(port)
+                }
+                PartitionEvent::Call(PartitionCall::Telemetry { name, value }) => {
+                    self.base.record_telemetry(name, *value)?
+                }
+                PartitionEvent::Call(PartitionCall::DroppedCalls(dropped)) => {
+                    self.base.record_dropped_calls(*dropped)
+                }
+                PartitionEvent::Call(PartitionCall::DelayedStart { process, delay }) => {
+                    self.run.delay(*process, *delay)
+                }
+                PartitionEvent::Call(t @ PartitionCall::Transition(mode)) => {
                     // In case of a transition to idle, just sleep. Do not care for the rest
                     t.print_partition_log(self.base.name());
-                    if let Some(OperatingMode::Idle) =
-                        self.run.handle_transition(&self.base, *mode)?
-                    {
-                        sleep(timeout.remaining_time());
-                        return Ok(());
+                    match self.transition(*mode, sampling_channels, queuing)? {
+                        Some(OperatingMode::Idle) => {
+                            deadline.sleep();
+                            return Ok(());
+                        }
+                        // The restarted partition calls through a new receiver
+                        Some(OperatingMode::ColdStart | OperatingMode::WarmStart) => {
+                            poller = EventPoller::new(&self.base, &self.run)?
+                        }
+                        _ => {}
                     }
                 }
-                None => {}
+                PartitionEvent::Populated(populated) => self.log_populated(*populated),
+                PartitionEvent::Timeout | PartitionEvent::Frozen => {}
             }
         }
 
@@ -816,8 +1931,14 @@ impl Partition {
     }
 
     /// Handles an error that occurred during self.run_* methods.
-    pub fn handle_error(&mut self, err: TypedError) -> LeveledResult<()> {
+    pub fn handle_error(
+        &mut self,
+        err: TypedError,
+        sampling_channels: &mut HashMap<String, Sampling>,
+        queuing: &mut HashMap<String, Queuing>,
+    ) -> LeveledResult<()> {
         debug!("Partition \"{}\" received err: {err:?}", self.base.name());
+        self.base.observed.errors.push(err.err());
 
         let now = Instant::now();
 
@@ -825,16 +1946,18 @@ impl Partition {
             None => {
                 warn!("Could not map \"{err:?}\" to action. Using Panic action instead");
                 match self.base.part_hm().panic {
-                    // We do not Handle Module Recovery actions here
+                    // Without an entry for the error, the module tables decide
                     RecoveryAction::Module(_) => {
                         return TypedResult::Err(err).lev(ErrorLevel::Partition)
                     }
                     RecoveryAction::Partition(action) => action,
                 }
             }
-            // We do not Handle Module Recovery actions here
-            Some(RecoveryAction::Module(_)) => {
-                return TypedResult::Err(err).lev(ErrorLevel::Partition)
+            // The module applies exactly the action of the partition table
+            Some(RecoveryAction::Module(action)) => {
+                return TypedResult::Err(err)
+                    .lev(ErrorLevel::Partition)
+                    .map_err(|e| e.with_module_action(action))
             }
             Some(RecoveryAction::Partition(action)) => action,
         };
@@ -851,11 +1974,25 @@ impl Partition {
                 .expect("Idle Transition Failed"),
             a653rs_linux_core::health::PartitionRecoveryAction::ColdStart => self
                 .run
-                .start_transition(&self.base, false, StartCondition::HmPartitionRestart)
+                .start_transition(
+                    &self.base,
+                    false,
+                    StartCondition::HmPartitionRestart,
+                    Some(err.err()),
+                    sampling_channels,
+                    queuing,
+                )
                 .expect("Start(Cold) Transition Failed"),
             a653rs_linux_core::health::PartitionRecoveryAction::WarmStart => self
                 .run
-                .start_transition(&self.base, false, StartCondition::HmPartitionRestart)
+                .start_transition(
+                    &self.base,
+                    false,
+                    StartCondition::HmPartitionRestart,
+                    Some(err.err()),
+                    sampling_channels,
+                    queuing,
+                )
                 .expect("Start(Warm) Transition Failed"),
         }
 
@@ -867,7 +2004,9 @@ impl Partition {
 impl PartitionConfig {
     /// Get the path to a partition binary
     ///
-    /// The [PartitionConfig::image] field must either:
+    /// Images given as cargo packages must have been built by
+    /// [Config::build_cargo_images](super::config::Config::build_cargo_images)
+    /// before. Otherwise, the [PartitionConfig::image] path must either:
     /// - not contain any path separators, in which case `$PATH` is searched for
     ///   a matching executable (like the `which` command in the shell would do)
     /// - be an absolute path, in which case the path is used verbatim after
@@ -877,8 +2016,16 @@ impl PartitionConfig {
     ///   - is executable
     /// - be a relative path starting with `./`, in which case it is resolved
     ///   relative to the hypervisors current workind directory
-    fn get_partition_bin(&self) -> TypedResult<PathBuf> {
+    pub(crate) fn get_partition_bin(&self) -> TypedResult<PathBuf> {
         let PartitionConfig { image, name, .. } = self;
+        let image = match image {
+            Image::Path(path) => path,
+            Image::Cargo { cargo } => problem!(
+                Config,
+                "image of partition {name} is the cargo package {}, which is only built with --allow-cargo-build",
+                cargo.package
+            ),
+        };
 
         // if image is either an absolute path or starts with ./ , it is left as is
         let bin = if image.is_absolute() || image.starts_with(path::Component::CurDir) {
@@ -922,87 +2069,497 @@ impl PartitionConfig {
     }
 }
 
-pub(crate) struct PeriodicPoller {
+/// Aggregates the events of a partition while it is scheduled
+///
+/// These are the calls of the partition, whether any of its processes is alive
+/// and, if watched, the freezing of the periodic process.
+pub(crate) struct EventPoller {
     poll: Poller,
-    events: OwnedFd,
+    periodic: Option<File>,
+    processes: File,
 }
 
-pub enum PeriodicEvent {
+pub enum PartitionEvent {
     Timeout,
+    /// The periodic process is done with its window, see [periodic_done]
     Frozen,
     Call(PartitionCall),
+    /// The partition has processes again (`true`) or all of them exited
+    /// (`false`)
+    Populated(bool),
 }
 
-impl PeriodicPoller {
-    const EVENTS_ID: usize = 1;
+impl EventPoller {
+    const PERIODIC_ID: usize = 1;
     const RECEIVER_ID: usize = 2;
+    const PROCESSES_ID: usize = 3;
+
+    pub fn new(base: &Base, run: &Run) -> TypedResult<EventPoller> {
+        Self::with_files(run.receiver(), base.cgroup_events()?, None)
+    }
 
-    pub fn new(run: &Run) -> TypedResult<PeriodicPoller> {
-        let events = run.periodic_events()?;
+    /// Additionally reports when the periodic process froze itself
+    pub fn new_periodic(base: &Base, run: &Run) -> TypedResult<EventPoller> {
+        Self::with_files(
+            run.receiver(),
+            base.cgroup_events()?,
+            Some(run.periodic_events()?),
+        )
+    }
 
+    fn with_files(
+        receiver: &IpcReceiver<PartitionCall>,
+        processes: File,
+        periodic: Option<File>,
+    ) -> TypedResult<EventPoller> {
         let poll = Poller::new().typ(SystemError::Panic)?;
         unsafe {
-            poll.add(events.as_raw_fd(), Event::readable(Self::EVENTS_ID))
+            // cgroup.events is always readable, changes are signalled as
+            // priority events instead
+            if let Some(periodic) = &periodic {
+                poll.add(
+                    periodic.as_raw_fd(),
+                    Event::none(Self::PERIODIC_ID).with_priority(),
+                )
+                .typ(SystemError::Panic)?;
+            }
+            poll.add(receiver.as_raw_fd(), Event::readable(Self::RECEIVER_ID))
                 .typ(SystemError::Panic)?;
             poll.add(
-                run.receiver().as_raw_fd(),
-                Event::readable(Self::RECEIVER_ID),
+                processes.as_raw_fd(),
+                Event::none(Self::PROCESSES_ID).with_priority(),
             )
             .typ(SystemError::Panic)?;
         }
 
-        Ok(PeriodicPoller { poll, events })
+        Ok(EventPoller {
+            poll,
+            periodic,
+            processes,
+        })
+    }
+
+    pub fn wait_timeout(
+        &mut self,
+        run: &mut Run,
+        deadline: Deadline,
+    ) -> TypedResult<PartitionEvent> {
+        let cgroup_periodic = &run.cgroup_periodic;
+        self.wait(
+            &run.call_rx,
+            &mut run.populated,
+            || periodic_done(cgroup_periodic),
+            deadline,
+        )
     }
 
-    pub fn wait_timeout(&mut self, run: &mut Run, timeout: Timeout) -> TypedResult<PeriodicEvent> {
-        if run.is_periodic_frozen()? {
-            return Ok(PeriodicEvent::Frozen);
+    /// Waits for the next event
+    ///
+    /// `populated` is the state of the processes reported last. Only changes
+    /// of it are reported, so that many processes exiting at once, or exiting
+    /// while nobody waited, result in a single event.
+    fn wait(
+        &mut self,
+        receiver: &IpcReceiver<PartitionCall>,
+        populated: &mut bool,
+        periodic_frozen: impl Fn() -> TypedResult<bool>,
+        deadline: Deadline,
+    ) -> TypedResult<PartitionEvent> {
+        if self.periodic.is_some() && periodic_frozen()? {
+            return Self::frozen(receiver);
+        }
+        if let Some(event) = self.populated_changed(populated)? {
+            return Ok(event);
         }
 
-        while timeout.has_time_left() {
+        while !deadline.has_passed() {
             let mut events = Events::new();
             self.poll
-                .wait(&mut events, Some(timeout.remaining_time()))
+                .wait(&mut events, deadline.poll_timeout())
                 .typ(SystemError::Panic)?;
 
+            // Re-sub all events first, so that none is missed when returning
+            // early. This will result in the event instantly being ready again
+            // should we have something to read, but that is better than
+            // accidentally missing an event (at the expense of one extra loop
+            // per receive)
             for e in events.iter() {
                 match e.key {
-                    // Got a Frozen event
-                    Self::EVENTS_ID => {
-                        // Re-sub the readable event
-                        self.poll
-                            .modify(&mut self.events, Event::readable(Self::EVENTS_ID))
-                            .typ(SystemError::Panic)?;
-
-                        // Then check if the cg is actually frozen
-                        if run.is_periodic_frozen()? {
-                            return Ok(PeriodicEvent::Frozen);
-                        }
-                    }
-                    // got a call events
-                    Self::RECEIVER_ID => {
-                        // Re-sub the readable event
-                        // This will result in the event instantly being ready again should we have
-                        // something to read, but that is better than
-                        // accidentally missing an event (at the expense of one extra loop per
-                        // receive)
-                        self.poll
-                            .modify(run.receiver(), Event::readable(Self::RECEIVER_ID))
-                            .typ(SystemError::Panic)?;
-
-                        // Now receive anything
-                        if let Some(call) = run.receiver().try_recv()? {
-                            return Ok(PeriodicEvent::Call(call));
+                    Self::PERIODIC_ID => {
+                        if let Some(periodic) = &self.periodic {
+                            self.poll
+                                .modify(periodic, Event::none(Self::PERIODIC_ID).with_priority())
+                                .typ(SystemError::Panic)?;
                         }
                     }
+                    Self::RECEIVER_ID => self
+                        .poll
+                        .modify(receiver, Event::readable(Self::RECEIVER_ID))
+                        .typ(SystemError::Panic)?,
+                    Self::PROCESSES_ID => self
+                        .poll
+                        .modify(
+                            &self.processes,
+                            Event::none(Self::PROCESSES_ID).with_priority(),
+                        )
+                        .typ(SystemError::Panic)?,
                     _ => {
                         return Err(anyhow!("Unexpected Event Received: {e:?}"))
                             .typ(SystemError::Panic)
                     }
                 }
             }
+
+            for e in events.iter() {
+                match e.key {
+                    // Got a Frozen event, check if the cg is actually frozen
+                    Self::PERIODIC_ID => {
+                        if let Some(periodic) = &self.periodic {
+                            acknowledge_events(periodic)?;
+                        }
+                        if periodic_frozen()? {
+                            return Self::frozen(receiver);
+                        }
+                    }
+                    // Now receive anything
+                    Self::RECEIVER_ID => {
+                        if let Some(call) = receiver.try_recv()? {
+                            return Ok(PartitionEvent::Call(call));
+                        }
+                    }
+                    Self::PROCESSES_ID => {
+                        if let Some(event) = self.populated_changed(populated)? {
+                            return Ok(event);
+                        }
+                    }
+                    _ => {}
+                }
+            }
         }
 
-        Ok(PeriodicEvent::Timeout)
+        Ok(PartitionEvent::Timeout)
+    }
+
+    /// The event once the periodic process is frozen
+    ///
+    /// The calls it made before are still received first, as they belong to
+    /// its phase. Otherwise the calls of a partition without an aperiodic
+    /// process would only be received in its next window.
+    fn frozen(receiver: &IpcReceiver<PartitionCall>) -> TypedResult<PartitionEvent> {
+        Ok(match receiver.try_recv()? {
+            Some(call) => PartitionEvent::Call(call),
+            None => PartitionEvent::Frozen,
+        })
+    }
+
+    /// Reads whether the partition has processes, returning an event if this
+    /// differs from `populated`
+    ///
+    /// Reading through the polled file descriptor acknowledges the change, a
+    /// newly opened file would not.
+    fn populated_changed(&self, populated: &mut bool) -> TypedResult<Option<PartitionEvent>> {
+        let events = acknowledge_events(&self.processes)?;
+        let now = events
+            .split(|b| *b == b'\n')
+            .any(|line| line == b"populated 1");
+
+        if now == *populated {
+            return Ok(None);
+        }
+        *populated = now;
+        Ok(Some(PartitionEvent::Populated(now)))
+    }
+}
+
+/// Reads a `cgroup.events` file through the polled file descriptor
+///
+/// Until then, the kernel keeps signalling its last change, so that polling it
+/// would never block again.
+fn acknowledge_events(events: &File) -> TypedResult<Vec<u8>> {
+    let mut buf = [0; 64];
+    let len = events.read_at(&mut buf, 0).typ(SystemError::CGroup)?;
+    Ok(buf[..len].to_vec())
+}
+
+/// The error for the malformed messages dropped from the `corrupted`
+/// channels, along with the level handling it
+///
+/// Corrupting any module-critical channel is an error of the module, naming
+/// only those channels.
+fn corruption_error(corrupted: &[(&str, Criticality)]) -> Option<(TypedError, Criticality)> {
+    if corrupted.is_empty() {
+        return None;
+    }
+    let critical = corrupted
+        .iter()
+        .filter(|(_, criticality)| *criticality == Criticality::Module)
+        .map(|(name, _)| *name)
+        .collect_vec();
+    let (kind, names, criticality) = if critical.is_empty() {
+        let names = corrupted.iter().map(|(name, _)| *name).collect_vec();
+        ("", names, Criticality::Partition)
+    } else {
+        ("module-critical ", critical, Criticality::Module)
+    };
+    let err = TypedError::new(
+        SystemError::Segmentation,
+        anyhow!(
+            "Dropped malformed messages written to the {kind}channels {}",
+            names.join(", ")
+        ),
+    );
+    Some((err, criticality))
+}
+
+#[cfg(test)]
+mod tests {
+    use a653rs_linux_core::channel::SamplingChannelConfig;
+    use a653rs_linux_core::health::{
+        module_action, ModuleInitHMTable, ModuleRunHMTable, PartitionRecoveryAction,
+    };
+    use a653rs_linux_core::sampling::SamplingSource;
+
+    use super::*;
+
+    /// A sampling channel of `criticality`, to which its source wrote a
+    /// message exceeding the message size
+    fn corrupted_channel(port: &str, criticality: &str) -> Sampling {
+        let yaml = format!(
+            "msg_size: 5B\n\
+            source: {{ partition: ctrl, port: {port} }}\n\
+            destination: [ {{ partition: act, port: {port} }} ]\n\
+            criticality: {criticality}\n"
+        );
+        let config: SamplingChannelConfig = serde_yaml::from_str(&yaml).unwrap();
+        let mut channel = Sampling::try_from(config).unwrap();
+        let mut source = SamplingSource::try_from(channel.source_fd().as_raw_fd()).unwrap();
+        assert!(source.write(b"too long!") > 5);
+        assert!(!channel.swap());
+        assert_eq!(channel.corrupted(), 1);
+        channel
+    }
+
+    #[test]
+    fn corrupted_critical_channels_reach_the_module() {
+        let status = corrupted_channel("Status", "partition");
+        let command = corrupted_channel("Command", "module");
+        assert_eq!(command.criticality(), Criticality::Module);
+        let init = ModuleInitHMTable::default();
+        let run = ModuleRunHMTable::default();
+
+        // The partition is restarted by its table
+        let (err, criticality) =
+            corruption_error(&[("ctrl:Status", status.criticality())]).unwrap();
+        assert_eq!(criticality, Criticality::Partition);
+        assert_eq!(
+            PartitionHMTable::default().try_action(err.err()),
+            Some(RecoveryAction::Partition(
+                PartitionRecoveryAction::WarmStart
+            ))
+        );
+
+        // The module is shut down by the run table, whatever the partition table says
+        let corrupted = [
+            ("ctrl:Status", status.criticality()),
+            ("ctrl:Command", command.criticality()),
+        ];
+        let (err, criticality) = corruption_error(&corrupted).unwrap();
+        assert_eq!(criticality, Criticality::Module);
+        assert!(
+            err.to_string()
+                .contains("module-critical channels ctrl:Command"),
+            "{err}"
+        );
+        assert!(!err.to_string().contains("ctrl:Status"), "{err}");
+        let err = TypedResult::<()>::Err(err)
+            .lev(ErrorLevel::ModuleRun)
+            .unwrap_err();
+        assert!(matches!(err.level(), ErrorLevel::ModuleRun));
+        assert_eq!(
+            module_action(&err, &init, &run),
+            ModuleRecoveryAction::Shutdown
+        );
+
+        assert!(corruption_error(&[]).is_none());
+    }
+
+    #[test]
+    fn uncreated_ports_are_reported() {
+        let sampling = HashMap::from([
+            (
+                "a:temperature".to_string(),
+                SamplingConstant {
+                    name: "temperature".into(),
+                    dir: PortDirection::Source,
+                    msg_size: 16,
+                    fd: -1,
+                    refresh_period: None,
+                },
+            ),
+            (
+                "b:status".to_string(),
+                SamplingConstant {
+                    name: "status".into(),
+                    dir: PortDirection::Destination,
+                    msg_size: 8,
+                    fd: -1,
+                    refresh_period: None,
+                },
+            ),
+        ]);
+        let queuing = HashMap::from([(
+            "a:commands".to_string(),
+            QueuingConstant {
+                name: "commands".into(),
+                dir: PortDirection::Source,
+                msg_size: 32,
+                max_num_msg: 4,
+                fd: -1,
+            },
+        )]);
+        let mut created_sampling = HashSet::new();
+        let mut created_queuing = HashSet::new();
+
+        assert_eq!(
+            uncreated_ports(&sampling, &queuing, &created_sampling, &created_queuing),
+            [
+                "queuing port \"commands\" of channel a:commands",
+                "sampling port \"status\" of channel b:status",
+                "sampling port \"temperature\" of channel a:temperature",
+            ]
+        );
+
+        created_sampling.insert("a:temperature".to_string());
+        created_queuing.insert("a:commands".to_string());
+        assert_eq!(
+            uncreated_ports(&sampling, &queuing, &created_sampling, &created_queuing),
+            ["sampling port \"status\" of channel b:status"]
+        );
+
+        created_sampling.insert("b:status".to_string());
+        assert!(
+            uncreated_ports(&sampling, &queuing, &created_sampling, &created_queuing).is_empty()
+        );
+    }
+
+    #[test]
+    fn strict_port_declarations() {
+        let sampling = HashMap::from([(
+            "temperature".to_string(),
+            SamplingConstant {
+                name: "temperature".into(),
+                dir: PortDirection::Source,
+                msg_size: 16,
+                fd: -1,
+                refresh_period: None,
+            },
+        )]);
+        let queuing = HashMap::<String, QueuingConstant>::new();
+        let matching = [PortDecl::Sampling {
+            name: "temperature".into(),
+            dir: PortDirection::Source,
+            msg_size: 16,
+        }];
+        let mismatching = [PortDecl::Sampling {
+            name: "temperature".into(),
+            dir: PortDirection::Source,
+            msg_size: 32,
+        }];
+
+        for strict in [false, true] {
+            verify_port_declarations("p", strict, &matching, sampling.iter(), queuing.iter())
+                .unwrap();
+        }
+        verify_port_declarations("p", false, &mismatching, sampling.iter(), queuing.iter())
+            .unwrap();
+        let err =
+            verify_port_declarations("p", true, &mismatching, sampling.iter(), queuing.iter())
+                .unwrap_err();
+        assert!(matches!(err.err(), SystemError::PartitionInit));
+    }
+
+    #[test]
+    fn environment_allowlist() {
+        let host = HashMap::from([
+            ("RUST_LOG", "debug"),
+            ("RUST_BACKTRACE", "1"),
+            ("HOME", "/root"),
+            ("MY_VAR", "42"),
+        ]);
+        let lookup = |var: &str| host.get(var).map(OsString::from);
+
+        let env = forwarded_env(&[], lookup);
+        assert_eq!(
+            env,
+            [
+                ("RUST_LOG".to_string(), OsString::from("debug")),
+                ("RUST_BACKTRACE".to_string(), OsString::from("1")),
+            ]
+        );
+
+        // Unset variables are skipped, and listing a default one again has no effect
+        let extra = ["MY_VAR", "UNSET", "RUST_LOG"].map(String::from);
+        let env = forwarded_env(&extra, lookup);
+        let vars = env.iter().map(|(var, _)| var.as_str()).collect_vec();
+        assert_eq!(vars, ["RUST_LOG", "RUST_BACKTRACE", "MY_VAR"]);
+    }
+
+    // Like the tests of the cgroup module, this needs a delegated cgroup
+    #[test]
+    fn populated_changes_are_reported_once() {
+        let root = cgroup::mount_point()
+            .unwrap()
+            .join(cgroup::current_cgroup().unwrap());
+        let cg =
+            CGroup::new_root(root, &format!("apex-test-events-{}", std::process::id())).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let receiver = bind_receiver::<PartitionCall>(&dir.path().join("calls")).unwrap();
+        let events = File::open(cg.get_events_path()).unwrap();
+        let mut poller = EventPoller::with_files(&receiver, events, None).unwrap();
+
+        let mut populated = false;
+        let mut wait = || {
+            let deadline = Deadline::after(Duration::from_millis(100));
+            poller
+                .wait(&receiver, &mut populated, || Ok(false), deadline)
+                .unwrap()
+        };
+        assert!(matches!(wait(), PartitionEvent::Timeout));
+
+        let mut procs = (0..3)
+            .map(|_| Command::new("sleep").arg("120").spawn().unwrap())
+            .collect_vec();
+        for proc in &procs {
+            cg.mv_proc(Pid::from_raw(proc.id() as i32)).unwrap();
+        }
+        assert!(matches!(wait(), PartitionEvent::Populated(true)));
+        assert!(matches!(wait(), PartitionEvent::Timeout));
+
+        // All processes exiting at once are reported as a single event
+        cg.kill().unwrap();
+        for proc in &mut procs {
+            proc.wait().unwrap();
+        }
+        assert!(matches!(wait(), PartitionEvent::Populated(false)));
+        assert!(matches!(wait(), PartitionEvent::Timeout));
+
+        cg.rm().unwrap();
+    }
+
+    #[test]
+    fn delayed_release_is_resolved_once() {
+        let time = |millis| MonotonicTime::from(Duration::from_millis(millis));
+
+        // The delay counts from the first release point
+        let release = Release::After(Duration::from_millis(50)).resolve(time(100));
+        assert_eq!(release, Some(Release::At(time(150))));
+        assert_eq!(release.unwrap().deadline(), Some(Deadline::at(time(150))));
+
+        // Later release points do not move it, until it is due
+        assert_eq!(release.unwrap().resolve(time(120)), release);
+        assert_eq!(release.unwrap().resolve(time(150)), None);
+
+        assert_eq!(Release::After(Duration::ZERO).resolve(time(100)), None);
+        assert_eq!(Release::After(Duration::from_millis(1)).deadline(), None);
     }
 }
@@ -1,4 +1,4 @@
-use std::fs;
+use std::fs::{self, OpenOptions};
 use std::path::{Path, PathBuf};
 
 use anyhow::{bail, Context};
@@ -20,37 +20,65 @@ pub struct FileMounter {
 impl FileMounter {
     // Mount (and consume) a device
     pub fn mount(self, base_dir: &Path) -> anyhow::Result<()> {
-        let relative_target = self.target.strip_prefix("/").unwrap_or(&self.target);
-        let target: &PathBuf = &base_dir.join(relative_target);
-        let fstype = self.fstype.map(PathBuf::from);
-        let data = self.data.map(PathBuf::from);
-
         if let Some(src) = &self.source {
             Self::exists(src)?;
         }
 
+        let target = self.prepare_target(base_dir)?;
+        mount(
+            self.source.as_deref(),
+            &target,
+            self.fstype.as_deref(),
+            self.flags,
+            self.data.as_deref(),
+        )
+        .with_context(|| {
+            format!(
+                "failed to mount {} on {} (fstype: {}, flags: {:?})",
+                self.source
+                    .as_ref()
+                    .map_or("none".into(), |s| s.display().to_string()),
+                target.display(),
+                self.fstype.as_deref().unwrap_or("none"),
+                self.flags
+            )
+        })
+    }
+
+    /// Creates the target below `base_dir` and its missing parent
+    /// directories, returning its path
+    ///
+    /// Directories are mounted on a directory, files on an empty file. An
+    /// existing target is kept as it is.
+    fn prepare_target(&self, base_dir: &Path) -> anyhow::Result<PathBuf> {
+        let relative_target = self.target.strip_prefix("/").unwrap_or(&self.target);
+        let target = base_dir.join(relative_target);
+
         if self.is_dir {
             trace!("Creating directory {}", target.display());
-            fs::create_dir_all(target).context("failed to create target directory")?;
+            fs::create_dir_all(&target).with_context(|| {
+                format!("failed to create target directory {}", target.display())
+            })?;
         } else {
             let parent = target.parent()
                 .expect("target to have at least one direct parent directory because it was based on the `base_dir` path");
             trace!("Creating directory {}", parent.display());
-            fs::create_dir_all(parent)
-                .context("failed to create parent directory of target file")?;
+            fs::create_dir_all(parent).with_context(|| {
+                format!(
+                    "failed to create parent directory of target file {}",
+                    target.display()
+                )
+            })?;
 
             trace!("Creating file {}", target.display());
-            fs::File::create(target).context("failed to create target file")?;
+            OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&target)
+                .with_context(|| format!("failed to create target file {}", target.display()))?;
         }
-
-        mount::<PathBuf, PathBuf, PathBuf, PathBuf>(
-            self.source.as_ref(),
-            target,
-            fstype.as_ref(),
-            self.flags,
-            data.as_ref(),
-        )
-        .context("failed to make `nix::mount()` call")
+        Ok(target)
     }
 
     fn exists<T: AsRef<Path>>(path: T) -> anyhow::Result<()> {
@@ -122,3 +150,90 @@ impl FileMounter {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_mounter(target: &str, is_dir: bool) -> FileMounter {
+        FileMounter {
+            source: None,
+            target: target.into(),
+            fstype: None,
+            flags: MsFlags::empty(),
+            data: None,
+            is_dir,
+        }
+    }
+
+    #[test]
+    fn nested_targets_are_created() {
+        let base = tempfile::tempdir().unwrap();
+
+        let file = file_mounter("/data/config/x.yaml", false)
+            .prepare_target(base.path())
+            .unwrap();
+        assert_eq!(file, base.path().join("data/config/x.yaml"));
+        assert!(file.is_file());
+
+        let dir = file_mounter("/data/logs/today", true)
+            .prepare_target(base.path())
+            .unwrap();
+        assert_eq!(dir, base.path().join("data/logs/today"));
+        assert!(dir.is_dir());
+
+        // Relative targets are below the base directory as well
+        let relative = file_mounter("dev/null", false)
+            .prepare_target(base.path())
+            .unwrap();
+        assert_eq!(relative, base.path().join("dev/null"));
+    }
+
+    #[test]
+    fn existing_targets_are_kept() {
+        let base = tempfile::tempdir().unwrap();
+        let existing = base.path().join("etc/hosts");
+        fs::create_dir_all(existing.parent().unwrap()).unwrap();
+        fs::write(&existing, "127.0.0.1 localhost").unwrap();
+
+        file_mounter("/etc/hosts", false)
+            .prepare_target(base.path())
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(&existing).unwrap(),
+            "127.0.0.1 localhost"
+        );
+        file_mounter("/etc", true)
+            .prepare_target(base.path())
+            .unwrap();
+    }
+
+    #[test]
+    fn file_in_place_of_a_parent_directory() {
+        let base = tempfile::tempdir().unwrap();
+        fs::write(base.path().join("data"), "").unwrap();
+
+        let err = file_mounter("/data/config/x.yaml", false)
+            .prepare_target(base.path())
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("data/config/x.yaml"), "{err:#}");
+    }
+
+    #[test]
+    fn mount_errors_name_the_mount() {
+        let base = tempfile::tempdir().unwrap();
+        let mounter = FileMounter {
+            fstype: Some("a653rs_no_such_fs".into()),
+            flags: MsFlags::MS_NOSUID,
+            ..file_mounter("/data/nested", true)
+        };
+
+        let err = format!("{:#}", mounter.mount(base.path()).unwrap_err());
+        assert!(err.contains("failed to mount none on"), "{err}");
+        assert!(err.contains("data/nested"), "{err}");
+        assert!(err.contains("fstype: a653rs_no_such_fs"), "{err}");
+        assert!(err.contains("MS_NOSUID"), "{err}");
+        // The target was still prepared
+        assert!(base.path().join("data/nested").is_dir());
+    }
+}
@@ -0,0 +1,744 @@
+//! Files of a partition kept in a directory of the host
+//!
+//! A partition with a `partition_fs` gets a tmpfs at its `target`, which is
+//! filled from `host_dir` on every start of the partition:
+//!
+//! ```yaml
+//! partition_fs: { host_dir: /var/lib/fuel_tank, sync_interval: 5s }
+//! ```
+//!
+//! A thread of the hypervisor copies the files the partition changed back to
+//! `host_dir` every `sync_interval`, so that a crash of the hypervisor only
+//! loses the changes of the last interval. Files count as changed if their
+//! size or modification time differs from the copy on the host. The files are
+//! synced once more before the partition is restarted and when the hypervisor
+//! exits.
+//!
+//! The thread only copies while the hypervisor sleeps between the steps of the
+//! schedule, see `idle`, when all partitions are frozen. It thus neither
+//! competes with a partition for the CPU nor sees files a partition is in the
+//! middle of writing. Files are copied in chunks, of which at most one runs
+//! into the next step, and at no more than [MAX_RATE]. A schedule without
+//! gaps between its windows leaves no time for the thread, so that the files
+//! are only synced on restarts and at the exit.
+//!
+//! # Conflicts
+//!
+//! Files are only ever copied from the partition to the host while it runs,
+//! and the last writer wins:
+//!
+//! - A file the partition changed replaces the one on the host, unless the one
+//!   on the host was modified later.
+//! - A file the partition deleted is deleted on the host as well, unless it was
+//!   modified on the host since the last sync.
+//! - A file the partition replaced with a directory, or the other way round, is
+//!   replaced on the host as well.
+//! - Files added on the host while the partition runs are kept. The partition
+//!   only sees them after its next start.
+//!
+//! Symbolic links and other special files of the partition are not synced.
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+use a653rs_linux_core::error::{ResultExt, SystemError, TypedResult};
+use anyhow::anyhow;
+use bytesize::ByteSize;
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+
+/// Bytes copied at once, before checking whether the hypervisor still sleeps
+const CHUNK: usize = 64 * 1024;
+
+/// Bytes copied per second at most, so that syncing large files does not
+/// keep the disk busy
+pub const MAX_RATE: u64 = 32 * 1024 * 1024;
+
+/// Time after which the sync thread checks whether it is to stop
+const STOP_POLL: Duration = Duration::from_millis(50);
+
+/// Lets the sync thread run while the hypervisor sleeps
+static GATE: Gate = Gate::new();
+
+/// Directory of the host holding the files of a partition
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct PartitionFsConfig {
+    /// Existing directory on the host
+    pub host_dir: PathBuf,
+
+    /// Absolute path of the files in the partition, `/data` by default
+    #[serde(default = "PartitionFsConfig::default_target")]
+    pub target: PathBuf,
+
+    /// Size of the tmpfs holding the files in the partition, `16MB` by
+    /// default
+    #[serde(default = "PartitionFsConfig::default_size")]
+    pub size: ByteSize,
+
+    /// Time between two syncs to the host, `5s` by default
+    #[serde(
+        with = "humantime_serde",
+        default = "PartitionFsConfig::default_sync_interval"
+    )]
+    pub sync_interval: Duration,
+}
+
+impl PartitionFsConfig {
+    fn default_target() -> PathBuf {
+        "/data".into()
+    }
+
+    fn default_size() -> ByteSize {
+        ByteSize::mb(16)
+    }
+
+    fn default_sync_interval() -> Duration {
+        Duration::from_secs(5)
+    }
+
+    /// Checks the files of the partition `partition`
+    pub fn validate(&self, partition: &str) -> TypedResult<()> {
+        let mut invalid = Vec::new();
+        if !self.host_dir.is_absolute() || !self.host_dir.is_dir() {
+            invalid.push(format!(
+                "host_dir {:?} is not an absolute path of an existing directory",
+                self.host_dir
+            ));
+        }
+        let mut components = self.target.components();
+        if components.next() != Some(Component::RootDir)
+            || !components.all(|c| matches!(c, Component::Normal(_)))
+            || self.target.parent().is_none()
+        {
+            invalid.push(format!(
+                "target {:?} is not an absolute path below the root",
+                self.target
+            ));
+        }
+        if self.size.as_u64() == 0 {
+            invalid.push("size must not be zero".into());
+        }
+        if self.sync_interval.is_zero() {
+            invalid.push("sync_interval must not be zero".into());
+        }
+        if !invalid.is_empty() {
+            return Err(anyhow!(
+                "invalid partition_fs of partition {partition:?}:\n{}",
+                invalid.join("\n")
+            ))
+            .typ(SystemError::Config);
+        }
+        Ok(())
+    }
+
+    /// The target, relative to the root of the partition
+    pub(crate) fn relative_target(&self) -> &Path {
+        self.target.strip_prefix("/").unwrap_or(&self.target)
+    }
+}
+
+/// Runs `f`, e.g. sleeping until the next step of the schedule, while the
+/// sync thread may copy files
+pub(crate) fn idle<T>(f: impl FnOnce() -> T) -> T {
+    GATE.open();
+    let res = f();
+    GATE.close();
+    res
+}
+
+/// Open while at least one caller holds it open
+#[derive(Debug)]
+struct Gate {
+    open: Mutex<u32>,
+    opened: Condvar,
+}
+
+impl Gate {
+    const fn new() -> Self {
+        Self {
+            open: Mutex::new(0),
+            opened: Condvar::new(),
+        }
+    }
+
+    fn open(&self) {
+        *self.open.lock().unwrap() += 1;
+        self.opened.notify_all();
+    }
+
+    fn close(&self) {
+        let mut open = self.open.lock().unwrap();
+        *open = open.saturating_sub(1);
+    }
+
+    /// Waits until the gate is open, returning false if `stop` was set
+    /// before
+    fn wait(&self, stop: &AtomicBool) -> bool {
+        let mut open = self.open.lock().unwrap();
+        while *open == 0 {
+            if stop.load(Ordering::SeqCst) {
+                return false;
+            }
+            open = self.opened.wait_timeout(open, STOP_POLL).unwrap().0;
+        }
+        !stop.load(Ordering::SeqCst)
+    }
+}
+
+/// What a sync did
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SyncStats {
+    pub copied: u64,
+    pub bytes: u64,
+    pub removed: u64,
+}
+
+/// The files of a running partition
+#[derive(Debug)]
+struct Tree {
+    /// The target of the partition, as seen by the hypervisor
+    source: Option<PathBuf>,
+    /// Time at which the last sync started
+    last_sync: SystemTime,
+}
+
+/// The files of a partition, shared by the partition and the sync thread
+#[derive(Debug)]
+pub(crate) struct PartitionFs {
+    partition: String,
+    config: PartitionFsConfig,
+    tree: Mutex<Tree>,
+}
+
+impl PartitionFs {
+    pub(crate) fn new(partition: &str, config: PartitionFsConfig) -> Arc<Self> {
+        Arc::new(Self {
+            partition: partition.to_string(),
+            config,
+            tree: Mutex::new(Tree {
+                source: None,
+                last_sync: SystemTime::now(),
+            }),
+        })
+    }
+
+    pub(crate) fn config(&self) -> &PartitionFsConfig {
+        &self.config
+    }
+
+    /// Records the main process `pid` of the partition, which was just
+    /// started with the files of the host
+    pub(crate) fn started(&self, pid: Pid) {
+        let mut tree = self.tree.lock().unwrap();
+        tree.source =
+            Some(PathBuf::from(format!("/proc/{pid}/root")).join(self.config.relative_target()));
+        tree.last_sync = SystemTime::now();
+    }
+
+    /// Syncs the files right away, e.g. before the partition is killed
+    pub(crate) fn sync_now(&self) {
+        // Lets the sync thread finish the sync it may be in the middle of
+        let res = idle(|| self.sync(&mut || true));
+        self.log(res);
+    }
+
+    /// Copies the changed files of the partition to the host, calling `pause`
+    /// before every chunk
+    ///
+    /// Nothing is synced while the partition is not running.
+    fn sync(&self, pause: &mut dyn FnMut() -> bool) -> io::Result<Option<SyncStats>> {
+        let mut tree = self.tree.lock().unwrap();
+        let Some(source) = &tree.source else {
+            return Ok(None);
+        };
+        if !source.is_dir() {
+            return Ok(None);
+        }
+        let start = SystemTime::now();
+        let mut stats = SyncStats::default();
+        sync_dir(
+            source,
+            &self.config.host_dir,
+            tree.last_sync,
+            pause,
+            &mut stats,
+        )?;
+        tree.last_sync = start;
+        Ok(Some(stats))
+    }
+
+    fn log(&self, res: io::Result<Option<SyncStats>>) {
+        match res {
+            Ok(Some(stats)) if stats != SyncStats::default() => debug!(
+                "Synced the files of {} to {}: {} copied ({}), {} removed",
+                self.partition,
+                self.config.host_dir.display(),
+                stats.copied,
+                ByteSize::b(stats.bytes),
+                stats.removed
+            ),
+            Ok(_) => {}
+            Err(e) => warn!(
+                "Could not sync the files of {} to {}: {e}",
+                self.partition,
+                self.config.host_dir.display()
+            ),
+        }
+    }
+}
+
+/// The thread syncing the files of the partitions periodically
+#[derive(Debug)]
+pub(crate) struct Syncer {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Syncer {
+    /// Starts syncing `filesystems`, if there are any
+    pub(crate) fn start(filesystems: Vec<Arc<PartitionFs>>) -> TypedResult<Option<Self>> {
+        if filesystems.is_empty() {
+            return Ok(None);
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = thread::Builder::new()
+            .name("partition-fs".to_string())
+            .spawn(move || run(&filesystems, &thread_stop))
+            .typ(SystemError::Panic)?;
+        Ok(Some(Self {
+            stop,
+            thread: Some(thread),
+        }))
+    }
+}
+
+impl Drop for Syncer {
+    fn drop(&mut self) {
+        // Abandons the current sync, the partitions are synced once more
+        // afterwards
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("The partition-fs thread panicked");
+            }
+        }
+    }
+}
+
+fn run(filesystems: &[Arc<PartitionFs>], stop: &AtomicBool) {
+    let now = Instant::now();
+    let mut due = filesystems
+        .iter()
+        .map(|fs| now + fs.config.sync_interval)
+        .collect::<Vec<_>>();
+    let mut throttle = Throttle::new(MAX_RATE);
+    loop {
+        let Some((i, next)) = due.iter().copied().enumerate().min_by_key(|(_, due)| *due) else {
+            return;
+        };
+        while Instant::now() < next {
+            if stop.load(Ordering::SeqCst) {
+                return;
+            }
+            thread::sleep(
+                next.saturating_duration_since(Instant::now())
+                    .min(STOP_POLL),
+            );
+        }
+
+        let fs = &filesystems[i];
+        let res = fs.sync(&mut || {
+            throttle.take(CHUNK as u64);
+            GATE.wait(stop)
+        });
+        if stop.load(Ordering::SeqCst) {
+            return;
+        }
+        fs.log(res);
+        due[i] = Instant::now() + fs.config.sync_interval;
+    }
+}
+
+/// Limits the bytes copied per second
+struct Throttle {
+    rate: u64,
+    start: Instant,
+    bytes: u64,
+}
+
+impl Throttle {
+    fn new(rate: u64) -> Self {
+        Self {
+            rate,
+            start: Instant::now(),
+            bytes: 0,
+        }
+    }
+
+    /// Waits until `bytes` more may be copied
+    fn take(&mut self, bytes: u64) {
+        // Counts per second, so that pauses are not saved up for a burst
+        if self.start.elapsed() >= Duration::from_secs(1) {
+            self.start = Instant::now();
+            self.bytes = 0;
+        }
+        self.bytes += bytes;
+        let allowed = Duration::from_secs_f64(self.bytes as f64 / self.rate as f64);
+        if let Some(wait) = allowed.checked_sub(self.start.elapsed()) {
+            thread::sleep(wait);
+        }
+    }
+}
+
+/// Copies the files of `host_dir` to the empty `target` of a starting
+/// partition
+pub(crate) fn restore(host_dir: &Path, target: &Path) -> io::Result<SyncStats> {
+    let mut stats = SyncStats::default();
+    sync_dir(
+        host_dir,
+        target,
+        SystemTime::now(),
+        &mut || true,
+        &mut stats,
+    )?;
+    Ok(stats)
+}
+
+/// Syncs the directory `dest` with `source`, see the [module](self) for the
+/// rules
+fn sync_dir(
+    source: &Path,
+    dest: &Path,
+    last_sync: SystemTime,
+    pause: &mut dyn FnMut() -> bool,
+    stats: &mut SyncStats,
+) -> io::Result<()> {
+    match fs::symlink_metadata(dest) {
+        Ok(meta) if meta.is_dir() => {}
+        // The partition replaced a file with a directory
+        Ok(_) => {
+            fs::remove_file(dest)?;
+            fs::create_dir(dest)?;
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => fs::create_dir(dest)?,
+        Err(e) => return Err(e),
+    }
+
+    let mut present = HashSet::new();
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let (from, to) = (entry.path(), dest.join(entry.file_name()));
+        if file_type.is_dir() {
+            sync_dir(&from, &to, last_sync, pause, stats)?;
+        } else if file_type.is_file() {
+            let meta = entry.metadata()?;
+            if changed(&meta, &to)? {
+                copy(&from, &to, &meta, pause, stats)?;
+            }
+        } else {
+            continue;
+        }
+        present.insert(entry.file_name());
+    }
+
+    for entry in fs::read_dir(dest)? {
+        let entry = entry?;
+        if !present.contains(&entry.file_name()) {
+            remove_deleted(&entry.path(), last_sync, stats)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether the file of the partition with the metadata `source` is to replace
+/// `dest`
+fn changed(source: &fs::Metadata, dest: &Path) -> io::Result<bool> {
+    let dest = match fs::symlink_metadata(dest) {
+        Ok(dest) => dest,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(true),
+        Err(e) => return Err(e),
+    };
+    let (modified, dest_modified) = (source.modified()?, dest.modified()?);
+    Ok(!dest.is_file()
+        || modified > dest_modified
+        || (modified == dest_modified && source.len() != dest.len()))
+}
+
+/// Copies the file `source` with the metadata `meta` to `dest`, replacing it
+/// at once
+fn copy(
+    source: &Path,
+    dest: &Path,
+    meta: &fs::Metadata,
+    pause: &mut dyn FnMut() -> bool,
+    stats: &mut SyncStats,
+) -> io::Result<()> {
+    let mut name = dest.file_name().unwrap_or_default().to_os_string();
+    name.push(".a653rs-sync");
+    let tmp = dest.with_file_name(name);
+    let res = (|| {
+        let mut from = File::open(source)?;
+        let mut to = File::create(&tmp)?;
+        let mut buf = vec![0; CHUNK];
+        loop {
+            if !pause() {
+                return Err(io::Error::new(ErrorKind::Interrupted, "sync was stopped"));
+            }
+            let len = from.read(&mut buf)?;
+            if len == 0 {
+                break;
+            }
+            to.write_all(&buf[..len])?;
+            stats.bytes += len as u64;
+        }
+        // Equal times mark the copy as unchanged for the next sync
+        to.set_modified(meta.modified()?)?;
+        to.sync_all()?;
+        if fs::symlink_metadata(dest).is_ok_and(|dest| dest.is_dir()) {
+            fs::remove_dir_all(dest)?;
+        }
+        fs::rename(&tmp, dest)
+    })();
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    res?;
+    stats.copied += 1;
+    Ok(())
+}
+
+/// Removes `path` of the host, which the partition deleted, unless it was
+/// modified on the host since `last_sync`
+fn remove_deleted(path: &Path, last_sync: SystemTime, stats: &mut SyncStats) -> io::Result<()> {
+    let meta = fs::symlink_metadata(path)?;
+    if meta.is_dir() {
+        for entry in fs::read_dir(path)? {
+            remove_deleted(&entry?.path(), last_sync, stats)?;
+        }
+        // Kept if anything inside was kept
+        if fs::read_dir(path)?.next().is_none() {
+            fs::remove_dir(path)?;
+            stats.removed += 1;
+        }
+    } else if meta.modified()? > last_sync {
+        debug!(
+            "Keeping {}, which was modified on the host since the last sync",
+            path.display()
+        );
+    } else {
+        fs::remove_file(path)?;
+        stats.removed += 1;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sync(source: &Path, dest: &Path, last_sync: SystemTime) -> SyncStats {
+        let mut stats = SyncStats::default();
+        sync_dir(source, dest, last_sync, &mut || true, &mut stats).unwrap();
+        stats
+    }
+
+    /// Sets the modification time of `path` to `secs` after the epoch
+    fn touch(path: &Path, secs: u64) {
+        File::options()
+            .append(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+            .unwrap();
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn changed_files_are_copied() {
+        let (partition, host) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let (source, dest) = (partition.path(), host.path());
+        fs::create_dir_all(source.join("logs")).unwrap();
+        fs::write(source.join("logs/run.log"), "started\n").unwrap();
+        fs::write(source.join("state"), "1").unwrap();
+
+        let stats = sync(source, dest, at(0));
+        assert_eq!((stats.copied, stats.bytes), (2, 9));
+        assert_eq!(
+            fs::read_to_string(dest.join("logs/run.log")).unwrap(),
+            "started\n"
+        );
+
+        // Unchanged files are not copied again
+        assert_eq!(sync(source, dest, at(0)), SyncStats::default());
+
+        // A growing file is copied again
+        File::options()
+            .append(true)
+            .open(source.join("logs/run.log"))
+            .unwrap()
+            .write_all(b"running\n")
+            .unwrap();
+        assert_eq!(sync(source, dest, at(0)).copied, 1);
+        assert_eq!(
+            fs::read_to_string(dest.join("logs/run.log")).unwrap(),
+            "started\nrunning\n"
+        );
+        // No temporary files are left behind
+        assert_eq!(fs::read_dir(dest.join("logs")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn last_writer_wins() {
+        let (partition, host) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let (source, dest) = (partition.path(), host.path());
+        for name in ["edited", "deleted", "kept"] {
+            fs::write(source.join(name), "partition").unwrap();
+            touch(&source.join(name), 100);
+        }
+        sync(source, dest, at(100));
+
+        // Edited on the host after the partition wrote it
+        fs::write(dest.join("edited"), "host").unwrap();
+        touch(&dest.join("edited"), 300);
+        fs::write(source.join("edited"), "partition again").unwrap();
+        touch(&source.join("edited"), 200);
+        // Deleted by the partition, but modified on the host since the sync
+        fs::remove_file(source.join("kept")).unwrap();
+        touch(&dest.join("kept"), 300);
+        fs::remove_file(source.join("deleted")).unwrap();
+        // Added on the host
+        fs::create_dir(dest.join("added")).unwrap();
+        fs::write(dest.join("added/file"), "host").unwrap();
+
+        let stats = sync(source, dest, at(250));
+        assert_eq!((stats.copied, stats.removed), (0, 1));
+        assert_eq!(fs::read_to_string(dest.join("edited")).unwrap(), "host");
+        assert!(!dest.join("deleted").exists());
+        assert!(dest.join("kept").exists());
+        assert!(dest.join("added/file").exists());
+
+        // The partition writes last
+        touch(&source.join("edited"), 400);
+        sync(source, dest, at(250));
+        assert_eq!(
+            fs::read_to_string(dest.join("edited")).unwrap(),
+            "partition again"
+        );
+    }
+
+    #[test]
+    fn deleted_directories() {
+        let (partition, host) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let (source, dest) = (partition.path(), host.path());
+        fs::create_dir_all(source.join("a/b")).unwrap();
+        fs::write(source.join("a/b/c"), "c").unwrap();
+        sync(source, dest, at(0));
+
+        fs::remove_dir_all(source.join("a")).unwrap();
+        let stats = sync(source, dest, SystemTime::now() + Duration::from_secs(60));
+        assert_eq!(stats.removed, 3);
+        assert_eq!(fs::read_dir(dest).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn restore_keeps_modification_times() {
+        let (host, partition) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        fs::write(host.path().join("state"), "42").unwrap();
+        touch(&host.path().join("state"), 100);
+        let target = partition.path().join("data");
+
+        let stats = restore(host.path(), &target).unwrap();
+        assert_eq!(stats.copied, 1);
+        assert_eq!(
+            fs::metadata(target.join("state"))
+                .unwrap()
+                .modified()
+                .unwrap(),
+            at(100)
+        );
+        // Unchanged, so syncing back copies nothing
+        assert_eq!(sync(&target, host.path(), at(100)), SyncStats::default());
+    }
+
+    #[test]
+    fn stopped_sync_leaves_no_partial_file() {
+        let (partition, host) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        fs::write(partition.path().join("large"), vec![1; 3 * CHUNK]).unwrap();
+
+        let mut chunks = 0;
+        let mut stats = SyncStats::default();
+        let err = sync_dir(
+            partition.path(),
+            host.path(),
+            at(0),
+            &mut || {
+                chunks += 1;
+                chunks < 2
+            },
+            &mut stats,
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Interrupted);
+        assert_eq!(fs::read_dir(host.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn gate() {
+        let gate = Arc::new(Gate::new());
+        let stop = Arc::new(AtomicBool::new(false));
+        gate.open();
+        assert!(gate.wait(&stop));
+        gate.close();
+
+        let waiter = {
+            let (gate, stop) = (gate.clone(), stop.clone());
+            thread::spawn(move || gate.wait(&stop))
+        };
+        thread::sleep(STOP_POLL * 2);
+        assert!(!waiter.is_finished());
+        gate.open();
+        assert!(waiter.join().unwrap());
+        gate.close();
+
+        stop.store(true, Ordering::SeqCst);
+        assert!(!gate.wait(&stop));
+    }
+
+    #[test]
+    fn validation() {
+        let host = tempfile::tempdir().unwrap();
+        let yaml = format!("host_dir: {}", host.path().display());
+        let config: PartitionFsConfig = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(config.target, Path::new("/data"));
+        assert_eq!(config.sync_interval, Duration::from_secs(5));
+        config.validate("p").unwrap();
+
+        for (field, value) in [
+            ("target", "data"),
+            ("target", "/"),
+            ("target", "/data/../etc"),
+            ("sync_interval", "0s"),
+            ("size", "0B"),
+        ] {
+            let yaml = format!("{yaml}\n{field}: {value}");
+            let config: PartitionFsConfig = serde_yaml::from_str(&yaml).unwrap();
+            let err = config.validate("p").unwrap_err();
+            assert!(err.to_string().contains(field), "{err}");
+        }
+
+        let config: PartitionFsConfig = serde_yaml::from_str("host_dir: /no/such/dir").unwrap();
+        assert!(config.validate("p").is_err());
+    }
+}
@@ -0,0 +1,1101 @@
+//! Recording the messages of channels to a file and replaying them
+//!
+//! The optional `recording` of the configuration writes every message
+//! transferred on the listed channels to a file:
+//!
+//! ```yaml
+//! recording:
+//!   path: /var/lib/a653rs/channels.rec
+//!   channels: [fuel_sensors, Cmd]
+//!   compression: zstd
+//! ```
+//!
+//! `--replay <FILE>` writes the messages of a recording to the destinations of
+//! their channels again, at the module time they were recorded at, and the
+//! `inspect-recording` command lists them.
+//!
+//! The file starts with [MAGIC] and the [VERSION] of the format, followed by
+//! the records, each in little endian:
+//!
+//! | Bytes | Content                                         |
+//! |-------|-------------------------------------------------|
+//! | 1     | flags, [COMPRESSED] if the data is compressed   |
+//! | 1     | kind of the channel, 0 sampling and 1 queuing   |
+//! | 2     | length of the name of the channel               |
+//! | n     | name of the channel, by its source port         |
+//! | 8     | module time of the transfer in nanoseconds      |
+//! | 4     | length of the data                              |
+//! | n     | data, the message as it is or compressed        |
+//!
+//! Every record is compressed on its own, so that a file stays readable up to
+//! the last complete record and may mix compressed and uncompressed ones.
+//! Records whose compression does not save anything are stored as they are.
+//!
+//! The file is written by a thread of its own, which also compresses the
+//! messages. The scheduler hands the messages to it through a bounded queue,
+//! which drops and counts messages when it is full, so that a slow disk never
+//! delays the schedule. The thread is only started with the schedule, after
+//! the partitions were created.
+//!
+//! The recording counts against the `recording` budget of the
+//! [capture](super::capture) configuration. Every rotated file starts with the
+//! header, so that it can be read on its own.
+//!
+//! Compression requires the `zstd` feature, also for reading compressed
+//! records.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TryRecvError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use a653rs_linux_core::error::{ResultExt, SystemError, TypedResult};
+use a653rs_linux_core::queuing::Queuing;
+use a653rs_linux_core::sampling::Sampling;
+use a653rs_linux_core::time::ModuleTime;
+use anyhow::{anyhow, bail, Context};
+use bytesize::ByteSize;
+use serde::{Deserialize, Serialize};
+
+use crate::hypervisor::capture::{Admission, Capture, CaptureManager, Feature};
+use crate::hypervisor::config::Channel;
+use crate::hypervisor::tap::{ChannelKey, Transferred};
+
+/// Start of every recording
+pub const MAGIC: &[u8; 7] = b"A653REC";
+
+/// Version of the format written, which readers accept up to
+pub const VERSION: u8 = 1;
+
+/// Bytes of the header, [MAGIC] and [VERSION]
+const HEADER_LEN: u64 = MAGIC.len() as u64 + 1;
+
+/// Flag of records whose data is compressed with zstd
+pub const COMPRESSED: u8 = 1 << 0;
+
+/// Compression level of zstd, favouring speed over size
+#[cfg(feature = "zstd")]
+const ZSTD_LEVEL: i32 = 3;
+
+/// Number of records read ahead of the schedule while replaying
+const REPLAY_QUEUE_SIZE: usize = 64;
+
+/// Configuration of the recording of channels
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct RecordingConfig {
+    /// File the messages are written to, replacing an existing one
+    pub path: PathBuf,
+
+    /// Names of the source ports of the recorded channels, or
+    /// `<partition>:<port>` if several channels share one
+    pub channels: Vec<String>,
+
+    #[serde(default)]
+    pub compression: Compression,
+
+    /// Number of messages buffered between the scheduler and the thread
+    /// writing the file
+    #[serde(default = "RecordingConfig::default_queue_size")]
+    pub queue_size: usize,
+}
+
+/// Compression of the recorded messages
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    /// The messages as they are
+    #[default]
+    None,
+    /// Every message compressed with zstd, requires the `zstd` feature
+    Zstd,
+}
+
+impl RecordingConfig {
+    fn default_queue_size() -> usize {
+        256
+    }
+
+    /// Keeps only the recorded `channels`, for
+    /// [Config::solo](super::config::Config::solo)
+    pub(crate) fn retain_channels(&mut self, channels: &[Channel]) {
+        self.channels
+            .retain(|c| ChannelKey::resolve(channels, c).is_ok());
+    }
+
+    /// Checks that every recorded channel is one of `channels`
+    pub fn validate(&self, channels: &[Channel]) -> TypedResult<()> {
+        let mut invalid = Vec::new();
+        if self.path.as_os_str().is_empty() {
+            invalid.push("path must not be empty".to_string());
+        }
+        if self.queue_size == 0 {
+            invalid.push("queue_size must be at least 1".to_string());
+        }
+        if self.compression == Compression::Zstd && !cfg!(feature = "zstd") {
+            invalid.push(
+                "zstd compression requires a hypervisor built with the \"zstd\" feature"
+                    .to_string(),
+            );
+        }
+        for channel in &self.channels {
+            if let Err(e) = ChannelKey::resolve(channels, channel) {
+                invalid.push(e);
+            }
+        }
+
+        if !invalid.is_empty() {
+            return Err(anyhow!("invalid recording:\n{}", invalid.join("\n")))
+                .typ(SystemError::Config);
+        }
+        Ok(())
+    }
+}
+
+/// A message of a recording
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Record {
+    pub channel: ChannelKey,
+    pub time: ModuleTime,
+    pub data: Vec<u8>,
+}
+
+/// A [Record] as it was read, along with the size it took in the file
+#[derive(Debug)]
+pub(crate) struct ReadRecord {
+    pub record: Record,
+    pub compressed: bool,
+    /// Bytes of the data in the file
+    pub stored: usize,
+}
+
+/// Writes the header of a recording to `w`
+pub(crate) fn write_header(w: &mut impl Write) -> io::Result<()> {
+    w.write_all(MAGIC)?;
+    w.write_all(&[VERSION])
+}
+
+/// Writes `record` to `w`, returning the number of bytes written
+pub(crate) fn write_record(
+    w: &mut impl Write,
+    record: &Record,
+    compression: Compression,
+) -> io::Result<usize> {
+    let (flags, data) = match compression {
+        Compression::None => (0, None),
+        Compression::Zstd => {
+            let compressed = compress(&record.data)?;
+            // Incompressible messages are kept as they are
+            if compressed.len() < record.data.len() {
+                (COMPRESSED, Some(compressed))
+            } else {
+                (0, None)
+            }
+        }
+    };
+    let data = data.as_deref().unwrap_or(&record.data);
+    let (kind, name) = match &record.channel {
+        ChannelKey::Sampling(name) => (0, name),
+        ChannelKey::Queuing(name) => (1, name),
+    };
+    let name_len = u16::try_from(name.len())
+        .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "channel name too long"))?;
+    let data_len = u32::try_from(data.len())
+        .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "message too large"))?;
+    let time = u64::try_from(record.time.as_duration().as_nanos()).unwrap_or(u64::MAX);
+
+    w.write_all(&[flags, kind])?;
+    w.write_all(&name_len.to_le_bytes())?;
+    w.write_all(name.as_bytes())?;
+    w.write_all(&time.to_le_bytes())?;
+    w.write_all(&data_len.to_le_bytes())?;
+    w.write_all(data)?;
+    Ok(2 + 2 + name.len() + 8 + 4 + data.len())
+}
+
+#[cfg(feature = "zstd")]
+fn compress(data: &[u8]) -> io::Result<Vec<u8>> {
+    zstd::bulk::compress(data, ZSTD_LEVEL)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn compress(_data: &[u8]) -> io::Result<Vec<u8>> {
+    Err(io::Error::new(
+        ErrorKind::Unsupported,
+        "zstd compression requires the \"zstd\" feature",
+    ))
+}
+
+#[cfg(feature = "zstd")]
+fn decompress(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    Ok(zstd::stream::decode_all(data)?)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn decompress(_data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    bail!("the record is compressed, reading it requires the \"zstd\" feature")
+}
+
+/// Reads the records of a recording, decompressing them as needed
+pub(crate) struct RecordingReader<R> {
+    reader: R,
+}
+
+impl RecordingReader<BufReader<File>> {
+    pub(crate) fn open(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path).with_context(|| format!("failed to open {path:?}"))?;
+        Self::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read> RecordingReader<R> {
+    /// Checks the header of the recording in `reader`
+    pub(crate) fn new(mut reader: R) -> anyhow::Result<Self> {
+        let mut header = [0; MAGIC.len() + 1];
+        reader
+            .read_exact(&mut header)
+            .context("failed to read the header")?;
+        if &header[..MAGIC.len()] != MAGIC {
+            bail!("not a recording of channels");
+        }
+        let version = header[MAGIC.len()];
+        if version == 0 || version > VERSION {
+            bail!("unsupported version {version} of the recording, up to {VERSION} is supported");
+        }
+        Ok(Self { reader })
+    }
+
+    /// Reads the next record, if the recording did not end
+    fn read_record(&mut self) -> anyhow::Result<Option<ReadRecord>> {
+        let mut head = [0; 4];
+        // A recording may only end in between records
+        if self.reader.read(&mut head[..1])? == 0 {
+            return Ok(None);
+        }
+        self.reader
+            .read_exact(&mut head[1..])
+            .context("truncated record")?;
+        let [flags, kind, name_len @ ..] = head;
+        if flags & !COMPRESSED != 0 {
+            bail!("unknown flags {flags:#x} of a record");
+        }
+        let mut name = vec![0; u16::from_le_bytes(name_len).into()];
+        self.reader
+            .read_exact(&mut name)
+            .context("truncated record")?;
+        let name = String::from_utf8(name).context("invalid channel name")?;
+        let channel = match kind {
+            0 => ChannelKey::Sampling(name),
+            1 => ChannelKey::Queuing(name),
+            _ => bail!("unknown kind {kind} of channel {name:?}"),
+        };
+
+        let mut time = [0; 8];
+        let mut data_len = [0; 4];
+        self.reader
+            .read_exact(&mut time)
+            .and_then(|_| self.reader.read_exact(&mut data_len))
+            .context("truncated record")?;
+        let mut data = vec![0; u32::from_le_bytes(data_len) as usize];
+        self.reader
+            .read_exact(&mut data)
+            .context("truncated record")?;
+
+        let compressed = flags & COMPRESSED != 0;
+        let stored = data.len();
+        if compressed {
+            data = decompress(&data)
+                .with_context(|| format!("failed to decompress a record of {channel:?}"))?;
+        }
+        let time = ModuleTime::from(Duration::from_nanos(u64::from_le_bytes(time)));
+        Ok(Some(ReadRecord {
+            record: Record {
+                channel,
+                time,
+                data,
+            },
+            compressed,
+            stored,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for RecordingReader<R> {
+    type Item = anyhow::Result<ReadRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+/// Message counters of the recorder
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RecorderStats {
+    /// Messages written to the file
+    pub recorded: u64,
+    /// Messages which were not written, because the queue to the thread
+    /// writing the file was full, they exceeded the budget of the recording or
+    /// writing failed
+    pub dropped: u64,
+    /// Bytes written to the file for the records
+    pub bytes: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    recorded: AtomicU64,
+    dropped: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl Counters {
+    fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> RecorderStats {
+        RecorderStats {
+            recorded: self.recorded.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// State of the thread writing the file
+struct Writer {
+    output: Box<dyn Write + Send>,
+    compression: Compression,
+    queue: Receiver<Record>,
+    counters: Arc<Counters>,
+    /// Budget of the file, which is only rotated if the recorder created it
+    capture: Option<Capture>,
+}
+
+/// Records the messages of channels
+pub struct Recorder {
+    channels: Vec<ChannelKey>,
+    queue: Option<SyncSender<Record>>,
+    counters: Arc<Counters>,
+    /// The writer until the recorder is started
+    writer: Option<Writer>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Recorder {
+    /// Prepares the recording of `config` of `channels` to `output`, which
+    /// already holds the header
+    pub(crate) fn new(
+        config: &RecordingConfig,
+        channels: &[Channel],
+        output: Box<dyn Write + Send>,
+    ) -> TypedResult<Self> {
+        config.validate(channels)?;
+        let channels = config
+            .channels
+            .iter()
+            .map(|c| ChannelKey::resolve(channels, c))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| anyhow!(e))
+            .typ(SystemError::Config)?;
+
+        let (queue_tx, queue_rx) = sync_channel(config.queue_size);
+        let counters = Arc::new(Counters::default());
+        Ok(Self {
+            channels,
+            queue: Some(queue_tx),
+            counters: counters.clone(),
+            writer: Some(Writer {
+                output,
+                compression: config.compression,
+                queue: queue_rx,
+                counters,
+                capture: None,
+            }),
+            thread: None,
+        })
+    }
+
+    /// Creates the file of the recording of `config`, within the budget of
+    /// the recording in `captures`
+    pub fn create(
+        config: &RecordingConfig,
+        channels: &[Channel],
+        captures: &Arc<CaptureManager>,
+    ) -> TypedResult<Self> {
+        let mut output = File::create(&config.path)
+            .map(BufWriter::new)
+            .with_context(|| format!("failed to create the recording {:?}", config.path))
+            .typ(SystemError::Config)?;
+        write_header(&mut output).typ(SystemError::Config)?;
+        let capture = captures.register(Feature::Recording, &config.path);
+        capture.written(HEADER_LEN);
+
+        let mut recorder = Self::new(config, channels, Box::new(output))?;
+        if let Some(writer) = &mut recorder.writer {
+            writer.capture = Some(capture);
+        }
+        Ok(recorder)
+    }
+
+    /// Keeps the messages of the recorded channels for [Recorder::record]
+    pub fn tap(
+        &self,
+        sampling: &mut HashMap<String, Sampling>,
+        queuing: &mut HashMap<String, Queuing>,
+    ) {
+        for channel in &self.channels {
+            channel.tap(sampling, queuing);
+        }
+    }
+
+    /// Starts the thread writing the file
+    pub fn start(&mut self) -> TypedResult<()> {
+        let Some(writer) = self.writer.take() else {
+            return Ok(());
+        };
+        let thread = thread::Builder::new()
+            .name("recorder".to_string())
+            .spawn(move || writer.run())
+            .typ(SystemError::Panic)?;
+        self.thread = Some(thread);
+        Ok(())
+    }
+
+    /// Hands the messages `transferred` on the recorded channels at `time` to
+    /// the thread writing the file
+    pub(crate) fn record(&mut self, transferred: &Transferred, time: ModuleTime) {
+        let Some(queue) = &self.queue else {
+            return;
+        };
+        for channel in &self.channels {
+            for data in transferred.of(channel) {
+                let record = Record {
+                    channel: channel.clone(),
+                    time,
+                    data: data.clone(),
+                };
+                if queue.try_send(record).is_err() {
+                    Counters::add(&self.counters.dropped, 1);
+                }
+            }
+        }
+    }
+
+    pub fn stats(&self) -> RecorderStats {
+        self.counters.snapshot()
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        // Ends the thread once it wrote the queued messages
+        self.queue = None;
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("The recorder thread panicked");
+            }
+        }
+        let stats = self.stats();
+        info!(
+            "Recorded {} messages in {}, dropping {}",
+            stats.recorded,
+            ByteSize::b(stats.bytes),
+            stats.dropped
+        );
+    }
+}
+
+impl Writer {
+    /// Writes the queued messages until the recorder is dropped
+    fn run(mut self) {
+        let mut failed = false;
+        while let Ok(record) = self.queue.recv() {
+            match self.write(&record) {
+                Ok(Some(bytes)) => {
+                    Counters::add(&self.counters.recorded, 1);
+                    Counters::add(&self.counters.bytes, bytes);
+                }
+                // Warned about by the budget
+                Ok(None) => Counters::add(&self.counters.dropped, 1),
+                Err(e) => {
+                    // Reported once, as the disk is likely to stay full
+                    if !failed {
+                        warn!("Failed to write the recording: {e}");
+                    }
+                    failed = true;
+                    Counters::add(&self.counters.dropped, 1);
+                }
+            }
+        }
+        if let Err(e) = self.output.flush() {
+            warn!("Failed to write the recording: {e}");
+        }
+    }
+
+    /// Writes `record` if it fits into the budget, returning the number of
+    /// bytes written
+    fn write(&mut self, record: &Record) -> io::Result<Option<u64>> {
+        let Some(capture) = &self.capture else {
+            let bytes = write_record(&mut self.output, record, self.compression)?;
+            return Ok(Some(bytes as u64));
+        };
+
+        // Encoded ahead, as compression decides the size
+        let mut encoded = Vec::new();
+        let bytes = write_record(&mut encoded, record, self.compression)? as u64;
+        loop {
+            match capture.admit(bytes) {
+                Admission::Write => break,
+                Admission::Drop => return Ok(None),
+                Admission::Rotate => {
+                    self.output.flush()?;
+                    capture.rotate()?;
+                    let mut output = BufWriter::new(File::create(capture.path())?);
+                    write_header(&mut output)?;
+                    capture.written(HEADER_LEN);
+                    self.output = Box::new(output);
+                }
+            }
+        }
+        self.output.write_all(&encoded)?;
+        capture.written(bytes);
+        Ok(Some(bytes))
+    }
+}
+
+/// Counters of the replay
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayStats {
+    /// Messages written to channels
+    pub replayed: u64,
+    /// Messages which were not written, because their channel is not
+    /// configured, they exceed the message size or the queuing channel was
+    /// full
+    pub dropped: u64,
+}
+
+/// Writes the messages of a recording to their channels
+pub struct Replayer {
+    /// The records read ahead, until the replay is dropped
+    queue: Option<Receiver<Record>>,
+    /// The next record, if it is not due yet
+    pending: Option<Record>,
+    stats: ReplayStats,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Replayer {
+    /// Starts reading the recording at `path`, checking its header right away
+    pub fn open(path: &Path) -> TypedResult<Self> {
+        let reader = RecordingReader::open(path).typ(SystemError::Config)?;
+        Self::new(reader)
+    }
+
+    pub(crate) fn new<R: Read + Send + 'static>(reader: RecordingReader<R>) -> TypedResult<Self> {
+        let (queue_tx, queue_rx) = sync_channel(REPLAY_QUEUE_SIZE);
+        let thread = thread::Builder::new()
+            .name("replay".to_string())
+            .spawn(move || {
+                for record in reader {
+                    match record {
+                        Ok(read) => {
+                            // The replay was dropped
+                            if queue_tx.send(read.record).is_err() {
+                                return;
+                            }
+                        }
+                        Err(e) => {
+                            warn!("Stopped replaying: {e:#}");
+                            return;
+                        }
+                    }
+                }
+            })
+            .typ(SystemError::Panic)?;
+        Ok(Self {
+            queue: Some(queue_rx),
+            pending: None,
+            stats: ReplayStats::default(),
+            thread: Some(thread),
+        })
+    }
+
+    /// Writes the messages recorded up to `now` to their channels
+    pub fn inject(
+        &mut self,
+        now: ModuleTime,
+        sampling: &mut HashMap<String, Sampling>,
+        queuing: &mut HashMap<String, Queuing>,
+    ) {
+        let Some(queue) = &self.queue else {
+            return;
+        };
+        loop {
+            let record = match self.pending.take() {
+                Some(record) => record,
+                None => match queue.try_recv() {
+                    Ok(record) => record,
+                    Err(TryRecvError::Empty | TryRecvError::Disconnected) => return,
+                },
+            };
+            if record.time > now {
+                self.pending = Some(record);
+                return;
+            }
+            let injected = match &record.channel {
+                ChannelKey::Sampling(name) => sampling
+                    .get_mut(name)
+                    .map(|c| c.inject(&record.data).map(|_| true)),
+                ChannelKey::Queuing(name) => queuing.get_mut(name).map(|c| c.inject(&record.data)),
+            };
+            match injected {
+                Some(Ok(true)) => self.stats.replayed += 1,
+                Some(Ok(false)) => {
+                    debug!("Dropped a replayed message for a full channel");
+                    self.stats.dropped += 1;
+                }
+                Some(Err(e)) => {
+                    warn!("Dropped a replayed message: {e}");
+                    self.stats.dropped += 1;
+                }
+                None => {
+                    debug!(
+                        "Dropped a replayed message of the unknown channel {}",
+                        record.channel.name()
+                    );
+                    self.stats.dropped += 1;
+                }
+            }
+        }
+    }
+
+    pub fn stats(&self) -> ReplayStats {
+        self.stats
+    }
+}
+
+impl Drop for Replayer {
+    fn drop(&mut self) {
+        // Unblocks the thread, which fails to queue the next record
+        self.queue = None;
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("The replay thread panicked");
+            }
+        }
+        info!(
+            "Replayed {} messages, dropping {}",
+            self.stats.replayed, self.stats.dropped
+        );
+    }
+}
+
+/// Prints the records of the recording at `path` and a summary, for the
+/// `inspect-recording` command
+pub fn inspect(path: &Path) -> TypedResult<()> {
+    let reader = RecordingReader::open(path).typ(SystemError::Config)?;
+    let mut messages = 0u64;
+    let mut bytes = 0u64;
+    let mut stored = 0u64;
+    let mut channels = HashSet::new();
+    for read in reader {
+        let read = read
+            .with_context(|| format!("after {messages} messages"))
+            .typ(SystemError::Config)?;
+        let Record {
+            channel,
+            time,
+            data,
+        } = &read.record;
+        let kind = match channel {
+            ChannelKey::Sampling(_) => "sampling",
+            ChannelKey::Queuing(_) => "queuing",
+        };
+        let compressed = if read.compressed {
+            format!(", compressed to {}", ByteSize::b(read.stored as u64))
+        } else {
+            String::new()
+        };
+        println!(
+            "{:>14} {kind} {} {}{compressed}",
+            time.as_duration().as_nanos(),
+            channel.name(),
+            ByteSize::b(data.len() as u64)
+        );
+        messages += 1;
+        bytes += data.len() as u64;
+        stored += read.stored as u64;
+        channels.insert(channel.clone());
+    }
+    println!(
+        "{messages} messages of {} channels, {} stored in {}",
+        channels.len(),
+        ByteSize::b(bytes),
+        ByteSize::b(stored)
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::os::fd::AsRawFd;
+    use std::sync::Mutex;
+
+    use a653rs_linux_core::sampling::SamplingDestination;
+
+    use super::*;
+
+    const CHANNELS: &str = r#"
+- !Sampling
+  msg_size: 64B
+  source: { partition: sensor, port: Fuel }
+  destination: [ { partition: ctrl, port: Fuel } ]
+- !Queuing
+  msg_size: 8B
+  msg_num: 4
+  source: { partition: ctrl, port: Log }
+  destination: { partition: logger, port: Log }
+"#;
+
+    fn channels() -> Vec<Channel> {
+        serde_yaml::from_str(CHANNELS).unwrap()
+    }
+
+    fn config(compression: Compression, queue_size: usize) -> RecordingConfig {
+        RecordingConfig {
+            path: PathBuf::from("channels.rec"),
+            channels: vec!["Fuel".into(), "Log".into()],
+            compression,
+            queue_size,
+        }
+    }
+
+    fn record(channel: ChannelKey, millis: u64, data: &[u8]) -> Record {
+        Record {
+            channel,
+            time: Duration::from_millis(millis).into(),
+            data: data.to_vec(),
+        }
+    }
+
+    fn fuel() -> ChannelKey {
+        ChannelKey::Sampling("sensor:Fuel".into())
+    }
+
+    fn log() -> ChannelKey {
+        ChannelKey::Queuing("ctrl:Log".into())
+    }
+
+    /// Output shared with the test, optionally slowed down for every write
+    #[derive(Clone, Default)]
+    struct Output {
+        data: Arc<Mutex<Vec<u8>>>,
+        delay: Duration,
+    }
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            thread::sleep(self.delay);
+            self.data.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn read_all(data: Vec<u8>) -> Vec<ReadRecord> {
+        RecordingReader::new(Cursor::new(data))
+            .unwrap()
+            .collect::<anyhow::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn configuration() {
+        let yaml = r#"
+path: /tmp/channels.rec
+channels: [Fuel]
+"#;
+        let config: RecordingConfig = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.compression, Compression::None);
+        assert_eq!(config.queue_size, 256);
+        config.validate(&channels()).unwrap();
+
+        let config = RecordingConfig {
+            channels: vec!["Unknown".into()],
+            queue_size: 0,
+            ..config
+        };
+        let err = config.validate(&channels()).unwrap_err().to_string();
+        assert!(err.contains("queue_size"), "{err}");
+        assert!(err.contains("\"Unknown\" is not configured"), "{err}");
+    }
+
+    #[test]
+    fn uncompressed_records_round_trip() {
+        let records = [
+            record(fuel(), 0, b"full"),
+            record(log(), 5, b""),
+            record(fuel(), 1000, b"empty"),
+        ];
+        let mut data = Vec::new();
+        write_header(&mut data).unwrap();
+        for r in &records {
+            write_record(&mut data, r, Compression::None).unwrap();
+        }
+
+        let read = read_all(data.clone());
+        assert_eq!(read.len(), 3);
+        for (read, written) in read.iter().zip(&records) {
+            assert_eq!(&read.record, written);
+            assert!(!read.compressed);
+        }
+
+        // A crash may leave the last record incomplete
+        data.pop();
+        let mut reader = RecordingReader::new(Cursor::new(data)).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().unwrap().is_ok());
+        let err = reader.next().unwrap().unwrap_err();
+        assert!(err.to_string().contains("truncated"), "{err}");
+    }
+
+    #[test]
+    fn headers_are_checked() {
+        assert!(RecordingReader::new(Cursor::new(b"A653REC".to_vec())).is_err());
+        assert!(RecordingReader::new(Cursor::new(b"NOTAREC\x01".to_vec())).is_err());
+        let err = RecordingReader::new(Cursor::new(b"A653REC\x02".to_vec()))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("version 2"), "{err}");
+        assert!(RecordingReader::new(Cursor::new(b"A653REC\x01".to_vec()))
+            .unwrap()
+            .next()
+            .is_none());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn compressed_records_round_trip() {
+        let repetitive = b"fuel level nominal ".repeat(100);
+        let records = [
+            record(fuel(), 0, &repetitive),
+            // Too short to gain anything, so it is stored as it is
+            record(log(), 1, b"x"),
+        ];
+        let mut data = Vec::new();
+        write_header(&mut data).unwrap();
+        write_record(&mut data, &records[0], Compression::Zstd).unwrap();
+        write_record(&mut data, &records[1], Compression::Zstd).unwrap();
+        // Files may mix both
+        write_record(&mut data, &records[0], Compression::None).unwrap();
+
+        let read = read_all(data);
+        assert_eq!(read[0].record, records[0]);
+        assert!(read[0].compressed);
+        assert!(read[0].stored < repetitive.len() / 10);
+        assert_eq!(read[1].record, records[1]);
+        assert!(!read[1].compressed);
+        assert_eq!(read[2].record, records[0]);
+        assert!(!read[2].compressed);
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    #[test]
+    fn compression_requires_the_feature() {
+        let err = config(Compression::Zstd, 1)
+            .validate(&channels())
+            .unwrap_err();
+        assert!(err.to_string().contains("\"zstd\" feature"), "{err}");
+
+        // Compressed records are rejected rather than returned as they are
+        let mut data = Vec::new();
+        write_header(&mut data).unwrap();
+        data.extend([COMPRESSED, 0, 1, 0, b'a']);
+        data.extend(0u64.to_le_bytes());
+        data.extend(1u32.to_le_bytes());
+        data.push(0);
+        let err = RecordingReader::new(Cursor::new(data))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("\"zstd\" feature"), "{err:#}");
+    }
+
+    #[test]
+    fn rotated_recordings_are_readable() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("channels.rec");
+        let config = RecordingConfig {
+            path: path.clone(),
+            ..config(Compression::None, 32)
+        };
+        // Files of 150 bytes, which hold the header and three records
+        let captures = CaptureManager::new(
+            serde_yaml::from_str("recording: { max_size: 300B, keep: 1 }").unwrap(),
+        );
+        let mut recorder = Recorder::create(&config, &channels(), &captures).unwrap();
+        for millis in 0..20 {
+            let queue = recorder.queue.as_ref().unwrap();
+            queue.send(record(fuel(), millis, &[0; 16])).unwrap();
+        }
+        recorder.start().unwrap();
+        drop(recorder);
+
+        let times = |path| {
+            RecordingReader::open(path)
+                .unwrap()
+                .map(|r| r.unwrap().record.time.as_duration().as_millis())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(times(&path), [18, 19]);
+        assert_eq!(times(&dir.path().join("channels.rec.1")), [15, 16, 17]);
+        assert!(!dir.path().join("channels.rec.2").exists());
+    }
+
+    #[test]
+    fn recorder_writes_tapped_messages() {
+        let compression = if cfg!(feature = "zstd") {
+            Compression::Zstd
+        } else {
+            Compression::None
+        };
+        let output = Output::default();
+        write_header(&mut *output.data.lock().unwrap()).unwrap();
+        let mut recorder = Recorder::new(
+            &config(compression, 16),
+            &channels(),
+            Box::new(output.clone()),
+        )
+        .unwrap();
+
+        let mut sampling = HashMap::new();
+        let mut queuing = HashMap::new();
+        for channel in channels() {
+            match channel {
+                Channel::Sampling(s) => {
+                    let s = Sampling::try_from(s).unwrap();
+                    sampling.insert(s.name(), s);
+                }
+                Channel::Queuing(q) => {
+                    let q = Queuing::try_from(q).unwrap();
+                    queuing.insert(q.name(), q);
+                }
+            }
+        }
+        recorder.tap(&mut sampling, &mut queuing);
+        recorder.start().unwrap();
+
+        let fuel_level = b"fuel level nominal, fuel level nominal, fuel level nominal";
+        sampling
+            .get_mut("sensor:Fuel")
+            .unwrap()
+            .inject(fuel_level)
+            .unwrap();
+        queuing.get_mut("ctrl:Log").unwrap().inject(b"one").unwrap();
+        queuing.get_mut("ctrl:Log").unwrap().inject(b"two").unwrap();
+        let transferred = Transferred::take(&mut sampling, &mut queuing);
+        recorder.record(&transferred, Duration::from_millis(20).into());
+        drop(recorder);
+
+        let read = read_all(output.data.lock().unwrap().clone());
+        let records = read.into_iter().map(|r| r.record).collect::<Vec<_>>();
+        assert_eq!(
+            records,
+            [
+                record(fuel(), 20, fuel_level),
+                record(log(), 20, b"one"),
+                record(log(), 20, b"two"),
+            ]
+        );
+    }
+
+    #[test]
+    fn slow_writers_drop_messages() {
+        let output = Output {
+            delay: Duration::from_millis(5),
+            ..Default::default()
+        };
+        let mut recorder =
+            Recorder::new(&config(Compression::None, 2), &channels(), Box::new(output)).unwrap();
+        recorder.start().unwrap();
+
+        let mut sampling = HashMap::new();
+        let mut queuing = HashMap::new();
+        let mut channel = Queuing::try_from(channels()[1].queueing().unwrap()).unwrap();
+        channel.tap();
+        for i in 0..4u8 {
+            channel.inject(&[i]).unwrap();
+        }
+        queuing.insert(channel.name(), channel);
+        let transferred = Transferred::take(&mut sampling, &mut queuing);
+        // Handed over far faster than the writer keeps up with
+        for _ in 0..10 {
+            recorder.record(&transferred, ModuleTime::ZERO);
+        }
+
+        let counters = recorder.counters.clone();
+        drop(recorder);
+        let stats = counters.snapshot();
+        assert_eq!(stats.recorded + stats.dropped, 40, "{stats:?}");
+        assert!(stats.dropped > 0, "{stats:?}");
+        // At least the queued ones are written before the thread ends
+        assert!(stats.recorded >= 2, "{stats:?}");
+    }
+
+    #[test]
+    fn replays_messages_when_due() {
+        let mut data = Vec::new();
+        write_header(&mut data).unwrap();
+        for r in [
+            record(fuel(), 10, b"first"),
+            record(ChannelKey::Sampling("unknown:Port".into()), 10, b"lost"),
+            record(fuel(), 30, b"second"),
+        ] {
+            write_record(&mut data, &r, Compression::None).unwrap();
+        }
+
+        let mut sampling = HashMap::new();
+        let mut queuing = HashMap::new();
+        let channel = Sampling::try_from(channels()[0].sampling().unwrap()).unwrap();
+        let mut destination =
+            SamplingDestination::try_from(channel.destination_fd().as_raw_fd()).unwrap();
+        sampling.insert(channel.name(), channel);
+        let mut replayer = Replayer::new(RecordingReader::new(Cursor::new(data)).unwrap()).unwrap();
+        // Until the thread queued all records
+        while !replayer.thread.as_ref().unwrap().is_finished() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let mut buf = [0; 64];
+        let mut replay = |millis: u64| {
+            replayer.inject(
+                Duration::from_millis(millis).into(),
+                &mut sampling,
+                &mut queuing,
+            );
+            replayer.stats()
+        };
+
+        assert_eq!(replay(5), ReplayStats::default());
+        assert_eq!(
+            replay(10),
+            ReplayStats {
+                replayed: 1,
+                dropped: 1
+            }
+        );
+        let (len, _) = destination.read(&mut buf);
+        assert_eq!(&buf[..len], b"first");
+        assert_eq!(replay(40).replayed, 2);
+        let (len, _) = destination.read(&mut buf);
+        assert_eq!(&buf[..len], b"second");
+    }
+}
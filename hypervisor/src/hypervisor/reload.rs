@@ -0,0 +1,53 @@
+//! Reloading the configuration through `SIGHUP`
+//!
+//! Like for [super::shutdown], the signal handler only records the request,
+//! which the main loop takes between partition windows. The reload restarts
+//! the module with the configuration file read again, while the partitions of
+//! the old module keep running until the new configuration is known to be
+//! valid.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+
+/// Whether a reload was requested and not yet taken
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Installs the signal handler for `SIGHUP`, which no longer terminates the
+/// hypervisor afterwards
+pub(crate) fn install() -> nix::Result<()> {
+    let action = SigAction::new(
+        SigHandler::Handler(handler),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    unsafe { sigaction(Signal::SIGHUP, &action) }?;
+    Ok(())
+}
+
+/// Whether a reload was requested since the last call
+///
+/// Several signals before the next call are a single reload.
+pub(crate) fn take_requested() -> bool {
+    REQUESTED.swap(false, Ordering::SeqCst)
+}
+
+extern "C" fn handler(_: libc::c_int) {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::sys::signal::raise;
+
+    use super::*;
+
+    #[test]
+    fn requests_are_taken_once() {
+        install().unwrap();
+        raise(Signal::SIGHUP).unwrap();
+        raise(Signal::SIGHUP).unwrap();
+        assert!(take_requested());
+        assert!(!take_requested());
+    }
+}
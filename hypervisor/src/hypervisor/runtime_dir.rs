@@ -0,0 +1,141 @@
+//! Directory of the temporary files of the hypervisor
+//!
+//! The working directories of the partitions, which hold their IPC sockets
+//! and the mount points of their root filesystems, are created below the
+//! runtime directory given with `--runtime-dir`. It defaults to
+//! `$XDG_RUNTIME_DIR`, or `/tmp` without one, but never to `TMPDIR`, so that
+//! hosts with a read-only root filesystem and a tiny `/tmp` can point the
+//! hypervisor at a suitable tmpfs. Everything the hypervisor creates below it
+//! is removed again when the partitions are dropped, while the directory
+//! itself and all other files in it are left alone.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use a653rs_linux_core::error::{ResultExt, SystemError, TypedResult};
+use anyhow::{anyhow, Context};
+use nix::sys::statfs::{statfs, TMPFS_MAGIC};
+use tempfile::TempDir;
+
+/// Runtime directory used without `--runtime-dir` and `$XDG_RUNTIME_DIR`
+pub const FALLBACK: &str = "/tmp";
+
+/// A directory checked to be suitable for the temporary files of the
+/// hypervisor
+#[derive(Debug, Clone)]
+pub struct RuntimeDir {
+    path: PathBuf,
+    tmpfs: bool,
+}
+
+impl RuntimeDir {
+    /// Opens the runtime directory `path`, or the default one without
+    ///
+    /// Fails if the directory does not exist or is not writable.
+    pub fn open(path: Option<&Path>) -> TypedResult<Self> {
+        let path = resolve(path, std::env::var_os("XDG_RUNTIME_DIR"));
+        if !path.is_dir() {
+            return Err(anyhow!(
+                "runtime directory {} is not a directory",
+                path.display()
+            ))
+            .typ(SystemError::Config);
+        }
+        // Probed with a directory, as partitions get one each
+        tempfile::Builder::new()
+            .prefix(".a653rs-probe-")
+            .tempdir_in(&path)
+            .with_context(|| format!("runtime directory {} is not writable", path.display()))
+            .typ(SystemError::Config)?;
+        let tmpfs = statfs(&path)
+            .with_context(|| format!("cannot inspect runtime directory {}", path.display()))
+            .typ(SystemError::Config)?
+            .filesystem_type()
+            == TMPFS_MAGIC;
+
+        Ok(Self { path, tmpfs })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the directory is on a tmpfs, as preferred for the sockets and
+    /// mount points of the partitions
+    pub fn is_tmpfs(&self) -> bool {
+        self.tmpfs
+    }
+
+    /// Creates a directory named after `prefix` in the runtime directory,
+    /// which is removed with all its contents when dropped
+    pub fn tempdir(&self, prefix: &str) -> TypedResult<TempDir> {
+        tempfile::Builder::new()
+            .prefix(prefix)
+            .tempdir_in(&self.path)
+            .with_context(|| format!("cannot create a directory in {}", self.path.display()))
+            .typ(SystemError::Panic)
+    }
+}
+
+/// The runtime directory `path`, or the default given the value of
+/// `$XDG_RUNTIME_DIR`
+///
+/// A relative or empty `$XDG_RUNTIME_DIR` is ignored, as the specification
+/// demands.
+fn resolve(path: Option<&Path>, xdg_runtime_dir: Option<OsString>) -> PathBuf {
+    if let Some(path) = path {
+        return path.to_path_buf();
+    }
+    xdg_runtime_dir
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .unwrap_or_else(|| PathBuf::from(FALLBACK))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn default_directory() {
+        let explicit = Path::new("/run/a653rs");
+        assert_eq!(
+            resolve(Some(explicit), Some("/run/user/1000".into())),
+            explicit
+        );
+        assert_eq!(
+            resolve(None, Some("/run/user/1000".into())),
+            Path::new("/run/user/1000")
+        );
+        assert_eq!(resolve(None, None), Path::new(FALLBACK));
+        assert_eq!(resolve(None, Some("".into())), Path::new(FALLBACK));
+        assert_eq!(resolve(None, Some("run".into())), Path::new(FALLBACK));
+    }
+
+    #[test]
+    fn unsuitable_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(RuntimeDir::open(Some(&dir.path().join("missing"))).is_err());
+
+        let file = dir.path().join("file");
+        fs::write(&file, "").unwrap();
+        assert!(RuntimeDir::open(Some(&file)).is_err());
+    }
+
+    #[test]
+    fn created_below_the_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let runtime_dir = RuntimeDir::open(Some(dir.path())).unwrap();
+        assert_eq!(runtime_dir.path(), dir.path());
+        // The probe is gone again
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        let working_dir = runtime_dir.tempdir("a653rs-test-").unwrap();
+        assert_eq!(working_dir.path().parent(), Some(dir.path()));
+        fs::write(working_dir.path().join("socket"), "").unwrap();
+        drop(working_dir);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
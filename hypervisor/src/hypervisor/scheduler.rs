@@ -1,100 +1,510 @@
 use std::collections::HashMap;
-use std::thread::sleep;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use a653rs::bindings::PartitionId;
 use a653rs::prelude::OperatingMode;
+use a653rs_linux_core::channel::Transfer;
+use a653rs_linux_core::deadline::Deadline;
 use a653rs_linux_core::error::{LeveledResult, TypedResult};
+use a653rs_linux_core::partition::PortActivity;
 use a653rs_linux_core::queuing::Queuing;
 use a653rs_linux_core::sampling::Sampling;
-pub(crate) use schedule::{PartitionSchedule, ScheduledTimeframe};
-pub(crate) use timeout::Timeout;
+use a653rs_linux_core::time::{ModuleTime, MonotonicTime};
+use itertools::Itertools;
+pub(crate) use schedule::{PartitionSchedule, Point, ScheduledTimeframe};
 
 use crate::hypervisor::partition::Partition;
+use crate::hypervisor::trace::{Activity, Lane, Tracer};
 
 mod schedule;
-mod timeout;
+
+/// The operations of a partition the [Scheduler] relies on
+pub(crate) trait SchedulablePartition {
+    fn name(&self) -> &str;
+
+    fn mode(&mut self) -> OperatingMode;
+
+    /// Time of each partition window reserved for the aperiodic process
+    fn aperiodic_reserve(&self) -> Duration;
+
+    /// Runs the partition until the end of its window
+    ///
+    /// The periodic process is only run if `release_periodic` is set,
+    /// otherwise the aperiodic process gets the whole window. The channels
+    /// connected to the partition are cleared as configured if it is
+    /// restarted within the window.
+    fn run_window(
+        &mut self,
+        deadline: Deadline,
+        periodic_deadline: Deadline,
+        release_periodic: bool,
+        sampling_channels: &mut HashMap<String, Sampling>,
+        queuing_channels: &mut HashMap<String, Queuing>,
+        tracer: &mut Tracer,
+    ) -> LeveledResult<()>;
+
+    /// Swaps the source ports of the partition which are transferred at
+    /// `transfer`. Returns the port activity this caused for each destination
+    /// partition.
+    ///
+    /// All of these ports are swapped in this single call, before any other
+    /// partition runs again. Destinations thus see the messages a partition
+    /// wrote within a window together, which the snapshots of the partition
+    /// library rely on.
+    ///
+    /// Messages the partition corrupted are dropped and raise an error of the
+    /// partition.
+    fn swap(
+        &mut self,
+        transfer: Transfer,
+        sampling_channels: &mut HashMap<String, Sampling>,
+        queuing_channels: &mut HashMap<String, Queuing>,
+        tracer: &mut Tracer,
+    ) -> LeveledResult<HashMap<String, PortActivity>>;
+
+    fn notify_port_activity(&self, activity: PortActivity);
+}
+
+impl SchedulablePartition for Partition {
+    fn name(&self) -> &str {
+        Partition::name(self)
+    }
+
+    fn mode(&mut self) -> OperatingMode {
+        self.get_base_run().1.mode()
+    }
+
+    fn aperiodic_reserve(&self) -> Duration {
+        Partition::aperiodic_reserve(self)
+    }
+
+    fn run_window(
+        &mut self,
+        deadline: Deadline,
+        periodic_deadline: Deadline,
+        release_periodic: bool,
+        sampling_channels: &mut HashMap<String, Sampling>,
+        queuing_channels: &mut HashMap<String, Queuing>,
+        tracer: &mut Tracer,
+    ) -> LeveledResult<()> {
+        PartitionTimeframeScheduler {
+            partition: self,
+            deadline,
+            periodic_deadline,
+            sampling_channels,
+            queuing_channels,
+            tracer,
+        }
+        .run(release_periodic)
+    }
+
+    fn swap(
+        &mut self,
+        transfer: Transfer,
+        sampling_channels: &mut HashMap<String, Sampling>,
+        queuing_channels: &mut HashMap<String, Queuing>,
+        tracer: &mut Tracer,
+    ) -> LeveledResult<HashMap<String, PortActivity>> {
+        self.run_post_timeframe(transfer, sampling_channels, queuing_channels, tracer)
+    }
+
+    fn notify_port_activity(&self, activity: PortActivity) {
+        Partition::notify_port_activity(self, activity)
+    }
+}
+
+/// The action executed by a single scheduling step
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// A new major frame started
+    FrameStart,
+    /// The window of `partition` ran until its end. The processes of an
+    /// `idle` partition are not run at all.
+    Window { partition: PartitionId, idle: bool },
+    /// The source ports of `partition` were swapped after its window. After
+    /// the last window of the major frame, the channels transferred at the
+    /// frame boundary of all partitions were swapped as well, ordered by the
+    /// id of their source partition and the name of their port.
+    Swap { partition: PartitionId },
+    /// The configured run-time of the hypervisor was reached
+    Terminate,
+}
+
+/// Description of a single scheduling step
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Step {
+    pub action: Action,
+    /// Number of the major frame the action belonged to, starting at zero
+    pub frame: u64,
+    /// Module time at which the next step is due
+    pub next_deadline: ModuleTime,
+}
+
+/// The position of the [Scheduler] in the schedule
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// No step was executed yet
+    Unstarted,
+    /// The point of the timeline at this index is next
+    At(usize),
+    Terminated,
+}
 
 /// A scheduler that schedules the execution timeframes of partition according
-/// to a given [PartitionSchedule]. By calling [Scheduler::run_major_frame] a
-/// single major frame can be run.
+/// to a given [PartitionSchedule]. Each [Scheduler::step] executes a single
+/// scheduling action, leaving the waiting for its deadline to the caller.
 pub(crate) struct Scheduler {
     schedule: PartitionSchedule,
+    /// The [PartitionSchedule::timeline] of every major frame
+    timeline: Vec<(Duration, Point)>,
+    major_frame: Duration,
+    terminate_after: Option<Duration>,
+    state: State,
+    /// Start of the first major frame, which is the start of the module
+    t0: MonotonicTime,
+    frame: u64,
+    frame_start: ModuleTime,
 }
 
 impl Scheduler {
-    pub fn new(schedule: PartitionSchedule) -> Self {
-        Self { schedule }
+    pub fn new(
+        schedule: PartitionSchedule,
+        major_frame: Duration,
+        terminate_after: Option<Duration>,
+    ) -> Self {
+        Self {
+            timeline: schedule.timeline(),
+            schedule,
+            major_frame,
+            terminate_after,
+            state: State::Unstarted,
+            t0: MonotonicTime::now(),
+            frame: 0,
+            frame_start: ModuleTime::ZERO,
+        }
+    }
+
+    /// Starts the first major frame at `t0`, which becomes the start of the
+    /// module time
+    pub fn start(&mut self, t0: MonotonicTime) {
+        self.state = State::At(0);
+        self.t0 = t0;
+        self.frame = 0;
+        self.frame_start = ModuleTime::ZERO;
+    }
+
+    /// The current module time
+    pub fn now(&self) -> ModuleTime {
+        ModuleTime::now(self.t0)
+    }
+
+    /// The module time of `time`
+    pub fn module_time(&self, time: MonotonicTime) -> ModuleTime {
+        ModuleTime::at(time, self.t0)
     }
+
+    pub fn is_started(&self) -> bool {
+        self.state != State::Unstarted
+    }
+
+    /// Extends the duration limit by `by`, returning the new limit
+    ///
+    /// `None` if the run is not limited or already terminated.
+    pub fn extend(&mut self, by: Duration) -> Option<Duration> {
+        if self.state == State::Terminated {
+            return None;
+        }
+        let limit = self.terminate_after.as_mut()?;
+        *limit = limit.saturating_add(by);
+        Some(*limit)
+    }
+
+    /// Executes the next scheduling action, starting the first major frame
+    /// now if [Scheduler::start] was not called before.
+    ///
     /// Takes &mut self for now because P4 limits scheduling to a single core
-    pub fn run_major_frame(
+    pub fn step<P: SchedulablePartition>(
         &mut self,
-        current_frame_start: Instant,
-        partitions: &mut HashMap<PartitionId, Partition>,
+        partitions: &mut HashMap<PartitionId, P>,
         sampling_channels_by_name: &mut HashMap<String, Sampling>,
         queuing_channels_by_name: &mut HashMap<String, Queuing>,
-    ) -> LeveledResult<()> {
-        for timeframe in self.schedule.iter() {
-            sleep(
-                timeframe
-                    .start
-                    .saturating_sub(current_frame_start.elapsed()),
-            );
+        tracer: &mut Tracer,
+    ) -> LeveledResult<Step> {
+        if !self.is_started() {
+            self.start(MonotonicTime::now());
+        }
+        let frame = self.frame;
+
+        let (action, next_deadline) = match self.state {
+            State::Unstarted | State::Terminated => (Action::Terminate, self.now()),
+            State::At(k) => {
+                let action = match self.timeline[k].1 {
+                    Point::FrameStart => {
+                        let elapsed = self.frame_start.as_duration();
+                        if self.terminate_after.is_some_and(|limit| elapsed >= limit) {
+                            self.state = State::Terminated;
+                            return Ok(Step {
+                                action: Action::Terminate,
+                                frame,
+                                next_deadline: self.now(),
+                            });
+                        }
+                        Action::FrameStart
+                    }
+                    Point::WindowStart(i) => self.run_window(
+                        i,
+                        partitions,
+                        sampling_channels_by_name,
+                        queuing_channels_by_name,
+                        tracer,
+                    )?,
+                    Point::Swap(i) => self.swap(
+                        i,
+                        partitions,
+                        sampling_channels_by_name,
+                        queuing_channels_by_name,
+                        tracer,
+                    )?,
+                };
+                (action, self.advance(k, tracer))
+            }
+        };
+
+        Ok(Step {
+            action,
+            frame,
+            next_deadline,
+        })
+    }
 
-            let timeframe_timeout = Timeout::new(current_frame_start, timeframe.end);
-            let partition = partitions
-                .get_mut(&timeframe.partition)
-                .expect("partition to exist because its name comes from `timeframe`");
-            PartitionTimeframeScheduler::new(partition, timeframe_timeout).run()?;
+    /// Runs the window of the timeframe at index `i` until its end
+    fn run_window<P: SchedulablePartition>(
+        &self,
+        i: usize,
+        partitions: &mut HashMap<PartitionId, P>,
+        sampling_channels_by_name: &mut HashMap<String, Sampling>,
+        queuing_channels_by_name: &mut HashMap<String, Queuing>,
+        tracer: &mut Tracer,
+    ) -> LeveledResult<Action> {
+        let schedule_start = Instant::now();
+        let timeframe = &self.schedule.timeframes[i];
+        let partition = partitions
+            .get_mut(&timeframe.partition)
+            .expect("partition to exist because its name comes from `timeframe`");
+        let end = self.frame_start + timeframe.end;
 
-            partition.run_post_timeframe(sampling_channels_by_name, queuing_channels_by_name);
+        let idle = partition.mode() == OperatingMode::Idle;
+        if idle {
+            trace!("Partition is IDLE, waiting till the end of the partition time window");
+        } else {
+            let deadline = Deadline::at(end.to_monotonic(self.t0));
+            // The periodic process may only use the window up to the aperiodic reserve
+            let window_start = (self.frame_start + timeframe.start).to_monotonic(self.t0);
+            let periodic_deadline =
+                deadline.before_reserve(window_start, partition.aperiodic_reserve());
+            let release = timeframe.releases_in(self.frame);
+            tracer.record_since(Lane::Hypervisor, Activity::Schedule, schedule_start);
+            partition.run_window(
+                deadline,
+                periodic_deadline,
+                release,
+                sampling_channels_by_name,
+                queuing_channels_by_name,
+                tracer,
+            )?;
         }
 
-        Ok(())
+        Ok(Action::Window {
+            partition: timeframe.partition,
+            idle,
+        })
+    }
+
+    /// Swaps the source ports of the partition of the timeframe at index `i`,
+    /// and the channels transferred at the frame boundary after the last one
+    fn swap<P: SchedulablePartition>(
+        &self,
+        i: usize,
+        partitions: &mut HashMap<PartitionId, P>,
+        sampling_channels_by_name: &mut HashMap<String, Sampling>,
+        queuing_channels_by_name: &mut HashMap<String, Queuing>,
+        tracer: &mut Tracer,
+    ) -> LeveledResult<Action> {
+        let id = self.schedule.timeframes[i].partition;
+        let partition = partitions
+            .get_mut(&id)
+            .expect("partition to exist because its name comes from `timeframe`");
+
+        let post_timeframe_start = Instant::now();
+        let mut activity = partition.swap(
+            Transfer::AfterSourceWindow,
+            sampling_channels_by_name,
+            queuing_channels_by_name,
+            tracer,
+        )?;
+        if i + 1 == self.schedule.timeframes.len() {
+            for id in partitions.keys().copied().sorted() {
+                let frame_activity = partitions.get_mut(&id).unwrap().swap(
+                    Transfer::FrameBoundary,
+                    sampling_channels_by_name,
+                    queuing_channels_by_name,
+                    tracer,
+                )?;
+                for (name, a) in frame_activity {
+                    let entry = activity.entry(name).or_default();
+                    entry.sampling |= a.sampling;
+                    entry.queuing |= a.queuing;
+                }
+            }
+        }
+        tracer.record_since(
+            Lane::Hypervisor,
+            Activity::PostTimeframe,
+            post_timeframe_start,
+        );
+        for partition in partitions.values() {
+            if let Some(activity) = activity.get(partition.name()) {
+                partition.notify_port_activity(*activity);
+            }
+        }
+
+        Ok(Action::Swap { partition: id })
+    }
+
+    /// Moves on from the point of the timeline at index `k`, returning the
+    /// time the next one is due
+    fn advance(&mut self, k: usize, tracer: &mut Tracer) -> ModuleTime {
+        match self.timeline.get(k + 1) {
+            Some((offset, _)) => {
+                self.state = State::At(k + 1);
+                self.frame_start + *offset
+            }
+            None => self.next_frame(tracer),
+        }
+    }
+
+    /// Moves on to the next major frame, returning its start
+    fn next_frame(&mut self, tracer: &mut Tracer) -> ModuleTime {
+        tracer.end_frame();
+        self.state = State::At(0);
+        self.frame += 1;
+        self.frame_start += self.major_frame;
+        self.frame_start
     }
 }
 
 /// A scheduler for a single partition timeframe
 struct PartitionTimeframeScheduler<'a> {
     partition: &'a mut Partition,
-    timeout: Timeout,
+    deadline: Deadline,
+    /// Deadline of the periodic phase, which ends before `deadline` if the
+    /// partition reserves time for its aperiodic process
+    periodic_deadline: Deadline,
+    sampling_channels: &'a mut HashMap<String, Sampling>,
+    queuing_channels: &'a mut HashMap<String, Queuing>,
+    tracer: &'a mut Tracer,
 }
 
-impl<'a> PartitionTimeframeScheduler<'a> {
-    fn new(partition: &'a mut Partition, timeout: Timeout) -> Self {
-        Self { partition, timeout }
+impl PartitionTimeframeScheduler<'_> {
+    /// Records an activity of the partition which just ended, adding the
+    /// time of its processes to the split of its windows
+    fn trace(&mut self, activity: Activity, start: Instant) {
+        let lane = Lane::Partition(self.partition.id());
+        self.tracer.record_since(lane, activity, start);
+        let (_, telemetry) = self.partition.telemetry();
+        match activity {
+            Activity::Periodic => telemetry.record_periodic(start.elapsed()),
+            Activity::Aperiodic => telemetry.record_aperiodic(start.elapsed()),
+            _ => {}
+        }
     }
 
-    fn run(&mut self) -> LeveledResult<()> {
+    fn run(&mut self, release_periodic: bool) -> LeveledResult<()> {
         // Stop if the time is already over
-        if !self.timeout.has_time_left() {
+        if self.deadline.has_passed() {
             return Ok(());
         }
 
-        if let OperatingMode::Idle = self.partition.get_base_run().1.mode() {
-            trace!("Partition is IDLE, waiting till the end of the partition time window");
-            std::thread::sleep(self.timeout.remaining_time());
-            return Ok(());
+        let mode = self.partition.get_base_run().1.mode();
+        // A periodic process started with a delay stays frozen until its
+        // release, leaving the window to the aperiodic process meanwhile. It
+        // misses the window if the release falls behind the periodic phase.
+        let held = if mode == OperatingMode::Normal && release_periodic {
+            self.partition.periodic_release()
+        } else {
+            None
+        };
+        if let Some(release) = held {
+            let aperiodic_start = Instant::now();
+            let res = self.partition.run_aperiodic_process(
+                release.min(self.periodic_deadline),
+                self.sampling_channels,
+                self.queuing_channels,
+            );
+            self.trace(Activity::Aperiodic, aperiodic_start);
+            self.handle_partition_result(res)?;
+            // Returns early without an aperiodic process
+            release.min(self.periodic_deadline).sleep();
         }
+        let release_periodic =
+            release_periodic && held.is_none_or(|release| release < self.periodic_deadline);
 
         // If we are in the normal mode at the beginning of the time frame,
-        // only then we may schedule the periodic process inside a partition
-        if let OperatingMode::Normal = self.partition.get_base_run().1.mode() {
-            let res = self.partition.run_periodic_process(self.timeout);
-            if self.handle_partition_result(res)? == Some(false) {
-                // Periodic process was not run -> run aperiodic process
-                let res = self.partition.run_aperiodic_process(self.timeout);
-                if self.handle_partition_result(res)? == Some(false) {
-                    // Aperiodic process was also not run
-                    let part_name = self.partition.name();
-                    warn!("partition {part_name}: no process is scheduled")
+        // only then we may schedule the periodic process inside a partition.
+        // Partitions with a period spanning several major frames release it
+        // in some of their windows only.
+        let mode = self.partition.get_base_run().1.mode();
+        if mode == OperatingMode::Normal && release_periodic {
+            let periodic_start = Instant::now();
+            let res = self.partition.run_periodic_process(
+                self.periodic_deadline,
+                self.sampling_channels,
+                self.queuing_channels,
+            );
+            self.trace(Activity::Periodic, periodic_start);
+            match self.handle_partition_result(res)? {
+                Some(false) => {
+                    // Periodic process was not run -> run aperiodic process
+                    let aperiodic_start = Instant::now();
+                    let res = self.partition.run_aperiodic_process(
+                        self.deadline,
+                        self.sampling_channels,
+                        self.queuing_channels,
+                    );
+                    self.trace(Activity::Aperiodic, aperiodic_start);
+                    if self.handle_partition_result(res)? == Some(false) {
+                        // Aperiodic process was also not run
+                        let part_name = self.partition.name();
+                        warn!("partition {part_name}: no process is scheduled")
+                    }
+                }
+                Some(true) => {
+                    // The periodic process used up its share, but the window still holds the
+                    // aperiodic reserve
+                    if self.periodic_deadline.has_passed() && !self.deadline.has_passed() {
+                        let res = self.partition.stop_periodic_overrun();
+                        self.handle_partition_result(res)?;
+                    }
+                    debug!(
+                        "partition {}: periodic phase took {:?}, {:?} left for aperiodic phase",
+                        self.partition.name(),
+                        periodic_start.elapsed(),
+                        self.deadline.remaining()
+                    );
                 }
+                None => {}
             }
         }
 
         // Only continue if we have time left
-        if self.timeout.has_time_left() {
+        if !self.deadline.has_passed() {
+            let activity = match self.partition.get_base_run().1.mode() {
+                OperatingMode::ColdStart | OperatingMode::WarmStart => Activity::Start,
+                OperatingMode::Normal | OperatingMode::Idle => Activity::Aperiodic,
+            };
+            let post_periodic_start = Instant::now();
             let res = self.run_post_periodic();
+            self.trace(activity, post_periodic_start);
             self.handle_partition_result(res)?;
         } else if self.partition.get_base_run().1.periodic_running() {
             warn!(
@@ -109,15 +519,20 @@ impl<'a> PartitionTimeframeScheduler<'a> {
         // if we are in the idle mode, just sleep until the end of the frame
         match self.partition.get_base_run().1.mode() {
             OperatingMode::Idle => {
-                sleep(self.timeout.remaining_time());
+                self.deadline.sleep();
                 Ok(())
             }
-            mode @ OperatingMode::ColdStart | mode @ OperatingMode::WarmStart => self
-                .partition
-                .run_start(self.timeout, mode == OperatingMode::WarmStart),
+            mode @ OperatingMode::ColdStart | mode @ OperatingMode::WarmStart => {
+                self.partition.run_start(
+                    self.deadline,
+                    mode == OperatingMode::WarmStart,
+                    self.sampling_channels,
+                    self.queuing_channels,
+                )
+            }
             OperatingMode::Normal => self
                 .partition
-                .run_aperiodic_process(self.timeout)
+                .run_aperiodic_process(self.deadline, self.sampling_channels, self.queuing_channels)
                 .map(|_| ()),
         }
     }
@@ -127,7 +542,410 @@ impl<'a> PartitionTimeframeScheduler<'a> {
     /// `Ok(None)`. In case of `Ok(_)` the contained value is returned as
     /// `Ok(Some(_))`.
     fn handle_partition_result<T>(&mut self, res: TypedResult<T>) -> LeveledResult<Option<T>> {
-        res.map(Some)
-            .or_else(|err| self.partition.handle_error(err).map(|_| None))
+        res.map(Some).or_else(|err| {
+            self.partition
+                .handle_error(err, self.sampling_channels, self.queuing_channels)
+                .map(|_| None)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::os::fd::AsRawFd;
+
+    use a653rs_linux_core::channel::{
+        Criticality, OnPartitionRestart, PortConfig, SamplingChannelConfig,
+    };
+    use a653rs_linux_core::sampling::{SamplingDestination, SamplingSource};
+    use bytesize::ByteSize;
+
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    struct MockPartition {
+        name: String,
+        mode: OperatingMode,
+        windows: usize,
+        /// Number of windows in which the periodic process was released
+        releases: usize,
+        /// Number of swaps after the windows of the partition
+        swaps: usize,
+        /// Sampling channels with their source port in this partition
+        sources: Vec<String>,
+    }
+
+    impl MockPartition {
+        fn new(name: &str, mode: OperatingMode) -> Self {
+            Self {
+                name: name.to_string(),
+                mode,
+                windows: 0,
+                releases: 0,
+                swaps: 0,
+                sources: Vec::new(),
+            }
+        }
+    }
+
+    impl SchedulablePartition for MockPartition {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn mode(&mut self) -> OperatingMode {
+            self.mode
+        }
+
+        fn aperiodic_reserve(&self) -> Duration {
+            Duration::ZERO
+        }
+
+        fn run_window(
+            &mut self,
+            _: Deadline,
+            _: Deadline,
+            release_periodic: bool,
+            _: &mut HashMap<String, Sampling>,
+            _: &mut HashMap<String, Queuing>,
+            _: &mut Tracer,
+        ) -> LeveledResult<()> {
+            self.windows += 1;
+            self.releases += usize::from(release_periodic);
+            Ok(())
+        }
+
+        fn swap(
+            &mut self,
+            transfer: Transfer,
+            sampling_channels: &mut HashMap<String, Sampling>,
+            _: &mut HashMap<String, Queuing>,
+            _: &mut Tracer,
+        ) -> LeveledResult<HashMap<String, PortActivity>> {
+            if transfer == Transfer::AfterSourceWindow {
+                self.swaps += 1;
+            }
+            let mut activity = HashMap::<String, PortActivity>::new();
+            for name in &self.sources {
+                let channel = sampling_channels.get_mut(name).unwrap();
+                if channel.transfer() == transfer && channel.swap() {
+                    for partition in channel.destination_partitions() {
+                        activity.entry(partition.to_string()).or_default().sampling = true;
+                    }
+                }
+            }
+            Ok(activity)
+        }
+
+        fn notify_port_activity(&self, _: PortActivity) {}
+    }
+
+    /// Partition 0 runs from 0ms to 20ms and partition 1 from 50ms to 80ms of
+    /// a 100ms major frame
+    fn test_scheduler(terminate_after: Option<Duration>) -> Scheduler {
+        let schedule = PartitionSchedule::from_timeframes(vec![
+            ScheduledTimeframe {
+                partition: 1,
+                start: 50 * MS,
+                end: 80 * MS,
+                release_every: 1,
+            },
+            ScheduledTimeframe {
+                partition: 0,
+                start: Duration::ZERO,
+                end: 20 * MS,
+                release_every: 1,
+            },
+        ])
+        .unwrap();
+        Scheduler::new(schedule, 100 * MS, terminate_after)
+    }
+
+    fn mock_partitions(mode_of_1: OperatingMode) -> HashMap<PartitionId, MockPartition> {
+        HashMap::from([
+            (0, MockPartition::new("p0", OperatingMode::Normal)),
+            (1, MockPartition::new("p1", mode_of_1)),
+        ])
+    }
+
+    fn run_steps(
+        scheduler: &mut Scheduler,
+        partitions: &mut HashMap<PartitionId, MockPartition>,
+        n: usize,
+    ) -> Vec<Step> {
+        run_steps_with_channels(scheduler, partitions, &mut HashMap::new(), n)
+    }
+
+    fn run_steps_with_channels(
+        scheduler: &mut Scheduler,
+        partitions: &mut HashMap<PartitionId, MockPartition>,
+        sampling_channels: &mut HashMap<String, Sampling>,
+        n: usize,
+    ) -> Vec<Step> {
+        let mut tracer = Tracer::disabled();
+        (0..n)
+            .map(|_| {
+                scheduler
+                    .step(
+                        partitions,
+                        sampling_channels,
+                        &mut HashMap::new(),
+                        &mut tracer,
+                    )
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn windows_in_order() {
+        let mut scheduler = test_scheduler(None);
+        let mut partitions = mock_partitions(OperatingMode::Normal);
+        scheduler.start(MonotonicTime::now());
+
+        let steps = run_steps(&mut scheduler, &mut partitions, 7);
+        let expected = [
+            (Action::FrameStart, 0, 0),
+            (
+                Action::Window {
+                    partition: 0,
+                    idle: false,
+                },
+                0,
+                20,
+            ),
+            (Action::Swap { partition: 0 }, 0, 50),
+            (
+                Action::Window {
+                    partition: 1,
+                    idle: false,
+                },
+                0,
+                80,
+            ),
+            (Action::Swap { partition: 1 }, 0, 100),
+            (Action::FrameStart, 1, 100),
+            (
+                Action::Window {
+                    partition: 0,
+                    idle: false,
+                },
+                1,
+                120,
+            ),
+        ];
+        for (step, (action, frame, deadline)) in steps.into_iter().zip(expected) {
+            assert_eq!(
+                step,
+                Step {
+                    action,
+                    frame,
+                    next_deadline: ModuleTime::from(deadline * MS),
+                }
+            );
+        }
+        assert_eq!(partitions[&0].windows, 2);
+        assert_eq!(partitions[&1].windows, 1);
+    }
+
+    #[test]
+    fn periods_spanning_major_frames() {
+        let mut scheduler = test_scheduler(None);
+        // Partition 0 has a period of three major frames
+        scheduler.schedule.timeframes[0].release_every = 3;
+        let mut partitions = mock_partitions(OperatingMode::Normal);
+        scheduler.start(MonotonicTime::now());
+
+        // Four major frames of five steps each
+        let steps = run_steps(&mut scheduler, &mut partitions, 20);
+        assert_eq!(steps[19].frame, 3);
+        // The windows are run in every major frame, the periodic process is only
+        // released in the first and the fourth
+        assert_eq!(partitions[&0].windows, 4);
+        assert_eq!(partitions[&0].releases, 2);
+        assert_eq!(partitions[&1].windows, 4);
+        assert_eq!(partitions[&1].releases, 4);
+    }
+
+    #[test]
+    fn idle_partitions_are_not_run() {
+        let mut scheduler = test_scheduler(None);
+        let mut partitions = mock_partitions(OperatingMode::Idle);
+        scheduler.start(MonotonicTime::now());
+
+        let steps = run_steps(&mut scheduler, &mut partitions, 5);
+        // The idle window still lasts until its end, followed by the swap
+        assert_eq!(
+            steps[3].action,
+            Action::Window {
+                partition: 1,
+                idle: true
+            }
+        );
+        assert_eq!(steps[3].next_deadline, ModuleTime::from(80 * MS));
+        assert_eq!(steps[4].action, Action::Swap { partition: 1 });
+        assert_eq!(partitions[&1].windows, 0);
+        assert_eq!(partitions[&1].swaps, 1);
+        assert_eq!(partitions[&0].windows, 1);
+    }
+
+    #[test]
+    fn duration_limit() {
+        let mut scheduler = test_scheduler(Some(Duration::ZERO));
+        let steps = run_steps(
+            &mut scheduler,
+            &mut mock_partitions(OperatingMode::Normal),
+            2,
+        );
+        assert!(steps.iter().all(|s| s.action == Action::Terminate));
+
+        // Frames starting at 0ms, 100ms and 200ms are run completely
+        let mut scheduler = test_scheduler(Some(250 * MS));
+        let mut partitions = mock_partitions(OperatingMode::Normal);
+        let steps = run_steps(&mut scheduler, &mut partitions, 16);
+        assert_eq!(steps[14].action, Action::Swap { partition: 1 });
+        assert_eq!(steps[14].frame, 2);
+        assert_eq!(steps[15].action, Action::Terminate);
+        assert_eq!(steps[15].frame, 3);
+        assert_eq!(partitions[&1].swaps, 3);
+
+        let more = run_steps(&mut scheduler, &mut partitions, 1);
+        assert_eq!(more[0].action, Action::Terminate);
+    }
+
+    #[test]
+    fn extended_duration_limit() {
+        assert_eq!(test_scheduler(None).extend(100 * MS), None);
+
+        let mut scheduler = test_scheduler(Some(50 * MS));
+        let mut partitions = mock_partitions(OperatingMode::Normal);
+        let steps = run_steps(&mut scheduler, &mut partitions, 5);
+        assert_eq!(steps[4].action, Action::Swap { partition: 1 });
+
+        // The frame starting at 100ms is run as well
+        assert_eq!(scheduler.extend(100 * MS), Some(150 * MS));
+        let steps = run_steps(&mut scheduler, &mut partitions, 6);
+        assert_eq!(steps[4].action, Action::Swap { partition: 1 });
+        assert_eq!(steps[4].frame, 1);
+        assert_eq!(steps[5].action, Action::Terminate);
+
+        // A terminated run stays terminated
+        assert_eq!(scheduler.extend(100 * MS), None);
+    }
+
+    #[test]
+    fn transfer_points() {
+        // A sampling channel from p0 to p1, which runs later in the same frame
+        for (transfer, seen_in_same_frame) in [
+            (Transfer::AfterSourceWindow, true),
+            (Transfer::FrameBoundary, false),
+        ] {
+            let channel = Sampling::try_from(SamplingChannelConfig {
+                msg_size: ByteSize::b(8),
+                source: PortConfig {
+                    partition: "p0".into(),
+                    port: "out".into(),
+                },
+                destination: HashSet::from([PortConfig {
+                    partition: "p1".into(),
+                    port: "in".into(),
+                }]),
+                on_partition_restart: OnPartitionRestart::Keep,
+                refresh_period: None,
+                sequenced: false,
+                transfer,
+                zeroize: false,
+                history: None,
+                criticality: Criticality::Partition,
+            })
+            .unwrap();
+            let mut source = SamplingSource::try_from(channel.source_fd().as_raw_fd()).unwrap();
+            let mut destination =
+                SamplingDestination::try_from(channel.destination_fd().as_raw_fd()).unwrap();
+            let mut channels = HashMap::from([("out".to_string(), channel)]);
+
+            let mut scheduler = test_scheduler(None);
+            let mut partitions = mock_partitions(OperatingMode::Normal);
+            partitions.get_mut(&0).unwrap().sources.push("out".into());
+            let mut buf = [0; 8];
+
+            // Frame start and the window of p0, which writes a message
+            run_steps_with_channels(&mut scheduler, &mut partitions, &mut channels, 2);
+            source.write(b"frame #0");
+            // Swap after p0 and the window of p1
+            run_steps_with_channels(&mut scheduler, &mut partitions, &mut channels, 2);
+            let len = destination.read(&mut buf).0;
+            assert_eq!(len == 8, seen_in_same_frame, "{transfer:?}");
+
+            // Every channel has been transferred by the start of the next frame
+            run_steps_with_channels(&mut scheduler, &mut partitions, &mut channels, 1);
+            assert_eq!(destination.read(&mut buf).0, 8, "{transfer:?}");
+            assert_eq!(&buf, b"frame #0");
+        }
+    }
+
+    #[test]
+    fn channels_of_a_partition_are_swapped_together() {
+        // Three sequenced sampling channels from p0 to p1, forming one snapshot
+        let names = ["level", "flow", "tick"];
+        let mut channels = HashMap::new();
+        let mut ports = Vec::new();
+        for name in names {
+            let channel = Sampling::try_from(SamplingChannelConfig {
+                msg_size: ByteSize::b(8),
+                source: PortConfig {
+                    partition: "p0".into(),
+                    port: name.into(),
+                },
+                destination: HashSet::from([PortConfig {
+                    partition: "p1".into(),
+                    port: name.into(),
+                }]),
+                on_partition_restart: OnPartitionRestart::Keep,
+                refresh_period: None,
+                sequenced: true,
+                transfer: Transfer::AfterSourceWindow,
+                zeroize: false,
+                history: None,
+                criticality: Criticality::Partition,
+            })
+            .unwrap();
+            ports.push((
+                SamplingSource::try_from(channel.source_fd().as_raw_fd()).unwrap(),
+                SamplingDestination::try_from(channel.destination_fd().as_raw_fd()).unwrap(),
+            ));
+            channels.insert(name.to_string(), channel);
+        }
+
+        let mut scheduler = test_scheduler(None);
+        let mut partitions = mock_partitions(OperatingMode::Normal);
+        partitions.get_mut(&0).unwrap().sources = names.map(String::from).to_vec();
+        let mut buf = [0; 8];
+
+        for frame in 1..=3u64 {
+            // Frame start and the window of p0, which writes all ports
+            run_steps_with_channels(&mut scheduler, &mut partitions, &mut channels, 2);
+            for (source, _) in &mut ports {
+                source.write(&frame.to_le_bytes());
+            }
+            // Until the swap, p1 sees the messages of the previous frame
+            for (_, destination) in &mut ports {
+                assert_eq!(destination.read_sequenced(&mut buf).2, frame - 1);
+            }
+
+            // A single swap after the window of p0 transfers all of them
+            let steps = run_steps_with_channels(&mut scheduler, &mut partitions, &mut channels, 1);
+            assert_eq!(steps[0].action, Action::Swap { partition: 0 });
+            for (_, destination) in &mut ports {
+                let (len, _, seq) = destination.read_sequenced(&mut buf);
+                assert_eq!((len, seq), (8, frame));
+                assert_eq!(buf, frame.to_le_bytes());
+            }
+
+            // The window of p1 and the swap after it
+            run_steps_with_channels(&mut scheduler, &mut partitions, &mut channels, 2);
+        }
     }
 }
@@ -32,6 +32,30 @@ impl PartitionSchedule {
     pub fn iter(&self) -> impl Iterator<Item = &ScheduledTimeframe> {
         self.timeframes.iter()
     }
+
+    /// The points of a major frame in the order the scheduler acts on them,
+    /// each with its offset from the start of the frame
+    pub fn timeline(&self) -> Vec<(Duration, Point)> {
+        let mut timeline = vec![(Duration::ZERO, Point::FrameStart)];
+        for (i, timeframe) in self.timeframes.iter().enumerate() {
+            timeline.push((timeframe.start, Point::WindowStart(i)));
+            timeline.push((timeframe.end, Point::Swap(i)));
+        }
+        timeline
+    }
+}
+
+/// A point of the major frame at which the scheduler acts
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Point {
+    /// Start of the major frame
+    FrameStart,
+    /// Start of the window of the timeframe at this index
+    WindowStart(usize),
+    /// End of the window of the timeframe at this index, where the source
+    /// ports of its partition are swapped. After the last window, the channels
+    /// transferred at the frame boundary are swapped as well.
+    Swap(usize),
 }
 
 /// A timeframe inside of a major frame.
@@ -42,6 +66,20 @@ pub(crate) struct ScheduledTimeframe {
     pub partition: PartitionId,
     pub start: Duration,
     pub end: Duration,
+    /// The periodic process of the partition is released in every
+    /// `release_every`-th major frame only, starting with the first one
+    ///
+    /// This is 1 unless the period of the partition spans several major
+    /// frames.
+    pub release_every: u64,
+}
+
+impl ScheduledTimeframe {
+    /// Whether the periodic process of the partition is released in this
+    /// timeframe of major frame `frame`
+    pub fn releases_in(&self, frame: u64) -> bool {
+        frame.is_multiple_of(self.release_every)
+    }
 }
 
 impl PartialEq for ScheduledTimeframe {
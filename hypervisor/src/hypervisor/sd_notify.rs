@@ -0,0 +1,259 @@
+//! Notifications of the service manager, see `sd_notify(3)`
+//!
+//! Run as a systemd service of `Type=notify` or `Type=notify-reload`, the
+//! hypervisor reports itself ready once the module completed its first major
+//! frame, so that units ordered after it only start once the partitions run.
+//! It then updates its status every few seconds, reports reloads of the
+//! configuration through `SIGHUP` and its shutdown.
+//!
+//! The messages are sent to the datagram socket in `$NOTIFY_SOCKET` directly,
+//! without linking libsystemd. Without the variable, nothing is sent.
+
+use std::ffi::OsStr;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::{Duration, Instant};
+
+use nix::time::{clock_gettime, ClockId};
+
+/// Variable holding the socket of the service manager
+pub const NOTIFY_SOCKET: &str = "NOTIFY_SOCKET";
+
+/// Time between two status updates
+const STATUS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Sends notifications to the service manager, if there is one
+#[derive(Debug)]
+pub struct Notifier {
+    target: Option<(UnixDatagram, SocketAddr)>,
+    /// Whether the readiness was reported since the start or the last reload
+    ready: bool,
+    last_status: Option<Instant>,
+    status_interval: Duration,
+}
+
+impl Notifier {
+    /// Notifies the socket in `$NOTIFY_SOCKET`, if set
+    pub fn from_env() -> Self {
+        Self::new(std::env::var_os(NOTIFY_SOCKET).as_deref())
+    }
+
+    /// Notifies the socket `socket`, given like in `$NOTIFY_SOCKET`: either a
+    /// path, or the name of an abstract socket prefixed with `@`
+    fn new(socket: Option<&OsStr>) -> Self {
+        let target = socket
+            .filter(|socket| !socket.is_empty())
+            .and_then(|socket| match connect(socket) {
+                Ok(target) => Some(target),
+                Err(e) => {
+                    warn!("Not notifying the service manager at {socket:?}: {e}");
+                    None
+                }
+            });
+        Self {
+            target,
+            ready: false,
+            last_status: None,
+            status_interval: STATUS_INTERVAL,
+        }
+    }
+
+    /// Reports the start of the major frame `frame`, counted from zero
+    ///
+    /// The module is reported ready at the start of the second frame, and its
+    /// status, which `status` describes, every few seconds afterwards.
+    pub fn frame_started(&mut self, frame: u64, status: impl FnOnce() -> String) {
+        if self.target.is_none() || frame == 0 {
+            return;
+        }
+        let due = match self.last_status {
+            Some(last) => last.elapsed() >= self.status_interval,
+            None => true,
+        };
+        if !self.ready {
+            self.send(&format!("READY=1\nSTATUS={}", status()));
+            self.ready = true;
+        } else if due {
+            self.send(&format!("STATUS={}", status()));
+        } else {
+            return;
+        }
+        self.last_status = Some(Instant::now());
+    }
+
+    /// Reports the start of a reload, after which the module is reported
+    /// ready again like after the start
+    pub fn reloading(&mut self) {
+        if self.target.is_none() {
+            return;
+        }
+        // Demanded by Type=notify-reload, to tell reloads apart
+        let usec = clock_gettime(ClockId::CLOCK_MONOTONIC)
+            .map(|now| Duration::from(now).as_micros())
+            .unwrap_or_default();
+        self.send(&format!("RELOADING=1\nMONOTONIC_USEC={usec}"));
+        self.ready = false;
+    }
+
+    /// Reports the end of a reload which kept the running module
+    pub fn reload_failed(&mut self, reason: &str) {
+        if self.target.is_none() {
+            return;
+        }
+        self.send(&format!("READY=1\nSTATUS={reason}"));
+        self.ready = true;
+        self.last_status = Some(Instant::now());
+    }
+
+    /// Reports the start of the shutdown
+    pub fn stopping(&mut self) {
+        self.send("STOPPING=1");
+    }
+
+    fn send(&self, message: &str) {
+        let Some((socket, addr)) = &self.target else {
+            return;
+        };
+        trace!("Notifying the service manager: {message:?}");
+        if let Err(e) = socket.send_to_addr(message.as_bytes(), addr) {
+            warn!("Could not notify the service manager: {e}");
+        }
+    }
+}
+
+/// An unbound socket for sending to the socket `socket`
+fn connect(socket: &OsStr) -> std::io::Result<(UnixDatagram, SocketAddr)> {
+    let addr = match socket.as_bytes() {
+        [b'@', name @ ..] => SocketAddr::from_abstract_name(name)?,
+        _ => SocketAddr::from_pathname(socket)?,
+    };
+    Ok((UnixDatagram::unbound()?, addr))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    use super::*;
+
+    /// The service manager, receiving on a socket in a temporary directory
+    struct Manager {
+        socket: UnixDatagram,
+        _dir: tempfile::TempDir,
+    }
+
+    impl Manager {
+        fn new() -> (Self, Notifier) {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("notify");
+            let socket = UnixDatagram::bind(&path).unwrap();
+            socket.set_nonblocking(true).unwrap();
+            let notifier = Notifier::new(Some(path.as_os_str()));
+            (Self { socket, _dir: dir }, notifier)
+        }
+
+        /// The messages received so far
+        fn received(&self) -> Vec<String> {
+            let mut messages = Vec::new();
+            let mut buf = [0; 256];
+            loop {
+                match self.socket.recv(&mut buf) {
+                    Ok(len) => messages.push(String::from_utf8_lossy(&buf[..len]).into_owned()),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => return messages,
+                    Err(e) => panic!("{e}"),
+                }
+            }
+        }
+    }
+
+    fn status(frame: u64) -> impl FnOnce() -> String {
+        move || format!("frame {frame}, 2/2 partitions NORMAL, 0 HM events")
+    }
+
+    #[test]
+    fn startup() {
+        let (manager, mut notifier) = Manager::new();
+        // Only ready once the first frame is complete
+        notifier.frame_started(0, status(0));
+        assert!(manager.received().is_empty());
+        notifier.frame_started(1, status(1));
+        assert_eq!(
+            manager.received(),
+            ["READY=1\nSTATUS=frame 1, 2/2 partitions NORMAL, 0 HM events"]
+        );
+
+        // The status is only updated every few seconds
+        notifier.frame_started(2, status(2));
+        assert!(manager.received().is_empty());
+        notifier.status_interval = Duration::ZERO;
+        notifier.frame_started(3, status(3));
+        assert_eq!(
+            manager.received(),
+            ["STATUS=frame 3, 2/2 partitions NORMAL, 0 HM events"]
+        );
+    }
+
+    #[test]
+    fn reload() {
+        let (manager, mut notifier) = Manager::new();
+        notifier.frame_started(1, status(1));
+        manager.received();
+
+        notifier.reloading();
+        // The reloaded module starts over
+        notifier.frame_started(0, status(0));
+        notifier.frame_started(1, status(1));
+        let received = manager.received();
+        assert_eq!(received.len(), 2, "{received:?}");
+        let usec = received[0]
+            .strip_prefix("RELOADING=1\nMONOTONIC_USEC=")
+            .unwrap();
+        assert!(usec.parse::<u64>().unwrap() > 0);
+        assert!(received[1].starts_with("READY=1\nSTATUS=frame 1"));
+
+        // A failed reload keeps the running module, which is ready right away
+        notifier.reloading();
+        notifier.reload_failed("reloading failed");
+        notifier.frame_started(2, status(2));
+        let received = manager.received();
+        assert_eq!(received.len(), 2, "{received:?}");
+        assert!(received[0].starts_with("RELOADING=1\n"));
+        assert_eq!(received[1], "READY=1\nSTATUS=reloading failed");
+    }
+
+    #[test]
+    fn shutdown() {
+        let (manager, mut notifier) = Manager::new();
+        notifier.frame_started(1, status(1));
+        notifier.stopping();
+        let received = manager.received();
+        assert_eq!(received.len(), 2, "{received:?}");
+        assert_eq!(received[1], "STOPPING=1");
+    }
+
+    #[test]
+    fn abstract_socket() {
+        let name = format!("a653rs-notify-test-{}", std::process::id());
+        let socket =
+            UnixDatagram::bind_addr(&SocketAddr::from_abstract_name(name.as_bytes()).unwrap())
+                .unwrap();
+        let mut notifier = Notifier::new(Some(OsStr::new(&format!("@{name}"))));
+        notifier.stopping();
+        let mut buf = [0; 16];
+        let len = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"STOPPING=1");
+    }
+
+    #[test]
+    fn without_service_manager() {
+        for socket in [None, Some(OsStr::new(""))] {
+            let mut notifier = Notifier::new(socket);
+            // Nothing to observe but the absence of panics
+            notifier.frame_started(1, || unreachable!("status of a silent notifier"));
+            notifier.reloading();
+            notifier.stopping();
+            assert!(notifier.target.is_none());
+        }
+    }
+}
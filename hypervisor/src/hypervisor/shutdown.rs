@@ -0,0 +1,150 @@
+//! Termination of the hypervisor through `SIGINT` and `SIGTERM`
+//!
+//! The signal handler only records the request. The main loop checks for it
+//! between partition windows and exits regularly, so logging, printing and the
+//! cleanup of the cgroups never happen in signal context, where they could
+//! deadlock on a lock held by the interrupted code.
+//!
+//! Should the main loop not have reacted before another signal arrives within
+//! the [GRACE_PERIOD] of the first one, the handler kills the cgroups of the
+//! partitions and exits immediately. The hypervisor itself is not killed with
+//! them, so that it exits with [FORCED_EXIT_CODE]. A signal arriving after the
+//! grace period starts a new one instead.
+
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+use std::time::Duration;
+
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+
+/// Exit code of a forced exit, as if terminated by `SIGINT`
+const FORCED_EXIT_CODE: libc::c_int = 128 + libc::SIGINT;
+
+/// Time after a termination signal during which another one forces the exit
+const GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// Monotonic time of the first termination signal of the current grace period
+/// in nanoseconds, or zero before any
+static FIRST_SIGNAL: AtomicU64 = AtomicU64::new(0);
+
+/// Paths of the `cgroup.kill` files of the partitions
+///
+/// Prepared outside of the signal handler, as allocating is not
+/// async-signal-safe. Lists replaced by a reload of the configuration are
+/// leaked, as the handler may still be reading them.
+static KILL_FILES: AtomicPtr<Vec<CString>> = AtomicPtr::new(ptr::null_mut());
+
+/// Installs the signal handler for `SIGINT` and `SIGTERM`
+pub(crate) fn install() -> nix::Result<()> {
+    let action = SigAction::new(
+        SigHandler::Handler(handler),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    for signal in [Signal::SIGINT, Signal::SIGTERM] {
+        unsafe { sigaction(signal, &action) }?;
+    }
+    Ok(())
+}
+
+/// Sets the cgroups of the partitions, `partitions` in `root`, which a forced
+/// exit kills
+pub(crate) fn set_cgroups<'a>(root: &Path, partitions: impl IntoIterator<Item = &'a str>) {
+    let kill_files = partitions
+        .into_iter()
+        .map(|partition| {
+            CString::new(
+                root.join(partition)
+                    .join("cgroup.kill")
+                    .as_os_str()
+                    .as_bytes(),
+            )
+            .expect("cgroup path to not contain nul bytes")
+        })
+        .collect();
+    KILL_FILES.store(Box::into_raw(Box::new(kill_files)), Ordering::SeqCst);
+}
+
+/// Whether a termination signal was received
+pub(crate) fn requested() -> bool {
+    FIRST_SIGNAL.load(Ordering::SeqCst) > 0
+}
+
+/// Monotonic time in nanoseconds, which is never zero
+fn now() -> u64 {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut time) };
+    (time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64).max(1)
+}
+
+/// Whether a signal at `now` forces the exit, after the first signal of the
+/// grace period at `first`
+fn forces_exit(first: u64, now: u64) -> bool {
+    first > 0 && now.saturating_sub(first) <= GRACE_PERIOD.as_nanos() as u64
+}
+
+extern "C" fn handler(_: libc::c_int) {
+    // Only async-signal-safe functions may be called in here
+    let now = now();
+    if !forces_exit(FIRST_SIGNAL.load(Ordering::SeqCst), now) {
+        FIRST_SIGNAL.store(now, Ordering::SeqCst);
+        return;
+    }
+
+    let kill_files = KILL_FILES.load(Ordering::SeqCst);
+    if let Some(kill_files) = unsafe { kill_files.as_ref() } {
+        for kill_file in kill_files {
+            unsafe {
+                let fd = libc::open(kill_file.as_ptr(), libc::O_WRONLY);
+                if fd >= 0 {
+                    libc::write(fd, b"1".as_ptr().cast(), 1);
+                    libc::close(fd);
+                }
+            }
+        }
+    }
+    unsafe { libc::_exit(FORCED_EXIT_CODE) }
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::sys::signal::raise;
+
+    use super::*;
+
+    #[test]
+    fn first_signal_is_only_recorded() {
+        install().unwrap();
+        assert!(!requested());
+        raise(Signal::SIGTERM).unwrap();
+        assert!(requested());
+    }
+
+    #[test]
+    fn only_signals_within_the_grace_period_force_the_exit() {
+        let grace = GRACE_PERIOD.as_nanos() as u64;
+        assert!(!forces_exit(0, 1));
+        assert!(forces_exit(1, 1));
+        assert!(forces_exit(1, 1 + grace));
+        assert!(!forces_exit(1, 2 + grace));
+    }
+
+    #[test]
+    fn kill_files_of_partitions() {
+        set_cgroups(Path::new("/sys/fs/cgroup/hv"), ["a", "b"]);
+        let kill_files = unsafe { &*KILL_FILES.load(Ordering::SeqCst) };
+        assert_eq!(
+            kill_files,
+            &[
+                CString::new("/sys/fs/cgroup/hv/a/cgroup.kill").unwrap(),
+                CString::new("/sys/fs/cgroup/hv/b/cgroup.kill").unwrap()
+            ]
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! Sockets passed to the partitions
+//!
+//! Some options only take effect before a socket is connected or bound, so the
+//! sockets are created through [nix] instead of the constructors of the
+//! standard library.
+
+use std::ffi::OsString;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::os::fd::{AsRawFd, OwnedFd};
+
+use a653rs_linux_core::error::{fd_limit_hint, ResultExt, SystemError, TypedResult};
+use anyhow::{anyhow, Context};
+use nix::sys::socket::{
+    self, setsockopt, sockopt, AddressFamily, SockFlag, SockType, SockaddrStorage,
+};
+
+use super::config::SocketOptions;
+
+/// Connects to `address` after applying `options`
+pub(crate) fn connect_tcp(address: &str, options: &SocketOptions) -> TypedResult<TcpStream> {
+    open(address, SockType::Stream, options, |fd, addr| {
+        socket::connect(fd.as_raw_fd(), addr)
+    })
+    .map(TcpStream::from)
+}
+
+/// Binds to `address` after applying `options`
+pub(crate) fn bind_udp(address: &str, options: &SocketOptions) -> TypedResult<UdpSocket> {
+    open(address, SockType::Datagram, options, |fd, addr| {
+        socket::bind(fd.as_raw_fd(), addr)
+    })
+    .map(UdpSocket::from)
+}
+
+/// Tries every address `address` resolves to, like the standard library does
+fn open(
+    address: &str,
+    ty: SockType,
+    options: &SocketOptions,
+    finish: impl Fn(&OwnedFd, &SockaddrStorage) -> nix::Result<()>,
+) -> TypedResult<OwnedFd> {
+    let addrs = address
+        .to_socket_addrs()
+        .with_context(|| format!("failed to resolve {address}"))
+        .typ(SystemError::Config)?;
+
+    let mut last_err = anyhow!("{address} did not resolve to any address");
+    for addr in addrs {
+        let family = match addr {
+            SocketAddr::V4(_) => AddressFamily::Inet,
+            SocketAddr::V6(_) => AddressFamily::Inet6,
+        };
+        let fd = socket::socket(family, ty, SockFlag::SOCK_CLOEXEC, None)
+            .map_err(fd_limit_hint)
+            .with_context(|| format!("failed to create a socket for {addr}"))
+            .typ(SystemError::Panic)?;
+        apply(&fd, options, addr)?;
+
+        match finish(&fd, &SockaddrStorage::from(addr)) {
+            Ok(()) => return Ok(fd),
+            Err(e) => last_err = anyhow!("failed to open a socket for {addr}: {e}"),
+        }
+    }
+    Err(last_err).typ(SystemError::Panic)
+}
+
+/// Sets all `options` which are given, failing on the first one the kernel
+/// rejects
+fn apply(fd: &OwnedFd, options: &SocketOptions, addr: SocketAddr) -> TypedResult<()> {
+    let SocketOptions {
+        nodelay,
+        send_buffer,
+        recv_buffer,
+        reuse_addr,
+        keepalive,
+        bind_to_device,
+    } = options;
+
+    // The sizes are limited by the validation of the configuration
+    let size = |size: bytesize::ByteSize| size.as_u64().min(SocketOptions::MAX_BUFFER) as usize;
+    let set = |name: &str, result: nix::Result<()>| {
+        result
+            .with_context(|| format!("failed to set {name} on the socket for {addr}"))
+            .typ(SystemError::Config)
+    };
+
+    if let Some(nodelay) = nodelay {
+        set("nodelay", setsockopt(fd, sockopt::TcpNoDelay, nodelay))?;
+    }
+    if let Some(send_buffer) = send_buffer {
+        set(
+            "send_buffer",
+            setsockopt(fd, sockopt::SndBuf, &size(*send_buffer)),
+        )?;
+    }
+    if let Some(recv_buffer) = recv_buffer {
+        set(
+            "recv_buffer",
+            setsockopt(fd, sockopt::RcvBuf, &size(*recv_buffer)),
+        )?;
+    }
+    if let Some(reuse_addr) = reuse_addr {
+        set("reuse_addr", setsockopt(fd, sockopt::ReuseAddr, reuse_addr))?;
+    }
+    if let Some(keepalive) = keepalive {
+        set("keepalive", setsockopt(fd, sockopt::KeepAlive, keepalive))?;
+    }
+    if let Some(device) = bind_to_device {
+        set(
+            "bind_to_device",
+            setsockopt(fd, sockopt::BindToDevice, &OsString::from(device)),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use bytesize::ByteSize;
+    use nix::sys::socket::getsockopt;
+
+    use super::*;
+
+    #[test]
+    fn tcp_options_on_loopback() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let stream = connect_tcp(&address, &SocketOptions::default()).unwrap();
+        assert!(!stream.nodelay().unwrap());
+
+        let options = SocketOptions {
+            nodelay: Some(true),
+            send_buffer: Some(ByteSize::kib(32)),
+            recv_buffer: Some(ByteSize::kib(64)),
+            reuse_addr: Some(true),
+            keepalive: Some(true),
+            bind_to_device: None,
+        };
+        let stream = connect_tcp(&address, &options).unwrap();
+        assert!(stream.nodelay().unwrap());
+        // The kernel doubles the requested buffer sizes for its bookkeeping
+        assert!(getsockopt(&stream, sockopt::SndBuf).unwrap() >= 32 * 1024);
+        assert!(getsockopt(&stream, sockopt::RcvBuf).unwrap() >= 64 * 1024);
+        assert!(getsockopt(&stream, sockopt::ReuseAddr).unwrap());
+        assert!(getsockopt(&stream, sockopt::KeepAlive).unwrap());
+        assert_eq!(stream.peer_addr().unwrap(), listener.local_addr().unwrap());
+    }
+
+    #[test]
+    fn udp_options_on_loopback() {
+        let options = SocketOptions {
+            recv_buffer: Some(ByteSize::kib(64)),
+            reuse_addr: Some(true),
+            ..Default::default()
+        };
+        let socket = bind_udp("127.0.0.1:0", &options).unwrap();
+        assert!(getsockopt(&socket, sockopt::RcvBuf).unwrap() >= 64 * 1024);
+        assert!(getsockopt(&socket, sockopt::ReuseAddr).unwrap());
+        assert!(socket.local_addr().unwrap().ip().is_loopback());
+    }
+
+    #[test]
+    fn rejected_options_are_config_errors() {
+        let options = SocketOptions {
+            bind_to_device: Some("nosuchdev0".into()),
+            ..Default::default()
+        };
+        let err = bind_udp("127.0.0.1:0", &options).unwrap_err();
+        assert_eq!(err.err(), SystemError::Config);
+
+        let err = bind_udp("not an address", &SocketOptions::default()).unwrap_err();
+        assert_eq!(err.err(), SystemError::Config);
+    }
+}
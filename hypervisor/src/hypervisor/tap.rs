@@ -0,0 +1,105 @@
+//! Copies of the messages transferred on channels, for consumers outside of
+//! the module
+//!
+//! A tapped channel keeps a copy of every message it transfers. The copies are
+//! taken once after every scheduling step as [Transferred], which is handed to
+//! all consumers, i.e. the [MQTT bridge](super::mqtt) and the
+//! [recorder](super::record).
+
+use std::collections::HashMap;
+
+use a653rs_linux_core::queuing::Queuing;
+use a653rs_linux_core::sampling::Sampling;
+use itertools::Itertools;
+
+use crate::hypervisor::config::Channel;
+
+/// A channel of the hypervisor, by the name of its source port
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum ChannelKey {
+    Sampling(String),
+    Queuing(String),
+}
+
+impl ChannelKey {
+    /// Finds the channel `name` refers to
+    pub(crate) fn resolve(channels: &[Channel], name: &str) -> Result<Self, String> {
+        let found = channels
+            .iter()
+            .filter_map(|c| match c {
+                Channel::Sampling(s) => (s.name() == name || s.source.name() == name)
+                    .then(|| ChannelKey::Sampling(s.source.name())),
+                Channel::Queuing(q) => (q.name() == name || q.source.name() == name)
+                    .then(|| ChannelKey::Queuing(q.source.name())),
+            })
+            .collect_vec();
+        match found.as_slice() {
+            [key] => Ok(key.clone()),
+            [] => Err(format!("channel {name:?} is not configured")),
+            _ => Err(format!(
+                "channel {name:?} is ambiguous, name it by its source port as \"<partition>:<port>\""
+            )),
+        }
+    }
+
+    /// Name of the source port of the channel
+    pub(crate) fn name(&self) -> &str {
+        match self {
+            ChannelKey::Sampling(name) | ChannelKey::Queuing(name) => name,
+        }
+    }
+
+    /// Keeps a copy of the messages of this channel for [Transferred::take]
+    pub(crate) fn tap(
+        &self,
+        sampling: &mut HashMap<String, Sampling>,
+        queuing: &mut HashMap<String, Queuing>,
+    ) {
+        match self {
+            ChannelKey::Sampling(name) => {
+                if let Some(channel) = sampling.get_mut(name) {
+                    channel.tap()
+                }
+            }
+            ChannelKey::Queuing(name) => {
+                if let Some(channel) = queuing.get_mut(name) {
+                    channel.tap()
+                }
+            }
+        }
+    }
+}
+
+/// The messages transferred on the tapped channels since the last step
+#[derive(Debug, Default)]
+pub(crate) struct Transferred {
+    messages: HashMap<ChannelKey, Vec<Vec<u8>>>,
+}
+
+impl Transferred {
+    /// Takes the messages the tapped channels transferred since the last call,
+    /// oldest first
+    pub(crate) fn take(
+        sampling: &mut HashMap<String, Sampling>,
+        queuing: &mut HashMap<String, Queuing>,
+    ) -> Self {
+        let mut messages = HashMap::new();
+        for (name, channel) in sampling {
+            if let Some(data) = channel.take_tapped() {
+                messages.insert(ChannelKey::Sampling(name.clone()), vec![data]);
+            }
+        }
+        for (name, channel) in queuing {
+            let data = channel.take_tapped();
+            if !data.is_empty() {
+                messages.insert(ChannelKey::Queuing(name.clone()), data);
+            }
+        }
+        Self { messages }
+    }
+
+    /// The messages transferred on `channel`, oldest first
+    pub(crate) fn of(&self, channel: &ChannelKey) -> &[Vec<u8>] {
+        self.messages.get(channel).map_or(&[], Vec::as_slice)
+    }
+}
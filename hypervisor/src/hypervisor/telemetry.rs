@@ -0,0 +1,562 @@
+//! Export of the telemetry reported by partitions
+//!
+//! Partitions report gauges with `ApexLinuxPartition::telemetry`. The
+//! hypervisor keeps the last value of up to `max_telemetry` gauges per
+//! partition and, with `telemetry_file` configured, writes them in the text
+//! format of Prometheus:
+//!
+//! ```text
+//! # HELP a653rs_partition_telemetry Last value reported by a partition
+//! # TYPE a653rs_partition_telemetry gauge
+//! a653rs_partition_telemetry{partition="fuel_tank",name="fuel_level"} 0.73
+//! ```
+//!
+//! Once a partition dropped calls to the hypervisor because its socket was
+//! full, their number is exported as well, see the `ipc_buffer` of the
+//! partition configuration:
+//!
+//! ```text
+//! # HELP a653rs_partition_ipc_dropped_total Calls a partition dropped because its socket to the hypervisor was full
+//! # TYPE a653rs_partition_ipc_dropped_total counter
+//! a653rs_partition_ipc_dropped_total{partition="fuel_tank"} 12
+//! ```
+//!
+//! Likewise, the log records of partitions with `suppress_messages` are
+//! counted instead of printed:
+//!
+//! ```text
+//! # HELP a653rs_partition_suppressed_messages_total Log records of a partition counted instead of printed
+//! # TYPE a653rs_partition_suppressed_messages_total counter
+//! a653rs_partition_suppressed_messages_total{partition="fuel_tank"} 5012
+//! ```
+//!
+//! The time the periodic and the aperiodic processes of each partition ran in
+//! its windows of the last major frame shows how the windows were split, e.g.
+//! whether the aperiodic process got its `aperiodic_reserve`:
+//!
+//! ```text
+//! # HELP a653rs_partition_window_seconds Time the processes of a partition ran in its windows of the last major frame
+//! # TYPE a653rs_partition_window_seconds gauge
+//! a653rs_partition_window_seconds{partition="fuel_tank",phase="periodic"} 0.08
+//! a653rs_partition_window_seconds{partition="fuel_tank",phase="aperiodic"} 0.02
+//! ```
+//!
+//! The scheduler quantum measured when the hypervisor started is exported as
+//! well, for comparing hosts:
+//!
+//! ```text
+//! # HELP a653rs_scheduler_quantum_seconds Time freezing and unfreezing a partition takes on this host
+//! # TYPE a653rs_scheduler_quantum_seconds gauge
+//! a653rs_scheduler_quantum_seconds 0.000312
+//! ```
+//!
+//! The file is replaced atomically, at most once per second, so that it can be
+//! collected by the textfile collector of the node exporter.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use a653rs_linux_core::error::{ResultExt, SystemError, TypedResult};
+use a653rs_linux_core::telemetry::{check_name, RateLimit};
+
+/// Name of the exported metric
+const METRIC: &str = "a653rs_partition_telemetry";
+
+/// Name of the exported counter of dropped calls
+const DROPPED_METRIC: &str = "a653rs_partition_ipc_dropped_total";
+
+/// Name of the exported counter of suppressed log records
+const SUPPRESSED_METRIC: &str = "a653rs_partition_suppressed_messages_total";
+
+/// Name of the exported scheduler quantum
+const QUANTUM_METRIC: &str = "a653rs_scheduler_quantum_seconds";
+
+/// Name of the exported split of the partition windows
+const WINDOW_METRIC: &str = "a653rs_partition_window_seconds";
+
+/// Time the processes of a partition ran in its windows
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct WindowSplit {
+    pub periodic: Duration,
+    pub aperiodic: Duration,
+}
+
+/// Gauges of a partition
+#[derive(Debug)]
+pub(crate) struct Telemetry {
+    max_names: usize,
+    values: BTreeMap<String, f64>,
+    limit: RateLimit,
+    /// Names rejected for exceeding `max_names`, which are only logged once
+    rejected: BTreeSet<String>,
+    /// Whether updates were dropped in the current second
+    throttled: bool,
+    /// Calls dropped by all processes of the partition since its creation
+    dropped_calls: u64,
+    /// Dropped calls last reported by the current process of the partition
+    reported_calls: u64,
+    /// Log records of the partition which were not printed
+    suppressed_messages: u64,
+    /// Split of the windows in the current major frame
+    window: WindowSplit,
+    /// Split of the windows in the last major frame, once one ended
+    last_window: Option<WindowSplit>,
+    changed: bool,
+}
+
+impl Telemetry {
+    pub fn new(max_names: usize) -> Self {
+        Self {
+            max_names,
+            values: BTreeMap::new(),
+            limit: RateLimit::default(),
+            rejected: BTreeSet::new(),
+            throttled: false,
+            dropped_calls: 0,
+            reported_calls: 0,
+            suppressed_messages: 0,
+            window: WindowSplit::default(),
+            last_window: None,
+            changed: false,
+        }
+    }
+
+    /// Records a value of the gauge `name` of `partition` reported at the
+    /// module time `now`, returning whether it was accepted
+    pub fn record(&mut self, partition: &str, name: &str, value: f64, now: Duration) -> bool {
+        if let Some(reason) = check_name(name) {
+            warn!("Partition {partition}: rejected telemetry {name:?}, as its name {reason}");
+            return false;
+        }
+        if !self.limit.allow(now) {
+            if !self.throttled {
+                warn!("Partition {partition}: dropping telemetry updates beyond the rate limit");
+                self.throttled = true;
+            }
+            return false;
+        }
+        self.throttled = false;
+
+        if let Some(last) = self.values.get_mut(name) {
+            *last = value;
+        } else if self.values.len() < self.max_names {
+            self.values.insert(name.to_string(), value);
+        } else {
+            if self.rejected.insert(name.to_string()) {
+                warn!(
+                    "Partition {partition}: rejected telemetry {name:?}, as it exceeds the maximum of {} gauges",
+                    self.max_names
+                );
+            }
+            return false;
+        }
+        self.changed = true;
+        true
+    }
+
+    /// Records the number of calls which the current process of `partition`
+    /// dropped so far
+    ///
+    /// A number below the last one stems from a new process after a restart.
+    pub fn record_dropped_calls(&mut self, partition: &str, dropped: u64) {
+        if dropped < self.reported_calls {
+            self.reported_calls = 0;
+        }
+        if self.reported_calls == 0 && dropped > 0 {
+            warn!("Partition {partition}: dropped calls to the hypervisor, as its socket was full");
+        }
+        if dropped != self.reported_calls {
+            self.dropped_calls += dropped - self.reported_calls;
+            self.reported_calls = dropped;
+            self.changed = true;
+        }
+    }
+
+    /// Counts a log record of the partition which was not printed
+    pub fn record_suppressed_message(&mut self) {
+        self.suppressed_messages += 1;
+        self.changed = true;
+    }
+
+    /// Adds `time` the periodic process ran to the current major frame
+    pub fn record_periodic(&mut self, time: Duration) {
+        self.window.periodic += time;
+    }
+
+    /// Adds `time` the aperiodic process ran to the current major frame
+    pub fn record_aperiodic(&mut self, time: Duration) {
+        self.window.aperiodic += time;
+    }
+
+    /// Exports the split of the windows in the major frame which just ended
+    pub fn end_frame(&mut self) {
+        self.last_window = Some(std::mem::take(&mut self.window));
+        self.changed = true;
+    }
+
+    /// Split of the windows in the last major frame
+    #[cfg(test)]
+    pub fn last_window(&self) -> Option<WindowSplit> {
+        self.last_window
+    }
+
+    /// Last values of all gauges, by name
+    pub fn values(&self) -> &BTreeMap<String, f64> {
+        &self.values
+    }
+
+    /// Calls dropped by the partition since its creation
+    #[cfg(test)]
+    pub fn dropped_calls(&self) -> u64 {
+        self.dropped_calls
+    }
+
+    /// Log records of the partition which were not printed since its creation
+    pub fn suppressed_messages(&self) -> u64 {
+        self.suppressed_messages
+    }
+}
+
+/// Formats the gauges of all partitions in the text format of Prometheus
+///
+/// The counters of dropped calls and suppressed log records are only included
+/// once any partition dropped a call or had a log record suppressed, the split
+/// of the windows once a major frame ended.
+pub(crate) fn exposition<'a>(
+    partitions: impl IntoIterator<Item = (&'a str, &'a Telemetry)>,
+) -> String {
+    let partitions = partitions.into_iter().collect::<Vec<_>>();
+    let mut text =
+        format!("# HELP {METRIC} Last value reported by a partition\n# TYPE {METRIC} gauge\n");
+    for (partition, telemetry) in &partitions {
+        for (name, value) in telemetry.values() {
+            writeln!(
+                text,
+                "{METRIC}{{partition=\"{}\",name=\"{}\"}} {}",
+                escape_label(partition),
+                escape_label(name),
+                format_value(*value)
+            )
+            .expect("writing to a String to succeed");
+        }
+    }
+
+    if partitions.iter().any(|(_, t)| t.dropped_calls > 0) {
+        text += &format!(
+            "# HELP {DROPPED_METRIC} Calls a partition dropped because its socket to the hypervisor was full\n# TYPE {DROPPED_METRIC} counter\n"
+        );
+        for (partition, telemetry) in &partitions {
+            writeln!(
+                text,
+                "{DROPPED_METRIC}{{partition=\"{}\"}} {}",
+                escape_label(partition),
+                telemetry.dropped_calls
+            )
+            .expect("writing to a String to succeed");
+        }
+    }
+
+    if partitions.iter().any(|(_, t)| t.suppressed_messages > 0) {
+        text += &format!(
+            "# HELP {SUPPRESSED_METRIC} Log records of a partition counted instead of printed\n# TYPE {SUPPRESSED_METRIC} counter\n"
+        );
+        for (partition, telemetry) in &partitions {
+            writeln!(
+                text,
+                "{SUPPRESSED_METRIC}{{partition=\"{}\"}} {}",
+                escape_label(partition),
+                telemetry.suppressed_messages
+            )
+            .expect("writing to a String to succeed");
+        }
+    }
+
+    if partitions.iter().any(|(_, t)| t.last_window.is_some()) {
+        text += &format!(
+            "# HELP {WINDOW_METRIC} Time the processes of a partition ran in its windows of the last major frame\n# TYPE {WINDOW_METRIC} gauge\n"
+        );
+        for (partition, telemetry) in &partitions {
+            let Some(split) = telemetry.last_window else {
+                continue;
+            };
+            for (phase, time) in [("periodic", split.periodic), ("aperiodic", split.aperiodic)] {
+                writeln!(
+                    text,
+                    "{WINDOW_METRIC}{{partition=\"{}\",phase=\"{phase}\"}} {}",
+                    escape_label(partition),
+                    format_value(time.as_secs_f64())
+                )
+                .expect("writing to a String to succeed");
+            }
+        }
+    }
+    text
+}
+
+/// Formats the measured scheduler quantum, if any, in the text format of
+/// Prometheus
+fn quantum_exposition(quantum: Option<Duration>) -> String {
+    match quantum {
+        Some(quantum) => format!(
+            "# HELP {QUANTUM_METRIC} Time freezing and unfreezing a partition takes on this host\n\
+             # TYPE {QUANTUM_METRIC} gauge\n{QUANTUM_METRIC} {}\n",
+            format_value(quantum.as_secs_f64())
+        ),
+        None => String::new(),
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".into()
+    } else if value == f64::INFINITY {
+        "+Inf".into()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".into()
+    } else {
+        value.to_string()
+    }
+}
+
+/// File to which the gauges are written
+#[derive(Debug)]
+pub(crate) struct TelemetryFile {
+    path: PathBuf,
+    /// Measured scheduler quantum, see [Config::scheduler_quantum]
+    ///
+    /// [Config::scheduler_quantum]: crate::hypervisor::config::Config::scheduler_quantum
+    quantum: Option<Duration>,
+    /// Second of the module time of the last write
+    written: Option<u64>,
+}
+
+impl TelemetryFile {
+    pub fn new(path: PathBuf, quantum: Option<Duration>) -> Self {
+        Self {
+            path,
+            quantum,
+            written: None,
+        }
+    }
+
+    /// Rewrites the file if any gauge changed and it was not yet written in
+    /// the current second of the module time `now`
+    pub fn update<'a>(
+        &mut self,
+        now: Duration,
+        partitions: impl IntoIterator<Item = (&'a str, &'a mut Telemetry)>,
+    ) -> TypedResult<()> {
+        if self.written == Some(now.as_secs()) {
+            return Ok(());
+        }
+        let mut partitions = partitions.into_iter().collect::<Vec<_>>();
+        if !partitions.iter().any(|(_, t)| t.changed) && self.written.is_some() {
+            return Ok(());
+        }
+
+        partitions.sort_by_key(|(partition, _)| *partition);
+        let mut text = exposition(partitions.iter().map(|(p, t)| (*p, &**t)));
+        text += &quantum_exposition(self.quantum);
+        // Replace the file at once, so that readers never see a partial write
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, text)
+            .and_then(|_| fs::rename(&tmp, &self.path))
+            .typ(SystemError::Panic)?;
+
+        for (_, telemetry) in partitions {
+            telemetry.changed = false;
+        }
+        self.written = Some(now.as_secs());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: Duration = Duration::from_secs(3);
+
+    #[test]
+    fn names_beyond_maximum_are_rejected() {
+        let mut telemetry = Telemetry::new(2);
+        assert!(telemetry.record("tank", "fuel_level", 0.5, NOW));
+        assert!(telemetry.record("tank", "pressure", 1.0, NOW));
+        assert!(!telemetry.record("tank", "temperature", 20.0, NOW));
+        assert!(!telemetry.record("tank", "temperature", 21.0, NOW));
+        // Known gauges are still updated
+        assert!(telemetry.record("tank", "fuel_level", 0.4, NOW));
+        assert_eq!(
+            telemetry.values().iter().collect::<Vec<_>>(),
+            [
+                (&"fuel_level".to_string(), &0.4),
+                (&"pressure".to_string(), &1.0)
+            ]
+        );
+        assert_eq!(telemetry.rejected.len(), 1);
+    }
+
+    #[test]
+    fn invalid_names_are_rejected() {
+        let mut telemetry = Telemetry::new(2);
+        assert!(!telemetry.record("tank", "fuel\"} 1\nevil", 0.5, NOW));
+        assert!(!telemetry.record("tank", "", 0.5, NOW));
+        assert!(telemetry.values().is_empty());
+    }
+
+    #[test]
+    fn updates_are_rate_limited() {
+        let mut telemetry = Telemetry::new(2);
+        let accepted = (0..150)
+            .filter(|i| telemetry.record("tank", "fuel_level", *i as f64, NOW))
+            .count();
+        assert_eq!(accepted, 100);
+        assert_eq!(telemetry.values()["fuel_level"], 99.0);
+        assert!(telemetry.record("tank", "fuel_level", 0.0, NOW + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn exposition_format() {
+        let mut tank = Telemetry::new(4);
+        tank.record("tank", "fuel_level", 0.73, NOW);
+        tank.record("tank", "leak", f64::NAN, NOW);
+        let mut odd = Telemetry::new(4);
+        odd.record("a\"b\\c\nd", "max", f64::INFINITY, NOW);
+        odd.record("a\"b\\c\nd", "min", f64::NEG_INFINITY, NOW);
+        let empty = Telemetry::new(4);
+
+        assert_eq!(
+            exposition([("tank", &tank), ("a\"b\\c\nd", &odd), ("empty", &empty)]),
+            "# HELP a653rs_partition_telemetry Last value reported by a partition\n\
+             # TYPE a653rs_partition_telemetry gauge\n\
+             a653rs_partition_telemetry{partition=\"tank\",name=\"fuel_level\"} 0.73\n\
+             a653rs_partition_telemetry{partition=\"tank\",name=\"leak\"} NaN\n\
+             a653rs_partition_telemetry{partition=\"a\\\"b\\\\c\\nd\",name=\"max\"} +Inf\n\
+             a653rs_partition_telemetry{partition=\"a\\\"b\\\\c\\nd\",name=\"min\"} -Inf\n"
+        );
+    }
+
+    #[test]
+    fn quantum_exposition_format() {
+        assert_eq!(quantum_exposition(None), "");
+        assert!(
+            quantum_exposition(Some(Duration::from_micros(312))).ends_with(
+                "# TYPE a653rs_scheduler_quantum_seconds gauge\n\
+             a653rs_scheduler_quantum_seconds 0.000312\n"
+            )
+        );
+    }
+
+    #[test]
+    fn dropped_calls_are_counted_across_restarts() {
+        let mut tank = Telemetry::new(4);
+        let empty = Telemetry::new(4);
+        assert!(!exposition([("tank", &tank)]).contains(DROPPED_METRIC));
+
+        tank.record_dropped_calls("tank", 3);
+        tank.record_dropped_calls("tank", 5);
+        assert_eq!(tank.dropped_calls(), 5);
+        // The process of the restarted partition counts from zero again
+        tank.record_dropped_calls("tank", 2);
+        assert_eq!(tank.dropped_calls(), 7);
+
+        let text = exposition([("tank", &tank), ("empty", &empty)]);
+        assert!(
+            text.ends_with(
+                "# TYPE a653rs_partition_ipc_dropped_total counter\n\
+                 a653rs_partition_ipc_dropped_total{partition=\"tank\"} 7\n\
+                 a653rs_partition_ipc_dropped_total{partition=\"empty\"} 0\n"
+            ),
+            "{text}"
+        );
+    }
+
+    #[test]
+    fn suppressed_messages_are_counted() {
+        let mut chatty = Telemetry::new(4);
+        let quiet = Telemetry::new(4);
+        assert!(!exposition([("chatty", &chatty)]).contains(SUPPRESSED_METRIC));
+
+        for _ in 0..3 {
+            chatty.record_suppressed_message();
+        }
+        assert_eq!(chatty.suppressed_messages(), 3);
+        assert!(chatty.changed);
+
+        let text = exposition([("chatty", &chatty), ("quiet", &quiet)]);
+        assert!(!text.contains(DROPPED_METRIC), "{text}");
+        assert!(
+            text.ends_with(
+                "# TYPE a653rs_partition_suppressed_messages_total counter\n\
+                 a653rs_partition_suppressed_messages_total{partition=\"chatty\"} 3\n\
+                 a653rs_partition_suppressed_messages_total{partition=\"quiet\"} 0\n"
+            ),
+            "{text}"
+        );
+    }
+
+    #[test]
+    fn window_split_of_the_last_frame() {
+        let mut tank = Telemetry::new(4);
+        let idle = Telemetry::new(4);
+        tank.record_periodic(Duration::from_millis(60));
+        tank.record_aperiodic(Duration::from_millis(15));
+        // A second window in the same major frame
+        tank.record_periodic(Duration::from_millis(20));
+        tank.record_aperiodic(Duration::from_millis(5));
+        assert!(!exposition([("tank", &tank)]).contains(WINDOW_METRIC));
+
+        tank.end_frame();
+        assert!(tank.changed);
+        tank.record_periodic(Duration::from_millis(1));
+        assert_eq!(
+            tank.last_window(),
+            Some(WindowSplit {
+                periodic: Duration::from_millis(80),
+                aperiodic: Duration::from_millis(20),
+            })
+        );
+
+        let text = exposition([("tank", &tank), ("idle", &idle)]);
+        assert!(
+            text.ends_with(
+                "# TYPE a653rs_partition_window_seconds gauge\n\
+                 a653rs_partition_window_seconds{partition=\"tank\",phase=\"periodic\"} 0.08\n\
+                 a653rs_partition_window_seconds{partition=\"tank\",phase=\"aperiodic\"} 0.02\n"
+            ),
+            "{text}"
+        );
+    }
+
+    #[test]
+    fn file_is_written_once_per_second() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("telemetry.prom");
+        let mut file = TelemetryFile::new(path.clone(), None);
+        let mut tank = Telemetry::new(4);
+
+        // The first update writes the file even without gauges
+        file.update(NOW, [("tank", &mut tank)]).unwrap();
+        assert!(!fs::read_to_string(&path).unwrap().contains("tank"));
+
+        tank.record("tank", "fuel_level", 0.5, NOW);
+        file.update(NOW, [("tank", &mut tank)]).unwrap();
+        assert!(!fs::read_to_string(&path).unwrap().contains("tank"));
+
+        let later = NOW + Duration::from_secs(1);
+        file.update(later, [("tank", &mut tank)]).unwrap();
+        assert!(fs::read_to_string(&path).unwrap().ends_with("} 0.5\n"));
+        assert!(!dir.path().join("telemetry.prom.tmp").exists());
+    }
+}
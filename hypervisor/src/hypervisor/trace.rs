@@ -0,0 +1,531 @@
+//! Recording of scheduling activities as a Chrome trace
+//!
+//! The trace uses the JSON array format of the [Trace Event Format], which can
+//! be opened by `chrome://tracing` or [Perfetto](https://ui.perfetto.dev). The
+//! hypervisor and every partition get their own lane. Each partition window is
+//! split into its phases, and each channel swap after a window is recorded on
+//! the lane of the hypervisor.
+//!
+//! Recording an event only stores its timestamps. Events are formatted and
+//! written when the buffer is flushed, which happens every
+//! [FLUSH_INTERVAL_FRAMES] major frames and when the tracer is dropped. Should
+//! the buffer fill up in between, further events are dropped and counted.
+//!
+//! The trace counts against the `trace` budget of the
+//! [capture](super::capture) configuration. A rotated file closes its JSON
+//! array, and the next one starts with the names of the lanes again, so that
+//! every file can be opened on its own.
+//!
+//! Timestamps are relative to the start of the first major frame, measured on
+//! the same clock as the frame scheduling itself.
+//!
+//! [Trace Event Format]: https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use a653rs::bindings::PartitionId;
+use a653rs_linux_core::error::{ResultExt, SystemError, TypedResult};
+
+use crate::hypervisor::capture::{Admission, Capture, CaptureManager, Feature};
+
+/// Number of major frames after which buffered events are written
+pub const FLUSH_INTERVAL_FRAMES: usize = 64;
+
+/// Maximum number of events buffered in between two flushes
+const MAX_BUFFERED_EVENTS: usize = 1 << 16;
+
+/// Process id of all events, the whole module is shown as a single process
+const TRACE_PID: u32 = 1;
+
+/// Thread id of the hypervisor lane
+const HYPERVISOR_TID: usize = 0;
+
+/// Lane on which an event is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Lane {
+    Hypervisor,
+    Partition(PartitionId),
+}
+
+/// Traced activity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Activity {
+    /// Selection of the next partition window
+    Schedule,
+    /// Periodic process running
+    Periodic,
+    /// Aperiodic process running
+    Aperiodic,
+    /// Cold or warm start of the partition
+    Start,
+    /// Transfer of the messages sent during a partition window
+    PostTimeframe,
+    /// Swap of a single channel, identified by the order in which the channels
+    /// were first swapped
+    Swap(usize),
+}
+
+impl Activity {
+    fn category(&self) -> &'static str {
+        match self {
+            Activity::Schedule | Activity::PostTimeframe | Activity::Swap(_) => "hypervisor",
+            Activity::Periodic | Activity::Aperiodic | Activity::Start => "partition",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Event {
+    lane: Lane,
+    activity: Activity,
+    start: Instant,
+    end: Instant,
+}
+
+/// Records duration events of the hypervisor
+///
+/// A disabled tracer ignores all events.
+#[derive(Debug)]
+pub(crate) struct Tracer<W: Write = BufWriter<File>> {
+    recording: Option<Recording<W>>,
+}
+
+#[derive(Debug)]
+struct Recording<W: Write> {
+    out: W,
+    epoch: Instant,
+    events: Vec<Event>,
+    dropped: usize,
+    frames: usize,
+    /// Partitions in the order of their lanes
+    partitions: Vec<(PartitionId, String)>,
+    channels: Vec<String>,
+    channel_ids: HashMap<String, usize>,
+    /// Whether the lane names were written already
+    metadata_written: bool,
+    /// Whether any event was written already, for placing the separators
+    written: bool,
+    /// Budget of the file, along with how a new one is created once the
+    /// file is rotated
+    capture: Option<(Capture, CreateFn<W>)>,
+}
+
+/// Creates the writer of a new trace file at a path
+type CreateFn<W> = fn(&Path) -> std::io::Result<W>;
+
+impl Tracer {
+    /// Creates a tracer writing to the file at `path`, within the budget of
+    /// the trace in `captures`
+    pub fn create(path: &Path, captures: &Arc<CaptureManager>) -> TypedResult<Self> {
+        let file = File::create(path).typ(SystemError::Config)?;
+        let mut tracer = Self::with_writer(BufWriter::new(file), Instant::now());
+        let create: CreateFn<BufWriter<File>> = |path| File::create(path).map(BufWriter::new);
+        if let Some(rec) = &mut tracer.recording {
+            rec.capture = Some((captures.register(Feature::Trace, path), create));
+        }
+        Ok(tracer)
+    }
+}
+
+impl<W: Write> Tracer<W> {
+    /// Creates a tracer which ignores all events
+    pub fn disabled() -> Self {
+        Self { recording: None }
+    }
+
+    /// Creates a tracer writing to `out`, with timestamps relative to `epoch`
+    pub fn with_writer(out: W, epoch: Instant) -> Self {
+        Self {
+            recording: Some(Recording {
+                out,
+                epoch,
+                events: Vec::new(),
+                dropped: 0,
+                frames: 0,
+                partitions: Vec::new(),
+                channels: Vec::new(),
+                channel_ids: HashMap::new(),
+                metadata_written: false,
+                written: false,
+                capture: None,
+            }),
+        }
+    }
+
+    /// Sets the point in time which is shown as zero
+    pub fn set_epoch(&mut self, epoch: Instant) {
+        if let Some(rec) = &mut self.recording {
+            rec.epoch = epoch;
+        }
+    }
+
+    /// Adds a lane for the partition
+    pub fn add_partition(&mut self, id: PartitionId, name: &str) {
+        if let Some(rec) = &mut self.recording {
+            rec.partitions.push((id, name.to_string()));
+        }
+    }
+
+    /// Records an activity which lasted from `start` until `end`
+    pub fn record(&mut self, lane: Lane, activity: Activity, start: Instant, end: Instant) {
+        let Some(rec) = &mut self.recording else {
+            return;
+        };
+        if rec.events.len() >= MAX_BUFFERED_EVENTS {
+            rec.dropped += 1;
+            return;
+        }
+        rec.events.push(Event {
+            lane,
+            activity,
+            start,
+            end,
+        });
+    }
+
+    /// Records an activity which started at `start` and just ended
+    pub fn record_since(&mut self, lane: Lane, activity: Activity, start: Instant) {
+        if self.recording.is_some() {
+            self.record(lane, activity, start, Instant::now())
+        }
+    }
+
+    /// Records the swap of a channel which lasted from `start` until `end`
+    pub fn record_swap(&mut self, channel: &str, start: Instant, end: Instant) {
+        let Some(rec) = &mut self.recording else {
+            return;
+        };
+        let id = match rec.channel_ids.get(channel) {
+            Some(id) => *id,
+            None => {
+                rec.channels.push(channel.to_string());
+                rec.channel_ids
+                    .insert(channel.to_string(), rec.channels.len() - 1);
+                rec.channels.len() - 1
+            }
+        };
+        self.record(Lane::Hypervisor, Activity::Swap(id), start, end)
+    }
+
+    /// Marks the end of a major frame, flushing the buffered events
+    /// periodically
+    pub fn end_frame(&mut self) {
+        let Some(rec) = &mut self.recording else {
+            return;
+        };
+        rec.frames += 1;
+        if rec.frames % FLUSH_INTERVAL_FRAMES == 0 {
+            self.flush();
+        }
+    }
+
+    /// Writes all buffered events
+    ///
+    /// Tracing is disabled if writing fails.
+    pub fn flush(&mut self) {
+        if let Some(rec) = &mut self.recording {
+            if let Err(e) = rec.flush() {
+                warn!("disabling tracing, because writing the trace failed: {e}");
+                self.recording = None;
+            }
+        }
+    }
+
+    /// Writes all buffered events, terminates the trace and returns the writer
+    #[cfg(test)]
+    pub fn finish(mut self) -> Option<W> {
+        let mut rec = self.recording.take()?;
+        if let Err(e) = rec.finish() {
+            warn!("failed to finish the trace: {e}");
+        }
+        Some(rec.out)
+    }
+}
+
+impl<W: Write> Drop for Tracer<W> {
+    fn drop(&mut self) {
+        if let Some(mut rec) = self.recording.take() {
+            if let Err(e) = rec.finish() {
+                warn!("failed to finish the trace: {e}");
+            }
+        }
+    }
+}
+
+impl<W: Write> Recording<W> {
+    fn tid(&self, lane: Lane) -> usize {
+        match lane {
+            Lane::Hypervisor => HYPERVISOR_TID,
+            Lane::Partition(id) => self
+                .partitions
+                .iter()
+                .position(|(p, _)| *p == id)
+                .map_or(HYPERVISOR_TID, |i| i + 1),
+        }
+    }
+
+    fn name(&self, activity: Activity) -> &str {
+        match activity {
+            Activity::Schedule => "schedule",
+            Activity::Periodic => "periodic",
+            Activity::Aperiodic => "aperiodic",
+            Activity::Start => "start",
+            Activity::PostTimeframe => "post timeframe",
+            Activity::Swap(id) => &self.channels[id],
+        }
+    }
+
+    /// Writes `bytes` to the file, accounting them to its budget
+    fn write_bytes(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.out.write_all(bytes)?;
+        if let Some((capture, _)) = &self.capture {
+            capture.written(bytes.len() as u64);
+        }
+        Ok(())
+    }
+
+    /// Writes `entry` as a new entry of the JSON array
+    fn write_entry(&mut self, entry: &str) -> std::io::Result<()> {
+        if self.written {
+            self.write_bytes(b",\n")?;
+        } else {
+            self.write_bytes(b"[\n")?;
+            self.written = true;
+        }
+        self.write_bytes(entry.as_bytes())
+    }
+
+    /// Asks the budget of the trace whether an entry of `len` bytes may be
+    /// written, rotating the file if it is full
+    fn admit(&mut self, len: usize) -> std::io::Result<bool> {
+        let Some((capture, create)) = self.capture.clone() else {
+            return Ok(true);
+        };
+        // Including the separator
+        let len = len as u64 + 2;
+        loop {
+            match capture.admit(len) {
+                Admission::Write => return Ok(true),
+                Admission::Drop => return Ok(false),
+                Admission::Rotate => {
+                    self.write_bytes(b"\n]\n")?;
+                    self.out.flush()?;
+                    capture.rotate()?;
+                    self.out = create(capture.path())?;
+                    self.written = false;
+                    self.write_metadata()?;
+                }
+            }
+        }
+    }
+
+    fn write_metadata(&mut self) -> std::io::Result<()> {
+        self.write_entry(&format!(
+            r#"{{"name":"process_name","ph":"M","pid":{TRACE_PID},"tid":{HYPERVISOR_TID},"args":{{"name":"a653rs-linux"}}}}"#
+        ))?;
+        let lanes = std::iter::once("hypervisor".to_string())
+            .chain(self.partitions.iter().map(|(_, name)| name.clone()))
+            .collect::<Vec<_>>();
+        for (tid, name) in lanes.iter().enumerate() {
+            self.write_entry(&format!(
+                r#"{{"name":"thread_name","ph":"M","pid":{TRACE_PID},"tid":{tid},"args":{{"name":"{}"}}}}"#,
+                escape(name)
+            ))?;
+            self.write_entry(&format!(
+                r#"{{"name":"thread_sort_index","ph":"M","pid":{TRACE_PID},"tid":{tid},"args":{{"sort_index":{tid}}}}}"#
+            ))?;
+        }
+        self.metadata_written = true;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.metadata_written {
+            self.write_metadata()?;
+        }
+
+        let events = std::mem::take(&mut self.events);
+        for event in &events {
+            let ts = event.start.saturating_duration_since(self.epoch);
+            let dur = event.end.saturating_duration_since(event.start);
+            let line = format!(
+                r#"{{"name":"{}","cat":"{}","ph":"X","ts":{},"dur":{},"pid":{TRACE_PID},"tid":{}}}"#,
+                escape(self.name(event.activity)),
+                event.activity.category(),
+                micros(ts),
+                micros(dur),
+                self.tid(event.lane),
+            );
+            // Dropped events are warned about by the budget
+            if self.admit(line.len())? {
+                self.write_entry(&line)?;
+            }
+        }
+        // Reuse the allocation of the buffer
+        self.events = events;
+        self.events.clear();
+
+        if self.dropped > 0 {
+            warn!("dropped {} trace events", self.dropped);
+            self.write_entry(&format!(
+                r#"{{"name":"dropped events","ph":"C","ts":0.000,"pid":{TRACE_PID},"args":{{"count":{}}}}}"#,
+                self.dropped
+            ))?;
+            self.dropped = 0;
+        }
+
+        self.out.flush()
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.flush()?;
+        self.write_bytes(b"\n]\n")?;
+        self.out.flush()
+    }
+}
+
+/// Formats a duration in microseconds, the time unit of the trace
+fn micros(d: Duration) -> String {
+    format!("{}.{:03}", d.as_micros(), d.subsec_nanos() % 1_000)
+}
+
+/// Escapes a string for use inside of a JSON string literal
+pub(crate) fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TWO_FRAMES: &str = include_str!("../../testdata/trace_two_frames.json");
+
+    #[test]
+    fn two_frames() {
+        let epoch = Instant::now();
+        let at = |us: u64| epoch + Duration::from_micros(us);
+
+        let mut tracer = Tracer::with_writer(Vec::new(), epoch);
+        tracer.add_partition(7, "sensor");
+        tracer.add_partition(3, "controller");
+
+        let major_frame = 10_000;
+        for frame in 0..2 {
+            let t = frame * major_frame;
+            tracer.record(Lane::Hypervisor, Activity::Schedule, at(t), at(t + 1));
+            tracer.record(
+                Lane::Partition(7),
+                Activity::Periodic,
+                at(t + 1),
+                at(t + 3_001),
+            );
+            tracer.record(
+                Lane::Partition(7),
+                Activity::Aperiodic,
+                at(t + 3_001),
+                at(t + 5_000),
+            );
+            tracer.record(
+                Lane::Hypervisor,
+                Activity::PostTimeframe,
+                at(t + 5_000),
+                at(t + 5_010),
+            );
+            tracer.record_swap("sensor:temperature", at(t + 5_002), at(t + 5_003));
+            tracer.record(
+                Lane::Hypervisor,
+                Activity::Schedule,
+                at(t + 5_010),
+                at(t + 5_011),
+            );
+            tracer.record(
+                Lane::Partition(3),
+                Activity::Start,
+                at(t + 5_011),
+                at(t + 10_000),
+            );
+            tracer.end_frame();
+        }
+
+        let trace = String::from_utf8(tracer.finish().unwrap()).unwrap();
+        assert_eq!(trace.trim(), TWO_FRAMES.trim());
+    }
+
+    #[test]
+    fn drops_events_when_full() {
+        let epoch = Instant::now();
+        let mut tracer = Tracer::with_writer(Vec::new(), epoch);
+        for _ in 0..MAX_BUFFERED_EVENTS + 3 {
+            tracer.record(Lane::Hypervisor, Activity::Schedule, epoch, epoch);
+        }
+        let trace = String::from_utf8(tracer.finish().unwrap()).unwrap();
+        assert!(trace.contains(r#""args":{"count":3}"#));
+        assert!(trace.ends_with("\n]\n"));
+    }
+
+    #[test]
+    fn rotated_files_are_complete() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.json");
+        // Files of 2000 bytes, each holding about 20 events
+        let captures =
+            CaptureManager::new(serde_yaml::from_str("trace: { max_size: 6KB, keep: 2 }").unwrap());
+        let mut tracer = Tracer::create(&path, &captures).unwrap();
+        tracer.add_partition(7, "sensor");
+        let epoch = Instant::now();
+        for _ in 0..FLUSH_INTERVAL_FRAMES * 2 {
+            tracer.record(Lane::Partition(7), Activity::Periodic, epoch, epoch);
+            tracer.end_frame();
+        }
+        drop(tracer);
+
+        for file in ["trace.json", "trace.json.1", "trace.json.2"] {
+            let trace = std::fs::read_to_string(dir.path().join(file)).unwrap();
+            assert!(
+                trace.starts_with("[\n{\"name\":\"process_name\""),
+                "{file}: {trace}"
+            );
+            assert!(
+                trace.contains(r#""args":{"name":"sensor"}"#),
+                "{file}: {trace}"
+            );
+            assert!(trace.contains(r#""name":"periodic""#), "{file}: {trace}");
+            assert!(trace.ends_with("\n]\n"), "{file}: {trace}");
+        }
+        assert!(!dir.path().join("trace.json.3").exists());
+    }
+
+    #[test]
+    fn disabled_tracer_ignores_events() {
+        let mut tracer = Tracer::<Vec<u8>>::disabled();
+        tracer.record_swap("channel", Instant::now(), Instant::now());
+        tracer.end_frame();
+        assert!(tracer.finish().is_none());
+    }
+
+    #[test]
+    fn escapes_names() {
+        assert_eq!(escape(r#"a"b\c"#), r#"a\"b\\c"#);
+        assert_eq!(escape("a\nb"), "a\\u000ab");
+    }
+
+    #[test]
+    fn formats_micros() {
+        assert_eq!(micros(Duration::ZERO), "0.000");
+        assert_eq!(micros(Duration::from_nanos(1_234_567)), "1234.567");
+    }
+}
@@ -0,0 +1,281 @@
+//! Validation of a partition image against its configuration
+//!
+//! The `validate-partition` command runs a single partition of a
+//! configuration on its own, like `--solo`, until it enters the NORMAL mode or
+//! a timeout expires. It then reports how long the start took, which ports
+//! the partition declared and created, how many log records it sent and which
+//! errors it raised. Every deviation from the configuration is a problem
+//! failing the validation, so that CI jobs can gate new partition images on
+//! it.
+
+use std::fmt::{self, Display};
+use std::thread::sleep;
+use std::time::Duration;
+
+use a653rs_linux_core::error::{ErrorLevel, LeveledResult, ResultExt, SystemError};
+use anyhow::anyhow;
+
+use super::config::Config;
+use super::partition::{describe_port, Observations};
+use super::scheduler::Action;
+use super::{shutdown, Hypervisor};
+
+/// Outcome of the validation of a partition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionReport {
+    pub partition: String,
+    pub timeout: Duration,
+    /// Module time at which the partition entered NORMAL
+    pub time_to_normal: Option<Duration>,
+    /// Ports declared by the partition, if it declared any
+    pub declared_ports: Option<Vec<String>>,
+    /// Ports created by the partition, in the order of their creation
+    pub created_ports: Vec<String>,
+    pub log_records: u64,
+    /// Errors of the partition handled by the health monitor
+    pub errors: Vec<SystemError>,
+    /// Deviations from the configuration, each failing the validation
+    pub problems: Vec<String>,
+}
+
+impl PartitionReport {
+    /// Judges the observations of `partition`, whose configured ports listed
+    /// in `uncreated` were not created
+    pub(crate) fn new(
+        partition: &str,
+        observed: &Observations,
+        uncreated: Vec<String>,
+        timeout: Duration,
+    ) -> Self {
+        let mut problems = Vec::new();
+        if observed.normal_at.is_none() {
+            problems.push(format!(
+                "did not enter NORMAL within {}",
+                humantime::Duration::from(timeout)
+            ));
+        }
+        problems.extend(observed.timing_mismatches.iter().cloned());
+        problems.extend(observed.port_mismatches.iter().cloned());
+        problems.extend(
+            observed
+                .unconfigured_ports
+                .iter()
+                .map(|p| format!("created the unconfigured {p}")),
+        );
+        problems.extend(uncreated.iter().map(|p| format!("did not create its {p}")));
+        problems.extend(observed.errors.iter().map(|e| format!("raised {e:?}")));
+
+        Self {
+            partition: partition.to_string(),
+            timeout,
+            time_to_normal: observed.normal_at.map(|t| t.as_duration()),
+            declared_ports: observed
+                .declared_ports
+                .as_ref()
+                .map(|ports| ports.iter().map(describe_port).collect()),
+            created_ports: observed.created_ports.clone(),
+            log_records: observed.log_records,
+            errors: observed.errors.clone(),
+            problems,
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl Display for PartitionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |items: &[String]| {
+            if items.is_empty() {
+                "none".to_string()
+            } else {
+                items.join(", ")
+            }
+        };
+
+        writeln!(f, "partition       {}", self.partition)?;
+        match self.time_to_normal {
+            Some(t) => writeln!(f, "time to NORMAL  {}", humantime::Duration::from(t))?,
+            None => writeln!(
+                f,
+                "time to NORMAL  not reached within {}",
+                humantime::Duration::from(self.timeout)
+            )?,
+        }
+        match &self.declared_ports {
+            Some(ports) => writeln!(f, "declared ports  {}", list(ports))?,
+            None => writeln!(f, "declared ports  no declaration")?,
+        }
+        writeln!(f, "created ports   {}", list(&self.created_ports))?;
+        writeln!(f, "log records     {}", self.log_records)?;
+        let errors = self
+            .errors
+            .iter()
+            .map(|e| format!("{e:?}"))
+            .collect::<Vec<_>>();
+        writeln!(f, "errors          {}", list(&errors))?;
+        for problem in &self.problems {
+            writeln!(f, "[FAIL] {problem}")?;
+        }
+        match self.problems.len() {
+            0 => writeln!(f, "result          passed"),
+            n => writeln!(f, "result          failed with {n} problems"),
+        }
+    }
+}
+
+/// Runs the only partition of `config`, as derived by [Config::solo], until
+/// it enters NORMAL or `timeout` expires, prints the report and fails if it
+/// found any problem
+///
+/// The timeout is only checked in between scheduling steps, so the window
+/// of the partition, spanning the whole major frame, is never interrupted.
+pub fn run(config: Config, timeout: Duration) -> LeveledResult<()> {
+    let [partition] = config.partitions.as_slice() else {
+        return Err(anyhow!(
+            "validation needs a configuration with a single partition"
+        ))
+        .lev_typ(SystemError::Config, ErrorLevel::ModuleInit);
+    };
+    let name = partition.name.clone();
+    let mut hv = Hypervisor::new(config, None, None)?;
+    let report = observe(&mut hv, timeout)?;
+    // Remove the partition before printing, so that its log does not end up in
+    // the middle of the report
+    drop(hv);
+
+    print!("{report}");
+    if !report.passed() {
+        return Err(anyhow!(
+            "partition {name} failed validation with {} problems",
+            report.problems.len()
+        ))
+        .lev_typ(SystemError::PartitionConfig, ErrorLevel::ModuleInit);
+    }
+    Ok(())
+}
+
+/// Steps through the schedule until the only partition of `hv` entered
+/// NORMAL or `timeout` expired
+fn observe(hv: &mut Hypervisor, timeout: Duration) -> LeveledResult<PartitionReport> {
+    loop {
+        if shutdown::requested() {
+            return Err(anyhow!("validation was interrupted"))
+                .lev_typ(SystemError::Panic, ErrorLevel::ModuleRun);
+        }
+
+        let step = hv.step()?;
+        let partition = hv
+            .partitions
+            .values()
+            .next()
+            .expect("solo configuration to hold a single partition");
+        let expired = hv.scheduler.now().as_duration() >= timeout;
+        let normal = partition.observations().normal_at.is_some();
+        if normal || expired || step.action == Action::Terminate {
+            return Ok(PartitionReport::new(
+                partition.name(),
+                partition.observations(),
+                partition.uncreated_ports(),
+                timeout,
+            ));
+        }
+
+        sleep(step.next_deadline.duration_since(hv.scheduler.now()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use a653rs::bindings::PortDirection;
+    use a653rs_linux_core::partition::PortDecl;
+    use a653rs_linux_core::time::ModuleTime;
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn normal_partition() -> Observations {
+        Observations {
+            normal_at: Some(ModuleTime::from(Duration::from_millis(12))),
+            declared_ports: Some(vec![PortDecl::Sampling {
+                name: "Hello".into(),
+                dir: PortDirection::Source,
+                msg_size: 16,
+            }]),
+            created_ports: vec!["sampling port \"Hello\"".into()],
+            log_records: 3,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn passing_report() {
+        let report = PartitionReport::new("hello", &normal_partition(), Vec::new(), TIMEOUT);
+        assert!(report.passed(), "{report}");
+        assert_eq!(report.time_to_normal, Some(Duration::from_millis(12)));
+        assert_eq!(
+            report.to_string(),
+            "partition       hello\n\
+             time to NORMAL  12ms\n\
+             declared ports  sampling port \"Hello\"\n\
+             created ports   sampling port \"Hello\"\n\
+             log records     3\n\
+             errors          none\n\
+             result          passed\n"
+        );
+    }
+
+    #[test]
+    fn every_deviation_is_a_problem() {
+        let observed = Observations {
+            normal_at: None,
+            timing_mismatches: vec!["period of 1s is required, but 2s is configured".into()],
+            port_mismatches: vec!["sampling port \"Hello\" is not configured".into()],
+            unconfigured_ports: vec!["queuing port \"Cmd\"".into()],
+            errors: vec![SystemError::ApplicationError],
+            ..normal_partition()
+        };
+        let uncreated = vec!["sampling port \"World\" of channel b:World".into()];
+        let report = PartitionReport::new("hello", &observed, uncreated, TIMEOUT);
+        assert!(!report.passed());
+        assert_eq!(
+            report.problems,
+            [
+                "did not enter NORMAL within 5s",
+                "period of 1s is required, but 2s is configured",
+                "sampling port \"Hello\" is not configured",
+                "created the unconfigured queuing port \"Cmd\"",
+                "did not create its sampling port \"World\" of channel b:World",
+                "raised ApplicationError",
+            ]
+        );
+        let text = report.to_string();
+        assert!(
+            text.contains("time to NORMAL  not reached within 5s"),
+            "{text}"
+        );
+        assert!(
+            text.ends_with("result          failed with 6 problems\n"),
+            "{text}"
+        );
+    }
+
+    #[test]
+    fn missing_declaration() {
+        let observed = Observations {
+            declared_ports: None,
+            created_ports: Vec::new(),
+            ..normal_partition()
+        };
+        let report = PartitionReport::new("hello", &observed, Vec::new(), TIMEOUT);
+        // Declaring the ports is optional
+        assert!(report.passed());
+        assert!(report
+            .to_string()
+            .contains("declared ports  no declaration"));
+        assert!(report.to_string().contains("created ports   none"));
+    }
+}
@@ -0,0 +1,157 @@
+//! Checks of the memory shared with the partitions, enabled with
+//! `--verify-shared-state`
+//!
+//! A partition may corrupt the memory of its ports or its mode file, e.g. by a
+//! stray write, which the hypervisor would otherwise only notice once it trips
+//! over the corrupted data. The [Verifier] checks the invariants of all of it
+//! at the start of every major frame, while no partition runs:
+//!
+//! - the length and first index of every queue fit its capacity
+//! - no message exceeds the message size of its channel
+//! - no message or clear request is stamped later than the check
+//! - the current message of a sampling port is not replaced during the check
+//! - the mode file holds the operating mode the hypervisor keeps
+//!
+//! A violation is attributed to the partition writing the affected memory,
+//! i.e. the source partition for the source end of a channel and the
+//! destination partitions for the destination end, whose memory only the
+//! hypervisor and the partition reading from it change. Only the first
+//! violation of a partition per frame is raised to its health monitor, all of
+//! them are logged. A violation on a channel marked module-critical is raised
+//! to the module instead, as no partition recovery restores its state.
+//!
+//! A pass reads the header of every message queued in the channels, so its
+//! duration grows with the number of messages. The duration of every pass is
+//! measured and summarized at the end of the run, so that the overhead can be
+//! judged for a given configuration.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use a653rs::bindings::PartitionId;
+use a653rs_linux_core::channel::Criticality;
+use a653rs_linux_core::error::{SystemError, TypedError};
+use a653rs_linux_core::queuing::Queuing;
+use a653rs_linux_core::sampling::Sampling;
+use a653rs_linux_core::time::MonotonicTime;
+use anyhow::anyhow;
+
+use super::partition::Partition;
+
+/// The violations found by a pass of the [Verifier]
+#[derive(Debug, Default)]
+pub(crate) struct Violations {
+    /// The first violation of every partition by its name
+    pub partitions: HashMap<String, TypedError>,
+    /// The first violation of a module-critical channel
+    pub module: Option<TypedError>,
+}
+
+/// Checks the shared memory once per major frame and measures the passes
+#[derive(Debug, Default)]
+pub(crate) struct Verifier {
+    passes: u32,
+    total: Duration,
+    longest: Duration,
+}
+
+impl Verifier {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks the memory of all channels and partitions
+    pub(crate) fn check(
+        &mut self,
+        partitions: &HashMap<PartitionId, Partition>,
+        sampling: &HashMap<String, Sampling>,
+        queuing: &HashMap<String, Queuing>,
+    ) -> Violations {
+        let start = Instant::now();
+        let now = MonotonicTime::now();
+        let mut violations = Violations::default();
+        let mut violate = |partition: &str, criticality: Criticality, err: TypedError| {
+            warn!("Partition {partition} violated the shared state: {err}");
+            match criticality {
+                Criticality::Module => {
+                    violations.module.get_or_insert(err);
+                }
+                Criticality::Partition => {
+                    violations
+                        .partitions
+                        .entry(partition.to_owned())
+                        .or_insert(err);
+                }
+            }
+        };
+
+        for (name, channel) in sampling {
+            let criticality = channel.criticality();
+            if let Err(e) = channel.verify_source(now) {
+                violate(
+                    channel.source_partition(),
+                    criticality,
+                    segmentation(name, "source", e),
+                );
+            }
+            if let Err(e) = channel.verify_destination(now) {
+                for partition in channel.destination_partitions() {
+                    violate(
+                        partition,
+                        criticality,
+                        segmentation(name, "destination", &e),
+                    );
+                }
+            }
+        }
+        for (name, channel) in queuing {
+            let criticality = channel.criticality();
+            if let Err(e) = channel.verify_source(now) {
+                violate(
+                    channel.source_partition(),
+                    criticality,
+                    segmentation(name, "source", e),
+                );
+            }
+            if let Err(e) = channel.verify_destination(now) {
+                violate(
+                    channel.destination_partition(),
+                    criticality,
+                    segmentation(name, "destination", e),
+                );
+            }
+        }
+        for partition in partitions.values() {
+            if let Err(e) = partition.verify_shared_state() {
+                violate(partition.name(), Criticality::Partition, e);
+            }
+        }
+
+        let elapsed = start.elapsed();
+        self.passes += 1;
+        self.total += elapsed;
+        self.longest = self.longest.max(elapsed);
+        violations
+    }
+
+    /// Logs how long the checks took
+    pub(crate) fn report(&self) {
+        if self.passes == 0 {
+            return;
+        }
+        info!(
+            "Verified the shared state {} times, taking {:?} on average and {:?} at most",
+            self.passes,
+            self.total / self.passes,
+            self.longest
+        );
+    }
+}
+
+/// A violation of the memory of the `end` of channel `name`
+fn segmentation(name: &str, end: &str, err: impl std::fmt::Display) -> TypedError {
+    TypedError::new(
+        SystemError::Segmentation,
+        anyhow!("{end} of channel {name}: {err}"),
+    )
+}
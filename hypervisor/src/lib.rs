@@ -3,57 +3,471 @@
 #[macro_use]
 extern crate log;
 
-use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use a653rs_linux_core::cgroup;
 use a653rs_linux_core::error::{ErrorLevel, LeveledResult, ResultExt, SystemError, TypedResultExt};
-use a653rs_linux_core::health::ModuleRecoveryAction;
-use anyhow::anyhow;
-use clap::Parser;
+use a653rs_linux_core::health::{module_action, ModuleRecoveryAction};
+use a653rs_linux_core::{buffer, cgroup};
+use anyhow::Context;
+use clap::{Parser, Subcommand};
 use hypervisor::config::Config;
-use nix::sys::signal::*;
+use hypervisor::layout::CgroupLayout;
 
-use crate::hypervisor::Hypervisor;
+use crate::hypervisor::control::ControlSocket;
+use crate::hypervisor::sd_notify::Notifier;
+use crate::hypervisor::{
+    doctor, gantt, generate, record, reload, shutdown, validate, Hypervisor, Stop,
+};
 
 pub mod hypervisor;
 
+/// Number of freezes and unfreezes measured for the scheduler quantum
+const CALIBRATION_ROUNDS: usize = 32;
+
+/// Exit status as listed by `--help`
+const EXIT_STATUS_HELP: &str = "\
+Exit status:
+  0    the run-time of --duration elapsed or a shutdown was requested
+  10   the health monitor shut down the module after an error
+  11   the module failed with an error it could not recover from
+  130  a second termination signal within 5s of the first forced the exit";
+
 /// Hypervisor based on cgroups in Linux
+///
+/// Without a subcommand, the hypervisor is run.
 #[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
+#[clap(
+    author,
+    version,
+    about,
+    long_about = None,
+    after_help = EXIT_STATUS_HELP,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 pub struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    #[clap(flatten)]
+    run: RunArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run the hypervisor (default)
+    Run(RunArgs),
+    /// Check whether this environment is able to run the hypervisor
+    Doctor(DoctorArgs),
+    /// Check that a partition starts and creates the ports of its
+    /// configuration, without running the schedule
+    ValidatePartition(ValidatePartitionArgs),
+    /// Generate a crate for a partition, with the ports of its channels
+    Generate(GenerateArgs),
+    /// List the messages of a recording of channels
+    InspectRecording(InspectRecordingArgs),
+    /// List the memfds of the channels of a running hypervisor
+    ListShm(ListShmArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct RunArgs {
     /// Configuration file for the hypervisor
-    #[clap()]
-    config_file: PathBuf,
+    #[clap(required = true)]
+    config_file: Option<PathBuf>,
 
     /// Target cgroup to use
     #[clap(short = 'g', long)]
     cgroup: Option<PathBuf>,
 
+    /// Create the partitions directly in the target cgroup
+    ///
+    /// Meant for a cgroup delegated to the hypervisor, e.g. by systemd with
+    /// `Delegate=yes`. Instead of creating a `linux-hypervisor` cgroup below
+    /// it, the hypervisor moves itself into its `supervisor` child.
+    #[clap(long)]
+    cgroup_use_parent: bool,
+
+    /// Directory the temporary files of the partitions are created in
+    ///
+    /// Defaults to `$XDG_RUNTIME_DIR`, or `/tmp` without it. A tmpfs is
+    /// preferred, as it holds the sockets and mount points of the partitions.
+    #[clap(long, value_name = "PATH")]
+    runtime_dir: Option<PathBuf>,
+
     /// Only execute the hypervisor for this duration, then quit
     ///
     /// The condition is only checked in between major frames, e.g. a major
     /// frame is never interrupted.
-    #[clap(short, long)]
+    #[clap(short, long, conflicts_with = "frames")]
     duration: Option<humantime::Duration>,
+
+    /// Only execute the hypervisor for this number of major frames, then quit
+    #[clap(long)]
+    frames: Option<u32>,
+
+    /// Receive commands on a Unix datagram socket at this path
+    ///
+    /// Sending `extend 30s` extends the run-time of `--duration` by 30s, for
+    /// interactive sessions. Commands are answered if the sender is bound to
+    /// a path.
+    #[clap(long, value_name = "PATH")]
+    control_socket: Option<PathBuf>,
+
+    /// Record the partition windows and channel swaps as a Chrome trace
+    ///
+    /// The resulting JSON file can be opened with chrome://tracing or
+    /// Perfetto.
+    #[clap(long)]
+    trace_file: Option<PathBuf>,
+
+    /// Only run this partition, for debugging it in isolation
+    ///
+    /// The partition gets the whole major frame as its window. Its channels
+    /// are kept, even though their other ends are not running. The timing of
+    /// the configured schedule does not apply in this mode.
+    #[clap(long, value_name = "PARTITION")]
+    solo: Option<String>,
+
+    /// Build partition images given as cargo packages before starting
+    ///
+    /// Only meant for development, the output of cargo is logged.
+    #[clap(long)]
+    allow_cargo_build: bool,
+
+    /// Check the memory shared with the partitions at every major frame
+    ///
+    /// The queues and messages of all channels and the mode file of every
+    /// partition are checked while no partition runs. The first violation of
+    /// a partition is logged and raised to its health monitor as a
+    /// segmentation error. Meant for testing, as the checks take time in
+    /// between the windows.
+    #[clap(long)]
+    verify_shared_state: bool,
+
+    /// Run a configuration without partitions
+    ///
+    /// Meant for testing the infrastructure around the hypervisor, which then
+    /// idles in empty major frames. Without this flag, a configuration
+    /// without partitions is an error.
+    #[clap(long)]
+    allow_empty: bool,
+
+    /// Write the messages of a recording to their channels
+    ///
+    /// Every message is written to the destinations of its channel at the
+    /// module time it was recorded at, as if its source had sent it.
+    #[clap(long, value_name = "FILE")]
+    replay: Option<PathBuf>,
+
+    /// Print the configuration with all defaults filled in as YAML and exit
+    ///
+    /// Shows e.g. the health monitor tables in effect.
+    #[clap(long)]
+    dump_config: bool,
+
+    /// Print a Gantt chart of the validated schedule and exit
+    ///
+    /// The chart shows the windows of every partition and the channel swaps
+    /// within a major frame, followed by the utilization of every partition,
+    /// the idle time and the tightest gap between two windows.
+    #[clap(long)]
+    print_schedule: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct DoctorArgs {
+    /// Configuration file whose partition images are checked as well
+    #[clap()]
+    config_file: Option<PathBuf>,
+
+    /// Target cgroup to check
+    #[clap(short = 'g', long)]
+    cgroup: Option<PathBuf>,
+
+    /// Runtime directory to check, see the option of the same name of `run`
+    #[clap(long, value_name = "PATH")]
+    runtime_dir: Option<PathBuf>,
+
+    /// Print the results as JSON, e.g. for gating CI jobs
+    #[clap(long)]
+    json: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct ValidatePartitionArgs {
+    /// Configuration file for the hypervisor
+    config_file: PathBuf,
+
+    /// Partition to validate
+    partition: String,
+
+    /// Target cgroup to use
+    #[clap(short = 'g', long)]
+    cgroup: Option<PathBuf>,
+
+    /// Create the partition directly in the target cgroup
+    #[clap(long)]
+    cgroup_use_parent: bool,
+
+    /// Directory the temporary files of the partitions are created in
+    ///
+    /// Defaults to `$XDG_RUNTIME_DIR`, or `/tmp` without it. A tmpfs is
+    /// preferred, as it holds the sockets and mount points of the partitions.
+    #[clap(long, value_name = "PATH")]
+    runtime_dir: Option<PathBuf>,
+
+    /// Time the partition gets to enter NORMAL
+    ///
+    /// Only checked in between scheduling steps, so the window of the
+    /// partition is never interrupted.
+    #[clap(short, long, default_value = "10s")]
+    timeout: humantime::Duration,
+
+    /// Build the partition image if it is given as a cargo package
+    #[clap(long)]
+    allow_cargo_build: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct GenerateArgs {
+    /// Configuration file for the hypervisor
+    config_file: PathBuf,
+
+    /// Partition to generate the crate for
+    partition: String,
+
+    /// Directory of the crate, by default named after the partition
+    #[clap(short, long)]
+    out: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct InspectRecordingArgs {
+    /// Recording written by the hypervisor
+    file: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+struct ListShmArgs {
+    /// Process id of the hypervisor
+    pid: i32,
+}
+
+/// How a run of the hypervisor ended without an unrecoverable error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    /// The run-time limit was reached, a shutdown was requested, or a command
+    /// other than running the hypervisor completed
+    Completed,
+    /// The health monitor shut down the module after an error
+    HmShutdown(SystemError),
+}
+
+impl Exit {
+    /// Exit status for errors the module could not recover from
+    pub const UNRECOVERABLE: u8 = 11;
+
+    /// Exit status of the process, as listed by `--help`
+    pub fn code(&self) -> u8 {
+        match self {
+            Exit::Completed => 0,
+            Exit::HmShutdown(_) => 10,
+        }
+    }
+}
+
+/// Exit status of the process for the result of [run_hypervisor]
+pub fn exit_code(result: &LeveledResult<Exit>) -> u8 {
+    match result {
+        Ok(exit) => exit.code(),
+        Err(_) => Exit::UNRECOVERABLE,
+    }
 }
 
 /// Hypervisor entrypoint
-pub fn run_hypervisor() -> LeveledResult<()> {
-    // Register Handler for SIGINT
-    // Maybe use https://crates.io/crates/signal-hook instead
-    let sig = SigAction::new(
-        SigHandler::Handler(sighdlr),
-        SaFlags::empty(),
-        SigSet::empty(),
-    );
-    unsafe { sigaction(SIGINT, &sig) }.lev_typ(SystemError::Panic, ErrorLevel::ModuleInit)?;
-    unsafe { sigaction(SIGTERM, &sig) }.lev_typ(SystemError::Panic, ErrorLevel::ModuleInit)?;
+pub fn run_hypervisor() -> LeveledResult<Exit> {
+    // Register Handler for SIGINT and SIGTERM
+    shutdown::install().lev_typ(SystemError::Panic, ErrorLevel::ModuleInit)?;
+    // Register Handler for SIGHUP
+    reload::install().lev_typ(SystemError::Panic, ErrorLevel::ModuleInit)?;
 
     trace!("parsing args");
-    let mut args = Args::parse();
+    let args = Args::parse();
+    let mut args = match args.command {
+        Some(Command::Doctor(doctor)) => {
+            return doctor::run(
+                doctor.config_file.as_deref(),
+                doctor.cgroup,
+                doctor.runtime_dir.as_deref(),
+                doctor.json,
+            )
+            .map(|_| Exit::Completed)
+        }
+        Some(Command::ValidatePartition(validate)) => {
+            return validate_partition(validate).map(|_| Exit::Completed)
+        }
+        Some(Command::Generate(args)) => {
+            let config = read_config(&args.config_file)?;
+            return generate::run(&config, &args.partition, args.out.as_deref())
+                .map(|_| Exit::Completed)
+                .lev(ErrorLevel::ModuleInit);
+        }
+        Some(Command::InspectRecording(args)) => {
+            return record::inspect(&args.file)
+                .map(|_| Exit::Completed)
+                .lev(ErrorLevel::ModuleInit);
+        }
+        Some(Command::ListShm(args)) => return list_shm(args.pid).map(|_| Exit::Completed),
+        Some(Command::Run(run)) => run,
+        None => args.run,
+    };
+    info!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+
+    info!("parsing config");
+    let config_file = args
+        .config_file
+        .take()
+        .context("no configuration file given")
+        .lev_typ(SystemError::Config, ErrorLevel::ModuleInit)?;
+    let mut config = load_config(&config_file, args.cgroup.take(), args.cgroup_use_parent)?;
+    if let Some(name) = &args.solo {
+        warn!("Only running partition {name}, the timing of the schedule does not apply");
+        config = config.solo(name).lev(ErrorLevel::ModuleInit)?;
+    }
+    if args.dump_config {
+        let yaml =
+            serde_yaml::to_string(&config).lev_typ(SystemError::Config, ErrorLevel::ModuleInit)?;
+        print!("{yaml}");
+        return Ok(Exit::Completed);
+    }
+    config.verify_shared_state = args.verify_shared_state;
+    config.allow_empty = args.allow_empty;
+    config.replay = args.replay.take();
+    config.runtime_dir = args.runtime_dir.take();
+    if args.print_schedule {
+        config.validate().lev(ErrorLevel::ModuleInit)?;
+        let chart = gantt::render(&config).lev(ErrorLevel::ModuleInit)?;
+        print!("{chart}");
+        return Ok(Exit::Completed);
+    }
+    if args.allow_cargo_build {
+        config.build_cargo_images().lev(ErrorLevel::ModuleInit)?;
+    }
+
+    // Bound once, so that commands are received across module resets
+    let control = args
+        .control_socket
+        .as_deref()
+        .map(ControlSocket::bind)
+        .transpose()
+        .lev(ErrorLevel::ModuleInit)?;
+
+    let mut notifier = Notifier::from_env();
+
+    'module: loop {
+        info!("Start Hypervisor");
+        let mut hv = Hypervisor::new(
+            config.clone(),
+            terminate_after(&args, &config),
+            args.trace_file.as_deref(),
+        )?;
+        let e = loop {
+            match hv.run(control.as_ref(), &mut notifier) {
+                Ok(Stop::Completed) => return Ok(Exit::Completed),
+                Ok(Stop::Reload) => {}
+                Err(e) => break e,
+            }
+            notifier.reloading();
+            match reload_config(&config_file, &config, &args) {
+                // Restarts the module, stopping the running one first
+                Ok(reloaded) => {
+                    config = reloaded;
+                    continue 'module;
+                }
+                Err(e) => {
+                    error!("Could not reload the configuration, keeping the running module: {e:?}");
+                    notifier.reload_failed(
+                        "reloading the configuration failed, running the previous one",
+                    );
+                }
+            }
+        };
+        let action = module_action(&e, &config.hm_init_table, &config.hm_run_table);
+        debug!("Apply Module Recovery Action {action:?} for {e:?}");
+        match action {
+            ModuleRecoveryAction::Ignore => {}
+            ModuleRecoveryAction::Shutdown => return Ok(Exit::HmShutdown(e.err())),
+            ModuleRecoveryAction::Reset => {}
+        }
+    }
+}
+
+/// The run-time limit of `--frames` or `--duration`, if any
+fn terminate_after(args: &RunArgs, config: &Config) -> Option<Duration> {
+    match args.frames {
+        Some(frames) => Some(config.major_frame.saturating_mul(frames)),
+        None => args.duration.map(|d| d.into()),
+    }
+}
+
+/// Reads `config_file` again for a reload of the module running `running`
+///
+/// The cgroup, the scheduler quantum and the options of the command line are
+/// kept. The configuration is validated here already, so that the running
+/// module is only stopped for a configuration it can be replaced with.
+fn reload_config(config_file: &Path, running: &Config, args: &RunArgs) -> LeveledResult<Config> {
+    let mut config = read_config(config_file)?;
+    config.cgroup = running.cgroup.clone();
+    config.cgroup_layout = running.cgroup_layout;
+    config.scheduler_quantum = running.scheduler_quantum;
+    if let Some(name) = &args.solo {
+        config = config.solo(name).lev(ErrorLevel::ModuleInit)?;
+    }
+    config.verify_shared_state = running.verify_shared_state;
+    config.allow_empty = running.allow_empty;
+    config.replay = running.replay.clone();
+    config.runtime_dir = running.runtime_dir.clone();
+    if args.allow_cargo_build {
+        config.build_cargo_images().lev(ErrorLevel::ModuleInit)?;
+    }
+    config.validate().lev(ErrorLevel::ModuleInit)?;
+    Ok(config)
+}
 
+/// Runs the `validate-partition` command
+fn validate_partition(args: ValidatePartitionArgs) -> LeveledResult<()> {
+    let config = load_config(&args.config_file, args.cgroup, args.cgroup_use_parent)?;
+    let mut config = config.solo(&args.partition).lev(ErrorLevel::ModuleInit)?;
+    config.runtime_dir = args.runtime_dir;
+    if args.allow_cargo_build {
+        config.build_cargo_images().lev(ErrorLevel::ModuleInit)?;
+    }
+    validate::run(config, args.timeout.into())
+}
+
+/// Prints the fd, size and name of every channel memfd open in the process
+/// `pid`, one per line
+fn list_shm(pid: i32) -> LeveledResult<()> {
+    let process =
+        procfs::process::Process::new(pid).lev_typ(SystemError::Config, ErrorLevel::ModuleInit)?;
+    let memfds =
+        buffer::channel_memfds(&process).lev_typ(SystemError::Config, ErrorLevel::ModuleInit)?;
+    for memfd in memfds {
+        println!("{memfd}");
+    }
+    Ok(())
+}
+
+/// Reads the configuration from `config_file`, placing the hypervisor in
+/// `cgroup`, or the cgroup of this process by default
+fn load_config(
+    config_file: &Path,
+    cgroup: Option<PathBuf>,
+    cgroup_use_parent: bool,
+) -> LeveledResult<Config> {
     let my_pid =
         procfs::process::Process::myself().lev_typ(SystemError::Panic, ErrorLevel::ModuleInit)?;
     trace!("My pid is {}", my_pid.pid);
@@ -63,7 +477,7 @@ pub fn run_hypervisor() -> LeveledResult<()> {
         .typ(SystemError::CGroup)
         .lev(ErrorLevel::ModuleInit)?;
 
-    let cgroup = args.cgroup.get_or_insert_with(|| {
+    let cgroup = cgroup.unwrap_or_else(|| {
         let cgroups = my_pid
             .cgroups()
             .expect("unable to retrieve my parent cgroup");
@@ -71,54 +485,38 @@ pub fn run_hypervisor() -> LeveledResult<()> {
         let cgroup_path = cgroups.pathname.strip_prefix('/').unwrap(); // this can't fail, the cgroup reported will always start with a leading '/'
         cgroups_mount_point.join(cgroup_path)
     });
-    // Add Additional cgroup layer
-    let cgroup = cgroup.join("linux-hypervisor");
+    let scheduler_quantum = match cgroup::transition_overhead(&cgroup, CALIBRATION_ROUNDS) {
+        Ok(quantum) => {
+            info!(
+                "Freezing and unfreezing a partition takes {}",
+                humantime::Duration::from(quantum)
+            );
+            Some(quantum)
+        }
+        Err(e) => {
+            warn!("Could not measure the scheduler quantum: {e:#}");
+            None
+        }
+    };
+    // Add Additional cgroup layer, unless the cgroup was delegated to us
+    let (cgroup, cgroup_layout) = if cgroup_use_parent {
+        (cgroup, CgroupLayout::Delegated)
+    } else {
+        (cgroup.join("linux-hypervisor"), CgroupLayout::Nested)
+    };
 
-    info!("parsing config");
-    let f = File::open(args.config_file).lev_typ(SystemError::Config, ErrorLevel::ModuleInit)?;
-    let mut config: Config =
-        serde_yaml::from_reader(&f).lev_typ(SystemError::Config, ErrorLevel::ModuleInit)?;
+    let mut config = read_config(config_file)?;
     config.cgroup = cgroup;
-
-    let terminate_after = args.duration.map(|d| d.into());
-
-    loop {
-        info!("Start Hypervisor");
-        match Hypervisor::new(config.clone(), terminate_after)?.run() {
-            Ok(_) => {
-                return Err(anyhow!(
-                    "Hypervisor Run is not supposed to exit with an OK variant"
-                ))
-                .lev_typ(SystemError::Panic, ErrorLevel::ModuleRun)
-            }
-            Err(e) => {
-                let action = match e.level() {
-                    // Partition Level is not expected here
-                    ErrorLevel::Partition => return Err(e),
-                    ErrorLevel::ModuleInit => config
-                        .hm_init_table
-                        .try_action(e.err())
-                        .unwrap_or(config.hm_init_table.panic),
-                    ErrorLevel::ModuleRun => config
-                        .hm_run_table
-                        .try_action(e.err())
-                        .unwrap_or(config.hm_run_table.panic),
-                };
-                match action {
-                    ModuleRecoveryAction::Ignore => {}
-                    ModuleRecoveryAction::Shutdown => return Ok(()),
-                    ModuleRecoveryAction::Reset => {}
-                }
-            }
-        }
-    }
+    config.cgroup_layout = cgroup_layout;
+    config.scheduler_quantum = scheduler_quantum;
+    Ok(config)
 }
 
-pub extern "C" fn sighdlr(_: i32) {
-    print!("\r");
-    std::io::stdout().flush().unwrap();
-    info!("Exiting");
-    quit::with_code(0)
+/// Parses the configuration in `config_file`
+fn read_config(config_file: &Path) -> LeveledResult<Config> {
+    let yaml =
+        fs::read_to_string(config_file).lev_typ(SystemError::Config, ErrorLevel::ModuleInit)?;
+    Config::from_yaml(&yaml).lev(ErrorLevel::ModuleInit)
 }
 
 /// Shorthand macro to return a new
@@ -160,9 +558,13 @@ macro_rules! problem {
 
 #[cfg(test)]
 mod test {
-    use a653rs_linux_core::error::{SystemError, TypedError, TypedResult};
+    use a653rs_linux_core::error::{
+        ErrorLevel, LeveledError, SystemError, TypedError, TypedResult,
+    };
     use anyhow::anyhow;
 
+    use crate::{exit_code, Exit};
+
     fn problem_manual() -> TypedResult<()> {
         let extra_info = "problem";
         let problem = anyhow!("a {extra_info} description");
@@ -174,6 +576,17 @@ mod test {
         problem!(Panic, "a {extra_info} description");
     }
 
+    #[test]
+    fn exit_codes() {
+        assert_eq!(exit_code(&Ok(Exit::Completed)), 0);
+        assert_eq!(
+            exit_code(&Ok(Exit::HmShutdown(SystemError::PartitionInit))),
+            10
+        );
+        let err = LeveledError::new(SystemError::Panic, ErrorLevel::ModuleRun, anyhow!("gone"));
+        assert_eq!(exit_code(&Err(err)), Exit::UNRECOVERABLE);
+    }
+
     #[test]
     fn problem() {
         assert_eq!(
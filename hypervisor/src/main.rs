@@ -1,27 +1,51 @@
 #[macro_use]
 extern crate log;
 
-use a653rs_linux_hypervisor::run_hypervisor;
+use std::io::IsTerminal;
+use std::time::Duration;
+
+use a653rs_linux_hypervisor::hypervisor::log_output::{self, LogWriter, QUEUED_RECORDS};
+use a653rs_linux_hypervisor::{exit_code, run_hypervisor, Exit};
+use env_logger::{Target, WriteStyle};
 use log::LevelFilter;
 
+#[cfg(feature = "mimalloc")]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
 /// Helper to print top-level errors through [log::error]
 #[quit::main]
 fn main() {
     let level = std::env::var("RUST_LOG").unwrap_or_else(|_| "info".into());
     std::env::set_var("RUST_LOG", level.clone());
 
+    // A supervisor closing the pipe of the log must not kill the hypervisor
+    log_output::ignore_sigpipe().unwrap();
+    // Colors are only kept for a terminal, as for the default target
+    let style = if std::io::stderr().is_terminal() {
+        WriteStyle::Always
+    } else {
+        WriteStyle::Never
+    };
+    let output = LogWriter::spawn(std::io::stderr(), QUEUED_RECORDS).unwrap();
     pretty_env_logger::formatted_builder()
         .parse_filters(&level)
         //.format(a653rs_linux_core::log_helper::format)
         .filter_module("polling", LevelFilter::Off)
         .format_timestamp_secs()
+        .target(Target::Pipe(Box::new(output.clone())))
+        .write_style(style)
         .init();
 
-    match run_hypervisor() {
-        Ok(_) => {}
-        Err(e) => {
-            error!("{e}");
-            quit::with_code(1);
+    let result = run_hypervisor();
+    match &result {
+        Ok(Exit::Completed) => {}
+        Ok(Exit::HmShutdown(err)) => {
+            error!("The health monitor shut down the module after {err:?}")
         }
+        Err(e) => error!("{e}"),
     }
+    // Written by a thread of its own, which would be stopped by the exit
+    output.drain(Duration::from_secs(1));
+    quit::with_code(exit_code(&result));
 }
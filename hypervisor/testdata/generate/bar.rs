@@ -0,0 +1,59 @@
+use a653rs::partition;
+use a653rs::prelude::PartitionExt;
+use a653rs_linux::partition::ApexLogger;
+use log::LevelFilter;
+
+fn main() {
+    ApexLogger::install_panic_hook();
+    ApexLogger::install_logger(LevelFilter::Info).unwrap();
+
+    bar::Partition.run()
+}
+
+#[partition(a653rs_linux::partition::ApexLinuxPartition)]
+mod bar {
+    use log::info;
+
+    #[sampling_in(name = "Hello", msg_size = "10000B", refresh_period = "1s")]
+    struct Hello;
+
+    #[start(cold)]
+    fn cold_start(mut ctx: start::Context) {
+        ctx.create_hello().unwrap();
+        ctx.create_periodic_process().unwrap().start().unwrap();
+        ctx.create_aperiodic_process().unwrap().start().unwrap();
+    }
+
+    #[start(warm)]
+    fn warm_start(ctx: start::Context) {
+        cold_start(ctx);
+    }
+
+    #[periodic(
+        period = "0ms",
+        time_capacity = "Infinite",
+        stack_size = "100KB",
+        base_priority = 1,
+        deadline = "Soft"
+    )]
+    fn periodic_process(ctx: periodic_process::Context) {
+        info!("started periodic process");
+        loop {
+            // TODO: the work of each period
+
+            ctx.periodic_wait().unwrap();
+        }
+    }
+
+    #[aperiodic(
+        time_capacity = "Infinite",
+        stack_size = "100KB",
+        base_priority = 1,
+        deadline = "Soft"
+    )]
+    fn aperiodic_process(ctx: aperiodic_process::Context) {
+        info!("started aperiodic process");
+        // TODO: background work, e.g. waiting for messages on a queuing port
+        let _ = ctx;
+    }
+}
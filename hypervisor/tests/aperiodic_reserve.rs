@@ -0,0 +1,62 @@
+//! Runs the `aperiodic_reserve` example, whose periodic process spins through
+//! every window, and checks that the aperiodic process still gets the reserve
+//! of 20ms of each 100ms window, as exported by the telemetry file
+//!
+//! Like the examples, this needs a delegated cgroup and the musl target of
+//! the host, e.g. `x86_64-unknown-linux-musl`, for the partition image, so it
+//! is ignored by default:
+//!
+//! ```sh
+//! systemd-run --user --scope cargo test -p a653rs-linux-hypervisor --test aperiodic_reserve -- --ignored
+//! ```
+
+use std::fs;
+
+mod common;
+
+/// Slack for the scheduling of the hypervisor, which competes with the
+/// spinning process when it has to freeze it
+const TOLERANCE: f64 = 0.01;
+
+/// The time a phase of the windows took in the last major frame, as exported
+/// to `telemetry`
+fn phase(telemetry: &str, phase: &str) -> f64 {
+    let series =
+        format!("a653rs_partition_window_seconds{{partition=\"Greedy\",phase=\"{phase}\"}} ");
+    telemetry
+        .lines()
+        .find_map(|line| line.strip_prefix(&series))
+        .unwrap_or_else(|| panic!("no {phase} phase exported\n{telemetry}"))
+        .parse()
+        .unwrap()
+}
+
+#[test]
+#[ignore = "needs a delegated cgroup and the musl target of the host"]
+fn aperiodic_reserve() {
+    let dir = tempfile::tempdir().unwrap();
+    let telemetry_file = dir.path().join("aperiodic_reserve.prom");
+    let config = format!(
+        "telemetry_file: {}\n{}",
+        telemetry_file.display(),
+        include_str!("../../examples/aperiodic_reserve/aperiodic_reserve.yaml").replace(
+            "image: aperiodic_reserve",
+            &format!("image: {}", common::image("aperiodic_reserve")),
+        )
+    );
+    let config_file = dir.path().join("aperiodic_reserve.yaml");
+    fs::write(&config_file, config).unwrap();
+    let log = common::output(common::hypervisor(&config_file).arg("--duration").arg("3s"));
+
+    assert!(
+        log.contains("periodic process of partition Greedy overran into the aperiodic reserve"),
+        "{log}"
+    );
+    assert!(log.contains("Background work 5"), "{log}");
+
+    let telemetry = fs::read_to_string(&telemetry_file).unwrap();
+    let periodic = phase(&telemetry, "periodic");
+    let aperiodic = phase(&telemetry, "aperiodic");
+    assert!(periodic <= 0.08 + TOLERANCE, "{telemetry}");
+    assert!(aperiodic >= 0.02 - TOLERANCE, "{telemetry}");
+}
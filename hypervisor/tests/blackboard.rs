@@ -0,0 +1,36 @@
+//! Runs the `blackboard` example, whose periodic process displays and clears
+//! messages on a blackboard read by its aperiodic process, and checks that the
+//! reader sees every change
+//!
+//! Like the examples, this needs a delegated cgroup and the musl target of
+//! the host, e.g. `x86_64-unknown-linux-musl`, for the partition image, so it
+//! is ignored by default:
+//!
+//! ```sh
+//! systemd-run --user --scope cargo test -p a653rs-linux-hypervisor --test blackboard -- --ignored
+//! ```
+
+mod common;
+
+#[test]
+#[ignore = "needs a delegated cgroup and the musl target of the host"]
+fn blackboard() {
+    let config = common::single_partition("Blackboard", "blackboard", "500ms", "100ms");
+    let log = common::run(&config, "3s");
+
+    // Every change of the blackboard is seen by the reader, in order
+    common::assert_in_order(
+        &log,
+        [
+            "Writer displayed message 1",
+            "Reader read message 1",
+            "Writer cleared the blackboard",
+            "Reader found the blackboard empty",
+            // The reader waits for the next message since it found the blackboard empty
+            "Writer sees waiting processes: 1",
+            "Writer displayed message 3",
+            "Reader read message 3",
+        ],
+    );
+    assert!(!log.contains("Reader did not read a message"), "{log}");
+}
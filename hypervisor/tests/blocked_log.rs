@@ -0,0 +1,97 @@
+//! Runs a chatty module with the log of the hypervisor going to a pipe which
+//! is never read, and to a closed one, and checks in the trace that the major
+//! frames keep their period
+//!
+//! Like the examples, this needs a delegated cgroup and the musl target of
+//! the host, e.g. `x86_64-unknown-linux-musl`, for the partition image, so it
+//! is ignored by default:
+//!
+//! ```sh
+//! systemd-run --user --scope cargo test -p a653rs-linux-hypervisor --test blocked_log -- --ignored
+//! ```
+
+use std::fs;
+use std::path::Path;
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+mod common;
+
+const FRAMES: usize = 30;
+
+/// The aperiodic process of `hello_part` logs a message every millisecond,
+/// which the hypervisor logs in turn
+fn config() -> String {
+    common::single_partition("Foo", "hello_part", "100ms", "20ms")
+}
+
+/// Runs the module, with its stdout and stderr `closed` or left unread, and
+/// returns the start times of the partition windows in the trace
+fn window_starts(dir: &Path, closed: bool) -> Vec<Duration> {
+    let config_file = dir.join("blocked_log.yaml");
+    fs::write(&config_file, config()).unwrap();
+    let trace_file = dir.join("trace.json");
+
+    let mut child = common::hypervisor(&config_file)
+        .env("RUST_LOG", "trace")
+        .arg("--frames")
+        .arg(FRAMES.to_string())
+        .arg("--trace-file")
+        .arg(&trace_file)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Closed right away, or held open without ever being read
+    let pipes = (child.stdout.take(), child.stderr.take());
+    if closed {
+        drop(pipes);
+    } else {
+        std::mem::forget(pipes);
+    }
+
+    // Generous, as the image may have to be built first
+    let deadline = Instant::now() + Duration::from_secs(600);
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            panic!("the hypervisor did not finish {FRAMES} frames");
+        }
+        sleep(Duration::from_millis(100));
+    };
+    assert!(status.success(), "{status}");
+
+    let trace = fs::read_to_string(trace_file).unwrap();
+    trace
+        .lines()
+        .filter(|event| event.contains(r#""name":"schedule""#))
+        .map(|event| {
+            let ts = event.split(r#""ts":"#).nth(1).unwrap();
+            let ts = ts.split(',').next().unwrap();
+            Duration::from_secs_f64(ts.parse::<f64>().unwrap() / 1e6)
+        })
+        .collect()
+}
+
+fn assert_periodic(starts: &[Duration]) {
+    assert_eq!(starts.len(), FRAMES, "{starts:?}");
+    for pair in starts.windows(2) {
+        let period = pair[1] - pair[0];
+        assert!(
+            period < Duration::from_millis(150),
+            "a frame took {period:?}: {starts:?}"
+        );
+    }
+}
+
+#[test]
+#[ignore = "needs a delegated cgroup and the musl target of the host"]
+fn blocked_log() {
+    let dir = tempfile::tempdir().unwrap();
+    assert_periodic(&window_starts(dir.path(), false));
+    assert_periodic(&window_starts(dir.path(), true));
+}
@@ -0,0 +1,31 @@
+//! Runs the `buffer` example, whose aperiodic process sends messages to a
+//! buffer faster than its periodic process receives them, and checks that the
+//! overflow is noticed and every message is received in order
+//!
+//! Like the examples, this needs a delegated cgroup and the musl target of
+//! the host, e.g. `x86_64-unknown-linux-musl`, for the partition image, so it
+//! is ignored by default:
+//!
+//! ```sh
+//! systemd-run --user --scope cargo test -p a653rs-linux-hypervisor --test buffer -- --ignored
+//! ```
+
+mod common;
+
+#[test]
+#[ignore = "needs a delegated cgroup and the musl target of the host"]
+fn buffer() {
+    let config = common::single_partition("Buffer", "buffer", "500ms", "100ms");
+    let log = common::run(&config, "3s");
+
+    // The producer blocks on the full buffer until the consumer takes messages
+    assert!(log.contains("Producer found the buffer full"), "{log}");
+    assert!(log.contains("waiting processes: 1"), "{log}");
+    // Every message is received once, in order
+    common::assert_in_order(
+        &log,
+        (1..=8).map(|i| format!("Consumer received message {i}")),
+    );
+    assert!(!log.contains("out of order"), "{log}");
+    assert!(!log.contains("Producer did not send"), "{log}");
+}
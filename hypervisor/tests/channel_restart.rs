@@ -0,0 +1,35 @@
+//! Runs the `channel_restart` example, whose receiver restarts with a message
+//! on each of its ports unread, and checks that only the channels configured
+//! with `on_partition_restart: clear` lost their message
+//!
+//! Like the examples, this needs a delegated cgroup and the musl target of
+//! the host, e.g. `x86_64-unknown-linux-musl`, for the partition image, so it
+//! is ignored by default:
+//!
+//! ```sh
+//! systemd-run --user --scope cargo test -p a653rs-linux-hypervisor --test channel_restart -- --ignored
+//! ```
+
+mod common;
+
+#[test]
+#[ignore = "needs a delegated cgroup and the musl target of the host"]
+fn channel_restart() {
+    let config = include_str!("../../examples/channel_restart/channel_restart.yaml").replace(
+        "image: channel_restart",
+        &format!("image: {}", common::image("channel_restart")),
+    );
+    let log = common::run(&config, "3s");
+
+    common::assert_in_order(
+        &log,
+        [
+            "Sent a message on every port",
+            "Restarting with unread messages",
+            r#"kept_sampling holds "before restart" after the restart"#,
+            "cleared_sampling is empty after the restart",
+            r#"kept_queuing holds "before restart" after the restart"#,
+            "cleared_queuing is empty after the restart",
+        ],
+    );
+}
@@ -0,0 +1,80 @@
+//! Running the hypervisor on modules of example partitions, shared by the
+//! integration tests
+//!
+//! The images are built by the hypervisor from the workspace of the current
+//! directory, for the musl target of the host.
+
+// Every test uses only some of the helpers
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use a653rs_linux_hypervisor::hypervisor::config::CargoImage;
+
+/// The image of a partition, built from the example `package`
+pub fn image(package: &str) -> String {
+    format!(
+        "{{ cargo: {{ package: {package}, target: {}, profile: release }} }}",
+        CargoImage::HOST_MUSL_TARGET
+    )
+}
+
+/// A module with the single partition `name`, running the example `package`
+/// in a window of `duration` at the start of every major frame of `period`
+pub fn single_partition(name: &str, package: &str, period: &str, duration: &str) -> String {
+    format!(
+        r#"major_frame: {period}
+partitions:
+  - id: 0
+    name: {name}
+    duration: {duration}
+    offset: 0ms
+    period: {period}
+    image: {}
+"#,
+        image(package)
+    )
+}
+
+/// The hypervisor for the module of `config_file`, logging at the info level
+pub fn hypervisor(config_file: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_a653rs-linux-hypervisor"));
+    command
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .env("RUST_LOG", "info")
+        .arg(config_file)
+        .arg("--allow-cargo-build");
+    command
+}
+
+/// Runs `command` until it exits, returning its log after asserting that it
+/// succeeded
+pub fn output(command: &mut Command) -> String {
+    let output = command.output().unwrap();
+    let log = String::from_utf8_lossy(&output.stderr).into_owned();
+    assert!(output.status.success(), "{}\n{log}", output.status);
+    log
+}
+
+/// Runs the module `config` for `duration`, returning the log
+pub fn run(config: &str, duration: &str) -> String {
+    let dir = tempfile::tempdir().unwrap();
+    let config_file = dir.path().join("module.yaml");
+    fs::write(&config_file, config).unwrap();
+    output(hypervisor(&config_file).arg("--duration").arg(duration))
+}
+
+/// Asserts that `lines` occur in `log` in this order, each one after the end
+/// of the one before
+pub fn assert_in_order<S: AsRef<str>>(log: &str, lines: impl IntoIterator<Item = S>) {
+    let mut at = 0;
+    for line in lines {
+        let line = line.as_ref();
+        let found = log[at..]
+            .find(line)
+            .unwrap_or_else(|| panic!("{line:?} does not follow in the log\n{log}"));
+        at += found + line.len();
+    }
+}
@@ -0,0 +1,31 @@
+//! Runs the `conformance` example, whose partition runs the smoke tests of
+//! all implemented APEX services, and checks that every test passed
+//!
+//! Like the examples, this needs a delegated cgroup and the musl target of
+//! the host, e.g. `x86_64-unknown-linux-musl`, for the partition image, so it
+//! is ignored by default:
+//!
+//! ```sh
+//! systemd-run --user --scope cargo test -p a653rs-linux-hypervisor --test conformance -- --ignored
+//! ```
+
+mod common;
+
+#[test]
+#[ignore = "needs a delegated cgroup and the musl target of the host"]
+fn conformance() {
+    let config = include_str!("../../examples/conformance/conformance.yaml").replace(
+        "image: conformance",
+        &format!("image: {}", common::image("conformance")),
+    );
+    let log = common::run(&config, "2s");
+
+    assert!(!log.contains("failed"), "{log}");
+    let (passed, tests) = log
+        .lines()
+        .find_map(|line| line.split_once(" smoke tests passed")?.0.rsplit_once("] "))
+        .and_then(|(_, counts)| counts.split_once(" of "))
+        .unwrap_or_else(|| panic!("the summary is missing from\n{log}"));
+    assert_eq!(passed, tests, "{log}");
+    assert_ne!(tests, "0", "{log}");
+}
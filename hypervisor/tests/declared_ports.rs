@@ -0,0 +1,56 @@
+//! Runs the `declared_ports` example, whose partition declares its ports, with
+//! a channel matching the declaration and with one that does not, and checks
+//! that `strict_ports` keeps the mismatching partition from reaching NORMAL
+//!
+//! Like the examples, this needs a delegated cgroup and the musl target of
+//! the host, e.g. `x86_64-unknown-linux-musl`, for the partition image, so it
+//! is ignored by default:
+//!
+//! ```sh
+//! systemd-run --user --scope cargo test -p a653rs-linux-hypervisor --test declared_ports -- --ignored
+//! ```
+
+mod common;
+
+const MISMATCH: &str = r#"Partition Sensor: sampling port "reading" is declared with msg_size 8, but configured with 16"#;
+
+/// The example, with the channel of `msg_size` and the partition with
+/// `strict_ports`
+fn config(msg_size: &str, strict_ports: bool) -> String {
+    let mut config = include_str!("../../examples/declared_ports/declared_ports.yaml")
+        .replace(
+            "image: declared_ports",
+            &format!("image: {}", common::image("declared_ports")),
+        )
+        .replace("msg_size: 8B", &format!("msg_size: {msg_size}"));
+    if strict_ports {
+        config = config.replace(
+            "    image:",
+            "    strict_ports: true\n    hm_table:\n      partition_init: !Partition Idle\n    image:",
+        );
+    }
+    config
+}
+
+#[test]
+#[ignore = "needs a delegated cgroup and the musl target of the host"]
+fn declared_ports() {
+    let log = common::run(&config("8B", true), "2s");
+    assert!(!log.contains("is declared"), "{log}");
+    assert!(log.contains("Running in NORMAL with the port"), "{log}");
+
+    // The mismatch is reported before the port creation fails
+    let log = common::run(&config("16B", false), "2s");
+    common::assert_in_order(
+        &log,
+        [
+            MISMATCH,
+            "Could not create the port reading: InvalidConfig",
+            "Running in NORMAL without the port",
+        ],
+    );
+
+    let log = common::run(&config("16B", true), "2s");
+    assert!(log.contains(MISMATCH), "{log}");
+    assert!(!log.contains("Running in NORMAL"), "{log}");
+}
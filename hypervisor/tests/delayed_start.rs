@@ -0,0 +1,68 @@
+//! Runs the `delayed_start` example in two partitions with different delays,
+//! and checks that their periodic processes are released at the start of
+//! their windows plus the delay
+//!
+//! Like the examples, this needs a delegated cgroup and the musl target of
+//! the host, e.g. `x86_64-unknown-linux-musl`, for the partition image, so it
+//! is ignored by default:
+//!
+//! ```sh
+//! systemd-run --user --scope cargo test -p a653rs-linux-hypervisor --test delayed_start -- --ignored
+//! ```
+
+mod common;
+
+const MAJOR_FRAME: f64 = 0.5;
+
+/// Slack for the scheduling of the hypervisor and the partition
+const TOLERANCE: f64 = 0.03;
+
+/// Offsets into the major frame at which the process of `partition` logged
+/// its releases
+fn releases(log: &str, partition: &str) -> Vec<f64> {
+    let target = format!("Partition: {partition}");
+    log.lines()
+        .filter(|line| line.contains(&target) && line.contains("Released after"))
+        // The module time of the record, as in `[1.050123 periodic ] Released`
+        .filter_map(|line| line.split_once(" periodic ]")?.0.rsplit_once('['))
+        .map(|(_, time)| time.trim())
+        .map(|time| time.parse::<f64>().unwrap() % MAJOR_FRAME)
+        .collect()
+}
+
+#[test]
+#[ignore = "needs a delegated cgroup and the musl target of the host"]
+fn delayed_start() {
+    let image = common::image("delayed_start");
+    let config = format!(
+        r#"major_frame: 500ms
+partitions:
+  - id: 0
+    name: Early
+    duration: 200ms
+    offset: 0ms
+    period: 500ms
+    image: {image}
+    role: "50"
+  - id: 1
+    name: Late
+    duration: 200ms
+    offset: 250ms
+    period: 500ms
+    image: {image}
+    role: "150"
+"#
+    );
+    let log = common::run(&config, "3s");
+
+    for (partition, release) in [("Early", 0.05), ("Late", 0.4)] {
+        let releases = releases(&log, partition);
+        assert!(releases.len() > 1, "{partition}\n{log}");
+        for time in releases {
+            assert!(
+                time >= release && time < release + TOLERANCE,
+                "{partition} released at {time} instead of {release}\n{log}"
+            );
+        }
+    }
+}
@@ -0,0 +1,51 @@
+//! Runs a module without partitions for two major frames with `--allow-empty`
+//!
+//! Like the examples, this needs a delegated cgroup, so it is ignored by
+//! default:
+//!
+//! ```sh
+//! systemd-run --user --scope cargo test -p a653rs-linux-hypervisor --test empty_module -- --ignored
+//! ```
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const CONFIG: &str = "major_frame: 100ms\npartitions: []\n";
+
+fn hypervisor(config_file: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_a653rs-linux-hypervisor"));
+    command
+        .env("RUST_LOG", "info")
+        .arg(config_file)
+        .arg("--frames")
+        .arg("2");
+    command
+}
+
+#[test]
+#[ignore = "needs a delegated cgroup"]
+fn empty_module() {
+    let dir = tempfile::tempdir().unwrap();
+    let config_file = dir.path().join("empty_module.yaml");
+    fs::write(&config_file, CONFIG).unwrap();
+
+    // Rejected without the flag
+    let output = hypervisor(&config_file).output().unwrap();
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "{}\n{log}", output.status);
+    assert!(log.contains("no partitions are configured"), "{log}");
+
+    // Idles in two empty major frames otherwise
+    let start = Instant::now();
+    let output = hypervisor(&config_file)
+        .arg("--allow-empty")
+        .output()
+        .unwrap();
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}\n{log}", output.status);
+    assert!(log.contains("No partitions are configured"), "{log}");
+    assert!(log.contains("a run-time of 200ms was reached"), "{log}");
+    assert!(start.elapsed() >= Duration::from_millis(200));
+}
@@ -0,0 +1,38 @@
+//! Runs the `event` example, whose periodic process sets an event in every
+//! period while its aperiodic process waits for it, and checks that every
+//! set wakes up the waiting process
+//!
+//! Like the examples, this needs a delegated cgroup and the musl target of
+//! the host, e.g. `x86_64-unknown-linux-musl`, for the partition image, so it
+//! is ignored by default:
+//!
+//! ```sh
+//! systemd-run --user --scope cargo test -p a653rs-linux-hypervisor --test event -- --ignored
+//! ```
+
+mod common;
+
+#[test]
+#[ignore = "needs a delegated cgroup and the musl target of the host"]
+fn event() {
+    let config = common::single_partition("Event", "event", "500ms", "100ms");
+    let log = common::run(&config, "3s");
+
+    // The listener waits for the event while the partition is frozen, and is
+    // woken once per period of the ticker
+    common::assert_in_order(
+        &log,
+        [
+            "Ticker sets the event in period 1",
+            "Listener woke up",
+            "Ticker sees waiting processes: 1",
+            "Ticker sets the event in period 2",
+            "Listener woke up",
+            "Ticker sees waiting processes: 1",
+            "Ticker sets the event in period 3",
+            "Listener woke up",
+        ],
+    );
+    assert!(!log.contains("Listener did not"), "{log}");
+    assert!(!log.contains("still up"), "{log}");
+}
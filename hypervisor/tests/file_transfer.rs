@@ -0,0 +1,113 @@
+//! Transfers a megabyte of pseudo-random bytes with the `file_transfer`
+//! example, whose partitions exchange the file in chunks over a queuing
+//! channel of 32 messages of 64KB
+//!
+//! Like the examples, this needs a delegated cgroup and the musl target of
+//! the host, e.g. `x86_64-unknown-linux-musl`, for the partition images, so it
+//! is ignored by default:
+//!
+//! ```sh
+//! systemd-run --user --scope cargo test -p a653rs-linux-hypervisor --test file_transfer -- --ignored
+//! ```
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::Hasher;
+use std::path::Path;
+use std::process::Command;
+
+use a653rs_linux_hypervisor::hypervisor::config::CargoImage;
+
+fn hash(data: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(data);
+    hasher.finish()
+}
+
+#[test]
+#[ignore = "needs a delegated cgroup and the musl target of the host"]
+fn file_transfer() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let dir = tempfile::tempdir().unwrap();
+    let outgoing = dir.path().join("outgoing");
+    let incoming = dir.path().join("incoming");
+    fs::create_dir(&outgoing).unwrap();
+    fs::create_dir(&incoming).unwrap();
+
+    // Differs from the file the sender generates itself
+    let mut state = 0x9e37_79b9_u32;
+    let sent = (0..1 << 20)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (state >> 8) as u8
+        })
+        .collect::<Vec<u8>>();
+    fs::write(outgoing.join("file.bin"), &sent).unwrap();
+
+    let image = |package: &str| {
+        format!(
+            "{{ cargo: {{ package: {package}, target: {}, profile: release }} }}",
+            CargoImage::HOST_MUSL_TARGET
+        )
+    };
+    let config = format!(
+        r#"major_frame: 1s
+partitions:
+  - id: 0
+    name: file_transfer_sender
+    duration: 100ms
+    offset: 0ms
+    period: 1s
+    image: {}
+    mounts:
+      - [ {}, /data ]
+  - id: 1
+    name: file_transfer_receiver
+    duration: 100ms
+    offset: 500ms
+    period: 1s
+    image: {}
+    mounts:
+      - [ {}, /data ]
+channel:
+  - !Queuing
+    msg_size: 64KB
+    msg_num: 32
+    source:
+      partition: file_transfer_sender
+      port: file_out
+    destination:
+      partition: file_transfer_receiver
+      port: file_in
+"#,
+        image("file_transfer_sender"),
+        outgoing.display(),
+        image("file_transfer_receiver"),
+        incoming.display()
+    );
+    let config_file = dir.path().join("file_transfer.yaml");
+    fs::write(&config_file, config).unwrap();
+
+    // The images are built from the workspace of the current directory
+    let output = Command::new(env!("CARGO_BIN_EXE_a653rs-linux-hypervisor"))
+        .current_dir(manifest_dir)
+        .env("RUST_LOG", "info")
+        .arg(&config_file)
+        .arg("--allow-cargo-build")
+        .arg("--duration")
+        .arg("3s")
+        .output()
+        .unwrap();
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}\n{log}", output.status);
+
+    let received = fs::read(incoming.join("received.bin")).unwrap();
+    assert_eq!(received.len(), sent.len(), "{log}");
+    assert_eq!(hash(&received), hash(&sent), "{log}");
+    assert!(
+        log.contains(&format!("with hash {:016x}", hash(&sent))),
+        "{log}"
+    );
+}
@@ -0,0 +1,27 @@
+//! Runs the `fork` example, whose periodic process forks, and checks that the
+//! forked child is refused the services of the partition
+//!
+//! Like the examples, this needs a delegated cgroup and the musl target of
+//! the host, e.g. `x86_64-unknown-linux-musl`, for the partition image, so it
+//! is ignored by default:
+//!
+//! ```sh
+//! systemd-run --user --scope cargo test -p a653rs-linux-hypervisor --test fork -- --ignored
+//! ```
+
+mod common;
+
+#[test]
+#[ignore = "needs a delegated cgroup and the musl target of the host"]
+fn forked_children_are_refused() {
+    let config = common::single_partition("Fork", "fork", "1s", "300ms");
+    let log = common::run(&config, "3s");
+
+    assert!(
+        log.contains("The forked child was refused the services of the partition"),
+        "{log}"
+    );
+    // Neither the message of the child, nor its log records reach the hypervisor
+    assert!(!log.contains("Message of the forked child"), "{log}");
+    assert!(!log.contains("forked child of the partition"), "{log}");
+}
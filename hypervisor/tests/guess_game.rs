@@ -0,0 +1,82 @@
+//! Runs the `guess_game` example with `stdin: pipe`, and feeds its guesses to
+//! the stdin of the partition through the control socket
+//!
+//! Like the examples, this needs a delegated cgroup and the musl target of
+//! the host, e.g. `x86_64-unknown-linux-musl`, for the partition image, so it
+//! is ignored by default:
+//!
+//! ```sh
+//! systemd-run --user --scope cargo test -p a653rs-linux-hypervisor --test guess_game -- --ignored
+//! ```
+
+use std::fs::{self, File};
+use std::os::unix::net::UnixDatagram;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+mod common;
+
+#[test]
+#[ignore = "needs a delegated cgroup and the musl target of the host"]
+fn guess_game() {
+    let dir = tempfile::tempdir().unwrap();
+    let config_file = dir.path().join("module.yaml");
+    let config = include_str!("../../examples/guess_game/guess_game.yaml").replace(
+        "image: guess_game",
+        &format!("image: {}", common::image("guess_game")),
+    );
+    fs::write(&config_file, config).unwrap();
+    let control = dir.path().join("control.sock");
+    // A file rather than a pipe, which the log of the build could fill
+    let log_file = dir.path().join("hypervisor.log");
+    let mut hypervisor = common::hypervisor(&config_file)
+        .arg("--control-socket")
+        .arg(&control)
+        .args(["--duration", "2s"])
+        .stderr(File::create(&log_file).unwrap())
+        .spawn()
+        .unwrap();
+
+    // The socket is bound once the image is built
+    let deadline = Instant::now() + Duration::from_secs(600);
+    while !control.exists() {
+        assert!(
+            Instant::now() < deadline,
+            "the control socket was not bound"
+        );
+        sleep(Duration::from_millis(100));
+    }
+    let client = UnixDatagram::bind(dir.path().join("client.sock")).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let mut reply = [0; 256];
+    let mut send = |command: String| {
+        client.send_to(command.as_bytes(), &control).unwrap();
+        let len = client.recv(&mut reply).unwrap();
+        String::from_utf8_lossy(&reply[..len]).into_owned()
+    };
+
+    let guesses = STANDARD.encode("50\nmany\n42\n");
+    assert_eq!(
+        send(format!("stdin GuessGame {guesses}")),
+        "ok: wrote 11 bytes to the stdin of GuessGame\n"
+    );
+    assert!(send(format!("stdin Nobody {guesses}")).starts_with("error: unknown partition"));
+
+    let status = hypervisor.wait().unwrap();
+    let log = fs::read_to_string(&log_file).unwrap();
+    assert!(status.success(), "{status}\n{log}");
+    common::assert_in_order(
+        &log,
+        [
+            "Guess a number between 0 and 100",
+            "50 is too high",
+            r#""many" is not a number"#,
+            "42 is correct",
+        ],
+    );
+}
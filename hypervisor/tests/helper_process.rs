@@ -0,0 +1,55 @@
+//! Runs the `helper_process` example, whose writer partition spawns a helper
+//! process that writes the sampling port of the partition, and checks that
+//! the reader partition receives the message of the helper
+//!
+//! Like the examples, this needs a delegated cgroup and the musl target of
+//! the host, e.g. `x86_64-unknown-linux-musl`, for the partition image, so it
+//! is ignored by default:
+//!
+//! ```sh
+//! systemd-run --user --scope cargo test -p a653rs-linux-hypervisor --test helper_process -- --ignored
+//! ```
+
+mod common;
+
+#[test]
+#[ignore = "needs a delegated cgroup and the musl target of the host"]
+fn helper_process() {
+    let image = common::image("helper_process");
+    let config = format!(
+        r#"major_frame: 1s
+partitions:
+  - id: 0
+    name: Writer
+    duration: 100ms
+    offset: 0ms
+    period: 1s
+    image: {image}
+    role: writer
+  - id: 1
+    name: Reader
+    duration: 100ms
+    offset: 500ms
+    period: 1s
+    image: {image}
+    role: reader
+channel:
+  - !Sampling
+    msg_size: 64B
+    source:
+      partition: Writer
+      port: Converted
+    destination:
+      - partition: Reader
+        port: Converted
+"#
+    );
+    let log = common::run(&config, "3s");
+
+    assert!(log.contains("helper wrote 19 bytes"), "{log}");
+    assert!(log.contains("helper exited with exit status: 0"), "{log}");
+    assert!(
+        log.contains("received \"converted by helper\", Valid"),
+        "{log}"
+    );
+}
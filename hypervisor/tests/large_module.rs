@@ -0,0 +1,125 @@
+//! Runs the module of `testdata/large_module.yaml`, whose eight partitions
+//! check the patterns of the messages they exchange on 31 channels
+//!
+//! Like the examples, this needs a delegated cgroup and the musl target of
+//! the host, e.g. `x86_64-unknown-linux-musl`, for the partition images, so it
+//! is ignored by default:
+//!
+//! ```sh
+//! systemd-run --user --scope cargo test -p a653rs-linux-hypervisor --release --test large_module -- --ignored
+//! ```
+//!
+//! It also guards against regressions of the time the hypervisor needs for
+//! transferring the messages after a partition window.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+use a653rs_linux_hypervisor::hypervisor::config::CargoImage;
+
+/// Duration of the major frame of the configuration
+const MAJOR_FRAME: Duration = Duration::from_millis(500);
+
+/// Number of major frames to run
+const FRAMES: u32 = 20;
+
+/// Longest transfer of the messages after a partition window
+const MAX_POST_TIMEFRAME: Duration = Duration::from_millis(5);
+
+const PARTITIONS: [&str; 8] = [
+    "nav_sensor",
+    "air_data",
+    "fuel_monitor",
+    "flight_control",
+    "autopilot",
+    "display",
+    "maintenance",
+    "data_recorder",
+];
+
+#[test]
+#[ignore = "needs a delegated cgroup and the musl target of the host"]
+fn large_module() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let dir = tempfile::tempdir().unwrap();
+    let trace = dir.path().join("trace.json");
+    let telemetry = dir.path().join("telemetry.prom");
+
+    // The configuration names the target of x86_64 hosts
+    let config = fs::read_to_string(manifest_dir.join("testdata/large_module.yaml"))
+        .unwrap()
+        .replace("x86_64-unknown-linux-musl", CargoImage::HOST_MUSL_TARGET);
+    let config_file = dir.path().join("large_module.yaml");
+    let config = format!("{config}telemetry_file: {}\n", telemetry.display());
+    fs::write(&config_file, config).unwrap();
+
+    // The run ends with the first major frame starting after the duration
+    let duration = MAJOR_FRAME * (FRAMES - 1);
+    // The images are built from the workspace of the current directory
+    let output = Command::new(env!("CARGO_BIN_EXE_a653rs-linux-hypervisor"))
+        .current_dir(manifest_dir)
+        .env("RUST_LOG", "debug")
+        .arg(&config_file)
+        .arg("--allow-cargo-build")
+        .arg("--duration")
+        .arg(format!("{}ms", duration.as_millis()))
+        .arg("--trace-file")
+        .arg(&trace)
+        .output()
+        .unwrap();
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}\n{log}", output.status);
+    assert!(
+        !log.contains("received err"),
+        "the health monitor handled errors:\n{log}"
+    );
+
+    let gauges = gauges(&fs::read_to_string(&telemetry).unwrap());
+    for partition in PARTITIONS {
+        let gauge = |name: &str| gauges[&(partition.to_string(), name.to_string())];
+        assert!(gauge("verified_messages") > 0.0, "{partition}");
+        assert_eq!(gauge("mismatched_messages"), 0.0, "{partition}");
+        assert_eq!(gauge("silent_ports"), 0.0, "{partition}");
+    }
+
+    let post_timeframe = post_timeframe_durations(&fs::read_to_string(&trace).unwrap());
+    // Every partition window is followed by a transfer, some partitions have
+    // two windows per major frame
+    assert!(post_timeframe.len() >= PARTITIONS.len() * FRAMES as usize);
+    let longest = post_timeframe.into_iter().max().unwrap();
+    assert!(
+        longest < MAX_POST_TIMEFRAME,
+        "transferring the messages after a window took {longest:?}"
+    );
+}
+
+/// Parses the telemetry file into the gauges by partition and name
+fn gauges(text: &str) -> HashMap<(String, String), f64> {
+    text.lines()
+        .filter_map(|line| line.strip_prefix("a653rs_partition_telemetry{partition=\""))
+        .map(|line| {
+            let (partition, line) = line.split_once("\",name=\"").unwrap();
+            let (name, value) = line.split_once("\"} ").unwrap();
+            (
+                (partition.to_string(), name.to_string()),
+                value.parse().unwrap(),
+            )
+        })
+        .collect()
+}
+
+/// Durations of the transfers after the partition windows in a trace
+fn post_timeframe_durations(trace: &str) -> Vec<Duration> {
+    trace
+        .lines()
+        .filter(|line| line.starts_with(r#"{"name":"post timeframe","#))
+        .map(|line| {
+            let (_, dur) = line.split_once(r#""dur":"#).unwrap();
+            let (micros, _) = dur.split_once(',').unwrap();
+            Duration::from_secs_f64(micros.parse::<f64>().unwrap() / 1e6)
+        })
+        .collect()
+}
@@ -0,0 +1,35 @@
+//! Runs the `mutex` example, whose periodic process holds a mutex across
+//! every other period while its aperiodic process tries to acquire it, and
+//! checks that the mutex is never owned by both
+//!
+//! Like the examples, this needs a delegated cgroup and the musl target of
+//! the host, e.g. `x86_64-unknown-linux-musl`, for the partition image, so it
+//! is ignored by default:
+//!
+//! ```sh
+//! systemd-run --user --scope cargo test -p a653rs-linux-hypervisor --test mutex -- --ignored
+//! ```
+
+mod common;
+
+#[test]
+#[ignore = "needs a delegated cgroup and the musl target of the host"]
+fn mutex() {
+    let config = common::single_partition("Mutex", "mutex", "500ms", "100ms");
+    let log = common::run(&config, "3s");
+
+    // The worker times out while the ticker holds the mutex across its
+    // periodic wait, and gets it once the ticker released it
+    common::assert_in_order(
+        &log,
+        [
+            "Ticker acquired the mutex in period 1",
+            "Worker timed out acquiring the mutex",
+            "Ticker holds the mutex 2 times",
+            "Ticker released the mutex in period 2",
+            "Worker acquired the mutex",
+        ],
+    );
+    assert!(!log.contains("acquired the mutex owned by"), "{log}");
+    assert!(!log.contains("Worker did not"), "{log}");
+}
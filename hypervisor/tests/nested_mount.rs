@@ -0,0 +1,62 @@
+//! Validates a partition with a user mount whose target lies in directories
+//! which do not exist in the partition yet
+//!
+//! Like the examples, this needs a delegated cgroup and the musl target of
+//! the host, e.g. `x86_64-unknown-linux-musl`, for the partition image, so it
+//! is ignored by default:
+//!
+//! ```sh
+//! systemd-run --user --scope cargo test -p a653rs-linux-hypervisor --test nested_mount -- --ignored
+//! ```
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use a653rs_linux_hypervisor::hypervisor::config::CargoImage;
+
+#[test]
+#[ignore = "needs a delegated cgroup and the musl target of the host"]
+fn nested_mount() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("x.yaml");
+    fs::write(&source, "answer: 42\n").unwrap();
+
+    let config = format!(
+        r#"major_frame: 1s
+partitions:
+  - id: 0
+    name: nested_mount
+    duration: 1s
+    offset: 0ms
+    period: 1s
+    image: {{ cargo: {{ package: dev_random, target: {}, profile: release }} }}
+    mounts:
+      - [ /dev/random, /dev/random ]
+      - [ {}, /data/config/x.yaml ]
+"#,
+        CargoImage::HOST_MUSL_TARGET,
+        source.display()
+    );
+    let config_file = dir.path().join("nested_mount.yaml");
+    fs::write(&config_file, config).unwrap();
+
+    // The image is built from the workspace of the current directory
+    let output = Command::new(env!("CARGO_BIN_EXE_a653rs-linux-hypervisor"))
+        .current_dir(manifest_dir)
+        .env("RUST_LOG", "debug")
+        .arg("validate-partition")
+        .arg(&config_file)
+        .arg("nested_mount")
+        .arg("--allow-cargo-build")
+        .output()
+        .unwrap();
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        output.status.success(),
+        "{}\n{}\n{log}",
+        output.status,
+        String::from_utf8_lossy(&output.stdout)
+    );
+}
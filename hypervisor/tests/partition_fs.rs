@@ -0,0 +1,134 @@
+//! Runs the `partition_fs` example, whose partition appends a line to a file
+//! in every period, and checks that the lines synced to the host survive the
+//! hypervisor being killed and are restored on the next start
+//!
+//! Like the examples, this needs a delegated cgroup and the musl target of
+//! the host, e.g. `x86_64-unknown-linux-musl`, for the partition image, so it
+//! is ignored by default:
+//!
+//! ```sh
+//! systemd-run --user --scope cargo test -p a653rs-linux-hypervisor --test partition_fs -- --ignored
+//! ```
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use a653rs_linux_core::cgroup;
+
+mod common;
+
+/// Time for building the image and starting the partition
+const STARTUP: Duration = Duration::from_secs(300);
+
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Asserts that `log` consists of the lines of the periods 1 to n, returning n
+fn periods(log: &str) -> usize {
+    for (i, line) in log.lines().enumerate() {
+        assert_eq!(line, format!("period {}", i + 1), "{log}");
+    }
+    log.lines().count()
+}
+
+#[test]
+#[ignore = "needs a delegated cgroup and the musl target of the host"]
+fn partition_fs() {
+    let dir = tempfile::tempdir().unwrap();
+    let host_dir = dir.path().join("logbook");
+    fs::create_dir(&host_dir).unwrap();
+    let host_log = host_dir.join("log.txt");
+    let cgroup = cgroup::mount_point()
+        .unwrap()
+        .join(cgroup::current_cgroup().unwrap())
+        .join("partition-fs-test");
+    let config = format!(
+        r#"major_frame: 500ms
+cgroup: {}
+partitions:
+  - id: 0
+    name: Logbook
+    duration: 100ms
+    offset: 0ms
+    period: 500ms
+    image: {}
+    partition_fs:
+      host_dir: {}
+      sync_interval: {}ms
+"#,
+        cgroup.display(),
+        common::image("partition_fs"),
+        host_dir.display(),
+        SYNC_INTERVAL.as_millis()
+    );
+    let config_file = dir.path().join("partition_fs.yaml");
+    fs::write(&config_file, config).unwrap();
+
+    let mut child = common::hypervisor(&config_file)
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let start = Instant::now();
+    while fs::read_to_string(&host_log).map_or(true, |log| log.lines().count() < 3) {
+        assert!(start.elapsed() < STARTUP, "no lines were synced");
+        assert!(child.try_wait().unwrap().is_none(), "hypervisor exited");
+        sleep(Duration::from_millis(100));
+    }
+    sleep(SYNC_INTERVAL * 2);
+    let synced = periods(&fs::read_to_string(&host_log).unwrap());
+
+    // No chance for a final sync
+    child.kill().unwrap();
+    child.wait().unwrap();
+    kill_leftover_cgroup(&cgroup, child.id());
+    // At most the lines of the last interval are lost
+    let kept = periods(&fs::read_to_string(&host_log).unwrap());
+    assert!(kept >= synced, "{kept} < {synced}");
+
+    // The partition continues after the restored lines, and its last lines are
+    // synced at the exit
+    let log = common::output(common::hypervisor(&config_file).arg("--duration").arg("3s"));
+    assert!(log.contains(&format!("Restored {kept} lines")), "{log}");
+    let last: usize = log
+        .lines()
+        .filter_map(|line| line.split("Appended period ").nth(1))
+        .next_back()
+        .expect(&log)
+        .trim()
+        .parse()
+        .unwrap();
+    // The log records of the last periods may not have been received
+    let kept_at_exit = periods(&fs::read_to_string(&host_log).unwrap());
+    assert!(
+        kept_at_exit >= last && last > kept,
+        "{kept_at_exit} {last} {kept}"
+    );
+}
+
+/// Kills the partitions of the killed hypervisor `pid`, which it left behind
+/// in its cgroup
+fn kill_leftover_cgroup(cgroup: &Path, pid: u32) {
+    let mut name = cgroup.file_name().unwrap().to_os_string();
+    name.push(format!("-{pid}"));
+    let leftover = cgroup.with_file_name(name);
+    if let Err(e) = fs::write(leftover.join("cgroup.kill"), "1") {
+        eprintln!("could not kill {}: {e}", leftover.display());
+        return;
+    }
+    // The cgroups can only be removed once their processes are gone
+    sleep(Duration::from_millis(500));
+    remove_cgroups(leftover);
+}
+
+fn remove_cgroups(path: PathBuf) {
+    if let Ok(entries) = fs::read_dir(&path) {
+        for entry in entries.flatten() {
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                remove_cgroups(entry.path());
+            }
+        }
+    }
+    fs::remove_dir(&path).ok();
+}
@@ -0,0 +1,23 @@
+//! Runs the `priorities` example, whose two processes of different priorities
+//! compete for a single CPU, and checks that the one of higher priority
+//! finishes its work first
+//!
+//! Like the examples, this needs a delegated cgroup and the musl target of
+//! the host, e.g. `x86_64-unknown-linux-musl`, for the partition image, so it
+//! is ignored by default:
+//!
+//! ```sh
+//! systemd-run --user --scope cargo test -p a653rs-linux-hypervisor --test priorities -- --ignored
+//! ```
+
+mod common;
+
+#[test]
+#[ignore = "needs a delegated cgroup and the musl target of the host"]
+fn priorities() {
+    let config = common::single_partition("Priorities", "priorities", "1s", "300ms");
+    let log = common::run(&config, "3s");
+
+    common::assert_in_order(&log, ["High finished its work", "Low finished its work"]);
+    assert!(log.contains("High now runs at priority 0"), "{log}");
+}
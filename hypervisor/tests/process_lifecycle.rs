@@ -0,0 +1,44 @@
+//! Runs the `process_lifecycle` example, whose processes stop, suspend and
+//! resume each other, and checks that only the started process is reported as
+//! waiting before NORMAL, and that a stopped periodic process neither
+//! blocks the schedule nor keeps running, and starts again from its entry
+//! point
+//!
+//! Like the examples, this needs a delegated cgroup and the musl target of
+//! the host, e.g. `x86_64-unknown-linux-musl`, for the partition image, so it
+//! is ignored by default:
+//!
+//! ```sh
+//! systemd-run --user --scope cargo test -p a653rs-linux-hypervisor --test process_lifecycle -- --ignored
+//! ```
+
+mod common;
+
+#[test]
+#[ignore = "needs a delegated cgroup and the musl target of the host"]
+fn process_lifecycle() {
+    let config = common::single_partition("Lifecycle", "process_lifecycle", "500ms", "100ms");
+    let log = common::run(&config, "5s");
+
+    common::assert_in_order(
+        &log,
+        [
+            "The started ticker is waiting, the supervisor is dormant",
+            "Ticker started, run 1",
+            "Ticker resumed the supervisor",
+            "Supervisor was resumed",
+            "Supervisor timed out suspending itself",
+            "Ticker stops itself",
+            "Ticker is dormant, starting it again",
+            "Ticker started, run 2",
+            "Ticker suspended the supervisor",
+            "The suspended supervisor is waiting",
+            "Ticker resumed the supervisor",
+        ],
+    );
+    // Stopping itself ended the share of the ticker in the window
+    assert!(!log.contains("overran"), "{log}");
+    assert!(!log.contains("Ticker period 4 of run 1"), "{log}");
+    assert!(!log.contains("Ticker continued"), "{log}");
+    assert!(!log.contains("did not"), "{log}");
+}
@@ -0,0 +1,62 @@
+//! Runs the `quiet_partition` example with `suppress_messages`, and checks
+//! that the log records of the partition are counted instead of printed, while
+//! its error is still printed and restarts it through the health monitor
+//!
+//! Like the examples, this needs a delegated cgroup and the musl target of
+//! the host, e.g. `x86_64-unknown-linux-musl`, for the partition image, so it
+//! is ignored by default:
+//!
+//! ```sh
+//! systemd-run --user --scope cargo test -p a653rs-linux-hypervisor --test quiet_partition -- --ignored
+//! ```
+
+use std::fs;
+
+mod common;
+
+#[test]
+#[ignore = "needs a delegated cgroup and the musl target of the host"]
+fn quiet_partition() {
+    let dir = tempfile::tempdir().unwrap();
+    let telemetry_file = dir.path().join("quiet_partition.prom");
+    let config = format!(
+        r#"major_frame: 500ms
+telemetry_file: {}
+partitions:
+  - id: 0
+    name: Chatty
+    duration: 100ms
+    offset: 0ms
+    period: 500ms
+    image: {}
+    suppress_messages: true
+"#,
+        telemetry_file.display(),
+        common::image("quiet_partition")
+    );
+    let config_file = dir.path().join("quiet_partition.yaml");
+    fs::write(&config_file, config).unwrap();
+    let log = common::output(common::hypervisor(&config_file).arg("--duration").arg("6s"));
+
+    assert!(!log.contains("chatter"), "{log}");
+    assert!(!log.contains("Chatter raised an error"), "{log}");
+    assert!(log.contains("ApplicationError"), "{log}");
+
+    // The partition was restarted by the health monitor, and its suppressed
+    // log records were counted
+    let telemetry = fs::read_to_string(&telemetry_file).unwrap();
+    assert!(
+        telemetry
+            .contains("a653rs_partition_telemetry{partition=\"Chatty\",name=\"hm_restart\"} 1\n"),
+        "{telemetry}"
+    );
+    let suppressed: u64 = telemetry
+        .lines()
+        .find_map(|line| {
+            line.strip_prefix("a653rs_partition_suppressed_messages_total{partition=\"Chatty\"} ")
+        })
+        .expect(&telemetry)
+        .parse()
+        .unwrap();
+    assert!(suppressed > 0);
+}
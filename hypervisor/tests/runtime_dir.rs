@@ -0,0 +1,71 @@
+//! Runs the `priorities` example with a runtime directory of its own and an
+//! empty `TMPDIR`, and checks that the hypervisor creates its temporary files
+//! only in the runtime directory, and removes them again
+//!
+//! Like the examples, this needs a delegated cgroup and the musl target of
+//! the host, e.g. `x86_64-unknown-linux-musl`, for the partition image, so it
+//! is ignored by default:
+//!
+//! ```sh
+//! systemd-run --user --scope cargo test -p a653rs-linux-hypervisor --test runtime_dir -- --ignored
+//! ```
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use a653rs_linux_hypervisor::hypervisor::config::CargoImage;
+
+mod common;
+
+/// Entries of the directory `dir`
+fn entries(dir: &Path) -> Vec<String> {
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect()
+}
+
+#[test]
+#[ignore = "needs a delegated cgroup and the musl target of the host"]
+fn temporary_files_stay_in_the_runtime_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = common::single_partition("Priorities", "priorities", "1s", "300ms");
+    let config_file = dir.path().join("priorities.yaml");
+    fs::write(&config_file, config).unwrap();
+    let runtime_dir = dir.path().join("run");
+    let tmp_dir = dir.path().join("tmp");
+    fs::create_dir(&runtime_dir).unwrap();
+    fs::create_dir(&tmp_dir).unwrap();
+    fs::write(runtime_dir.join("foreign"), "").unwrap();
+
+    // Built up front, as the compiler creates temporary files in TMPDIR
+    let status = Command::new(env!("CARGO"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(["build", "--release", "-p", "priorities", "--target"])
+        .arg(CargoImage::HOST_MUSL_TARGET)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let log = common::output(
+        common::hypervisor(&config_file)
+            .env("TMPDIR", &tmp_dir)
+            .arg("--runtime-dir")
+            .arg(&runtime_dir)
+            .arg("--duration")
+            .arg("3s"),
+    );
+
+    assert!(
+        log.contains(&format!(
+            "Creating temporary files in {}",
+            runtime_dir.display()
+        )),
+        "{log}"
+    );
+    assert!(log.contains("Low finished its work"), "{log}");
+    assert_eq!(entries(&tmp_dir), Vec::<String>::new());
+    // Only what the hypervisor created is removed
+    assert_eq!(entries(&runtime_dir), ["foreign"]);
+}
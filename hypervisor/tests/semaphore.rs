@@ -0,0 +1,36 @@
+//! Runs the `semaphore` example, whose periodic process holds a semaphore
+//! across the windows of the partition while its aperiodic process waits for
+//! it, and checks that the wait times out and is woken by the release
+//!
+//! Like the examples, this needs a delegated cgroup and the musl target of
+//! the host, e.g. `x86_64-unknown-linux-musl`, for the partition image, so it
+//! is ignored by default:
+//!
+//! ```sh
+//! systemd-run --user --scope cargo test -p a653rs-linux-hypervisor --test semaphore -- --ignored
+//! ```
+
+mod common;
+
+#[test]
+#[ignore = "needs a delegated cgroup and the musl target of the host"]
+fn semaphore() {
+    let config = common::single_partition("Semaphore", "semaphore", "500ms", "100ms");
+    let log = common::run(&config, "3s");
+
+    // The reader waits for the semaphore while the partition is frozen, and
+    // takes it once the writer releases it in its next period
+    common::assert_in_order(
+        &log,
+        [
+            "Writer took the semaphore",
+            "Reader found the semaphore taken",
+            "Reader timed out waiting for the semaphore",
+            "Writer sees waiting processes: 1",
+            "Writer released the semaphore",
+            "Reader took the semaphore after waiting",
+        ],
+    );
+    assert!(!log.contains("took the semaphore held by"), "{log}");
+    assert!(!log.contains("Reader did not"), "{log}");
+}
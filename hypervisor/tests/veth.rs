@@ -0,0 +1,92 @@
+//! Runs the `priorities` example connected to a bridge on the host by a veth
+//! pair, and checks that its address is reachable from the host while it runs,
+//! and that the pair is removed again
+//!
+//! Besides a delegated cgroup and the musl target of the host, e.g.
+//! `x86_64-unknown-linux-musl`, for the partition image, this needs
+//! `CAP_NET_ADMIN` and the bridge `a653-test`, so it is ignored by default and
+//! skipped without the bridge:
+//!
+//! ```sh
+//! ip link add a653-test type bridge
+//! ip addr add 10.10.0.1/24 dev a653-test
+//! ip link set a653-test up
+//! cargo test -p a653rs-linux-hypervisor --test veth -- --ignored
+//! ```
+
+use std::fs;
+use std::io::ErrorKind;
+use std::net::UdpSocket;
+use std::path::Path;
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+
+mod common;
+
+const BRIDGE: &str = "a653-test";
+
+/// Time for building the image and starting the partition
+const STARTUP: Duration = Duration::from_secs(300);
+
+/// Whether the kernel in the namespace of the partition answers on its address
+///
+/// A datagram to a closed port is answered by an ICMP port unreachable, which
+/// fails the next receive.
+fn answers() -> bool {
+    let socket = UdpSocket::bind("10.10.0.1:0").unwrap();
+    socket.connect("10.10.0.2:9").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    socket.send(b"ping").ok();
+    socket
+        .recv(&mut [0; 4])
+        .is_err_and(|e| e.kind() == ErrorKind::ConnectionRefused)
+}
+
+#[test]
+#[ignore = "needs CAP_NET_ADMIN, a delegated cgroup and the musl target of the host"]
+fn veth() {
+    if !Path::new("/sys/class/net").join(BRIDGE).exists() {
+        eprintln!("skipped, the bridge {BRIDGE} does not exist");
+        return;
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = common::single_partition("Networked", "priorities", "500ms", "100ms")
+        + &format!(
+            r#"    network:
+      veth:
+        bridge: {BRIDGE}
+        address: 10.10.0.2/24
+        gateway: 10.10.0.1
+"#
+        );
+    let config_file = dir.path().join("veth.yaml");
+    fs::write(&config_file, config).unwrap();
+
+    let host = Path::new("/sys/class/net/a653h0");
+    let mut child = common::hypervisor(&config_file)
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let start = Instant::now();
+    while !answers() {
+        assert!(start.elapsed() < STARTUP, "the partition is not reachable");
+        assert!(child.try_wait().unwrap().is_none(), "hypervisor exited");
+        sleep(Duration::from_millis(100));
+    }
+    assert_eq!(
+        fs::read_link(host.join("master")).unwrap().file_name(),
+        Some(BRIDGE.as_ref())
+    );
+
+    // Stopped like by systemd, so that the partition is removed
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGTERM).unwrap();
+    child.wait().unwrap();
+    assert!(!host.exists());
+}
@@ -0,0 +1,54 @@
+//! Compares writing a sampling port once per sub-step of a window with
+//! staging the messages locally and writing only the last one, as done by
+//! `CoalescedSamplingSource`
+//!
+//! The shared memory of the port is a [SamplingBuffer], as the ports of a
+//! partition only exist below the hypervisor. Run with
+//! `cargo bench -p a653rs-linux --bench coalesced_sampling`.
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use a653rs_linux_core::buffer::SamplingBuffer;
+
+const MSG_SIZE: usize = 64 * 1024;
+const WRITES_PER_WINDOW: usize = 50;
+const WINDOWS: u32 = 200;
+
+fn bench(name: &str, mut window: impl FnMut(&[u8])) {
+    let message = vec![0xA5; MSG_SIZE];
+    // Warm up, so that the memory of the buffers is faulted in
+    window(&message);
+
+    let start = Instant::now();
+    for _ in 0..WINDOWS {
+        window(black_box(&message));
+    }
+    let per_window = start.elapsed() / WINDOWS;
+    println!("{name:<12} {per_window:>12?} per window");
+}
+
+fn main() {
+    let mut port = SamplingBuffer::new("bench_coalesced_sampling", MSG_SIZE).unwrap();
+    bench("direct", |message| {
+        for _ in 0..WRITES_PER_WINDOW {
+            port.write(message);
+        }
+    });
+
+    let mut port = SamplingBuffer::new("bench_coalesced_sampling", MSG_SIZE).unwrap();
+    let mut staged = Vec::with_capacity(MSG_SIZE);
+    bench("coalesced", |message| {
+        for _ in 0..WRITES_PER_WINDOW {
+            staged.clear();
+            staged.extend_from_slice(message);
+        }
+        port.write(&staged);
+    });
+
+    // Lower bound of a window writing its message only once
+    let mut port = SamplingBuffer::new("bench_coalesced_sampling", MSG_SIZE).unwrap();
+    bench("single", |message| {
+        port.write(message);
+    });
+}
@@ -1,20 +1,27 @@
 use std::process::exit;
+use std::sync::Arc;
 use std::thread::sleep;
+use std::time::Duration;
 
 use a653rs::bindings::*;
 use a653rs::prelude::{Name, SystemTime};
 use a653rs_linux_core::error::SystemError;
-use a653rs_linux_core::queuing::{QueuingDestination, QueuingSource};
+use a653rs_linux_core::health_event::{PartitionCall, ProcessKind};
+use a653rs_linux_core::partition::QueuingConstant;
+use a653rs_linux_core::queuing::{QueuingDestination, QueuingSource, Received};
 use a653rs_linux_core::sampling::{SamplingDestination, SamplingSource};
-use nix::libc::EAGAIN;
+use a653rs_linux_core::time::MonotonicTime;
 
-use crate::partition::ApexLinuxPartition;
+use crate::context::{Caller, Service};
+use crate::partition::{ApexLinuxPartition, PortDecl};
+use crate::priority::Scheduling;
 use crate::process::Process as LinuxProcess;
-use crate::*;
+use crate::time::{self, Timeout};
+use crate::{blackboard, buffer, event, fork, mutex, semaphore, *};
 
 impl ApexPartitionP4 for ApexLinuxPartition {
     fn get_partition_status() -> ApexPartitionStatus {
-        let operating_mode = PARTITION_MODE.read().unwrap();
+        let operating_mode = operating_mode();
 
         ApexPartitionStatus {
             period: CONSTANTS.period.as_nanos() as i64,
@@ -28,7 +35,8 @@ impl ApexPartitionP4 for ApexLinuxPartition {
     }
 
     fn set_partition_mode(operating_mode: OperatingMode) -> Result<(), ErrorReturnCode> {
-        let current_mode = PARTITION_MODE.read().unwrap();
+        fork::check()?;
+        let current_mode: OperatingMode = crate::operating_mode();
 
         if let OperatingMode::Idle = current_mode {
             panic!()
@@ -59,29 +67,262 @@ impl ApexPartitionP4 for ApexLinuxPartition {
 
 impl ApexProcessP4 for ApexLinuxPartition {
     fn create_process(attributes: &ApexProcessAttribute) -> Result<ProcessId, ErrorReturnCode> {
-        // TODO do not unwrap both
-        // Check current State (only allowed in warm and cold start)
-        let attr = attributes.clone().into();
-        Ok(LinuxProcess::create(attr).unwrap())
+        fork::check()?;
+        Service::CreateProcess.check(operating_mode(), Caller::current())?;
+
+        if Scheduling::of(attributes.base_priority, CONSTANTS.realtime).is_none() {
+            trace!(
+                "yielding InvalidParam, because base priority {} is out of range",
+                attributes.base_priority
+            );
+            return Err(ErrorReturnCode::InvalidParam);
+        }
+
+        LinuxProcess::create(attributes).map_err(|e| {
+            trace!("yielding InvalidConfig, because the process could not be created: {e}");
+            ErrorReturnCode::InvalidConfig
+        })
     }
 
     fn start(process_id: ProcessId) -> Result<(), ErrorReturnCode> {
-        let proc = match process_id {
-            1 => APERIODIC_PROCESS.get(),
-            2 => PERIODIC_PROCESS.get(),
-            _ => None,
-        };
+        fork::check()?;
+        Service::Start.check(operating_mode(), Caller::current())?;
 
-        let proc = match proc {
-            Some(proc) => proc,
-            None => return Err(ErrorReturnCode::InvalidParam),
-        };
+        let proc = LinuxProcess::get(process_id).ok_or(ErrorReturnCode::InvalidParam)?;
+
+        start_process(&proc, Duration::ZERO)
+    }
+}
+
+impl ApexProcessP1 for ApexLinuxPartition {
+    fn set_priority(process_id: ProcessId, priority: Priority) -> Result<(), ErrorReturnCode> {
+        fork::check()?;
+        let proc = LinuxProcess::get(process_id).ok_or(ErrorReturnCode::InvalidParam)?;
+        if Scheduling::of(priority, CONSTANTS.realtime).is_none() {
+            trace!("yielding InvalidParam, because priority {priority} is out of range");
+            return Err(ErrorReturnCode::InvalidParam);
+        }
+        if let ProcessState::Dormant = proc.state() {
+            trace!("yielding InvalidMode, because process {process_id} is dormant");
+            return Err(ErrorReturnCode::InvalidMode);
+        }
+
+        proc.set_priority(priority).map_err(|e| {
+            trace!("yielding InvalidConfig, because the partition may not use priority {priority}: {e}");
+            ErrorReturnCode::InvalidConfig
+        })
+    }
+
+    fn suspend_self(time_out: ApexSystemTime) -> Result<(), ErrorReturnCode> {
+        fork::check()?;
+        Service::SuspendSelf.check(operating_mode(), Caller::current())?;
+        // Only the aperiodic process passes the check above
+        let proc = LinuxProcess::get_self().ok_or(ErrorReturnCode::InvalidMode)?;
+
+        let timeout = Timeout::from(time_out);
+        if timeout == Timeout::Immediate || proc.suspend_self(timeout) {
+            Ok(())
+        } else {
+            Err(ErrorReturnCode::TimedOut)
+        }
+    }
+
+    fn suspend(process_id: ProcessId) -> Result<(), ErrorReturnCode> {
+        fork::check()?;
+        let proc = other_process(process_id)?;
+        if proc.periodic() {
+            trace!("yielding InvalidMode, because process {process_id} is periodic");
+            return Err(ErrorReturnCode::InvalidMode);
+        }
+        if let ProcessState::Dormant = proc.state() {
+            trace!("yielding InvalidMode, because process {process_id} is dormant");
+            return Err(ErrorReturnCode::InvalidMode);
+        }
 
         // TODO use a bigger result which contains both panic and non-panic errors
-        proc.start().unwrap();
+        if proc.suspend().unwrap() {
+            Ok(())
+        } else {
+            Err(ErrorReturnCode::NoAction)
+        }
+    }
+
+    fn resume(process_id: ProcessId) -> Result<(), ErrorReturnCode> {
+        fork::check()?;
+        let proc = other_process(process_id)?;
+        if let ProcessState::Dormant = proc.state() {
+            trace!("yielding InvalidMode, because process {process_id} is dormant");
+            return Err(ErrorReturnCode::InvalidMode);
+        }
+
+        if proc.resume().unwrap() {
+            Ok(())
+        } else {
+            Err(ErrorReturnCode::NoAction)
+        }
+    }
+
+    fn stop_self() {
+        let proc = fork::check().ok().and_then(|_| LinuxProcess::get_self());
+        let Some(proc) = proc else {
+            warn!("STOP_SELF called by the main process, parking it instead");
+            loop {
+                std::thread::park()
+            }
+        };
+        trace!("Stop process {}", proc.id());
+        proc.stop_self()
+    }
 
+    fn stop(process_id: ProcessId) -> Result<(), ErrorReturnCode> {
+        fork::check()?;
+        let proc = other_process(process_id)?;
+        if let ProcessState::Dormant = proc.state() {
+            trace!("yielding NoAction, because process {process_id} is dormant already");
+            return Err(ErrorReturnCode::NoAction);
+        }
+
+        proc.stop().unwrap();
         Ok(())
     }
+
+    /// The hypervisor holds the process in its frozen cgroup until the delay
+    /// elapsed. The delay of a periodic process counts from the start of each
+    /// of its release windows, the one of an aperiodic process from the start
+    /// of the NORMAL mode, or from the call in the NORMAL mode.
+    fn delayed_start(
+        process_id: ProcessId,
+        delay_time: ApexSystemTime,
+    ) -> Result<(), ErrorReturnCode> {
+        fork::check()?;
+        Service::Start.check(operating_mode(), Caller::current())?;
+        let proc = LinuxProcess::get(process_id).ok_or(ErrorReturnCode::InvalidParam)?;
+
+        let delay = match Timeout::from(delay_time) {
+            Timeout::Immediate => Duration::ZERO,
+            Timeout::Finite(delay) => delay,
+            Timeout::Infinite => {
+                trace!("yielding InvalidParam, because the delay is infinite");
+                return Err(ErrorReturnCode::InvalidParam);
+            }
+        };
+        if proc.periodic() && delay >= CONSTANTS.period {
+            trace!(
+                "yielding InvalidParam, because the delay {delay:?} is not shorter than the period {:?}",
+                CONSTANTS.period
+            );
+            return Err(ErrorReturnCode::InvalidParam);
+        }
+        if !matches!(proc.state(), ProcessState::Dormant) {
+            trace!("yielding NoAction, because process {process_id} is not dormant");
+            return Err(ErrorReturnCode::NoAction);
+        }
+
+        start_process(&proc, delay)
+    }
+
+    fn lock_preemption() -> Result<LockLevel, ErrorReturnCode> {
+        Err(ErrorReturnCode::NotAvailable)
+    }
+
+    fn unlock_preemption() -> Result<LockLevel, ErrorReturnCode> {
+        Err(ErrorReturnCode::NotAvailable)
+    }
+
+    fn get_my_id() -> Result<ProcessId, ErrorReturnCode> {
+        fork::check()?;
+        Service::GetMyId.check(operating_mode(), Caller::current())?;
+        // Only processes pass the check above
+        LinuxProcess::get_self()
+            .map(|p| p.id())
+            .ok_or(ErrorReturnCode::InvalidMode)
+    }
+
+    fn get_process_id(process_name: ProcessName) -> Result<ProcessId, ErrorReturnCode> {
+        fork::check()?;
+        let name = Name::new(process_name);
+        let name = name.to_str().map_err(|e| {
+            trace!("yielding InvalidConfig, because process name is not valid UTF-8:\n{e}");
+            ErrorReturnCode::InvalidConfig
+        })?;
+        LinuxProcess::by_name(name)
+            .map(|p| p.id())
+            .ok_or(ErrorReturnCode::InvalidConfig)
+    }
+
+    /// The deadline time is always infinite, as deadlines are not monitored.
+    fn get_process_status(process_id: ProcessId) -> Result<ApexProcessStatus, ErrorReturnCode> {
+        fork::check()?;
+        let proc = LinuxProcess::get(process_id).ok_or(ErrorReturnCode::InvalidParam)?;
+        Ok(ApexProcessStatus {
+            deadline_time: time::to_apex_timeout(SystemTime::Infinite),
+            current_priority: proc.priority(),
+            process_state: proc.state(),
+            attributes: proc.attributes().clone(),
+        })
+    }
+
+    fn initialize_process_core_affinity(
+        _process_id: ProcessId,
+        _processor_core_id: ProcessorCoreId,
+    ) -> Result<(), ErrorReturnCode> {
+        Err(ErrorReturnCode::NotAvailable)
+    }
+
+    fn get_my_processor_core_id() -> ProcessorCoreId {
+        // Partitions are assigned a single core, see get_partition_status
+        0
+    }
+
+    fn get_my_index() -> Result<ProcessIndex, ErrorReturnCode> {
+        Err(ErrorReturnCode::NotAvailable)
+    }
+}
+
+/// Starts the process `proc`, whose releases the hypervisor holds back by
+/// `delay`
+///
+/// The started thread stays in the frozen cgroup of the process until the
+/// hypervisor releases it, which it does in its next phase at the earliest.
+/// The delay is sent in the meantime. A zero delay is only sent to undo the
+/// delay of an earlier start, so that partitions which never delay a process
+/// also run on hypervisors without DELAYED_START.
+fn start_process(proc: &LinuxProcess, delay: Duration) -> Result<(), ErrorReturnCode> {
+    proc.start().map_err(|e| {
+        trace!("yielding NotAvailable, because the process could not be started: {e}");
+        ErrorReturnCode::NotAvailable
+    })?;
+
+    if !proc.set_delayed(!delay.is_zero()) {
+        return Ok(());
+    }
+    let process = if proc.periodic() {
+        ProcessKind::Periodic
+    } else {
+        ProcessKind::Aperiodic
+    };
+    if let Err(e) = SENDER.try_send(&PartitionCall::DelayedStart { process, delay }) {
+        trace!("yielding NotAvailable, because the delay could not be sent: {e:?}");
+        proc.set_delayed(false);
+        if let Err(e) = proc.stop() {
+            warn!("could not stop the process whose delay was not sent: {e}");
+        }
+        return Err(ErrorReturnCode::NotAvailable);
+    }
+    Ok(())
+}
+
+/// The process `process_id`, which is not the calling one
+///
+/// Services acting on the calling process itself have a variant of their own,
+/// e.g. STOP_SELF for STOP.
+fn other_process(process_id: ProcessId) -> Result<Arc<LinuxProcess>, ErrorReturnCode> {
+    let proc = LinuxProcess::get(process_id).ok_or(ErrorReturnCode::InvalidParam)?;
+    if LinuxProcess::get_self().is_some_and(|p| p.id() == process_id) {
+        trace!("yielding InvalidParam, because process {process_id} is the calling process");
+        return Err(ErrorReturnCode::InvalidParam);
+    }
+    Ok(proc)
 }
 
 impl ApexSamplingPortP4 for ApexLinuxPartition {
@@ -91,10 +332,12 @@ impl ApexSamplingPortP4 for ApexLinuxPartition {
         port_direction: PortDirection,
         refresh_period: ApexSystemTime,
     ) -> Result<SamplingPortId, ErrorReturnCode> {
-        if refresh_period <= 0 {
-            trace!("yielding InvalidConfig, because refresh period <= 0");
+        fork::check()?;
+        // check if refresh_period is in range
+        let Some(refresh) = time::refresh_period(refresh_period) else {
+            trace!("yielding InvalidConfig, because refresh period is out of range: got {refresh_period:?}");
             return Err(ErrorReturnCode::InvalidConfig);
-        }
+        };
 
         let name = Name::new(sampling_port_name);
         let name = name.to_str().map_err(|e| {
@@ -119,17 +362,8 @@ impl ApexSamplingPortP4 for ApexLinuxPartition {
                 return Err(ErrorReturnCode::InvalidConfig);
             }
 
-            // check partition mode
-            if let OperatingMode::Normal = PARTITION_MODE.read().unwrap() {
-                trace!("yielding InvalidMode, because sampling port creation is not allowed in normal mode");
-                return Err(ErrorReturnCode::InvalidMode);
-            }
+            Service::CreateSamplingPort.check(operating_mode(), Caller::current())?;
 
-            // check if refresh_period is in range
-            let SystemTime::Normal(refresh) = SystemTime::new(refresh_period) else {
-                trace!("yielding InvalidConfig, because refresh period is out of range: got {refresh_period:?}");
-                return Err(ErrorReturnCode::InvalidConfig);
-            };
             let ch = (i, refresh);
 
             // check if max number of channels is reached
@@ -142,6 +376,11 @@ impl ApexSamplingPortP4 for ApexLinuxPartition {
                 return Err(ErrorReturnCode::InvalidConfig);
             }
             SAMPLING_PORTS.write(&channels).unwrap();
+            ApexLinuxPartition::report_port_created(PortDecl::Sampling {
+                name: s.name.clone(),
+                dir: s.dir,
+                msg_size: s.msg_size,
+            });
 
             return Ok(channels.len() as SamplingPortId);
         }
@@ -150,72 +389,98 @@ impl ApexSamplingPortP4 for ApexLinuxPartition {
         Err(ErrorReturnCode::InvalidConfig)
     }
 
+    /// Empty messages are rejected as the standard demands. Sources publish
+    /// "no data" with [ApexLinuxPartition::write_no_data] instead.
     fn write_sampling_message(
         sampling_port_id: SamplingPortId,
         message: &[ApexByte],
     ) -> Result<(), ErrorReturnCode> {
-        // reduce port id by one
-        let sampling_port_id = (sampling_port_id as usize)
-            .checked_sub(1)
-            .ok_or(ErrorReturnCode::InvalidParam)?;
-        if let Some((port, _)) = SAMPLING_PORTS.read().unwrap().get(sampling_port_id) {
-            if let Some(port) = CONSTANTS.sampling.get(*port) {
-                if message.len() > port.msg_size {
-                    return Err(ErrorReturnCode::InvalidConfig);
-                } else if message.is_empty() {
-                    return Err(ErrorReturnCode::InvalidParam);
-                } else if port.dir != PortDirection::Source {
-                    return Err(ErrorReturnCode::InvalidMode);
-                }
-                SamplingSource::try_from(port.fd).unwrap().write(message);
-                return Ok(());
-            }
+        fork::check()?;
+        if message.is_empty() {
+            return Err(ErrorReturnCode::InvalidParam);
         }
-
-        Err(ErrorReturnCode::InvalidParam)
+        write_sampling_message(sampling_port_id, message)
     }
 
     unsafe fn read_sampling_message(
         sampling_port_id: SamplingPortId,
         message: &mut [ApexByte],
     ) -> Result<(Validity, MessageSize), ErrorReturnCode> {
-        let read = if let Ok(read) = SAMPLING_PORTS.read() {
-            read
-        } else {
-            return Err(ErrorReturnCode::NotAvailable);
-        };
+        fork::check()?;
+        read_sampling_message(sampling_port_id, message).map(|(valid, len, _)| (valid, len))
+    }
+}
 
-        // reduce port id by one
-        let sampling_port_id = (sampling_port_id as usize)
-            .checked_sub(1)
-            .ok_or(ErrorReturnCode::InvalidParam)?;
-        if let Some((port, val)) = read.get(sampling_port_id) {
-            if let Some(port) = CONSTANTS.sampling.get(*port) {
-                if message.is_empty() {
-                    return Err(ErrorReturnCode::InvalidParam);
-                } else if port.dir != PortDirection::Destination {
-                    return Err(ErrorReturnCode::InvalidMode);
-                }
-                let (msg_len, copied) = SamplingDestination::try_from(port.fd)
-                    .unwrap()
-                    .read(message);
+/// Writes a message to a sampling source port, which may be empty
+pub(crate) fn write_sampling_message(
+    sampling_port_id: SamplingPortId,
+    message: &[ApexByte],
+) -> Result<(), ErrorReturnCode> {
+    // reduce port id by one
+    let sampling_port_id = (sampling_port_id as usize)
+        .checked_sub(1)
+        .ok_or(ErrorReturnCode::InvalidParam)?;
+    if let Some((port, _)) = SAMPLING_PORTS.read().unwrap().get(sampling_port_id) {
+        if let Some(port) = CONSTANTS.sampling.get(*port) {
+            if message.len() > port.msg_size {
+                return Err(ErrorReturnCode::InvalidConfig);
+            } else if port.dir != PortDirection::Source {
+                return Err(ErrorReturnCode::InvalidMode);
+            }
+            SamplingSource::try_from(port.fd).unwrap().write(message);
+            return Ok(());
+        }
+    }
 
-                if msg_len == 0 {
-                    return Err(ErrorReturnCode::NoAction);
-                }
+    Err(ErrorReturnCode::InvalidParam)
+}
 
-                let valid = if copied.elapsed() <= *val {
-                    Validity::Valid
-                } else {
-                    Validity::Invalid
-                };
+/// Reads the current message of a sampling destination port together with its
+/// sequence number, which is zero on channels that are not sequenced
+///
+/// A port that never received a message yields `NoAction`, while a "no data"
+/// marker written by the source is read as a message of length zero.
+pub(crate) fn read_sampling_message(
+    sampling_port_id: SamplingPortId,
+    message: &mut [ApexByte],
+) -> Result<(Validity, MessageSize, u64), ErrorReturnCode> {
+    let read = if let Ok(read) = SAMPLING_PORTS.read() {
+        read
+    } else {
+        return Err(ErrorReturnCode::NotAvailable);
+    };
 
-                return Ok((valid, msg_len as u32));
+    // reduce port id by one
+    let sampling_port_id = (sampling_port_id as usize)
+        .checked_sub(1)
+        .ok_or(ErrorReturnCode::InvalidParam)?;
+    if let Some((port, val)) = read.get(sampling_port_id) {
+        if let Some(port) = CONSTANTS.sampling.get(*port) {
+            if message.is_empty() {
+                return Err(ErrorReturnCode::InvalidParam);
+            } else if port.dir != PortDirection::Destination {
+                return Err(ErrorReturnCode::InvalidMode);
             }
-        }
+            let (msg_len, copied, seq) = SamplingDestination::try_from(port.fd)
+                .unwrap()
+                .read_sequenced(message);
 
-        Err(ErrorReturnCode::InvalidParam)
+            // Only a message that was never written has no timestamp
+            if copied.is_zero() {
+                return Err(ErrorReturnCode::NoAction);
+            }
+
+            let valid = if copied.elapsed() <= *val {
+                Validity::Valid
+            } else {
+                Validity::Invalid
+            };
+
+            return Ok((valid, msg_len as u32, seq));
+        }
     }
+
+    Err(ErrorReturnCode::InvalidParam)
 }
 
 impl ApexQueuingPortP4 for ApexLinuxPartition {
@@ -226,6 +491,7 @@ impl ApexQueuingPortP4 for ApexLinuxPartition {
         port_direction: PortDirection,
         _queuing_discipline: QueuingDiscipline,
     ) -> Result<QueuingPortId, ErrorReturnCode> {
+        fork::check()?;
         let name = Name::new(queuing_port_name);
         let name = name.to_str().map_err(|e| {
             trace!("yielding InvalidConfig, because queuing port is not valid UTF-8:\n{e}");
@@ -256,11 +522,7 @@ impl ApexQueuingPortP4 for ApexLinuxPartition {
                 return Err(ErrorReturnCode::InvalidConfig);
             }
 
-            // check partition mode
-            if let OperatingMode::Normal = PARTITION_MODE.read().unwrap() {
-                trace!("yielding InvalidMode, because queuing port creation is not allowed in normal mode");
-                return Err(ErrorReturnCode::InvalidMode);
-            }
+            Service::CreateQueuingPort.check(operating_mode(), Caller::current())?;
 
             let ch = i;
 
@@ -281,6 +543,12 @@ impl ApexQueuingPortP4 for ApexLinuxPartition {
                 return Err(ErrorReturnCode::InvalidConfig);
             }
             QUEUING_PORTS.write(&channels).unwrap();
+            ApexLinuxPartition::report_port_created(PortDecl::Queuing {
+                name: q.name.clone(),
+                dir: q.dir,
+                msg_size: q.msg_size,
+                max_num_msg: q.max_num_msg,
+            });
 
             return Ok(channels.len() as QueuingPortId);
         }
@@ -292,8 +560,9 @@ impl ApexQueuingPortP4 for ApexLinuxPartition {
     fn send_queuing_message(
         queuing_port_id: QueuingPortId,
         message: &[ApexByte],
-        _time_out: ApexSystemTime,
+        time_out: ApexSystemTime,
     ) -> Result<(), ErrorReturnCode> {
+        fork::check()?;
         // reduce port id by one
         let queuing_port_id = (queuing_port_id as usize)
             .checked_sub(1)
@@ -305,18 +574,20 @@ impl ApexQueuingPortP4 for ApexLinuxPartition {
             .and_then(|port| CONSTANTS.queuing.get(port))
             .ok_or(ErrorReturnCode::InvalidParam)?;
 
+        // Zero-length messages are sent like any other
         if message.len() > port.msg_size {
             return Err(ErrorReturnCode::InvalidConfig);
-        } else if message.is_empty() {
-            return Err(ErrorReturnCode::InvalidParam);
         } else if port.dir != PortDirection::Source {
             return Err(ErrorReturnCode::InvalidMode);
         }
 
-        let written_bytes = QueuingSource::try_from(port.fd)
-            .unwrap()
-            .write(message, *SYSTEM_TIME)
-            .ok_or(ErrorReturnCode::NotAvailable)?; // Queue is overflowed
+        let timeout = Timeout::from(time_out);
+        let mut source = QueuingSource::try_from(port.fd).unwrap();
+        let written_bytes = timeout
+            .retry_parked(&mut source, QueuingSource::set_waiting, |source| {
+                source.write(message, MonotonicTime::now())
+            })
+            .ok_or(timeout.expired())?; // Queue is overflowed
 
         if written_bytes < message.len() {
             warn!(
@@ -331,9 +602,10 @@ impl ApexQueuingPortP4 for ApexLinuxPartition {
 
     unsafe fn receive_queuing_message(
         queuing_port_id: QueuingPortId,
-        _time_out: ApexSystemTime,
+        time_out: ApexSystemTime,
         message: &mut [ApexByte],
     ) -> Result<(MessageSize, QueueOverflow), ErrorReturnCode> {
+        fork::check()?;
         // reduce port id by one
         let queuing_port_id = (queuing_port_id as usize)
             .checked_sub(1)
@@ -350,18 +622,25 @@ impl ApexQueuingPortP4 for ApexLinuxPartition {
         } else if port.dir != PortDirection::Destination {
             return Err(ErrorReturnCode::InvalidMode);
         }
-        let (msg_len, has_overflowed) = QueuingDestination::try_from(port.fd)
-            .unwrap()
-            .read(message)
-            .ok_or(ErrorReturnCode::NotAvailable)?; // standard states that a length of 0 should also be set here, which the API
-                                                    // does not allow
+        let timeout = Timeout::from(time_out);
+        let mut destination = QueuingDestination::try_from(port.fd).unwrap();
+        // standard states that a length of 0 should also be set here, which the API
+        // does not allow
+        let received = timeout
+            .retry_parked(
+                &mut destination,
+                QueuingDestination::set_waiting,
+                |destination| destination.read(message),
+            )
+            .ok_or(timeout.expired())?;
 
-        Ok((msg_len as MessageSize, has_overflowed as QueueOverflow))
+        received_queuing_message(received)
     }
 
     fn get_queuing_port_status(
         queuing_port_id: QueuingPortId,
     ) -> Result<QueuingPortStatus, ErrorReturnCode> {
+        fork::check()?;
         // reduce port id by one
         let queuing_port_id = (queuing_port_id as usize)
             .checked_sub(1)
@@ -373,13 +652,21 @@ impl ApexQueuingPortP4 for ApexLinuxPartition {
             .and_then(|port| CONSTANTS.queuing.get(port))
             .ok_or(ErrorReturnCode::InvalidParam)?;
 
-        let num_msgs = match port.dir {
-            PortDirection::Source => QueuingSource::try_from(port.fd)
-                .unwrap()
-                .get_current_num_messages(),
-            PortDirection::Destination => QueuingDestination::try_from(port.fd)
-                .unwrap()
-                .get_current_num_messages(),
+        let (num_msgs, waiting) = match port.dir {
+            PortDirection::Source => {
+                let mut source = QueuingSource::try_from(port.fd).unwrap();
+                (
+                    source.get_current_num_messages(),
+                    source.num_waiting_processes(),
+                )
+            }
+            PortDirection::Destination => {
+                let mut destination = QueuingDestination::try_from(port.fd).unwrap();
+                (
+                    destination.get_current_num_messages(),
+                    destination.num_waiting_processes(),
+                )
+            }
         };
 
         let status = QueuingPortStatus {
@@ -387,13 +674,14 @@ impl ApexQueuingPortP4 for ApexLinuxPartition {
             max_nb_message: port.max_num_msg as MessageRange,
             max_message_size: port.msg_size as MessageSize,
             port_direction: port.dir,
-            waiting_processes: 0,
+            waiting_processes: waiting as WaitingRange,
         };
 
         Ok(status)
     }
 
     fn clear_queuing_port(queuing_port_id: QueuingPortId) -> Result<(), ErrorReturnCode> {
+        fork::check()?;
         // reduce port id by one
         let queuing_port_id = (queuing_port_id as usize)
             .checked_sub(1)
@@ -411,47 +699,109 @@ impl ApexQueuingPortP4 for ApexLinuxPartition {
 
         QueuingDestination::try_from(port.fd)
             .unwrap()
-            .clear(*SYSTEM_TIME);
+            .clear(MonotonicTime::now());
 
         Ok(())
     }
 }
 
+/// Result of receiving a message from a queuing port
+///
+/// A message longer than the provided area is consumed like any other, as the
+/// standard leaves no way to receive it again, but yields `InvalidParam`
+/// instead of a silently truncated message. The overflow flag is not reset
+/// by reading, so it is reported by the next successful receive.
+fn received_queuing_message(
+    received: Received,
+) -> Result<(MessageSize, QueueOverflow), ErrorReturnCode> {
+    if received.truncated() {
+        trace!(
+            "yielding InvalidParam, because the message of {} bytes does not fit into {} bytes",
+            received.len,
+            received.copied
+        );
+        return Err(ErrorReturnCode::InvalidParam);
+    }
+    Ok((
+        received.len as MessageSize,
+        received.overflowed as QueueOverflow,
+    ))
+}
+
+impl ApexQueuingPortP1 for ApexLinuxPartition {
+    fn get_queuing_port_id(
+        queuing_port_name: QueuingPortName,
+    ) -> Result<QueuingPortId, ErrorReturnCode> {
+        fork::check()?;
+        let name = Name::new(queuing_port_name);
+        let name = name.to_str().map_err(|e| {
+            trace!("yielding InvalidConfig, because queuing port is not valid UTF-8:\n{e}");
+            ErrorReturnCode::InvalidConfig
+        })?;
+        let created = QUEUING_PORTS
+            .read()
+            .map_err(|_| ErrorReturnCode::NotAvailable)?;
+
+        queuing_port_id(&CONSTANTS.queuing, &created, name)
+    }
+}
+
+/// Looks up the id of the created queuing port `name`, given the configured
+/// ports and the indices of the created ones among them in creation order
+///
+/// Yields [ErrorReturnCode::InvalidConfig] if no such port was created, as
+/// required for GET_QUEUING_PORT_ID.
+fn queuing_port_id(
+    constants: &[QueuingConstant],
+    created: &[QueuingPortsType],
+    name: &str,
+) -> Result<QueuingPortId, ErrorReturnCode> {
+    let Some(index) = constants.iter().position(|q| q.name == name) else {
+        trace!("yielding InvalidConfig, configuration does not declare queuing port {name}");
+        return Err(ErrorReturnCode::InvalidConfig);
+    };
+
+    match created.iter().position(|c| *c == index) {
+        // Port ids start at one
+        Some(i) => Ok(i as QueuingPortId + 1),
+        None => {
+            trace!("yielding InvalidConfig, queuing port {name} has not been created");
+            Err(ErrorReturnCode::InvalidConfig)
+        }
+    }
+}
+
 impl ApexTimeP4 for ApexLinuxPartition {
     fn periodic_wait() -> Result<(), ErrorReturnCode> {
-        // TODO do not unwrap() (Maybe raise an error?);
-        let proc = LinuxProcess::get_self().unwrap();
-        if !proc.periodic() {
-            return Err(ErrorReturnCode::InvalidMode);
-        }
+        fork::check()?;
+        Service::PeriodicWait.check(operating_mode(), Caller::current())?;
+        // Only the periodic process passes the check above
+        let proc = LinuxProcess::get_self().ok_or(ErrorReturnCode::InvalidMode)?;
+
+        // Publish the messages staged within this window before it is handed back
+        #[cfg(feature = "extensions")]
+        crate::sampling::flush_all()?;
 
         proc.cg().unwrap().freeze().unwrap();
         Ok(())
     }
 
     fn get_time() -> ApexSystemTime {
-        SYSTEM_TIME
-            .elapsed()
-            .as_nanos()
-            .clamp(0, ApexSystemTime::MAX as u128) as ApexSystemTime
+        time::to_apex_time(module_time().into())
     }
 }
 
 impl ApexErrorP4 for ApexLinuxPartition {
     fn report_application_message(message: &[ApexByte]) -> Result<(), ErrorReturnCode> {
+        fork::check()?;
         if message.len() > MAX_ERROR_MESSAGE_SIZE {
             return Err(ErrorReturnCode::InvalidParam);
         }
         if let Ok(msg) = std::str::from_utf8(message) {
             // Logging may fail temporarily, because the resource can not be written to
             // (e.g. queue is full), but the API does not allow us any other
-            // return code than INVALID_PARAM.
-            if let Err(e) = SENDER.try_send(&PartitionCall::Message(msg.to_string())) {
-                if let Some(e) = e.source().downcast_ref::<std::io::Error>() {
-                    if e.raw_os_error() == Some(EAGAIN) {
-                        return Ok(());
-                    }
-                }
+            // return code than INVALID_PARAM. Such messages are dropped.
+            if let Err(e) = ApexLinuxPartition::send_log_record(None, msg.to_string()) {
                 panic!("Failed to report application message: {}", e);
             }
         }
@@ -462,6 +812,7 @@ impl ApexErrorP4 for ApexLinuxPartition {
         error_code: ErrorCode,
         message: &[ApexByte],
     ) -> Result<(), ErrorReturnCode> {
+        fork::check()?;
         if let ErrorCode::ApplicationError = error_code {
             Self::report_application_message(message).unwrap();
             Self::raise_system_error(SystemError::ApplicationError);
@@ -471,3 +822,750 @@ impl ApexErrorP4 for ApexLinuxPartition {
         }
     }
 }
+
+impl ApexBlackboardP1 for ApexLinuxPartition {
+    fn create_blackboard(
+        blackboard_name: BlackboardName,
+        max_message_size: MessageSize,
+    ) -> Result<BlackboardId, ErrorReturnCode> {
+        fork::check()?;
+        let name = Name::new(blackboard_name);
+        let name = name.to_str().map_err(|e| {
+            trace!("yielding InvalidConfig, because blackboard name is not valid UTF-8:\n{e}");
+            ErrorReturnCode::InvalidConfig
+        })?;
+        if max_message_size == 0 {
+            trace!(
+                "yielding InvalidParam, because the max message size of blackboard {name} is zero"
+            );
+            return Err(ErrorReturnCode::InvalidParam);
+        }
+
+        Service::CreateBlackboard.check(operating_mode(), Caller::current())?;
+
+        let mut blackboards = BLACKBOARDS.read().unwrap();
+
+        // check if blackboard already exists
+        if blackboards.iter().any(|(n, _, _)| *n == blackboard_name) {
+            trace!("yielding NoAction, because blackboard {name} has already been created");
+            return Err(ErrorReturnCode::NoAction);
+        }
+
+        // check if max number of blackboards is reached
+        if blackboards.len() == blackboards.capacity() {
+            trace!(
+                "yielding InvalidConfig, maximum number of blackboards (={}) already reached",
+                blackboards.len()
+            );
+            return Err(ErrorReturnCode::InvalidConfig);
+        }
+
+        let fd = blackboard::Blackboard::create(name, max_message_size as usize).map_err(|e| {
+            trace!("yielding InvalidConfig, because blackboard {name} could not be created: {e}");
+            ErrorReturnCode::InvalidConfig
+        })?;
+        blackboards.push((blackboard_name, fd, max_message_size));
+        BLACKBOARDS.write(&blackboards).unwrap();
+
+        // Blackboard ids start at one
+        Ok(blackboards.len() as BlackboardId)
+    }
+
+    fn display_blackboard(
+        blackboard_id: BlackboardId,
+        message: &[ApexByte],
+    ) -> Result<(), ErrorReturnCode> {
+        fork::check()?;
+        let blackboard = blackboard::get(blackboard_id)?;
+        if message.is_empty() || message.len() > blackboard.max_size() {
+            trace!(
+                "yielding InvalidParam, because the message length {} is not within 1 and {}",
+                message.len(),
+                blackboard.max_size()
+            );
+            return Err(ErrorReturnCode::InvalidParam);
+        }
+        blackboard.display(message);
+        Ok(())
+    }
+
+    unsafe fn read_blackboard(
+        blackboard_id: BlackboardId,
+        time_out: ApexSystemTime,
+        message: &mut [ApexByte],
+    ) -> Result<MessageSize, ErrorReturnCode> {
+        fork::check()?;
+        let blackboard = blackboard::get(blackboard_id)?;
+        let timeout = Timeout::from(time_out);
+        let len = blackboard
+            .read_timeout(message, timeout)
+            .ok_or(timeout.expired())?;
+        if len > message.len() {
+            trace!(
+                "yielding InvalidParam, because the message of {len} bytes does not fit into {} bytes",
+                message.len()
+            );
+            return Err(ErrorReturnCode::InvalidParam);
+        }
+        Ok(len as MessageSize)
+    }
+
+    fn clear_blackboard(blackboard_id: BlackboardId) -> Result<(), ErrorReturnCode> {
+        fork::check()?;
+        blackboard::get(blackboard_id)?.clear();
+        Ok(())
+    }
+
+    fn get_blackboard_id(blackboard_name: BlackboardName) -> Result<BlackboardId, ErrorReturnCode> {
+        fork::check()?;
+        let name = Name::new(blackboard_name);
+        let name = name.to_str().map_err(|e| {
+            trace!("yielding InvalidConfig, because blackboard name is not valid UTF-8:\n{e}");
+            ErrorReturnCode::InvalidConfig
+        })?;
+        let created = BLACKBOARDS
+            .read()
+            .map_err(|_| ErrorReturnCode::NotAvailable)?;
+        match created.iter().position(|(n, _, _)| *n == blackboard_name) {
+            // Blackboard ids start at one
+            Some(i) => Ok(i as BlackboardId + 1),
+            None => {
+                trace!("yielding InvalidConfig, blackboard {name} has not been created");
+                Err(ErrorReturnCode::InvalidConfig)
+            }
+        }
+    }
+
+    fn get_blackboard_status(
+        blackboard_id: BlackboardId,
+    ) -> Result<BlackboardStatus, ErrorReturnCode> {
+        fork::check()?;
+        let blackboard = blackboard::get(blackboard_id)?;
+        let empty_indicator = if blackboard.is_empty() {
+            EmptyIndicator::Empty
+        } else {
+            EmptyIndicator::Occupied
+        };
+        Ok(BlackboardStatus {
+            empty_indicator,
+            max_message_size: blackboard.max_size() as MessageSize,
+            waiting_processes: blackboard.waiting() as WaitingRange,
+        })
+    }
+}
+
+impl ApexBufferP1 for ApexLinuxPartition {
+    fn create_buffer(
+        buffer_name: BufferName,
+        max_message_size: MessageSize,
+        max_nb_message: MessageRange,
+        _queuing_discipline: QueuingDiscipline,
+    ) -> Result<BufferId, ErrorReturnCode> {
+        fork::check()?;
+        let name = Name::new(buffer_name);
+        let name = name.to_str().map_err(|e| {
+            trace!("yielding InvalidConfig, because buffer name is not valid UTF-8:\n{e}");
+            ErrorReturnCode::InvalidConfig
+        })?;
+        if max_message_size == 0 || max_nb_message == 0 {
+            trace!(
+                "yielding InvalidParam, because the max message size or the max number of messages of buffer {name} is zero"
+            );
+            return Err(ErrorReturnCode::InvalidParam);
+        }
+
+        Service::CreateBuffer.check(operating_mode(), Caller::current())?;
+
+        let mut buffers = BUFFERS.read().unwrap();
+
+        // check if buffer already exists
+        if buffers.iter().any(|(n, _)| *n == buffer_name) {
+            trace!("yielding NoAction, because buffer {name} has already been created");
+            return Err(ErrorReturnCode::NoAction);
+        }
+
+        // check if max number of buffers is reached
+        if buffers.len() == buffers.capacity() {
+            trace!(
+                "yielding InvalidConfig, maximum number of buffers (={}) already reached",
+                buffers.len()
+            );
+            return Err(ErrorReturnCode::InvalidConfig);
+        }
+
+        let fd = buffer::Buffer::create(name, max_message_size as usize, max_nb_message as usize)
+            .map_err(|e| {
+            trace!("yielding InvalidConfig, because buffer {name} could not be created: {e}");
+            ErrorReturnCode::InvalidConfig
+        })?;
+        buffers.push((buffer_name, fd));
+        BUFFERS.write(&buffers).unwrap();
+
+        // Buffer ids start at one
+        Ok(buffers.len() as BufferId)
+    }
+
+    fn send_buffer(
+        buffer_id: BufferId,
+        message: &[ApexByte],
+        time_out: ApexSystemTime,
+    ) -> Result<(), ErrorReturnCode> {
+        fork::check()?;
+        let buffer = buffer::get(buffer_id)?;
+        if message.is_empty() || message.len() > buffer.max_size() {
+            trace!(
+                "yielding InvalidParam, because the message length {} is not within 1 and {}",
+                message.len(),
+                buffer.max_size()
+            );
+            return Err(ErrorReturnCode::InvalidParam);
+        }
+        let timeout = Timeout::from(time_out);
+        if buffer.send(message, timeout) {
+            Ok(())
+        } else {
+            Err(timeout.expired())
+        }
+    }
+
+    unsafe fn receive_buffer(
+        buffer_id: BufferId,
+        time_out: ApexSystemTime,
+        message: &mut [ApexByte],
+    ) -> Result<MessageSize, ErrorReturnCode> {
+        fork::check()?;
+        let buffer = buffer::get(buffer_id)?;
+        let timeout = Timeout::from(time_out);
+        let len = buffer
+            .receive(message, timeout)
+            .ok_or(timeout.expired())?
+            .map_err(|e| {
+                trace!("yielding InvalidParam, because the message does not fit: {e}");
+                ErrorReturnCode::InvalidParam
+            })?;
+        Ok(len as MessageSize)
+    }
+
+    fn get_buffer_id(buffer_name: BufferName) -> Result<BufferId, ErrorReturnCode> {
+        fork::check()?;
+        let name = Name::new(buffer_name);
+        let name = name.to_str().map_err(|e| {
+            trace!("yielding InvalidConfig, because buffer name is not valid UTF-8:\n{e}");
+            ErrorReturnCode::InvalidConfig
+        })?;
+        let created = BUFFERS.read().map_err(|_| ErrorReturnCode::NotAvailable)?;
+        match created.iter().position(|(n, _)| *n == buffer_name) {
+            // Buffer ids start at one
+            Some(i) => Ok(i as BufferId + 1),
+            None => {
+                trace!("yielding InvalidConfig, buffer {name} has not been created");
+                Err(ErrorReturnCode::InvalidConfig)
+            }
+        }
+    }
+
+    fn get_buffer_status(buffer_id: BufferId) -> Result<BufferStatus, ErrorReturnCode> {
+        fork::check()?;
+        let buffer = buffer::get(buffer_id)?;
+        Ok(BufferStatus {
+            nb_message: buffer.len() as MessageRange,
+            max_nb_message: buffer.capacity() as MessageRange,
+            max_message_size: buffer.max_size() as MessageSize,
+            waiting_processes: buffer.waiting() as WaitingRange,
+        })
+    }
+}
+
+impl ApexSemaphoreP1 for ApexLinuxPartition {
+    fn create_semaphore(
+        semaphore_name: SemaphoreName,
+        current_value: SemaphoreValue,
+        maximum_value: SemaphoreValue,
+        _queuing_discipline: QueuingDiscipline,
+    ) -> Result<SemaphoreId, ErrorReturnCode> {
+        fork::check()?;
+        let name = Name::new(semaphore_name);
+        let name = name.to_str().map_err(|e| {
+            trace!("yielding InvalidConfig, because semaphore name is not valid UTF-8:\n{e}");
+            ErrorReturnCode::InvalidConfig
+        })?;
+        if !(0..=semaphore::MAX_VALUE).contains(&maximum_value)
+            || !(0..=maximum_value).contains(&current_value)
+        {
+            trace!(
+                "yielding InvalidParam, because the values {current_value} of {maximum_value} of semaphore {name} are out of range"
+            );
+            return Err(ErrorReturnCode::InvalidParam);
+        }
+
+        Service::CreateSemaphore.check(operating_mode(), Caller::current())?;
+
+        let mut semaphores = SEMAPHORES.read().unwrap();
+
+        // check if semaphore already exists
+        if semaphores.iter().any(|(n, _)| *n == semaphore_name) {
+            trace!("yielding NoAction, because semaphore {name} has already been created");
+            return Err(ErrorReturnCode::NoAction);
+        }
+
+        // check if max number of semaphores is reached
+        if semaphores.len() == semaphores.capacity() {
+            trace!(
+                "yielding InvalidConfig, maximum number of semaphores (={}) already reached",
+                semaphores.len()
+            );
+            return Err(ErrorReturnCode::InvalidConfig);
+        }
+
+        let fd = semaphore::Semaphore::create(name, current_value as u32, maximum_value as u32)
+            .map_err(|e| {
+                trace!(
+                    "yielding InvalidConfig, because semaphore {name} could not be created: {e}"
+                );
+                ErrorReturnCode::InvalidConfig
+            })?;
+        semaphores.push((semaphore_name, fd));
+        SEMAPHORES.write(&semaphores).unwrap();
+
+        // Semaphore ids start at one
+        Ok(semaphores.len() as SemaphoreId)
+    }
+
+    fn wait_semaphore(
+        semaphore_id: SemaphoreId,
+        time_out: ApexSystemTime,
+    ) -> Result<(), ErrorReturnCode> {
+        fork::check()?;
+        let semaphore = semaphore::get(semaphore_id)?;
+        let timeout = Timeout::from(time_out);
+        if semaphore.wait(timeout) {
+            Ok(())
+        } else {
+            Err(timeout.expired())
+        }
+    }
+
+    fn signal_semaphore(semaphore_id: SemaphoreId) -> Result<(), ErrorReturnCode> {
+        fork::check()?;
+        let semaphore = semaphore::get(semaphore_id)?;
+        if semaphore.signal() {
+            Ok(())
+        } else {
+            trace!("yielding NoAction, because semaphore {semaphore_id} is at its maximum value");
+            Err(ErrorReturnCode::NoAction)
+        }
+    }
+
+    fn get_semaphore_id(semaphore_name: SemaphoreName) -> Result<SemaphoreId, ErrorReturnCode> {
+        fork::check()?;
+        let name = Name::new(semaphore_name);
+        let name = name.to_str().map_err(|e| {
+            trace!("yielding InvalidConfig, because semaphore name is not valid UTF-8:\n{e}");
+            ErrorReturnCode::InvalidConfig
+        })?;
+        let created = SEMAPHORES
+            .read()
+            .map_err(|_| ErrorReturnCode::NotAvailable)?;
+        match created.iter().position(|(n, _)| *n == semaphore_name) {
+            // Semaphore ids start at one
+            Some(i) => Ok(i as SemaphoreId + 1),
+            None => {
+                trace!("yielding InvalidConfig, semaphore {name} has not been created");
+                Err(ErrorReturnCode::InvalidConfig)
+            }
+        }
+    }
+
+    fn get_semaphore_status(semaphore_id: SemaphoreId) -> Result<SemaphoreStatus, ErrorReturnCode> {
+        fork::check()?;
+        let semaphore = semaphore::get(semaphore_id)?;
+        Ok(SemaphoreStatus {
+            current_value: semaphore.value() as SemaphoreValue,
+            maximum_value: semaphore.max() as SemaphoreValue,
+            waiting_processes: semaphore.waiting() as WaitingRange,
+        })
+    }
+}
+
+impl ApexEventP1 for ApexLinuxPartition {
+    fn create_event(event_name: EventName) -> Result<EventId, ErrorReturnCode> {
+        fork::check()?;
+        let name = Name::new(event_name);
+        let name = name.to_str().map_err(|e| {
+            trace!("yielding InvalidConfig, because event name is not valid UTF-8:\n{e}");
+            ErrorReturnCode::InvalidConfig
+        })?;
+
+        Service::CreateEvent.check(operating_mode(), Caller::current())?;
+
+        let mut events = EVENTS.read().unwrap();
+
+        // check if event already exists
+        if events.iter().any(|(n, _)| *n == event_name) {
+            trace!("yielding NoAction, because event {name} has already been created");
+            return Err(ErrorReturnCode::NoAction);
+        }
+
+        // check if max number of events is reached
+        if events.len() == events.capacity() {
+            trace!(
+                "yielding InvalidConfig, maximum number of events (={}) already reached",
+                events.len()
+            );
+            return Err(ErrorReturnCode::InvalidConfig);
+        }
+
+        let fd = event::Event::create(name).map_err(|e| {
+            trace!("yielding InvalidConfig, because event {name} could not be created: {e}");
+            ErrorReturnCode::InvalidConfig
+        })?;
+        events.push((event_name, fd));
+        EVENTS.write(&events).unwrap();
+
+        // Event ids start at one
+        Ok(events.len() as EventId)
+    }
+
+    fn set_event(event_id: EventId) -> Result<(), ErrorReturnCode> {
+        fork::check()?;
+        event::get(event_id)?.set();
+        Ok(())
+    }
+
+    fn reset_event(event_id: EventId) -> Result<(), ErrorReturnCode> {
+        fork::check()?;
+        event::get(event_id)?.reset();
+        Ok(())
+    }
+
+    fn wait_event(event_id: EventId, time_out: ApexSystemTime) -> Result<(), ErrorReturnCode> {
+        fork::check()?;
+        let event = event::get(event_id)?;
+        let timeout = Timeout::from(time_out);
+        if event.wait(timeout) {
+            Ok(())
+        } else {
+            Err(timeout.expired())
+        }
+    }
+
+    fn get_event_id(event_name: EventName) -> Result<EventId, ErrorReturnCode> {
+        fork::check()?;
+        let name = Name::new(event_name);
+        let name = name.to_str().map_err(|e| {
+            trace!("yielding InvalidConfig, because event name is not valid UTF-8:\n{e}");
+            ErrorReturnCode::InvalidConfig
+        })?;
+        let created = EVENTS.read().map_err(|_| ErrorReturnCode::NotAvailable)?;
+        match created.iter().position(|(n, _)| *n == event_name) {
+            // Event ids start at one
+            Some(i) => Ok(i as EventId + 1),
+            None => {
+                trace!("yielding InvalidConfig, event {name} has not been created");
+                Err(ErrorReturnCode::InvalidConfig)
+            }
+        }
+    }
+
+    fn get_event_status(event_id: EventId) -> Result<EventStatus, ErrorReturnCode> {
+        fork::check()?;
+        let event = event::get(event_id)?;
+        Ok(EventStatus {
+            event_state: if event.is_up() {
+                EventState::Up
+            } else {
+                EventState::Down
+            },
+            waiting_processes: event.waiting() as WaitingRange,
+        })
+    }
+}
+
+impl ApexMutexP1 for ApexLinuxPartition {
+    fn create_mutex(
+        mutex_name: MutexName,
+        mutex_priority: Priority,
+        _queuing_discipline: QueuingDiscipline,
+    ) -> Result<MutexId, ErrorReturnCode> {
+        fork::check()?;
+        let name = Name::new(mutex_name);
+        let name = name.to_str().map_err(|e| {
+            trace!("yielding InvalidConfig, because mutex name is not valid UTF-8:\n{e}");
+            ErrorReturnCode::InvalidConfig
+        })?;
+        if Scheduling::of(mutex_priority, CONSTANTS.realtime).is_none() {
+            trace!("yielding InvalidParam, because priority {mutex_priority} of mutex {name} is out of range");
+            return Err(ErrorReturnCode::InvalidParam);
+        }
+
+        Service::CreateMutex.check(operating_mode(), Caller::current())?;
+
+        let mut mutexes = MUTEXES.read().unwrap();
+
+        // check if mutex already exists
+        if mutexes.iter().any(|(n, _)| *n == mutex_name) {
+            trace!("yielding NoAction, because mutex {name} has already been created");
+            return Err(ErrorReturnCode::NoAction);
+        }
+
+        // check if max number of mutexes is reached
+        if mutexes.len() == mutexes.capacity() {
+            trace!(
+                "yielding InvalidConfig, maximum number of mutexes (={}) already reached",
+                mutexes.len()
+            );
+            return Err(ErrorReturnCode::InvalidConfig);
+        }
+
+        let fd = mutex::Mutex::create(name, mutex_priority).map_err(|e| {
+            trace!("yielding InvalidConfig, because mutex {name} could not be created: {e}");
+            ErrorReturnCode::InvalidConfig
+        })?;
+        mutexes.push((mutex_name, fd));
+        MUTEXES.write(&mutexes).unwrap();
+
+        // Mutex ids start at one
+        Ok(mutexes.len() as MutexId)
+    }
+
+    fn acquire_mutex(mutex_id: MutexId, time_out: ApexSystemTime) -> Result<(), ErrorReturnCode> {
+        fork::check()?;
+        let mutex = mutex::get(mutex_id)?;
+        Service::AcquireMutex.check(operating_mode(), Caller::current())?;
+        let process = LinuxProcess::get_self().ok_or(ErrorReturnCode::InvalidMode)?;
+        if process.priority() > mutex.priority() {
+            trace!(
+                "yielding InvalidConfig, because the priority {} of process {} is above the priority {} of mutex {mutex_id}",
+                process.priority(),
+                process.id(),
+                mutex.priority()
+            );
+            return Err(ErrorReturnCode::InvalidConfig);
+        }
+        mutex.acquire(process.id(), Timeout::from(time_out))
+    }
+
+    fn release_mutex(mutex_id: MutexId) -> Result<(), ErrorReturnCode> {
+        fork::check()?;
+        let mutex = mutex::get(mutex_id)?;
+        let process = LinuxProcess::get_self().ok_or(ErrorReturnCode::InvalidMode)?;
+        mutex.release(process.id())
+    }
+
+    fn reset_mutex(mutex_id: MutexId, process_id: ProcessId) -> Result<(), ErrorReturnCode> {
+        fork::check()?;
+        let mutex = mutex::get(mutex_id)?;
+        LinuxProcess::get(process_id).ok_or(ErrorReturnCode::InvalidParam)?;
+        mutex.reset(process_id)
+    }
+
+    fn get_mutex_id(mutex_name: MutexName) -> Result<MutexId, ErrorReturnCode> {
+        fork::check()?;
+        let name = Name::new(mutex_name);
+        let name = name.to_str().map_err(|e| {
+            trace!("yielding InvalidConfig, because mutex name is not valid UTF-8:\n{e}");
+            ErrorReturnCode::InvalidConfig
+        })?;
+        let created = MUTEXES.read().map_err(|_| ErrorReturnCode::NotAvailable)?;
+        match created.iter().position(|(n, _)| *n == mutex_name) {
+            // Mutex ids start at one
+            Some(i) => Ok(i as MutexId + 1),
+            None => {
+                trace!("yielding InvalidConfig, mutex {name} has not been created");
+                Err(ErrorReturnCode::InvalidConfig)
+            }
+        }
+    }
+
+    fn get_mutex_status(mutex_id: MutexId) -> Result<MutexStatus, ErrorReturnCode> {
+        fork::check()?;
+        let mutex = mutex::get(mutex_id)?;
+        let owner = mutex.owner();
+        Ok(MutexStatus {
+            mutex_owner: owner.unwrap_or(mutex::NO_OWNER),
+            mutex_state: match owner {
+                Some(_) => MutexState::Owned,
+                None => MutexState::Available,
+            },
+            mutex_priority: mutex.priority(),
+            lock_count: mutex.lock_count(),
+            waiting_processes: mutex.waiting() as WaitingRange,
+        })
+    }
+
+    fn get_process_mutex_state(process_id: ProcessId) -> Result<MutexId, ErrorReturnCode> {
+        fork::check()?;
+        LinuxProcess::get(process_id).ok_or(ErrorReturnCode::InvalidParam)?;
+        mutex::owned_by(process_id)
+    }
+}
+
+crate::conformance::conformance_table! {
+    impl ApexPartitionP4 {
+        get_partition_status => Partial: "lock_level is always 0",
+        set_partition_mode => Implemented,
+    }
+    impl ApexProcessP4 {
+        create_process => Partial: "at most one periodic and one aperiodic process, priorities range from 0 to 255, deadlines are ignored",
+        start => Implemented,
+    }
+    impl ApexProcessP1 {
+        set_priority => Partial: "mapped to nice values or SCHED_FIFO priorities, may only be raised above the start priority if the hypervisor could grant it",
+        suspend_self => Partial: "only the aperiodic process can be suspended, time capacities are not monitored",
+        suspend => Partial: "only the aperiodic process can be suspended, the calling process yields InvalidParam",
+        resume => Implemented,
+        stop_self => Partial: "the thread is held frozen until the partition restarts, a mutex it owns is reset, the main process is parked",
+        stop => Partial: "the thread is held frozen until the partition restarts, a mutex it owns is reset, the calling process yields InvalidParam",
+        delayed_start => Partial: "a periodic process whose release falls behind the periodic phase of its window is released at the start of the next one, NotAvailable if the delay could not be sent to the hypervisor",
+        lock_preemption => Stub: "yields NotAvailable",
+        unlock_preemption => Stub: "yields NotAvailable",
+        get_my_id => Implemented,
+        get_process_id => Implemented,
+        get_process_status => Partial: "the deadline time is infinite",
+        initialize_process_core_affinity => Stub: "yields NotAvailable",
+        get_my_processor_core_id => Implemented,
+        get_my_index => Stub: "yields NotAvailable",
+    }
+    impl ApexSamplingPortP4 {
+        create_sampling_port => Implemented,
+        write_sampling_message => Implemented,
+        read_sampling_message => Partial: "no-data markers of the source are read as messages of length zero",
+    }
+    impl ApexQueuingPortP4 {
+        create_queuing_port => Partial: "queuing discipline is ignored",
+        send_queuing_message => Partial: "blocking waits poll the port instead of queuing the process, zero-length messages are sent",
+        receive_queuing_message => Partial: "blocking waits poll the port instead of queuing the process, messages longer than the provided area are consumed and yield InvalidParam, zero-length messages are received with length zero",
+        get_queuing_port_status => Implemented,
+        clear_queuing_port => Implemented,
+    }
+    impl ApexQueuingPortP1 {
+        get_queuing_port_id => Implemented,
+    }
+    impl ApexTimeP4 {
+        periodic_wait => Implemented,
+        get_time => Implemented,
+    }
+    impl ApexErrorP4 {
+        report_application_message => Partial: "messages are dropped while the hypervisor socket is full",
+        raise_application_error => Implemented,
+    }
+    impl ApexBlackboardP1 {
+        create_blackboard => Implemented,
+        display_blackboard => Implemented,
+        read_blackboard => Partial: "messages longer than the provided area yield InvalidParam",
+        clear_blackboard => Implemented,
+        get_blackboard_id => Implemented,
+        get_blackboard_status => Implemented,
+    }
+    impl ApexBufferP1 {
+        create_buffer => Partial: "queuing discipline is ignored",
+        send_buffer => Partial: "blocking waits poll the buffer instead of queuing the process",
+        receive_buffer => Partial: "blocking waits poll the buffer instead of queuing the process, messages longer than the provided area are kept and yield InvalidParam",
+        get_buffer_id => Implemented,
+        get_buffer_status => Implemented,
+    }
+    impl ApexSemaphoreP1 {
+        create_semaphore => Partial: "queuing discipline is ignored, waiting processes are always served in FIFO order",
+        wait_semaphore => Implemented,
+        signal_semaphore => Implemented,
+        get_semaphore_id => Implemented,
+        get_semaphore_status => Implemented,
+    }
+    impl ApexEventP1 {
+        create_event => Implemented,
+        set_event => Implemented,
+        reset_event => Implemented,
+        wait_event => Implemented,
+        get_event_id => Implemented,
+        get_event_status => Implemented,
+    }
+    impl ApexMutexP1 {
+        create_mutex => Partial: "queuing discipline is ignored, the priority is reported but not applied to the owner",
+        acquire_mutex => Implemented,
+        release_mutex => Implemented,
+        reset_mutex => Implemented,
+        get_mutex_id => Implemented,
+        get_mutex_status => Implemented,
+        get_process_mutex_state => Implemented,
+    }
+    missing ApexTimeP1 {
+        timed_wait,
+        replenish,
+    }
+    missing ApexSamplingPortP1 {
+        get_sampling_port_id,
+        get_sampling_port_status,
+    }
+    missing ApexErrorP1 {
+        create_error_handler,
+        get_error_status,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn constant(name: &str) -> QueuingConstant {
+        QueuingConstant {
+            name: name.into(),
+            dir: PortDirection::Source,
+            msg_size: 8,
+            max_num_msg: 4,
+            fd: -1,
+        }
+    }
+
+    #[test]
+    fn queuing_port_ids() {
+        let constants = [constant("a"), constant("b"), constant("c")];
+        // Created in the order c, a
+        let created = [2, 0];
+
+        assert_eq!(queuing_port_id(&constants, &created, "c"), Ok(1));
+        assert_eq!(queuing_port_id(&constants, &created, "a"), Ok(2));
+        assert_eq!(
+            queuing_port_id(&constants, &created, "b"),
+            Err(ErrorReturnCode::InvalidConfig)
+        );
+        assert_eq!(
+            queuing_port_id(&constants, &created, "d"),
+            Err(ErrorReturnCode::InvalidConfig)
+        );
+        assert_eq!(
+            queuing_port_id(&constants, &[], "a"),
+            Err(ErrorReturnCode::InvalidConfig)
+        );
+    }
+
+    #[test]
+    fn truncated_queuing_messages() {
+        let received = |len, copied, overflowed| Received {
+            len,
+            copied,
+            overflowed,
+        };
+
+        assert_eq!(
+            received_queuing_message(received(8, 8, false)),
+            Ok((8, false))
+        );
+        assert_eq!(
+            received_queuing_message(received(8, 8, true)),
+            Ok((8, true))
+        );
+        assert_eq!(
+            received_queuing_message(received(8, 7, false)),
+            Err(ErrorReturnCode::InvalidParam)
+        );
+        assert_eq!(
+            received_queuing_message(received(8, 0, true)),
+            Err(ErrorReturnCode::InvalidParam)
+        );
+        // Zero-length messages fit into any area
+        assert_eq!(
+            received_queuing_message(received(0, 0, false)),
+            Ok((0, false))
+        );
+    }
+}
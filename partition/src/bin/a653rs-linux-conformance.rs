@@ -0,0 +1,14 @@
+//! Prints the ARINC 653 conformance matrix of the partition library
+//!
+//! Pass `--csv` for machine-readable output.
+
+use a653rs_linux::apex::SERVICES;
+use a653rs_linux::conformance::{report, report_csv};
+
+fn main() {
+    if std::env::args().skip(1).any(|a| a == "--csv") {
+        print!("{}", report_csv(SERVICES));
+    } else {
+        print!("{}", report(SERVICES));
+    }
+}
@@ -0,0 +1,394 @@
+//! Blackboards for the communication between the processes of a partition
+//!
+//! A blackboard holds at most one message, which stays displayed until it is
+//! replaced or cleared. Each blackboard lives in a memfd of its own, sized to
+//! its maximum message size, and the blackboards created so far are listed in
+//! a registry like the ports of the partition, see [crate::BLACKBOARDS].
+//! Helper processes do not inherit the blackboards.
+//!
+//! The message is guarded by a sequence number, which is odd while a message
+//! is displayed or the blackboard is cleared. Readers copy the message and
+//! retry if the sequence number changed meanwhile. Processes waiting for a
+//! message sleep on the sequence number with a futex, so that they are woken
+//! by the next display instead of polling. So do processes finding another one
+//! in the middle of a change, until the change is done. A process frozen at
+//! the end of the window while waiting continues to wait in the next one.
+
+use std::ffi::CString;
+use std::fs::File;
+use std::mem::{size_of, ManuallyDrop};
+use std::os::fd::{FromRawFd, IntoRawFd, RawFd};
+use std::sync::atomic::{fence, AtomicU32, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use a653rs::bindings::{BlackboardId, ErrorReturnCode};
+use a653rs_linux_core::error::{ResultExt, SystemError, TypedResult};
+use anyhow::anyhow;
+use memmap2::MmapMut;
+use once_cell::sync::Lazy;
+
+use crate::time::Timeout;
+use crate::{futex, BLACKBOARDS};
+
+/// The blackboards mapped into this process, in the order of [BLACKBOARDS]
+static MAPPED: Lazy<Mutex<Vec<Arc<Blackboard>>>> = Lazy::new(Default::default);
+
+/// Start of the shared memory of a blackboard, which is followed by the
+/// message
+///
+/// A new memfd is filled with zeros, which is an empty blackboard.
+#[repr(C)]
+struct Header {
+    /// Odd while the message is changed, incremented by every change
+    sequence: AtomicU32,
+    /// Length of the displayed message, zero if the blackboard is empty
+    len: AtomicU32,
+    /// Processes waiting for a message to be displayed
+    waiting: AtomicU32,
+    /// Processes waiting for a change of the message to be done
+    contending: AtomicU32,
+}
+
+/// A blackboard mapped into this process
+#[derive(Debug)]
+pub(crate) struct Blackboard {
+    mmap: MmapMut,
+    max_size: usize,
+}
+
+impl Blackboard {
+    /// Creates the memfd of a blackboard for messages of up to `max_size`
+    /// bytes, returning its fd
+    pub(crate) fn create(name: &str, max_size: usize) -> TypedResult<RawFd> {
+        let name = CString::new(format!("blackboard_{name}")).typ(SystemError::Panic)?;
+        // Not close-on-exec, like the other files of the partition
+        let fd = unsafe { libc::memfd_create(name.as_ptr(), 0) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error()).typ(SystemError::Panic);
+        }
+        let file = unsafe { File::from_raw_fd(fd) };
+        file.set_len((size_of::<Header>() + max_size) as u64)
+            .typ(SystemError::Panic)?;
+        Ok(file.into_raw_fd())
+    }
+
+    /// Maps the blackboard in the memfd `fd` for messages of up to `max_size`
+    /// bytes
+    pub(crate) fn open(fd: RawFd, max_size: usize) -> TypedResult<Self> {
+        // The fd stays open for later mappings
+        let file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+        let mmap = unsafe { MmapMut::map_mut(&*file) }.typ(SystemError::Panic)?;
+        if mmap.len() < size_of::<Header>() + max_size {
+            return Err(anyhow!(
+                "blackboard of {} bytes does not fit messages of {max_size} bytes",
+                mmap.len()
+            ))
+            .typ(SystemError::Panic);
+        }
+        Ok(Self { mmap, max_size })
+    }
+
+    fn header(&self) -> &Header {
+        // The mapping is page aligned and large enough, see Blackboard::open
+        unsafe { &*(self.mmap.as_ptr() as *const Header) }
+    }
+
+    fn message(&self) -> &[AtomicU8] {
+        unsafe {
+            std::slice::from_raw_parts(
+                self.mmap.as_ptr().add(size_of::<Header>()) as *const AtomicU8,
+                self.max_size,
+            )
+        }
+    }
+
+    pub(crate) fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Number of processes waiting for a message
+    pub(crate) fn waiting(&self) -> u32 {
+        self.header().waiting.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.header().len.load(Ordering::Acquire) == 0
+    }
+
+    /// Sleeps until the change of the message at the odd `sequence` is done
+    fn wait_for_change(&self, sequence: u32) {
+        let header = self.header();
+        // The end of the change after loading the sequence number changed it, so that
+        // the wait returns at once
+        header.contending.fetch_add(1, Ordering::SeqCst);
+        futex::wait(&header.sequence, sequence, None);
+        // Saturate, should the memory have been tampered with
+        let _ = header
+            .contending
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+    }
+
+    /// Changes the message with `change`, while no other process does
+    fn change(&self, change: impl FnOnce(&Header, &[AtomicU8])) {
+        let header = self.header();
+        let mut sequence = header.sequence.load(Ordering::SeqCst);
+        loop {
+            if sequence % 2 == 1 {
+                self.wait_for_change(sequence);
+                sequence = header.sequence.load(Ordering::SeqCst);
+                continue;
+            }
+            match header.sequence.compare_exchange_weak(
+                sequence,
+                sequence.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => sequence = current,
+            }
+        }
+        change(header, self.message());
+        header
+            .sequence
+            .store(sequence.wrapping_add(2), Ordering::SeqCst);
+        if header.contending.load(Ordering::SeqCst) > 0 {
+            futex::wake(&header.sequence);
+        }
+    }
+
+    /// Displays `message`, waking all processes waiting for one
+    ///
+    /// The message must not be empty, or longer than the maximum message size.
+    pub(crate) fn display(&self, message: &[u8]) {
+        debug_assert!(!message.is_empty() && message.len() <= self.max_size);
+        self.change(|header, dst| {
+            for (dst, src) in dst.iter().zip(message) {
+                dst.store(*src, Ordering::Relaxed);
+            }
+            header.len.store(message.len() as u32, Ordering::Relaxed);
+        });
+        if self.waiting() > 0 {
+            futex::wake(&self.header().sequence);
+        }
+    }
+
+    pub(crate) fn clear(&self) {
+        self.change(|header, _| header.len.store(0, Ordering::Relaxed));
+    }
+
+    /// Copies the displayed message into `buf`, as far as it fits, and
+    /// returns its length, or `None` if the blackboard is empty
+    pub(crate) fn read(&self, buf: &mut [u8]) -> Option<usize> {
+        let header = self.header();
+        loop {
+            let before = header.sequence.load(Ordering::SeqCst);
+            if before % 2 == 1 {
+                self.wait_for_change(before);
+                continue;
+            }
+            let len = (header.len.load(Ordering::Relaxed) as usize).min(self.max_size);
+            for (dst, src) in buf.iter_mut().zip(&self.message()[..len]) {
+                *dst = src.load(Ordering::Relaxed);
+            }
+            fence(Ordering::Acquire);
+            if header.sequence.load(Ordering::Relaxed) == before {
+                return (len != 0).then_some(len);
+            }
+        }
+    }
+
+    /// Reads the message like [Blackboard::read], waiting up to `timeout` for
+    /// one to be displayed if the blackboard is empty
+    pub(crate) fn read_timeout(&self, buf: &mut [u8], timeout: Timeout) -> Option<usize> {
+        // A deadline too far in the future is the same as no deadline at all
+        let deadline = timeout
+            .duration()
+            .and_then(|d| Instant::now().checked_add(d));
+        let header = self.header();
+        loop {
+            let sequence = header.sequence.load(Ordering::SeqCst);
+            if let Some(len) = self.read(buf) {
+                return Some(len);
+            }
+            let remaining = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return None;
+                    }
+                    Some(remaining)
+                }
+                None => None,
+            };
+
+            // A display after loading the sequence number changed it, so that the wait
+            // returns at once
+            header.waiting.fetch_add(1, Ordering::SeqCst);
+            futex::wait(&header.sequence, sequence, remaining);
+            // Saturate, should the memory have been tampered with
+            let _ = header
+                .waiting
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+        }
+    }
+}
+
+/// The blackboard with the id `id`, mapping it into this process first if
+/// necessary
+///
+/// Yields [ErrorReturnCode::InvalidParam] if no such blackboard was created.
+pub(crate) fn get(id: BlackboardId) -> Result<Arc<Blackboard>, ErrorReturnCode> {
+    // Blackboard ids start at one
+    let index = usize::try_from(id)
+        .ok()
+        .and_then(|id| id.checked_sub(1))
+        .ok_or(ErrorReturnCode::InvalidParam)?;
+    let mut mapped = MAPPED.lock().unwrap();
+    if let Some(blackboard) = mapped.get(index) {
+        return Ok(blackboard.clone());
+    }
+
+    let created = BLACKBOARDS
+        .read()
+        .map_err(|_| ErrorReturnCode::NotAvailable)?;
+    if index >= created.len() {
+        return Err(ErrorReturnCode::InvalidParam);
+    }
+    for (_, fd, max_size) in created[mapped.len()..=index].iter() {
+        let blackboard = Blackboard::open(*fd, *max_size as usize).map_err(|e| {
+            trace!("yielding NotAvailable, because blackboard could not be mapped: {e}");
+            ErrorReturnCode::NotAvailable
+        })?;
+        mapped.push(Arc::new(blackboard));
+    }
+    Ok(mapped[index].clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn blackboard(max_size: usize) -> Blackboard {
+        let fd = Blackboard::create("test", max_size).unwrap();
+        Blackboard::open(fd, max_size).unwrap()
+    }
+
+    #[test]
+    fn display_read_clear() {
+        let blackboard = blackboard(8);
+        let mut buf = [0; 8];
+        assert!(blackboard.is_empty());
+        assert_eq!(blackboard.read(&mut buf), None);
+
+        blackboard.display(b"hello");
+        assert!(!blackboard.is_empty());
+        assert_eq!(blackboard.read(&mut buf), Some(5));
+        assert_eq!(&buf[..5], b"hello");
+        // Reading does not consume the message
+        assert_eq!(blackboard.read(&mut buf), Some(5));
+
+        blackboard.display(b"bye");
+        assert_eq!(blackboard.read(&mut buf), Some(3));
+        assert_eq!(&buf[..3], b"bye");
+
+        // A short buffer gets what fits, with the full length
+        let mut short = [0; 2];
+        assert_eq!(blackboard.read(&mut short), Some(3));
+        assert_eq!(&short, b"by");
+
+        blackboard.clear();
+        assert!(blackboard.is_empty());
+        assert_eq!(blackboard.read(&mut buf), None);
+    }
+
+    #[test]
+    fn shared_between_mappings() {
+        let fd = Blackboard::create("shared", 4).unwrap();
+        let writer = Blackboard::open(fd, 4).unwrap();
+        let reader = Blackboard::open(fd, 4).unwrap();
+        writer.display(b"1234");
+        let mut buf = [0; 4];
+        assert_eq!(reader.read(&mut buf), Some(4));
+        assert_eq!(&buf, b"1234");
+
+        // Mapped for larger messages than it was created for
+        assert!(Blackboard::open(fd, 4096).is_err());
+    }
+
+    #[test]
+    fn waiting_for_a_message() {
+        let blackboard = Arc::new(blackboard(8));
+        let mut buf = [0; 8];
+        assert_eq!(blackboard.read_timeout(&mut buf, Timeout::Immediate), None);
+
+        let start = Instant::now();
+        let timeout = Duration::from_millis(20);
+        assert_eq!(
+            blackboard.read_timeout(&mut buf, Timeout::Finite(timeout)),
+            None
+        );
+        assert!(start.elapsed() >= timeout);
+        assert_eq!(blackboard.waiting(), 0);
+
+        let reader = {
+            let blackboard = blackboard.clone();
+            thread::spawn(move || {
+                let mut buf = [0; 8];
+                let len = blackboard.read_timeout(&mut buf, Timeout::Infinite);
+                (len, buf)
+            })
+        };
+        while blackboard.waiting() == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        blackboard.display(b"wake up");
+        let (len, buf) = reader.join().unwrap();
+        assert_eq!(len, Some(7));
+        assert_eq!(&buf[..7], b"wake up");
+        assert_eq!(blackboard.waiting(), 0);
+
+        // A displayed message is read without waiting
+        assert_eq!(
+            blackboard.read_timeout(&mut [0; 8], Timeout::Infinite),
+            Some(7)
+        );
+    }
+
+    #[test]
+    fn waiting_for_a_change() {
+        let blackboard = Arc::new(blackboard(8));
+        blackboard.display(b"before");
+        let reader = {
+            let blackboard = blackboard.clone();
+            thread::spawn(move || {
+                while blackboard
+                    .header()
+                    .sequence
+                    .load(Ordering::SeqCst)
+                    .is_multiple_of(2)
+                {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                let mut buf = [0; 8];
+                let len = blackboard.read(&mut buf);
+                (len, buf)
+            })
+        };
+        // The reader sleeps until the change is done
+        blackboard.change(|header, message| {
+            while header.contending.load(Ordering::SeqCst) == 0 {
+                thread::sleep(Duration::from_millis(1));
+            }
+            message[0].store(b'B', Ordering::Relaxed);
+        });
+        let (len, buf) = reader.join().unwrap();
+        assert_eq!(len, Some(6));
+        assert_eq!(&buf[..6], b"Before");
+        assert_eq!(blackboard.header().contending.load(Ordering::SeqCst), 0);
+    }
+}
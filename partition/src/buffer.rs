@@ -0,0 +1,226 @@
+//! Buffers for the communication between the processes of a partition
+//!
+//! A buffer queues up to a maximum number of messages of up to a maximum size,
+//! both given on creation. Each buffer is a [MessageBuffer] in a memfd of its
+//! own, and the buffers created so far are listed in a registry like the
+//! blackboards, see [crate::BUFFERS]. Helper processes do not inherit the
+//! buffers.
+//!
+//! Processes blocked on a full or an empty buffer poll it like those blocked
+//! on a queuing port, see [Timeout::retry_parked], and are counted as waiting
+//! meanwhile. A [MessageBuffer] supports a single consumer only, so receiving
+//! processes take turns for every attempt.
+
+use std::os::fd::{BorrowedFd, IntoRawFd, RawFd};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use a653rs::bindings::{BufferId, ErrorReturnCode};
+use a653rs_linux_core::buffer::{BufferError, MessageBuffer};
+use a653rs_linux_core::error::{ResultExt, SystemError, TypedResult};
+use once_cell::sync::Lazy;
+
+use crate::time::Timeout;
+use crate::BUFFERS;
+
+/// The buffers mapped into this process, in the order of [BUFFERS]
+static MAPPED: Lazy<Mutex<Vec<Arc<Buffer>>>> = Lazy::new(Default::default);
+
+/// A buffer mapped into this process
+#[derive(Debug)]
+pub(crate) struct Buffer {
+    messages: MessageBuffer,
+    /// Processes waiting to send or receive a message
+    waiting: AtomicU32,
+    /// Held by a receiving process while it pops a message
+    receiver: Mutex<()>,
+}
+
+impl Buffer {
+    /// Creates the memfd of a buffer of `capacity` messages of up to
+    /// `max_size` bytes, returning its fd
+    pub(crate) fn create(name: &str, max_size: usize, capacity: usize) -> TypedResult<RawFd> {
+        let messages = MessageBuffer::new(format!("buffer_{name}"), max_size, capacity)
+            .typ(SystemError::Panic)?;
+        // The fd stays open for later mappings, while this one is dropped
+        let fd = messages.fd().try_clone_to_owned().typ(SystemError::Panic)?;
+        Ok(fd.into_raw_fd())
+    }
+
+    /// Maps the buffer in the memfd `fd`
+    pub(crate) fn open(fd: RawFd) -> TypedResult<Self> {
+        let fd = unsafe { BorrowedFd::borrow_raw(fd) }
+            .try_clone_to_owned()
+            .typ(SystemError::Panic)?;
+        // Only buffer::create puts fds into the registry
+        let messages = unsafe { MessageBuffer::from_fd(fd) }.typ(SystemError::Panic)?;
+        Ok(Self {
+            messages,
+            waiting: AtomicU32::new(0),
+            receiver: Mutex::new(()),
+        })
+    }
+
+    pub(crate) fn max_size(&self) -> usize {
+        self.messages.msg_size()
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.messages.capacity()
+    }
+
+    /// Number of messages in the buffer
+    pub(crate) fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// Number of processes waiting to send or receive a message
+    pub(crate) fn waiting(&self) -> u32 {
+        self.waiting.load(Ordering::SeqCst)
+    }
+
+    /// Appends `message`, waiting for at most `timeout` while the buffer is
+    /// full
+    ///
+    /// Returns whether the message was sent. The message must not be longer
+    /// than [Buffer::max_size].
+    pub(crate) fn send(&self, message: &[u8], timeout: Timeout) -> bool {
+        let mut buffer = self;
+        timeout
+            .retry_parked(&mut buffer, park, |buffer| {
+                matches!(buffer.messages.push(message), Ok(true)).then_some(())
+            })
+            .is_some()
+    }
+
+    /// Removes the oldest message and copies it into `buf`, waiting for at
+    /// most `timeout` while the buffer is empty
+    ///
+    /// Returns `None` if no message arrived in time. A message longer than
+    /// `buf` stays in the buffer and yields an error.
+    pub(crate) fn receive(
+        &self,
+        buf: &mut [u8],
+        timeout: Timeout,
+    ) -> Option<Result<usize, BufferError>> {
+        let mut buffer = self;
+        timeout.retry_parked(&mut buffer, park, |buffer| {
+            let _turn = buffer.receiver.lock().unwrap();
+            buffer.messages.pop(buf).transpose()
+        })
+    }
+}
+
+/// Counts the caller as waiting on `buffer` while it is `parked`
+fn park(buffer: &mut &Buffer, parked: bool) {
+    if parked {
+        buffer.waiting.fetch_add(1, Ordering::SeqCst);
+    } else {
+        buffer.waiting.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Gets the buffer `id`, mapping the buffers created so far if necessary
+pub(crate) fn get(id: BufferId) -> Result<Arc<Buffer>, ErrorReturnCode> {
+    // Buffer ids start at one
+    let index = usize::try_from(id)
+        .ok()
+        .and_then(|id| id.checked_sub(1))
+        .ok_or(ErrorReturnCode::InvalidParam)?;
+    let mut mapped = MAPPED.lock().unwrap();
+    if let Some(buffer) = mapped.get(index) {
+        return Ok(buffer.clone());
+    }
+
+    let created = BUFFERS.read().map_err(|_| ErrorReturnCode::NotAvailable)?;
+    if index >= created.len() {
+        return Err(ErrorReturnCode::InvalidParam);
+    }
+    for (_, fd) in created[mapped.len()..=index].iter() {
+        let buffer = Buffer::open(*fd).map_err(|e| {
+            trace!("yielding NotAvailable, because buffer could not be mapped: {e}");
+            ErrorReturnCode::NotAvailable
+        })?;
+        mapped.push(Arc::new(buffer));
+    }
+    Ok(mapped[index].clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    fn buffer(max_size: usize, capacity: usize) -> Buffer {
+        let fd = Buffer::create("test", max_size, capacity).unwrap();
+        Buffer::open(fd).unwrap()
+    }
+
+    #[test]
+    fn messages_in_order() {
+        let buffer = buffer(8, 3);
+        assert_eq!(buffer.max_size(), 8);
+        assert_eq!(buffer.capacity(), 3);
+
+        for message in [&b"first"[..], b"second", b"third"] {
+            assert!(buffer.send(message, Timeout::Immediate));
+        }
+        assert_eq!(buffer.len(), 3);
+        // The buffer overflows
+        assert!(!buffer.send(b"fourth", Timeout::Immediate));
+        let start = Instant::now();
+        let timeout = Duration::from_millis(20);
+        assert!(!buffer.send(b"fourth", Timeout::Finite(timeout)));
+        assert!(start.elapsed() >= timeout);
+        assert_eq!(buffer.waiting(), 0);
+
+        let mut buf = [0; 8];
+        for message in [&b"first"[..], b"second", b"third"] {
+            let len = buffer.receive(&mut buf, Timeout::Immediate);
+            assert_eq!(len.unwrap().unwrap(), message.len());
+            assert_eq!(&buf[..message.len()], message);
+        }
+        assert!(buffer.receive(&mut buf, Timeout::Immediate).is_none());
+
+        // A message too long for the area is kept
+        assert!(buffer.send(b"long", Timeout::Immediate));
+        assert!(buffer
+            .receive(&mut [0; 2], Timeout::Immediate)
+            .unwrap()
+            .is_err());
+        assert_eq!(buffer.len(), 1);
+    }
+
+    #[test]
+    fn producer_and_consumer() {
+        let fd = Buffer::create("shared", 8, 2).unwrap();
+        let consumer = Arc::new(Buffer::open(fd).unwrap());
+        let producer = Buffer::open(fd).unwrap();
+
+        let receiving = {
+            let consumer = consumer.clone();
+            thread::spawn(move || {
+                let mut received = Vec::new();
+                let mut buf = [0; 8];
+                while let Some(len) = consumer.receive(&mut buf, Timeout::Infinite) {
+                    let len = len.unwrap();
+                    if len == 0 {
+                        break;
+                    }
+                    received.push(u64::from_ne_bytes(buf));
+                }
+                received
+            })
+        };
+        // The producer blocks while the consumer has not caught up
+        for i in 0..100u64 {
+            assert!(producer.send(&i.to_ne_bytes(), Timeout::Infinite));
+        }
+        assert!(producer.send(b"", Timeout::Infinite));
+
+        assert_eq!(receiving.join().unwrap(), (0..100).collect::<Vec<_>>());
+        assert_eq!(consumer.waiting(), 0);
+    }
+}
@@ -0,0 +1,475 @@
+//! Partitions assembled at runtime, without the `partition` macro of a653rs
+//!
+//! A [PartitionBuilder] takes the start hooks and the processes of a
+//! partition as closures:
+//!
+//! ```no_run
+//! use a653rs_linux::builder::{PartitionBuilder, ProcessOptions};
+//!
+//! PartitionBuilder::new()
+//!     .cold_start(|_ctx| {
+//!         // Create the ports here and return them as the state of the processes
+//!         42
+//!     })
+//!     .periodic("worker", ProcessOptions::default(), |ctx| loop {
+//!         log::info!("the answer is {}", ctx.state());
+//!         ctx.periodic_wait().unwrap();
+//!     })
+//!     .run();
+//! ```
+//!
+//! The start hook returns the state shared by the processes, e.g. the ports
+//! it created, which each process accesses through its [ProcessContext]. This
+//! is what the macro generates for a partition as well, but the processes may
+//! be chosen at runtime, e.g. by a plugin loader or a script host.
+//!
+//! Processes are still created and started in COLD_START or WARM_START, from
+//! the same start hook. Like with the macro, a partition has at most one
+//! periodic and one aperiodic process.
+//!
+//! The ports the start hook creates may be declared with
+//! [PartitionBuilder::declare_ports], which sends them to the hypervisor on
+//! every start, before the hook runs. A mismatch with the channel
+//! configuration is then reported at once, see
+//! [ApexLinuxPartition::declare_ports].
+
+use std::fmt::Display;
+use std::ops::Deref;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use a653rs::bindings::{ApexTimeP4, ErrorReturnCode, Priority, StackSize};
+use a653rs::prelude::{
+    Deadline, Name, Partition, PartitionExt, ProcessAttribute, StartContext, SystemTime,
+};
+use once_cell::sync::OnceCell;
+
+use crate::partition::{ApexLinuxPartition, PortDecl};
+
+/// Body of a process whose state was bound to it
+type Entry = Box<dyn Fn() + Send + Sync>;
+
+/// Bodies of the processes, called by their entry points
+static PERIODIC_ENTRY: OnceCell<Entry> = OnceCell::new();
+static APERIODIC_ENTRY: OnceCell<Entry> = OnceCell::new();
+
+extern "C" fn periodic_entry() {
+    if let Some(entry) = PERIODIC_ENTRY.get() {
+        entry()
+    }
+}
+
+extern "C" fn aperiodic_entry() {
+    if let Some(entry) = APERIODIC_ENTRY.get() {
+        entry()
+    }
+}
+
+type StartHook<S> = Box<dyn Fn(&mut StartContext<ApexLinuxPartition>) -> S>;
+type Body<S> = Arc<dyn Fn(&ProcessContext<S>) + Send + Sync>;
+
+/// Attributes of a process besides its name, entry point and period
+#[derive(Debug, Clone)]
+pub struct ProcessOptions {
+    pub time_capacity: SystemTime,
+    pub stack_size: StackSize,
+    pub base_priority: Priority,
+    pub deadline: Deadline,
+}
+
+impl Default for ProcessOptions {
+    /// Unlimited time capacity, 100KB of stack, priority 1 and a soft deadline
+    fn default() -> Self {
+        Self {
+            time_capacity: SystemTime::Infinite,
+            stack_size: 100_000,
+            base_priority: 1,
+            deadline: Deadline::Soft,
+        }
+    }
+}
+
+/// Why a [PartitionBuilder] does not describe a partition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    /// No hook was given with [PartitionBuilder::cold_start]
+    MissingColdStart,
+    /// More than one process has this name
+    DuplicateName(String),
+    /// This name is not a valid ARINC 653 name, e.g. as it is too long
+    InvalidName(String),
+    /// A second periodic or aperiodic process, which a partition does not
+    /// support
+    TooManyProcesses { periodic: bool },
+}
+
+impl Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildError::MissingColdStart => f.write_str("the partition has no cold start hook"),
+            BuildError::DuplicateName(name) => {
+                write!(f, "more than one process is named {name:?}")
+            }
+            BuildError::InvalidName(name) => write!(f, "{name:?} is not a valid process name"),
+            BuildError::TooManyProcesses { periodic: true } => {
+                f.write_str("a partition has at most one periodic process")
+            }
+            BuildError::TooManyProcesses { periodic: false } => {
+                f.write_str("a partition has at most one aperiodic process")
+            }
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// What a process gets to see of the partition
+///
+/// Dereferences to the state returned by the start hook.
+pub struct ProcessContext<'a, S> {
+    state: &'a S,
+    name: &'a str,
+}
+
+impl<S> ProcessContext<'_, S> {
+    /// The state returned by the start hook
+    pub fn state(&self) -> &S {
+        self.state
+    }
+
+    /// Name of this process
+    pub fn name(&self) -> &str {
+        self.name
+    }
+
+    /// Waits for the next release of this process, see `PERIODIC_WAIT`
+    pub fn periodic_wait(&self) -> Result<(), ErrorReturnCode> {
+        ApexLinuxPartition::periodic_wait()
+    }
+
+    /// The current module time, see `GET_TIME`
+    pub fn get_time(&self) -> SystemTime {
+        SystemTime::new(ApexLinuxPartition::get_time())
+    }
+}
+
+impl<S> Deref for ProcessContext<'_, S> {
+    type Target = S;
+
+    fn deref(&self) -> &S {
+        self.state
+    }
+}
+
+struct ProcessSpec<S> {
+    name: String,
+    periodic: bool,
+    options: ProcessOptions,
+    body: Body<S>,
+}
+
+/// Collects the start hooks and processes of a partition, see the [module
+/// documentation](self)
+pub struct PartitionBuilder<S> {
+    cold_start: Option<StartHook<S>>,
+    warm_start: Option<StartHook<S>>,
+    processes: Vec<ProcessSpec<S>>,
+    ports: Vec<PortDecl>,
+}
+
+impl<S: Send + Sync + 'static> Default for PartitionBuilder<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: Send + Sync + 'static> PartitionBuilder<S> {
+    pub fn new() -> Self {
+        Self {
+            cold_start: None,
+            warm_start: None,
+            processes: Vec::new(),
+            ports: Vec::new(),
+        }
+    }
+
+    /// Runs `hook` in COLD_START, returning the state of the processes
+    ///
+    /// This is also the hook for WARM_START, unless another one is given with
+    /// [PartitionBuilder::warm_start].
+    pub fn cold_start(
+        mut self,
+        hook: impl Fn(&mut StartContext<ApexLinuxPartition>) -> S + 'static,
+    ) -> Self {
+        self.cold_start = Some(Box::new(hook));
+        self
+    }
+
+    /// Runs `hook` in WARM_START instead of the cold start hook
+    pub fn warm_start(
+        mut self,
+        hook: impl Fn(&mut StartContext<ApexLinuxPartition>) -> S + 'static,
+    ) -> Self {
+        self.warm_start = Some(Box::new(hook));
+        self
+    }
+
+    /// Declares ports the start hooks are going to create
+    ///
+    /// The declarations are sent to the hypervisor before a start hook runs.
+    /// With `strict_ports` enabled in the partition configuration, a mismatch
+    /// fails the initialization of the partition.
+    pub fn declare_ports(mut self, ports: impl IntoIterator<Item = PortDecl>) -> Self {
+        self.ports.extend(ports);
+        self
+    }
+
+    /// Adds the periodic process `name`, which runs `body` once it is first
+    /// released
+    ///
+    /// Like the entry point of a process, `body` is called only once. It
+    /// waits for each following release with
+    /// [ProcessContext::periodic_wait].
+    pub fn periodic(
+        self,
+        name: &str,
+        options: ProcessOptions,
+        body: impl Fn(&ProcessContext<S>) + Send + Sync + 'static,
+    ) -> Self {
+        self.process(name, true, options, Arc::new(body))
+    }
+
+    /// Adds the aperiodic process `name`, which runs `body` once the partition
+    /// enters NORMAL
+    pub fn aperiodic(
+        self,
+        name: &str,
+        options: ProcessOptions,
+        body: impl Fn(&ProcessContext<S>) + Send + Sync + 'static,
+    ) -> Self {
+        self.process(name, false, options, Arc::new(body))
+    }
+
+    fn process(
+        mut self,
+        name: &str,
+        periodic: bool,
+        options: ProcessOptions,
+        body: Body<S>,
+    ) -> Self {
+        self.processes.push(ProcessSpec {
+            name: name.to_string(),
+            periodic,
+            options,
+            body,
+        });
+        self
+    }
+
+    /// Checks that the hooks and processes describe a partition
+    pub fn build(self) -> Result<BuiltPartition<S>, BuildError> {
+        let cold_start = self.cold_start.ok_or(BuildError::MissingColdStart)?;
+        for (i, process) in self.processes.iter().enumerate() {
+            if Name::from_str(&process.name).is_err() {
+                return Err(BuildError::InvalidName(process.name.clone()));
+            }
+            let earlier = &self.processes[..i];
+            if earlier.iter().any(|p| p.name == process.name) {
+                return Err(BuildError::DuplicateName(process.name.clone()));
+            }
+            if earlier.iter().any(|p| p.periodic == process.periodic) {
+                return Err(BuildError::TooManyProcesses {
+                    periodic: process.periodic,
+                });
+            }
+        }
+
+        Ok(BuiltPartition {
+            cold_start,
+            warm_start: self.warm_start,
+            processes: self.processes,
+            ports: self.ports,
+        })
+    }
+
+    /// Builds the partition and runs it
+    ///
+    /// # Panics
+    /// If the partition can not be built, see [PartitionBuilder::build].
+    pub fn run(self) {
+        match self.build() {
+            Ok(partition) => partition.run(),
+            Err(e) => panic!("invalid partition: {e}"),
+        }
+    }
+}
+
+/// A partition described by a [PartitionBuilder]
+pub struct BuiltPartition<S> {
+    cold_start: StartHook<S>,
+    warm_start: Option<StartHook<S>>,
+    processes: Vec<ProcessSpec<S>>,
+    ports: Vec<PortDecl>,
+}
+
+impl<S: Send + Sync + 'static> BuiltPartition<S> {
+    /// Declares the ports, runs `hook`, then creates and starts the processes
+    /// with its state
+    fn start(&self, hook: &StartHook<S>, ctx: &mut StartContext<ApexLinuxPartition>) {
+        if !self.ports.is_empty() {
+            ApexLinuxPartition::declare_ports(self.ports.clone());
+        }
+        let state = Arc::new(hook(ctx));
+
+        for process in &self.processes {
+            let (slot, entry_point, period) = if process.periodic {
+                (
+                    &PERIODIC_ENTRY,
+                    periodic_entry as extern "C" fn(),
+                    SystemTime::Normal(Duration::ZERO),
+                )
+            } else {
+                (
+                    &APERIODIC_ENTRY,
+                    aperiodic_entry as extern "C" fn(),
+                    SystemTime::Infinite,
+                )
+            };
+
+            let body = process.body.clone();
+            let state = state.clone();
+            let name = process.name.clone();
+            let entry: Entry = Box::new(move || {
+                body(&ProcessContext {
+                    state: &state,
+                    name: &name,
+                })
+            });
+            if slot.set(entry).is_err() {
+                panic!("process {} was created before", process.name);
+            }
+
+            let ProcessOptions {
+                time_capacity,
+                stack_size,
+                base_priority,
+                deadline,
+            } = process.options.clone();
+            let attributes = ProcessAttribute {
+                period,
+                time_capacity,
+                entry_point,
+                stack_size,
+                base_priority,
+                deadline,
+                // Checked by the builder
+                name: Name::from_str(&process.name).unwrap(),
+            };
+            let handle = ctx
+                .create_process(attributes)
+                .unwrap_or_else(|e| panic!("failed to create process {}: {e:?}", process.name));
+            handle
+                .start()
+                .unwrap_or_else(|e| panic!("failed to start process {}: {e:?}", process.name));
+        }
+    }
+}
+
+impl<S: Send + Sync + 'static> Partition<ApexLinuxPartition> for BuiltPartition<S> {
+    fn cold_start(&self, ctx: &mut StartContext<ApexLinuxPartition>) {
+        self.start(&self.cold_start, ctx)
+    }
+
+    fn warm_start(&self, ctx: &mut StartContext<ApexLinuxPartition>) {
+        self.start(self.warm_start.as_ref().unwrap_or(&self.cold_start), ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builder() -> PartitionBuilder<()> {
+        PartitionBuilder::new().cold_start(|_| ())
+    }
+
+    #[test]
+    fn valid() {
+        let partition = builder()
+            .periodic("periodic", ProcessOptions::default(), |_| {})
+            .aperiodic("aperiodic", ProcessOptions::default(), |_| {})
+            .build()
+            .unwrap();
+        assert_eq!(partition.processes.len(), 2);
+        assert!(partition.warm_start.is_none());
+
+        assert!(builder().build().is_ok());
+    }
+
+    #[test]
+    fn declared_ports() {
+        let port = |name: &str| PortDecl::Sampling {
+            name: name.to_string(),
+            dir: a653rs::bindings::PortDirection::Source,
+            msg_size: 8,
+        };
+        let partition = builder()
+            .declare_ports([port("a")])
+            .declare_ports([port("b")])
+            .build()
+            .unwrap();
+        assert_eq!(partition.ports, [port("a"), port("b")]);
+    }
+
+    #[test]
+    fn missing_cold_start() {
+        let missing = PartitionBuilder::<()>::new()
+            .warm_start(|_| ())
+            .periodic("periodic", ProcessOptions::default(), |_| {})
+            .build();
+        assert_eq!(missing.err(), Some(BuildError::MissingColdStart));
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let duplicate = builder()
+            .periodic("worker", ProcessOptions::default(), |_| {})
+            .aperiodic("worker", ProcessOptions::default(), |_| {})
+            .build();
+        assert_eq!(
+            duplicate.err(),
+            Some(BuildError::DuplicateName("worker".to_string()))
+        );
+    }
+
+    #[test]
+    fn too_many_processes() {
+        let periodic = builder()
+            .periodic("a", ProcessOptions::default(), |_| {})
+            .periodic("b", ProcessOptions::default(), |_| {})
+            .build();
+        assert_eq!(
+            periodic.err(),
+            Some(BuildError::TooManyProcesses { periodic: true })
+        );
+
+        let aperiodic = builder()
+            .aperiodic("a", ProcessOptions::default(), |_| {})
+            .aperiodic("b", ProcessOptions::default(), |_| {})
+            .build();
+        assert_eq!(
+            aperiodic.err(),
+            Some(BuildError::TooManyProcesses { periodic: false })
+        );
+    }
+
+    #[test]
+    fn invalid_names() {
+        let long = "x".repeat(64);
+        let invalid = builder()
+            .aperiodic(&long, ProcessOptions::default(), |_| {})
+            .build();
+        assert_eq!(invalid.err(), Some(BuildError::InvalidName(long)));
+    }
+}
@@ -0,0 +1,339 @@
+//! Transfer of files over queuing ports
+//!
+//! A file rarely fits into a single message, so [send_file] splits it into
+//! chunks, which [recv_file] puts together again on the other end of the
+//! channel. Every chunk starts with a header of [HEADER_LEN] bytes:
+//!
+//! - the sequence number of the chunk, starting at zero for every file, as a
+//!   little endian `u32`
+//! - flags, of which the lowest bit marks the last chunk of the file
+//!
+//! The payload of up to `chunk_size` bytes follows. Even an empty file is
+//! sent as a single, empty last chunk.
+//!
+//! The hypervisor only transfers the chunks after the window of the sending
+//! partition, so at most the number of messages of the channel are sent per
+//! window. Once the queue of the source port is full, the sender backs off
+//! by blocking until the hypervisor made space, instead of overflowing the
+//! queue. The receiver still checks the sequence numbers and fails on a
+//! missing chunk, e.g. if the sender gave up on one after its timeout. A
+//! first chunk received in the middle of a file, e.g. from a restarted
+//! sender, starts the file over.
+
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use a653rs::bindings::{
+    ApexByte, ApexQueuingPortP4, ErrorReturnCode, QueueOverflow, QueuingPortId,
+};
+use a653rs::prelude::SystemTime;
+
+use crate::partition::ApexLinuxPartition;
+use crate::time;
+
+/// Length of the header in front of the payload of every chunk
+pub const HEADER_LEN: usize = 5;
+
+/// Flag of the last chunk of a file
+const LAST: u8 = 1;
+
+/// Errors of a file transfer
+#[derive(Debug)]
+pub enum ChunkedError {
+    /// A service of the queuing port failed
+    Port(ErrorReturnCode),
+    /// Reading or writing the file failed
+    Io(io::Error),
+    /// The received chunk does not follow the previous one
+    Missing { expected: u32, received: u32 },
+    /// A message too short for the header of a chunk
+    Malformed { len: usize },
+    /// The file needs more chunks than the sequence numbers allow
+    TooManyChunks,
+}
+
+impl Display for ChunkedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChunkedError::Port(e) => write!(f, "queuing port failed: {e:?}"),
+            ChunkedError::Io(e) => write!(f, "file access failed: {e}"),
+            ChunkedError::Missing { expected, received } => {
+                write!(f, "received chunk {received} instead of {expected}")
+            }
+            ChunkedError::Malformed { len } => {
+                write!(f, "message of {len} bytes is too short for a chunk")
+            }
+            ChunkedError::TooManyChunks => f.write_str("file needs too many chunks"),
+        }
+    }
+}
+
+impl std::error::Error for ChunkedError {}
+
+impl From<ErrorReturnCode> for ChunkedError {
+    fn from(value: ErrorReturnCode) -> Self {
+        ChunkedError::Port(value)
+    }
+}
+
+impl From<io::Error> for ChunkedError {
+    fn from(value: io::Error) -> Self {
+        ChunkedError::Io(value)
+    }
+}
+
+/// Sends the file at `path` on the queuing source `port` in chunks of up to
+/// `chunk_size` bytes, returning the length of the file
+///
+/// Every chunk waits for up to `timeout` for space in the queue. The header
+/// and `chunk_size` must fit into a message of the port, otherwise
+/// [ErrorReturnCode::InvalidConfig] is returned.
+pub fn send_file(
+    port: QueuingPortId,
+    path: impl AsRef<Path>,
+    chunk_size: usize,
+    timeout: SystemTime,
+) -> Result<u64, ChunkedError> {
+    let status = ApexLinuxPartition::get_queuing_port_status(port)?;
+    if chunk_size == 0 || HEADER_LEN + chunk_size > status.max_message_size as usize {
+        return Err(ErrorReturnCode::InvalidConfig.into());
+    }
+    let timeout = time::to_apex_timeout(timeout);
+    send(File::open(path)?, chunk_size, |chunk| {
+        ApexLinuxPartition::send_queuing_message(port, chunk, timeout)
+    })
+}
+
+/// Receives a file sent with [send_file] on the queuing destination `port`
+/// and writes it to `path`, returning its length
+///
+/// Every chunk is waited for for up to `timeout`.
+pub fn recv_file(
+    port: QueuingPortId,
+    path: impl AsRef<Path>,
+    timeout: SystemTime,
+) -> Result<u64, ChunkedError> {
+    let status = ApexLinuxPartition::get_queuing_port_status(port)?;
+    let mut buf = vec![0; status.max_message_size as usize];
+    let mut file = File::create(path)?;
+    let timeout = time::to_apex_timeout(timeout);
+    let len = recv(&mut file, &mut buf, |buf| {
+        let (len, overflow) =
+            unsafe { ApexLinuxPartition::receive_queuing_message(port, timeout, buf) }?;
+        Ok((len as usize, overflow))
+    })?;
+    // A restarted transfer may have left a longer file behind
+    file.set_len(len)?;
+    Ok(len)
+}
+
+/// Sends the chunks of `file` with `send`
+fn send(
+    mut file: impl Read,
+    chunk_size: usize,
+    mut send: impl FnMut(&[ApexByte]) -> Result<(), ErrorReturnCode>,
+) -> Result<u64, ChunkedError> {
+    // One chunk is read ahead, to know whether the current one is the last
+    let mut current = vec![0; HEADER_LEN + chunk_size];
+    let mut next = vec![0; HEADER_LEN + chunk_size];
+    let mut len = read_full(&mut file, &mut current[HEADER_LEN..])?;
+    let mut sent = 0;
+    let mut seq = 0u32;
+    loop {
+        let next_len = if len < chunk_size {
+            0
+        } else {
+            read_full(&mut file, &mut next[HEADER_LEN..])?
+        };
+        let last = next_len == 0;
+
+        current[..4].copy_from_slice(&seq.to_le_bytes());
+        current[4] = if last { LAST } else { 0 };
+        send(&current[..HEADER_LEN + len])?;
+        sent += len as u64;
+        if last {
+            return Ok(sent);
+        }
+
+        std::mem::swap(&mut current, &mut next);
+        len = next_len;
+        seq = seq.checked_add(1).ok_or(ChunkedError::TooManyChunks)?;
+    }
+}
+
+/// Reads until `buf` is full or the end of `file` is reached
+fn read_full(file: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match file.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+/// Writes the chunks received with `receive` to `file`, until the last one
+fn recv(
+    file: &mut (impl Write + Seek),
+    buf: &mut [ApexByte],
+    mut receive: impl FnMut(&mut [ApexByte]) -> Result<(usize, QueueOverflow), ErrorReturnCode>,
+) -> Result<u64, ChunkedError> {
+    let mut expected = 0u32;
+    let mut len = 0;
+    loop {
+        let (msg_len, overflow) = receive(buf)?;
+        if overflow {
+            // Lost chunks are noticed by their sequence numbers
+            debug!("queue of the file transfer overflowed");
+        }
+        let msg = &buf[..msg_len];
+        if msg.len() < HEADER_LEN {
+            return Err(ChunkedError::Malformed { len: msg.len() });
+        }
+        let seq = u32::from_le_bytes(msg[..4].try_into().unwrap());
+        if seq == 0 && expected != 0 {
+            info!("file transfer started over after {expected} chunks");
+            file.seek(SeekFrom::Start(0))?;
+            len = 0;
+        } else if seq != expected {
+            return Err(ChunkedError::Missing {
+                expected,
+                received: seq,
+            });
+        }
+
+        let payload = &msg[HEADER_LEN..];
+        file.write_all(payload)?;
+        len += payload.len() as u64;
+        if msg[4] & LAST != 0 {
+            file.flush()?;
+            return Ok(len);
+        }
+        expected = seq.checked_add(1).ok_or(ChunkedError::TooManyChunks)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// Pseudo-random bytes, so that misplaced chunks are noticed
+    fn content(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    /// Chunks sent of `data`
+    fn chunks(data: &[u8], chunk_size: usize) -> VecDeque<Vec<u8>> {
+        let mut chunks = VecDeque::new();
+        let sent = send(data, chunk_size, |chunk| {
+            chunks.push_back(chunk.to_vec());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(sent, data.len() as u64);
+        chunks
+    }
+
+    /// Receives `chunks` into a file, returning its content
+    fn receive(mut chunks: VecDeque<Vec<u8>>, msg_size: usize) -> Result<Vec<u8>, ChunkedError> {
+        let mut file = Cursor::new(Vec::new());
+        let mut buf = vec![0; msg_size];
+        let len = recv(&mut file, &mut buf, |buf| {
+            let chunk = chunks.pop_front().ok_or(ErrorReturnCode::NotAvailable)?;
+            buf[..chunk.len()].copy_from_slice(&chunk);
+            Ok((chunk.len(), false))
+        })?;
+        let mut data = file.into_inner();
+        data.truncate(len as usize);
+        Ok(data)
+    }
+
+    #[test]
+    fn files_are_transferred() {
+        for (len, chunk_size) in [
+            (0, 16),
+            (1, 16),
+            (16, 16),
+            (17, 16),
+            (1 << 20, 64 * 1024 - 5),
+        ] {
+            let data = content(len);
+            let chunks = chunks(&data, chunk_size);
+            assert_eq!(chunks.len(), len.div_ceil(chunk_size).max(1), "{len}");
+            assert!(chunks.iter().all(|c| c.len() <= HEADER_LEN + chunk_size));
+            assert_eq!(
+                chunks.iter().filter(|c| c[4] & LAST != 0).count(),
+                1,
+                "{len}"
+            );
+            assert_eq!(receive(chunks, HEADER_LEN + chunk_size).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn missing_chunks_are_detected() {
+        let data = content(100);
+        let mut chunks = chunks(&data, 10);
+        chunks.remove(3);
+        assert!(matches!(
+            receive(chunks, 15),
+            Err(ChunkedError::Missing {
+                expected: 3,
+                received: 4
+            })
+        ));
+    }
+
+    #[test]
+    fn restarted_transfers_start_over() {
+        let first = content(100);
+        let second = content(35);
+        let mut chunks = chunks(&first, 10);
+        chunks.truncate(5);
+        chunks.extend(self::chunks(&second, 10));
+        assert_eq!(receive(chunks, 15).unwrap(), second);
+    }
+
+    #[test]
+    fn malformed_and_failed_receives() {
+        let chunks = VecDeque::from([vec![0, 0, 0]]);
+        assert!(matches!(
+            receive(chunks, 15),
+            Err(ChunkedError::Malformed { len: 3 })
+        ));
+        assert!(matches!(
+            receive(VecDeque::new(), 15),
+            Err(ChunkedError::Port(ErrorReturnCode::NotAvailable))
+        ));
+    }
+
+    #[test]
+    fn failed_sends_stop_the_transfer() {
+        let mut attempts = 0;
+        let result = send(&content(100)[..], 10, |_| {
+            attempts += 1;
+            Err(ErrorReturnCode::TimedOut)
+        });
+        assert!(matches!(
+            result,
+            Err(ChunkedError::Port(ErrorReturnCode::TimedOut))
+        ));
+        assert_eq!(attempts, 1);
+    }
+}
@@ -0,0 +1,216 @@
+//! Implementation status of the ARINC 653 services
+//!
+//! The status of every service is declared in [crate::apex::SERVICES] right
+//! next to the implementations. Entries for implemented services reference the
+//! trait function on [crate::partition::ApexLinuxPartition], so renaming or
+//! removing an implementation without updating the table is a compile error.
+//! Likewise, implementing a service group which the table lists as missing
+//! fails to compile until its entries are updated.
+//!
+//! Every implemented service has a smoke test in [smoke], which calls it
+//! within the `conformance` example partition and checks its result.
+
+use std::fmt::{Display, Write};
+
+pub mod smoke;
+
+/// Implementation status of a single APEX service
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// The service behaves as specified by the standard
+    Implemented,
+    /// The service is available, but deviates from the standard
+    Partial,
+    /// The service exists, but does not do anything useful yet
+    Stub,
+    /// The service is not available
+    Missing,
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Status::Implemented => "Implemented",
+            Status::Partial => "Partial",
+            Status::Stub => "Stub",
+            Status::Missing => "Missing",
+        };
+        f.pad(s)
+    }
+}
+
+/// Conformance entry of an APEX service
+#[derive(Debug, Clone, Copy)]
+pub struct Service {
+    /// Name of the a653rs trait providing the service
+    pub group: &'static str,
+    /// Name of the trait function implementing the service
+    pub name: &'static str,
+    pub status: Status,
+    /// Deviations from the standard
+    pub notes: &'static str,
+    /// Smoke test of an implemented service
+    pub smoke: Option<smoke::SmokeTest>,
+}
+
+impl Service {
+    /// ARINC 653 name of the service, e.g. `SEND_QUEUING_MESSAGE`
+    pub fn apex_name(&self) -> String {
+        self.name.to_uppercase()
+    }
+}
+
+/// Declares the conformance table of all APEX services.
+///
+/// Services with an implementation are checked at compile time against the
+/// trait implementation of [crate::partition::ApexLinuxPartition], and groups
+/// of missing services fail to compile once it implements their trait.
+/// Every implemented service gets the smoke test of the same name in [smoke],
+/// so a missing smoke test is a compile error as well.
+macro_rules! conformance_table {
+    (
+        $(impl $group:ident {
+            $($name:ident => $status:ident $(: $notes:literal)?,)*
+        })*
+        $(missing $m_group:ident {
+            $($m_name:ident,)*
+        })*
+    ) => {
+        $($(
+            const _: () = {
+                let _ = <crate::partition::ApexLinuxPartition as a653rs::bindings::$group>::$name;
+            };
+        )*)*
+
+        // The call of `missing_service` is ambiguous if the trait is implemented
+        $(
+            const _: fn() = || {
+                trait AmbiguousIfImplemented<A> {
+                    fn missing_service() {}
+                }
+                impl<T> AmbiguousIfImplemented<()> for T {}
+                struct Implemented;
+                impl<T: a653rs::bindings::$m_group> AmbiguousIfImplemented<Implemented> for T {}
+                <crate::partition::ApexLinuxPartition as AmbiguousIfImplemented<_>>::missing_service();
+            };
+        )*
+
+        /// Implementation status of all APEX services.
+        ///
+        /// See [crate::conformance] for generating a report from it.
+        pub const SERVICES: &[crate::conformance::Service] = &[
+            $($(
+                crate::conformance::Service {
+                    group: stringify!($group),
+                    name: stringify!($name),
+                    status: crate::conformance::Status::$status,
+                    notes: concat!("" $(, $notes)?),
+                    smoke: crate::conformance::smoke_test!($status $name),
+                },
+            )*)*
+            $($(
+                crate::conformance::Service {
+                    group: stringify!($m_group),
+                    name: stringify!($m_name),
+                    status: crate::conformance::Status::Missing,
+                    notes: "",
+                    smoke: None,
+                },
+            )*)*
+        ];
+    };
+}
+pub(crate) use conformance_table;
+
+/// The smoke test of a service, if it is implemented
+macro_rules! smoke_test {
+    (Implemented $name:ident) => {
+        Some(crate::conformance::smoke::$name)
+    };
+    ($status:ident $name:ident) => {
+        None
+    };
+}
+pub(crate) use smoke_test;
+
+/// Renders the conformance table as a human-readable text table
+pub fn report(services: &[Service]) -> String {
+    let name_width = services
+        .iter()
+        .map(|s| s.apex_name().len())
+        .max()
+        .unwrap_or_default();
+    let group_width = services
+        .iter()
+        .map(|s| s.group.len())
+        .max()
+        .unwrap_or_default();
+
+    let mut out = String::new();
+    for s in services {
+        let _ = writeln!(
+            out,
+            "{:group_width$}  {:name_width$}  {:11}  {}",
+            s.group,
+            s.apex_name(),
+            s.status,
+            s.notes
+        );
+    }
+    out
+}
+
+/// Renders the conformance table as CSV with a header line
+pub fn report_csv(services: &[Service]) -> String {
+    let mut out = String::from("group,service,status,notes\n");
+    for s in services {
+        let _ = writeln!(
+            out,
+            "{},{},{},\"{}\"",
+            s.group,
+            s.apex_name(),
+            s.status,
+            s.notes.replace('"', "\"\"")
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::apex::SERVICES;
+
+    #[test]
+    fn services_are_unique() {
+        let mut seen = HashSet::new();
+        for s in SERVICES {
+            assert!(seen.insert((s.group, s.name)), "duplicate entry {s:?}");
+        }
+    }
+
+    #[test]
+    fn deviations_are_explained() {
+        for s in SERVICES {
+            if let Status::Partial | Status::Stub = s.status {
+                assert!(!s.notes.is_empty(), "{s:?} has no notes");
+            }
+        }
+    }
+
+    #[test]
+    fn csv_lists_every_service() {
+        let csv = report_csv(SERVICES);
+        assert_eq!(csv.lines().count(), SERVICES.len() + 1);
+        assert!(csv.contains("ApexQueuingPortP4,SEND_QUEUING_MESSAGE,Partial,"));
+    }
+
+    #[test]
+    fn implemented_services_have_smoke_tests() {
+        for s in SERVICES {
+            assert_eq!(s.smoke.is_some(), s.status == Status::Implemented, "{s:?}");
+        }
+    }
+}
@@ -0,0 +1,559 @@
+//! Smoke tests of the implemented APEX services
+//!
+//! The conformance table attaches the function of this module named after a
+//! service to every service it marks as [Implemented](super::Status), so a
+//! service can not be marked as implemented without a smoke test. Each test
+//! calls its service and checks the result.
+//!
+//! The services only work within a partition, so the tests are run by the
+//! `conformance` example: its start hook calls [prepare], which creates the
+//! objects used by the tests and the processes running them. The periodic
+//! process then runs every test once with [run] and logs the results, which
+//! the ignored `conformance` test of the hypervisor checks.
+
+use std::fmt::Debug;
+use std::str::FromStr;
+use std::thread::sleep;
+use std::time::Duration;
+
+use a653rs::bindings::*;
+use a653rs::prelude::{Name, OperatingMode};
+use once_cell::sync::OnceCell;
+
+use crate::apex::SERVICES;
+use crate::partition::ApexLinuxPartition;
+
+type Hypervisor = ApexLinuxPartition;
+
+/// Result of a smoke test, describing the first unexpected result
+pub type Outcome = Result<(), String>;
+
+/// Smoke test of a service
+pub type SmokeTest = fn(&Fixture) -> Outcome;
+
+/// Name of the periodic process running the tests
+pub const TESTER: &str = "smoke_tester";
+/// Name of the aperiodic process which the tests suspend and resume
+pub const HELPER: &str = "smoke_helper";
+/// Name of the sampling source port, which needs a channel of
+/// [MSG_SIZE] bytes
+pub const SAMPLING_PORT: &str = "smoke_sampling";
+/// Name of the queuing destination port, which needs a channel of
+/// [MSG_SIZE] bytes and [MAX_NB_MESSAGE] messages from another partition
+pub const QUEUING_PORT: &str = "smoke_queuing";
+pub const MSG_SIZE: MessageSize = 8;
+pub const MAX_NB_MESSAGE: MessageRange = 4;
+
+const BLACKBOARD: &str = "smoke_blackboard";
+const BUFFER: &str = "smoke_buffer";
+const SEMAPHORE: &str = "smoke_semaphore";
+const EVENT: &str = "smoke_event";
+const MUTEX: &str = "smoke_mutex";
+const MUTEX_PRIORITY: Priority = 7;
+
+/// Refresh period of the sampling port, which is ignored for sources
+const REFRESH_PERIOD: ApexSystemTime = 1_000_000_000;
+/// Do not wait in services which may block
+const NO_WAIT: ApexSystemTime = 0;
+
+static FIXTURE: OnceCell<Fixture> = OnceCell::new();
+
+/// The objects used by the smoke tests, with the results of creating them in
+/// the start mode of the partition
+#[derive(Debug)]
+pub struct Fixture {
+    tester: Result<ProcessId, ErrorReturnCode>,
+    helper: Result<ProcessId, ErrorReturnCode>,
+    started: Result<(), ErrorReturnCode>,
+    sampling_port: Result<SamplingPortId, ErrorReturnCode>,
+    queuing_port: Result<QueuingPortId, ErrorReturnCode>,
+    blackboard: Result<BlackboardId, ErrorReturnCode>,
+    buffer: Result<BufferId, ErrorReturnCode>,
+    semaphore: Result<SemaphoreId, ErrorReturnCode>,
+    event: Result<EventId, ErrorReturnCode>,
+    mutex: Result<MutexId, ErrorReturnCode>,
+}
+
+/// Creates the objects used by the smoke tests, and the processes running
+/// them
+///
+/// Must be called in COLD_START or WARM_START, once.
+pub fn prepare() {
+    let tester = process(TESTER, true);
+    let helper = process(HELPER, false);
+    let started = [&tester, &helper]
+        .into_iter()
+        .try_for_each(|p| Hypervisor::start((*p)?));
+    let fixture = Fixture {
+        tester,
+        helper,
+        started,
+        sampling_port: Hypervisor::create_sampling_port(
+            name(SAMPLING_PORT),
+            MSG_SIZE,
+            PortDirection::Source,
+            REFRESH_PERIOD,
+        ),
+        queuing_port: Hypervisor::create_queuing_port(
+            name(QUEUING_PORT),
+            MSG_SIZE,
+            MAX_NB_MESSAGE,
+            PortDirection::Destination,
+            QueuingDiscipline::Fifo,
+        ),
+        blackboard: Hypervisor::create_blackboard(name(BLACKBOARD), MSG_SIZE),
+        buffer: Hypervisor::create_buffer(
+            name(BUFFER),
+            MSG_SIZE,
+            MAX_NB_MESSAGE,
+            QueuingDiscipline::Fifo,
+        ),
+        semaphore: Hypervisor::create_semaphore(name(SEMAPHORE), 1, 1, QueuingDiscipline::Fifo),
+        event: Hypervisor::create_event(name(EVENT)),
+        mutex: Hypervisor::create_mutex(name(MUTEX), MUTEX_PRIORITY, QueuingDiscipline::Fifo),
+    };
+    if FIXTURE.set(fixture).is_err() {
+        panic!("the smoke tests were started before");
+    }
+}
+
+/// Runs every smoke test and returns the number of failed tests
+///
+/// Logs every failure and a summary. Passing tests are only logged at the
+/// debug level, as a record per test would overflow the hypervisor socket.
+///
+/// Must be called by the periodic process created by [prepare].
+pub fn run() -> usize {
+    let fixture = FIXTURE.get().expect("the smoke tests to be started");
+    let tests = SERVICES
+        .iter()
+        .filter_map(|s| s.smoke.map(|test| (s, test)))
+        .collect::<Vec<_>>();
+    let mut failed = 0;
+    for (service, test) in &tests {
+        match test(fixture) {
+            Ok(()) => debug!("Smoke test of {} passed", service.apex_name()),
+            Err(e) => {
+                error!("Smoke test of {} failed: {e}", service.apex_name());
+                failed += 1;
+            }
+        }
+    }
+    info!(
+        "{} of {} smoke tests passed",
+        tests.len() - failed,
+        tests.len()
+    );
+    failed
+}
+
+extern "C" fn tester() {
+    run();
+    loop {
+        Hypervisor::periodic_wait().unwrap();
+    }
+}
+
+extern "C" fn helper() {
+    loop {
+        sleep(Duration::from_secs(1));
+    }
+}
+
+fn process(process_name: &str, periodic: bool) -> Result<ProcessId, ErrorReturnCode> {
+    let (period, entry_point) = if periodic {
+        (0, tester as extern "C" fn())
+    } else {
+        (INFINITE_TIME_VALUE, helper as extern "C" fn())
+    };
+    Hypervisor::create_process(&ApexProcessAttribute {
+        period,
+        time_capacity: INFINITE_TIME_VALUE,
+        entry_point,
+        stack_size: 100_000,
+        base_priority: 1,
+        deadline: Deadline::Soft,
+        name: name(process_name),
+    })
+}
+
+fn name(name: &str) -> ApexName {
+    Name::from_str(name).unwrap().into_inner()
+}
+
+/// The value of `result`, or a description of the error `service` yielded
+fn ok<T>(service: &str, result: Result<T, ErrorReturnCode>) -> Result<T, String> {
+    result.map_err(|e| format!("{service} yielded {e:?}"))
+}
+
+/// Checks that `what` is `expected`
+fn check<T: Debug + PartialEq>(what: &str, actual: T, expected: T) -> Outcome {
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(format!("{what} is {actual:?} instead of {expected:?}"))
+    }
+}
+
+pub fn set_partition_mode(_: &Fixture) -> Outcome {
+    // The tests run in NORMAL, which a restart would leave
+    check(
+        "setting NORMAL in NORMAL",
+        Hypervisor::set_partition_mode(OperatingMode::Normal),
+        Err(ErrorReturnCode::NoAction),
+    )
+}
+
+pub fn start(f: &Fixture) -> Outcome {
+    ok("start", f.started)
+}
+
+pub fn resume(f: &Fixture) -> Outcome {
+    let helper = ok("create_process", f.helper)?;
+    ok("suspend", Hypervisor::suspend(helper))?;
+    ok("resume", Hypervisor::resume(helper))?;
+    check(
+        "resuming a process which is not suspended",
+        Hypervisor::resume(helper),
+        Err(ErrorReturnCode::NoAction),
+    )
+}
+
+pub fn get_my_id(f: &Fixture) -> Outcome {
+    check("the id of the tester", Hypervisor::get_my_id(), f.tester)
+}
+
+pub fn get_process_id(f: &Fixture) -> Outcome {
+    check(
+        "the id of the helper",
+        Hypervisor::get_process_id(name(HELPER)),
+        f.helper,
+    )
+}
+
+pub fn get_my_processor_core_id(_: &Fixture) -> Outcome {
+    check("the core", Hypervisor::get_my_processor_core_id(), 0)
+}
+
+pub fn create_sampling_port(f: &Fixture) -> Outcome {
+    ok("create_sampling_port", f.sampling_port).map(drop)
+}
+
+pub fn write_sampling_message(f: &Fixture) -> Outcome {
+    let port = ok("create_sampling_port", f.sampling_port)?;
+    ok(
+        "write_sampling_message",
+        Hypervisor::write_sampling_message(port, b"smoke"),
+    )?;
+    check(
+        "writing an empty message",
+        Hypervisor::write_sampling_message(port, &[]),
+        Err(ErrorReturnCode::InvalidParam),
+    )
+}
+
+pub fn get_queuing_port_status(f: &Fixture) -> Outcome {
+    let port = ok("create_queuing_port", f.queuing_port)?;
+    let status = ok(
+        "get_queuing_port_status",
+        Hypervisor::get_queuing_port_status(port),
+    )?;
+    check(
+        "the size and capacity",
+        (status.max_message_size, status.max_nb_message),
+        (MSG_SIZE, MAX_NB_MESSAGE),
+    )?;
+    check(
+        "the direction",
+        status.port_direction,
+        PortDirection::Destination,
+    )
+}
+
+pub fn clear_queuing_port(f: &Fixture) -> Outcome {
+    let port = ok("create_queuing_port", f.queuing_port)?;
+    ok("clear_queuing_port", Hypervisor::clear_queuing_port(port))?;
+    let status = ok(
+        "get_queuing_port_status",
+        Hypervisor::get_queuing_port_status(port),
+    )?;
+    check("the number of messages", status.nb_message, 0)
+}
+
+pub fn get_queuing_port_id(f: &Fixture) -> Outcome {
+    check(
+        "the id of the queuing port",
+        Hypervisor::get_queuing_port_id(name(QUEUING_PORT)),
+        f.queuing_port,
+    )
+}
+
+pub fn periodic_wait(_: &Fixture) -> Outcome {
+    ok("periodic_wait", Hypervisor::periodic_wait())
+}
+
+pub fn get_time(_: &Fixture) -> Outcome {
+    match Hypervisor::get_time() {
+        time if time > 0 => Ok(()),
+        time => Err(format!("the module time is {time}")),
+    }
+}
+
+pub fn raise_application_error(_: &Fixture) -> Outcome {
+    ok(
+        "raise_application_error",
+        Hypervisor::raise_application_error(ErrorCode::ApplicationError, b"smoke test"),
+    )?;
+    check(
+        "raising another error",
+        Hypervisor::raise_application_error(ErrorCode::DeadlineMissed, b"smoke test"),
+        Err(ErrorReturnCode::InvalidParam),
+    )
+}
+
+pub fn create_blackboard(f: &Fixture) -> Outcome {
+    ok("create_blackboard", f.blackboard).map(drop)
+}
+
+pub fn display_blackboard(f: &Fixture) -> Outcome {
+    let blackboard = ok("create_blackboard", f.blackboard)?;
+    ok(
+        "display_blackboard",
+        Hypervisor::display_blackboard(blackboard, b"smoke"),
+    )?;
+    let status = ok(
+        "get_blackboard_status",
+        Hypervisor::get_blackboard_status(blackboard),
+    )?;
+    check(
+        "the blackboard",
+        status.empty_indicator,
+        EmptyIndicator::Occupied,
+    )
+}
+
+pub fn clear_blackboard(f: &Fixture) -> Outcome {
+    let blackboard = ok("create_blackboard", f.blackboard)?;
+    ok(
+        "display_blackboard",
+        Hypervisor::display_blackboard(blackboard, b"smoke"),
+    )?;
+    ok("clear_blackboard", Hypervisor::clear_blackboard(blackboard))?;
+    let status = ok(
+        "get_blackboard_status",
+        Hypervisor::get_blackboard_status(blackboard),
+    )?;
+    check(
+        "the blackboard",
+        status.empty_indicator,
+        EmptyIndicator::Empty,
+    )
+}
+
+pub fn get_blackboard_id(f: &Fixture) -> Outcome {
+    check(
+        "the id of the blackboard",
+        Hypervisor::get_blackboard_id(name(BLACKBOARD)),
+        f.blackboard,
+    )
+}
+
+pub fn get_blackboard_status(f: &Fixture) -> Outcome {
+    let blackboard = ok("create_blackboard", f.blackboard)?;
+    let status = ok(
+        "get_blackboard_status",
+        Hypervisor::get_blackboard_status(blackboard),
+    )?;
+    check("the message size", status.max_message_size, MSG_SIZE)
+}
+
+pub fn get_buffer_id(f: &Fixture) -> Outcome {
+    check(
+        "the id of the buffer",
+        Hypervisor::get_buffer_id(name(BUFFER)),
+        f.buffer,
+    )
+}
+
+pub fn get_buffer_status(f: &Fixture) -> Outcome {
+    let buffer = ok("create_buffer", f.buffer)?;
+    let status = ok("get_buffer_status", Hypervisor::get_buffer_status(buffer))?;
+    check(
+        "the size, capacity and number of messages",
+        (
+            status.max_message_size,
+            status.max_nb_message,
+            status.nb_message,
+        ),
+        (MSG_SIZE, MAX_NB_MESSAGE, 0),
+    )
+}
+
+pub fn wait_semaphore(f: &Fixture) -> Outcome {
+    let semaphore = ok("create_semaphore", f.semaphore)?;
+    ok(
+        "wait_semaphore",
+        Hypervisor::wait_semaphore(semaphore, NO_WAIT),
+    )?;
+    let unavailable = Hypervisor::wait_semaphore(semaphore, NO_WAIT);
+    ok("signal_semaphore", Hypervisor::signal_semaphore(semaphore))?;
+    check(
+        "waiting for a taken semaphore",
+        unavailable,
+        Err(ErrorReturnCode::NotAvailable),
+    )
+}
+
+pub fn signal_semaphore(f: &Fixture) -> Outcome {
+    let semaphore = ok("create_semaphore", f.semaphore)?;
+    ok(
+        "wait_semaphore",
+        Hypervisor::wait_semaphore(semaphore, NO_WAIT),
+    )?;
+    ok("signal_semaphore", Hypervisor::signal_semaphore(semaphore))?;
+    let status = ok(
+        "get_semaphore_status",
+        Hypervisor::get_semaphore_status(semaphore),
+    )?;
+    check("the value", status.current_value, 1)
+}
+
+pub fn get_semaphore_id(f: &Fixture) -> Outcome {
+    check(
+        "the id of the semaphore",
+        Hypervisor::get_semaphore_id(name(SEMAPHORE)),
+        f.semaphore,
+    )
+}
+
+pub fn get_semaphore_status(f: &Fixture) -> Outcome {
+    let semaphore = ok("create_semaphore", f.semaphore)?;
+    let status = ok(
+        "get_semaphore_status",
+        Hypervisor::get_semaphore_status(semaphore),
+    )?;
+    check(
+        "the value and maximum",
+        (status.current_value, status.maximum_value),
+        (1, 1),
+    )
+}
+
+pub fn create_event(f: &Fixture) -> Outcome {
+    ok("create_event", f.event).map(drop)
+}
+
+pub fn set_event(f: &Fixture) -> Outcome {
+    let event = ok("create_event", f.event)?;
+    ok("set_event", Hypervisor::set_event(event))?;
+    let status = ok("get_event_status", Hypervisor::get_event_status(event))?;
+    ok("reset_event", Hypervisor::reset_event(event))?;
+    check("the event", status.event_state, EventState::Up)
+}
+
+pub fn reset_event(f: &Fixture) -> Outcome {
+    let event = ok("create_event", f.event)?;
+    ok("set_event", Hypervisor::set_event(event))?;
+    ok("reset_event", Hypervisor::reset_event(event))?;
+    let status = ok("get_event_status", Hypervisor::get_event_status(event))?;
+    check("the event", status.event_state, EventState::Down)
+}
+
+pub fn wait_event(f: &Fixture) -> Outcome {
+    let event = ok("create_event", f.event)?;
+    ok("set_event", Hypervisor::set_event(event))?;
+    ok("wait_event", Hypervisor::wait_event(event, NO_WAIT))?;
+    ok("reset_event", Hypervisor::reset_event(event))?;
+    check(
+        "waiting for an event which is down",
+        Hypervisor::wait_event(event, NO_WAIT),
+        Err(ErrorReturnCode::NotAvailable),
+    )
+}
+
+pub fn get_event_id(f: &Fixture) -> Outcome {
+    check(
+        "the id of the event",
+        Hypervisor::get_event_id(name(EVENT)),
+        f.event,
+    )
+}
+
+pub fn get_event_status(f: &Fixture) -> Outcome {
+    let event = ok("create_event", f.event)?;
+    let status = ok("get_event_status", Hypervisor::get_event_status(event))?;
+    check(
+        "the event and its waiting processes",
+        (status.event_state, status.waiting_processes),
+        (EventState::Down, 0),
+    )
+}
+
+pub fn acquire_mutex(f: &Fixture) -> Outcome {
+    let (mutex, tester) = (
+        ok("create_mutex", f.mutex)?,
+        ok("create_process", f.tester)?,
+    );
+    ok("acquire_mutex", Hypervisor::acquire_mutex(mutex, NO_WAIT))?;
+    let status = ok("get_mutex_status", Hypervisor::get_mutex_status(mutex))?;
+    ok("release_mutex", Hypervisor::release_mutex(mutex))?;
+    check(
+        "the owner and lock count",
+        (status.mutex_state, status.mutex_owner, status.lock_count),
+        (MutexState::Owned, tester, 1),
+    )
+}
+
+pub fn release_mutex(f: &Fixture) -> Outcome {
+    let mutex = ok("create_mutex", f.mutex)?;
+    ok("acquire_mutex", Hypervisor::acquire_mutex(mutex, NO_WAIT))?;
+    ok("release_mutex", Hypervisor::release_mutex(mutex))?;
+    let status = ok("get_mutex_status", Hypervisor::get_mutex_status(mutex))?;
+    check("the mutex", status.mutex_state, MutexState::Available)
+}
+
+pub fn reset_mutex(f: &Fixture) -> Outcome {
+    let (mutex, tester) = (
+        ok("create_mutex", f.mutex)?,
+        ok("create_process", f.tester)?,
+    );
+    ok("acquire_mutex", Hypervisor::acquire_mutex(mutex, NO_WAIT))?;
+    ok("acquire_mutex", Hypervisor::acquire_mutex(mutex, NO_WAIT))?;
+    ok("reset_mutex", Hypervisor::reset_mutex(mutex, tester))?;
+    let status = ok("get_mutex_status", Hypervisor::get_mutex_status(mutex))?;
+    check("the mutex", status.mutex_state, MutexState::Available)
+}
+
+pub fn get_mutex_id(f: &Fixture) -> Outcome {
+    check(
+        "the id of the mutex",
+        Hypervisor::get_mutex_id(name(MUTEX)),
+        f.mutex,
+    )
+}
+
+pub fn get_mutex_status(f: &Fixture) -> Outcome {
+    let mutex = ok("create_mutex", f.mutex)?;
+    let status = ok("get_mutex_status", Hypervisor::get_mutex_status(mutex))?;
+    check(
+        "the mutex and its priority",
+        (status.mutex_state, status.mutex_priority),
+        (MutexState::Available, MUTEX_PRIORITY),
+    )
+}
+
+pub fn get_process_mutex_state(f: &Fixture) -> Outcome {
+    let (mutex, tester) = (
+        ok("create_mutex", f.mutex)?,
+        ok("create_process", f.tester)?,
+    );
+    check(
+        "the mutex of the tester before acquiring it",
+        Hypervisor::get_process_mutex_state(tester),
+        Ok(NO_MUTEX_OWNED),
+    )?;
+    ok("acquire_mutex", Hypervisor::acquire_mutex(mutex, NO_WAIT))?;
+    let owned = Hypervisor::get_process_mutex_state(tester);
+    ok("release_mutex", Hypervisor::release_mutex(mutex))?;
+    check("the mutex of the tester", owned, Ok(mutex))
+}
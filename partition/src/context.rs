@@ -0,0 +1,205 @@
+//! Preconditions of APEX services on the calling context
+//!
+//! Some services may only be used in certain operating modes or by certain
+//! processes. They check this before doing anything else, so that a service
+//! called in the wrong context fails with the error code required by ARINC 653
+//! instead of panicking.
+
+use a653rs::bindings::ErrorReturnCode;
+use a653rs::prelude::OperatingMode;
+
+use crate::process::Process;
+
+/// The context an APEX service is called from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Caller {
+    /// The main process, which initializes the partition in a start mode
+    Main,
+    Periodic,
+    Aperiodic,
+}
+
+impl Caller {
+    /// Determines the caller of the currently executed service
+    pub(crate) fn current() -> Self {
+        match Process::get_self() {
+            Some(p) if p.periodic() => Caller::Periodic,
+            Some(_) => Caller::Aperiodic,
+            None => Caller::Main,
+        }
+    }
+}
+
+/// Services with preconditions on their calling context
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Service {
+    CreateProcess,
+    Start,
+    PeriodicWait,
+    CreateSamplingPort,
+    CreateQueuingPort,
+    CreateBlackboard,
+    CreateBuffer,
+    CreateSemaphore,
+    CreateEvent,
+    CreateMutex,
+    AcquireMutex,
+    SuspendSelf,
+    GetMyId,
+}
+
+impl Service {
+    /// Checks whether this service may be called by `caller` while the
+    /// partition is in `mode`
+    pub(crate) fn check(self, mode: OperatingMode, caller: Caller) -> Result<(), ErrorReturnCode> {
+        let allowed = match self {
+            // Processes, ports and intra-partition communication objects are only created
+            // during the initialization of the partition
+            Service::CreateProcess
+            | Service::CreateSamplingPort
+            | Service::CreateQueuingPort
+            | Service::CreateBlackboard
+            | Service::CreateBuffer
+            | Service::CreateSemaphore
+            | Service::CreateEvent
+            | Service::CreateMutex => mode != OperatingMode::Normal,
+            // Processes started in a start mode begin to run with the normal mode
+            Service::Start => true,
+            Service::PeriodicWait => mode == OperatingMode::Normal && caller == Caller::Periodic,
+            // Only processes can own a mutex, which the main process is not
+            Service::AcquireMutex => caller != Caller::Main,
+            // Neither is the main process suspended, nor may periodic processes be
+            Service::SuspendSelf => caller == Caller::Aperiodic,
+            // The main process is no process in the sense of the standard
+            Service::GetMyId => caller != Caller::Main,
+        };
+
+        if allowed {
+            Ok(())
+        } else {
+            trace!("yielding InvalidMode, because {self:?} may not be called by {caller:?} in {mode:?} mode");
+            Err(ErrorReturnCode::InvalidMode)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use a653rs::bindings::ErrorReturnCode::{InvalidMode, InvalidParam, NoAction};
+
+    use super::*;
+    use crate::apex::SERVICES;
+    use crate::conformance::Status;
+
+    const MODES: [OperatingMode; 4] = [
+        OperatingMode::Idle,
+        OperatingMode::ColdStart,
+        OperatingMode::WarmStart,
+        OperatingMode::Normal,
+    ];
+    const CALLERS: [Caller; 3] = [Caller::Main, Caller::Periodic, Caller::Aperiodic];
+    const START_MODES: &[OperatingMode] = &[
+        OperatingMode::Idle,
+        OperatingMode::ColdStart,
+        OperatingMode::WarmStart,
+    ];
+
+    #[test]
+    fn every_service_in_every_mode() {
+        // The modes and callers a service is allowed for, all others must yield
+        // InvalidMode
+        let table: [(Service, &[OperatingMode], &[Caller]); 13] = [
+            (Service::CreateProcess, START_MODES, &CALLERS),
+            (Service::Start, &MODES, &CALLERS),
+            (
+                Service::PeriodicWait,
+                &[OperatingMode::Normal],
+                &[Caller::Periodic],
+            ),
+            (Service::CreateSamplingPort, START_MODES, &CALLERS),
+            (Service::CreateQueuingPort, START_MODES, &CALLERS),
+            (Service::CreateBlackboard, START_MODES, &CALLERS),
+            (Service::CreateBuffer, START_MODES, &CALLERS),
+            (Service::CreateSemaphore, START_MODES, &CALLERS),
+            (Service::CreateEvent, START_MODES, &CALLERS),
+            (Service::CreateMutex, START_MODES, &CALLERS),
+            (
+                Service::AcquireMutex,
+                &MODES,
+                &[Caller::Periodic, Caller::Aperiodic],
+            ),
+            (Service::SuspendSelf, &MODES, &[Caller::Aperiodic]),
+            (
+                Service::GetMyId,
+                &MODES,
+                &[Caller::Periodic, Caller::Aperiodic],
+            ),
+        ];
+
+        for (service, modes, callers) in table {
+            for mode in MODES {
+                for caller in CALLERS {
+                    let expected = if modes.contains(&mode) && callers.contains(&caller) {
+                        Ok(())
+                    } else {
+                        Err(ErrorReturnCode::InvalidMode)
+                    };
+                    assert_eq!(
+                        service.check(mode, caller),
+                        expected,
+                        "{service:?} called by {caller:?} in {mode:?} mode"
+                    );
+                }
+            }
+        }
+    }
+
+    /// Services of processes and time which [Service::check] does not cover,
+    /// with the error codes they yield instead
+    ///
+    /// They are allowed in every mode and for every caller, and fail
+    /// depending on the process they target only, which needs a partition;
+    /// see the smoke tests of [crate::conformance::smoke].
+    const UNCHECKED: [(&str, &[(&str, ErrorReturnCode)]); 4] = [
+        (
+            "suspend",
+            &[
+                ("an unknown process", InvalidParam),
+                ("the calling process", InvalidParam),
+                ("a periodic process", InvalidMode),
+                ("a dormant process", InvalidMode),
+                ("a suspended process", NoAction),
+            ],
+        ),
+        (
+            "resume",
+            &[
+                ("an unknown process", InvalidParam),
+                ("the calling process", InvalidParam),
+                ("a dormant process", InvalidMode),
+                ("a process which is not suspended", NoAction),
+            ],
+        ),
+        (
+            "stop",
+            &[
+                ("an unknown process", InvalidParam),
+                ("the calling process", InvalidParam),
+                ("a dormant process", NoAction),
+            ],
+        ),
+        // Never fails
+        ("get_time", &[]),
+    ];
+
+    #[test]
+    fn unchecked_services_are_available() {
+        for (name, _) in UNCHECKED {
+            let service = SERVICES
+                .iter()
+                .find(|s| s.name == name)
+                .unwrap_or_else(|| panic!("{name} is no service"));
+            assert_ne!(service.status, Status::Missing, "{service:?}");
+        }
+    }
+}
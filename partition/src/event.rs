@@ -0,0 +1,249 @@
+//! Events for the synchronization of the processes of a partition
+//!
+//! An event is either up or down. Setting it wakes all processes waiting for
+//! it, while processes waiting for an event which is up continue at once.
+//! Each event lives in a memfd of its own, and the events created so far are
+//! listed in a registry like the blackboards, see [crate::EVENTS]. Helper
+//! processes do not inherit the events.
+//!
+//! The state of an event counts how often it was set, so that a process
+//! waiting for it is released by a set even if the event is reset before the
+//! process runs again, as ARINC 653 demands. Waiting processes sleep on the
+//! state with a futex, see [crate::futex], which also lets their timeout
+//! expire while the partition is frozen.
+
+use std::ffi::CString;
+use std::fs::File;
+use std::mem::{size_of, ManuallyDrop};
+use std::os::fd::{FromRawFd, IntoRawFd, RawFd};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use a653rs::bindings::{ErrorReturnCode, EventId};
+use a653rs_linux_core::error::{ResultExt, SystemError, TypedResult};
+use anyhow::anyhow;
+use memmap2::MmapMut;
+use once_cell::sync::Lazy;
+
+use crate::time::Timeout;
+use crate::{futex, EVENTS};
+
+/// The events mapped into this process, in the order of [EVENTS]
+static MAPPED: Lazy<Mutex<Vec<Arc<Event>>>> = Lazy::new(Default::default);
+
+/// Bit of [Header::state] set while the event is up
+const UP: u32 = 1;
+
+/// Shared memory of an event
+///
+/// A new memfd is filled with zeros, which is an event that is down.
+#[repr(C)]
+struct Header {
+    /// [UP] while the event is up, above it the number of times the event was
+    /// set, wrapping around
+    state: AtomicU32,
+    /// Processes waiting for the event to be set
+    waiting: AtomicU32,
+}
+
+/// An event mapped into this process
+#[derive(Debug)]
+pub(crate) struct Event {
+    mmap: MmapMut,
+}
+
+impl Event {
+    /// Creates the memfd of an event, which is down, returning its fd
+    pub(crate) fn create(name: &str) -> TypedResult<RawFd> {
+        let name = CString::new(format!("event_{name}")).typ(SystemError::Panic)?;
+        // Not close-on-exec, like the other files of the partition
+        let fd = unsafe { libc::memfd_create(name.as_ptr(), 0) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error()).typ(SystemError::Panic);
+        }
+        let file = unsafe { File::from_raw_fd(fd) };
+        file.set_len(size_of::<Header>() as u64)
+            .typ(SystemError::Panic)?;
+        Ok(file.into_raw_fd())
+    }
+
+    /// Maps the event in the memfd `fd`
+    pub(crate) fn open(fd: RawFd) -> TypedResult<Self> {
+        // The fd stays open for later mappings
+        let file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+        let mmap = unsafe { MmapMut::map_mut(&*file) }.typ(SystemError::Panic)?;
+        if mmap.len() < size_of::<Header>() {
+            return Err(anyhow!("event of {} bytes is too small", mmap.len()))
+                .typ(SystemError::Panic);
+        }
+        Ok(Self { mmap })
+    }
+
+    fn header(&self) -> &Header {
+        // The mapping is page aligned and large enough, see Event::open
+        unsafe { &*(self.mmap.as_ptr() as *const Header) }
+    }
+
+    pub(crate) fn is_up(&self) -> bool {
+        self.header().state.load(Ordering::Acquire) & UP != 0
+    }
+
+    /// Number of processes waiting for the event to be set
+    pub(crate) fn waiting(&self) -> u32 {
+        self.header().waiting.load(Ordering::SeqCst)
+    }
+
+    /// Sets the event up, waking all processes waiting for it
+    pub(crate) fn set(&self) {
+        let header = self.header();
+        let set = header
+            .state
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |state| {
+                (state & UP == 0).then_some(state.wrapping_add(2) | UP)
+            });
+        // Nobody waits for an event which is already up
+        if set.is_ok() && self.waiting() > 0 {
+            futex::wake(&header.state);
+        }
+    }
+
+    /// Sets the event down, without affecting the processes it released
+    pub(crate) fn reset(&self) {
+        self.header().state.fetch_and(!UP, Ordering::SeqCst);
+    }
+
+    /// Waits for at most `timeout` until the event is set, unless it is up
+    ///
+    /// Returns whether the event was up or set meanwhile.
+    pub(crate) fn wait(&self, timeout: Timeout) -> bool {
+        // A deadline too far in the future is the same as no deadline at all
+        let deadline = timeout
+            .duration()
+            .and_then(|d| Instant::now().checked_add(d));
+        let header = self.header();
+        let start = header.state.load(Ordering::SeqCst);
+        let mut state = start;
+        loop {
+            // Set at some point since the wait started, if the count differs
+            if state & UP != 0 || state != start {
+                return true;
+            }
+            let remaining = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return false;
+                    }
+                    Some(remaining)
+                }
+                None => None,
+            };
+
+            // A set after loading the state changed it, so that the wait returns at once
+            header.waiting.fetch_add(1, Ordering::SeqCst);
+            futex::wait(&header.state, state, remaining);
+            // Saturate, should the memory have been tampered with
+            let _ = header
+                .waiting
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+            state = header.state.load(Ordering::SeqCst);
+        }
+    }
+}
+
+/// The event with the id `id`, mapping it into this process first if
+/// necessary
+///
+/// Yields [ErrorReturnCode::InvalidParam] if no such event was created.
+pub(crate) fn get(id: EventId) -> Result<Arc<Event>, ErrorReturnCode> {
+    // Event ids start at one
+    let index = usize::try_from(id)
+        .ok()
+        .and_then(|id| id.checked_sub(1))
+        .ok_or(ErrorReturnCode::InvalidParam)?;
+    let mut mapped = MAPPED.lock().unwrap();
+    if let Some(event) = mapped.get(index) {
+        return Ok(event.clone());
+    }
+
+    let created = EVENTS.read().map_err(|_| ErrorReturnCode::NotAvailable)?;
+    if index >= created.len() {
+        return Err(ErrorReturnCode::InvalidParam);
+    }
+    for (_, fd) in created[mapped.len()..=index].iter() {
+        let event = Event::open(*fd).map_err(|e| {
+            trace!("yielding NotAvailable, because event could not be mapped: {e}");
+            ErrorReturnCode::NotAvailable
+        })?;
+        mapped.push(Arc::new(event));
+    }
+    Ok(mapped[index].clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn event() -> Event {
+        let fd = Event::create("test").unwrap();
+        Event::open(fd).unwrap()
+    }
+
+    #[test]
+    fn up_and_down() {
+        let event = event();
+        assert!(!event.is_up());
+        assert!(!event.wait(Timeout::Immediate));
+
+        let start = Instant::now();
+        let timeout = Duration::from_millis(20);
+        assert!(!event.wait(Timeout::Finite(timeout)));
+        assert!(start.elapsed() >= timeout);
+        assert_eq!(event.waiting(), 0);
+
+        event.set();
+        assert!(event.is_up());
+        // Setting an event which is up changes nothing
+        event.set();
+        assert!(event.wait(Timeout::Immediate));
+        assert!(event.wait(Timeout::Infinite));
+
+        event.reset();
+        assert!(!event.is_up());
+        assert!(!event.wait(Timeout::Immediate));
+
+        // Shared between mappings
+        let fd = Event::create("shared").unwrap();
+        let first = Event::open(fd).unwrap();
+        let second = Event::open(fd).unwrap();
+        first.set();
+        assert!(second.is_up());
+    }
+
+    #[test]
+    fn set_wakes_all_waiting() {
+        let event = Arc::new(event());
+        let waiters: Vec<_> = (0..3)
+            .map(|_| {
+                let event = event.clone();
+                thread::spawn(move || event.wait(Timeout::Finite(Duration::from_secs(5))))
+            })
+            .collect();
+        while event.waiting() < 3 {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // Released even though the event is down again before they run
+        event.set();
+        event.reset();
+        for waiter in waiters {
+            assert!(waiter.join().unwrap());
+        }
+        assert!(!event.is_up());
+        assert_eq!(event.waiting(), 0);
+    }
+}
@@ -0,0 +1,25 @@
+//! Services of the Linux hypervisor, abstracted from their implementation
+//!
+//! The traits are implemented by the types of the `linux` feature, but are
+//! available without it. Application logic which is generic over these traits
+//! and the APEX traits of [a653rs] does not depend on the Linux backend.
+
+use log::{LevelFilter, SetLoggerError};
+
+/// Role of a partition, as configured for the hypervisor
+pub trait PartitionRole {
+    /// Role of this partition, if one was configured
+    ///
+    /// Partitions sharing an image should branch on their role rather than
+    /// on their identifier.
+    fn role() -> Option<&'static str>;
+}
+
+/// Forwarding of log records and panics to the hypervisor
+pub trait PartitionLogger {
+    /// Installs the logger for all records up to `level`
+    fn install_logger(level: LevelFilter) -> Result<(), SetLoggerError>;
+
+    /// Logs panics as errors, so that they reach the hypervisor
+    fn install_panic_hook();
+}
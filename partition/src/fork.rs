@@ -0,0 +1,94 @@
+//! Detection of processes forked by a partition
+//!
+//! A partition may fork within its namespace, but the child inherits all state
+//! of this library: the socket to the hypervisor, the mappings of the ports
+//! and registries, and every static initialized so far. Were the child to use
+//! it, both processes would send interleaved calls to the hypervisor, which
+//! accounts them to the one partition, and share the processes and ports of
+//! the partition. Services with an error return therefore refuse to be called
+//! by a forked child with [ErrorReturnCode::InvalidMode], calls to the
+//! hypervisor without one are dropped, and log records are written to the
+//! stderr of the child instead. Services only reading the status of the
+//! partition, like `GET_TIME`, remain available.
+//!
+//! A process which needs the services of the partition is started with
+//! [ApexLinuxPartition::spawn_helper](crate::partition::ApexLinuxPartition::spawn_helper)
+//! instead, which executes a new image that sets up state of its own.
+//!
+//! The process owning the state is recorded before `main`, so that a child
+//! forked before the partition called any service is detected as well.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use a653rs::bindings::ErrorReturnCode;
+
+/// Process which loaded this library, zero if not recorded
+static OWNER: AtomicU32 = AtomicU32::new(0);
+
+#[used]
+#[link_section = ".init_array"]
+static RECORD_OWNER: extern "C" fn() = record_owner;
+
+extern "C" fn record_owner() {
+    OWNER.store(std::process::id(), Ordering::Relaxed);
+}
+
+/// Whether the calling process is a forked child of the partition
+pub(crate) fn is_forked() -> bool {
+    forked_from(OWNER.load(Ordering::Relaxed), std::process::id())
+}
+
+fn forked_from(owner: u32, pid: u32) -> bool {
+    owner != 0 && owner != pid
+}
+
+/// Refuses services called by a forked child of the partition
+pub(crate) fn check() -> Result<(), ErrorReturnCode> {
+    if is_forked() {
+        warn!(
+            "yielding InvalidMode, because process {} is a forked child of the partition, which may only use its services if started with spawn_helper",
+            std::process::id()
+        );
+        return Err(ErrorReturnCode::InvalidMode);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::sys::wait::{waitpid, WaitStatus};
+    use nix::unistd::{fork, ForkResult};
+
+    use super::*;
+
+    #[test]
+    fn owner() {
+        assert!(!forked_from(0, 42));
+        assert!(!forked_from(42, 42));
+        assert!(forked_from(42, 43));
+
+        // Recorded before the tests run
+        assert_eq!(OWNER.load(Ordering::Relaxed), std::process::id());
+        assert!(!is_forked());
+        assert_eq!(check(), Ok(()));
+    }
+
+    #[test]
+    fn forked_children_are_refused() {
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                // Other tests run on other threads, so only getpid is called here, as the tests
+                // install no logger
+                let code = match check() {
+                    Err(ErrorReturnCode::InvalidMode) if is_forked() => 0,
+                    _ => 1,
+                };
+                unsafe { libc::_exit(code) }
+            }
+            ForkResult::Parent { child } => {
+                assert_eq!(waitpid(child, None).unwrap(), WaitStatus::Exited(child, 0));
+                assert_eq!(check(), Ok(()));
+            }
+        }
+    }
+}
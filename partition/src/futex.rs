@@ -0,0 +1,47 @@
+//! Futexes on words in memory shared between the processes of a partition
+//!
+//! A process waiting on a futex sleeps in the kernel until it is woken or its
+//! timeout expires. The processes of a partition are frozen while waiting at
+//! the end of the window of the partition, which interrupts the wait. Callers
+//! therefore check their condition again after every wait, and compute the
+//! remaining timeout from a deadline, so that a timeout expired while frozen
+//! is noticed as soon as the process runs again.
+
+use std::sync::atomic::AtomicU32;
+use std::time::Duration;
+
+/// Sleeps while `word` holds `expected`, for at most `timeout`
+///
+/// Returns early on a signal, e.g. when the process is frozen and thawed
+/// again, or spuriously, which the caller handles by checking again.
+pub(crate) fn wait(word: &AtomicU32, expected: u32, timeout: Option<Duration>) {
+    let timeout = timeout.map(|d| libc::timespec {
+        tv_sec: d.as_secs().try_into().unwrap_or(libc::time_t::MAX),
+        tv_nsec: d.subsec_nanos() as _,
+    });
+    let timeout = timeout
+        .as_ref()
+        .map_or(std::ptr::null(), |t| t as *const libc::timespec);
+    // Not private, as the memory may be mapped by other processes
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            word as *const AtomicU32,
+            libc::FUTEX_WAIT,
+            expected,
+            timeout,
+        )
+    };
+}
+
+/// Wakes all processes waiting on `word`
+pub(crate) fn wake(word: &AtomicU32) {
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            word as *const AtomicU32,
+            libc::FUTEX_WAKE,
+            i32::MAX,
+        )
+    };
+}
@@ -0,0 +1,179 @@
+//! Helper processes spawned by a partition
+//!
+//! A partition may `exec` tools inside its namespace, e.g. a short-lived data
+//! converter. Linked against this crate, such a helper uses the ports of the
+//! partition, if it finds the files the partition shares with the hypervisor
+//! and the registries of the ports created by the partition. Those are looked
+//! up by name otherwise, which breaks once a process in between closed them.
+//! [ApexLinuxPartition::spawn_helper](crate::partition::ApexLinuxPartition::spawn_helper)
+//! therefore passes them on explicitly:
+//!
+//! - the fd of the constants in [PartitionConstants::PARTITION_CONSTANTS_FD]
+//! - the fds of the registries in [PartitionConstants::PORT_REGISTRIES]
+//! - the fds of the channels and the other files shared with the hypervisor
+//!
+//! All other fds of the partition are closed on exec. The sockets of the
+//! partition are not passed on, as only one process may receive them.
+
+use std::io;
+use std::os::fd::RawFd;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+
+use a653rs_linux_core::partition::PartitionConstants;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+
+use crate::{CONSTANTS, QUEUING_PORTS, SAMPLING_PORTS};
+
+/// The fds of the sampling and queuing port registries inherited from the
+/// partition, if this is a helper process
+pub(crate) fn inherited_registries() -> Option<(RawFd, RawFd)> {
+    let value = std::env::var(PartitionConstants::PORT_REGISTRIES).ok()?;
+    let registries = parse_registries(&value);
+    if registries.is_none() {
+        warn!("Ignoring malformed port registries {value:?}");
+    }
+    registries
+}
+
+/// Parses the value of [PartitionConstants::PORT_REGISTRIES]
+fn parse_registries(value: &str) -> Option<(RawFd, RawFd)> {
+    let (sampling, queuing) = value.split_once(',')?;
+    Some((sampling.trim().parse().ok()?, queuing.trim().parse().ok()?))
+}
+
+/// The fds a helper inherits
+fn inherited_fds(
+    constants: &PartitionConstants,
+    constants_fd: RawFd,
+    registries: (RawFd, RawFd),
+) -> Vec<RawFd> {
+    let mut fds = vec![
+        constants_fd,
+        constants.start_time_fd,
+        constants.partition_mode_fd,
+        constants.restart_cause_fd,
+        constants.conditions_fd,
+        constants.activity_fd,
+        constants.mode_word_fd,
+        constants.mode_event_fd,
+        registries.0,
+        registries.1,
+    ];
+    fds.extend(constants.sampling.iter().map(|s| s.fd));
+    fds.extend(constants.queuing.iter().map(|q| q.fd));
+    fds
+}
+
+/// The fds currently open, except for stdio
+fn open_fds() -> io::Result<Vec<RawFd>> {
+    let proc = procfs::process::Process::myself().map_err(io::Error::other)?;
+    let fds = proc
+        .fd()
+        .map_err(io::Error::other)?
+        .flatten()
+        .map(|fd| fd.fd)
+        .filter(|fd| *fd > 2)
+        .collect();
+    Ok(fds)
+}
+
+/// Spawns `command` with the fds and environment a helper needs
+pub(crate) fn spawn(mut command: Command) -> io::Result<Child> {
+    let constants_fd = PartitionConstants::fd().map_err(io::Error::other)?;
+    let registries = (SAMPLING_PORTS.fd(), QUEUING_PORTS.fd());
+    let keep = inherited_fds(&CONSTANTS, constants_fd, registries);
+    let close = open_fds()?
+        .into_iter()
+        .filter(|fd| !keep.contains(fd))
+        .collect::<Vec<_>>();
+
+    command
+        .env(
+            PartitionConstants::PARTITION_CONSTANTS_FD,
+            constants_fd.to_string(),
+        )
+        .env(
+            PartitionConstants::PORT_REGISTRIES,
+            format!("{},{}", registries.0, registries.1),
+        );
+    if let Some(role) = &CONSTANTS.role {
+        command.env(PartitionConstants::PARTITION_ROLE, role);
+    }
+    // Only async-signal-safe calls after the fork, so the lists are prepared
+    // up front. Fds opened by other threads meanwhile are not closed, unless
+    // they were opened close-on-exec anyway, like those of the std library.
+    unsafe {
+        command.pre_exec(move || {
+            for &fd in &keep {
+                fcntl(fd, FcntlArg::F_SETFD(FdFlag::empty())).ok();
+            }
+            for &fd in &close {
+                fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)).ok();
+            }
+            Ok(())
+        });
+    }
+    command.spawn()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use a653rs::bindings::PortDirection;
+    use a653rs::prelude::StartCondition;
+    use a653rs_linux_core::partition::{QueuingConstant, SamplingConstant};
+
+    use super::*;
+
+    #[test]
+    fn registries() {
+        assert_eq!(parse_registries("5,6"), Some((5, 6)));
+        assert_eq!(parse_registries(" 5 , 6 "), Some((5, 6)));
+        assert_eq!(parse_registries("5"), None);
+        assert_eq!(parse_registries("5,six"), None);
+        assert_eq!(parse_registries(""), None);
+    }
+
+    #[test]
+    fn helpers_inherit_the_shared_files() {
+        let constants = PartitionConstants {
+            name: "converter".into(),
+            identifier: 1,
+            role: None,
+            period: Duration::from_millis(100),
+            duration: Duration::from_millis(20),
+            start_condition: StartCondition::NormalStart,
+            start_time_fd: 3,
+            partition_mode_fd: 4,
+            restart_cause_fd: 5,
+            conditions_fd: 6,
+            udp_io_fd: 7,
+            tcp_io_fd: 8,
+            activity_fd: 9,
+            sampling: vec![SamplingConstant {
+                name: "converted".into(),
+                dir: PortDirection::Source,
+                msg_size: 8,
+                fd: 10,
+                refresh_period: None,
+            }],
+            queuing: vec![QueuingConstant {
+                name: "raw".into(),
+                dir: PortDirection::Destination,
+                msg_size: 8,
+                max_num_msg: 2,
+                fd: 11,
+            }],
+            ipc_buffer: None,
+            realtime: None,
+            mode_word_fd: 15,
+            mode_event_fd: 16,
+        };
+        let mut fds = inherited_fds(&constants, 12, (13, 14));
+        fds.sort();
+        // Everything but the sockets
+        assert_eq!(fds, [3, 4, 5, 6, 9, 10, 11, 12, 13, 14, 15, 16]);
+    }
+}
@@ -0,0 +1,204 @@
+//! Linux glue shared by the APEX implementation
+//!
+//! Holds the constants passed by the hypervisor and the channels to it,
+//! which are opened on first use.
+
+#[cfg(feature = "socket")]
+use std::net::{TcpStream, UdpSocket};
+#[cfg(feature = "socket")]
+use std::os::fd::FromRawFd;
+use std::os::fd::RawFd;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use a653rs::bindings::{ApexName, MessageSize};
+use a653rs_linux_core::conditions::ModuleConditions;
+use a653rs_linux_core::file::{get_memfd, TempFile};
+use a653rs_linux_core::health_event::PartitionCall;
+#[cfg(feature = "socket")]
+use a653rs_linux_core::ipc::IoReceiver;
+use a653rs_linux_core::ipc::{self, IpcSender};
+use a653rs_linux_core::mode::ModeWatcher;
+use a653rs_linux_core::partition::*;
+use a653rs_linux_core::shmem::TypedMmap;
+use a653rs_linux_core::syscall::sender::SyscallSender;
+use a653rs_linux_core::syscall::SYSCALL_SOCKET_PATH;
+use a653rs_linux_core::telemetry::RateLimit;
+use a653rs_linux_core::time::{ModuleTime, MonotonicTime};
+use once_cell::sync::{Lazy, OnceCell};
+use polling::{Event, PollMode, Poller};
+use tinyvec::ArrayVec;
+
+use crate::helper::inherited_registries;
+use crate::process::Process;
+
+const SAMPLING_PORTS_FILE: &str = "sampling_channels";
+const QUEUING_PORTS_FILE: &str = "queuing_channels";
+const BLACKBOARDS_FILE: &str = "blackboards";
+const BUFFERS_FILE: &str = "buffers";
+const SEMAPHORES_FILE: &str = "semaphores";
+const EVENTS_FILE: &str = "events";
+const MUTEXES_FILE: &str = "mutexes";
+
+/// Blackboards, buffers, semaphores, events and mutexes a partition may
+/// create of each kind
+const MAX_OBJECTS: usize = 32;
+
+pub(crate) static CONSTANTS: Lazy<PartitionConstants> =
+    Lazy::new(|| PartitionConstants::open().unwrap());
+
+/// Start of the module, as recorded by the hypervisor
+pub(crate) static SYSTEM_TIME: Lazy<MonotonicTime> = Lazy::new(|| {
+    TempFile::<MonotonicTime>::try_from(CONSTANTS.start_time_fd)
+        .unwrap()
+        .read()
+        .unwrap()
+});
+
+/// The current module time
+pub(crate) fn module_time() -> ModuleTime {
+    ModuleTime::now(*SYSTEM_TIME)
+}
+
+/// The current mode, as published by the hypervisor
+pub(crate) static MODE: Lazy<ModeWatcher> =
+    Lazy::new(|| ModeWatcher::open(CONSTANTS.mode_word_fd, CONSTANTS.mode_event_fd).unwrap());
+
+/// The current mode of the partition
+///
+/// Only reads memory shared with the hypervisor, instead of the mode file for
+/// every service as before.
+pub(crate) fn operating_mode() -> a653rs::prelude::OperatingMode {
+    MODE.get().unwrap().mode.into()
+}
+
+static MODULE_CONDITIONS_FILE: Lazy<TempFile<ModuleConditions>> =
+    Lazy::new(|| TempFile::<ModuleConditions>::try_from(CONSTANTS.conditions_fd).unwrap());
+
+/// The conditions of the module affecting the partition, which the hypervisor
+/// updates at the start of every major frame
+pub(crate) static MODULE_CONDITIONS: Lazy<TypedMmap<'static, ModuleConditions>> =
+    Lazy::new(|| MODULE_CONDITIONS_FILE.get_typed_mmap().unwrap());
+
+pub(crate) static PERIODIC_PROCESS: OnceCell<Arc<Process>> = OnceCell::new();
+pub(crate) static APERIODIC_PROCESS: OnceCell<Arc<Process>> = OnceCell::new();
+
+pub(crate) type SamplingPortsType = (usize, Duration);
+pub(crate) static SAMPLING_PORTS: Lazy<
+    TempFile<ArrayVec<[SamplingPortsType; MAX_PORTS_PER_PARTITION]>>,
+> = Lazy::new(|| open_registry(SAMPLING_PORTS_FILE, inherited_registries().map(|(s, _)| s)));
+
+pub(crate) type QueuingPortsType = usize;
+pub(crate) static QUEUING_PORTS: Lazy<
+    TempFile<ArrayVec<[QueuingPortsType; MAX_PORTS_PER_PARTITION]>>,
+> = Lazy::new(|| open_registry(QUEUING_PORTS_FILE, inherited_registries().map(|(_, q)| q)));
+
+/// Name, memfd and maximum message size of a created blackboard
+pub(crate) type BlackboardsType = (ApexName, RawFd, MessageSize);
+/// The blackboards created by the partition, which helper processes do not
+/// inherit
+pub(crate) static BLACKBOARDS: Lazy<TempFile<ArrayVec<[BlackboardsType; MAX_OBJECTS]>>> =
+    Lazy::new(|| open_registry(BLACKBOARDS_FILE, None));
+
+/// Name and memfd of a created buffer
+pub(crate) type BuffersType = (ApexName, RawFd);
+/// The buffers created by the partition, which helper processes do not
+/// inherit
+pub(crate) static BUFFERS: Lazy<TempFile<ArrayVec<[BuffersType; MAX_OBJECTS]>>> =
+    Lazy::new(|| open_registry(BUFFERS_FILE, None));
+
+/// Name and memfd of a created semaphore
+pub(crate) type SemaphoresType = (ApexName, RawFd);
+/// The semaphores created by the partition, which helper processes do not
+/// inherit
+pub(crate) static SEMAPHORES: Lazy<TempFile<ArrayVec<[SemaphoresType; MAX_OBJECTS]>>> =
+    Lazy::new(|| open_registry(SEMAPHORES_FILE, None));
+
+/// Name and memfd of a created event
+pub(crate) type EventsType = (ApexName, RawFd);
+/// The events created by the partition, which helper processes do not inherit
+pub(crate) static EVENTS: Lazy<TempFile<ArrayVec<[EventsType; MAX_OBJECTS]>>> =
+    Lazy::new(|| open_registry(EVENTS_FILE, None));
+
+/// Name and memfd of a created mutex
+pub(crate) type MutexesType = (ApexName, RawFd);
+/// The mutexes created by the partition, which helper processes do not inherit
+pub(crate) static MUTEXES: Lazy<TempFile<ArrayVec<[MutexesType; MAX_OBJECTS]>>> =
+    Lazy::new(|| open_registry(MUTEXES_FILE, None));
+
+/// Opens the registry of created ports `name`, unless a helper process
+/// `inherited` it from the partition
+fn open_registry<T: Send + Clone + Default>(name: &str, inherited: Option<RawFd>) -> TempFile<T> {
+    if let Some(fd) = inherited {
+        TempFile::try_from(fd).unwrap()
+    } else if let Ok(fd) = get_memfd(name) {
+        TempFile::try_from(fd).unwrap()
+    } else {
+        let file = TempFile::create(name).unwrap();
+        file.write(&Default::default()).unwrap();
+        file
+    }
+}
+
+pub(crate) static SENDER: Lazy<IpcSender<PartitionCall>> = Lazy::new(|| {
+    let sender = ipc::connect_sender(PartitionConstants::IPC_SENDER.as_ref()).unwrap();
+    // Already limited to net.core.wmem_max by the hypervisor
+    if let Some(size) = CONSTANTS.ipc_buffer {
+        sender.set_send_buffer(size).unwrap();
+    }
+    sender
+});
+
+/// Limits the telemetry updates sent by all processes of the partition
+pub(crate) static TELEMETRY_LIMIT: Lazy<Mutex<RateLimit>> =
+    Lazy::new(|| Mutex::new(RateLimit::default()));
+
+#[cfg(feature = "socket")]
+pub(crate) static UDP_IO_RX: Lazy<IoReceiver<UdpSocket>> =
+    Lazy::new(|| unsafe { IoReceiver::<UdpSocket>::from_raw_fd(CONSTANTS.udp_io_fd) });
+
+#[cfg(feature = "socket")]
+pub(crate) static TCP_IO_RX: Lazy<IoReceiver<TcpStream>> =
+    Lazy::new(|| unsafe { IoReceiver::<TcpStream>::from_raw_fd(CONSTANTS.tcp_io_fd) });
+
+pub(crate) static PORT_ACTIVITY: Lazy<Poller> = Lazy::new(|| {
+    let poller = Poller::new().unwrap();
+    unsafe {
+        poller
+            .add_with_mode(CONSTANTS.activity_fd, Event::readable(0), PollMode::Level)
+            .unwrap()
+    };
+    poller
+});
+
+#[allow(unused)]
+pub(crate) static SYSCALL: Lazy<SyscallSender> = Lazy::new(|| {
+    SyscallSender::from_path(SYSCALL_SOCKET_PATH)
+        .expect("opening a syscall socket to always succeed")
+});
+
+#[cfg(feature = "socket")]
+pub(crate) static UDP_SOCKETS: Lazy<Vec<UdpSocket>> = Lazy::new(|| receive_sockets(&UDP_IO_RX));
+
+#[cfg(feature = "socket")]
+pub(crate) static TCP_SOCKETS: Lazy<Vec<TcpStream>> = Lazy::new(|| receive_sockets(&TCP_IO_RX));
+
+/// Receives sockets from the hypervisor.
+/// Will panic if an error occurs while receiving the file descriptors of the
+/// sockets.
+#[cfg(feature = "socket")]
+fn receive_sockets<T: FromRawFd>(receiver: &IoReceiver<T>) -> Vec<T> {
+    let mut sockets: Vec<T> = Vec::default();
+    loop {
+        match unsafe { receiver.try_receive() } {
+            Ok(i) => {
+                if let Some(i) = i {
+                    sockets.push(i);
+                } else {
+                    return sockets;
+                }
+            }
+            Err(e) => panic!("Could not receive sockets from hypervisor: {e:?}"),
+        }
+    }
+}
@@ -0,0 +1,419 @@
+//! Mutexes for the mutual exclusion of the processes of a partition
+//!
+//! A mutex is owned by at most one process at a time, which may acquire it
+//! again up to [MAX_LOCK_COUNT] times and has to release it as often. Each
+//! mutex lives in a memfd of its own, and the mutexes created so far are
+//! listed in a registry like the events, see [crate::MUTEXES]. Helper processes
+//! do not inherit the mutexes.
+//!
+//! The owner of a mutex is a word of its memfd, which processes waiting for
+//! the mutex sleep on with a futex. The mutex is not bound to the thread of its
+//! owner like a pthread mutex, so that any process can reset it, e.g. the
+//! error handler releasing the mutex of a faulty process. The priority of a
+//! mutex is stored and reported, but not applied to its owner.
+
+use std::ffi::CString;
+use std::fs::File;
+use std::mem::{size_of, ManuallyDrop};
+use std::os::fd::{FromRawFd, IntoRawFd, RawFd};
+use std::sync::atomic::{AtomicI32, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Instant;
+
+use a653rs::bindings::{ErrorReturnCode, LockCount, MutexId, Priority, ProcessId};
+use a653rs_linux_core::error::{ResultExt, SystemError, TypedResult};
+use anyhow::anyhow;
+use memmap2::MmapMut;
+use once_cell::sync::Lazy;
+
+use crate::time::Timeout;
+use crate::{futex, MUTEXES};
+
+/// The mutexes mapped into this process, in the order of [MUTEXES]
+static MAPPED: Lazy<StdMutex<Vec<Arc<Mutex>>>> = Lazy::new(Default::default);
+
+/// Times a process may acquire a mutex it owns, the `MAX_LOCK_LEVEL` of
+/// ARINC 653
+pub(crate) const MAX_LOCK_COUNT: LockCount = 16;
+
+/// Id reported for a process owning no mutex
+pub(crate) const NO_MUTEX_OWNED: MutexId = -2;
+
+/// Owner of a mutex nobody owns, the `NULL_PROCESS_ID` of ARINC 653
+pub(crate) const NO_OWNER: ProcessId = 0;
+
+/// Shared memory of a mutex
+///
+/// A new memfd is filled with zeros, which is an available mutex.
+#[repr(C)]
+struct Header {
+    /// The owning process, or [NO_OWNER], a futex word
+    owner: AtomicU32,
+    /// Times the owner acquired the mutex, only changed by the owner and
+    /// resets
+    lock_count: AtomicI32,
+    /// Processes blocked in acquiring the mutex
+    waiting: AtomicU32,
+    /// The priority given on creation
+    priority: AtomicI32,
+}
+
+/// A mutex mapped into this process
+#[derive(Debug)]
+pub(crate) struct Mutex {
+    mmap: MmapMut,
+}
+
+impl Mutex {
+    /// Creates the memfd of an available mutex with the priority `priority`,
+    /// returning its fd
+    pub(crate) fn create(name: &str, priority: Priority) -> TypedResult<RawFd> {
+        let name = CString::new(format!("mutex_{name}")).typ(SystemError::Panic)?;
+        // Not close-on-exec, like the other files of the partition
+        let fd = unsafe { libc::memfd_create(name.as_ptr(), 0) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error()).typ(SystemError::Panic);
+        }
+        let file = unsafe { File::from_raw_fd(fd) };
+        file.set_len(size_of::<Header>() as u64)
+            .typ(SystemError::Panic)?;
+        Self::open(fd)?
+            .header()
+            .priority
+            .store(priority, Ordering::SeqCst);
+        Ok(file.into_raw_fd())
+    }
+
+    /// Maps the mutex in the memfd `fd`
+    pub(crate) fn open(fd: RawFd) -> TypedResult<Self> {
+        // The fd stays open for later mappings
+        let file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+        let mmap = unsafe { MmapMut::map_mut(&*file) }.typ(SystemError::Panic)?;
+        if mmap.len() < size_of::<Header>() {
+            return Err(anyhow!("mutex of {} bytes is too small", mmap.len()))
+                .typ(SystemError::Panic);
+        }
+        Ok(Self { mmap })
+    }
+
+    fn header(&self) -> &Header {
+        // The mapping is page aligned and large enough, see Mutex::open
+        unsafe { &*(self.mmap.as_ptr() as *const Header) }
+    }
+
+    /// The priority given on creation
+    pub(crate) fn priority(&self) -> Priority {
+        self.header().priority.load(Ordering::SeqCst)
+    }
+
+    /// The owning process, if any
+    pub(crate) fn owner(&self) -> Option<ProcessId> {
+        match self.header().owner.load(Ordering::SeqCst) as ProcessId {
+            NO_OWNER => None,
+            owner => Some(owner),
+        }
+    }
+
+    pub(crate) fn lock_count(&self) -> LockCount {
+        self.header().lock_count.load(Ordering::SeqCst)
+    }
+
+    /// Number of processes blocked in acquiring the mutex
+    pub(crate) fn waiting(&self) -> u32 {
+        self.header().waiting.load(Ordering::SeqCst)
+    }
+
+    /// Acquires the mutex for `process`, waiting for at most `timeout` if
+    /// another process owns it
+    ///
+    /// Yields [ErrorReturnCode::InvalidConfig] if `process` acquired the mutex
+    /// [MAX_LOCK_COUNT] times already, and the error of the expired timeout if
+    /// the mutex stayed owned.
+    pub(crate) fn acquire(
+        &self,
+        process: ProcessId,
+        timeout: Timeout,
+    ) -> Result<(), ErrorReturnCode> {
+        let header = self.header();
+        let word = owner_word(process);
+        if header.owner.load(Ordering::SeqCst) == word {
+            let count = header.lock_count.load(Ordering::SeqCst);
+            if count >= MAX_LOCK_COUNT {
+                trace!("yielding InvalidConfig, because process {process} acquired the mutex {count} times");
+                return Err(ErrorReturnCode::InvalidConfig);
+            }
+            header.lock_count.store(count + 1, Ordering::SeqCst);
+            return Ok(());
+        }
+
+        // A deadline too far in the future is the same as no deadline at all
+        let deadline = timeout
+            .duration()
+            .and_then(|d| Instant::now().checked_add(d));
+        loop {
+            let owner = match header.owner.compare_exchange(
+                owner_word(NO_OWNER),
+                word,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => break,
+                Err(owner) => owner,
+            };
+            let remaining = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return Err(timeout.expired());
+                    }
+                    Some(remaining)
+                }
+                None => None,
+            };
+
+            // A release after loading the owner changed it, so that the wait returns at
+            // once
+            header.waiting.fetch_add(1, Ordering::SeqCst);
+            futex::wait(&header.owner, owner, remaining);
+            // Saturate, should the memory have been tampered with
+            let _ = header
+                .waiting
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+        }
+        header.lock_count.store(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Releases the mutex once for `process`, making it available after the
+    /// last release
+    ///
+    /// Yields [ErrorReturnCode::InvalidMode] if `process` does not own it.
+    pub(crate) fn release(&self, process: ProcessId) -> Result<(), ErrorReturnCode> {
+        let header = self.header();
+        if header.owner.load(Ordering::SeqCst) != owner_word(process) {
+            trace!("yielding InvalidMode, because process {process} does not own the mutex");
+            return Err(ErrorReturnCode::InvalidMode);
+        }
+        let count = header.lock_count.load(Ordering::SeqCst) - 1;
+        header.lock_count.store(count, Ordering::SeqCst);
+        if count <= 0 {
+            self.unlock(process)?;
+        }
+        Ok(())
+    }
+
+    /// Releases the mutex owned by `process` completely, regardless of how
+    /// often it was acquired
+    ///
+    /// Any process may reset the mutex of another one.
+    pub(crate) fn reset(&self, process: ProcessId) -> Result<(), ErrorReturnCode> {
+        self.unlock(process)
+    }
+
+    /// Makes the mutex owned by `process` available, waking the processes
+    /// waiting for it
+    ///
+    /// Yields [ErrorReturnCode::InvalidMode] if `process` does not own it,
+    /// e.g. since it was reset meanwhile.
+    fn unlock(&self, process: ProcessId) -> Result<(), ErrorReturnCode> {
+        let header = self.header();
+        let word = owner_word(process);
+        if header.owner.load(Ordering::SeqCst) != word {
+            trace!("yielding InvalidMode, because process {process} does not own the mutex");
+            return Err(ErrorReturnCode::InvalidMode);
+        }
+        header.lock_count.store(0, Ordering::SeqCst);
+        if header
+            .owner
+            .compare_exchange(
+                word,
+                owner_word(NO_OWNER),
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            )
+            .is_err()
+        {
+            trace!(
+                "yielding InvalidMode, because the mutex of process {process} was reset meanwhile"
+            );
+            return Err(ErrorReturnCode::InvalidMode);
+        }
+        if header.waiting.load(Ordering::SeqCst) > 0 {
+            futex::wake(&header.owner);
+        }
+        Ok(())
+    }
+}
+
+/// The word of the owner `process` in the memfd of a mutex
+fn owner_word(process: ProcessId) -> u32 {
+    // Process ids are small and positive, see crate::process
+    process as u32
+}
+
+/// The mutex with the id `id`, mapping it into this process first if
+/// necessary
+///
+/// Yields [ErrorReturnCode::InvalidParam] if no such mutex was created.
+pub(crate) fn get(id: MutexId) -> Result<Arc<Mutex>, ErrorReturnCode> {
+    // Mutex ids start at one
+    let index = usize::try_from(id)
+        .ok()
+        .and_then(|id| id.checked_sub(1))
+        .ok_or(ErrorReturnCode::InvalidParam)?;
+    let mut mapped = MAPPED.lock().unwrap();
+    if let Some(mutex) = mapped.get(index) {
+        return Ok(mutex.clone());
+    }
+
+    let created = MUTEXES.read().map_err(|_| ErrorReturnCode::NotAvailable)?;
+    if index >= created.len() {
+        return Err(ErrorReturnCode::InvalidParam);
+    }
+    for (_, fd) in created[mapped.len()..=index].iter() {
+        let mutex = Mutex::open(*fd).map_err(|e| {
+            trace!("yielding NotAvailable, because mutex could not be mapped: {e}");
+            ErrorReturnCode::NotAvailable
+        })?;
+        mapped.push(Arc::new(mutex));
+    }
+    Ok(mapped[index].clone())
+}
+
+/// The id of the mutex owned by `process`, or [NO_MUTEX_OWNED]
+pub(crate) fn owned_by(process: ProcessId) -> Result<MutexId, ErrorReturnCode> {
+    let created = MUTEXES
+        .read()
+        .map_err(|_| ErrorReturnCode::NotAvailable)?
+        .len();
+    for id in 1..=created as MutexId {
+        if get(id)?.owner() == Some(process) {
+            return Ok(id);
+        }
+    }
+    Ok(NO_MUTEX_OWNED)
+}
+
+/// Resets the mutex owned by `process`, if any, e.g. since it was stopped
+pub(crate) fn reset_owned_by(process: ProcessId) -> Result<(), ErrorReturnCode> {
+    match owned_by(process)? {
+        NO_MUTEX_OWNED => Ok(()),
+        id => get(id)?.reset(process),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn mutex() -> Arc<Mutex> {
+        let fd = Mutex::create("test", 10).unwrap();
+        Arc::new(Mutex::open(fd).unwrap())
+    }
+
+    #[test]
+    fn acquire_and_release() {
+        let mutex = mutex();
+        assert_eq!(mutex.owner(), None);
+        assert_eq!(mutex.priority(), 10);
+        assert_eq!(mutex.release(1), Err(ErrorReturnCode::InvalidMode));
+
+        // Acquired again by its owner, up to the maximum
+        for count in 1..=MAX_LOCK_COUNT {
+            mutex.acquire(1, Timeout::Immediate).unwrap();
+            assert_eq!(mutex.lock_count(), count);
+        }
+        assert_eq!(
+            mutex.acquire(1, Timeout::Immediate),
+            Err(ErrorReturnCode::InvalidConfig)
+        );
+        assert_eq!(mutex.owner(), Some(1));
+        assert_eq!(mutex.release(2), Err(ErrorReturnCode::InvalidMode));
+
+        for count in (0..MAX_LOCK_COUNT).rev() {
+            mutex.release(1).unwrap();
+            assert_eq!(mutex.lock_count(), count);
+        }
+        assert_eq!(mutex.owner(), None);
+
+        // Reset releases all acquisitions at once
+        mutex.acquire(1, Timeout::Immediate).unwrap();
+        mutex.acquire(1, Timeout::Immediate).unwrap();
+        assert_eq!(mutex.reset(2), Err(ErrorReturnCode::InvalidMode));
+        mutex.reset(1).unwrap();
+        assert_eq!(mutex.owner(), None);
+        assert_eq!(mutex.lock_count(), 0);
+    }
+
+    #[test]
+    fn owned_by_another_process() {
+        let mutex = mutex();
+        let owner = {
+            let mutex = mutex.clone();
+            thread::spawn(move || {
+                mutex.acquire(1, Timeout::Infinite).unwrap();
+                thread::sleep(Duration::from_millis(100));
+                mutex.release(1).unwrap();
+            })
+        };
+        while mutex.owner().is_none() {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(
+            mutex.acquire(2, Timeout::Immediate),
+            Err(ErrorReturnCode::NotAvailable)
+        );
+        let start = Instant::now();
+        let timeout = Duration::from_millis(20);
+        assert_eq!(
+            mutex.acquire(2, Timeout::Finite(timeout)),
+            Err(ErrorReturnCode::TimedOut)
+        );
+        assert!(start.elapsed() >= timeout);
+
+        // Handed over once released
+        mutex
+            .acquire(2, Timeout::Finite(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(mutex.owner(), Some(2));
+        assert_eq!(mutex.waiting(), 0);
+        owner.join().unwrap();
+        mutex.release(2).unwrap();
+    }
+
+    #[test]
+    fn reset_by_another_process() {
+        let mutex = mutex();
+        {
+            let mutex = mutex.clone();
+            thread::spawn(move || {
+                mutex.acquire(1, Timeout::Immediate).unwrap();
+                mutex.acquire(1, Timeout::Immediate).unwrap();
+            })
+            .join()
+            .unwrap();
+        }
+        assert_eq!(mutex.owner(), Some(1));
+
+        let waiter = {
+            let mutex = mutex.clone();
+            thread::spawn(move || mutex.acquire(2, Timeout::Infinite))
+        };
+        while mutex.waiting() == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        // The waiting process is handed the mutex
+        assert_eq!(mutex.reset(2), Err(ErrorReturnCode::InvalidMode));
+        mutex.reset(1).unwrap();
+        waiter.join().unwrap().unwrap();
+        assert_eq!(mutex.owner(), Some(2));
+        assert_eq!(mutex.lock_count(), 1);
+        assert_eq!(mutex.release(1), Err(ErrorReturnCode::InvalidMode));
+        mutex.release(2).unwrap();
+        assert_eq!(mutex.owner(), None);
+    }
+}
@@ -1,4 +1,9 @@
-use std::cmp::min;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::process::{Child, Command};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
 #[cfg(feature = "socket")]
 use std::{
     fmt::Display,
@@ -6,15 +11,86 @@ use std::{
     net::{TcpStream, UdpSocket},
 };
 
-use a653rs::prelude::{ApexErrorP4Ext, MAX_ERROR_MESSAGE_SIZE};
-use a653rs_linux_core::error::SystemError;
-use a653rs_linux_core::health_event::PartitionCall;
-use log::{set_logger, set_max_level, LevelFilter, Record, SetLoggerError};
+use a653rs::bindings::{
+    ApexByte, ApexName, ApexQueuingPortP4, ApexSamplingPortP4, ApexSystemTime, ErrorReturnCode,
+    MessageRange, MessageSize, PortDirection, QueuingDiscipline, QueuingPortId, SamplingPortId,
+    Validity,
+};
+use a653rs::prelude::{Name, OperatingMode, SystemTime, MAX_ERROR_MESSAGE_SIZE};
+pub use a653rs_linux_core::conditions::ModuleConditions;
+use a653rs_linux_core::error::{SystemError, TypedResult};
+use a653rs_linux_core::file::TempFile;
+use a653rs_linux_core::health_event::{LogRecord, PartitionCall, ProcessKind};
+use a653rs_linux_core::partition::{PartitionConstants, RestartCause};
+pub use a653rs_linux_core::partition::{
+    PortActivity, PortDecl, QueuingConstant, Requirements, SamplingConstant,
+};
+use a653rs_linux_core::telemetry::check_name;
+use a653rs_linux_core::time::ModuleTime;
+use a653rs_linux_core::wire;
+use log::{set_logger, set_max_level, Level, LevelFilter, Record, SetLoggerError};
+use nix::errno::Errno;
+use nix::libc::EAGAIN;
+use polling::Events;
 
-use crate::{CONSTANTS, SENDER};
+use crate::ext::{PartitionLogger, PartitionRole};
+use crate::process::Process;
+use crate::time::{self, Timeout};
+use crate::{
+    apex, fork, helper, module_time, CONSTANTS, MODE, MODULE_CONDITIONS, PORT_ACTIVITY, SENDER,
+    TELEMETRY_LIMIT,
+};
 #[cfg(feature = "socket")]
 use crate::{TCP_SOCKETS, UDP_SOCKETS};
 
+/// Ports created by [ApexLinuxPartition::create_configured_ports], by port
+/// name
+#[derive(Debug, Clone, Default)]
+pub struct ConfiguredPorts {
+    pub sampling: HashMap<String, (SamplingPortId, PortDirection)>,
+    pub queuing: HashMap<String, (QueuingPortId, PortDirection)>,
+}
+
+/// Parameters of a port as configured for this partition
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum PortParameters {
+    Sampling {
+        name: String,
+        dir: PortDirection,
+        msg_size: MessageSize,
+        refresh_period: ApexSystemTime,
+    },
+    Queuing {
+        name: String,
+        dir: PortDirection,
+        msg_size: MessageSize,
+        max_num_msg: MessageRange,
+    },
+}
+
+impl PortParameters {
+    /// Parameters of all ports in `constants`, sampling ports without a
+    /// configured refresh period get `default_refresh`
+    pub(crate) fn configured(
+        constants: &PartitionConstants,
+        default_refresh: Duration,
+    ) -> Vec<Self> {
+        let sampling = constants.sampling.iter().map(|s| PortParameters::Sampling {
+            name: s.name.clone(),
+            dir: s.dir,
+            msg_size: s.msg_size as MessageSize,
+            refresh_period: time::to_apex_time(s.refresh_period.unwrap_or(default_refresh)),
+        });
+        let queuing = constants.queuing.iter().map(|q| PortParameters::Queuing {
+            name: q.name.clone(),
+            dir: q.dir,
+            msg_size: q.msg_size as MessageSize,
+            max_num_msg: q.max_num_msg as MessageRange,
+        });
+        sampling.chain(queuing).collect()
+    }
+}
+
 /// Static functions for within a partition
 #[derive(Debug, Clone, Copy)]
 pub struct ApexLinuxPartition;
@@ -24,6 +100,14 @@ impl ApexLinuxPartition {
         CONSTANTS.name.clone()
     }
 
+    /// Role of this partition as configured for the hypervisor
+    ///
+    /// Partitions sharing an image should branch on their role rather than
+    /// on their identifier.
+    pub fn role() -> Option<&'static str> {
+        CONSTANTS.role.as_deref()
+    }
+
     #[cfg(feature = "socket")]
     pub fn get_udp_socket(sockaddr: &str) -> Result<Option<UdpSocket>, ApexLinuxError> {
         for stored in UDP_SOCKETS.iter() {
@@ -46,13 +130,454 @@ impl ApexLinuxPartition {
         Ok(None)
     }
 
+    /// Blocks until new data arrived on any destination port of this
+    /// partition, or the timeout elapsed.
+    ///
+    /// Returns which classes of ports received data since the last call. The
+    /// hypervisor signals activity after transferring the data of a source
+    /// partition at the end of its window. Spurious wakeups are allowed, so
+    /// callers should still expect empty ports. A process waiting here is
+    /// frozen at the end of the partition window like any other process and
+    /// continues waiting in the next window.
+    pub fn wait_for_port_activity(timeout: SystemTime) -> Result<PortActivity, ErrorReturnCode> {
+        fork::check()?;
+        let timeout = Timeout::from(timeout);
+        let mut events = Events::new();
+        if let Err(e) = PORT_ACTIVITY.wait(&mut events, timeout.duration()) {
+            warn!("failed to wait for port activity: {e}");
+            return Err(ErrorReturnCode::NotAvailable);
+        }
+
+        let mut value = [0; std::mem::size_of::<u64>()];
+        match nix::unistd::read(CONSTANTS.activity_fd, &mut value) {
+            Ok(_) => Ok(PortActivity::from_eventfd_value(u64::from_ne_bytes(value))),
+            // Nothing was signalled (yet)
+            Err(Errno::EAGAIN) => Err(timeout.expired()),
+            Err(e) => {
+                warn!("failed to read port activity: {e}");
+                Err(ErrorReturnCode::NotAvailable)
+            }
+        }
+    }
+
+    /// Blocks until the operating mode of the partition differs from `seen`,
+    /// or the timeout elapsed.
+    ///
+    /// Returns the new mode. Passing the mode last seen, e.g. in the
+    /// partition status, makes sure that a change right before the call is
+    /// not missed. The hypervisor publishes every mode change in memory shared
+    /// with the partition and then signals an eventfd, which wakes up all
+    /// waiting threads at once. As processes only run in
+    /// [OperatingMode::Normal], and everything is frozen in
+    /// [OperatingMode::Idle], this mostly serves threads started during the
+    /// initialization of the partition, which wait for it to go into
+    /// [OperatingMode::Normal].
+    pub fn wait_for_mode_change(
+        seen: OperatingMode,
+        timeout: SystemTime,
+    ) -> Result<OperatingMode, ErrorReturnCode> {
+        fork::check()?;
+        let timeout = Timeout::from(timeout);
+        let seen = wire::OperatingMode::from(seen);
+        let word = MODE.get().map_err(|e| {
+            warn!("failed to read the operating mode: {e}");
+            ErrorReturnCode::NotAvailable
+        })?;
+        if word.mode != seen {
+            return Ok(word.mode.into());
+        }
+
+        match MODE.wait_for_change(word.generation, timeout.duration()) {
+            Ok(word) if word.mode != seen => Ok(word.mode.into()),
+            Ok(_) => Err(timeout.expired()),
+            Err(e) => {
+                warn!("failed to wait for a mode change: {e}");
+                Err(ErrorReturnCode::NotAvailable)
+            }
+        }
+    }
+
+    /// Declares all ports this partition is going to create.
+    ///
+    /// Call this right after the start of the partition. The hypervisor then
+    /// reports every mismatch with the channel configuration at once, instead
+    /// of [ErrorReturnCode::InvalidConfig] being returned by the individual
+    /// port creation services later on. With `strict_ports` enabled in the
+    /// partition configuration, a mismatch fails the initialization of the
+    /// partition.
+    ///
+    /// Partitions assembled with the [builder](crate::builder) declare the
+    /// ports given to
+    /// [PartitionBuilder::declare_ports](crate::builder::PartitionBuilder::declare_ports)
+    /// on every start. The `partition` macro of a653rs keeps the attributes of
+    /// its ports to itself, so its partitions have to call this themselves.
+    pub fn declare_ports(ports: Vec<PortDecl>) {
+        if fork::is_forked() {
+            warn!("Dropping port declarations of a forked child of the partition");
+            return;
+        }
+        if let Err(e) = SENDER.try_send(&PartitionCall::DeclarePorts(ports)) {
+            warn!("Could not send port declarations: {e:?}")
+        }
+    }
+
+    /// Declares what this partition expects of its configuration: its period,
+    /// the shortest window it needs and the ports it is going to create.
+    ///
+    /// Like [ApexLinuxPartition::declare_ports], this is meant to be called
+    /// right after the start of the partition, e.g. with
+    /// [declare_requirements](crate::declare_requirements). The hypervisor
+    /// logs every requirement the configuration does not meet. With
+    /// `strict_requirements` enabled in the partition configuration, it
+    /// refuses to bring the partition to NORMAL then.
+    pub fn declare_requirements(requirements: Requirements) {
+        if fork::is_forked() {
+            warn!("Dropping requirements of a forked child of the partition");
+            return;
+        }
+        if let Err(e) = SENDER.try_send(&PartitionCall::DeclareRequirements(requirements)) {
+            warn!("Could not send requirements: {e:?}")
+        }
+    }
+
+    /// The sampling ports configured for this partition, whether or not they
+    /// were created yet
+    ///
+    /// Meant for partitions which handle whatever ports the configuration
+    /// gives them, e.g. routers or loggers:
+    ///
+    /// ```no_run
+    /// use a653rs_linux::partition::ApexLinuxPartition;
+    ///
+    /// for port in ApexLinuxPartition::sampling_port_configs() {
+    ///     let dir = if port.is_source() { "source" } else { "destination" };
+    ///     let refresh = port.refresh_period;
+    ///     println!("{}: {dir}, {} bytes, refresh {refresh:?}", port.name, port.msg_size);
+    /// }
+    /// ```
+    pub fn sampling_port_configs() -> &'static [SamplingConstant] {
+        &CONSTANTS.sampling
+    }
+
+    /// The queuing ports configured for this partition, whether or not they
+    /// were created yet
+    ///
+    /// ```no_run
+    /// use a653rs_linux::partition::ApexLinuxPartition;
+    ///
+    /// let destinations = ApexLinuxPartition::queuing_port_configs()
+    ///     .iter()
+    ///     .filter(|port| !port.is_source());
+    /// for port in destinations {
+    ///     println!("{}: up to {} messages", port.name, port.max_num_msg);
+    /// }
+    /// ```
+    pub fn queuing_port_configs() -> &'static [QueuingConstant] {
+        &CONSTANTS.queuing
+    }
+
+    /// Creates every sampling and queuing port configured for this partition
+    ///
+    /// Meant for partitions whose ports are defined by the configuration
+    /// alone, e.g. gateways. Like the individual port creation services, this
+    /// may only be called in a start mode. Sampling ports get the refresh
+    /// period configured for their channel, or `default_refresh` if there is
+    /// none. The returned ids are those used by [ApexSamplingPortP4] and
+    /// [ApexQueuingPortP4].
+    pub fn create_configured_ports(
+        default_refresh: Duration,
+    ) -> Result<ConfiguredPorts, ErrorReturnCode> {
+        let mut ports = ConfiguredPorts::default();
+        for port in PortParameters::configured(&CONSTANTS, default_refresh) {
+            match port {
+                PortParameters::Sampling {
+                    name,
+                    dir,
+                    msg_size,
+                    refresh_period,
+                } => {
+                    let id = Self::create_sampling_port(
+                        Self::port_name(&name)?,
+                        msg_size,
+                        dir,
+                        refresh_period,
+                    )?;
+                    ports.sampling.insert(name, (id, dir));
+                }
+                PortParameters::Queuing {
+                    name,
+                    dir,
+                    msg_size,
+                    max_num_msg,
+                } => {
+                    let id = Self::create_queuing_port(
+                        Self::port_name(&name)?,
+                        msg_size,
+                        max_num_msg,
+                        dir,
+                        QueuingDiscipline::Fifo,
+                    )?;
+                    ports.queuing.insert(name, (id, dir));
+                }
+            }
+        }
+        Ok(ports)
+    }
+
+    fn port_name(name: &str) -> Result<ApexName, ErrorReturnCode> {
+        match Name::from_str(name) {
+            Ok(n) => Ok(n.into_inner()),
+            Err(e) => {
+                trace!("yielding InvalidConfig, because port name {name:?} is invalid: {e:?}");
+                Err(ErrorReturnCode::InvalidConfig)
+            }
+        }
+    }
+
+    /// Publishes "no data" on a sampling source port
+    ///
+    /// [ApexSamplingPortP4::write_sampling_message] rejects empty messages as
+    /// the standard demands. This writes an empty message instead, which
+    /// destinations read as a valid message of length zero, while a port that
+    /// never received anything still yields [ErrorReturnCode::NoAction].
+    pub fn write_no_data(sampling_port_id: SamplingPortId) -> Result<(), ErrorReturnCode> {
+        fork::check()?;
+        apex::write_sampling_message(sampling_port_id, &[])
+    }
+
+    /// Reads a sampling port like [ApexSamplingPortP4::read_sampling_message],
+    /// additionally returning the sequence number of the message
+    ///
+    /// The sequence number is `None` if the channel is not `sequenced`.
+    /// Otherwise it increases by one with every message transferred to the
+    /// port, so that a destination can tell a message it already read from a
+    /// new one, and notice messages overwritten before it read them.
+    pub fn receive_with_seq(
+        sampling_port_id: SamplingPortId,
+        message: &mut [ApexByte],
+    ) -> Result<(Validity, MessageSize, Option<u64>), ErrorReturnCode> {
+        fork::check()?;
+        apex::read_sampling_message(sampling_port_id, message)
+            .map(|(valid, len, seq)| (valid, len, (seq != 0).then_some(seq)))
+    }
+
+    /// Reports the current value of the gauge `name` to the hypervisor,
+    /// which exports the last value of each gauge of this partition.
+    ///
+    /// ```no_run
+    /// use a653rs_linux::partition::ApexLinuxPartition;
+    ///
+    /// ApexLinuxPartition::telemetry("fuel_level", 0.73).ok();
+    /// ```
+    ///
+    /// Names follow [check_name](a653rs_linux_core::telemetry::check_name),
+    /// otherwise [ErrorReturnCode::InvalidParam] is returned. Updates beyond
+    /// [MAX_UPDATES_PER_SECOND](a653rs_linux_core::telemetry::MAX_UPDATES_PER_SECOND)
+    /// per second, and updates while the hypervisor socket is full, are
+    /// dropped with [ErrorReturnCode::NotAvailable]. The hypervisor rejects
+    /// the names exceeding the `max_telemetry` of the partition configuration.
+    pub fn telemetry(name: &str, value: f64) -> Result<(), ErrorReturnCode> {
+        fork::check()?;
+        if let Some(reason) = check_name(name) {
+            warn!("Invalid telemetry name {name:?}: {reason}");
+            return Err(ErrorReturnCode::InvalidParam);
+        }
+        let allowed = TELEMETRY_LIMIT
+            .lock()
+            .is_ok_and(|mut limit| limit.allow(module_time().as_duration()));
+        if !allowed {
+            return Err(ErrorReturnCode::NotAvailable);
+        }
+
+        let call = PartitionCall::Telemetry {
+            name: name.to_string(),
+            value,
+        };
+        SENDER
+            .try_send(&call)
+            .map_err(|_| ErrorReturnCode::NotAvailable)?;
+        Self::report_dropped_calls();
+        Ok(())
+    }
+
+    /// Tells the hypervisor how many calls were dropped so far because its
+    /// socket was full, once sending succeeds again
+    fn report_dropped_calls() {
+        static REPORTED: AtomicU64 = AtomicU64::new(0);
+        let dropped = SENDER.dropped();
+        if dropped > REPORTED.load(Ordering::Relaxed)
+            && SENDER
+                .try_send(&PartitionCall::DroppedCalls(dropped))
+                .is_ok()
+        {
+            REPORTED.fetch_max(dropped, Ordering::Relaxed);
+        }
+    }
+
+    /// Tells the hypervisor that a port was created, so that it starts to
+    /// transfer the messages of its channel
+    pub(crate) fn report_port_created(port: PortDecl) {
+        if let Err(e) = SENDER.try_send(&PartitionCall::PortCreated(port)) {
+            warn!("Could not report port creation: {e:?}")
+        }
+    }
+
+    /// Returns the error which made the health monitor restart this partition
+    /// and the module time at which the error was handled.
+    ///
+    /// `None` if the current start was not initiated by the health monitor,
+    /// i.e. on the first start of the module and after restarts requested by
+    /// the partition itself. Together with the start condition of the
+    /// partition status, this allows the initialization to adapt to the
+    /// preceding failure.
+    pub fn last_restart_cause() -> Option<(SystemError, ModuleTime)> {
+        let cause = TempFile::<Option<RestartCause>>::try_from(CONSTANTS.restart_cause_fd)
+            .and_then(|file| file.read());
+        match cause {
+            Ok(cause) => cause.map(|c| (c.error, c.time)),
+            Err(e) => {
+                warn!("Could not read restart cause: {e:?}");
+                None
+            }
+        }
+    }
+
+    /// Returns the conditions of the module currently affecting this
+    /// partition, e.g. whether one of its channels is degraded
+    ///
+    /// The hypervisor updates them at the start of every major frame and
+    /// clears a condition once its cause resolved. Stale data on a port can
+    /// thus be told apart from a source which stopped writing. Reading them
+    /// only loads a word from memory shared with the hypervisor.
+    pub fn module_conditions() -> ModuleConditions {
+        // Volatile, as the hypervisor changes the memory behind our back
+        unsafe { std::ptr::read_volatile(MODULE_CONDITIONS.as_ref()) }
+    }
+
+    /// Returns the conditions of the module if they changed since `last`,
+    /// which is updated to them
+    ///
+    /// Meant to be polled cheaply, e.g. at the start of every periodic
+    /// activation, starting with [ModuleConditions::NONE].
+    pub fn module_conditions_changed(last: &mut ModuleConditions) -> Option<ModuleConditions> {
+        conditions_changed(Self::module_conditions(), last)
+    }
+
+    /// Spawns `command` as a helper process of this partition, e.g. a tool
+    /// converting data, which may use the ports of the partition by linking
+    /// against this crate
+    ///
+    /// The helper inherits the files shared with the hypervisor and the
+    /// registries of the created ports, which are passed in its environment;
+    /// all other file descriptors are closed on exec. It uses the ports by the
+    /// ids the partition got when creating them, as it may not create ports
+    /// itself.
+    ///
+    /// Helpers run in the cgroup of the process spawning them, so they count
+    /// towards the pids limit of the partition and are frozen together with
+    /// it, and are killed with it when it is restarted.
+    ///
+    /// This is the only way to start processes using the services of the
+    /// partition. A process merely forked by the partition shares its state,
+    /// so its calls to services are refused with
+    /// [ErrorReturnCode::InvalidMode].
+    pub fn spawn_helper(command: Command) -> std::io::Result<Child> {
+        helper::spawn(command)
+    }
+
+    /// Forwards a log message to the hypervisor, tagged with the emitting
+    /// process and the current module time.
+    ///
+    /// Messages are dropped while the hypervisor socket is full. Forked
+    /// children of the partition write them to their stderr instead.
+    pub(crate) fn send_log_record(level: Option<Level>, message: String) -> TypedResult<()> {
+        if fork::is_forked() {
+            match level {
+                Some(level) => eprintln!("[{level}] {message}"),
+                None => eprintln!("{message}"),
+            }
+            return Ok(());
+        }
+        let process = match Process::get_self() {
+            Some(p) if p.periodic() => ProcessKind::Periodic,
+            Some(_) => ProcessKind::Aperiodic,
+            None => ProcessKind::Main,
+        };
+        let record = LogRecord {
+            level,
+            process,
+            time: module_time(),
+            message,
+        };
+        match SENDER.try_send(&PartitionCall::Message(record)) {
+            Ok(()) => {
+                Self::report_dropped_calls();
+                Ok(())
+            }
+            Err(e)
+                if e.source()
+                    .downcast_ref::<std::io::Error>()
+                    .and_then(|e| e.raw_os_error())
+                    == Some(EAGAIN) =>
+            {
+                Ok(())
+            }
+            res => res,
+        }
+    }
+
     pub(crate) fn raise_system_error(error: SystemError) {
-        if let Err(e) = SENDER.try_send(&PartitionCall::Error(error)) {
+        // Unlike log records, errors are not dropped while the socket is full
+        if let Err(e) = SENDER.send(&PartitionCall::Error(error)) {
             panic!("Could not send SystemError event {error:?}. {e:?}")
         };
     }
 }
 
+impl PartitionRole for ApexLinuxPartition {
+    fn role() -> Option<&'static str> {
+        ApexLinuxPartition::role()
+    }
+}
+
+/// Declares the requirements of this partition with
+/// [ApexLinuxPartition::declare_requirements]
+///
+/// Fields which are not given are not checked. The period and the shortest
+/// window are [Duration]s, the ports are [PortDecl]s:
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use a653rs::bindings::PortDirection;
+/// use a653rs_linux::declare_requirements;
+/// use a653rs_linux::partition::PortDecl;
+///
+/// declare_requirements!(
+///     period: Duration::from_millis(500),
+///     min_duration: Duration::from_millis(100),
+///     ports: [PortDecl::Sampling {
+///         name: "fuel_level".into(),
+///         dir: PortDirection::Source,
+///         msg_size: 8,
+///     }],
+/// );
+/// ```
+///
+/// The timing attributes of the a653rs partition macro are not available at
+/// run-time, so they have to be repeated here.
+#[macro_export]
+macro_rules! declare_requirements {
+    ($($field:ident: $value:expr),* $(,)?) => {
+        $crate::partition::ApexLinuxPartition::declare_requirements(
+            $crate::partition::Requirements {
+                $($field: ::core::convert::Into::into($value),)*
+                ..::core::default::Default::default()
+            },
+        )
+    };
+}
+
 #[cfg(feature = "socket")]
 #[derive(Debug, Clone)]
 pub enum ApexLinuxError {
@@ -85,28 +610,210 @@ impl ApexLogger {
         set_logger(&APEX_LOGGER).map(|()| set_max_level(level))
     }
 
+    /// Installs the logger with the level of `RUST_LOG`, or `default` if it is
+    /// not set
+    ///
+    /// The hypervisor passes on its own `RUST_LOG`. As the log of a partition
+    /// is only filtered by level, directives for single modules are ignored,
+    /// e.g. `debug,polling=info` results in `debug`.
+    pub fn install_logger_from_env(default: LevelFilter) -> Result<(), SetLoggerError> {
+        let level = std::env::var("RUST_LOG")
+            .ok()
+            .and_then(|value| env_level(&value))
+            .unwrap_or(default);
+        Self::install_logger(level)
+    }
+
     pub fn install_panic_hook() {
         std::panic::set_hook(Box::new(|panic_info| error!("{panic_info:#?}")));
     }
 }
 
+impl PartitionLogger for ApexLogger {
+    fn install_logger(level: LevelFilter) -> Result<(), SetLoggerError> {
+        ApexLogger::install_logger(level)
+    }
+
+    fn install_panic_hook() {
+        ApexLogger::install_panic_hook()
+    }
+}
+
+/// The global level of a `RUST_LOG` value, i.e. its last directive without a
+/// module
+fn env_level(value: &str) -> Option<LevelFilter> {
+    value
+        .split(',')
+        .filter(|directive| !directive.contains('='))
+        .filter_map(|directive| directive.trim().parse().ok())
+        .next_back()
+}
+
+thread_local! {
+    /// Whether this thread is forwarding a log record
+    static FORWARDING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Clears [FORWARDING] when dropped, even on a panic while forwarding
+struct Forwarding;
+
+impl Drop for Forwarding {
+    fn drop(&mut self) {
+        FORWARDING.set(false);
+    }
+}
+
 impl log::Log for ApexLogger {
     fn enabled(&self, _meta: &log::Metadata) -> bool {
         true
     }
 
     fn log(&self, record: &Record) {
-        let level = record.level() as usize;
+        // Forwarding the first record initializes statics which log themselves,
+        // e.g. with trace records of the core crate. Those records are dropped,
+        // as initializing the same static again would deadlock.
+        if FORWARDING.replace(true) {
+            return;
+        }
+        let _forwarding = Forwarding;
         for line in record.args().to_string().lines() {
-            let msg = if line.len() < MAX_ERROR_MESSAGE_SIZE {
-                format!("{level}{line}")
+            let msg = if line.len() <= MAX_ERROR_MESSAGE_SIZE {
+                line.to_string()
             } else {
-                format!("{level}{}..", &line[..(MAX_ERROR_MESSAGE_SIZE - 3)])
+                let mut end = MAX_ERROR_MESSAGE_SIZE - 2;
+                while !line.is_char_boundary(end) {
+                    end -= 1;
+                }
+                format!("{}..", &line[..end])
             };
-            let max = min(MAX_ERROR_MESSAGE_SIZE, msg.len());
-            ApexLinuxPartition::report_application_message(&msg.as_bytes()[0..max]).ok();
+            ApexLinuxPartition::send_log_record(Some(record.level()), msg).ok();
         }
     }
 
     fn flush(&self) {}
 }
+
+/// Returns `current` if it differs from `last`, updating `last`
+fn conditions_changed(
+    current: ModuleConditions,
+    last: &mut ModuleConditions,
+) -> Option<ModuleConditions> {
+    (current != *last).then(|| {
+        *last = current;
+        current
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use a653rs::prelude::StartCondition;
+
+    use super::*;
+
+    #[test]
+    fn forwarding_is_cleared_on_panic() {
+        std::panic::catch_unwind(|| {
+            FORWARDING.set(true);
+            let _forwarding = Forwarding;
+            panic!("while forwarding");
+        })
+        .unwrap_err();
+        assert!(!FORWARDING.get());
+    }
+
+    #[test]
+    fn changed_conditions() {
+        let mut last = ModuleConditions::NONE;
+        assert_eq!(conditions_changed(ModuleConditions::NONE, &mut last), None);
+
+        let degraded = ModuleConditions::CHANNEL_DEGRADED;
+        assert_eq!(conditions_changed(degraded, &mut last), Some(degraded));
+        assert_eq!(last, degraded);
+        assert_eq!(conditions_changed(degraded, &mut last), None);
+
+        // Cleared conditions are changes as well
+        let cleared = ModuleConditions::NONE;
+        assert_eq!(conditions_changed(cleared, &mut last), Some(cleared));
+        assert_eq!(last, cleared);
+    }
+
+    #[test]
+    fn level_of_rust_log() {
+        assert_eq!(env_level("debug"), Some(LevelFilter::Debug));
+        assert_eq!(env_level("WARN"), Some(LevelFilter::Warn));
+        assert_eq!(env_level("polling=info, trace"), Some(LevelFilter::Trace));
+        assert_eq!(env_level("polling=info"), None);
+        assert_eq!(env_level(""), None);
+        assert_eq!(env_level("verbose"), None);
+    }
+
+    #[test]
+    fn configured_port_parameters() {
+        let constants = PartitionConstants {
+            name: "gateway".into(),
+            identifier: 1,
+            role: None,
+            period: Duration::from_millis(100),
+            duration: Duration::from_millis(20),
+            start_condition: StartCondition::NormalStart,
+            start_time_fd: -1,
+            partition_mode_fd: -1,
+            restart_cause_fd: -1,
+            conditions_fd: -1,
+            udp_io_fd: -1,
+            tcp_io_fd: -1,
+            activity_fd: -1,
+            sampling: vec![
+                SamplingConstant {
+                    name: "status".into(),
+                    dir: PortDirection::Source,
+                    msg_size: 8,
+                    fd: -1,
+                    refresh_period: None,
+                },
+                SamplingConstant {
+                    name: "temperature".into(),
+                    dir: PortDirection::Destination,
+                    msg_size: 16,
+                    fd: -1,
+                    refresh_period: Some(Duration::from_millis(200)),
+                },
+            ],
+            queuing: vec![QueuingConstant {
+                name: "commands".into(),
+                dir: PortDirection::Destination,
+                msg_size: 32,
+                max_num_msg: 4,
+                fd: -1,
+            }],
+            ipc_buffer: None,
+            realtime: None,
+            mode_word_fd: -1,
+            mode_event_fd: -1,
+        };
+
+        assert_eq!(
+            PortParameters::configured(&constants, Duration::from_secs(1)),
+            vec![
+                PortParameters::Sampling {
+                    name: "status".into(),
+                    dir: PortDirection::Source,
+                    msg_size: 8,
+                    refresh_period: 1_000_000_000,
+                },
+                PortParameters::Sampling {
+                    name: "temperature".into(),
+                    dir: PortDirection::Destination,
+                    msg_size: 16,
+                    refresh_period: 200_000_000,
+                },
+                PortParameters::Queuing {
+                    name: "commands".into(),
+                    dir: PortDirection::Destination,
+                    msg_size: 32,
+                    max_num_msg: 4,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,130 @@
+//! Mapping of APEX process priorities to the scheduling of Linux threads
+//!
+//! Every process runs in a thread whose Linux scheduling is derived from the
+//! current priority of the process, so that the kernel prefers processes of
+//! a higher priority over those of a lower one within the partition window:
+//! - By default, a process of priority `p` runs at the nice value `19 - p * 19
+//!   / 255`, i.e. priority 255 at nice 0 and priority 0 at nice
+//!   19. Processes of a lower priority still get a small share of the CPU.
+//! - In a partition granted real-time scheduling up to the `SCHED_FIFO`
+//!   priority `ceiling`, a process of priority `p` runs with the `SCHED_FIFO`
+//!   priority `1 + p * (ceiling - 1) / 255`. A process is then only preempted
+//!   by those of a higher `SCHED_FIFO` priority, and runs until it waits.
+//!
+//! Both mappings are monotonic, so a process never gets a lower Linux priority
+//! than one of a lower APEX priority. APEX priorities close to each other may
+//! share a Linux priority.
+
+use a653rs::bindings::Priority;
+use nix::errno::Errno;
+use nix::unistd::Pid;
+
+/// Lowest priority of a process
+pub(crate) const MIN_PRIORITY: Priority = 0;
+/// Highest priority of a process
+pub(crate) const MAX_PRIORITY: Priority = 255;
+
+/// Highest nice value, given to processes of [MIN_PRIORITY]
+const MAX_NICE: i32 = 19;
+
+/// Linux scheduling of the thread of a process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Scheduling {
+    /// `SCHED_OTHER` with this nice value
+    Nice(i32),
+    /// `SCHED_FIFO` with this priority
+    Fifo(i32),
+}
+
+impl Scheduling {
+    /// The scheduling of a process of `priority`, in a partition granted
+    /// real-time scheduling up to the `SCHED_FIFO` priority `realtime`
+    ///
+    /// Returns `None` if `priority` is not within [MIN_PRIORITY] and
+    /// [MAX_PRIORITY].
+    pub(crate) fn of(priority: Priority, realtime: Option<u8>) -> Option<Self> {
+        if !(MIN_PRIORITY..=MAX_PRIORITY).contains(&priority) {
+            return None;
+        }
+        Some(match realtime {
+            Some(ceiling) => {
+                let span = i32::from(ceiling.saturating_sub(1));
+                Scheduling::Fifo(1 + priority * span / MAX_PRIORITY)
+            }
+            None => Scheduling::Nice(MAX_NICE - priority * MAX_NICE / MAX_PRIORITY),
+        })
+    }
+
+    /// Applies this scheduling to the thread `tid`
+    ///
+    /// Raising the priority of a thread fails with `EPERM` if the hypervisor
+    /// could not grant it to the partition.
+    pub(crate) fn apply(self, tid: Pid) -> nix::Result<()> {
+        let res = match self {
+            // Despite its name, PRIO_PROCESS only affects the given thread on Linux
+            Scheduling::Nice(nice) => unsafe {
+                libc::setpriority(libc::PRIO_PROCESS, tid.as_raw() as libc::id_t, nice)
+            },
+            Scheduling::Fifo(priority) => {
+                // musl has further fields for SCHED_SPORADIC, which stay zeroed
+                let mut param: libc::sched_param = unsafe { std::mem::zeroed() };
+                param.sched_priority = priority;
+                unsafe { libc::sched_setscheduler(tid.as_raw(), libc::SCHED_FIFO, &param) }
+            }
+        };
+        Errno::result(res).map(drop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nice_values() {
+        let nice = |priority| Scheduling::of(priority, None);
+        assert_eq!(nice(MAX_PRIORITY), Some(Scheduling::Nice(0)));
+        assert_eq!(nice(MIN_PRIORITY), Some(Scheduling::Nice(19)));
+        assert_eq!(nice(128), Some(Scheduling::Nice(10)));
+        assert_eq!(nice(254), Some(Scheduling::Nice(1)));
+        assert_eq!(nice(13), Some(Scheduling::Nice(19)));
+        assert_eq!(nice(14), Some(Scheduling::Nice(18)));
+    }
+
+    #[test]
+    fn fifo_priorities() {
+        let fifo = |priority, ceiling| Scheduling::of(priority, Some(ceiling));
+        assert_eq!(fifo(MAX_PRIORITY, 99), Some(Scheduling::Fifo(99)));
+        assert_eq!(fifo(MIN_PRIORITY, 99), Some(Scheduling::Fifo(1)));
+        assert_eq!(fifo(128, 99), Some(Scheduling::Fifo(50)));
+        assert_eq!(fifo(MAX_PRIORITY, 10), Some(Scheduling::Fifo(10)));
+        assert_eq!(fifo(254, 10), Some(Scheduling::Fifo(9)));
+        // A ceiling of one leaves no room for distinct priorities
+        assert_eq!(fifo(MAX_PRIORITY, 1), Some(Scheduling::Fifo(1)));
+    }
+
+    #[test]
+    fn out_of_bounds() {
+        for realtime in [None, Some(99)] {
+            assert_eq!(Scheduling::of(MIN_PRIORITY - 1, realtime), None);
+            assert_eq!(Scheduling::of(MAX_PRIORITY + 1, realtime), None);
+        }
+    }
+
+    #[test]
+    fn monotonic() {
+        for realtime in [None, Some(2), Some(50), Some(99)] {
+            let linux = |priority| match Scheduling::of(priority, realtime).unwrap() {
+                // A lower nice value is a higher priority
+                Scheduling::Nice(nice) => -nice,
+                Scheduling::Fifo(priority) => priority,
+            };
+            for priority in MIN_PRIORITY..MAX_PRIORITY {
+                assert!(
+                    linux(priority) <= linux(priority + 1),
+                    "priority {priority} with {realtime:?}"
+                );
+            }
+        }
+    }
+}
@@ -1,6 +1,7 @@
-use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread::Builder;
+use std::time::Instant;
 
 use a653rs::bindings::*;
 use a653rs::prelude::{ProcessAttribute, SystemTime};
@@ -11,23 +12,58 @@ use a653rs_linux_core::error::{
 };
 use a653rs_linux_core::partition::PartitionConstants;
 use anyhow::anyhow;
+use nix::errno::Errno;
 use nix::unistd::{gettid, Pid};
 
-use crate::{APERIODIC_PROCESS, PERIODIC_PROCESS};
+use crate::priority::Scheduling;
+use crate::time::Timeout;
+use crate::{futex, mutex, APERIODIC_PROCESS, CONSTANTS, PERIODIC_PROCESS};
 
 #[repr(C)]
 #[derive(Debug, Clone)]
 pub(crate) struct Process {
     id: i32,
     attr: ProcessAttribute,
+    /// The attributes as they were passed on creation, for the status
+    attributes: ApexProcessAttribute,
+    /// The current priority, which starts at the base priority
+    priority: Arc<AtomicI32>,
     activated: Arc<AtomicBool>,
     pid: Arc<AtomicI32>,
+    /// Whether the process is suspended (1) or not (0), a futex word
+    ///
+    /// Not behind a lock, which the process could hold while it is frozen.
+    suspended: Arc<AtomicU32>,
+    /// Whether the process was last started with a delay, which the
+    /// hypervisor holds its releases back by
+    delayed: Arc<AtomicBool>,
     periodic: bool,
     stack_size: usize,
 }
 
 impl Process {
-    pub fn create(attr: ProcessAttribute) -> LeveledResult<ProcessId> {
+    pub fn create(attributes: &ApexProcessAttribute) -> LeveledResult<ProcessId> {
+        let process = Self::new(attributes)?;
+        let (id, periodic) = (process.id, process.periodic);
+        let name = process.name()?.to_string();
+        let proc_file = if periodic {
+            &PERIODIC_PROCESS
+        } else {
+            &APERIODIC_PROCESS
+        };
+
+        if proc_file.try_insert(Arc::new(process)).is_ok() {
+            trace!("Created process \"{name}\" with id: {id}");
+            Ok(id as ProcessId)
+        } else {
+            Err(anyhow!("Process type already exists. Periodic: {periodic}"))
+                .lev_typ(SystemError::Panic, ErrorLevel::Partition)
+        }
+    }
+
+    /// A dormant process with `attributes`, which is not registered yet
+    fn new(attributes: &ApexProcessAttribute) -> LeveledResult<Self> {
+        let attr: ProcessAttribute = attributes.clone().into();
         let name = attr
             .name
             .to_str()
@@ -40,31 +76,38 @@ impl Process {
             .lev_typ(SystemError::Panic, ErrorLevel::Partition)?;
 
         let periodic = attr.period != SystemTime::Infinite;
-        let id = periodic as i32 + 1;
-
-        let proc_file = if periodic {
-            &PERIODIC_PROCESS
-        } else {
-            &APERIODIC_PROCESS
-        };
-
-        let res = proc_file.try_insert(Arc::new(Self {
-            id,
+        Ok(Self {
+            id: periodic as i32 + 1,
+            priority: Arc::new(AtomicI32::new(attributes.base_priority)),
+            attributes: attributes.clone(),
             attr,
             activated: Arc::new(AtomicBool::new(false)),
             pid: Arc::new(AtomicI32::new(0)),
+            suspended: Arc::new(AtomicU32::new(0)),
+            delayed: Arc::new(AtomicBool::new(false)),
             periodic,
             stack_size,
-        }));
-        if res.is_ok() {
-            trace!("Created process \"{name}\" with id: {id}");
-            Ok(id as ProcessId)
-        } else {
-            Err(anyhow!("Process type already exists. Periodic: {periodic}"))
-                .lev_typ(SystemError::Panic, ErrorLevel::Partition)
+        })
+    }
+
+    /// The process with the id `id`, if it was created
+    pub(crate) fn get(id: ProcessId) -> Option<Arc<Self>> {
+        match id {
+            1 => APERIODIC_PROCESS.get().cloned(),
+            2 => PERIODIC_PROCESS.get().cloned(),
+            _ => None,
         }
     }
 
+    /// The process named `name`, if it was created
+    pub(crate) fn by_name(name: &str) -> Option<Arc<Self>> {
+        [APERIODIC_PROCESS.get(), PERIODIC_PROCESS.get()]
+            .into_iter()
+            .flatten()
+            .find(|p| p.name().is_ok_and(|n| n == name))
+            .cloned()
+    }
+
     pub(crate) fn get_self() -> Option<Arc<Self>> {
         if let Some(p) = APERIODIC_PROCESS.get() {
             let id = p.pid.load(Ordering::SeqCst);
@@ -127,6 +170,9 @@ impl Process {
         let pid_raw = pid_rx.recv().unwrap();
         self.pid.store(pid_raw, Ordering::SeqCst);
         let pid = Pid::from_raw(pid_raw);
+        // Starting a process resets its priority to the base priority
+        self.set_priority(self.attributes.base_priority)
+            .lev_typ(SystemError::Panic, ErrorLevel::Partition)?;
         // Freeze thread by moving it to the cgroup
         cg.mv_thread(pid).unwrap();
         // Now unlock the `sync` mutex, so the thread can continue execution when the
@@ -142,11 +188,18 @@ impl Process {
         } else {
             PartitionConstants::APERIODIC_PROCESS_CGROUP
         };
+        Self::processes_cg(cg_name)
+    }
+
+    /// The frozen cgroup holding the threads of stopped and suspended
+    /// processes
+    fn held_cg() -> TypedResult<CGroup> {
+        Self::processes_cg(PartitionConstants::HELD_PROCESSES_CGROUP)
+    }
 
+    fn processes_cg(name: &str) -> TypedResult<CGroup> {
         let path = cgroup::mount_point().typ(SystemError::CGroup)?;
-        let path = path
-            .join(PartitionConstants::PROCESSES_CGROUP)
-            .join(cg_name);
+        let path = path.join(PartitionConstants::PROCESSES_CGROUP).join(name);
 
         CGroup::import_root(path).typ(SystemError::CGroup)
     }
@@ -154,4 +207,252 @@ impl Process {
     pub fn periodic(&self) -> bool {
         self.periodic
     }
+
+    pub(crate) fn id(&self) -> ProcessId {
+        self.id as ProcessId
+    }
+
+    pub(crate) fn attributes(&self) -> &ApexProcessAttribute {
+        &self.attributes
+    }
+
+    /// The thread of the process, once it is started
+    fn tid(&self) -> Option<Pid> {
+        match self.pid.load(Ordering::SeqCst) {
+            0 => None,
+            pid => Some(Pid::from_raw(pid)),
+        }
+    }
+
+    pub(crate) fn priority(&self) -> Priority {
+        self.priority.load(Ordering::SeqCst)
+    }
+
+    /// Changes the current priority of the process, and the scheduling of its
+    /// thread accordingly once it is started, see [crate::priority]
+    ///
+    /// Fails with `EINVAL` for priorities out of bounds, and with `EPERM` if
+    /// the partition is not allowed to raise the Linux priority of the thread
+    /// that far.
+    pub(crate) fn set_priority(&self, priority: Priority) -> nix::Result<()> {
+        let scheduling = Scheduling::of(priority, CONSTANTS.realtime).ok_or(Errno::EINVAL)?;
+        if let Some(tid) = self.tid() {
+            scheduling.apply(tid)?;
+        }
+        self.priority.store(priority, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Stops the process, which becomes dormant until it is started again
+    ///
+    /// Its thread can not be killed on its own, so it is moved to the frozen
+    /// cgroup of held threads, where it stays until the partition restarts.
+    /// The mutex it owns is reset for the processes waiting for it. Starting
+    /// the process again starts a new thread at its entry point.
+    pub(crate) fn stop(&self) -> TypedResult<()> {
+        if let Some(tid) = self.tid() {
+            Self::held_cg()?.mv_thread(tid).typ(SystemError::CGroup)?;
+        }
+        self.set_dormant();
+        Ok(())
+    }
+
+    /// Stops the calling process, see [Process::stop]
+    ///
+    /// The periodic process leaves its cgroup empty, which ends its share of
+    /// the window like a periodic wait.
+    pub(crate) fn stop_self(&self) -> ! {
+        self.set_dormant();
+        if let Err(e) =
+            Self::held_cg().and_then(|cg| cg.mv_thread(gettid()).typ(SystemError::CGroup))
+        {
+            warn!("could not hold the thread of the stopped process, parking it instead: {e}");
+        }
+        // Moving into a frozen cgroup freezes the thread only on its way back
+        // from the kernel
+        loop {
+            std::thread::park()
+        }
+    }
+
+    fn set_dormant(&self) {
+        self.pid.store(0, Ordering::SeqCst);
+        if let Err(e) = mutex::reset_owned_by(self.id()) {
+            warn!("could not reset the mutex of the stopped process: {e:?}");
+        }
+        self.suspended.store(0, Ordering::SeqCst);
+        futex::wake(&self.suspended);
+    }
+
+    /// Suspends another process until it is resumed, returning false if it
+    /// was suspended already
+    ///
+    /// Its thread is held in the frozen cgroup meanwhile, so that it does not
+    /// run at all.
+    pub(crate) fn suspend(&self) -> TypedResult<bool> {
+        if self
+            .suspended
+            .compare_exchange(0, 1, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            return Ok(false);
+        }
+        if let Some(tid) = self.tid() {
+            if let Err(e) =
+                Self::held_cg().and_then(|cg| cg.mv_thread(tid).typ(SystemError::CGroup))
+            {
+                self.suspended.store(0, Ordering::SeqCst);
+                return Err(e);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Suspends the calling process until it is resumed or the `timeout`
+    /// expired, returning false in the latter case
+    ///
+    /// The process sleeps meanwhile. Like the other timeouts, this one also
+    /// expires while the partition is frozen.
+    pub(crate) fn suspend_self(&self, timeout: Timeout) -> bool {
+        // A deadline too far in the future is the same as no deadline at all
+        let deadline = timeout
+            .duration()
+            .and_then(|d| Instant::now().checked_add(d));
+        self.suspended.store(1, Ordering::SeqCst);
+        loop {
+            if self.suspended.load(Ordering::SeqCst) == 0 {
+                return true;
+            }
+            let remaining = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        // Resumed right at the deadline otherwise
+                        return self.suspended.swap(0, Ordering::SeqCst) == 0;
+                    }
+                    Some(remaining)
+                }
+                None => None,
+            };
+            futex::wait(&self.suspended, 1, remaining);
+        }
+    }
+
+    /// Resumes the suspended process, returning false if it was not
+    /// suspended
+    pub(crate) fn resume(&self) -> TypedResult<bool> {
+        if !self.suspended() {
+            return Ok(false);
+        }
+        // A process which suspended itself is in its cgroup already
+        if let Some(tid) = self.tid() {
+            self.cg()?.mv_thread(tid).typ(SystemError::CGroup)?;
+        }
+        self.suspended.store(0, Ordering::SeqCst);
+        futex::wake(&self.suspended);
+        Ok(true)
+    }
+
+    /// Records whether the process is started with a delay, returning
+    /// whether the hypervisor has to be told about it
+    ///
+    /// It does not, unless this or the previous start was delayed.
+    pub(crate) fn set_delayed(&self, delayed: bool) -> bool {
+        self.delayed.swap(delayed, Ordering::SeqCst) || delayed
+    }
+
+    /// Whether the process is suspended
+    pub(crate) fn suspended(&self) -> bool {
+        self.suspended.load(Ordering::SeqCst) == 1
+    }
+
+    /// The state of the process, as far as the partition knows it
+    ///
+    /// A started process is waiting while it is suspended or its cgroup is
+    /// frozen, i.e. before the partition enters NORMAL and, for the periodic
+    /// process, until its next release. A stopped process is dormant again.
+    pub(crate) fn state(&self) -> ProcessState {
+        match self.tid() {
+            None => ProcessState::Dormant,
+            Some(tid) if tid == gettid() => ProcessState::Running,
+            Some(_) if self.suspended() => ProcessState::Waiting,
+            Some(_) if self.cg().is_ok_and(|cg| cg.frozen().unwrap_or(false)) => {
+                ProcessState::Waiting
+            }
+            Some(_) => ProcessState::Ready,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    use a653rs::prelude::{Deadline, Name};
+
+    use super::*;
+
+    extern "C" fn entry() {}
+
+    fn attributes(name: &str, period: SystemTime) -> ApexProcessAttribute {
+        ProcessAttribute {
+            period,
+            time_capacity: SystemTime::Infinite,
+            entry_point: entry,
+            stack_size: 100_000,
+            base_priority: 7,
+            deadline: Deadline::Soft,
+            name: Name::from_str(name).unwrap(),
+        }
+        .into()
+    }
+
+    #[test]
+    fn attributes_of_new_processes() {
+        let periodic = Process::new(&attributes(
+            "Periodic",
+            SystemTime::Normal(Duration::from_millis(100)),
+        ))
+        .unwrap();
+        let aperiodic = Process::new(&attributes("Aperiodic", SystemTime::Infinite)).unwrap();
+        assert_eq!((aperiodic.id(), periodic.id()), (1, 2));
+        assert_eq!(periodic.name().unwrap(), "Periodic");
+
+        // The attributes are the ones given on creation
+        assert_eq!(periodic.attributes().period, 100_000_000);
+        assert_eq!(aperiodic.attributes().period, -1);
+        for process in [periodic, aperiodic] {
+            assert_eq!(process.priority(), 7);
+            assert_eq!(process.attributes().base_priority, 7);
+            // Started by the partition only, see the process_lifecycle example
+            assert!(matches!(process.state(), ProcessState::Dormant));
+        }
+    }
+
+    #[test]
+    fn suspended_until_resumed_or_timed_out() {
+        let process = Process::new(&attributes("Worker", SystemTime::Infinite)).unwrap();
+        assert!(!process.resume().unwrap());
+
+        assert!(!process.suspend_self(Timeout::Finite(Duration::from_millis(10))));
+        assert!(!process.suspended());
+
+        let resumer = process.clone();
+        let resumer = std::thread::spawn(move || {
+            // Resuming only succeeds once the process suspended itself
+            while !resumer.resume().unwrap() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        });
+        assert!(process.suspend_self(Timeout::Infinite));
+        resumer.join().unwrap();
+        assert!(!process.suspended());
+
+        // Stopping a process which was never started only ends its suspension
+        process.suspended.store(1, Ordering::SeqCst);
+        process.stop().unwrap();
+        assert!(!process.suspended());
+        assert!(matches!(process.state(), ProcessState::Dormant));
+    }
 }
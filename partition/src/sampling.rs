@@ -0,0 +1,238 @@
+//! Coalescing of repeated writes to sampling ports
+//!
+//! A sampling port only holds its latest message. The hypervisor copies it to
+//! the destinations after the window of the partition, while the partition is
+//! frozen, so of several writes within a window only the last one is ever
+//! seen by other partitions. Still, every write copies the whole message into
+//! the shared memory of the channel.
+//!
+//! A [CoalescedSamplingSource] keeps the message in a local staging buffer
+//! instead, and copies it into the shared memory once per window: on
+//! [CoalescedSamplingSource::flush], or right before `periodic_wait()` hands
+//! the window back to the hypervisor.
+//!
+//! Other partitions observe the same messages as with plain writes, as long
+//! as the last write of a window is followed by a flush within that window.
+//! This is always the case for a periodic process calling `periodic_wait()`.
+//! Writes of the aperiodic process, or of a periodic process overrunning its
+//! window, are only published by the next flush. The message is stamped with
+//! the time of the flush rather than that of the write.
+
+use std::sync::Mutex;
+
+use a653rs::bindings::{
+    ApexByte, ApexSamplingPortP4, ErrorReturnCode, MessageSize, SamplingPortId,
+};
+use a653rs::prelude::ConstSamplingPortSource;
+
+use crate::partition::ApexLinuxPartition;
+
+/// Staging buffers of all coalesced ports of this partition
+static STAGED: Mutex<Vec<Staged>> = Mutex::new(Vec::new());
+
+#[derive(Debug)]
+struct Staged {
+    id: SamplingPortId,
+    message: Vec<ApexByte>,
+    /// Whether `message` was written since it was last flushed
+    dirty: bool,
+}
+
+/// Sampling source port which publishes only the last message written within
+/// a window
+///
+/// Wrappers of the same port share their staging buffer.
+#[derive(Debug)]
+pub struct CoalescedSamplingSource<const MSG_SIZE: MessageSize> {
+    id: SamplingPortId,
+}
+
+impl<const MSG_SIZE: MessageSize> CoalescedSamplingSource<MSG_SIZE> {
+    /// Stages the writes to `port`
+    pub fn new(port: &ConstSamplingPortSource<MSG_SIZE, ApexLinuxPartition>) -> Self {
+        let id = port.id();
+        register(id, MSG_SIZE);
+        Self { id }
+    }
+
+    /// Replaces the staged message
+    ///
+    /// Fails like a plain write for empty messages and messages exceeding
+    /// the size of the port.
+    pub fn write(&self, message: &[ApexByte]) -> Result<(), ErrorReturnCode> {
+        check_message(message, MSG_SIZE)?;
+        stage(&mut STAGED.lock().unwrap(), self.id, message);
+        Ok(())
+    }
+
+    /// Copies the staged message into the port, if it was written since the
+    /// last flush
+    pub fn flush(&self) -> Result<(), ErrorReturnCode> {
+        flush_ports(&[self.id])
+    }
+}
+
+/// Creates the staging buffer of the port `id` with messages of up to
+/// `msg_size` bytes, unless it exists already
+pub(crate) fn register(id: SamplingPortId, msg_size: MessageSize) {
+    let mut staged = STAGED.lock().unwrap();
+    if !staged.iter().any(|s| s.id == id) {
+        staged.push(Staged {
+            id,
+            message: Vec::with_capacity(msg_size as usize),
+            dirty: false,
+        });
+    }
+}
+
+/// Checks `message` like a plain write to a port of `msg_size` would
+pub(crate) fn check_message(
+    message: &[ApexByte],
+    msg_size: MessageSize,
+) -> Result<(), ErrorReturnCode> {
+    if message.len() > msg_size as usize {
+        Err(ErrorReturnCode::InvalidConfig)
+    } else if message.is_empty() {
+        Err(ErrorReturnCode::InvalidParam)
+    } else {
+        Ok(())
+    }
+}
+
+/// Stages the messages of several registered ports at once, so that no flush
+/// publishes only some of them
+pub(crate) fn stage_messages(messages: &[(SamplingPortId, &[ApexByte])]) {
+    let mut staged = STAGED.lock().unwrap();
+    for (id, message) in messages {
+        stage(&mut staged, *id, message);
+    }
+}
+
+/// Flushes the staged messages of `ports`
+pub(crate) fn flush_ports(ports: &[SamplingPortId]) -> Result<(), ErrorReturnCode> {
+    flush(
+        &mut STAGED.lock().unwrap(),
+        Some(ports),
+        ApexLinuxPartition::write_sampling_message,
+    )
+}
+
+/// Flushes the staged messages of all coalesced ports
+pub(crate) fn flush_all() -> Result<(), ErrorReturnCode> {
+    flush(
+        &mut STAGED.lock().unwrap(),
+        None,
+        ApexLinuxPartition::write_sampling_message,
+    )
+}
+
+fn stage(staged: &mut [Staged], id: SamplingPortId, message: &[ApexByte]) {
+    let staged = staged
+        .iter_mut()
+        .find(|s| s.id == id)
+        .expect("port to be registered on construction");
+    staged.message.clear();
+    staged.message.extend_from_slice(message);
+    staged.dirty = true;
+}
+
+/// Writes the staged messages of `ports`, or of all ports, with `write`
+fn flush(
+    staged: &mut [Staged],
+    ports: Option<&[SamplingPortId]>,
+    mut write: impl FnMut(SamplingPortId, &[ApexByte]) -> Result<(), ErrorReturnCode>,
+) -> Result<(), ErrorReturnCode> {
+    for staged in staged
+        .iter_mut()
+        .filter(|s| s.dirty && ports.is_none_or(|ports| ports.contains(&s.id)))
+    {
+        write(staged.id, &staged.message)?;
+        staged.dirty = false;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ports(ids: &[SamplingPortId]) -> Vec<Staged> {
+        ids.iter()
+            .map(|&id| Staged {
+                id,
+                message: Vec::new(),
+                dirty: false,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn only_last_write_is_flushed() {
+        let mut staged = ports(&[1, 2]);
+        for i in 0..50u8 {
+            stage(&mut staged, 1, &[i; 4]);
+        }
+        stage(&mut staged, 2, b"other");
+
+        let mut written = Vec::new();
+        flush(&mut staged, Some(&[1]), |id, msg| {
+            written.push((id, msg.to_vec()));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(written, [(1, vec![49; 4])]);
+
+        // Port 1 is clean, port 2 is still pending
+        written.clear();
+        flush(&mut staged, None, |id, msg| {
+            written.push((id, msg.to_vec()));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(written, [(2, b"other".to_vec())]);
+
+        flush(&mut staged, None, |_, _| panic!("nothing was written")).unwrap();
+    }
+
+    #[test]
+    fn flush_of_several_ports() {
+        let mut staged = ports(&[1, 2, 3]);
+        for id in 1..=3 {
+            stage(&mut staged, id, &[id as u8]);
+        }
+
+        let mut written = Vec::new();
+        flush(&mut staged, Some(&[3, 1]), |id, msg| {
+            written.push((id, msg.to_vec()));
+            Ok(())
+        })
+        .unwrap();
+        // In the order of registration
+        assert_eq!(written, [(1, vec![1]), (3, vec![3])]);
+        assert!(staged[1].dirty);
+    }
+
+    #[test]
+    fn messages_are_checked_like_plain_writes() {
+        assert!(check_message(b"msg", 3).is_ok());
+        assert!(matches!(
+            check_message(b"msg", 2),
+            Err(ErrorReturnCode::InvalidConfig)
+        ));
+        assert!(matches!(
+            check_message(b"", 2),
+            Err(ErrorReturnCode::InvalidParam)
+        ));
+    }
+
+    #[test]
+    fn failed_flush_stays_pending() {
+        let mut staged = ports(&[1]);
+        stage(&mut staged, 1, b"msg");
+        assert!(matches!(
+            flush(&mut staged, None, |_, _| Err(ErrorReturnCode::InvalidMode)),
+            Err(ErrorReturnCode::InvalidMode)
+        ));
+        assert!(staged[0].dirty);
+    }
+}
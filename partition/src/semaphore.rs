@@ -0,0 +1,382 @@
+//! Counting semaphores for the synchronization of the processes of a
+//! partition
+//!
+//! Each semaphore lives in a memfd of its own, and the semaphores created so
+//! far are listed in a registry like the blackboards, see
+//! [crate::SEMAPHORES]. Helper processes do not inherit the semaphores.
+//!
+//! Processes waiting for a semaphore queue up in slots behind its value, in
+//! the order of the tickets they draw. A signal hands the semaphore to the
+//! first of them directly, instead of raising the value, so that no process
+//! overtakes one waiting longer. Each waiting process sleeps on a futex of its
+//! own slot, see [crate::futex], and leaves its slot again if its timeout
+//! expires first. A timeout expiring while the partition is frozen is
+//! reported as soon as the process runs in the next window.
+//!
+//! The value and the queue are only changed under a lock word, which is held
+//! for a few instructions and never while sleeping. The processes of a
+//! partition are frozen together at the end of its window, so a process frozen
+//! with the lock held keeps the others only until the next window.
+
+use std::ffi::CString;
+use std::fs::File;
+use std::mem::{size_of, ManuallyDrop};
+use std::os::fd::{FromRawFd, IntoRawFd, RawFd};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use a653rs::bindings::{ErrorReturnCode, SemaphoreId, SemaphoreValue};
+use a653rs_linux_core::error::{ResultExt, SystemError, TypedResult};
+use anyhow::anyhow;
+use memmap2::MmapMut;
+use once_cell::sync::Lazy;
+
+use crate::time::Timeout;
+use crate::{futex, SEMAPHORES};
+
+/// Largest maximum value of a semaphore, `MAX_SEMAPHORE_VALUE` of ARINC 653
+pub(crate) const MAX_VALUE: SemaphoreValue = 32767;
+
+/// Processes which may wait for a semaphore at the same time
+const MAX_WAITERS: usize = 32;
+
+/// The semaphores mapped into this process, in the order of [SEMAPHORES]
+static MAPPED: Lazy<Mutex<Vec<Arc<Semaphore>>>> = Lazy::new(Default::default);
+
+/// Start of the shared memory of a semaphore, which is followed by the slots
+/// of the waiting processes
+///
+/// A new memfd is filled with zeros, which is an unlocked semaphore without
+/// waiting processes.
+#[repr(C)]
+struct Header {
+    /// Ticket drawn by the latest waiting process
+    ticket: AtomicU64,
+    /// One while the semaphore is changed, see [Semaphore::locked]
+    lock: AtomicU32,
+    value: AtomicU32,
+    max: AtomicU32,
+    /// Processes in the queue which were not handed the semaphore yet
+    waiting: AtomicU32,
+}
+
+/// Slot of a process waiting for a semaphore
+#[repr(C)]
+struct Waiter {
+    /// Position of the process in the queue, zero if the slot is free
+    ticket: AtomicU64,
+    /// Set to one once the semaphore was handed to the process, which sleeps
+    /// on it meanwhile
+    granted: AtomicU32,
+}
+
+/// Outcome of the first attempt to take a semaphore
+enum Attempt<'a> {
+    Taken,
+    Unavailable,
+    Queued(&'a Waiter),
+}
+
+/// A semaphore mapped into this process
+#[derive(Debug)]
+pub(crate) struct Semaphore {
+    mmap: MmapMut,
+}
+
+impl Semaphore {
+    const SIZE: usize = size_of::<Header>() + MAX_WAITERS * size_of::<Waiter>();
+
+    /// Creates the memfd of a semaphore with the value `value`, which may be
+    /// raised up to `max`, returning its fd
+    pub(crate) fn create(name: &str, value: u32, max: u32) -> TypedResult<RawFd> {
+        let name = CString::new(format!("semaphore_{name}")).typ(SystemError::Panic)?;
+        // Not close-on-exec, like the other files of the partition
+        let fd = unsafe { libc::memfd_create(name.as_ptr(), 0) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error()).typ(SystemError::Panic);
+        }
+        let file = unsafe { File::from_raw_fd(fd) };
+        file.set_len(Self::SIZE as u64).typ(SystemError::Panic)?;
+        let fd = file.into_raw_fd();
+
+        let semaphore = Self::open(fd)?;
+        semaphore.header().value.store(value, Ordering::SeqCst);
+        semaphore.header().max.store(max, Ordering::SeqCst);
+        Ok(fd)
+    }
+
+    /// Maps the semaphore in the memfd `fd`
+    pub(crate) fn open(fd: RawFd) -> TypedResult<Self> {
+        // The fd stays open for later mappings
+        let file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+        let mmap = unsafe { MmapMut::map_mut(&*file) }.typ(SystemError::Panic)?;
+        if mmap.len() < Self::SIZE {
+            return Err(anyhow!(
+                "semaphore of {} bytes is smaller than {} bytes",
+                mmap.len(),
+                Self::SIZE
+            ))
+            .typ(SystemError::Panic);
+        }
+        Ok(Self { mmap })
+    }
+
+    fn header(&self) -> &Header {
+        // The mapping is page aligned and large enough, see Semaphore::open
+        unsafe { &*(self.mmap.as_ptr() as *const Header) }
+    }
+
+    fn waiters(&self) -> &[Waiter] {
+        unsafe {
+            std::slice::from_raw_parts(
+                self.mmap.as_ptr().add(size_of::<Header>()) as *const Waiter,
+                MAX_WAITERS,
+            )
+        }
+    }
+
+    pub(crate) fn value(&self) -> u32 {
+        self.header().value.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn max(&self) -> u32 {
+        self.header().max.load(Ordering::SeqCst)
+    }
+
+    /// Number of processes waiting for the semaphore
+    pub(crate) fn waiting(&self) -> u32 {
+        self.header().waiting.load(Ordering::SeqCst)
+    }
+
+    /// Calls `f` while holding the lock of the semaphore
+    fn locked<'a, T>(&'a self, f: impl FnOnce(&'a Header, &'a [Waiter]) -> T) -> T {
+        let header = self.header();
+        while header
+            .lock
+            .compare_exchange_weak(0, 1, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            std::thread::yield_now();
+        }
+        let t = f(header, self.waiters());
+        header.lock.store(0, Ordering::Release);
+        t
+    }
+
+    /// Takes the semaphore, waiting up to `timeout` behind the processes
+    /// already waiting for it
+    ///
+    /// Returns whether the semaphore was taken.
+    pub(crate) fn wait(&self, timeout: Timeout) -> bool {
+        // A deadline too far in the future is the same as no deadline at all
+        let deadline = timeout
+            .duration()
+            .and_then(|d| Instant::now().checked_add(d));
+        let attempt = self.locked(|header, waiters| {
+            // Signals hand the semaphore to waiting processes, so there are none while the
+            // value is positive
+            let value = header.value.load(Ordering::Relaxed);
+            if value > 0 {
+                header.value.store(value - 1, Ordering::Relaxed);
+                return Attempt::Taken;
+            }
+            if timeout == Timeout::Immediate {
+                return Attempt::Unavailable;
+            }
+            let Some(waiter) = waiters
+                .iter()
+                .find(|w| w.ticket.load(Ordering::Relaxed) == 0)
+            else {
+                warn!("more than {MAX_WAITERS} processes are waiting for a semaphore");
+                return Attempt::Unavailable;
+            };
+            let ticket = header.ticket.load(Ordering::Relaxed) + 1;
+            header.ticket.store(ticket, Ordering::Relaxed);
+            waiter.granted.store(0, Ordering::Relaxed);
+            waiter.ticket.store(ticket, Ordering::Relaxed);
+            header.waiting.fetch_add(1, Ordering::Relaxed);
+            Attempt::Queued(waiter)
+        });
+        let waiter = match attempt {
+            Attempt::Taken => return true,
+            Attempt::Unavailable => return false,
+            Attempt::Queued(waiter) => waiter,
+        };
+
+        while waiter.granted.load(Ordering::Acquire) == 0 {
+            let remaining = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        break;
+                    }
+                    Some(remaining)
+                }
+                None => None,
+            };
+            futex::wait(&waiter.granted, 0, remaining);
+        }
+
+        // Leaves the queue, unless the semaphore was handed over since the timeout
+        // expired
+        self.locked(|header, _| {
+            let granted = waiter.granted.load(Ordering::Relaxed) == 1;
+            if !granted {
+                header.waiting.fetch_sub(1, Ordering::Relaxed);
+            }
+            waiter.ticket.store(0, Ordering::Relaxed);
+            granted
+        })
+    }
+
+    /// Hands the semaphore to the process waiting longest for it, or raises
+    /// its value if none is waiting
+    ///
+    /// Returns `false` if the value is already at its maximum.
+    pub(crate) fn signal(&self) -> bool {
+        self.locked(|header, waiters| {
+            let first = waiters
+                .iter()
+                .filter(|w| {
+                    w.ticket.load(Ordering::Relaxed) != 0 && w.granted.load(Ordering::Relaxed) == 0
+                })
+                .min_by_key(|w| w.ticket.load(Ordering::Relaxed));
+            if let Some(waiter) = first {
+                waiter.granted.store(1, Ordering::Release);
+                header.waiting.fetch_sub(1, Ordering::Relaxed);
+                futex::wake(&waiter.granted);
+                return true;
+            }
+
+            let value = header.value.load(Ordering::Relaxed);
+            if value >= header.max.load(Ordering::Relaxed) {
+                return false;
+            }
+            header.value.store(value + 1, Ordering::Relaxed);
+            true
+        })
+    }
+}
+
+/// The semaphore with the id `id`, mapping it into this process first if
+/// necessary
+///
+/// Yields [ErrorReturnCode::InvalidParam] if no such semaphore was created.
+pub(crate) fn get(id: SemaphoreId) -> Result<Arc<Semaphore>, ErrorReturnCode> {
+    // Semaphore ids start at one
+    let index = usize::try_from(id)
+        .ok()
+        .and_then(|id| id.checked_sub(1))
+        .ok_or(ErrorReturnCode::InvalidParam)?;
+    let mut mapped = MAPPED.lock().unwrap();
+    if let Some(semaphore) = mapped.get(index) {
+        return Ok(semaphore.clone());
+    }
+
+    let created = SEMAPHORES
+        .read()
+        .map_err(|_| ErrorReturnCode::NotAvailable)?;
+    if index >= created.len() {
+        return Err(ErrorReturnCode::InvalidParam);
+    }
+    for (_, fd) in created[mapped.len()..=index].iter() {
+        let semaphore = Semaphore::open(*fd).map_err(|e| {
+            trace!("yielding NotAvailable, because semaphore could not be mapped: {e}");
+            ErrorReturnCode::NotAvailable
+        })?;
+        mapped.push(Arc::new(semaphore));
+    }
+    Ok(mapped[index].clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn semaphore(value: u32, max: u32) -> Semaphore {
+        let fd = Semaphore::create("test", value, max).unwrap();
+        Semaphore::open(fd).unwrap()
+    }
+
+    /// Waits until `n` processes wait for `semaphore`
+    fn until_waiting(semaphore: &Semaphore, n: u32) {
+        while semaphore.waiting() != n {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn counting() {
+        let semaphore = semaphore(1, 2);
+        assert_eq!((semaphore.value(), semaphore.max()), (1, 2));
+        assert!(semaphore.wait(Timeout::Immediate));
+        assert!(!semaphore.wait(Timeout::Immediate));
+        assert_eq!(semaphore.value(), 0);
+
+        assert!(semaphore.signal());
+        assert!(semaphore.signal());
+        // The value is at its maximum
+        assert!(!semaphore.signal());
+        assert_eq!(semaphore.value(), 2);
+
+        assert!(semaphore.wait(Timeout::Infinite));
+        assert!(semaphore.wait(Timeout::Finite(Duration::from_secs(1))));
+        assert_eq!(semaphore.value(), 0);
+        assert_eq!(semaphore.waiting(), 0);
+    }
+
+    #[test]
+    fn timeout_expiry() {
+        let semaphore = semaphore(0, 1);
+        let start = Instant::now();
+        let timeout = Duration::from_millis(20);
+        assert!(!semaphore.wait(Timeout::Finite(timeout)));
+        assert!(start.elapsed() >= timeout);
+        assert_eq!(semaphore.waiting(), 0);
+        assert!(semaphore
+            .waiters()
+            .iter()
+            .all(|w| w.ticket.load(Ordering::SeqCst) == 0));
+
+        // The expired wait does not take a later signal
+        assert!(semaphore.signal());
+        assert_eq!(semaphore.value(), 1);
+    }
+
+    #[test]
+    fn fifo_wakeup() {
+        let fd = Semaphore::create("fifo", 0, 1).unwrap();
+        let semaphore = Semaphore::open(fd).unwrap();
+        let (tx, rx) = mpsc::channel();
+
+        let waiters: Vec<_> = (0..3)
+            .map(|i| {
+                let tx = tx.clone();
+                let waiter = thread::spawn(move || {
+                    let semaphore = Semaphore::open(fd).unwrap();
+                    assert!(semaphore.wait(Timeout::Infinite));
+                    tx.send(i).unwrap();
+                });
+                // Queued one after the other
+                until_waiting(&semaphore, i + 1);
+                waiter
+            })
+            .collect();
+
+        for i in 0..3 {
+            assert!(semaphore.signal());
+            assert_eq!(rx.recv().unwrap(), i);
+            assert_eq!(semaphore.waiting(), 2 - i);
+        }
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+        // Every signal was handed over
+        assert_eq!(semaphore.value(), 0);
+    }
+}
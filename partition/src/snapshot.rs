@@ -0,0 +1,229 @@
+//! Coherent snapshots of several sampling ports
+//!
+//! Related values spread over several sampling ports may be read as a mixture
+//! of old and new messages, if each port is written on its own. A
+//! [SnapshotPublisher] stages the messages of a group of source ports like a
+//! [CoalescedSamplingSource](crate::sampling::CoalescedSamplingSource), and
+//! copies all of them into their ports together: on
+//! [SnapshotPublisher::publish], or right before `periodic_wait()` hands the
+//! window back to the hypervisor.
+//!
+//! The hypervisor swaps all sampling channels of a partition sharing their
+//! `transfer` setting in a single pass, while the partition is frozen. The
+//! destinations thus see the messages of a group published within a window
+//! together. A publish cut short by the end of the window, e.g. of a process
+//! overrunning it, may still transfer only part of a group.
+//!
+//! A [SnapshotReader] reads a group of destination ports and tells whether
+//! their messages were published together. This needs the channels of the
+//! group to be `sequenced` and to be written by the [SnapshotPublisher]
+//! alone, so that their sequence numbers advance in lockstep.
+
+use a653rs::bindings::{ApexByte, ErrorReturnCode, MessageSize, SamplingPortId, Validity};
+use a653rs::prelude::{ConstSamplingPortDestination, ConstSamplingPortSource};
+
+use crate::partition::ApexLinuxPartition;
+use crate::sampling;
+
+/// Group of sampling source ports whose messages are published together
+#[derive(Debug, Default)]
+pub struct SnapshotPublisher {
+    ids: Vec<SamplingPortId>,
+    msg_sizes: Vec<MessageSize>,
+}
+
+impl SnapshotPublisher {
+    /// Creates an empty group
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `port` as the next member of the group
+    ///
+    /// The port shares its staging buffer with any
+    /// [CoalescedSamplingSource](crate::sampling::CoalescedSamplingSource) of
+    /// it, whose writes would break the coherence of the group.
+    pub fn add<const MSG_SIZE: MessageSize>(
+        &mut self,
+        port: &ConstSamplingPortSource<MSG_SIZE, ApexLinuxPartition>,
+    ) {
+        sampling::register(port.id(), MSG_SIZE);
+        self.ids.push(port.id());
+        self.msg_sizes.push(MSG_SIZE);
+    }
+
+    /// Stages a message for every member of the group, in the order they were
+    /// added
+    ///
+    /// Fails with [ErrorReturnCode::InvalidParam] if the number of messages
+    /// does not match the group, and like a plain write for empty messages and
+    /// messages exceeding the size of their port. Nothing is staged then.
+    pub fn write(&self, messages: &[&[ApexByte]]) -> Result<(), ErrorReturnCode> {
+        if messages.len() != self.ids.len() {
+            return Err(ErrorReturnCode::InvalidParam);
+        }
+        for (message, &msg_size) in messages.iter().zip(&self.msg_sizes) {
+            sampling::check_message(message, msg_size)?;
+        }
+        let staged = self
+            .ids
+            .iter()
+            .copied()
+            .zip(messages.iter().copied())
+            .collect::<Vec<_>>();
+        sampling::stage_messages(&staged);
+        Ok(())
+    }
+
+    /// Copies the staged messages into their ports, if they were written since
+    /// the last publish
+    pub fn publish(&self) -> Result<(), ErrorReturnCode> {
+        sampling::flush_ports(&self.ids)
+    }
+}
+
+/// Group of sampling destination ports which are read together
+#[derive(Debug, Default)]
+pub struct SnapshotReader {
+    ids: Vec<SamplingPortId>,
+}
+
+/// The messages of a group read by [SnapshotReader::read]
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    /// Validity and length of the message of every member
+    pub messages: Vec<(Validity, MessageSize)>,
+    /// Sequence number of the message of every member
+    pub seqs: Vec<u64>,
+}
+
+impl Snapshot {
+    /// Whether all messages carry the same sequence number, i.e. were
+    /// published together
+    pub fn coherent(&self) -> bool {
+        self.seqs.windows(2).all(|pair| pair[0] == pair[1])
+    }
+}
+
+impl SnapshotReader {
+    /// Creates an empty group
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `port` as the next member of the group
+    pub fn add<const MSG_SIZE: MessageSize>(
+        &mut self,
+        port: &ConstSamplingPortDestination<MSG_SIZE, ApexLinuxPartition>,
+    ) {
+        self.ids.push(port.id());
+    }
+
+    /// Reads the message of every member into the buffer of the same index
+    ///
+    /// Fails with [ErrorReturnCode::InvalidParam] if the number of buffers
+    /// does not match the group, with [ErrorReturnCode::InvalidConfig] if a
+    /// channel is not `sequenced`, and like a plain read otherwise, e.g. with
+    /// [ErrorReturnCode::NoAction] while a member never received a message.
+    pub fn read(&self, bufs: &mut [&mut [ApexByte]]) -> Result<Snapshot, ErrorReturnCode> {
+        read(&self.ids, bufs, ApexLinuxPartition::receive_with_seq)
+    }
+}
+
+/// Reads the messages of the ports `ids` into `bufs` with `receive`
+fn read(
+    ids: &[SamplingPortId],
+    bufs: &mut [&mut [ApexByte]],
+    mut receive: impl FnMut(
+        SamplingPortId,
+        &mut [ApexByte],
+    ) -> Result<(Validity, MessageSize, Option<u64>), ErrorReturnCode>,
+) -> Result<Snapshot, ErrorReturnCode> {
+    if bufs.len() != ids.len() {
+        return Err(ErrorReturnCode::InvalidParam);
+    }
+    let mut snapshot = Snapshot {
+        messages: Vec::with_capacity(ids.len()),
+        seqs: Vec::with_capacity(ids.len()),
+    };
+    for (&id, buf) in ids.iter().zip(bufs.iter_mut()) {
+        let (validity, len, seq) = receive(id, &mut buf[..])?;
+        snapshot.messages.push((validity, len));
+        snapshot
+            .seqs
+            .push(seq.ok_or(ErrorReturnCode::InvalidConfig)?);
+    }
+    Ok(snapshot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Receives from ports whose sequence number is given by `seqs`
+    fn read_seqs(seqs: &[Option<u64>]) -> Result<Snapshot, ErrorReturnCode> {
+        let ids = (1..=seqs.len() as SamplingPortId).collect::<Vec<_>>();
+        let mut bufs = vec![[0; 8]; seqs.len()];
+        let mut bufs = bufs.iter_mut().map(|b| &mut b[..]).collect::<Vec<_>>();
+        read(&ids, &mut bufs, |id, buf| {
+            buf[0] = id as u8;
+            Ok((Validity::Valid, 1, seqs[id as usize - 1]))
+        })
+    }
+
+    #[test]
+    fn coherent_snapshots() {
+        let snapshot = read_seqs(&[Some(7), Some(7), Some(7)]).unwrap();
+        assert!(snapshot.coherent());
+        assert_eq!(snapshot.seqs, [7, 7, 7]);
+        assert_eq!(snapshot.messages.len(), 3);
+
+        let snapshot = read_seqs(&[Some(7), Some(6), Some(7)]).unwrap();
+        assert!(!snapshot.coherent());
+
+        assert!(read_seqs(&[]).unwrap().coherent());
+    }
+
+    #[test]
+    fn unsequenced_channels_are_errors() {
+        assert!(matches!(
+            read_seqs(&[Some(1), None]),
+            Err(ErrorReturnCode::InvalidConfig)
+        ));
+    }
+
+    #[test]
+    fn failed_reads() {
+        let mut buf = [0; 8];
+        assert!(matches!(
+            read(&[1, 2], &mut [&mut buf[..]], |_, _| unreachable!()),
+            Err(ErrorReturnCode::InvalidParam)
+        ));
+        assert!(matches!(
+            read(&[1], &mut [&mut buf[..]], |_, _| Err(
+                ErrorReturnCode::NoAction
+            )),
+            Err(ErrorReturnCode::NoAction)
+        ));
+    }
+
+    #[test]
+    fn invalid_writes_stage_nothing() {
+        let publisher = SnapshotPublisher {
+            ids: vec![1, 2],
+            msg_sizes: vec![4, 4],
+        };
+        assert!(matches!(
+            publisher.write(&[b"ab"]),
+            Err(ErrorReturnCode::InvalidParam)
+        ));
+        assert!(matches!(
+            publisher.write(&[b"ab", b"abcde"]),
+            Err(ErrorReturnCode::InvalidConfig)
+        ));
+        assert!(matches!(
+            publisher.write(&[b"", b"ab"]),
+            Err(ErrorReturnCode::InvalidParam)
+        ));
+    }
+}
@@ -0,0 +1,259 @@
+//! Conversion of [ApexSystemTime] values used by the APEX services
+//!
+//! All services interpret time values through this module, so that the edge
+//! encodings are treated the same everywhere:
+//! - A negative value is [SystemTime::Infinite].
+//! - A timeout of zero means that the service does not block.
+//! - Durations which do not fit into an [ApexSystemTime] saturate at
+//!   [ApexSystemTime::MAX].
+
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use a653rs::bindings::{ApexSystemTime, ErrorReturnCode};
+use a653rs::prelude::SystemTime;
+
+/// Interval in which blocking services retry their operation
+const RETRY_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Timeout of a potentially blocking APEX service
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Timeout {
+    /// Do not block at all
+    Immediate,
+    /// Block for at most the given duration
+    Finite(Duration),
+    /// Block until the operation succeeds
+    Infinite,
+}
+
+impl From<ApexSystemTime> for Timeout {
+    fn from(time: ApexSystemTime) -> Self {
+        SystemTime::new(time).into()
+    }
+}
+
+impl From<SystemTime> for Timeout {
+    fn from(time: SystemTime) -> Self {
+        match time {
+            SystemTime::Infinite => Timeout::Infinite,
+            SystemTime::Normal(Duration::ZERO) => Timeout::Immediate,
+            SystemTime::Normal(d) => Timeout::Finite(d),
+        }
+    }
+}
+
+impl Timeout {
+    /// Calls `op` until it returns `Some` or the timeout elapsed.
+    ///
+    /// `op` is called at least once. While blocking, the calling process may be
+    /// frozen at the end of the partition window, in which case the wait
+    /// continues in the next window.
+    pub(crate) fn retry<T>(&self, mut op: impl FnMut() -> Option<T>) -> Option<T> {
+        let deadline = match self {
+            Timeout::Immediate => return op(),
+            // A deadline too far in the future is the same as no deadline at all
+            Timeout::Finite(d) => Instant::now().checked_add(*d),
+            Timeout::Infinite => None,
+        };
+
+        loop {
+            if let Some(t) = op() {
+                return Some(t);
+            }
+            match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return None;
+                    }
+                    sleep(remaining.min(RETRY_INTERVAL));
+                }
+                None => sleep(RETRY_INTERVAL),
+            }
+        }
+    }
+
+    /// Calls `op` on `port` like [Timeout::retry], counting the caller as
+    /// waiting on the port while blocked.
+    ///
+    /// `park` is called with `true` before blocking and with `false` once the
+    /// wait is over. It is not called if the first attempt succeeds or the
+    /// timeout does not allow blocking.
+    pub(crate) fn retry_parked<P, T>(
+        &self,
+        port: &mut P,
+        park: impl Fn(&mut P, bool),
+        mut op: impl FnMut(&mut P) -> Option<T>,
+    ) -> Option<T> {
+        if let Some(t) = op(port) {
+            return Some(t);
+        }
+        if *self == Timeout::Immediate {
+            return None;
+        }
+
+        park(port, true);
+        let res = self.retry(|| op(port));
+        park(port, false);
+        res
+    }
+
+    /// Maximum duration to wait for, `None` meaning forever
+    pub(crate) fn duration(&self) -> Option<Duration> {
+        match self {
+            Timeout::Immediate => Some(Duration::ZERO),
+            Timeout::Finite(d) => Some(*d),
+            Timeout::Infinite => None,
+        }
+    }
+
+    /// Error code of a service which did not succeed within this timeout
+    pub(crate) fn expired(&self) -> ErrorReturnCode {
+        match self {
+            Timeout::Immediate => ErrorReturnCode::NotAvailable,
+            Timeout::Finite(_) | Timeout::Infinite => ErrorReturnCode::TimedOut,
+        }
+    }
+}
+
+/// Converts a refresh period of a sampling port.
+///
+/// Returns `None` for zero and [SystemTime::Infinite], because a message of a
+/// port with an infinite refresh period could never become invalid, which
+/// ARINC 653 treats as an out-of-range refresh period.
+pub(crate) fn refresh_period(time: ApexSystemTime) -> Option<Duration> {
+    match SystemTime::new(time) {
+        SystemTime::Normal(d) if !d.is_zero() => Some(d),
+        _ => None,
+    }
+}
+
+/// Converts a timeout to the [ApexSystemTime] taken by the services
+pub(crate) fn to_apex_timeout(time: SystemTime) -> ApexSystemTime {
+    match time {
+        SystemTime::Infinite => -1,
+        SystemTime::Normal(d) => to_apex_time(d),
+    }
+}
+
+/// Converts a duration to an [ApexSystemTime], saturating at
+/// [ApexSystemTime::MAX]
+pub(crate) fn to_apex_time(duration: Duration) -> ApexSystemTime {
+    duration
+        .as_nanos()
+        .try_into()
+        .unwrap_or(ApexSystemTime::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeout_encodings() {
+        assert_eq!(Timeout::from(0), Timeout::Immediate);
+        assert_eq!(Timeout::from(1), Timeout::Finite(Duration::from_nanos(1)));
+        assert_eq!(Timeout::from(-1), Timeout::Infinite);
+        assert_eq!(Timeout::from(ApexSystemTime::MIN), Timeout::Infinite);
+        assert_eq!(
+            Timeout::from(ApexSystemTime::MAX),
+            Timeout::Finite(Duration::from_nanos(ApexSystemTime::MAX as u64))
+        );
+    }
+
+    #[test]
+    fn refresh_period_encodings() {
+        assert_eq!(refresh_period(0), None);
+        assert_eq!(refresh_period(-1), None);
+        assert_eq!(refresh_period(ApexSystemTime::MIN), None);
+        assert_eq!(refresh_period(1), Some(Duration::from_nanos(1)));
+        assert_eq!(
+            refresh_period(ApexSystemTime::MAX),
+            Some(Duration::from_nanos(ApexSystemTime::MAX as u64))
+        );
+    }
+
+    #[test]
+    fn apex_time_saturates() {
+        assert_eq!(to_apex_time(Duration::ZERO), 0);
+        assert_eq!(to_apex_time(Duration::from_nanos(42)), 42);
+        assert_eq!(
+            to_apex_time(Duration::from_nanos(ApexSystemTime::MAX as u64)),
+            ApexSystemTime::MAX
+        );
+        assert_eq!(to_apex_time(Duration::MAX), ApexSystemTime::MAX);
+    }
+
+    #[test]
+    fn retry_timeouts() {
+        let mut calls = 0;
+        assert_eq!(
+            Timeout::Immediate.retry(|| {
+                calls += 1;
+                None::<()>
+            }),
+            None
+        );
+        assert_eq!(calls, 1);
+
+        let start = Instant::now();
+        let timeout = Duration::from_millis(5);
+        assert_eq!(Timeout::Finite(timeout).retry(|| None::<()>), None);
+        assert!(start.elapsed() >= timeout);
+
+        let mut calls = 0;
+        let res = Timeout::Infinite.retry(|| {
+            calls += 1;
+            (calls == 3).then_some(calls)
+        });
+        assert_eq!(res, Some(3));
+
+        // A huge timeout must neither overflow nor block when the operation succeeds
+        assert_eq!(
+            Timeout::from(ApexSystemTime::MAX).retry(|| Some(1)),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn parked_while_blocking() {
+        // The port records (parked, whether parked at each attempt)
+        let park = |port: &mut (bool, Vec<bool>), parked| port.0 = parked;
+        let attempt = |succeed_at: usize| {
+            move |port: &mut (bool, Vec<bool>)| {
+                port.1.push(port.0);
+                (port.1.len() == succeed_at).then_some(())
+            }
+        };
+
+        let mut port = (false, Vec::new());
+        assert_eq!(
+            Timeout::Infinite.retry_parked(&mut port, park, attempt(1)),
+            Some(())
+        );
+        assert_eq!(port, (false, vec![false]));
+
+        let mut port = (false, Vec::new());
+        assert_eq!(
+            Timeout::Infinite.retry_parked(&mut port, park, attempt(3)),
+            Some(())
+        );
+        assert_eq!(port, (false, vec![false, true, true]));
+
+        let mut port = (false, Vec::new());
+        assert_eq!(
+            Timeout::Immediate.retry_parked(&mut port, park, attempt(2)),
+            None
+        );
+        assert_eq!(port, (false, vec![false]));
+
+        let mut port = (false, Vec::new());
+        let timeout = Timeout::Finite(Duration::from_millis(2));
+        assert_eq!(
+            timeout.retry_parked(&mut port, park, attempt(usize::MAX)),
+            None
+        );
+        assert!(!port.0 && port.1[1..].iter().all(|parked| *parked));
+    }
+}